@@ -0,0 +1,61 @@
+use steel::rvals::SteelVal;
+use steel::steel_vm::engine::Engine;
+use steel_derive::steel;
+
+// #[steel] generates a predicate, a constructor, getters and setters
+#[steel]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    owner: String,
+    balance_in_cents: isize,
+}
+
+// The name used in Steel can be changed
+#[steel(name = "pair")]
+#[derive(Clone, Debug)]
+pub struct Pair {
+    left: isize,
+    right: isize,
+}
+
+pub fn main() {
+    let mut vm = Engine::new();
+
+    Account::register_steel_type(&mut vm);
+    Pair::register_steel_type(&mut vm);
+
+    vm.run(
+        r#"
+        (define account (Account "alice" 100))
+        (set-Account-balance-in-cents! account (+ (Account-balance-in-cents account) 50))
+        (define balance (Account-balance-in-cents account))
+        (define is-account (Account? account))
+
+        (define p (pair 1 2))
+        (set-pair-left! p 10)
+        (define sum (+ (pair-left p) (pair-right p)))
+        (define is-not-account (Account? p))
+    "#,
+    )
+    .unwrap();
+
+    assert_eq!(150, vm.extract::<isize>("balance").unwrap());
+    assert_eq!(
+        SteelVal::BoolV(true),
+        vm.extract_value("is-account").unwrap()
+    );
+    assert_eq!(
+        SteelVal::BoolV(false),
+        vm.extract_value("is-not-account").unwrap()
+    );
+    assert_eq!(12, vm.extract::<isize>("sum").unwrap());
+
+    let account = vm.extract::<Account>("account").unwrap();
+    assert_eq!(
+        account,
+        Account {
+            owner: "alice".to_string(),
+            balance_in_cents: 150
+        }
+    );
+}
@@ -0,0 +1,72 @@
+use steel::rvals::{FromSteelVal, IntoSteelVal};
+use steel::steel_vm::engine::Engine;
+use steel::steel_vm::register_fn::RegisterFn;
+use steel_derive::SteelConvert;
+
+// Converted field by field to and from a hash map, rather than kept as an opaque value
+#[derive(Clone, Debug, PartialEq, SteelConvert)]
+pub struct Config {
+    max_size: usize,
+    #[steel_convert(rename = "label")]
+    name: String,
+    verbose: bool,
+    // Only meaningful on the Rust side
+    #[steel_convert(skip)]
+    cache: Vec<String>,
+}
+
+fn describe(config: Config) -> String {
+    format!("{} holds {}", config.name, config.max_size)
+}
+
+pub fn main() {
+    let mut vm = Engine::new();
+
+    let config = Config {
+        max_size: 10,
+        name: "cache".to_string(),
+        verbose: false,
+        cache: vec!["ignored".to_string()],
+    };
+
+    vm.register_value("config", config.into_steelval().unwrap());
+    vm.register_fn("describe", describe);
+
+    vm.run(
+        r#"
+        (define size (hash-get config 'max-size))
+        (define label (hash-get config 'label))
+        (define updated (hash-insert config 'max-size 20))
+        (define description (describe (hash 'max-size 3 'label "small" 'verbose #t)))
+
+        (struct settings (max-size label verbose))
+        (define from-struct (settings 5 "struct" #f))
+    "#,
+    )
+    .unwrap();
+
+    assert_eq!(10, vm.extract::<usize>("size").unwrap());
+    assert_eq!("cache", vm.extract::<String>("label").unwrap());
+    assert_eq!(
+        "small holds 3",
+        vm.extract::<String>("description").unwrap()
+    );
+
+    let updated = vm.extract::<Config>("updated").unwrap();
+    assert_eq!(
+        updated,
+        Config {
+            max_size: 20,
+            name: "cache".to_string(),
+            verbose: false,
+            cache: Vec::new(),
+        }
+    );
+
+    let from_struct = vm.extract::<Config>("from-struct").unwrap();
+    assert_eq!(5, from_struct.max_size);
+
+    // Missing fields can't be filled in
+    let missing = Config::from_steelval(vm.run("(hash 'label \"x\")").unwrap().pop().unwrap());
+    assert!(missing.is_err());
+}
@@ -0,0 +1,61 @@
+use steel::steel_vm::engine::Engine;
+use steel_derive::steel_module;
+
+// Every #[function] in the module is registered by the generated `register_module`
+#[steel_module]
+mod math {
+    #[function]
+    pub fn square(x: isize) -> isize {
+        x * x
+    }
+
+    #[function(name = "clamp-to")]
+    pub fn clamp(x: isize, low: isize, high: isize) -> isize {
+        x.max(low).min(high)
+    }
+
+    // Not marked, so this stays private to Rust
+    pub fn helper() -> isize {
+        0
+    }
+}
+
+// Names can share a prefix to keep them apart from other modules
+#[steel_module(prefix = "text/")]
+mod text {
+    #[function]
+    pub fn shout(s: String) -> String {
+        s.to_uppercase()
+    }
+
+    #[function]
+    pub fn word_count(s: String) -> usize {
+        s.split_whitespace().count()
+    }
+}
+
+pub fn main() {
+    let mut vm = Engine::new();
+
+    math::register_module(&mut vm);
+    text::register_module(&mut vm);
+
+    let output = vm
+        .run(
+            r#"
+            (define squared (square 12))
+            (define clamped (clamp-to 100 0 10))
+            (define shouted (text/shout "hello"))
+            (define words (text/word-count "the quick brown fox"))
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(144, vm.extract::<isize>("squared").unwrap());
+    assert_eq!(10, vm.extract::<isize>("clamped").unwrap());
+    assert_eq!("HELLO", vm.extract::<String>("shouted").unwrap());
+    assert_eq!(4, vm.extract::<usize>("words").unwrap());
+    assert_eq!(0, math::helper());
+
+    println!("{:?}", output);
+}
@@ -0,0 +1,98 @@
+use steel::rvals::{IntoSteelVal, SteelVal};
+use steel::steel_vm::engine::Engine;
+use steel::steel_vm::register_fn::RegisterFn;
+use steel_derive::steel_trait;
+
+// The interface plugins implement, callable from Steel through `DynShape`
+#[steel_trait]
+pub trait Shape {
+    fn area(&self) -> f64;
+    fn scale(&mut self, factor: f64);
+    fn describe(&self, precision: usize) -> String;
+}
+
+pub struct Circle {
+    radius: f64,
+}
+
+impl Shape for Circle {
+    fn area(&self) -> f64 {
+        std::f64::consts::PI * self.radius * self.radius
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.radius *= factor;
+    }
+
+    fn describe(&self, precision: usize) -> String {
+        format!("circle with radius {:.*}", precision, self.radius)
+    }
+}
+
+pub struct Square {
+    side: f64,
+}
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.side * self.side
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.side *= factor;
+    }
+
+    fn describe(&self, precision: usize) -> String {
+        format!("square with side {:.*}", precision, self.side)
+    }
+}
+
+// Hosts can hand out trait objects from functions too
+fn unit_square() -> DynShape {
+    DynShape::new(Square { side: 1.0 })
+}
+
+pub fn main() {
+    let mut vm = Engine::new();
+
+    DynShape::register_steel_trait(&mut vm);
+    vm.register_fn("unit-square", unit_square);
+
+    let circle = DynShape::new(Circle { radius: 1.0 });
+    vm.register_value("circle", circle.clone().into_steelval().unwrap());
+
+    vm.run(
+        r#"
+        (define shapes (list circle (unit-square)))
+        (define scaled (map (lambda (shape) (Shape-scale shape 2.0)) shapes))
+        (define areas (map Shape-area shapes))
+        (define descriptions (map (lambda (shape) (Shape-describe shape 1)) shapes))
+        (define all-shapes (map Shape? shapes))
+        (define not-a-shape (Shape? 10))
+    "#,
+    )
+    .unwrap();
+
+    let areas = vm.extract::<Vec<f64>>("areas").unwrap();
+    assert!((areas[0] - 4.0 * std::f64::consts::PI).abs() < 1e-9);
+    assert_eq!(areas[1], 4.0);
+
+    assert_eq!(
+        vec![
+            "circle with radius 2.0".to_string(),
+            "square with side 2.0".to_string()
+        ],
+        vm.extract::<Vec<String>>("descriptions").unwrap()
+    );
+    assert_eq!(
+        vec![true, true],
+        vm.extract::<Vec<bool>>("all-shapes").unwrap()
+    );
+    assert_eq!(
+        SteelVal::BoolV(false),
+        vm.extract_value("not-a-shape").unwrap()
+    );
+
+    // The handle is shared, so the host sees what the script did to the value
+    assert_eq!(circle.0.borrow().describe(0), "circle with radius 2");
+}
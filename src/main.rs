@@ -2,12 +2,23 @@ extern crate steel;
 extern crate steel_derive;
 extern crate steel_repl;
 
+use steel::compiler::compiler::RedefinitionPolicy;
+use steel::compiler::program::{Executable, MappedExecutable};
+use steel::diagnostics::apply_fixes;
+use steel::docs::{self, DocEntry};
+use steel::literate::{self, LiterateFile};
+use steel::lsp::analysis::{modules_near, Analysis};
+use steel::rerrs::{ErrorFormat, ErrorKind, SendableErr, SteelErr};
+use steel::steel_vm::snapshots::{is_serial, SnapshotMode, Snapshots};
 use steel::steel_vm::{engine::Engine, register_fn::RegisterAsyncFn};
 use steel_repl::repl::repl_base;
 
 use std::env::args;
 use std::fs;
+use std::path::Path;
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 // use env_logger::Builder;
 // use log::LevelFilter;
@@ -24,36 +35,481 @@ fn main() {
     //     )
     //     .init();
 
-    let args = args().collect::<Vec<_>>();
+    let (error_format, args) = match parse_args(args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // `--timings` prints how long each phase of compiling the program took
+    let timings = args.iter().any(|x| x == "--timings");
+    let args = args
+        .into_iter()
+        .filter(|x| x != "--timings")
+        .collect::<Vec<_>>();
 
     let mut vm = configure_engine();
 
-    if args.len() == 1 {
+    if args.is_empty() {
         finish(repl_base(vm));
-    } else if args.len() == 2 {
-        let path = &args[1];
+    } else if args.len() == 4 && args[0] == "compile" && args[2] == "-o" {
+        if load_core_libraries(&mut vm) {
+            compile(&mut vm, &args[1], &args[3], error_format, timings);
+        }
+    } else if args.len() == 1 && args[0] == "lsp" {
+        finish(steel::lsp::serve_stdio());
+    } else if args[0] == "fmt" {
+        format_files(&args[1..], error_format);
+    } else if args[0] == "fix" {
+        fix_files(&vm, &args[1..], error_format);
+    } else if args[0] == "test" {
+        run_tests(&args[1..], error_format);
+    } else if args[0] == "doc" {
+        if load_core_libraries(&mut vm) {
+            doc(&mut vm, &args[1..], error_format);
+        }
+    } else if args.len() == 1 {
+        let path = &args[0];
+
+        if !load_core_libraries(&mut vm) {
+            return;
+        }
 
-        let core_libraries = &[
-            steel::stdlib::PRELUDE,
-            steel::stdlib::DISPLAY,
-            steel::stdlib::CONTRACTS,
-        ];
+        let bytes = fs::read(path).expect("Something went wrong reading the file");
 
-        for core in core_libraries {
-            let res = vm.parse_and_execute_without_optimizations(core);
+        // Programs compiled with `steel compile` have no source to report errors against
+        if Executable::is_executable(&bytes) || Executable::is_mappable(&bytes) {
+            let res = if Executable::is_mappable(&bytes) {
+                MappedExecutable::new(bytes.as_slice()).and_then(|x| vm.run_mapped_executable(&x))
+            } else {
+                Executable::deserialize(&bytes).and_then(|x| vm.run_executable(x))
+            };
             if let Err(e) = res {
                 eprintln!("{}", e);
-                return;
+                process::exit(1);
+            }
+            return;
+        }
+
+        let contents = String::from_utf8(bytes).expect("Something went wrong reading the file");
+        let res = run_source(&mut vm, path, &contents, timings);
+
+        let mut reporter = error_format.reporter();
+        if let Err(e) = res {
+            reporter.report(&e, path, &contents);
+        }
+        reporter.finish();
+    }
+}
+
+fn run_source(vm: &mut Engine, path: &str, contents: &str, timings: bool) -> Result<(), SteelErr> {
+    // Literate files keep their prose blanked out, so errors are reported against the
+    // original file
+    let code = if is_literate(path) {
+        LiterateFile::parse(contents).code()
+    } else {
+        contents.to_string()
+    };
+
+    let (program, compile_timings) = vm.compile_with_timings(&code)?;
+    if timings {
+        eprint!("{}", compile_timings);
+    }
+    vm.execute_program(program).map(|_| ())
+}
+
+// `steel test [--update-snapshots] <path> ...`, runs each file in its own fork of an engine
+// with the core libraries loaded, checking its `check-snapshot`s against the snapshots kept
+// next to it. Files run in parallel, except those marked `#:serial`, which run one at a
+// time once the rest are done.
+fn run_tests(args: &[String], error_format: ErrorFormat) {
+    if let [flag, paths @ ..] = args {
+        if flag == "--doc" {
+            return run_doc_tests(paths, error_format);
+        }
+    }
+
+    let mode = if args.iter().any(|x| x == "--update-snapshots") {
+        SnapshotMode::Update
+    } else {
+        SnapshotMode::Check
+    };
+    let paths = args
+        .iter()
+        .filter(|x| *x != "--update-snapshots")
+        .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        eprintln!("steel test expects the files to run");
+        process::exit(1);
+    }
+
+    let sources = paths
+        .iter()
+        .map(|path| fs::read_to_string(path).expect("Something went wrong reading the file"))
+        .collect::<Vec<_>>();
+    let (serial, parallel): (Vec<usize>, Vec<usize>) =
+        (0..paths.len()).partition(|i| is_serial(&sources[*i]));
+
+    let mut results: Vec<Option<Result<(), SendableErr>>> = vec![None; paths.len()];
+
+    // Engines can't be sent between threads, so each worker loads the core libraries once
+    // and forks that engine for every file it takes
+    let workers = thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+        .min(parallel.len());
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let base = test_engine();
+                    let mut done = Vec::new();
+                    while let Some(&i) = parallel.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let res = run_test(&base, paths[i], &sources[i], mode);
+                        done.push((i, res.map_err(SendableErr::from)));
+                    }
+                    done
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for worker in workers {
+            for (i, res) in worker.join().expect("a test worker panicked") {
+                results[i] = Some(res);
+            }
+        }
+    });
+
+    if !serial.is_empty() {
+        let base = test_engine();
+        for i in serial {
+            let res = run_test(&base, paths[i], &sources[i], mode);
+            results[i] = Some(res.map_err(SendableErr::from));
+        }
+    }
+
+    // Reported in the order the files were given, however they finished
+    let mut reporter = error_format.reporter();
+    let mut failed = 0;
+    for ((path, contents), res) in paths.iter().zip(&sources).zip(results) {
+        match res.expect("every test file is run") {
+            Ok(()) => eprintln!("ok {}", path),
+            Err(e) => {
+                failed += 1;
+                eprintln!("FAILED {}", path);
+                reporter.report(&e.into(), path, contents);
             }
         }
+    }
+    reporter.finish();
+
+    eprintln!("{} passed, {} failed", paths.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+// `steel test --doc [<path> ...]`, runs the examples in the documentation of the builtins, and
+// of the functions each file documents once that file has been run
+fn run_doc_tests(paths: &[String], error_format: ErrorFormat) {
+    let base = test_engine();
+    let mut failed = docs::run_examples(&base, &base.docs());
+    let mut tested = with_examples(&base.docs());
 
+    for path in paths {
         let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
-        let res = vm.parse_and_execute_without_optimizations(&contents);
+        let mut vm = base.fork();
+        let entries = match run_source(&mut vm, path, &contents, false)
+            .and_then(|_| docs::file_docs(&contents, path))
+        {
+            Ok(entries) => entries,
+            Err(e) => {
+                let mut reporter = error_format.reporter();
+                reporter.report(&e, path, &contents);
+                reporter.finish();
+                process::exit(1);
+            }
+        };
+
+        tested += with_examples(&entries);
+        failed.extend(docs::run_examples(&vm, &entries));
+    }
+
+    let mut reporter = error_format.reporter();
+    for (entry, e) in &failed {
+        let name = format!("{} ({})", entry.name, entry.module);
+        eprintln!("FAILED {}", name);
+        reporter.report(e, &name, &entry.doc);
+    }
+    reporter.finish();
+
+    eprintln!("{} passed, {} failed", tested - failed.len(), failed.len());
+    if !failed.is_empty() {
+        process::exit(1);
+    }
+}
 
+fn with_examples(entries: &[DocEntry]) -> usize {
+    entries
+        .iter()
+        .filter(|x| LiterateFile::parse(&x.doc).blocks() > 0)
+        .count()
+}
+
+fn test_engine() -> Engine {
+    let mut vm = configure_engine();
+    if !load_core_libraries(&mut vm) {
+        process::exit(1);
+    }
+    vm
+}
+
+fn run_test(base: &Engine, path: &str, contents: &str, mode: SnapshotMode) -> Result<(), SteelErr> {
+    let snapshots = match Snapshots::load(Snapshots::path_for(path), mode) {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("Unable to read the snapshots for {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut vm = base.fork();
+    vm.record_snapshots(snapshots.clone());
+    let res = run_source(&mut vm, path, contents, false);
+
+    // Snapshots taken before a failure are still kept
+    if let Err(e) = snapshots.save() {
+        eprintln!("Unable to write the snapshots for {}: {}", path, e);
+    }
+    res
+}
+
+// `steel fmt [--check] <path> ...`, rewrites each file formatted, or with `--check` only
+// lists the files that aren't
+fn format_files(args: &[String], error_format: ErrorFormat) {
+    let check = args.iter().any(|x| x == "--check");
+    let paths = args.iter().filter(|x| *x != "--check").collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        eprintln!("steel fmt expects the files to format");
+        process::exit(1);
+    }
+
+    let mut reporter = error_format.reporter();
+    let mut failed = false;
+    for path in &paths {
+        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+        let formatted = match steel::fmt::format(&contents) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                failed = true;
+                reporter.report(&e, path, &contents);
+                continue;
+            }
+        };
+
+        if formatted == contents {
+            continue;
+        }
+        if check {
+            failed = true;
+            eprintln!("{} isn't formatted", path);
+        } else if let Err(e) = fs::write(path, formatted) {
+            failed = true;
+            eprintln!("Unable to write {}: {}", path, e);
+        }
+    }
+    reporter.finish();
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+// `steel fix <path> ...`, adds the requires each file is missing, for names it uses that exactly
+// one builtin module or file next to it provides. Names more than one module provides are
+// listed, to be required by hand.
+fn fix_files(vm: &Engine, paths: &[String], error_format: ErrorFormat) {
+    if paths.is_empty() {
+        eprintln!("steel fix expects the files to fix");
+        process::exit(1);
+    }
+
+    let mut reporter = error_format.reporter();
+    let mut failed = false;
+    for path in paths {
+        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+        let analysis = match Analysis::parse(&contents) {
+            Ok(analysis) => analysis,
+            Err(e) => {
+                failed = true;
+                reporter.report(&e, path, &contents);
+                continue;
+            }
+        };
+
+        let mut modules = vm.module_exports();
+        if let Some(directory) = Path::new(path).parent() {
+            let directory = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory
+            };
+            modules.extend(modules_near(directory));
+        }
+
+        let missing = analysis.missing_requires(&contents, &modules);
+        for diagnostic in missing.iter().filter(|x| x.fixes.len() > 1) {
+            eprintln!("{}: {}", path, diagnostic.message);
+        }
+        let fixes = missing
+            .iter()
+            .filter(|x| x.fixes.len() == 1)
+            .map(|x| &x.fixes[0])
+            .collect::<Vec<_>>();
+
+        if fixes.is_empty() {
+            continue;
+        }
+        if let Err(e) = fs::write(path, apply_fixes(&contents, &fixes)) {
+            failed = true;
+            eprintln!("Unable to write {}: {}", path, e);
+        }
+    }
+    reporter.finish();
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn load_core_libraries(vm: &mut Engine) -> bool {
+    let core_libraries = &[
+        steel::stdlib::PRELUDE,
+        steel::stdlib::DISPLAY,
+        steel::stdlib::CONTRACTS,
+    ];
+
+    for core in core_libraries {
+        let res = vm.parse_and_execute_without_optimizations(core);
         if let Err(e) = res {
-            e.emit_result(path, &contents);
+            eprintln!("{}", e);
+            return false;
         }
     }
+
+    true
+}
+
+// `steel compile <path> -o <output>`, writes the program out as bytecode that `steel <output>`
+// can run without the source. Unlike at the REPL, redefining a global is an error.
+fn compile(vm: &mut Engine, path: &str, output: &str, error_format: ErrorFormat, timings: bool) {
+    let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+    vm.set_redefinition_policy(RedefinitionPolicy::Error);
+
+    let res = vm.compile_executable(&contents);
+    if timings {
+        eprint!("{}", vm.compile_timings());
+    }
+
+    match res {
+        Ok(executable) => {
+            if let Err(e) = fs::write(output, executable.serialize()) {
+                eprintln!("Unable to write {}: {}", output, e);
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            let mut reporter = error_format.reporter();
+            reporter.report(&e, path, &contents);
+            reporter.finish();
+        }
+    }
+}
+
+fn is_literate(path: &str) -> bool {
+    Path::new(path).extension().and_then(|x| x.to_str()) == Some(literate::EXTENSION)
+}
+
+// `steel doc <path>` with a literate file prints it back out with what each of its code blocks
+// returned right after it. Otherwise `steel doc [--html | --json] [<path> ...]` prints the
+// documentation of the builtin modules and of what each file defines, as markdown, a searchable
+// HTML page, or JSON with the arities of each function for editors to complete calls with.
+fn doc(vm: &mut Engine, args: &[String], error_format: ErrorFormat) {
+    match args {
+        [path] if is_literate(path) => render_literate(vm, path, error_format),
+        [flag, paths @ ..] if flag == "--html" => {
+            print!("{}", docs::html(&collect_docs(vm, paths)))
+        }
+        [flag, paths @ ..] if flag == "--json" => {
+            println!("{}", docs::json(&collect_docs(vm, paths)))
+        }
+        paths => print!("{}", docs::markdown(&collect_docs(vm, paths))),
+    }
+}
+
+fn collect_docs(vm: &Engine, paths: &[String]) -> Vec<DocEntry> {
+    let mut entries = vm.docs();
+
+    for path in paths {
+        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+        match docs::file_docs(&contents, path) {
+            Ok(docs) => entries.extend(docs),
+            Err(e) => {
+                e.emit_result(path, &contents);
+                process::exit(1);
+            }
+        }
+    }
+
+    entries
+}
+
+fn render_literate(vm: &mut Engine, path: &str, error_format: ErrorFormat) {
+    let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+
+    match LiterateFile::parse(&contents).render(vm) {
+        Ok(rendered) => print!("{}", rendered),
+        Err(e) => {
+            let mut reporter = error_format.reporter();
+            reporter.report(&e, path, &contents);
+            reporter.finish();
+            process::exit(1);
+        }
+    }
+}
+
+// Pulls `--error-format <format>` (or `--error-format=<format>`) out of the arguments,
+// leaving the rest in order
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(ErrorFormat, Vec<String>), SteelErr> {
+    let mut error_format = ErrorFormat::Human;
+    let mut rest = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if let Some(format) = arg.strip_prefix("--error-format=") {
+            error_format = format.parse()?;
+        } else if arg == "--error-format" {
+            match args.next() {
+                Some(format) => error_format = format.parse()?,
+                None => {
+                    return Err(SteelErr::new(
+                        ErrorKind::Generic,
+                        "--error-format expects one of human, json or sarif".to_string(),
+                    ))
+                }
+            }
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    Ok((error_format, rest))
 }
 
 fn finish(result: Result<(), std::io::Error>) -> ! {
@@ -0,0 +1,43 @@
+// With the `codegen` feature, the super instructions the compiler fuses are generated again from
+// the patterns checked in next to them, so they can't fall out of step with the opcodes.
+// The opcodes are declared in a file of their own, which is compiled in here as well.
+#[cfg(feature = "codegen")]
+#[allow(dead_code)]
+#[path = "src/core/opcode.rs"]
+mod opcode;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "codegen")]
+    generate_super_patterns();
+}
+
+#[cfg(feature = "codegen")]
+fn generate_super_patterns() {
+    use opcode::OpCode;
+    use steel_gen::Fusion;
+
+    const PATTERNS: &str = "src/core/patterns.json";
+    const GENERATED: &str = "src/core/super_patterns.rs";
+
+    println!("cargo:rerun-if-changed={}", PATTERNS);
+    println!("cargo:rerun-if-changed=src/core/opcode.rs");
+
+    let json = std::fs::read_to_string(PATTERNS).unwrap();
+    let source = steel_gen::generate_super_patterns(&json, |name| {
+        OpCode::ALL
+            .iter()
+            .find(|x| x.mnemonic() == name)
+            .map(|x| Fusion {
+                first: x.fuses_first(),
+                second: x.fuses_second(),
+            })
+    })
+    .unwrap_or_else(|e| panic!("{}: {}", PATTERNS, e));
+
+    // Only written when it changes, otherwise every build would see a new file
+    if std::fs::read_to_string(GENERATED).ok().as_deref() != Some(source.as_str()) {
+        std::fs::write(GENERATED, source).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use crate::compiler::program::checksum;
+use crate::core::instructions::DenseInstruction;
+use crate::gc::Gc;
+use crate::primitives::ListOperations;
+use crate::rvals::{Result, SteelVal};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+
+// Bump this whenever the layout of a cached program or the bytecode changes
+pub(crate) const CACHE_VERSION: u32 = 6;
+
+/// Compiled programs saved to disk, keyed by a hash of their source along with
+/// everything in the compiler that the output depends on. The hash is the same from one
+/// build of Steel to the next, so the cache can be shared between them.
+#[derive(Clone)]
+pub(crate) struct CompilationCache {
+    directory: PathBuf,
+    hits: usize,
+}
+
+/// The parts of a compiled program that need to be restored for it to run again, along with
+/// what it was compiled from to check a hit against
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedProgram {
+    pub(crate) instructions: Vec<Vec<DenseInstruction>>,
+    pub(crate) symbols: Vec<String>,
+    pub(crate) constants: Vec<CachedConstant>,
+    /// The checksum of the source of the program
+    pub(crate) source: u64,
+    /// Every module the program requires, directly or not, with the checksum of its source
+    pub(crate) modules: Vec<(PathBuf, u64)>,
+}
+
+impl CachedProgram {
+    // Whether the program was compiled from `source`, against the modules as they are now
+    fn compiled_from(&self, source: &str) -> bool {
+        self.source == checksum(source.as_bytes())
+            && self.modules.iter().all(|(path, expected)| {
+                fs::read(path).map(|x| checksum(&x)).ok() == Some(*expected)
+            })
+    }
+}
+
+/// Constants are written out as data, anything that isn't data can't be cached
+#[derive(Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub(crate) enum CachedConstant {
+    Bool(bool),
+    Int(isize),
+    // Stored as bits so that the constant can be hashed
+    Num(u64),
+    Char(char),
+    String(String),
+    Symbol(String),
+    Keyword(String),
+    Void,
+    List(Vec<CachedConstant>),
+    Vector(Vec<CachedConstant>),
+}
+
+impl TryFrom<&SteelVal> for CachedConstant {
+    type Error = ();
+
+    fn try_from(value: &SteelVal) -> std::result::Result<Self, Self::Error> {
+        let items = |items: &mut dyn Iterator<Item = SteelVal>| {
+            items
+                .map(|x| CachedConstant::try_from(&x))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        };
+
+        match value {
+            SteelVal::BoolV(b) => Ok(CachedConstant::Bool(*b)),
+            SteelVal::IntV(i) => Ok(CachedConstant::Int(*i)),
+            SteelVal::NumV(n) => Ok(CachedConstant::Num(n.to_bits())),
+            SteelVal::CharV(c) => Ok(CachedConstant::Char(*c)),
+            SteelVal::StringV(s) => Ok(CachedConstant::String(s.unwrap())),
+            SteelVal::SymbolV(s) => Ok(CachedConstant::Symbol(s.unwrap())),
+            SteelVal::KeywordV(k) => Ok(CachedConstant::Keyword(k.unwrap())),
+            SteelVal::Void => Ok(CachedConstant::Void),
+            SteelVal::Pair(_) => {
+                items(&mut SteelVal::iter(value.clone())).map(CachedConstant::List)
+            }
+            SteelVal::VectorV(v) => items(&mut v.iter().cloned()).map(CachedConstant::Vector),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CachedConstant {
+    pub(crate) fn into_steelval(self) -> Result<SteelVal> {
+        let items = |items: Vec<CachedConstant>| {
+            items
+                .into_iter()
+                .map(CachedConstant::into_steelval)
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(match self {
+            CachedConstant::Bool(b) => SteelVal::BoolV(b),
+            CachedConstant::Int(i) => SteelVal::IntV(i),
+            CachedConstant::Num(n) => SteelVal::NumV(f64::from_bits(n)),
+            CachedConstant::Char(c) => SteelVal::CharV(c),
+            CachedConstant::String(s) => SteelVal::StringV(s.into()),
+            CachedConstant::Symbol(s) => SteelVal::SymbolV(s.into()),
+            CachedConstant::Keyword(k) => SteelVal::KeywordV(k.into()),
+            CachedConstant::Void => SteelVal::Void,
+            CachedConstant::List(l) => ListOperations::built_in_list_func_flat_non_gc(items(l)?)?,
+            CachedConstant::Vector(v) => {
+                SteelVal::VectorV(Gc::new(items(v)?.into_iter().collect()))
+            }
+        })
+    }
+}
+
+/// Builds the key a program is cached under, from the bytes each part is serialized to
+pub(crate) struct CacheKey(Vec<u8>);
+
+impl CacheKey {
+    pub(crate) fn new() -> Self {
+        let mut key = CacheKey(Vec::new());
+        key.add(&CACHE_VERSION).add(env!("CARGO_PKG_VERSION"));
+        key
+    }
+
+    pub(crate) fn add<T: Serialize + ?Sized>(&mut self, value: &T) -> &mut Self {
+        bincode::serialize_into(&mut self.0, value).expect("cache keys can always be serialized");
+        self
+    }
+
+    fn file_name(&self) -> String {
+        format!("{:016x}.bin", checksum(&self.0))
+    }
+}
+
+impl CompilationCache {
+    pub(crate) fn new(directory: PathBuf) -> Self {
+        CompilationCache { directory, hits: 0 }
+    }
+
+    pub(crate) fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub(crate) fn record_hit(&mut self) {
+        self.hits += 1;
+    }
+
+    // The cache is only ever an optimization, so failing to read or write it just means compiling
+    pub(crate) fn load(&self, key: &CacheKey, source: &str) -> Option<CachedProgram> {
+        let path = self.directory.join(key.file_name());
+        let bytes = fs::read(&path).ok()?;
+
+        match bincode::deserialize::<CachedProgram>(&bytes) {
+            Ok(program) if program.compiled_from(source) => {
+                debug!("Loaded compiled program from {:?}", path);
+                Some(program)
+            }
+            Ok(_) => {
+                debug!("Ignoring compiled program {:?} from another source", path);
+                None
+            }
+            Err(e) => {
+                debug!("Ignoring unreadable compiled program {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn store(&self, key: &CacheKey, program: &CachedProgram) {
+        let path = self.directory.join(key.file_name());
+
+        let result = fs::create_dir_all(&self.directory)
+            .map_err(|e| e.to_string())
+            .and_then(|_| bincode::serialize(program).map_err(|e| e.to_string()))
+            .and_then(|bytes| fs::write(&path, bytes).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            debug!("Unable to cache compiled program at {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    fn cache_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("steel-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn cached_files(directory: &PathBuf) -> usize {
+        fs::read_dir(directory).map(|x| x.count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn constants_round_trip() {
+        let list = ListOperations::built_in_list_func_flat(&[
+            SteelVal::IntV(1),
+            SteelVal::StringV("two".into()),
+            SteelVal::SymbolV("three".into()),
+        ])
+        .unwrap();
+
+        for value in [list, SteelVal::NumV(0.5), SteelVal::CharV('λ')].iter() {
+            let constant = CachedConstant::try_from(value).unwrap();
+            // Lists and floats don't compare equal to themselves
+            assert_eq!(
+                constant.into_steelval().unwrap().to_string(),
+                value.to_string()
+            );
+        }
+
+        assert!(CachedConstant::try_from(&SteelVal::FuncV(|_| Ok(SteelVal::Void))).is_err());
+    }
+
+    #[test]
+    fn programs_are_loaded_in_a_new_engine() {
+        let directory = cache_directory("reload");
+        let program =
+            r#"(define greeting "hello") (define (greet x) (list greeting x)) (greet 'world)"#;
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        let expected = vm.run(program).unwrap();
+        assert_eq!(vm.metrics().compilation_cache_hits, 0);
+        assert_eq!(cached_files(&directory), 1);
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        assert_eq!(
+            vm.run(program).unwrap().last().unwrap().to_string(),
+            expected.last().unwrap().to_string()
+        );
+        assert_eq!(vm.metrics().compilation_cache_hits, 1);
+
+        // The definitions from the cached program are there for the next one
+        assert_eq!(
+            vm.run("(greet 10)").unwrap().pop().unwrap().to_string(),
+            "'(\"hello\" 10)"
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn the_same_source_in_a_different_setting_is_compiled() {
+        let directory = cache_directory("setting");
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        vm.run("(define x 1) (+ x 1)").unwrap();
+
+        // x gets a different global slot here, so the cached bytecode doesn't apply
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        vm.run("(define y 0)").unwrap();
+        vm.run("(define x 1) (+ x 1)").unwrap();
+        assert_eq!(vm.metrics().compilation_cache_hits, 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn programs_defining_macros_are_cached() {
+        let directory = cache_directory("macros");
+        let program = "(define-syntax swap (syntax-rules () ((swap a b) (list b a)))) (swap 1 2)";
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        vm.run(program).unwrap();
+        assert_eq!(cached_files(&directory), 1);
+
+        // The macro is still defined for the programs after the cached one
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        vm.run(program).unwrap();
+        assert_eq!(vm.metrics().compilation_cache_hits, 1);
+        assert_eq!(
+            vm.run("(swap 3 4)").unwrap().pop().unwrap().to_string(),
+            "'(4 3)"
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn programs_requiring_modules_are_cached_until_the_modules_change() {
+        let directory = cache_directory("modules");
+        fs::create_dir_all(&directory).unwrap();
+        let module = directory.join("rate.scm");
+        fs::write(&module, "(provide rate) (define rate 2)").unwrap();
+        let program = format!("(require {:?}) (* rate 10)", module.display().to_string());
+        let cache = directory.join("cache");
+
+        let run = |program: &str| {
+            let mut vm = Engine::new();
+            vm.with_compilation_cache(&cache);
+            let result = vm.run(program).unwrap().pop().unwrap();
+            (result, vm.metrics().compilation_cache_hits)
+        };
+
+        assert_eq!(run(&program), (SteelVal::IntV(20), 0));
+        assert_eq!(run(&program), (SteelVal::IntV(20), 1));
+
+        fs::write(&module, "(provide rate) (define rate 3)").unwrap();
+        assert_eq!(run(&program), (SteelVal::IntV(30), 0));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn entries_are_checked_against_their_source() {
+        let first = cache_directory("first-source");
+        let second = cache_directory("second-source");
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&first);
+        vm.run("(+ 1 2)").unwrap();
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&second);
+        vm.run("(+ 10 20)").unwrap();
+
+        // Swap the entry for another program's in, as if their keys collided
+        let entry = |directory: &PathBuf| fs::read_dir(directory).unwrap().next().unwrap().unwrap();
+        fs::copy(entry(&first).path(), entry(&second).path()).unwrap();
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&second);
+        assert_eq!(vm.run("(+ 10 20)").unwrap(), vec![SteelVal::IntV(30)]);
+        assert_eq!(vm.metrics().compilation_cache_hits, 0);
+
+        fs::remove_dir_all(&first).unwrap();
+        fs::remove_dir_all(&second).unwrap();
+    }
+
+    #[test]
+    fn unreadable_entries_fall_back_to_compiling() {
+        let directory = cache_directory("corrupt");
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        vm.run("(+ 1 2)").unwrap();
+
+        for entry in fs::read_dir(&directory).unwrap() {
+            fs::write(entry.unwrap().path(), b"not a program").unwrap();
+        }
+
+        let mut vm = Engine::new();
+        vm.with_compilation_cache(&directory);
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+        assert_eq!(vm.metrics().compilation_cache_hits, 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -5,7 +5,10 @@ use super::{
     map::SymbolMap,
 };
 use crate::{
-    core::{instructions::Instruction, opcode::OpCode},
+    core::{
+        instructions::Instruction,
+        opcode::{super_instruction_payload, OpCode},
+    },
     parser::{ast::Atom, parser::SyntaxObject, span_visitor::get_span, tokens::TokenType},
     values::structs::SteelStruct,
 };
@@ -491,10 +494,15 @@ impl<'a> VisitorMut for CodeGenerator<'a> {
     }
 
     fn visit_apply(&mut self, apply: &crate::parser::ast::Apply) -> Self::Output {
-        // todo!()
         self.visit(&apply.func)?;
+        for arg in &apply.args {
+            self.visit(arg)?;
+        }
         self.visit(&apply.list)?;
-        self.push(Instruction::new_apply(apply.location.clone()));
+        self.push(Instruction::new_apply(
+            apply.location.clone(),
+            apply.args.len(),
+        ));
         Ok(())
     }
 
@@ -837,6 +845,15 @@ fn transform_tail_call(instructions: &mut [Instruction], defining_context: &str)
         if *index < 2 {
             continue;
         }
+
+        if let Some(x) = instructions.get_mut(index - 1) {
+            if x.op_code == OpCode::APPLY {
+                x.op_code = OpCode::TAILAPPLY;
+                transformed = true;
+                continue;
+            }
+        }
+
         let prev_instruction = instructions.get(index - 1);
         let prev_func_push = instructions.get(index - 2);
 
@@ -906,6 +923,15 @@ fn transform_letrec_tail_call(instructions: &mut [Instruction], defining_context
         if *index < 2 {
             continue;
         }
+
+        if let Some(x) = instructions.get_mut(index - 1) {
+            if x.op_code == OpCode::APPLY {
+                x.op_code = OpCode::TAILAPPLY;
+                transformed = true;
+                continue;
+            }
+        }
+
         let prev_instruction = instructions.get(index - 1);
         let prev_func_push = instructions.get(index - 2);
 
@@ -1056,6 +1082,126 @@ pub fn convert_call_globals(instructions: &mut [Instruction]) {
     }
 }
 
+// Comparisons and simple numeric operations show up constantly in loops. When calling one of
+// these builtins with the arity it expects, swap the global call out for a specialized
+// opcode so that the VM can operate on numbers directly instead of going through the function
+pub fn specialize_builtin_calls(instructions: &mut [Instruction]) {
+    if instructions.is_empty() {
+        return;
+    }
+
+    for i in 0..instructions.len() - 1 {
+        let call_global = instructions.get(i);
+        let pass = instructions.get(i + 1);
+
+        if let (
+            Some(Instruction {
+                op_code: OpCode::CALLGLOBAL,
+                contents:
+                    Some(SyntaxObject {
+                        ty: TokenType::Identifier(s),
+                        ..
+                    }),
+                ..
+            }),
+            Some(Instruction {
+                op_code: OpCode::PASS,
+                payload_size: arity,
+                ..
+            }),
+        ) = (call_global, pass)
+        {
+            let op_code = match OpCode::specialization_of(s, *arity) {
+                Some(op_code) => op_code,
+                None => continue,
+            };
+
+            if let Some(x) = instructions.get_mut(i) {
+                x.op_code = op_code;
+            }
+        }
+    }
+}
+
+// A comparison whose result is immediately consumed by an `if` doesn't need to push the boolean
+// onto the stack. Fuse these into a single compare-and-branch instruction, leaving the PASS, IF and
+// JMP in place so the VM can find the branch targets (and fall back to the normal call if needed)
+pub fn fuse_compare_and_branch(instructions: &mut [Instruction]) {
+    for i in 0..instructions.len() {
+        let compare = instructions.get(i);
+        let pass = instructions.get(i + 1);
+        let if_instr = instructions.get(i + 2);
+
+        if let (
+            Some(Instruction {
+                op_code, contents, ..
+            }),
+            Some(Instruction {
+                op_code: OpCode::PASS,
+                payload_size: 2,
+                ..
+            }),
+            Some(Instruction {
+                op_code: OpCode::IF,
+                ..
+            }),
+        ) = (compare, pass, if_instr)
+        {
+            let op_code = match (op_code, contents) {
+                (
+                    OpCode::CALLGLOBAL,
+                    Some(SyntaxObject {
+                        ty: TokenType::Identifier(s),
+                        ..
+                    }),
+                ) if s == "=" => OpCode::BRANCHEQ,
+                (op_code, _) => match op_code.branch_form() {
+                    Some(op_code) => op_code,
+                    None => continue,
+                },
+            };
+
+            if let Some(x) = instructions.get_mut(i) {
+                x.op_code = op_code;
+            }
+        }
+    }
+}
+
+// Pairs of instructions that run back to back the most get fused into a super instruction, which
+// runs both without going around the VM's loop in between. Only the first instruction is rewritten,
+// the second stays where it is so that jumping straight to it still works
+pub fn fuse_super_instructions(instructions: &mut [Instruction], patterns: &[(OpCode, OpCode)]) {
+    let mut i = 0;
+
+    while i + 1 < instructions.len() {
+        let pair = (instructions[i].op_code, instructions[i + 1].op_code);
+
+        // CGLOCALCONST reads the two instructions after it in place
+        let read_in_place =
+            (1..=2).any(|k| i >= k && instructions[i - k].op_code == OpCode::CGLOCALCONST);
+
+        let payload = if !read_in_place
+            && pair.0.fuses_first()
+            && pair.1.fuses_second()
+            && patterns.contains(&pair)
+        {
+            super_instruction_payload(pair.0, instructions[i].payload_size)
+        } else {
+            None
+        };
+
+        match payload {
+            Some(payload) => {
+                instructions[i].op_code = OpCode::SUPER;
+                instructions[i].payload_size = payload;
+                i += 2;
+            }
+            None => i += 1,
+        }
+    }
+}
+
 // 0    READLOCAL : 0
 // 1    LOADINT2 : 12
 // 2    CALLGLOBAL : 6
@@ -1189,6 +1335,15 @@ fn check_and_transform_mutual_recursion(instructions: &mut [Instruction]) -> boo
         if *index < 2 {
             continue;
         }
+
+        if let Some(x) = instructions.get_mut(index - 1) {
+            if x.op_code == OpCode::APPLY {
+                x.op_code = OpCode::TAILAPPLY;
+                transformed = true;
+                continue;
+            }
+        }
+
         let prev_instruction = instructions.get(index - 1);
         let prev_func_push = instructions.get(index - 2);
 
@@ -1246,6 +1401,7 @@ fn eval_atom(t: &SyntaxObject) -> Result<SteelVal> {
         TokenType::StringLiteral(s) => Ok(SteelVal::StringV(s.clone().into())),
         TokenType::CharacterLiteral(c) => Ok(SteelVal::CharV(*c)),
         TokenType::IntegerLiteral(n) => Ok(SteelVal::IntV(*n)),
+        TokenType::Keyword(k) => Ok(SteelVal::KeywordV(k.clone().into())),
         what => {
             // println!("getting here in the eval_atom");
             stop!(UnexpectedToken => what; t.span)
@@ -1,39 +1,64 @@
 use crate::compiler::{
+    cache::{CacheKey, CachedConstant, CachedProgram, CompilationCache},
     code_generator::{convert_call_globals, CodeGenerator},
     constants::{ConstantMap, ConstantTable},
     map::SymbolMap,
-    passes::begin::flatten_begins_and_expand_defines,
-    program::Program,
+    passes::{
+        begin::flatten_begins_and_expand_defines,
+        constant_folding::fold_constants,
+        dead_code::eliminate_dead_code,
+        inline::inline_functions,
+        matches::{lower_matches, CompileWarning, LOWERED_FORMS},
+    },
+    program::{checksum, Executable, MappedExecutable, Program},
+    timings::CompileTimings,
 };
 use crate::core::{instructions::Instruction, opcode::OpCode};
+use crate::docs::{DocEntry, Docs};
 
+use std::convert::TryFrom;
 use std::iter::Iterator;
 use std::{
+    cell::RefCell,
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{Result, SteelVal};
 
-use crate::parser::ast::ExprKind;
+use crate::parser::ast::{Define, ExprKind};
+use crate::parser::cond_expand::{default_features, resolve_conditionals};
+use crate::parser::expand_visitor::extract_procedural_macros;
 use crate::parser::expander::SteelMacro;
+use crate::parser::include::expand_includes;
 use crate::parser::parser::SyntaxObject;
 use crate::parser::parser::{ParseError, Parser};
-// use crate::parser::span::Span;
+use crate::parser::serializable_lambda::expand_serializable_lambdas;
+use crate::parser::span::Span;
 use crate::parser::tokens::TokenType;
+use crate::parser::tracing::{lower_tracing, TRACING, TRACING_FORMS};
 
 use crate::values::structs::SteelStruct;
 
 use crate::core::instructions::{densify, DenseInstruction};
+use crate::core::super_patterns::DYNAMIC_SUPER_PATTERNS;
 
 use crate::stop;
 
 use log::debug;
 
-use crate::steel_vm::const_evaluation::ConstantEvaluatorManager;
+use crate::steel_vm::kernel::Kernel;
 
-use super::{code_generator::loop_condition_local_const_arity_two, modules::ModuleManager};
+use super::{
+    code_generator::{
+        fuse_compare_and_branch, fuse_super_instructions, loop_condition_local_const_arity_two,
+        specialize_builtin_calls,
+    },
+    modules::{ExportSummary, ModuleManager, ModuleReload},
+};
 
 use im_rc::HashMap as ImmutableHashMap;
 
@@ -219,6 +244,19 @@ fn inject_heap_save_to_pop(instructions: &mut [Instruction]) {
     }
 }
 
+fn parse_program(expr_str: &str, path: &Option<PathBuf>) -> Result<Vec<ExprKind>> {
+    let mut intern = HashMap::new();
+
+    // Could fail here
+    let parsed: std::result::Result<Vec<ExprKind>, ParseError> = if let Some(p) = path {
+        Parser::new_from_source(expr_str, &mut intern, p.clone()).collect()
+    } else {
+        Parser::new(expr_str, &mut intern).collect()
+    };
+
+    Ok(parsed?)
+}
+
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub enum OptLevel {
     Zero = 0,
@@ -227,12 +265,49 @@ pub enum OptLevel {
     Three,
 }
 
+/// What happens when a program defines a global that is already defined, either by an earlier
+/// program or earlier in the same one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RedefinitionPolicy {
+    /// The new definition replaces the old one, and code calling the global picks it up. This
+    /// is what a REPL wants.
+    #[default]
+    Allow,
+    /// Like `Allow`, with a compile warning for each redefinition
+    Warn,
+    /// Programs redefining a global fail to compile, and executables that do fail when they
+    /// get to the redefinition
+    Error,
+}
+
+// The defines at the top level of the program, including those spliced in with `begin`
+fn top_level_defines<'a>(exprs: &'a [ExprKind], defines: &mut Vec<&'a Define>) {
+    for expr in exprs {
+        match expr {
+            ExprKind::Define(define) => defines.push(define),
+            ExprKind::Begin(begin) => top_level_defines(&begin.exprs, defines),
+            _ => {}
+        }
+    }
+}
+
 pub struct Compiler {
     pub(crate) symbol_map: SymbolMap,
     pub(crate) constant_map: ConstantMap,
     pub(crate) macro_env: HashMap<String, SteelMacro>,
     module_manager: ModuleManager,
     opt_level: OptLevel,
+    dead_code_elimination: bool,
+    inlining: bool,
+    redefinition_policy: RedefinitionPolicy,
+    docs: Rc<RefCell<Docs>>,
+    pub(crate) features: HashSet<String>,
+    compile_errors: usize,
+    warnings: Vec<CompileWarning>,
+    cache: Option<CompilationCache>,
+    kernel: Option<Rc<RefCell<Kernel>>>,
+    // How long the phases of compiling the last program took
+    timings: CompileTimings,
 }
 
 impl Compiler {
@@ -248,6 +323,16 @@ impl Compiler {
             macro_env,
             module_manager,
             opt_level: OptLevel::Three,
+            dead_code_elimination: true,
+            inlining: true,
+            redefinition_policy: RedefinitionPolicy::default(),
+            docs: Rc::new(RefCell::new(Docs::default())),
+            features: default_features(),
+            compile_errors: 0,
+            warnings: Vec::new(),
+            cache: None,
+            kernel: None,
+            timings: CompileTimings::default(),
         }
     }
 
@@ -260,6 +345,64 @@ impl Compiler {
         )
     }
 
+    /// A compiler that starts out knowing everything this one does. The symbols and constants
+    /// are shared until one of them adds more, and the compiled modules are never copied.
+    pub(crate) fn fork(&self) -> Compiler {
+        Compiler {
+            symbol_map: self.symbol_map.clone(),
+            constant_map: self.constant_map.clone(),
+            macro_env: self.macro_env.clone(),
+            module_manager: self.module_manager.fork(),
+            opt_level: self.opt_level,
+            dead_code_elimination: self.dead_code_elimination,
+            inlining: self.inlining,
+            redefinition_policy: self.redefinition_policy,
+            docs: Rc::clone(&self.docs),
+            features: self.features.clone(),
+            compile_errors: 0,
+            warnings: Vec::new(),
+            cache: self.cache.clone(),
+            kernel: self
+                .kernel
+                .as_ref()
+                .map(|kernel| Rc::new(RefCell::new(kernel.borrow().fork()))),
+            timings: CompileTimings::default(),
+        }
+    }
+
+    pub(crate) fn set_opt_level(&mut self, opt_level: OptLevel) {
+        self.opt_level = opt_level;
+    }
+
+    pub(crate) fn set_dead_code_elimination(&mut self, enabled: bool) {
+        self.dead_code_elimination = enabled;
+    }
+
+    pub(crate) fn set_inlining(&mut self, enabled: bool) {
+        self.inlining = enabled;
+    }
+
+    fn inline(&self, exprs: Vec<ExprKind>) -> (Vec<ExprKind>, bool) {
+        if self.inlining {
+            inline_functions(exprs)
+        } else {
+            (exprs, false)
+        }
+    }
+
+    pub(crate) fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+        self.redefinition_policy = policy;
+    }
+
+    pub(crate) fn redefinition_policy(&self) -> RedefinitionPolicy {
+        self.redefinition_policy
+    }
+
+    // Shared with `%doc`, so that it sees what gets documented later on
+    pub(crate) fn docs_handle(&self) -> Rc<RefCell<Docs>> {
+        Rc::clone(&self.docs)
+    }
+
     /// Registers a name in the underlying symbol map and returns the idx that it maps to
     pub fn register(&mut self, name: &str) -> usize {
         self.symbol_map.get_or_add(name)
@@ -278,31 +421,249 @@ impl Compiler {
         path: Option<PathBuf>,
         constants: ImmutableHashMap<String, SteelVal>,
     ) -> Result<Program> {
-        let instructions = self.emit_instructions(expr_str, path, constants)?;
+        if self.cache.is_some() {
+            return self.rolling_back_errors(|compiler| {
+                compiler.compile_program_with_cache(expr_str, path, constants)
+            });
+        }
+
+        let instructions = self.rolling_back_errors(|compiler| {
+            compiler.emit_instructions(expr_str, path, constants)
+        })?;
 
         // TODO Perhaps use a different representation for the constant map
         let program = Program::new(instructions, self.constant_map.clone());
         Ok(program)
     }
 
+    /// Compiles a program to be run later by [`load_executable`](Compiler::load_executable),
+    /// possibly in another engine
+    pub fn compile_executable(
+        &mut self,
+        expr_str: &str,
+        path: Option<PathBuf>,
+        constants: ImmutableHashMap<String, SteelVal>,
+    ) -> Result<Executable> {
+        let (symbol_map, constant_map) = (self.symbol_map.clone(), self.constant_map.clone());
+        let executable = self.rolling_back_errors(|compiler| {
+            let instructions = compiler.emit_instructions(expr_str, path, constants)?;
+            let constants = match compiler.cached_constants() {
+                Some(constants) => constants,
+                None => {
+                    stop!(Generic => "the program uses constants that can't be saved as bytecode")
+                }
+            };
+
+            Ok(Executable::new(
+                instructions,
+                symbol_map.len(),
+                compiler.symbol_map.copy_underlying_vec(),
+                constants,
+                compiler.module_manager.required_checksums(),
+            ))
+        });
+
+        // The globals the program defines only exist once it runs, which may be somewhere else
+        self.symbol_map = symbol_map;
+        self.constant_map = constant_map;
+        executable
+    }
+
+    /// Puts the compiler in the state compiling the executable left its compiler in, so that
+    /// it can run. This only works if the executable was compiled against the same globals
+    /// as this compiler has.
+    pub(crate) fn load_executable(&mut self, executable: Executable) -> Result<Program> {
+        if !executable.compiled_for(&self.symbol_map.copy_underlying_vec()) {
+            stop!(Generic => "the program was compiled by an engine that was set up differently");
+        }
+        executable.check_modules()?;
+
+        let (instructions, symbols, constants) = executable.into_parts();
+
+        let constants = constants
+            .into_iter()
+            .map(CachedConstant::into_steelval)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.symbol_map = SymbolMap::from_symbols(symbols);
+        self.constant_map = ConstantMap::from_values(constants);
+
+        Ok(Program::new(instructions, self.constant_map.clone()))
+    }
+
+    /// Sets up the globals and constants of a mapped executable, leaving its instructions
+    /// to be read as it runs
+    pub(crate) fn load_mapped_executable<B: AsRef<[u8]>>(
+        &mut self,
+        executable: &MappedExecutable<B>,
+    ) -> Result<ConstantMap> {
+        if !executable.compiled_for(&self.symbol_map.copy_underlying_vec()) {
+            stop!(Generic => "the program was compiled by an engine that was set up differently");
+        }
+        executable.check_modules()?;
+
+        let constants = executable
+            .constants()
+            .iter()
+            .cloned()
+            .map(CachedConstant::into_steelval)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.symbol_map = SymbolMap::from_symbols(executable.symbols().to_vec());
+        self.constant_map = ConstantMap::from_values(constants);
+
+        Ok(self.constant_map.clone())
+    }
+
+    /// Compile a program that has already been parsed, or was built directly as syntax
+    pub fn compile_exprs(
+        &mut self,
+        exprs: Vec<ExprKind>,
+        path: Option<PathBuf>,
+        constants: ImmutableHashMap<String, SteelVal>,
+    ) -> Result<Program> {
+        let instructions = self.rolling_back_errors(|compiler| {
+            compiler.emit_instructions_from_exprs(exprs, path, constants)
+        })?;
+        Ok(Program::new(instructions, self.constant_map.clone()))
+    }
+
+    /// Saves compiled programs to `directory`, so that compiling the same source again
+    /// against the same definitions, macros and modules can skip straight to running it.
+    /// Programs loaded from the cache are still expanded, since that's where the macros and
+    /// modules they add to the compiler come from.
+    pub fn with_compilation_cache(&mut self, directory: PathBuf) -> &mut Self {
+        self.cache = Some(CompilationCache::new(directory));
+        self
+    }
+
     pub fn emit_instructions(
         &mut self,
         expr_str: &str,
         path: Option<PathBuf>,
         constants: ImmutableHashMap<String, SteelVal>,
     ) -> Result<Vec<Vec<DenseInstruction>>> {
-        let mut intern = HashMap::new();
+        let start = Instant::now();
+        let parsed = parse_program(expr_str, &path)?;
+        let parse = start.elapsed();
 
-        // Could fail here
-        let parsed: std::result::Result<Vec<ExprKind>, ParseError> = if let Some(p) = &path {
-            Parser::new_from_source(expr_str, &mut intern, p.clone()).collect()
-        } else {
-            Parser::new(expr_str, &mut intern).collect()
-        };
+        let instructions = self.emit_instructions_from_exprs(parsed, path, constants);
+        self.timings.parse = parse;
+        instructions
+    }
 
-        let parsed = parsed?;
+    fn compile_program_with_cache(
+        &mut self,
+        expr_str: &str,
+        path: Option<PathBuf>,
+        constants: ImmutableHashMap<String, SteelVal>,
+    ) -> Result<Program> {
+        let start = Instant::now();
+        let parsed = parse_program(expr_str, &path)?;
+        let parse = start.elapsed();
+        // Included files are part of the key, so they have to be read in first
+        let exprs = expand_includes(parsed, path.as_deref())?;
+
+        let macros = self.macro_fingerprint();
+
+        let key = self.cached_constants().map(|cached_constants| {
+            let mut key = CacheKey::new();
+            let mut features: Vec<_> = self.features.iter().collect();
+            features.sort();
+            let mut constant_names: Vec<_> = constants.keys().collect();
+            constant_names.sort();
+            let mut module_paths: Vec<_> =
+                self.module_manager.compile_times().into_keys().collect();
+            module_paths.sort();
+
+            key.add(expr_str)
+                .add(&exprs.iter().map(|x| x.to_string()).collect::<Vec<_>>())
+                .add(&path)
+                .add(&self.symbol_map.copy_underlying_vec())
+                .add(&cached_constants)
+                .add(&macros)
+                .add(&features)
+                .add(&constant_names)
+                .add(&module_paths)
+                .add(&(self.opt_level as u8))
+                .add(&self.dead_code_elimination)
+                .add(&self.inlining)
+                .add(&(self.redefinition_policy as u8));
+            key
+        });
+
+        if let Some(program) = key
+            .as_ref()
+            .and_then(|key| self.cache.as_ref()?.load(key, expr_str))
+        {
+            // Loading the program doesn't bring back the macros, modules and docs compiling it
+            // adds to the compiler, which all come from expanding it
+            let start = Instant::now();
+            self.expand_expressions(exprs, path)?;
+            let program = self.load_cached_program(program)?;
+            self.timings = CompileTimings {
+                parse,
+                expand: start.elapsed(),
+                ..CompileTimings::default()
+            };
+            return Ok(program);
+        }
 
-        self.emit_instructions_from_exprs(parsed, path, constants)
+        let instructions = self.emit_instructions_from_exprs(exprs, path, constants);
+        self.timings.parse = parse;
+        let instructions = instructions?;
+
+        if let (Some(key), Some(constants)) = (&key, self.cached_constants()) {
+            let program = CachedProgram {
+                instructions: instructions.clone(),
+                symbols: self.symbol_map.copy_underlying_vec(),
+                constants,
+                source: checksum(expr_str.as_bytes()),
+                modules: self.module_manager.required_checksums(),
+            };
+
+            if let Some(cache) = &self.cache {
+                cache.store(key, &program);
+            }
+        }
+
+        Ok(Program::new(instructions, self.constant_map.clone()))
+    }
+
+    fn load_cached_program(&mut self, program: CachedProgram) -> Result<Program> {
+        let constants = program
+            .constants
+            .into_iter()
+            .map(CachedConstant::into_steelval)
+            .collect::<Result<Vec<_>>>()?;
+
+        // Put the compiler back in the state compiling the program would have left it in
+        self.symbol_map = SymbolMap::from_symbols(program.symbols);
+        self.constant_map = ConstantMap::from_values(constants);
+
+        if let Some(cache) = &mut self.cache {
+            cache.record_hit();
+        }
+
+        Ok(Program::new(
+            program.instructions,
+            self.constant_map.clone(),
+        ))
+    }
+
+    // `None` if any of the constants can't be written out
+    fn cached_constants(&self) -> Option<Vec<CachedConstant>> {
+        self.constant_map
+            .values()
+            .iter()
+            .map(|x| CachedConstant::try_from(x).ok())
+            .collect()
+    }
+
+    fn macro_fingerprint(&self) -> Vec<String> {
+        let mut macros: Vec<_> = self.macro_env.iter().collect();
+        macros.sort_by(|a, b| a.0.cmp(b.0));
+        macros.iter().map(|x| format!("{:?}", x)).collect()
     }
 
     pub fn emit_debug_instructions(
@@ -340,18 +701,29 @@ impl Compiler {
 
         match self.opt_level {
             OptLevel::Three => loop {
-                let mut manager = ConstantEvaluatorManager::new(constants.clone(), self.opt_level);
-                expanded_statements = manager.run(expanded_statements)?;
-                if !manager.changed {
+                let (folded, folded_any) =
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?;
+                let (inlined, changed) = self.inline(folded);
+                expanded_statements = inlined;
+                if !folded_any && !changed {
                     break;
                 }
             },
             OptLevel::Two => {
                 expanded_statements =
-                    ConstantEvaluatorManager::new(constants.clone(), self.opt_level)
-                        .run(expanded_statements)?;
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?.0;
             }
-            _ => {}
+            _ => loop {
+                let (inlined, changed) = self.inline(expanded_statements);
+                expanded_statements = inlined;
+                if !changed {
+                    break;
+                }
+            },
+        }
+
+        if self.dead_code_elimination {
+            expanded_statements = eliminate_dead_code(expanded_statements);
         }
 
         // let expanded_statements =
@@ -367,14 +739,175 @@ impl Compiler {
         exprs: Vec<ExprKind>,
         path: Option<PathBuf>,
     ) -> Result<Vec<ExprKind>> {
+        let exprs = expand_includes(exprs, path.as_deref())?;
+        let exprs = resolve_conditionals(exprs, &self.features)?;
+        let traced = TRACING_FORMS
+            .iter()
+            .copied()
+            .filter(|x| self.get_idx(x).is_some())
+            .collect::<Vec<_>>();
+        let exprs = lower_tracing(exprs, self.features.contains(TRACING), &traced)?;
+        let exprs = expand_serializable_lambdas(exprs)?;
+        let exprs = extract_procedural_macros(exprs, &mut self.macro_env, &mut self.kernel)?;
+        let module = path
+            .as_ref()
+            .map(|x| x.display().to_string())
+            .unwrap_or_else(|| "top level".to_string());
+
         #[cfg(feature = "modules")]
-        return self
-            .module_manager
-            .compile_main(&mut self.macro_env, exprs, path);
+        let exprs =
+            self.module_manager
+                .compile_main(&mut self.macro_env, exprs, path, &self.features)?;
 
         #[cfg(not(feature = "modules"))]
+        let exprs = self
+            .module_manager
+            .expand_expressions(&mut self.macro_env, exprs)?;
+
+        let start = Instant::now();
+        self.warnings.clear();
+        let defined = LOWERED_FORMS
+            .iter()
+            .copied()
+            .filter(|x| self.get_idx(x).is_some())
+            .collect::<Vec<_>>();
+        let exprs = lower_matches(exprs, &mut self.warnings, &defined)?;
+        self.check_redefinitions(&exprs)?;
+        self.collect_docs(&exprs, module);
+        self.timings.analysis = start.elapsed();
+        Ok(exprs)
+    }
+
+    // Done before dead code elimination, which drops the doc strings
+    fn collect_docs(&mut self, exprs: &[ExprKind], module: String) {
+        let mut docs = self.docs.borrow_mut();
         self.module_manager
-            .expand_expressions(&mut self.macro_env, exprs)
+            .docs()
+            .into_iter()
+            .for_each(|x| docs.insert(x));
+
+        let mut defines = Vec::new();
+        top_level_defines(exprs, &mut defines);
+        for define in defines {
+            if let Some(entry) = DocEntry::from_define(define, &module) {
+                docs.insert(entry);
+            }
+        }
+    }
+
+    fn check_redefinitions(&mut self, exprs: &[ExprKind]) -> Result<()> {
+        if self.redefinition_policy == RedefinitionPolicy::Allow {
+            return Ok(());
+        }
+
+        let mut defines = Vec::new();
+        top_level_defines(exprs, &mut defines);
+
+        let mut defined = HashSet::new();
+        for define in defines {
+            let name = match define.name.atom_identifier_or_else(|| {}) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            // Defines made up by the compiler, like those bringing in what a required module
+            // provides each time it's required, have no span, and names starting with `#`
+            let span = define.location.span;
+            let fresh = defined.insert(name);
+            if span == Span::new(0, 0)
+                || name.starts_with('#')
+                || (fresh && self.get_idx(name).is_none())
+            {
+                continue;
+            }
+
+            if self.redefinition_policy == RedefinitionPolicy::Error {
+                stop!(BadSyntax => "{} is already defined, and can't be defined again", name; span);
+            }
+            self.warnings.push(CompileWarning {
+                message: format!("{} is already defined, this definition replaces it", name),
+                span,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Compiles with `compile`, counting it if it fails and forgetting the globals and constants
+    // it got as far as adding, so that a corrected program compiles as if it was the first
+    fn rolling_back_errors<T>(
+        &mut self,
+        compile: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let (symbol_map, constant_map) = (self.symbol_map.clone(), self.constant_map.clone());
+        let result = compile(self);
+        if result.is_err() {
+            self.compile_errors += 1;
+            self.symbol_map = symbol_map;
+            self.constant_map = constant_map;
+        }
+        result
+    }
+
+    /// The number of programs that failed to compile
+    pub fn compile_errors(&self) -> usize {
+        self.compile_errors
+    }
+
+    /// The warnings from the last program that was compiled
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
+    }
+
+    /// The number of programs loaded from the compilation cache instead of being compiled
+    pub fn compilation_cache_hits(&self) -> usize {
+        self.cache.as_ref().map(CompilationCache::hits).unwrap_or(0)
+    }
+
+    /// How long each required module took to compile the last time it was compiled
+    pub fn module_compile_times(&self) -> HashMap<PathBuf, Duration> {
+        self.module_manager.compile_times()
+    }
+
+    /// Compiles a program that requires the module at `path` again, after dropping it and the
+    /// modules that depend on it from the module cache. The program redefines the globals the
+    /// module provides, so it never comes from the compilation cache and can't fall foul of
+    /// the redefinition policy.
+    pub(crate) fn compile_module_reload(
+        &mut self,
+        path: &Path,
+        constants: ImmutableHashMap<String, SteelVal>,
+    ) -> Result<(Program, ModuleReload)> {
+        let path = match self.module_manager.cached_path(path) {
+            Some(path) => path,
+            None => {
+                stop!(Generic => format!("{} hasn't been required, so it can't be reloaded", path.display()))
+            }
+        };
+
+        let before = self.module_manager.provided(&path);
+        let folded = self.module_manager.folded(&path);
+        self.module_manager.invalidate(&path);
+
+        let policy = std::mem::take(&mut self.redefinition_policy);
+        let instructions =
+            self.emit_instructions(&format!("(require {:?})", path), None, constants);
+        self.redefinition_policy = policy;
+
+        let after = self.module_manager.provided(&path);
+        Ok((
+            Program::new(instructions?, self.constant_map.clone()),
+            ModuleReload::new(before, after, folded),
+        ))
+    }
+
+    /// The arity, purity and constant value of everything each required module provides
+    pub fn module_summaries(&self) -> HashMap<PathBuf, HashMap<String, ExportSummary>> {
+        self.module_manager.summaries()
+    }
+
+    pub fn module_exports(&self) -> Vec<(PathBuf, Vec<String>)> {
+        self.module_manager.exports()
     }
 
     // This only works at the top level
@@ -386,7 +919,7 @@ impl Compiler {
     ) -> Result<Vec<ExprKind>> {
         let mut non_structs = Vec::new();
         let mut struct_instructions = Vec::new();
-        for expr in exprs {
+        for expr in splice_struct_begins(exprs) {
             if let ExprKind::Struct(s) = expr {
                 let builder = SteelStruct::generate_from_ast(&s)?;
 
@@ -423,7 +956,7 @@ impl Compiler {
     ) -> Result<Vec<ExprKind>> {
         let mut non_structs = Vec::new();
         let mut struct_instructions = Vec::new();
-        for expr in exprs {
+        for expr in splice_struct_begins(exprs) {
             if let ExprKind::Struct(s) = expr {
                 let builder = SteelStruct::generate_from_ast(&s)?;
 
@@ -478,9 +1011,15 @@ impl Compiler {
         convert_call_globals(&mut instruction_buffer);
         replace_defines_with_debruijn_indices(&mut instruction_buffer, &mut self.symbol_map)?;
 
+        specialize_builtin_calls(&mut instruction_buffer);
+        fuse_compare_and_branch(&mut instruction_buffer);
+
         // TODO
         loop_condition_local_const_arity_two(&mut instruction_buffer);
 
+        // The disassembly leaves these out, and shows the instructions they're made of instead
+        fuse_super_instructions(&mut instruction_buffer, DYNAMIC_SUPER_PATTERNS);
+
         for idx in index_buffer {
             let extracted: Vec<Instruction> = instruction_buffer.drain(0..idx).collect();
             // pretty_print_instructions(extracted.as_slice());
@@ -514,6 +1053,9 @@ impl Compiler {
         convert_call_globals(&mut instruction_buffer);
         replace_defines_with_debruijn_indices(&mut instruction_buffer, &mut self.symbol_map)?;
 
+        specialize_builtin_calls(&mut instruction_buffer);
+        fuse_compare_and_branch(&mut instruction_buffer);
+
         // TODO
         loop_condition_local_const_arity_two(&mut instruction_buffer);
 
@@ -549,18 +1091,29 @@ impl Compiler {
 
         match self.opt_level {
             OptLevel::Three => loop {
-                let mut manager = ConstantEvaluatorManager::new(constants.clone(), self.opt_level);
-                expanded_statements = manager.run(expanded_statements)?;
-                if !manager.changed {
+                let (folded, folded_any) =
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?;
+                let (inlined, changed) = self.inline(folded);
+                expanded_statements = inlined;
+                if !folded_any && !changed {
                     break;
                 }
             },
             OptLevel::Two => {
                 expanded_statements =
-                    ConstantEvaluatorManager::new(constants.clone(), self.opt_level)
-                        .run(expanded_statements)?;
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?.0;
             }
-            _ => {}
+            _ => loop {
+                let (inlined, changed) = self.inline(expanded_statements);
+                expanded_statements = inlined;
+                if !changed {
+                    break;
+                }
+            },
+        }
+
+        if self.dead_code_elimination {
+            expanded_statements = eliminate_dead_code(expanded_statements);
         }
 
         let expanded_statements = flatten_begins_and_expand_defines(expanded_statements);
@@ -587,7 +1140,20 @@ impl Compiler {
     ) -> Result<Vec<Vec<DenseInstruction>>> {
         let mut results = Vec::new();
 
+        self.timings = CompileTimings::default();
+        let modules_before = self.module_manager.compile_times();
+        let start = Instant::now();
         let expanded_statements = self.expand_expressions(exprs, path)?;
+        self.timings.expand = start.elapsed().saturating_sub(self.timings.analysis);
+
+        let mut modules: Vec<_> = self
+            .module_manager
+            .compile_times()
+            .into_iter()
+            .filter(|(path, _)| !modules_before.contains_key(path))
+            .collect();
+        modules.sort_by_key(|x| std::cmp::Reverse(x.1));
+        self.timings.modules = modules;
 
         debug!(
             "Generating instructions for the expression: {:?}",
@@ -601,21 +1167,46 @@ impl Compiler {
 
         match self.opt_level {
             OptLevel::Three => loop {
-                let mut manager = ConstantEvaluatorManager::new(constants.clone(), self.opt_level);
-                expanded_statements = manager.run(expanded_statements)?;
-
-                if !manager.changed {
+                let start = Instant::now();
+                let (folded, folded_any) =
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?;
+                self.timings
+                    .add_pass("constant evaluation", start.elapsed());
+
+                let start = Instant::now();
+                let (inlined, changed) = self.inline(folded);
+                expanded_statements = inlined;
+                self.timings.add_pass("inlining", start.elapsed());
+                if !folded_any && !changed {
                     break;
                 }
             },
             OptLevel::Two => {
+                let start = Instant::now();
                 expanded_statements =
-                    ConstantEvaluatorManager::new(constants.clone(), self.opt_level)
-                        .run(expanded_statements)?;
+                    fold_constants(expanded_statements, constants.clone(), self.opt_level)?.0;
+                self.timings
+                    .add_pass("constant evaluation", start.elapsed());
             }
-            _ => {}
+            _ => loop {
+                let start = Instant::now();
+                let (inlined, changed) = self.inline(expanded_statements);
+                expanded_statements = inlined;
+                self.timings.add_pass("inlining", start.elapsed());
+                if !changed {
+                    break;
+                }
+            },
+        }
+
+        if self.dead_code_elimination {
+            let start = Instant::now();
+            expanded_statements = eliminate_dead_code(expanded_statements);
+            self.timings
+                .add_pass("dead code elimination", start.elapsed());
         }
 
+        let start = Instant::now();
         debug!("About to expand defines");
         let expanded_statements = flatten_begins_and_expand_defines(expanded_statements);
 
@@ -637,6 +1228,28 @@ impl Compiler {
         //         .join("\n\n")
         // );
 
-        self.generate_dense_instructions(statements_without_structs, results)
+        let instructions = self.generate_dense_instructions(statements_without_structs, results);
+        self.timings.codegen = start.elapsed();
+        instructions
+    }
+
+    /// How long the phases of compiling the last program took
+    pub fn timings(&self) -> &CompileTimings {
+        &self.timings
+    }
+}
+
+// A `begin` at the top level that defines a struct, like the ones `define-record-type` expands
+// into, is spliced in so that the struct is defined at the top level along with the rest
+fn splice_struct_begins(exprs: Vec<ExprKind>) -> Vec<ExprKind> {
+    let mut spliced = Vec::with_capacity(exprs.len());
+    for expr in exprs {
+        match expr {
+            ExprKind::Begin(b) if b.exprs.iter().any(|x| matches!(x, ExprKind::Struct(_))) => {
+                spliced.extend(splice_struct_begins(b.exprs))
+            }
+            other => spliced.push(other),
+        }
     }
+    spliced
 }
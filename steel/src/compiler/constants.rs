@@ -11,9 +11,11 @@ use std::convert::TryFrom;
 // use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
+use std::rc::Rc;
 
+// Shared with the programs compiled against it, and with forks of the engine, until it changes
 #[derive(Debug, PartialEq, Clone)]
-pub struct ConstantMap(Vec<SteelVal>);
+pub struct ConstantMap(Rc<Vec<SteelVal>>);
 
 // #[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 // struct ConstantExprMap {
@@ -22,7 +24,15 @@ pub struct ConstantMap(Vec<SteelVal>);
 
 impl ConstantMap {
     pub fn new() -> ConstantMap {
-        ConstantMap(Vec::new())
+        ConstantMap(Rc::new(Vec::new()))
+    }
+
+    pub(crate) fn from_values(values: Vec<SteelVal>) -> ConstantMap {
+        ConstantMap(Rc::new(values))
+    }
+
+    pub(crate) fn values(&self) -> &[SteelVal] {
+        &self.0
     }
 
     fn to_constant_expr_map(&self) -> Vec<String> {
@@ -59,7 +69,7 @@ impl ConstantMap {
                 Ok(SteelVal::try_from(parsed[0].clone()).unwrap())
             })
             .collect::<Result<Vec<_>>>()
-            .map(ConstantMap)
+            .map(ConstantMap::from_values)
     }
 
     // pub fn from_bytes(encoded: &[u8]) -> ConstantMap {
@@ -70,7 +80,7 @@ impl ConstantMap {
 impl ConstantTable for ConstantMap {
     fn add(&mut self, val: SteelVal) -> usize {
         let idx = self.0.len();
-        self.0.push(val);
+        Rc::make_mut(&mut self.0).push(val);
         idx
     }
 
@@ -101,12 +111,12 @@ impl ConstantTable for ConstantMap {
     }
 
     fn roll_back(&mut self, idx: usize) {
-        self.0.truncate(idx);
+        Rc::make_mut(&mut self.0).truncate(idx);
     }
 
     #[cfg(test)]
     fn clear(&mut self) {
-        self.0.clear()
+        Rc::make_mut(&mut self.0).clear()
     }
 }
 
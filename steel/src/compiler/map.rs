@@ -3,12 +3,19 @@ use crate::rvals::Result;
 use crate::stop;
 use crate::values::structs::StructFuncBuilder;
 
-#[derive(Debug, PartialEq)]
-pub struct SymbolMap(Vec<String>);
+use std::rc::Rc;
+
+// Shared with forks of the engine until one of them adds a symbol
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolMap(Rc<Vec<String>>);
 
 impl SymbolMap {
     pub fn new() -> Self {
-        SymbolMap(Vec::new())
+        SymbolMap(Rc::new(Vec::new()))
+    }
+
+    pub(crate) fn from_symbols(symbols: Vec<String>) -> Self {
+        SymbolMap(Rc::new(symbols))
     }
 
     pub fn is_empty(&self) -> bool {
@@ -17,13 +24,18 @@ impl SymbolMap {
 
     pub fn add(&mut self, ident: &str) -> usize {
         let idx = self.0.len();
-        self.0.push(ident.to_string());
+        Rc::make_mut(&mut self.0).push(ident.to_string());
         // println!("`add`: {} @ {}", ident, idx);
         idx
     }
 
+    /// The name of the global at `idx`
+    pub(crate) fn name(&self, idx: usize) -> Option<&str> {
+        self.0.get(idx).map(String::as_str)
+    }
+
     pub fn copy_underlying_vec(&self) -> Vec<String> {
-        self.0.clone()
+        self.0.to_vec()
     }
 
     pub fn get_or_add(&mut self, ident: &str) -> usize {
@@ -38,7 +50,7 @@ impl SymbolMap {
         }
 
         let idx = self.0.len();
-        self.0.push(ident.to_string());
+        Rc::make_mut(&mut self.0).push(ident.to_string());
 
         // println!("`get_or_add` - ADD: {} @ {}", ident, idx);
         // println!("Adding {} with index {}", ident, idx);
@@ -65,7 +77,7 @@ impl SymbolMap {
 
     pub fn roll_back(&mut self, idx: usize) {
         // println!("Rolling back to: {}", idx);
-        self.0.truncate(idx);
+        Rc::make_mut(&mut self.0).truncate(idx);
 
         // unimplemented!()
     }
@@ -1,3 +1,4 @@
+pub(crate) mod cache;
 pub mod code_generator;
 pub mod compiler;
 pub mod constants;
@@ -5,3 +6,4 @@ pub mod map;
 pub mod modules;
 pub mod passes;
 pub mod program;
+pub mod timings;
@@ -1,5 +1,8 @@
+use crate::compiler::passes::VisitorMutUnit;
+use crate::compiler::program::checksum;
+use crate::docs::DocEntry;
 use crate::parser::{
-    ast::{Atom, ExprKind, List},
+    ast::{Atom, Begin, Define, ExprKind, LambdaFunction, List, Quote, Set},
     parser::{ParseError, Parser, SyntaxObject},
     tokens::TokenType,
 };
@@ -10,15 +13,20 @@ use crate::rerrs::{ErrorKind, SteelErr};
 use std::{
     collections::{HashMap, HashSet},
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 
 use crate::parser::expander::SteelMacro;
+use crate::steel_vm::primitives::BUILTIN_MODULES;
 use crate::stop;
 
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::parser::cond_expand::resolve_conditionals;
 use crate::parser::expand_visitor::{expand, extract_macro_defs};
+use crate::parser::include::expand_includes;
+use crate::parser::serializable_lambda::expand_serializable_lambdas;
 
 use itertools::Itertools;
 use log::debug;
@@ -28,20 +36,23 @@ use log::debug;
 /// Also keeps track of the metadata for each file in order to determine
 /// if it needs to be recompiled
 pub(crate) struct ModuleManager {
-    compiled_modules: HashMap<PathBuf, CompiledModule>,
+    compiled_modules: HashMap<PathBuf, Rc<CompiledModule>>,
     file_metadata: HashMap<PathBuf, SystemTime>,
     visited: HashSet<PathBuf>,
+    // The modules the last program compiled required itself
+    required: Vec<PathBuf>,
 }
 
 impl ModuleManager {
     pub(crate) fn new(
-        compiled_modules: HashMap<PathBuf, CompiledModule>,
+        compiled_modules: HashMap<PathBuf, Rc<CompiledModule>>,
         file_metadata: HashMap<PathBuf, SystemTime>,
     ) -> Self {
         ModuleManager {
             compiled_modules,
             file_metadata,
             visited: HashSet::new(),
+            required: Vec::new(),
         }
     }
 
@@ -49,11 +60,22 @@ impl ModuleManager {
         Self::new(HashMap::new(), HashMap::new())
     }
 
+    /// A manager with the same cache, where the compiled modules themselves are shared
+    pub(crate) fn fork(&self) -> Self {
+        ModuleManager {
+            compiled_modules: self.compiled_modules.clone(),
+            file_metadata: self.file_metadata.clone(),
+            visited: self.visited.clone(),
+            required: self.required.clone(),
+        }
+    }
+
     pub(crate) fn compile_main(
         &mut self,
         global_macro_map: &mut HashMap<String, SteelMacro>,
         exprs: Vec<ExprKind>,
         path: Option<PathBuf>,
+        features: &HashSet<String>,
     ) -> Result<Vec<ExprKind>> {
         // Wipe the visited set on entry
         self.visited.clear();
@@ -69,9 +91,11 @@ impl ModuleManager {
             &mut self.compiled_modules,
             &mut self.visited,
             &mut self.file_metadata,
+            features,
         )?;
 
         let mut module_statements = module_builder.compile()?;
+        self.required = module_builder.requires.clone();
 
         module_statements.append(&mut module_builder.source_ast);
 
@@ -81,6 +105,124 @@ impl ModuleManager {
             .collect::<Result<_>>()
     }
 
+    /// How long each module in the cache took to compile
+    pub(crate) fn compile_times(&self) -> HashMap<PathBuf, Duration> {
+        self.compiled_modules
+            .iter()
+            .map(|(name, module)| (name.clone(), module.compile_time))
+            .collect()
+    }
+
+    /// Every module the last program compiled required, directly or not, along with the
+    /// checksum of the source it was compiled from
+    pub(crate) fn required_checksums(&self) -> Vec<(PathBuf, u64)> {
+        let mut seen = HashSet::new();
+        let mut pending = self.required.clone();
+        let mut checksums = Vec::new();
+
+        while let Some(path) = pending.pop() {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            if let Some(module) = self.compiled_modules.get(&path) {
+                checksums.push((path, module.checksum));
+                pending.extend(module.dependencies.iter().cloned());
+            }
+        }
+
+        checksums.sort();
+        checksums
+    }
+
+    /// What each module in the cache provides, as seen by the modules that require it
+    pub(crate) fn summaries(&self) -> HashMap<PathBuf, HashMap<String, ExportSummary>> {
+        self.compiled_modules
+            .iter()
+            .map(|(name, module)| (name.clone(), module.summary.clone()))
+            .collect()
+    }
+
+    /// The path `path` is cached under, if it is a module that has been required
+    pub(crate) fn cached_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = std::fs::canonicalize(path).ok()?;
+        self.compiled_modules
+            .keys()
+            .find(|x| std::fs::canonicalize(x).ok().as_ref() == Some(&path))
+            .cloned()
+    }
+
+    /// Every module in the cache along with the names it provides
+    pub(crate) fn exports(&self) -> Vec<(PathBuf, Vec<String>)> {
+        self.compiled_modules
+            .keys()
+            .map(|path| (path.clone(), self.provided(path)))
+            .collect()
+    }
+
+    /// The names the cached module at `path` provides
+    pub(crate) fn provided(&self, path: &Path) -> Vec<String> {
+        self.compiled_modules
+            .get(path)
+            .map(|x| {
+                provided_names(&x.provides)
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The names the module at `path` provides that are folded into the code requiring it
+    pub(crate) fn folded(&self, path: &Path) -> Vec<String> {
+        self.compiled_modules
+            .get(path)
+            .map(|x| {
+                x.summary
+                    .iter()
+                    .filter(|(_, export)| export.constant.is_some() || export.function.is_some())
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drops the module at `path` from the cache, along with every module that requires it
+    /// directly or not. Those inline the module they require, so they have to be compiled
+    /// again as well the next time they're required.
+    pub(crate) fn invalidate(&mut self, path: &Path) {
+        let mut stale = HashSet::new();
+        stale.insert(path.to_path_buf());
+
+        loop {
+            let dependents: Vec<_> = self
+                .compiled_modules
+                .iter()
+                .filter(|(name, module)| {
+                    !stale.contains(*name) && module.dependencies.iter().any(|x| stale.contains(x))
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            if dependents.is_empty() {
+                break;
+            }
+            stale.extend(dependents);
+        }
+
+        for name in &stale {
+            self.compiled_modules.remove(name);
+            self.file_metadata.remove(name);
+        }
+    }
+
+    /// The doc strings of what each module in the cache provides
+    pub(crate) fn docs(&self) -> Vec<DocEntry> {
+        self.compiled_modules
+            .values()
+            .flat_map(|x| x.docs())
+            .collect()
+    }
+
     #[cfg(not(feature = "modules"))]
     pub(crate) fn expand_expressions(
         &mut self,
@@ -100,11 +242,255 @@ pub struct CompiledModule {
     provides: Vec<ExprKind>,
     requires: Vec<ExprKind>,
     ast: Vec<ExprKind>,
+    compile_time: Duration,
+    summary: HashMap<String, ExportSummary>,
+    // The paths of the modules it requires
+    dependencies: Vec<PathBuf>,
+    checksum: u64,
+}
+
+/// How the exports of a module changed when it was reloaded, see
+/// [`Engine::reload_module`](crate::steel_vm::engine::Engine::reload_module)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleReload {
+    /// Provided now, but not before
+    pub added: Vec<String>,
+    /// Provided before, but not anymore. The globals they were bound to keep their old values.
+    pub removed: Vec<String>,
+    /// Provided before and now, bound to their new definitions
+    pub updated: Vec<String>,
+    /// Provided before and now, bound to their new definitions, but their old values were
+    /// constants or `define/pure` functions that got folded into the code that required the
+    /// module. That code keeps the old values until it's compiled again.
+    pub folded: Vec<String>,
+}
+
+impl ModuleReload {
+    // `folded` are the names whose old values could have been folded into other code
+    pub(crate) fn new(before: Vec<String>, after: Vec<String>, folded: Vec<String>) -> Self {
+        let mut reload = ModuleReload::default();
+        for name in &after {
+            if folded.contains(name) {
+                reload.folded.push(name.clone());
+            } else if before.contains(name) {
+                reload.updated.push(name.clone());
+            } else {
+                reload.added.push(name.clone());
+            }
+        }
+        reload.removed = before.into_iter().filter(|x| !after.contains(x)).collect();
+
+        reload.added.sort();
+        reload.removed.sort();
+        reload.updated.sort();
+        reload.folded.sort();
+        reload
+    }
+}
+
+/// What a requiring module can know about something a module provides, without having to
+/// look inside of the module
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportSummary {
+    /// The number of arguments it takes, if it is a function
+    pub arity: Option<usize>,
+    /// Whether it is a function declared with `define/pure`
+    pub pure: bool,
+    /// The literal it is defined as, if it is never changed
+    pub constant: Option<ExprKind>,
+    // A pure function that doesn't refer to anything private to the module, which can be
+    // copied into the requiring module so calls to it with constant arguments get folded
+    function: Option<Box<LambdaFunction>>,
+}
+
+// The names a module provides, along with whether they're provided with a contract
+fn provided_names(provides: &[ExprKind]) -> Vec<(String, bool)> {
+    let mut names = Vec::new();
+    for provide in provides {
+        let args = match provide {
+            ExprKind::List(l) => &l.args[1..],
+            _ => continue,
+        };
+
+        for arg in args {
+            match arg {
+                ExprKind::List(l) if l.first_ident() == Some("contract/out") => {
+                    if let Some(Ok(name)) = l.args.get(1).map(|x| x.atom_identifier_or_else(|| ()))
+                    {
+                        names.push((name.to_string(), true));
+                    }
+                }
+                _ => {
+                    if let Ok(name) = arg.atom_identifier_or_else(|| ()) {
+                        names.push((name.to_string(), false));
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+// Every identifier referred to in the expressions, and every variable that gets `set!`
+#[derive(Default)]
+struct References {
+    identifiers: HashSet<String>,
+    assigned: HashSet<String>,
+}
+
+impl VisitorMutUnit for References {
+    fn visit_atom(&mut self, a: &Atom) {
+        if let TokenType::Identifier(ident) = &a.syn.ty {
+            self.identifiers.insert(ident.clone());
+        }
+    }
+
+    fn visit_set(&mut self, s: &Set) {
+        if let Ok(name) = s.variable.atom_identifier_or_else(|| ()) {
+            self.assigned.insert(name.to_string());
+        }
+        self.visit(&s.expr);
+    }
+
+    fn visit_quote(&mut self, _quote: &Quote) {}
+}
+
+// Summarizes each name the module provides without a contract. `private` is every other name
+// bound inside of the module, which a copied function can't refer to.
+fn summarize(
+    provides: &[ExprKind],
+    ast: &[ExprKind],
+    mut private: HashSet<String>,
+) -> HashMap<String, ExportSummary> {
+    let mut references = References::default();
+    ast.iter().for_each(|x| references.visit(x));
+
+    let mut defines: HashMap<&str, Vec<&Define>> = HashMap::new();
+    for expr in ast {
+        if let ExprKind::Define(d) = expr {
+            if let Ok(name) = d.name.atom_identifier_or_else(|| ()) {
+                defines.entry(name).or_default().push(d);
+                private.insert(name.to_string());
+            }
+        }
+    }
+
+    let mut summary = HashMap::new();
+    for (name, contracted) in provided_names(provides) {
+        let define = match defines.get(name.as_str()).map(|x| x.as_slice()) {
+            Some([define]) if !contracted => define,
+            _ => continue,
+        };
+        let fixed = !references.assigned.contains(&name);
+
+        let export = match &define.body {
+            ExprKind::Atom(a) => ExportSummary {
+                arity: None,
+                pure: false,
+                constant: match a.syn.ty {
+                    TokenType::Identifier(_) => None,
+                    _ if fixed => Some(define.body.clone()),
+                    _ => None,
+                },
+                function: None,
+            },
+            ExprKind::LambdaFunction(l) => {
+                let mut body = References::default();
+                body.visit(&l.body);
+                let self_contained = body
+                    .identifiers
+                    .iter()
+                    .filter(|x| **x != name)
+                    .all(|x| !private.contains(x));
+
+                ExportSummary {
+                    arity: Some(l.args.len()),
+                    pure: define.is_pure(),
+                    constant: None,
+                    function: if define.is_pure() && fixed && self_contained {
+                        Some(l.clone())
+                    } else {
+                        None
+                    },
+                }
+            }
+            _ => ExportSummary {
+                arity: None,
+                pure: false,
+                constant: None,
+                function: None,
+            },
+        };
+        summary.insert(name, export);
+    }
+    summary
 }
 
 impl CompiledModule {
-    // Turn the module into the AST node that represents the macro module in the stdlib
+    fn docs(&self) -> Vec<DocEntry> {
+        let provided: HashSet<_> = provided_names(&self.provides)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+
+        self.ast
+            .iter()
+            .filter_map(|expr| match expr {
+                ExprKind::Define(d) => {
+                    let name = d.name.atom_identifier_or_else(|| ()).ok()?;
+                    if !provided.contains(name) {
+                        return None;
+                    }
+                    DocEntry::from_define(d, &self.name.display().to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Turn the module into the AST node that represents the macro module in the stdlib,
+    // followed by the constants and pure functions it provides, so that they can be folded
+    // into the code that requires the module
     fn to_module_ast_node(&self) -> ExprKind {
+        let module = self.to_macro_module();
+
+        let mut specialized = Vec::new();
+        for (name, _) in provided_names(&self.provides) {
+            let export = match self.summary.get(&name) {
+                Some(export) => export,
+                None => continue,
+            };
+            let name = ExprKind::Atom(Atom::new(SyntaxObject::default(TokenType::Identifier(
+                name,
+            ))));
+
+            if let Some(constant) = &export.constant {
+                specialized.push(ExprKind::Define(Box::new(Define::new(
+                    name,
+                    constant.clone(),
+                    SyntaxObject::default(TokenType::Define),
+                ))));
+            } else if let Some(function) = &export.function {
+                specialized.push(ExprKind::Define(Box::new(Define::new(
+                    name,
+                    ExprKind::LambdaFunction(function.clone()),
+                    SyntaxObject::default(TokenType::DefinePure),
+                ))));
+            }
+        }
+
+        if specialized.is_empty() {
+            return module;
+        }
+
+        specialized.insert(0, module);
+        ExprKind::Begin(Begin::new(
+            specialized,
+            SyntaxObject::default(TokenType::Begin),
+        ))
+    }
+
+    fn to_macro_module(&self) -> ExprKind {
         let mut body = vec![
             ExprKind::Atom(Atom::new(SyntaxObject::default(TokenType::Identifier(
                 "module".to_string(),
@@ -134,18 +520,23 @@ struct ModuleBuilder<'a> {
     macro_map: HashMap<String, SteelMacro>,
     requires: Vec<PathBuf>,
     provides: Vec<ExprKind>,
-    compiled_modules: &'a mut HashMap<PathBuf, CompiledModule>,
+    compiled_modules: &'a mut HashMap<PathBuf, Rc<CompiledModule>>,
     visited: &'a mut HashSet<PathBuf>,
     file_metadata: &'a mut HashMap<PathBuf, SystemTime>,
+    features: &'a HashSet<String>,
+    started: Instant,
+    // Of the source the module was read from
+    checksum: u64,
 }
 
 impl<'a> ModuleBuilder<'a> {
     fn main(
         name: Option<PathBuf>,
         source_ast: Vec<ExprKind>,
-        compiled_modules: &'a mut HashMap<PathBuf, CompiledModule>,
+        compiled_modules: &'a mut HashMap<PathBuf, Rc<CompiledModule>>,
         visited: &'a mut HashSet<PathBuf>,
         file_metadata: &'a mut HashMap<PathBuf, SystemTime>,
+        features: &'a HashSet<String>,
     ) -> Result<Self> {
         // TODO don't immediately canonicalize the path unless we _know_ its coming from a path
         // change the path to not always be required
@@ -167,6 +558,9 @@ impl<'a> ModuleBuilder<'a> {
             compiled_modules,
             visited,
             file_metadata,
+            features,
+            started: Instant::now(),
+            checksum: 0,
         })
     }
 
@@ -184,7 +578,7 @@ impl<'a> ModuleBuilder<'a> {
         }
 
         if self.visited.contains(&self.name) {
-            stop!(Generic => format!("circular dependency found during module resolution with: {:?}", self.name))
+            stop!(Generic => "circular dependency found during module resolution with: {:?}", self.name)
         }
 
         self.visited.insert(self.name.clone());
@@ -247,6 +641,7 @@ impl<'a> ModuleBuilder<'a> {
                     &mut self.compiled_modules,
                     &mut self.visited,
                     &mut self.file_metadata,
+                    self.features,
                 )?;
 
                 // Walk the tree and compile any dependencies
@@ -291,24 +686,32 @@ impl<'a> ModuleBuilder<'a> {
         let provides = std::mem::replace(&mut self.provides, Vec::new());
 
         let mut requires = Vec::new();
+        let mut required_names = HashSet::new();
 
         for require in &self.requires {
-            let m = self
-                .compiled_modules
-                .get(require)
-                .unwrap()
-                .to_module_ast_node();
-            requires.push(m);
+            let required = self.compiled_modules.get(require).unwrap();
+            required_names.extend(
+                provided_names(&required.provides)
+                    .into_iter()
+                    .map(|(name, _)| name),
+            );
+            requires.push(required.to_module_ast_node());
         }
 
+        let ast = ast
+            .into_iter()
+            .map(|x| expand(x, &self.macro_map))
+            .collect::<Result<Vec<_>>>()?;
+
         let module = CompiledModule {
             name: self.name.clone(),
+            summary: summarize(&provides, &ast, required_names),
             provides,
             requires,
-            ast: ast
-                .into_iter()
-                .map(|x| expand(x, &self.macro_map))
-                .collect::<Result<Vec<_>>>()?,
+            ast,
+            compile_time: self.started.elapsed(),
+            dependencies: self.requires.clone(),
+            checksum: self.checksum,
         };
         let result = module.to_module_ast_node();
         // println!(
@@ -318,7 +721,8 @@ impl<'a> ModuleBuilder<'a> {
 
         debug!("Adding {:?} to the module cache", self.name);
 
-        self.compiled_modules.insert(self.name.clone(), module);
+        self.compiled_modules
+            .insert(self.name.clone(), Rc::new(module));
 
         Ok(result)
     }
@@ -385,6 +789,11 @@ impl<'a> ModuleBuilder<'a> {
                             },
                     } = atom
                     {
+                        // Builtin modules are already part of the engine
+                        if BUILTIN_MODULES.contains(&s.as_str()) {
+                            continue;
+                        }
+
                         let mut current = self.name.clone();
                         if current.is_file() {
                             current.pop();
@@ -412,18 +821,21 @@ impl<'a> ModuleBuilder<'a> {
 
     fn new_from_path(
         name: PathBuf,
-        compiled_modules: &'a mut HashMap<PathBuf, CompiledModule>,
+        compiled_modules: &'a mut HashMap<PathBuf, Rc<CompiledModule>>,
         visited: &'a mut HashSet<PathBuf>,
         file_metadata: &'a mut HashMap<PathBuf, SystemTime>,
+        features: &'a HashSet<String>,
     ) -> Result<Self> {
-        ModuleBuilder::raw(name, compiled_modules, visited, file_metadata).parse_from_path()
+        ModuleBuilder::raw(name, compiled_modules, visited, file_metadata, features)
+            .parse_from_path()
     }
 
     fn raw(
         name: PathBuf,
-        compiled_modules: &'a mut HashMap<PathBuf, CompiledModule>,
+        compiled_modules: &'a mut HashMap<PathBuf, Rc<CompiledModule>>,
         visited: &'a mut HashSet<PathBuf>,
         file_metadata: &'a mut HashMap<PathBuf, SystemTime>,
+        features: &'a HashSet<String>,
     ) -> Self {
         ModuleBuilder {
             name,
@@ -435,6 +847,9 @@ impl<'a> ModuleBuilder<'a> {
             compiled_modules,
             visited,
             file_metadata,
+            features,
+            started: Instant::now(),
+            checksum: 0,
         }
     }
 
@@ -444,13 +859,16 @@ impl<'a> ModuleBuilder<'a> {
             .insert(self.name.clone(), file.metadata()?.modified()?);
         let mut exprs = String::new();
         file.read_to_string(&mut exprs)?;
+        self.checksum = checksum(exprs.as_bytes());
 
         let mut intern = HashMap::new();
 
         let parsed = Parser::new_from_source(&exprs, &mut intern, self.name.clone())
             .collect::<std::result::Result<Vec<_>, ParseError>>()?;
 
-        self.source_ast = parsed;
+        let parsed = expand_includes(parsed, Some(&self.name))?;
+        self.source_ast =
+            expand_serializable_lambdas(resolve_conditionals(parsed, self.features)?)?;
 
         Ok(self)
     }
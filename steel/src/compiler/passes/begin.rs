@@ -159,7 +159,8 @@ impl<'a> ExpressionType<'a> {
             | TokenType::NumberLiteral(_)
             | TokenType::StringLiteral(_)
             | TokenType::CharacterLiteral(_)
-            | TokenType::IntegerLiteral(_) => true,
+            | TokenType::IntegerLiteral(_)
+            | TokenType::Keyword(_) => true,
             _ => false,
         }
     }
@@ -0,0 +1,75 @@
+use crate::compiler::compiler::OptLevel;
+use crate::parser::ast::ExprKind;
+use crate::rvals::{Result, SteelVal};
+use crate::steel_vm::const_evaluation::ConstantEvaluatorManager;
+
+use im_rc::HashMap as ImmutableHashMap;
+
+/// Replaces calls to builtins whose arguments are all constants with the values they return,
+/// using the constant evaluator. That covers arithmetic, `string-append`, and lists built out of
+/// constants, which become quoted lists:
+///
+/// ```scheme
+/// (define (greeting) (string-append "hello" " world"))
+/// (define (pair) (list (* 2 3) "x"))
+/// ;; =>
+/// (define (greeting) "hello world")
+/// (define (pair) (quote (6 "x")))
+/// ```
+///
+/// `constants` are the globals known to be constant, like the builtins themselves. Also returns
+/// whether anything changed, since folding can open up more inlining and the other way around.
+pub fn fold_constants(
+    exprs: Vec<ExprKind>,
+    constants: ImmutableHashMap<String, SteelVal>,
+    opt_level: OptLevel,
+) -> Result<(Vec<ExprKind>, bool)> {
+    let mut manager = ConstantEvaluatorManager::new(constants, opt_level);
+    let folded = manager.run(exprs)?;
+    Ok((folded, manager.changed))
+}
+
+#[cfg(test)]
+mod constant_folding_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn expand(program: &str) -> String {
+        let mut vm = Engine::new();
+        vm.set_inlining(false).set_dead_code_elimination(false);
+        vm.emit_fully_expanded_ast_to_string(program).unwrap()
+    }
+
+    #[test]
+    fn arithmetic_strings_and_lists_are_folded() {
+        assert_eq!(expand("(+ 1 (* 2 3))"), "7");
+        assert_eq!(expand("(string-append \"a\" \"b\")"), "\"ab\"");
+
+        let expanded = expand("(define (pair) (list (* 2 3) \"x\"))");
+        assert!(
+            expanded.contains("(quote\n      (6 \"x\"))"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn calls_with_arguments_that_arent_constant_are_kept() {
+        let expanded = expand("(define (f x) (+ x (* 2 3)))");
+        assert!(expanded.contains("(+ x 6)"), "{}", expanded);
+    }
+
+    #[test]
+    fn folding_can_be_turned_off() {
+        let program = "(define (f) (string-append \"a\" \"b\")) (f)";
+
+        let mut vm = Engine::new();
+        vm.set_constant_folding(false);
+        let expanded = vm.emit_fully_expanded_ast_to_string(program).unwrap();
+        assert!(expanded.contains("string-append"));
+        assert_eq!(
+            vm.run(program).unwrap().pop(),
+            Some(SteelVal::StringV("ab".into()))
+        );
+    }
+}
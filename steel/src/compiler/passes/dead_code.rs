@@ -0,0 +1,212 @@
+use crate::parser::ast::{Atom, Begin, Define, ExprKind, LambdaFunction, Quote};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::tokens::TokenType;
+use std::collections::HashSet;
+
+use super::{Folder, VisitorMutUnit};
+
+/// Removes code that can't have any effect on the program:
+///
+/// * expressions in the middle of a `begin` whose values are thrown away, as long as evaluating
+///   them can't do anything - literals, quoted data and lambdas
+/// * defines inside of a function body whose name is never referred to, as long as their value
+///   can't do anything either
+///
+/// ```scheme
+/// (define (f x)
+///   (define (unused y) y)
+///   "docs"
+///   (+ x 1))
+/// ;; =>
+/// (define (f x) (+ x 1))
+/// ```
+///
+/// Top level defines are always kept, since later programs run on the same engine can still
+/// refer to them by name.
+pub fn eliminate_dead_code(exprs: Vec<ExprKind>) -> Vec<ExprKind> {
+    DeadCodeEliminator { depth: 0 }.fold(exprs)
+}
+
+struct DeadCodeEliminator {
+    // How many functions deep we are, defines are only local inside of one
+    depth: usize,
+}
+
+// Whether evaluating the expression can't have any effect other than producing its value
+fn is_pure(expr: &ExprKind) -> bool {
+    match expr {
+        ExprKind::Atom(Atom { syn }) => !matches!(syn.ty, TokenType::Identifier(_)),
+        ExprKind::Quote(_) | ExprKind::LambdaFunction(_) => true,
+        _ => false,
+    }
+}
+
+fn defined_name(define: &Define) -> Option<&str> {
+    match &define.name {
+        ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(name),
+                    ..
+                },
+        }) => Some(name),
+        _ => None,
+    }
+}
+
+// Every identifier referred to in the expressions
+#[derive(Default)]
+struct References(HashSet<String>);
+
+impl VisitorMutUnit for References {
+    fn visit_atom(&mut self, a: &Atom) {
+        if let TokenType::Identifier(ident) = &a.syn.ty {
+            self.0.insert(ident.clone());
+        }
+    }
+
+    fn visit_quote(&mut self, _quote: &Quote) {}
+}
+
+// A `begin` inside of another one gets spliced into it, like the ones macros expand to, so its
+// defines belong to the enclosing scope and have to be judged against all of it
+fn splice(exprs: Vec<ExprKind>) -> Vec<ExprKind> {
+    let mut spliced = Vec::with_capacity(exprs.len());
+    for expr in exprs {
+        match expr {
+            ExprKind::Begin(b) if !b.exprs.is_empty() => spliced.extend(splice(b.exprs)),
+            expr => spliced.push(expr),
+        }
+    }
+    spliced
+}
+
+impl DeadCodeEliminator {
+    // Whether the expression at `index` of a function body can be dropped
+    fn is_dead(&self, exprs: &[ExprKind], index: usize) -> bool {
+        match &exprs[index] {
+            ExprKind::Define(d) if self.depth > 0 && is_pure(&d.body) => {
+                let name = match defined_name(d) {
+                    Some(name) => name,
+                    None => return false,
+                };
+
+                // A function that only calls itself is still unused
+                let referenced_elsewhere = exprs
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != index)
+                    .any(|(_, expr)| {
+                        let mut references = References::default();
+                        references.visit(expr);
+                        references.0.contains(name)
+                    });
+
+                !referenced_elsewhere
+            }
+            expr => is_pure(expr),
+        }
+    }
+}
+
+impl Folder for DeadCodeEliminator {
+    fn visit_lambda_function(&mut self, mut lambda_function: Box<LambdaFunction>) -> ExprKind {
+        self.depth += 1;
+        lambda_function.body = self.visit(lambda_function.body);
+        self.depth -= 1;
+        ExprKind::LambdaFunction(lambda_function)
+    }
+
+    fn visit_begin(&mut self, begin: Begin) -> ExprKind {
+        let location = begin.location;
+        let exprs: Vec<_> = splice(begin.exprs)
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect();
+
+        // The last expression is the value of the begin, so it always stays
+        let last = exprs.len().saturating_sub(1);
+        let dead: Vec<bool> = (0..exprs.len())
+            .map(|i| i != last && self.is_dead(&exprs, i))
+            .collect();
+
+        let mut exprs: Vec<_> = exprs
+            .into_iter()
+            .zip(dead)
+            .filter(|(_, dead)| !dead)
+            .map(|(expr, _)| expr)
+            .collect();
+
+        if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            ExprKind::Begin(Begin::new(exprs, location))
+        }
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> ExprKind {
+        ExprKind::Quote(quote)
+    }
+}
+
+#[cfg(test)]
+mod dead_code_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn expand(program: &str) -> String {
+        let mut vm = Engine::new();
+        vm.set_constant_folding(false).set_inlining(false);
+        vm.emit_fully_expanded_ast_to_string(program).unwrap()
+    }
+
+    #[test]
+    fn unused_local_defines_are_removed() {
+        let expanded = expand(
+            r#"
+            (define (f x)
+              (define (unused y) (unused y))
+              (define (used y) (+ y 1))
+              (used x))
+            "#,
+        );
+        assert!(!expanded.contains("unused"));
+        assert!(expanded.contains("used"));
+    }
+
+    #[test]
+    fn values_that_are_thrown_away_are_removed() {
+        let expanded = expand("(define (f x) \"docs\" 'data (lambda () x) (display x) x)");
+        assert!(!expanded.contains("docs"));
+        assert!(!expanded.contains("data"));
+        assert!(!expanded.contains("lambda ()"));
+        assert!(expanded.contains("display"));
+    }
+
+    #[test]
+    fn top_level_defines_and_results_are_kept() {
+        let mut vm = Engine::new();
+        let results = vm.run("(define (unused) 1) 10 (begin 1 2)").unwrap();
+        assert_eq!(results[1..], [SteelVal::IntV(10), SteelVal::IntV(2)]);
+        assert_eq!(vm.run("(unused)").unwrap(), vec![SteelVal::IntV(1)]);
+    }
+
+    #[test]
+    fn defines_from_macros_are_judged_against_the_whole_body() {
+        let mut vm = Engine::new();
+        let results = vm
+            .run(
+                r#"
+                (define-syntax two-defs
+                  (syntax-rules ()
+                    [(_ a b) (begin (define (a) 10) (define (b) 20))]))
+                (define (f)
+                  (two-defs x y)
+                  (+ (x) (y)))
+                (f)
+                "#,
+            )
+            .unwrap();
+        assert_eq!(results.last(), Some(&SteelVal::IntV(30)));
+    }
+}
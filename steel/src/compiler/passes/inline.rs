@@ -0,0 +1,454 @@
+use crate::parser::ast::{Atom, Define, ExprKind, LambdaFunction, List, Quote, Return, Set};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::tokens::TokenType;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::{Folder, VisitorMutUnit};
+
+// Functions with bodies up to this many expressions get inlined wherever they're called
+const INLINE_THRESHOLD: usize = 16;
+
+// The keyword a function body can start with to always be inlined
+const INLINE_MARKER: &str = "inline";
+
+/// Replaces calls to small functions with their bodies, so that the constant evaluator can
+/// fold them with the arguments they're called with.
+///
+/// ```scheme
+/// (define (area r)
+///   (define (square x) (* x x))
+///   (* 3 (square r)))
+/// ;; =>
+/// (define (area r)
+///   (define (square x) (* x x))
+///   (* 3 (* r r)))
+/// ```
+///
+/// A function is inlined when its body doesn't define, capture, `set!` or call anything it
+/// would have to bind itself, and it is either small, called from only one place, or marked
+/// by starting its body with `#:inline`. Only local functions are inlined automatically, since
+/// a top level one can be redefined by a later program. Top level functions are inlined into
+/// the rest of the program they're defined in when they're marked:
+///
+/// ```scheme
+/// (define (square x) #:inline (* x x))
+/// ```
+///
+/// Calls are only inlined when every argument is a constant or a variable, so nothing gets
+/// evaluated more than once or out of order. Returns whether anything was inlined.
+pub fn inline_functions(exprs: Vec<ExprKind>) -> (Vec<ExprKind>, bool) {
+    let mut inliner = Inliner {
+        scopes: Vec::new(),
+        changed: false,
+    };
+
+    let mut exprs = exprs;
+    let scope = candidates(&mut exprs, true);
+    inliner.scopes.push(scope);
+    let exprs = inliner.fold(exprs);
+
+    (exprs, inliner.changed)
+}
+
+struct Candidate {
+    params: Vec<String>,
+    body: ExprKind,
+    // The variables the body refers to that it doesn't bind, which have to mean the same thing
+    // wherever it gets inlined
+    free: HashSet<String>,
+    depth: usize,
+}
+
+// What each name in scope is bound to, with the functions that can be inlined
+type Scope = HashMap<String, Option<Rc<Candidate>>>;
+
+struct Inliner {
+    scopes: Vec<Scope>,
+    changed: bool,
+}
+
+fn identifier(expr: &ExprKind) -> Option<&str> {
+    match expr {
+        ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(s),
+                    ..
+                },
+        }) => Some(s),
+        _ => None,
+    }
+}
+
+// Removes the `#:inline` a function body starts with, returning whether it was there
+fn strip_marker(lambda: &mut LambdaFunction) -> bool {
+    let exprs = match &mut lambda.body {
+        ExprKind::Begin(b) => &mut b.exprs,
+        _ => return false,
+    };
+
+    let marked = matches!(exprs.first(), Some(ExprKind::Atom(Atom {
+        syn: SyntaxObject { ty: TokenType::Keyword(k), .. },
+    })) if k == INLINE_MARKER);
+
+    if marked && exprs.len() > 1 {
+        exprs.remove(0);
+        if exprs.len() == 1 {
+            lambda.body = exprs.pop().unwrap();
+        }
+    }
+    marked
+}
+
+// Counts each identifier, and checks whether the expressions bind anything themselves
+#[derive(Default)]
+struct Usage {
+    references: HashMap<String, usize>,
+    assigned: HashSet<String>,
+    size: usize,
+    binds: bool,
+}
+
+impl VisitorMutUnit for Usage {
+    fn visit_atom(&mut self, a: &Atom) {
+        self.size += 1;
+        if let TokenType::Identifier(ident) = &a.syn.ty {
+            *self.references.entry(ident.clone()).or_insert(0) += 1;
+        }
+    }
+
+    fn visit_list(&mut self, l: &List) {
+        self.size += 1;
+        l.args.iter().for_each(|x| self.visit(x));
+    }
+
+    fn visit_quote(&mut self, _quote: &Quote) {
+        self.size += 1;
+    }
+
+    fn visit_define(&mut self, define: &Define) {
+        self.binds = true;
+        self.visit(&define.body);
+    }
+
+    fn visit_lambda_function(&mut self, lambda_function: &LambdaFunction) {
+        self.binds = true;
+        self.visit(&lambda_function.body);
+    }
+
+    fn visit_set(&mut self, s: &Set) {
+        self.binds = true;
+        if let Some(name) = identifier(&s.variable) {
+            self.assigned.insert(name.to_string());
+        }
+        self.visit(&s.expr);
+    }
+
+    fn visit_return(&mut self, r: &Return) {
+        self.binds = true;
+        self.visit(&r.expr);
+    }
+}
+
+fn usage(exprs: &[ExprKind]) -> Usage {
+    let mut usage = Usage::default();
+    exprs.iter().for_each(|x| usage.visit(x));
+    usage
+}
+
+// The defines of a scope, including those in a `begin` that gets spliced into it, like the
+// ones macros expand to
+fn defines(exprs: &mut [ExprKind]) -> Vec<&mut Define> {
+    let mut defines = Vec::new();
+    for expr in exprs {
+        match expr {
+            ExprKind::Define(d) => defines.push(d.as_mut()),
+            ExprKind::Begin(b) => defines.extend(self::defines(&mut b.exprs)),
+            _ => {}
+        }
+    }
+    defines
+}
+
+// The functions defined in a scope that can be inlined, along with every other name it binds
+fn candidates(exprs: &mut [ExprKind], top_level: bool) -> Scope {
+    let scope_usage = usage(exprs);
+    let mut defines = defines(exprs);
+
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    for define in &defines {
+        if let Some(name) = identifier(&define.name) {
+            *definitions.entry(name.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    let mut scope = Scope::new();
+    for define in defines.iter_mut() {
+        let name = match identifier(&define.name) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let lambda = match &mut define.body {
+            ExprKind::LambdaFunction(l) => l,
+            _ => {
+                scope.insert(name, None);
+                continue;
+            }
+        };
+
+        let marked = strip_marker(lambda);
+        let params: Option<Vec<String>> = lambda
+            .args
+            .iter()
+            .map(|x| identifier(x).map(|x| x.to_string()))
+            .collect();
+
+        let body = usage(std::slice::from_ref(&lambda.body));
+        let calls = scope_usage.references.get(&name).copied().unwrap_or(0);
+
+        let inlinable = match params {
+            Some(params)
+                if (marked || !top_level)
+                    && (marked || calls == 1 || body.size <= INLINE_THRESHOLD)
+                    && !body.binds
+                    && !body.references.contains_key(&name)
+                    && !scope_usage.assigned.contains(&name)
+                    && definitions[&name] == 1 =>
+            {
+                let free = body
+                    .references
+                    .into_keys()
+                    .filter(|x| !params.contains(x))
+                    .collect();
+                Some(Rc::new(Candidate {
+                    params,
+                    body: lambda.body.clone(),
+                    free,
+                    depth: 0,
+                }))
+            }
+            _ => None,
+        };
+
+        scope.insert(name, inlinable);
+    }
+
+    scope
+}
+
+impl Inliner {
+    // How deep the scope the name is bound in is, and what it is bound to
+    fn lookup(&self, name: &str) -> Option<(usize, Option<Rc<Candidate>>)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.get(name).map(|x| (depth, x.clone())))
+    }
+
+    fn push_scope(&mut self, mut scope: Scope) {
+        let depth = self.scopes.len();
+        for candidate in scope.values_mut().flatten() {
+            if let Some(c) = Rc::get_mut(candidate) {
+                c.depth = depth;
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    fn inline(&mut self, l: &List) -> Option<ExprKind> {
+        let name = identifier(l.args.first()?)?;
+        let candidate = match self.lookup(name)? {
+            (_, Some(candidate)) => candidate,
+            _ => return None,
+        };
+
+        let args = &l.args[1..];
+        if args.len() != candidate.params.len()
+            || !args.iter().all(|x| matches!(x, ExprKind::Atom(_)))
+        {
+            return None;
+        }
+
+        // The body's variables can't have been shadowed between the definition and the call
+        let shadowed = candidate.free.iter().any(|x| match self.lookup(x) {
+            Some((depth, _)) => depth > candidate.depth,
+            None => false,
+        });
+        if shadowed {
+            return None;
+        }
+
+        let bindings: HashMap<&str, &ExprKind> = candidate
+            .params
+            .iter()
+            .map(|x| x.as_str())
+            .zip(args.iter())
+            .collect();
+        Some(Substitute { bindings }.visit(candidate.body.clone()))
+    }
+}
+
+impl Folder for Inliner {
+    fn visit_lambda_function(&mut self, mut lambda_function: Box<LambdaFunction>) -> ExprKind {
+        let mut scope = match &mut lambda_function.body {
+            ExprKind::Begin(b) => candidates(&mut b.exprs, false),
+            _ => Scope::new(),
+        };
+        for arg in &lambda_function.args {
+            if let Some(name) = identifier(arg) {
+                scope.insert(name.to_string(), None);
+            }
+        }
+
+        self.push_scope(scope);
+        lambda_function.body = self.visit(lambda_function.body);
+        self.scopes.pop();
+
+        ExprKind::LambdaFunction(lambda_function)
+    }
+
+    fn visit_list(&mut self, mut l: List) -> ExprKind {
+        l.args = l.args.into_iter().map(|e| self.visit(e)).collect();
+
+        match self.inline(&l) {
+            Some(inlined) => {
+                self.changed = true;
+                inlined
+            }
+            None => ExprKind::List(l),
+        }
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> ExprKind {
+        ExprKind::Quote(quote)
+    }
+}
+
+// Replaces the parameters of an inlined function with its arguments
+struct Substitute<'a> {
+    bindings: HashMap<&'a str, &'a ExprKind>,
+}
+
+impl<'a> Folder for Substitute<'a> {
+    fn visit_atom(&mut self, a: Atom) -> ExprKind {
+        match &a.syn.ty {
+            TokenType::Identifier(s) if self.bindings.contains_key(s.as_str()) => {
+                self.bindings[s.as_str()].clone()
+            }
+            _ => ExprKind::Atom(a),
+        }
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> ExprKind {
+        ExprKind::Quote(quote)
+    }
+}
+
+#[cfg(test)]
+mod inline_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn expand(program: &str) -> String {
+        Engine::new()
+            .emit_fully_expanded_ast_to_string(program)
+            .unwrap()
+    }
+
+    #[test]
+    fn small_local_functions_are_inlined_and_folded() {
+        let program = r#"
+            (define (area)
+              (define (square x) (* x x))
+              (* 3 (square 2)))
+        "#;
+        let expanded = expand(program);
+        assert!(!expanded.contains("square"));
+        assert!(expanded.contains("12"));
+
+        let mut vm = Engine::new();
+        vm.run(program).unwrap();
+        assert_eq!(vm.run("(area)").unwrap(), vec![SteelVal::IntV(12)]);
+    }
+
+    #[test]
+    fn inlining_and_folding_are_turned_off_separately() {
+        let program = r#"
+            (define (area)
+              (define (square x) (* x x))
+              (* 3 (square 2)))
+        "#;
+
+        let mut vm = Engine::new();
+        vm.set_constant_folding(false);
+        let expanded = vm.emit_fully_expanded_ast_to_string(program).unwrap();
+        assert!(!expanded.contains("(square 2)"));
+        assert!(expanded.contains("(* 2 2)"));
+
+        let mut vm = Engine::new();
+        vm.set_inlining(false);
+        let expanded = vm.emit_fully_expanded_ast_to_string(program).unwrap();
+        assert!(expanded.contains("(square 2)"));
+        vm.run(program).unwrap();
+        assert_eq!(vm.run("(area)").unwrap(), vec![SteelVal::IntV(12)]);
+    }
+
+    #[test]
+    fn top_level_functions_need_to_be_marked() {
+        assert!(expand("(define (square x) (* x x)) (square 3)").contains("(square 3)"));
+        assert!(!expand("(define (square x) #:inline (* x x)) (square 3)").contains("(square 3)"));
+
+        let mut vm = Engine::new();
+        let results = vm
+            .run("(define (square x) #:inline (* x x)) (define y 4) (square y)")
+            .unwrap();
+        assert_eq!(results.last(), Some(&SteelVal::IntV(16)));
+    }
+
+    #[test]
+    fn shadowed_variables_are_not_captured() {
+        let program = r#"
+            (define (f n)
+              (define (add-n x) (+ x n))
+              ((lambda (n) (add-n n)) 100))
+            (f 1)
+        "#;
+        let mut vm = Engine::new();
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(101)));
+    }
+
+    #[test]
+    fn functions_defined_by_macros_shadow_outer_ones() {
+        let program = r#"
+            (define-syntax local-sq
+              (syntax-rules ()
+                [(_ name) (begin (define (name x) (+ x 1000)) (define (dummy) (name 0)))]))
+            (define (outer a)
+              (define (sq x) (* x x))
+              (define (inner b)
+                (local-sq sq)
+                (sq b))
+              (+ (sq a) (inner a)))
+            (outer 3)
+        "#;
+        let mut vm = Engine::new();
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(1012)));
+    }
+
+    #[test]
+    fn recursive_and_mutated_functions_are_left_alone() {
+        let program = r#"
+            (define (f n)
+              (define (count-down x) (if (= x 0) 'done (count-down (- x 1))))
+              (define (g x) x)
+              (set! g (lambda (x) (* x 2)))
+              (list (count-down n) (g n)))
+            (f 3)
+        "#;
+        let mut vm = Engine::new();
+        let result = vm.run(program).unwrap().pop().unwrap();
+        assert_eq!(result.to_string(), "'(done 6)");
+    }
+}
@@ -0,0 +1,1031 @@
+use crate::parser::ast::*;
+use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::ConsumingVisitor;
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+use crate::{stop, throw};
+
+use log::warn;
+use std::collections::HashSet;
+
+/// Something about a program that is worth pointing out, but doesn't stop it from compiling
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileWarning {
+    pub message: String,
+    pub span: Span,
+}
+
+/// Lowers `match` forms into nested `if`s, e.g.
+///
+/// ```scheme
+/// (match value
+///   [0 'zero]
+///   [(list x y) #:when (> x y) (- x y)]
+///   [(cons head _) head]
+///   [(vector a b) (+ a b)]
+///   [(point x y) (+ x y)]
+///   [(? string? s) s]
+///   [_ 'other])
+/// ```
+///
+/// Patterns are `_`, identifiers which bind the value, literals and quoted data compared with
+/// `equal?`, `(list p ...)`, `(cons p p)`, `(vector p ...)`, `(? predicate p ...)`,
+/// `(and p ...)`, and `(name p ...)` for the fields of a struct named `name`. A clause can have a
+/// guard after the pattern with `#:when`. Matches that can fall through every clause raise an
+/// error when they do, and get a warning, as do clauses that come after one matching anything.
+///
+/// Destructuring binds with `let-match` and `define-destructure` are lowered here too:
+///
+/// ```scheme
+/// (let-match ([(x y . rest) (list 1 2 3 4)]
+///             [(a (b c)) (f)])
+///   (+ x y a b c))
+///
+/// (define-destructure (first second) (list 1 2))
+/// ```
+///
+/// Their patterns are shapes rather than `match` patterns: `_`, identifiers, literals, and
+/// lists of patterns, with an optional `. rest` pattern for whatever is left over. A value
+/// that doesn't have the shape raises an error. When the value is written out as a `(list ...)`
+/// its shape is checked while compiling, and the elements are bound without taking the list apart.
+///
+/// Programs that define one of these forms themselves, either at the top level or in an earlier
+/// run (`already_defined`), keep their own.
+pub fn lower_matches(
+    exprs: Vec<ExprKind>,
+    warnings: &mut Vec<CompileWarning>,
+    already_defined: &[&str],
+) -> Result<Vec<ExprKind>> {
+    let lowered = LOWERED_FORMS
+        .iter()
+        .copied()
+        .filter(|form| !already_defined.contains(form) && !exprs.iter().any(|x| defines(x, form)))
+        .collect::<Vec<_>>();
+    if lowered.is_empty() {
+        return Ok(exprs);
+    }
+
+    let mut lowering = MatchLowering {
+        warnings,
+        matches: 0,
+        lowered,
+        locals: Vec::new(),
+    };
+    exprs.into_iter().map(|x| lowering.visit(x)).collect()
+}
+
+/// The forms [`lower_matches`] lowers
+pub const LOWERED_FORMS: &[&str] = &["match", "let-match", "define-destructure"];
+
+/// The builtins the lowered forms call, which are registered a second time with a `#%` prefix
+/// so that variables of the program with the same names don't get in the way
+pub const MATCH_PRIMITIVES: &[&str] = &[
+    "car",
+    "cdr",
+    "cons",
+    "list",
+    "null?",
+    "pair?",
+    "equal?",
+    "=",
+    "vector?",
+    "vector-length",
+    "vector-ref",
+    "error!",
+];
+
+struct MatchLowering<'a> {
+    warnings: &'a mut Vec<CompileWarning>,
+    // Used to name the value being matched on, so that nested matches don't shadow it
+    matches: usize,
+    // The forms the program hasn't defined itself
+    lowered: Vec<&'static str>,
+    // Variables bound by the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+// The checks a value has to pass to match a pattern, and the variables it binds along the way
+#[derive(Default)]
+struct CompiledPattern {
+    tests: Vec<ExprKind>,
+    bindings: Vec<(String, ExprKind)>,
+}
+
+impl<'a> MatchLowering<'a> {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    fn lower(&mut self, expr: List, span: Span) -> Result<ExprKind> {
+        if expr.len() < 3 {
+            stop!(BadSyntax => "match expects a value and at least one clause"; span);
+        }
+
+        let mut args = expr.args.into_iter().skip(1);
+        let value = self.visit(args.next().unwrap())?;
+
+        self.matches += 1;
+        let name = format!("##match-value-{}", self.matches);
+
+        let mut clauses = Vec::new();
+        for clause in args {
+            clauses.push(self.clause(clause, &name, span)?);
+        }
+
+        self.check_exhaustive(&clauses, span);
+
+        let no_match = primitive(
+            "error!",
+            vec![string("match: no clause matched"), identifier(&name, span)],
+            span,
+        );
+
+        let body = clauses
+            .into_iter()
+            .rev()
+            .fold(no_match, |otherwise, clause| {
+                let test = clause
+                    .pattern
+                    .tests
+                    .into_iter()
+                    .chain(clause.guard)
+                    .rev()
+                    .fold(None, |rest, test| {
+                        Some(match rest {
+                            Some(rest) => if_expr(test, rest, boolean(false, span), span),
+                            None => test,
+                        })
+                    });
+
+                match test {
+                    Some(test) => if_expr(test, clause.body, otherwise, span),
+                    None => clause.body,
+                }
+            });
+
+        Ok(let_expr(vec![(name, value)], body, span))
+    }
+
+    fn clause(&mut self, clause: ExprKind, name: &str, span: Span) -> Result<Clause> {
+        let parts = match clause {
+            ExprKind::List(l) if l.len() >= 2 => l.args,
+            other => {
+                stop!(BadSyntax => "match expects clauses like [pattern body ...], found {}", other; span)
+            }
+        };
+
+        let mut parts = parts.into_iter();
+        let pattern_expr = parts.next().unwrap();
+        let mut pattern = CompiledPattern::default();
+        self.pattern(&pattern_expr, identifier(name, span), &mut pattern, span)?;
+
+        let mut rest: Vec<ExprKind> = parts.collect();
+        let guard = if is_keyword(&rest[0], "when") {
+            if rest.len() < 3 {
+                stop!(BadSyntax => "match expects a guard and a body after #:when"; span);
+            }
+            let guard = self.visit(rest.remove(1))?;
+            rest.remove(0);
+            Some(let_expr(pattern.bindings.clone(), guard, span))
+        } else {
+            None
+        };
+
+        let body = rest
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        let body = if body.len() == 1 {
+            body.into_iter().next().unwrap()
+        } else {
+            ExprKind::Begin(Begin::new(body, SyntaxObject::new(TokenType::Begin, span)))
+        };
+
+        Ok(Clause {
+            matches_anything: pattern.tests.is_empty() && guard.is_none(),
+            literal: match &pattern_expr {
+                ExprKind::Atom(Atom {
+                    syn: SyntaxObject { ty, .. },
+                }) if guard.is_none() => Some(ty.clone()),
+                _ => None,
+            },
+            body: let_expr(std::mem::take(&mut pattern.bindings), body, span),
+            pattern,
+            guard,
+        })
+    }
+
+    // Adds the checks for `value` (an expression that gets to the part of the value being
+    // matched) to match `pattern`
+    fn pattern(
+        &mut self,
+        pattern: &ExprKind,
+        value: ExprKind,
+        compiled: &mut CompiledPattern,
+        span: Span,
+    ) -> Result<()> {
+        match pattern {
+            ExprKind::Atom(Atom {
+                syn: SyntaxObject { ty, .. },
+            }) => match ty {
+                TokenType::Identifier(s) if s == "_" => {}
+                TokenType::Identifier(s) => {
+                    if compiled.bindings.iter().any(|(bound, _)| bound == s) {
+                        stop!(BadSyntax => "match pattern binds {} more than once", s; span);
+                    }
+                    compiled.bindings.push((s.clone(), value));
+                }
+                _ => compiled
+                    .tests
+                    .push(primitive("equal?", vec![value, pattern.clone()], span)),
+            },
+            ExprKind::Quote(_) => {
+                compiled
+                    .tests
+                    .push(primitive("equal?", vec![value, pattern.clone()], span))
+            }
+            ExprKind::List(l) if !l.is_empty() => {
+                let head = l.args[0].atom_identifier_or_else(
+                    throw!(BadSyntax => "match doesn't understand the pattern {}", pattern; span),
+                )?;
+                let subpatterns = &l.args[1..];
+
+                match head {
+                    "list" => {
+                        let mut rest = value;
+                        for subpattern in subpatterns {
+                            compiled
+                                .tests
+                                .push(primitive("pair?", vec![rest.clone()], span));
+                            self.pattern(
+                                subpattern,
+                                primitive("car", vec![rest.clone()], span),
+                                compiled,
+                                span,
+                            )?;
+                            rest = primitive("cdr", vec![rest], span);
+                        }
+                        compiled.tests.push(primitive("null?", vec![rest], span));
+                    }
+                    "cons" => {
+                        if subpatterns.len() != 2 {
+                            stop!(BadSyntax => "match expects two patterns in a cons pattern"; span);
+                        }
+                        compiled
+                            .tests
+                            .push(primitive("pair?", vec![value.clone()], span));
+                        self.pattern(
+                            &subpatterns[0],
+                            primitive("car", vec![value.clone()], span),
+                            compiled,
+                            span,
+                        )?;
+                        self.pattern(
+                            &subpatterns[1],
+                            primitive("cdr", vec![value], span),
+                            compiled,
+                            span,
+                        )?;
+                    }
+                    "vector" => {
+                        compiled
+                            .tests
+                            .push(primitive("vector?", vec![value.clone()], span));
+                        compiled.tests.push(primitive(
+                            "=",
+                            vec![
+                                primitive("vector-length", vec![value.clone()], span),
+                                integer(subpatterns.len(), span),
+                            ],
+                            span,
+                        ));
+                        for (i, subpattern) in subpatterns.iter().enumerate() {
+                            self.pattern(
+                                subpattern,
+                                primitive(
+                                    "vector-ref",
+                                    vec![value.clone(), integer(i, span)],
+                                    span,
+                                ),
+                                compiled,
+                                span,
+                            )?;
+                        }
+                    }
+                    "?" => {
+                        if subpatterns.is_empty() {
+                            stop!(BadSyntax => "match expects a predicate in a ? pattern"; span);
+                        }
+                        let predicate = self.visit(subpatterns[0].clone())?;
+                        compiled
+                            .tests
+                            .push(ExprKind::List(List::new(vec![predicate, value.clone()])));
+                        for subpattern in &subpatterns[1..] {
+                            self.pattern(subpattern, value.clone(), compiled, span)?;
+                        }
+                    }
+                    "and" => {
+                        for subpattern in subpatterns {
+                            self.pattern(subpattern, value.clone(), compiled, span)?;
+                        }
+                    }
+                    name => {
+                        compiled
+                            .tests
+                            .push(call(&format!("{}?", name), vec![value.clone()], span));
+                        for (i, subpattern) in subpatterns.iter().enumerate() {
+                            self.pattern(
+                                subpattern,
+                                call("%struct-ref", vec![value.clone(), integer(i, span)], span),
+                                compiled,
+                                span,
+                            )?;
+                        }
+                    }
+                }
+            }
+            _ => {
+                stop!(BadSyntax => "match doesn't understand the pattern {}", pattern; span)
+            }
+        }
+
+        Ok(())
+    }
+
+    // `(let-match ([pattern expr] ...) body ...)`, where each pattern can use what the ones
+    // before it bound
+    fn lower_let_match(&mut self, expr: List, span: Span) -> Result<ExprKind> {
+        let mut args = expr.args.into_iter().skip(1);
+        let bindings = match args.next() {
+            Some(ExprKind::List(l)) => l.args,
+            _ => {
+                stop!(BadSyntax => "let-match expects a list of bindings like ([pattern expr] ...)"; span)
+            }
+        };
+
+        let body = args.map(|x| self.visit(x)).collect::<Result<Vec<_>>>()?;
+        let mut body = match body.len() {
+            0 => stop!(BadSyntax => "let-match expects a body"; span),
+            1 => body.into_iter().next().unwrap(),
+            _ => ExprKind::Begin(Begin::new(body, SyntaxObject::new(TokenType::Begin, span))),
+        };
+
+        let mut destructures = Vec::new();
+        for binding in bindings {
+            match binding {
+                ExprKind::List(l) if l.len() == 2 => {
+                    let mut parts = l.args.into_iter();
+                    let pattern = parts.next().unwrap();
+                    let value = self.visit(parts.next().unwrap())?;
+                    destructures.push(self.destructure("let-match", &pattern, value, span)?);
+                }
+                other => {
+                    stop!(BadSyntax => "let-match expects bindings like [pattern expr], found {}", other; span)
+                }
+            }
+        }
+
+        for destructure in destructures.into_iter().rev() {
+            let checked = destructure.check(
+                let_expr(destructure.pattern.bindings.clone(), body, span),
+                span,
+            );
+            body = let_expr(destructure.sources, checked, span);
+        }
+        Ok(body)
+    }
+
+    // `(define-destructure pattern expr)`, which defines everything the pattern binds
+    fn lower_define_destructure(&mut self, expr: List, span: Span) -> Result<ExprKind> {
+        if expr.len() != 3 {
+            stop!(BadSyntax => "define-destructure expects a pattern and a value"; span);
+        }
+
+        let mut args = expr.args.into_iter().skip(1);
+        let pattern = args.next().unwrap();
+        let value = self.visit(args.next().unwrap())?;
+        let destructure = self.destructure("define-destructure", &pattern, value, span)?;
+
+        let check = format!("##destructure-check-{}", self.matches);
+        let checked = destructure.check(boolean(true, span), span);
+        let defines = destructure
+            .sources
+            .iter()
+            .cloned()
+            .chain(std::iter::once((check, checked)))
+            .chain(destructure.pattern.bindings.iter().cloned())
+            .map(|(name, value)| define(&name, value, span))
+            .collect();
+
+        Ok(ExprKind::Begin(Begin::new(
+            defines,
+            SyntaxObject::new(TokenType::Begin, span),
+        )))
+    }
+
+    // Compiles binding `pattern` to `value`
+    fn destructure(
+        &mut self,
+        form: &'static str,
+        pattern: &ExprKind,
+        value: ExprKind,
+        span: Span,
+    ) -> Result<Destructure> {
+        self.matches += 1;
+        let name = format!("##destructure-{}", self.matches);
+        let mut destructure = Destructure {
+            form,
+            sources: Vec::new(),
+            pattern: CompiledPattern::default(),
+            value: identifier(&name, span),
+        };
+
+        // A list written out in full is bound element by element, once its length is checked
+        if let (ExprKind::List(p), Some(elements)) = (pattern, list_elements(&value)) {
+            let (fixed, rest) = split_rest(form, p, span)?;
+            let arity_matches = match rest {
+                Some(_) => elements.len() >= fixed.len(),
+                None => elements.len() == fixed.len(),
+            };
+            if !arity_matches {
+                stop!(BadSyntax => "{}: the pattern {} needs {}{} values, but the list has {}",
+                    form, pattern, if rest.is_some() { "at least " } else { "" }, fixed.len(), elements.len();
+                    span);
+            }
+
+            let mut elements = elements.iter().cloned();
+            for (i, subpattern) in fixed.iter().enumerate() {
+                let element = format!("{}-{}", name, i);
+                destructure
+                    .sources
+                    .push((element.clone(), elements.next().unwrap()));
+                self.shape(
+                    form,
+                    subpattern,
+                    identifier(&element, span),
+                    &mut destructure.pattern,
+                    span,
+                )?;
+            }
+            if let Some(rest) = rest {
+                let element = format!("{}-rest", name);
+                destructure
+                    .sources
+                    .push((element.clone(), primitive("list", elements.collect(), span)));
+                self.shape(
+                    form,
+                    rest,
+                    identifier(&element, span),
+                    &mut destructure.pattern,
+                    span,
+                )?;
+            }
+
+            // What the list was, for the error when a nested pattern doesn't match
+            let mut sources = destructure.sources.iter().map(|(x, _)| identifier(x, span));
+            let tail = match rest {
+                Some(_) => sources.next_back().unwrap(),
+                None => primitive("list", Vec::new(), span),
+            };
+            destructure.value = sources
+                .rev()
+                .fold(tail, |tail, x| primitive("cons", vec![x, tail], span));
+            return Ok(destructure);
+        }
+
+        destructure.sources.push((name.clone(), value));
+        self.shape(
+            form,
+            pattern,
+            identifier(&name, span),
+            &mut destructure.pattern,
+            span,
+        )?;
+        Ok(destructure)
+    }
+
+    // Like `pattern`, for the shapes that destructuring binds take apart
+    fn shape(
+        &mut self,
+        form: &str,
+        pattern: &ExprKind,
+        value: ExprKind,
+        compiled: &mut CompiledPattern,
+        span: Span,
+    ) -> Result<()> {
+        match pattern {
+            ExprKind::Atom(_) | ExprKind::Quote(_) => self.pattern(pattern, value, compiled, span),
+            ExprKind::List(l) => {
+                let (fixed, rest) = split_rest(form, l, span)?;
+                let mut remaining = value;
+                for subpattern in fixed {
+                    compiled
+                        .tests
+                        .push(primitive("pair?", vec![remaining.clone()], span));
+                    self.shape(
+                        form,
+                        subpattern,
+                        primitive("car", vec![remaining.clone()], span),
+                        compiled,
+                        span,
+                    )?;
+                    remaining = primitive("cdr", vec![remaining], span);
+                }
+                match rest {
+                    Some(rest) => self.shape(form, rest, remaining, compiled, span),
+                    None => {
+                        compiled
+                            .tests
+                            .push(primitive("null?", vec![remaining], span));
+                        Ok(())
+                    }
+                }
+            }
+            _ => {
+                stop!(BadSyntax => "{} doesn't understand the pattern {}", form, pattern; span)
+            }
+        }
+    }
+
+    fn check_exhaustive(&mut self, clauses: &[Clause], span: Span) {
+        if let Some(i) = clauses.iter().position(|x| x.matches_anything) {
+            if i + 1 < clauses.len() {
+                self.warn(
+                    format!(
+                        "match has clauses after clause {}, which matches anything, so they never run",
+                        i + 1
+                    ),
+                    span,
+                );
+            }
+            return;
+        }
+
+        let has_literal = |b| {
+            clauses
+                .iter()
+                .any(|x| x.literal == Some(TokenType::BooleanLiteral(b)))
+        };
+        if has_literal(true) && has_literal(false) {
+            return;
+        }
+
+        self.warn(
+            "match might not be exhaustive, add a clause with `_` to handle every other value"
+                .to_string(),
+            span,
+        );
+    }
+
+    fn warn(&mut self, message: String, span: Span) {
+        warn!("{} at {:?}", message, span);
+        self.warnings.push(CompileWarning { message, span });
+    }
+}
+
+// A destructuring bind: the values to evaluate first, and the pattern to check and take apart
+// `value`, the first of them
+struct Destructure {
+    form: &'static str,
+    sources: Vec<(String, ExprKind)>,
+    pattern: CompiledPattern,
+    value: ExprKind,
+}
+
+impl Destructure {
+    // `body` if the pattern matches, otherwise an error
+    fn check(&self, body: ExprKind, span: Span) -> ExprKind {
+        let test = self
+            .pattern
+            .tests
+            .iter()
+            .cloned()
+            .rev()
+            .fold(None, |rest, test| {
+                Some(match rest {
+                    Some(rest) => if_expr(test, rest, boolean(false, span), span),
+                    None => test,
+                })
+            });
+
+        match test {
+            Some(test) => {
+                let message = format!(
+                    "{}: the value doesn't have the shape of the pattern",
+                    self.form
+                );
+                let error = primitive("error!", vec![string(&message), self.value.clone()], span);
+                if_expr(test, body, error, span)
+            }
+            None => body,
+        }
+    }
+}
+
+// Splits the patterns of a list pattern at the `.` before the rest pattern, if there is one
+fn split_rest<'a>(
+    form: &str,
+    pattern: &'a List,
+    span: Span,
+) -> Result<(&'a [ExprKind], Option<&'a ExprKind>)> {
+    let dot = pattern
+        .args
+        .iter()
+        .position(|x| matches!(x.atom_identifier_or_else(|| ()), Ok(".")));
+    match dot {
+        Some(i) if i + 2 == pattern.args.len() => {
+            Ok((&pattern.args[..i], Some(&pattern.args[i + 1])))
+        }
+        Some(_) => {
+            stop!(BadSyntax => "{} expects exactly one pattern after the . in {}", form, ExprKind::List(pattern.clone()); span)
+        }
+        None => Ok((&pattern.args, None)),
+    }
+}
+
+// The elements of a value written out as `(list ...)`
+fn list_elements(value: &ExprKind) -> Option<&[ExprKind]> {
+    match value {
+        ExprKind::List(l) if matches!(l.first_ident(), Some("list")) => Some(&l.args[1..]),
+        _ => None,
+    }
+}
+
+struct Clause {
+    pattern: CompiledPattern,
+    guard: Option<ExprKind>,
+    body: ExprKind,
+    matches_anything: bool,
+    // The pattern, if it is a single literal without a guard
+    literal: Option<TokenType>,
+}
+
+fn defines(expr: &ExprKind, name: &str) -> bool {
+    match expr {
+        ExprKind::Define(d) => matches!(d.name.atom_identifier_or_else(|| ()), Ok(n) if n == name),
+        ExprKind::Begin(b) => b.exprs.iter().any(|x| defines(x, name)),
+        _ => false,
+    }
+}
+
+fn is_keyword(expr: &ExprKind, keyword: &str) -> bool {
+    matches!(expr, ExprKind::Atom(Atom { syn: SyntaxObject { ty: TokenType::Keyword(k), .. } }) if k == keyword)
+}
+
+fn identifier(name: &str, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::Identifier(name.to_string()),
+        span,
+    )))
+}
+
+fn integer(value: usize, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::IntegerLiteral(value as isize),
+        span,
+    )))
+}
+
+fn boolean(value: bool, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::BooleanLiteral(value),
+        span,
+    )))
+}
+
+fn string(value: &str) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::default(TokenType::StringLiteral(
+        value.to_string(),
+    ))))
+}
+
+fn call(function: &str, args: Vec<ExprKind>, span: Span) -> ExprKind {
+    ExprKind::List(List::new(
+        std::iter::once(identifier(function, span))
+            .chain(args)
+            .collect(),
+    ))
+}
+
+// A call to one of the `MATCH_PRIMITIVES`
+fn primitive(function: &str, args: Vec<ExprKind>, span: Span) -> ExprKind {
+    call(&format!("#%{}", function), args, span)
+}
+
+fn define(name: &str, value: ExprKind, span: Span) -> ExprKind {
+    ExprKind::Define(Box::new(Define::new(
+        identifier(name, span),
+        value,
+        SyntaxObject::new(TokenType::Define, span),
+    )))
+}
+
+fn if_expr(test: ExprKind, then_expr: ExprKind, else_expr: ExprKind, span: Span) -> ExprKind {
+    ExprKind::If(Box::new(If::new(
+        test,
+        then_expr,
+        else_expr,
+        SyntaxObject::new(TokenType::If, span),
+    )))
+}
+
+// A `let` binding `bindings` around `body`, or just `body` when there is nothing to bind
+fn let_expr(bindings: Vec<(String, ExprKind)>, body: ExprKind, span: Span) -> ExprKind {
+    if bindings.is_empty() {
+        return body;
+    }
+
+    let (names, values): (Vec<_>, Vec<_>) = bindings.into_iter().unzip();
+    let function = ExprKind::LambdaFunction(Box::new(LambdaFunction::new(
+        names.iter().map(|x| identifier(x, span)).collect(),
+        body,
+        SyntaxObject::new(TokenType::Let, span),
+    )));
+
+    ExprKind::List(List::new(std::iter::once(function).chain(values).collect()))
+}
+
+impl<'a> ConsumingVisitor for MatchLowering<'a> {
+    type Output = Result<ExprKind>;
+
+    fn visit_if(&mut self, mut f: Box<If>) -> Self::Output {
+        f.test_expr = self.visit(f.test_expr)?;
+        f.then_expr = self.visit(f.then_expr)?;
+        f.else_expr = self.visit(f.else_expr)?;
+        Ok(ExprKind::If(f))
+    }
+
+    fn visit_define(&mut self, mut define: Box<Define>) -> Self::Output {
+        define.body = self.visit(define.body)?;
+        Ok(ExprKind::Define(define))
+    }
+
+    fn visit_lambda_function(&mut self, mut lambda_function: Box<LambdaFunction>) -> Self::Output {
+        self.locals.push(lambda_function.locals());
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
+        Ok(ExprKind::LambdaFunction(lambda_function))
+    }
+
+    fn visit_begin(&mut self, mut begin: Begin) -> Self::Output {
+        begin.exprs = begin
+            .exprs
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::Begin(begin))
+    }
+
+    fn visit_return(&mut self, mut r: Box<Return>) -> Self::Output {
+        r.expr = self.visit(r.expr)?;
+        Ok(ExprKind::Return(r))
+    }
+
+    fn visit_apply(&mut self, mut apply: Box<Apply>) -> Self::Output {
+        apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        apply.list = self.visit(apply.list)?;
+        Ok(ExprKind::Apply(apply))
+    }
+
+    fn visit_panic(&mut self, mut p: Box<Panic>) -> Self::Output {
+        p.message = self.visit(p.message)?;
+        Ok(ExprKind::Panic(p))
+    }
+
+    fn visit_transduce(&mut self, mut transduce: Box<Transduce>) -> Self::Output {
+        transduce.transducer = self.visit(transduce.transducer)?;
+        transduce.func = self.visit(transduce.func)?;
+        transduce.initial_value = self.visit(transduce.initial_value)?;
+        transduce.iterable = self.visit(transduce.iterable)?;
+        Ok(ExprKind::Transduce(transduce))
+    }
+
+    fn visit_read(&mut self, mut read: Box<Read>) -> Self::Output {
+        read.expr = self.visit(read.expr)?;
+        Ok(ExprKind::Read(read))
+    }
+
+    fn visit_execute(&mut self, mut execute: Box<Execute>) -> Self::Output {
+        execute.transducer = self.visit(execute.transducer)?;
+        execute.collection = self.visit(execute.collection)?;
+        execute.output_type = execute.output_type.map(|x| self.visit(x)).transpose()?;
+        Ok(ExprKind::Execute(execute))
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> Self::Output {
+        Ok(ExprKind::Quote(quote))
+    }
+
+    fn visit_struct(&mut self, s: Box<Struct>) -> Self::Output {
+        Ok(ExprKind::Struct(s))
+    }
+
+    fn visit_macro(&mut self, m: Macro) -> Self::Output {
+        Ok(ExprKind::Macro(m))
+    }
+
+    fn visit_eval(&mut self, mut e: Box<Eval>) -> Self::Output {
+        e.expr = self.visit(e.expr)?;
+        Ok(ExprKind::Eval(e))
+    }
+
+    fn visit_atom(&mut self, a: Atom) -> Self::Output {
+        Ok(ExprKind::Atom(a))
+    }
+
+    fn visit_list(&mut self, mut l: List) -> Self::Output {
+        if let Some(ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(s),
+                    span,
+                    ..
+                },
+        })) = l.first()
+        {
+            if self.lowered.contains(&s.as_str()) && !self.is_local(s) {
+                let span = *span;
+                return match s.as_str() {
+                    "match" => self.lower(l, span),
+                    "let-match" => self.lower_let_match(l, span),
+                    _ => self.lower_define_destructure(l, span),
+                };
+            }
+        }
+
+        l.args = l
+            .args
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::List(l))
+    }
+
+    fn visit_syntax_rules(&mut self, s: SyntaxRules) -> Self::Output {
+        Ok(ExprKind::SyntaxRules(s))
+    }
+
+    fn visit_set(&mut self, mut s: Box<Set>) -> Self::Output {
+        s.expr = self.visit(s.expr)?;
+        Ok(ExprKind::Set(s))
+    }
+
+    fn visit_require(&mut self, r: Require) -> Self::Output {
+        Ok(ExprKind::Require(r))
+    }
+
+    fn visit_callcc(&mut self, mut cc: Box<CallCC>) -> Self::Output {
+        cc.expr = self.visit(cc.expr)?;
+        Ok(ExprKind::CallCC(cc))
+    }
+}
+
+#[cfg(test)]
+mod match_lowering_tests {
+    use crate::rerrs::ErrorKind;
+    use crate::steel_vm::engine::Engine;
+
+    fn warnings(program: &str) -> Vec<String> {
+        let mut vm = Engine::new();
+        vm.run(program).unwrap();
+        vm.compile_warnings()
+            .iter()
+            .map(|x| x.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn warns_about_clauses_that_can_never_run() {
+        let found = warnings("(define (f x) (match x [y y] [0 'zero]))");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].contains("never run"));
+    }
+
+    #[test]
+    fn booleans_are_exhaustive() {
+        assert!(warnings("(define (f x) (match x [#t 1] [#f 0]))").is_empty());
+        assert_eq!(warnings("(define (f x) (match x [#t 1] [0 0]))").len(), 1);
+    }
+
+    #[test]
+    fn guards_are_not_exhaustive() {
+        assert_eq!(
+            warnings("(define (f x) (match x [y #:when (> y 0) y]))").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn falling_through_every_clause_is_an_error() {
+        let mut vm = Engine::new();
+        let err = vm.run("(match 3 [0 'zero] [(list) 'empty])").unwrap_err();
+        assert!(err.to_string().contains("no clause matched"));
+    }
+
+    #[test]
+    fn patterns_cannot_bind_a_name_twice() {
+        let mut vm = Engine::new();
+        assert!(vm.run("(match (list 1 2) [(list x x) x] [_ 0])").is_err());
+    }
+
+    #[test]
+    fn let_match_takes_values_apart() {
+        let mut vm = Engine::new();
+        let result = vm
+            .run(
+                r#"
+                (define (pairs) (list 1 (list 2 3) 4 5))
+                (let-match ([(a (b c) . rest) (pairs)]
+                            [(d e) rest])
+                  (list a b c d e (+ a e)))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(result.last().unwrap().to_string(), "'(1 2 3 4 5 6)");
+
+        let err = vm.run("(let-match ([(a b) (pairs)]) a)").unwrap_err();
+        assert!(err.to_string().contains("doesn't have the shape"));
+    }
+
+    #[test]
+    fn define_destructure_defines_every_name() {
+        let mut vm = Engine::new();
+        let result = vm
+            .run(
+                r#"
+                (define-destructure (x _ . more) (list 1 2 3 4))
+                (define (sum-first-two lst)
+                  (define-destructure (a b . _) lst)
+                  (+ a b))
+                (list x more (sum-first-two more))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(result.last().unwrap().to_string(), "'(1 (3 4) 7)");
+    }
+
+    #[test]
+    fn known_list_lengths_are_checked_while_compiling() {
+        let mut vm = Engine::new();
+
+        // Never called, so only the compiler can catch it
+        let err = vm
+            .run("(define (f) (let-match ([(a b) (list 1 2 3)]) a))")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadSyntax);
+        assert!(err
+            .to_string()
+            .contains("needs 2 values, but the list has 3"));
+
+        let err = vm
+            .run("(define-destructure (a b c . rest) (list 1 2))")
+            .unwrap_err();
+        assert!(err.to_string().contains("needs at least 3 values"));
+
+        // Elements are still evaluated in order, once each
+        let result = vm
+            .run(
+                r#"
+                (define calls '())
+                (define (note x) (set! calls (cons x calls)) x)
+                (let-match ([(a ((b) c) . rest) (list (note 1) (list (list (note 2)) 3) (note 4))])
+                  (list a b c rest calls))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(result.last().unwrap().to_string(), "'(1 2 3 (4) (4 2 1))");
+    }
+
+    #[test]
+    fn programs_can_still_define_their_own_match() {
+        let mut vm = Engine::new();
+        vm.run("(define (match a b) (+ a b))").unwrap();
+        let result = vm.run("(match 1 2)").unwrap();
+        assert_eq!(result.last().unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn functions_can_bind_match_and_the_builtins_it_uses() {
+        let mut vm = Engine::new();
+        let result = vm
+            .run(
+                r#"
+                (define (f match) (match 1 2))
+                (define (g car pair?) (match (list 1 2) [(cons a b) (list a b car pair?)]))
+                (list (f +) (g 10 20))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(result.last().unwrap().to_string(), "'(3 (1 (2) 10 20))");
+    }
+}
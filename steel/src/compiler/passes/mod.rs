@@ -1,5 +1,9 @@
 pub mod begin;
+pub mod constant_folding;
+pub mod dead_code;
+pub mod inline;
 pub mod manager;
+pub mod matches;
 
 use crate::parser::ast::ExprKind;
 use crate::parser::ast::*;
@@ -73,6 +77,7 @@ pub trait Folder {
     #[inline]
     fn visit_apply(&mut self, mut apply: Box<Apply>) -> ExprKind {
         apply.func = self.visit(apply.func);
+        apply.args = apply.args.into_iter().map(|x| self.visit(x)).collect();
         apply.list = self.visit(apply.list);
         ExprKind::Apply(apply)
     }
@@ -221,6 +226,9 @@ pub trait VisitorMutUnit {
     #[inline]
     fn visit_apply(&mut self, apply: &Apply) {
         self.visit(&apply.func);
+        for arg in &apply.args {
+            self.visit(arg);
+        }
         self.visit(&apply.list);
     }
 
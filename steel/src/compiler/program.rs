@@ -1,7 +1,40 @@
+use crate::compiler::cache::{CachedConstant, CACHE_VERSION};
 use crate::compiler::constants::ConstantMap;
 use crate::core::instructions::DenseInstruction;
+use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::Result;
+use crate::stop;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Every serialized executable starts with this, followed by the format version
+const EXECUTABLE_MAGIC: &[u8; 4] = b"STBC";
+
+// The same for executables laid out to be run in place, see `MappedExecutable`
+const MAPPED_MAGIC: &[u8; 4] = b"STBM";
+
+/// A checksum of the source of a module, which has to stay the same between versions of Steel
+/// since it's saved in executables. This is 64 bit FNV-1a.
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Modules that can't be read are left alone, so that a program can be run from just its
+// executable
+fn check_modules(modules: &[(PathBuf, u64)]) -> Result<()> {
+    for (path, expected) in modules {
+        if let Ok(source) = std::fs::read(path) {
+            if checksum(&source) != *expected {
+                stop!(Generic => format!("the program was compiled against a different version of {}, compile it again", path.display()));
+            }
+        }
+    }
+    Ok(())
+}
 
 pub struct ProgramBuilder(Vec<Vec<DenseInstruction>>);
 
@@ -72,3 +105,459 @@ impl Program {
         })
     }
 }
+
+/// A compiled program that can be saved as bytecode and run later without its source.
+///
+/// Global variables are referred to by their slot in the engine, so an executable can only be
+/// run by an engine set up the same way as the one that compiled it - with the same types,
+/// functions and definitions registered, in the same order.
+///
+/// The modules a program requires are compiled into it, so it doesn't need them to run. It
+/// does keep a checksum of each one though, and refuses to run if a module it was compiled
+/// with has changed since, rather than running with the old code.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// use steel::compiler::program::Executable;
+/// use steel::rvals::SteelVal;
+///
+/// let mut vm = Engine::new();
+/// let bytes = vm
+///     .compile_executable("(define (square x) (* x x)) (square 12)")
+///     .unwrap()
+///     .serialize();
+///
+/// let mut vm = Engine::new();
+/// let executable = Executable::deserialize(&bytes).unwrap();
+/// let output = vm.run_executable(executable).unwrap();
+/// assert_eq!(output.last(), Some(&SteelVal::IntV(144)));
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Executable {
+    instructions: Vec<Vec<DenseInstruction>>,
+    // How many globals the engine had before the program was compiled
+    base_symbols: usize,
+    // The global names and the constants of the compiler once the program was compiled
+    symbols: Vec<String>,
+    constants: Vec<CachedConstant>,
+    // Every module the program required, with the checksum of its source
+    modules: Vec<(PathBuf, u64)>,
+}
+
+impl Executable {
+    pub(crate) fn new(
+        instructions: Vec<Vec<DenseInstruction>>,
+        base_symbols: usize,
+        symbols: Vec<String>,
+        constants: Vec<CachedConstant>,
+        modules: Vec<(PathBuf, u64)>,
+    ) -> Self {
+        Executable {
+            instructions,
+            base_symbols,
+            symbols,
+            constants,
+            modules,
+        }
+    }
+
+    /// Whether an engine with these globals is set up the way the program was compiled for
+    pub(crate) fn compiled_for(&self, symbols: &[String]) -> bool {
+        self.symbols.get(..self.base_symbols) == Some(symbols)
+    }
+
+    /// The modules the program required, directly or not, in the order of their paths
+    pub fn modules(&self) -> impl Iterator<Item = &Path> {
+        self.modules.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Fails if one of the modules the program was compiled with has changed since
+    pub(crate) fn check_modules(&self) -> Result<()> {
+        check_modules(&self.modules)
+    }
+
+    pub(crate) fn into_parts(
+        self,
+    ) -> (Vec<Vec<DenseInstruction>>, Vec<String>, Vec<CachedConstant>) {
+        (self.instructions, self.symbols, self.constants)
+    }
+
+    /// Whether `bytes` look like an executable, rather than source code
+    pub fn is_executable(bytes: &[u8]) -> bool {
+        bytes.starts_with(EXECUTABLE_MAGIC)
+    }
+
+    /// Whether `bytes` look like an executable written by
+    /// [`serialize_mappable`](Executable::serialize_mappable)
+    pub fn is_mappable(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAPPED_MAGIC)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = EXECUTABLE_MAGIC.to_vec();
+        bytes.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(self).expect("executables can always be serialized"));
+        bytes
+    }
+
+    /// Reads an executable written by [`serialize`](Executable::serialize), which has to have
+    /// been written by a version of Steel with the same bytecode
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if !Self::is_executable(bytes) || bytes.len() < 8 {
+            stop!(Generic => "not a compiled steel program");
+        }
+
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != CACHE_VERSION {
+            stop!(Generic => "the program was compiled to version {} of the bytecode, expected version {}", version, CACHE_VERSION);
+        }
+
+        match bincode::deserialize(&bytes[8..]) {
+            Ok(executable) => Ok(executable),
+            Err(e) => stop!(Generic => format!("unable to read the compiled program: {}", e)),
+        }
+    }
+
+    /// Serializes the executable so that it can be run in place by a [`MappedExecutable`],
+    /// with each top level expression stored separately behind an index
+    pub fn serialize_mappable(&self) -> Vec<u8> {
+        let metadata = bincode::serialize(&(
+            self.base_symbols,
+            &self.symbols,
+            &self.constants,
+            &self.modules,
+        ))
+        .expect("executables can always be serialized");
+        let expressions = self
+            .instructions
+            .iter()
+            .map(|x| bincode::serialize(x).expect("executables can always be serialized"))
+            .collect::<Vec<_>>();
+
+        let mut bytes = MAPPED_MAGIC.to_vec();
+        bytes.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(expressions.len() as u64).to_le_bytes());
+
+        let mut offset = bytes.len() + 16 * expressions.len() + metadata.len();
+        for expression in &expressions {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(expression.len() as u64).to_le_bytes());
+            offset += expression.len();
+        }
+
+        bytes.extend(metadata);
+        for expression in expressions {
+            bytes.extend(expression);
+        }
+        bytes
+    }
+}
+
+/// An executable written by [`Executable::serialize_mappable`], run straight from the bytes
+/// it was written to. Those are usually a memory mapped file, like a `memmap2::Mmap`, so
+/// that starting a large precompiled program only reads the pages it runs.
+///
+/// Opening the executable reads the globals and constants it needs. The instructions of each
+/// top level expression are only decoded once [`Engine::run_mapped_executable`] gets to it,
+/// and are dropped again once it has run, rather than the whole program being held in
+/// memory up front. Anything the program mutates is a value the VM made, so the bytes are
+/// never written to.
+///
+/// [`Engine::run_mapped_executable`]: crate::steel_vm::engine::Engine::run_mapped_executable
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// use steel::compiler::program::MappedExecutable;
+/// use steel::rvals::SteelVal;
+///
+/// let mut vm = Engine::new();
+/// let bytes = vm
+///     .compile_executable("(define (square x) (* x x)) (square 12)")
+///     .unwrap()
+///     .serialize_mappable();
+///
+/// let mut vm = Engine::new();
+/// let executable = MappedExecutable::new(bytes).unwrap();
+/// let output = vm.run_mapped_executable(&executable).unwrap();
+/// assert_eq!(output.last(), Some(&SteelVal::IntV(144)));
+/// ```
+pub struct MappedExecutable<B> {
+    bytes: B,
+    base_symbols: usize,
+    symbols: Vec<String>,
+    constants: Vec<CachedConstant>,
+    modules: Vec<(PathBuf, u64)>,
+    // Where the instructions of each top level expression are in the bytes
+    expressions: Vec<Range<usize>>,
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Result<usize> {
+    match bytes.get(at..at + 8) {
+        Some(x) => Ok(u64::from_le_bytes(x.try_into().unwrap()) as usize),
+        None => stop!(Generic => "unable to read the compiled program: it was cut short"),
+    }
+}
+
+impl<B: AsRef<[u8]>> MappedExecutable<B> {
+    /// Opens an executable written by [`serialize_mappable`](Executable::serialize_mappable),
+    /// reading its index, globals and constants, but none of its instructions
+    pub fn new(bytes: B) -> Result<Self> {
+        let data = bytes.as_ref();
+        if !Executable::is_mappable(data) || data.len() < 8 {
+            stop!(Generic => "not a compiled steel program");
+        }
+
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if version != CACHE_VERSION {
+            stop!(Generic => "the program was compiled to version {} of the bytecode, expected version {}", version, CACHE_VERSION);
+        }
+
+        let metadata_len = read_u64(data, 8)?;
+        let count = read_u64(data, 16)?;
+
+        let mut expressions = Vec::new();
+        for i in 0..count {
+            let start = read_u64(data, 24 + 16 * i)?;
+            let len = read_u64(data, 32 + 16 * i)?;
+            match start.checked_add(len) {
+                Some(end) if end <= data.len() => expressions.push(start..end),
+                _ => stop!(Generic => "unable to read the compiled program: it was cut short"),
+            }
+        }
+
+        let metadata_start = 24 + 16 * count;
+        let metadata = match data
+            .get(metadata_start..)
+            .and_then(|x| x.get(..metadata_len))
+        {
+            Some(metadata) => metadata,
+            None => stop!(Generic => "unable to read the compiled program: it was cut short"),
+        };
+        let (base_symbols, symbols, constants, modules) = match bincode::deserialize(metadata) {
+            Ok(metadata) => metadata,
+            Err(e) => stop!(Generic => format!("unable to read the compiled program: {}", e)),
+        };
+
+        Ok(MappedExecutable {
+            bytes,
+            base_symbols,
+            symbols,
+            constants,
+            modules,
+            expressions,
+        })
+    }
+
+    /// The number of top level expressions in the program
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+
+    pub(crate) fn compiled_for(&self, symbols: &[String]) -> bool {
+        self.symbols.get(..self.base_symbols) == Some(symbols)
+    }
+
+    pub(crate) fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    pub(crate) fn check_modules(&self) -> Result<()> {
+        check_modules(&self.modules)
+    }
+
+    pub(crate) fn constants(&self) -> &[CachedConstant] {
+        &self.constants
+    }
+
+    /// Decodes the instructions of the top level expression `index`
+    pub(crate) fn instructions(&self, index: usize) -> Result<Vec<DenseInstruction>> {
+        let bytes = &self.bytes.as_ref()[self.expressions[index].clone()];
+        match bincode::deserialize(bytes) {
+            Ok(instructions) => Ok(instructions),
+            Err(e) => stop!(Generic => format!("unable to read the compiled program: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod executable_tests {
+    use super::*;
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    fn compile(program: &str) -> Vec<u8> {
+        Engine::new()
+            .compile_executable(program)
+            .unwrap()
+            .serialize()
+    }
+
+    #[test]
+    fn executables_run_without_their_source() {
+        let bytes = compile(
+            r#"
+            (define greeting "hello")
+            (define-syntax swap (syntax-rules () [(swap a b) (list b a)]))
+            (define (greet x) (swap x greeting))
+            (greet (list 1.5 #\λ))
+            "#,
+        );
+
+        let mut vm = Engine::new();
+        let output = vm
+            .run_executable(Executable::deserialize(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(
+            output.last().unwrap().to_string(),
+            "'(\"hello\" (1.5 #\\λ))"
+        );
+
+        // What the executable defined is there for the programs after it
+        let output = vm.run("(greet 10)").unwrap();
+        assert_eq!(output[0].to_string(), "'(\"hello\" 10)");
+    }
+
+    #[test]
+    fn executables_run_on_the_engine_that_compiled_them() {
+        let mut vm = Engine::new();
+        let executable = vm.compile_executable("(define x 1) (+ x 1)").unwrap();
+        assert!(vm.run("x").is_err());
+
+        let output = vm.run_executable(executable).unwrap();
+        assert_eq!(output.last(), Some(&SteelVal::IntV(2)));
+        let output = vm.run("(define y 2) (+ x y)").unwrap();
+        assert_eq!(output.last(), Some(&SteelVal::IntV(3)));
+    }
+
+    #[test]
+    fn mapped_executables_run_in_place() {
+        let bytes = Engine::new()
+            .compile_executable(
+                r#"
+                (define greeting "hello")
+                (define (greet x) (list greeting x))
+                (greet (* 3 4))
+                "#,
+            )
+            .unwrap()
+            .serialize_mappable();
+
+        let executable = MappedExecutable::new(bytes.as_slice()).unwrap();
+        assert_eq!(executable.len(), 3);
+
+        let mut vm = Engine::new();
+        let output = vm.run_mapped_executable(&executable).unwrap();
+        assert_eq!(output.last().unwrap().to_string(), "'(\"hello\" 12)");
+
+        let output = vm.run("(greet 10)").unwrap();
+        assert_eq!(output[0].to_string(), "'(\"hello\" 10)");
+    }
+
+    #[test]
+    fn only_complete_mapped_executables_are_opened() {
+        let bytes = Engine::new()
+            .compile_executable("(define x 10) (+ x 1)")
+            .unwrap()
+            .serialize_mappable();
+
+        assert!(!Executable::is_executable(&bytes));
+        assert!(MappedExecutable::new(compile("(+ 1 2)")).is_err());
+
+        let err = MappedExecutable::new(&bytes[..bytes.len() - 1])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("cut short"));
+        let err = MappedExecutable::new(&bytes[..20]).err().unwrap();
+        assert!(err.to_string().contains("cut short"));
+    }
+
+    #[test]
+    fn compiling_does_not_run_the_program() {
+        let mut vm = Engine::new();
+        vm.run("(define count 0)").unwrap();
+        vm.compile_executable("(set! count (+ count 1))").unwrap();
+        assert_eq!(vm.extract_value("count").unwrap(), crate::SteelVal::IntV(0));
+    }
+
+    #[test]
+    fn only_compatible_bytes_are_read() {
+        let err = Executable::deserialize(b"(+ 1 2)").unwrap_err();
+        assert!(err.to_string().contains("not a compiled steel program"));
+
+        let mut bytes = compile("(+ 1 2)");
+        bytes[4] = bytes[4].wrapping_add(1);
+        let err = Executable::deserialize(&bytes).unwrap_err();
+        assert!(err.to_string().contains("version"));
+
+        let bytes = compile("(+ 1 2)");
+        let err = Executable::deserialize(&bytes[..bytes.len() / 2]).unwrap_err();
+        assert!(err.to_string().contains("unable to read"));
+    }
+
+    #[test]
+    fn engines_set_up_differently_are_rejected() {
+        let mut vm = Engine::new();
+        vm.run("(define offset 10)").unwrap();
+        let executable = vm.compile_executable("(+ offset 1)").unwrap();
+
+        let err = Engine::new().run_executable(executable).unwrap_err();
+        assert!(err.to_string().contains("set up differently"));
+
+        let executable = Engine::new().compile_executable("(+ 1 2)").unwrap();
+        let err = vm.run_executable(executable).unwrap_err();
+        assert!(err.to_string().contains("set up differently"));
+    }
+
+    #[test]
+    fn changed_modules_are_caught_and_missing_ones_ignored() {
+        let path = std::env::temp_dir().join(format!("steel-checksum-{}.scm", std::process::id()));
+        std::fs::write(&path, "(provide next) (define (next x) (+ x 1))").unwrap();
+
+        let executable = Engine::new()
+            .compile_executable(&format!("(require {:?}) (next 1)", path))
+            .unwrap();
+        assert_eq!(executable.modules().collect::<Vec<_>>(), [path.as_path()]);
+        let bytes = executable.serialize();
+        let mapped = executable.serialize_mappable();
+
+        // Both kinds of executable behave the same
+        let run = |bytes: &[u8], mapped: &[u8]| -> Result<String> {
+            let last = |x: Vec<SteelVal>| x.last().unwrap().to_string();
+            let output = Engine::new()
+                .run_executable(Executable::deserialize(bytes)?)
+                .map(last);
+            let mapped = Engine::new()
+                .run_mapped_executable(&MappedExecutable::new(mapped)?)
+                .map(last);
+            assert_eq!(output, mapped);
+            output
+        };
+
+        assert_eq!(run(&bytes, &mapped).unwrap(), "2");
+
+        std::fs::write(&path, "(provide next) (define (next x) (+ x 2))").unwrap();
+        let err = run(&bytes, &mapped).unwrap_err();
+        assert!(err.to_string().contains("different version of"));
+
+        // Without the source, the program runs as it was compiled
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run(&bytes, &mapped).unwrap(), "2");
+    }
+
+    #[test]
+    fn checksums_are_stable() {
+        assert_eq!(checksum(b""), 0xcbf29ce484222325);
+        assert_eq!(checksum(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
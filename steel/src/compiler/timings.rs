@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long each phase of compiling a program took, see
+/// [`Engine::compile_with_timings`](crate::steel_vm::engine::Engine::compile_with_timings)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompileTimings {
+    /// Reading the source into syntax
+    pub parse: Duration,
+    /// Expanding includes, conditionals and macros, and compiling the modules the program
+    /// requires
+    pub expand: Duration,
+    /// The modules compiled while expanding, which is part of `expand`, slowest first
+    pub modules: Vec<(PathBuf, Duration)>,
+    /// Lowering `match`, and checking for redefinitions
+    pub analysis: Duration,
+    /// Each optimization pass that ran, like constant evaluation and inlining, in the order
+    /// they first ran. Passes that run more than once are added up.
+    pub passes: Vec<(&'static str, Duration)>,
+    /// Generating the bytecode
+    pub codegen: Duration,
+}
+
+impl CompileTimings {
+    /// The time taken by every phase together
+    pub fn total(&self) -> Duration {
+        self.parse
+            + self.expand
+            + self.analysis
+            + self.passes.iter().map(|(_, x)| *x).sum::<Duration>()
+            + self.codegen
+    }
+
+    pub(crate) fn add_pass(&mut self, name: &'static str, time: Duration) {
+        match self.passes.iter_mut().find(|(x, _)| *x == name) {
+            Some((_, total)) => *total += time,
+            None => self.passes.push((name, time)),
+        }
+    }
+}
+
+/// A table of the phases, with what each module and pass took under the phase it's part of
+impl fmt::Display for CompileTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut row = |name: &str, time: Duration| {
+            writeln!(f, "{:<40} {:>12}", name, format!("{:.3?}", time))
+        };
+
+        row("parse", self.parse)?;
+        row("expand", self.expand)?;
+        for (module, time) in &self.modules {
+            row(&format!("  {}", module.display()), *time)?;
+        }
+        row("analysis", self.analysis)?;
+        row(
+            "optimize",
+            self.passes.iter().map(|(_, x)| *x).sum::<Duration>(),
+        )?;
+        for (pass, time) in &self.passes {
+            row(&format!("  {}", pass), *time)?;
+        }
+        row("codegen", self.codegen)?;
+        row("total", self.total())
+    }
+}
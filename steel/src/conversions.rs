@@ -1,11 +1,71 @@
 use crate::{
     gc::Gc,
-    primitives::ListOperations,
+    primitives::{Array, FfiBuffer, ListOperations},
     rerrs::ErrorKind,
     rvals::{FromSteelVal, IntoSteelVal, Result},
     SteelErr, SteelVal,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+// Values that are already Steel values cross over as they are
+impl IntoSteelVal for SteelVal {
+    fn into_steelval(self) -> Result<SteelVal> {
+        Ok(self)
+    }
+}
+
+impl FromSteelVal for SteelVal {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        Ok(val)
+    }
+}
+
+// The persistent collections Steel uses are shared rather than copied
+impl IntoSteelVal for im_rc::Vector<SteelVal> {
+    fn into_steelval(self) -> Result<SteelVal> {
+        Ok(SteelVal::VectorV(Gc::new(self)))
+    }
+}
+
+impl FromSteelVal for im_rc::Vector<SteelVal> {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        match val {
+            SteelVal::VectorV(v) => Ok(v.unwrap()),
+            SteelVal::Pair(_) => Ok(SteelVal::iter(val).collect()),
+            _ => crate::stop!(ConversionError => "expected a vector, found: {}", val),
+        }
+    }
+}
+
+impl IntoSteelVal for im_rc::HashMap<SteelVal, SteelVal> {
+    fn into_steelval(self) -> Result<SteelVal> {
+        Ok(SteelVal::HashMapV(Gc::new(self)))
+    }
+}
+
+impl FromSteelVal for im_rc::HashMap<SteelVal, SteelVal> {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        match val {
+            SteelVal::HashMapV(hm) => Ok(hm.unwrap()),
+            _ => crate::stop!(ConversionError => "expected a hash map, found: {}", val),
+        }
+    }
+}
+
+impl IntoSteelVal for im_rc::HashSet<SteelVal> {
+    fn into_steelval(self) -> Result<SteelVal> {
+        Ok(SteelVal::HashSetV(Gc::new(self)))
+    }
+}
+
+impl FromSteelVal for im_rc::HashSet<SteelVal> {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        match val {
+            SteelVal::HashSetV(hs) => Ok(hs.unwrap()),
+            _ => crate::stop!(ConversionError => "expected a hash set, found: {}", val),
+        }
+    }
+}
 
 // Vectors
 impl<T: IntoSteelVal> IntoSteelVal for Vec<T> {
@@ -16,7 +76,7 @@ impl<T: IntoSteelVal> IntoSteelVal for Vec<T> {
             Ok(l) => ListOperations::built_in_list_func_flat(&l),
             _ => Err(SteelErr::new(
                 ErrorKind::ConversionError,
-                "Could not convert vector of values to SteelVal list".to_string(),
+                "Could not convert vector of values to SteelVal list",
             )),
         }
     }
@@ -35,7 +95,7 @@ impl<T: FromSteelVal> FromSteelVal for Vec<T> {
                     Ok(x) => Ok(x),
                     _ => Err(SteelErr::new(
                         ErrorKind::ConversionError,
-                        "Could not convert SteelVal list to Vector of values".to_string(),
+                        "Could not convert SteelVal list to Vector of values",
                     )),
                 }
             }
@@ -48,18 +108,69 @@ impl<T: FromSteelVal> FromSteelVal for Vec<T> {
                     Ok(x) => Ok(x),
                     _ => Err(SteelErr::new(
                         ErrorKind::ConversionError,
-                        "Could not convert SteelVal list to Vector of values".to_string(),
+                        "Could not convert SteelVal list to Vector of values",
                     )),
                 }
             } // TODO
+            // A vector becomes its elements, and a matrix its rows
+            SteelVal::Custom(_) if Array::from_value(&val).is_some() => {
+                let rows = Array::from_value(&val).unwrap().rows()?;
+                rows.into_iter().map(FromSteelVal::from_steelval).collect()
+            }
+            // And a buffer its bytes
+            SteelVal::Custom(_) if FfiBuffer::from_value(&val).is_some() => {
+                let bytes = FfiBuffer::from_value(&val).unwrap().to_vec();
+                bytes
+                    .into_iter()
+                    .map(|x| T::from_steelval(SteelVal::IntV(x as isize)))
+                    .collect()
+            }
             _ => Err(SteelErr::new(
                 ErrorKind::ConversionError,
-                "Could not convert SteelVal list to Vector of values".to_string(),
+                "Could not convert SteelVal list to Vector of values",
             )),
         }
     }
 }
 
+// Tuples, which are lists of a fixed length
+macro_rules! impl_tuple {
+    ($len:expr => $($param:ident $value:ident),*) => {
+        impl<$($param: IntoSteelVal),*> IntoSteelVal for ($($param,)*) {
+            fn into_steelval(self) -> Result<SteelVal> {
+                let ($($value,)*) = self;
+                ListOperations::built_in_list_func_flat(&[$($value.into_steelval()?),*])
+            }
+        }
+
+        impl<$($param: FromSteelVal),*> FromSteelVal for ($($param,)*) {
+            fn from_steelval(val: SteelVal) -> Result<Self> {
+                let values: Vec<SteelVal> = match &val {
+                    SteelVal::Pair(_) => SteelVal::iter(val.clone()).collect(),
+                    SteelVal::VectorV(v) => v.iter().cloned().collect(),
+                    _ => Vec::new(),
+                };
+
+                if values.len() != $len {
+                    crate::stop!(ConversionError => "expected a list of {} values, found: {}", $len, val);
+                }
+
+                let mut values = values.into_iter();
+                Ok(($($param::from_steelval(values.next().unwrap())?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!(1 => A a);
+impl_tuple!(2 => A a, B b);
+impl_tuple!(3 => A a, B b, C c);
+impl_tuple!(4 => A a, B b, C c, D d);
+impl_tuple!(5 => A a, B b, C c, D d, E e);
+impl_tuple!(6 => A a, B b, C c, D d, E e, F f);
+impl_tuple!(7 => A a, B b, C c, D d, E e, F f, G g);
+impl_tuple!(8 => A a, B b, C c, D d, E e, F f, G g, H h);
+
 // HashMap
 impl<K: IntoSteelVal, V: IntoSteelVal> IntoSteelVal for HashMap<K, V> {
     fn into_steelval(mut self) -> Result<SteelVal> {
@@ -83,28 +194,39 @@ impl<K: FromSteelVal + Eq + std::hash::Hash, V: FromSteelVal> FromSteelVal for H
         } else {
             Err(SteelErr::new(
                 ErrorKind::ConversionError,
-                "Could not convert SteelVal to HashMap".to_string(),
+                "Could not convert SteelVal to HashMap",
             ))
         }
     }
 }
 
 // BTreeMap
-// impl<K: IntoSteelVal, V: IntoSteelVal> IntoSteelVal for BTreeMap<K, V> {
-//     fn into_steelval(self) -> Result<SteelVal> {
-//         let mut hm = im_rc::HashMap::new();
-//         for (key, val) in self.drain() {
-//             hm.insert(key.into_steelval()?, val.into_steelval()?);
-//         }
-//         Ok(SteelVal::HashMapV(Gc::new(hm)))
-//     }
-// }
-
-// impl<K: FromSteelVal, V: FromSteelVal> FromSteelVal for BTreeMap<K, V> {
-//     fn from_steelval(val: SteelVal) -> Result<Self> {
-//         todo!()
-//     }
-// }
+impl<K: IntoSteelVal, V: IntoSteelVal> IntoSteelVal for BTreeMap<K, V> {
+    fn into_steelval(self) -> Result<SteelVal> {
+        let mut hm = im_rc::HashMap::new();
+        for (key, val) in self {
+            hm.insert(key.into_steelval()?, val.into_steelval()?);
+        }
+        Ok(SteelVal::HashMapV(Gc::new(hm)))
+    }
+}
+
+impl<K: FromSteelVal + Ord, V: FromSteelVal> FromSteelVal for BTreeMap<K, V> {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        if let SteelVal::HashMapV(hm) = val {
+            let mut h = BTreeMap::new();
+            for (key, value) in hm.unwrap().into_iter() {
+                h.insert(K::from_steelval(key)?, V::from_steelval(value)?);
+            }
+            Ok(h)
+        } else {
+            Err(SteelErr::new(
+                ErrorKind::ConversionError,
+                "Could not convert SteelVal to BTreeMap",
+            ))
+        }
+    }
+}
 
 // HashSet
 impl<K: IntoSteelVal> IntoSteelVal for HashSet<K> {
@@ -128,24 +250,75 @@ impl<K: FromSteelVal + Eq + std::hash::Hash> FromSteelVal for HashSet<K> {
         } else {
             Err(SteelErr::new(
                 ErrorKind::ConversionError,
-                "Could not convert SteelVal to HashSet".to_string(),
+                "Could not convert SteelVal to HashSet",
             ))
         }
     }
 }
 
 // BTreeSet
-// impl<K: IntoSteelVal> IntoSteelVal for BTreeSet<K> {
-//     fn into_steelval(self) -> Result<SteelVal> {
-//         todo!()
-//     }
-// }
-
-// impl<K: FromSteelVal> FromSteelVal for BTreeSet<K> {
-//     fn from_steelval(val: SteelVal) -> Result<Self> {
-//         todo!()
-//     }
-// }
+impl<K: IntoSteelVal> IntoSteelVal for BTreeSet<K> {
+    fn into_steelval(self) -> Result<SteelVal> {
+        let mut hs = im_rc::HashSet::new();
+        for value in self {
+            hs.insert(value.into_steelval()?);
+        }
+        Ok(SteelVal::HashSetV(Gc::new(hs)))
+    }
+}
+
+impl<K: FromSteelVal + Ord> FromSteelVal for BTreeSet<K> {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        if let SteelVal::HashSetV(hs) = val {
+            let mut h = BTreeSet::new();
+            for k in hs.unwrap().into_iter() {
+                h.insert(K::from_steelval(k)?);
+            }
+            Ok(h)
+        } else {
+            Err(SteelErr::new(
+                ErrorKind::ConversionError,
+                "Could not convert SteelVal to BTreeSet",
+            ))
+        }
+    }
+}
+
+// Named fields, used by `#[derive(SteelConvert)]`
+
+/// Looks up a field of `value` by name, which can either be a hash map keyed by symbols or
+/// strings, or a struct. `type_name` is only used for the error when the field is missing.
+pub fn field_by_name(value: &SteelVal, type_name: &str, field: &str) -> Result<SteelVal> {
+    let found = match value {
+        SteelVal::HashMapV(hm) => hm
+            .get(&SteelVal::SymbolV(field.into()))
+            .or_else(|| hm.get(&SteelVal::StringV(field.into())))
+            .cloned(),
+        SteelVal::StructV(s) => s
+            .field_names()
+            .iter()
+            .position(|name| name.as_ref() == field)
+            .map(|i| s.fields()[i].clone()),
+        _ => {
+            crate::stop!(ConversionError => "expected a hash map or struct to convert to {}, found: {}", type_name, value)
+        }
+    };
+
+    match found {
+        Some(found) => Ok(found),
+        None => crate::stop!(ConversionError => "{} is missing the field: {}", type_name, field),
+    }
+}
+
+/// Builds a hash map from the name and value of each field, keyed by symbols
+pub fn fields_to_hash_map(fields: Vec<(&str, SteelVal)>) -> SteelVal {
+    SteelVal::HashMapV(Gc::new(
+        fields
+            .into_iter()
+            .map(|(name, value)| (SteelVal::SymbolV(name.into()), value))
+            .collect(),
+    ))
+}
 
 #[cfg(test)]
 mod conversion_tests {
@@ -251,4 +424,146 @@ mod conversion_tests {
 
         assert_eq!(<HashSet<String>>::from_steelval(input).unwrap(), expected);
     }
+
+    #[test]
+    fn tuples_round_trip_as_lists() {
+        let value = (1, "two".to_string(), 'c', vec![4.5])
+            .into_steelval()
+            .unwrap();
+        assert_eq!(value.to_string(), "'(1 \"two\" #\\c (4.5))");
+
+        let (a, b, c, d) = <(isize, String, char, Vec<f64>)>::from_steelval(value).unwrap();
+        assert_eq!((a, b, c, d), (1, "two".to_string(), 'c', vec![4.5]));
+
+        let vector = SteelVal::VectorV(Gc::new(vector![SteelVal::IntV(1), SteelVal::IntV(2)]));
+        assert_eq!(<(i32, i32)>::from_steelval(vector).unwrap(), (1, 2));
+    }
+
+    #[test]
+    fn tuples_need_the_right_number_of_values() {
+        let value = (1, 2, 3).into_steelval().unwrap();
+        let err = <(i32, i32)>::from_steelval(value).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConversionError);
+        assert!(err.to_string().contains("expected a list of 2 values"));
+
+        assert!(<(i32,)>::from_steelval(SteelVal::IntV(1)).is_err());
+    }
+
+    #[test]
+    fn aggregates_nest() {
+        let mut inner = HashMap::new();
+        inner.insert("scores".to_string(), Some(vec![(1, true), (2, false)]));
+        inner.insert("nothing".to_string(), None);
+        let mut outer = HashMap::new();
+        outer.insert("player".to_string(), inner);
+
+        let value = outer.clone().into_steelval().unwrap();
+        let result =
+            <HashMap<String, HashMap<String, Option<Vec<(u8, bool)>>>>>::from_steelval(value)
+                .unwrap();
+        assert_eq!(result, outer);
+    }
+
+    #[test]
+    fn structured_types_can_be_registered() {
+        use crate::steel_vm::engine::Engine;
+        use crate::steel_vm::register_fn::RegisterFn;
+
+        let mut vm = Engine::new();
+        vm.register_fn("swap-pair", |(a, b): (isize, String)| (b, a));
+        vm.register_fn("total", |m: HashMap<String, Vec<isize>>| -> isize {
+            m.values().flatten().sum()
+        });
+
+        let output = vm
+            .run(r#"(list (swap-pair (list 1 "a")) (total (hash "x" (list 1 2) "y" (list 3))))"#)
+            .unwrap();
+        assert_eq!(output[0].to_string(), "'((\"a\" 1) 6)");
+    }
+
+    #[test]
+    fn persistent_collections_are_shared() {
+        let vector = vector![SteelVal::IntV(1), SteelVal::IntV(2)];
+        let value = vector.clone().into_steelval().unwrap();
+        assert_eq!(value, SteelVal::VectorV(Gc::new(vector.clone())));
+        assert_eq!(
+            <im_rc::Vector<SteelVal>>::from_steelval(value).unwrap(),
+            vector
+        );
+
+        let map = im_rc::hashmap! { SteelVal::IntV(1) => SteelVal::BoolV(true) };
+        let value = map.clone().into_steelval().unwrap();
+        assert_eq!(
+            <im_rc::HashMap<SteelVal, SteelVal>>::from_steelval(value).unwrap(),
+            map
+        );
+        assert!(<im_rc::HashSet<SteelVal>>::from_steelval(SteelVal::IntV(1)).is_err());
+    }
+
+    #[test]
+    fn ordered_collections_round_trip() {
+        let mut map = BTreeMap::new();
+        map.insert("b".to_string(), 2);
+        map.insert("a".to_string(), 1);
+        let value = map.clone().into_steelval().unwrap();
+        assert_eq!(<BTreeMap<String, i32>>::from_steelval(value).unwrap(), map);
+
+        let set: BTreeSet<_> = vec![3, 1, 2].into_iter().collect();
+        let value = set.clone().into_steelval().unwrap();
+        assert_eq!(<BTreeSet<i32>>::from_steelval(value).unwrap(), set);
+    }
+
+    #[test]
+    fn buffers_convert_to_their_bytes() {
+        let buffer = FfiBuffer::new(vec![1, 2, 255]);
+        let value = buffer.slice(1, 3).unwrap().into_steelval().unwrap();
+        assert_eq!(<Vec<u8>>::from_steelval(value).unwrap(), vec![2, 255]);
+    }
+
+    #[test]
+    fn errors_from_registered_functions_keep_their_kind() {
+        use crate::steel_vm::engine::Engine;
+        use crate::steel_vm::register_fn::RegisterFn;
+
+        let mut vm = Engine::new();
+        vm.register_fn("checked-div", |a: isize, b: isize| {
+            if b == 0 {
+                Err(SteelErr::new(
+                    ErrorKind::ContractViolation,
+                    "divided by zero",
+                ))
+            } else {
+                Ok(a / b)
+            }
+        });
+        vm.register_fn("parse-int", |s: String| s.parse::<isize>());
+
+        let err = vm.run("(checked-div 1 0)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+        assert!(err.to_string().contains("divided by zero"));
+
+        let err = vm.run("(parse-int \"x\")").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Generic);
+        assert!(err.to_string().contains("InvalidDigit"));
+    }
+
+    #[test]
+    fn fields_are_found_by_name() {
+        let map = fields_to_hash_map(vec![("max-size", SteelVal::IntV(10))]);
+        assert_eq!(
+            field_by_name(&map, "Config", "max-size").unwrap(),
+            SteelVal::IntV(10)
+        );
+
+        let mut strings = im_rc::HashMap::new();
+        strings.insert(SteelVal::StringV("label".into()), SteelVal::BoolV(true));
+        let strings = SteelVal::HashMapV(Gc::new(strings));
+        assert_eq!(
+            field_by_name(&strings, "Config", "label").unwrap(),
+            SteelVal::BoolV(true)
+        );
+
+        assert!(field_by_name(&map, "Config", "label").is_err());
+        assert!(field_by_name(&SteelVal::IntV(1), "Config", "label").is_err());
+    }
 }
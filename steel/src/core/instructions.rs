@@ -39,10 +39,11 @@ impl Instruction {
         }
     }
 
-    pub fn new_apply(span: SyntaxObject) -> Instruction {
+    /// `args` is the number of arguments passed before the list
+    pub fn new_apply(span: SyntaxObject, args: usize) -> Instruction {
         Instruction {
             op_code: OpCode::APPLY,
-            payload_size: 0,
+            payload_size: args,
             contents: Some(span),
             constant: false,
         }
@@ -344,22 +345,33 @@ pub fn densify(instructions: Vec<Instruction>) -> Vec<DenseInstruction> {
 pub fn pretty_print_dense_instructions(instrs: &[DenseInstruction]) {
     for (i, instruction) in instrs.iter().enumerate() {
         println!(
-            "{}    {:?} : {}",
-            i, instruction.op_code, instruction.payload_size
+            "{}    {} : {}",
+            i,
+            instruction.op_code.mnemonic(),
+            instruction.payload_size
         );
     }
 }
 
+// Instructions that don't use their payload leave the column empty
+fn payload_to_string(instruction: &Instruction) -> String {
+    if instruction.op_code.has_payload() {
+        instruction.payload_size.to_string()
+    } else {
+        String::new()
+    }
+}
+
 pub fn disassemble(instructions: &[Instruction]) -> String {
     let first_column_width = instructions.len().to_string().len();
     let second_column_width = instructions
         .iter()
-        .map(|x| format!("{:?}", x.op_code).len())
+        .map(|x| x.op_code.mnemonic().len())
         .max()
         .unwrap();
     let third_column_width = instructions
         .iter()
-        .map(|x| x.payload_size.to_string().len())
+        .map(|x| payload_to_string(x).len())
         .max()
         .unwrap();
 
@@ -375,15 +387,15 @@ pub fn disassemble(instructions: &[Instruction]) -> String {
 
         buffer.push_str("    ");
 
-        let op_code = format!("{:?}", instruction.op_code);
-        buffer.push_str(op_code.as_str());
+        let op_code = instruction.op_code.mnemonic();
+        buffer.push_str(op_code);
         for _ in 0..(second_column_width - op_code.len()) {
             buffer.push(' ');
         }
 
         buffer.push_str(" : ");
 
-        let payload_size = instruction.payload_size.to_string();
+        let payload_size = payload_to_string(instruction);
         buffer.push_str(payload_size.as_str());
         for _ in 0..(third_column_width - payload_size.len()) {
             buffer.push(' ');
@@ -1,2 +1,3 @@
 pub mod instructions;
 pub mod opcode;
+pub mod super_patterns;
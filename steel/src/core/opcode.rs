@@ -1,49 +1,275 @@
 use serde::{Deserialize, Serialize};
 
-#[repr(u8)]
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
-pub enum OpCode {
-    VOID = 0,
-    PUSH = 1,
-    LOOKUP = 2,
-    IF = 3,
-    JMP = 4,
-    FUNC = 5,
-    SCLOSURE = 6,
-    ECLOSURE = 7,
-    STRUCT = 8,
-    POP = 9,
-    BIND = 10,
-    SDEF = 11,
-    EDEF = 12,
-    PASS = 13,
-    PUSHCONST = 14,
-    NDEFS = 15,
-    EVAL = 16,
-    PANIC = 17,
-    CLEAR = 18,
-    TAILCALL = 19,
-    APPLY,
-    SET,
-    COLLECT,
-    TRANSDUCE,
-    READ,
-    COLLECTTO,
-    METALOOKUP,
-    CALLCC,
-    READLOCAL,
-    SETLOCAL,
-    READUPVALUE,
-    SETUPVALUE,
-    FILLUPVALUE,
-    FILLLOCALUPVALUE,
-    CLOSEUPVALUE, // Should be 1 for close, 0 for not
-    TCOJMP,
-    CALLGLOBAL,
-    CALLGLOBALTAIL,
-    LOADINT0, // Load const 0
-    LOADINT1,
-    LOADINT2,
-    CGLOCALCONST,
-    INNERSTRUCT,
+// Each opcode is declared once along with whether it reads its payload, its effect on the
+// stack, and optionally the builtin call it specializes and the compare-and-branch it fuses into.
+// The enum and the lookup tables are all generated from this, so adding an opcode is one line.
+macro_rules! declare_opcodes {
+    ($(
+        $name:ident {
+            payload: $payload:expr,
+            stack: $effect:expr
+            $(, specializes: ($builtin:literal, $arity:literal))?
+            $(, branch: $branch:ident)?
+            $(,)?
+        }
+    ),* $(,)?) => {
+        #[repr(u8)]
+        #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+        pub enum OpCode {
+            $($name,)*
+        }
+
+        impl OpCode {
+            /// Every opcode, in discriminant order
+            pub const ALL: &'static [OpCode] = &[$(OpCode::$name,)*];
+
+            /// The name printed by the disassembler
+            pub fn mnemonic(self) -> &'static str {
+                match self {
+                    $(OpCode::$name => stringify!($name),)*
+                }
+            }
+
+            /// Whether the VM reads the payload of this instruction. Instructions without one
+            /// always carry a payload of 0.
+            pub fn has_payload(self) -> bool {
+                match self {
+                    $(OpCode::$name => $payload,)*
+                }
+            }
+
+            /// The net number of values this instruction pushes onto (or pops off of) the stack.
+            /// `None` when that depends on the payload or the surrounding instructions, i.e. calls
+            /// and returns, or when the instruction is only read as part of another one.
+            pub fn stack_effect(self) -> Option<i32> {
+                match self {
+                    $(OpCode::$name => $effect,)*
+                }
+            }
+
+            /// The builtins that calls are specialized into opcodes for, along with `=`, which
+            /// only has a compare-and-branch form
+            pub const SPECIALIZED_BUILTINS: &'static [&'static str] = &[$($($builtin,)?)* "="];
+
+            /// The specialized instruction for calling the builtin `name` with `arity` arguments
+            pub fn specialization_of(name: &str, arity: usize) -> Option<OpCode> {
+                match (name, arity) {
+                    $($(($builtin, $arity) => Some(OpCode::$name),)?)*
+                    _ => None,
+                }
+            }
+
+            /// The fused compare-and-branch version of this comparison
+            pub fn branch_form(self) -> Option<OpCode> {
+                match self {
+                    $($(OpCode::$name => Some(OpCode::$branch),)?)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+declare_opcodes! {
+    VOID { payload: false, stack: Some(1) },
+    PUSH { payload: true, stack: Some(1) },
+    LOOKUP { payload: true, stack: None },
+    IF { payload: true, stack: Some(-1) },
+    JMP { payload: true, stack: Some(0) },
+    FUNC { payload: true, stack: None },
+    SCLOSURE { payload: true, stack: Some(1) },
+    ECLOSURE { payload: true, stack: None },
+    STRUCT { payload: true, stack: None },
+    POP { payload: true, stack: None },
+    BIND { payload: true, stack: Some(-1) },
+    SDEF { payload: false, stack: Some(0) },
+    EDEF { payload: false, stack: Some(0) },
+    PASS { payload: true, stack: Some(0) },
+    PUSHCONST { payload: true, stack: Some(1) },
+    NDEFS { payload: true, stack: None },
+    EVAL { payload: false, stack: None },
+    PANIC { payload: false, stack: None },
+    CLEAR { payload: false, stack: Some(0) },
+    TAILCALL { payload: true, stack: None },
+    APPLY { payload: true, stack: None },
+    SET { payload: true, stack: Some(0) },
+    COLLECT { payload: false, stack: None },
+    TRANSDUCE { payload: false, stack: None },
+    READ { payload: false, stack: None },
+    COLLECTTO { payload: false, stack: None },
+    METALOOKUP { payload: false, stack: None },
+    CALLCC { payload: false, stack: None },
+    READLOCAL { payload: true, stack: Some(1) },
+    SETLOCAL { payload: true, stack: Some(0) },
+    READUPVALUE { payload: true, stack: Some(1) },
+    SETUPVALUE { payload: true, stack: Some(0) },
+    FILLUPVALUE { payload: true, stack: None },
+    FILLLOCALUPVALUE { payload: true, stack: None },
+    // Should be 1 for close, 0 for not
+    CLOSEUPVALUE { payload: true, stack: None },
+    TCOJMP { payload: true, stack: None },
+    CALLGLOBAL { payload: true, stack: None },
+    CALLGLOBALTAIL { payload: true, stack: None },
+    // Load const 0
+    LOADINT0 { payload: false, stack: Some(1) },
+    LOADINT1 { payload: false, stack: Some(1) },
+    LOADINT2 { payload: false, stack: Some(1) },
+    CGLOCALCONST { payload: true, stack: None },
+    INNERSTRUCT { payload: true, stack: None },
+    // The specialized calls keep the index of the global as the payload, to fall back on
+    LT { payload: true, stack: Some(-1), specializes: ("<", 2), branch: BRANCHLT },
+    LTE { payload: true, stack: Some(-1), specializes: ("<=", 2), branch: BRANCHLTE },
+    GT { payload: true, stack: Some(-1), specializes: (">", 2), branch: BRANCHGT },
+    GTE { payload: true, stack: Some(-1), specializes: (">=", 2), branch: BRANCHGTE },
+    MOD { payload: true, stack: Some(-1), specializes: ("modulo", 2) },
+    REMAINDER { payload: true, stack: Some(-1), specializes: ("remainder", 2) },
+    ABS { payload: true, stack: Some(0), specializes: ("abs", 1) },
+    MIN { payload: true, stack: Some(-1), specializes: ("min", 2) },
+    MAX { payload: true, stack: Some(-1), specializes: ("max", 2) },
+    // Both operands are consumed by the branch, nothing gets pushed
+    BRANCHLT { payload: true, stack: Some(-2) },
+    BRANCHLTE { payload: true, stack: Some(-2) },
+    BRANCHGT { payload: true, stack: Some(-2) },
+    BRANCHGTE { payload: true, stack: Some(-2) },
+    BRANCHEQ { payload: true, stack: Some(-2) },
+    TAILAPPLY { payload: true, stack: None },
+    // Runs the instruction packed into its payload and then the one after it, see `fuses_first`
+    SUPER { payload: true, stack: None },
+}
+
+/// The number of opcodes, used for sizing dispatch tables
+pub const OPCODE_COUNT: usize = OpCode::ALL.len();
+
+// A super instruction keeps the opcode of the first instruction it runs in the top byte of its
+// payload, and the payload of that instruction in the rest
+const SUPER_PAYLOAD_BITS: u32 = 24;
+
+impl OpCode {
+    /// Whether this instruction can start a super instruction. These only touch the stack or
+    /// the globals and move on to the next instruction, so running the next one straight after
+    /// without going through the VM's loop in between does the same thing.
+    pub fn fuses_first(self) -> bool {
+        use OpCode::*;
+        matches!(
+            self,
+            PUSH | PUSHCONST
+                | READLOCAL
+                | SETLOCAL
+                | READUPVALUE
+                | SETUPVALUE
+                | SET
+                | BIND
+                | SDEF
+                | EDEF
+                | CLEAR
+                | LOADINT1
+                | LOADINT2
+                | VOID
+        )
+    }
+
+    /// Whether this instruction can end a super instruction. Nothing in the super instruction
+    /// runs after it, so besides the ones that can start one, this takes the ones that jump,
+    /// branch or make a closure, and the specialized calls, which may call a redefined global.
+    pub fn fuses_second(self) -> bool {
+        use OpCode::*;
+        self.fuses_first()
+            || matches!(
+                self,
+                IF | JMP
+                    | TCOJMP
+                    | SCLOSURE
+                    | LT
+                    | LTE
+                    | GT
+                    | GTE
+                    | MOD
+                    | REMAINDER
+                    | ABS
+                    | MIN
+                    | MAX
+                    | BRANCHLT
+                    | BRANCHLTE
+                    | BRANCHGT
+                    | BRANCHGTE
+                    | BRANCHEQ
+            )
+    }
+}
+
+/// The payload of a super instruction starting with `op_code`, if `payload` leaves room for it
+pub fn super_instruction_payload(op_code: OpCode, payload: usize) -> Option<usize> {
+    if payload < 1 << SUPER_PAYLOAD_BITS {
+        Some((op_code as usize) << SUPER_PAYLOAD_BITS | payload)
+    } else {
+        None
+    }
+}
+
+/// The opcode and payload of the first instruction in a super instruction
+pub fn unpack_super_instruction(payload: u32) -> (OpCode, u32) {
+    (
+        OpCode::ALL[(payload >> SUPER_PAYLOAD_BITS) as usize],
+        payload & ((1 << SUPER_PAYLOAD_BITS) - 1),
+    )
+}
+
+#[cfg(test)]
+mod opcode_tests {
+    use super::*;
+    use steel_gen::Fusion;
+
+    #[test]
+    fn discriminants_match_declaration_order() {
+        for (i, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as usize, i);
+        }
+        assert_eq!(OpCode::TAILCALL as usize, 19);
+        assert_eq!(OPCODE_COUNT, OpCode::SUPER as usize + 1);
+    }
+
+    #[test]
+    fn super_instructions_keep_the_first_instruction() {
+        let payload = super_instruction_payload(OpCode::READLOCAL, 7).unwrap();
+        assert_eq!(
+            unpack_super_instruction(payload as u32),
+            (OpCode::READLOCAL, 7)
+        );
+        assert_eq!(super_instruction_payload(OpCode::PUSH, 1 << 24), None);
+    }
+
+    // Otherwise they need generating again, with `cargo build --features codegen`
+    #[test]
+    fn super_patterns_are_generated_from_the_current_opcodes() {
+        let source = steel_gen::generate_super_patterns(include_str!("patterns.json"), |name| {
+            OpCode::ALL
+                .iter()
+                .find(|x| x.mnemonic() == name)
+                .map(|x| Fusion {
+                    first: x.fuses_first(),
+                    second: x.fuses_second(),
+                })
+        });
+        assert_eq!(source.as_deref(), Ok(include_str!("super_patterns.rs")));
+    }
+
+    #[test]
+    fn mnemonics_are_the_variant_names() {
+        for op in OpCode::ALL {
+            assert_eq!(op.mnemonic(), format!("{:?}", op));
+        }
+    }
+
+    #[test]
+    fn specializations_round_trip() {
+        assert_eq!(OpCode::specialization_of("<", 2), Some(OpCode::LT));
+        assert_eq!(OpCode::specialization_of("abs", 1), Some(OpCode::ABS));
+        assert_eq!(OpCode::specialization_of("abs", 2), None);
+        assert_eq!(OpCode::specialization_of("+", 2), None);
+
+        assert_eq!(OpCode::GTE.branch_form(), Some(OpCode::BRANCHGTE));
+        assert_eq!(OpCode::MOD.branch_form(), None);
+        assert_eq!(OpCode::BRANCHLT.branch_form(), None);
+    }
 }
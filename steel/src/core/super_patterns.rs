@@ -0,0 +1,14 @@
+// @generated by steel_gen from patterns.json, don't edit by hand
+use crate::core::opcode::OpCode;
+
+/// The pairs of instructions that ran back to back the most while recording patterns, hottest
+/// first. The compiler fuses these into super instructions.
+pub const DYNAMIC_SUPER_PATTERNS: &[(OpCode, OpCode)] = &[
+    (OpCode::READLOCAL, OpCode::PUSHCONST),
+    (OpCode::PUSHCONST, OpCode::BRANCHEQ),
+    (OpCode::PUSHCONST, OpCode::BRANCHLTE),
+    (OpCode::PUSHCONST, OpCode::JMP),
+    (OpCode::READUPVALUE, OpCode::READUPVALUE),
+    (OpCode::READLOCAL, OpCode::READLOCAL),
+    (OpCode::READLOCAL, OpCode::SETUPVALUE),
+];
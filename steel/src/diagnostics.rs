@@ -0,0 +1,246 @@
+//! Errors and warnings as data, for tools like editors that draw them over the source rather
+//! than printing them. Spans are byte offsets into the source the program was compiled from.
+
+use crate::compiler::passes::matches::CompileWarning;
+use crate::parser::span::Span;
+use crate::rerrs::{ErrorKind, SteelErr};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A part of the source a diagnostic points at, along with what to say about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiagnosticLabel {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A change that would fix the problem, replacing the source in `span` with `replacement`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixSuggestion {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// Something wrong with a program, either an error that stopped it from compiling or running,
+/// or a warning about something that didn't
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The code of the kind of error, like `E02` for free identifiers. Warnings don't have one.
+    pub code: Option<String>,
+    pub message: String,
+    /// Where the problem is, when that is known
+    pub span: Option<Span>,
+    pub labels: Vec<DiagnosticLabel>,
+    pub notes: Vec<String>,
+    pub fixes: Vec<FixSuggestion>,
+}
+
+impl Diagnostic {
+    pub fn from_error(error: &SteelErr) -> Self {
+        let message = error.message().to_string();
+        let mut notes = Vec::new();
+        if error.kind() == ErrorKind::FreeIdentifier && is_identifier(&message) {
+            notes.push(format!(
+                "`{}` isn't defined anywhere the program can see",
+                message
+            ));
+        }
+
+        Diagnostic {
+            severity: Severity::Error,
+            code: Some(error.kind().to_error_code().to_string()),
+            message: format!("{}: {}", error.kind(), message),
+            span: error.span(),
+            labels: error
+                .span()
+                .map(|span| DiagnosticLabel { span, message })
+                .into_iter()
+                .collect(),
+            notes,
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn from_warning(warning: &CompileWarning) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: None,
+            message: warning.message.clone(),
+            span: Some(warning.span),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    /// Suggests replacing a free identifier with the closest of `names`, if any are close
+    /// enough to be a typo of it
+    pub(crate) fn suggest_identifier(mut self, names: &[String]) -> Self {
+        let (identifier, span) = match (self.labels.first(), self.span) {
+            (Some(label), Some(span)) if is_identifier(&label.message) => {
+                (label.message.clone(), span)
+            }
+            _ => return self,
+        };
+
+        let closest = names
+            .iter()
+            .filter(|name| !name.starts_with('#') && !name.starts_with("__"))
+            .map(|name| (edit_distance(&identifier, name), name))
+            .filter(|(distance, _)| *distance <= std::cmp::max(1, identifier.len() / 3))
+            .min_by_key(|(distance, _)| *distance);
+
+        if let Some((_, name)) = closest {
+            self.fixes.push(FixSuggestion {
+                message: format!("did you mean `{}`?", name),
+                span,
+                replacement: name.clone(),
+            });
+        }
+        self
+    }
+}
+
+/// Makes the changes `fixes` suggest to `source`. A fix that overlaps one before it, or makes
+/// the same change as one before it, is left out.
+pub fn apply_fixes(source: &str, fixes: &[&FixSuggestion]) -> String {
+    let mut kept: Vec<&FixSuggestion> = Vec::new();
+    for fix in fixes {
+        let overlaps = kept.iter().any(|x| {
+            (x.span == fix.span && x.replacement == fix.replacement)
+                || (x.span.start() < fix.span.end() && fix.span.start() < x.span.end())
+        });
+        if !overlaps {
+            kept.push(fix);
+        }
+    }
+
+    // From the end, so the spans of what's left to change stay where they are. Insertions at
+    // the same place end up in the order they were given.
+    kept.sort_by_key(|x| (x.span.start(), x.span.end()));
+    let mut fixed = source.to_string();
+    for fix in kept.into_iter().rev() {
+        fixed.replace_range(fix.span.range(), &fix.replacement);
+    }
+    fixed
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && !s.contains(char::is_whitespace)
+}
+
+// The number of characters that have to be added, removed or changed, or pairs of neighbouring
+// characters swapped, to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    // distances[i][j] is the distance between the first i characters of `a` and the first j of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod diagnostic_tests {
+    use super::*;
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("display", "display"), 0);
+        assert_eq!(edit_distance("dispaly", "display"), 1);
+        assert_eq!(edit_distance("lenght", "length"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("car", "cdr"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn free_identifiers_suggest_a_fix() {
+        let mut vm = Engine::new();
+        let source = "(define (area r) (* r r))\n(aera 10)";
+        let diagnostics = vm.diagnostics(source);
+
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.code.as_deref(), Some("E02"));
+
+        let span = diagnostic.span.unwrap();
+        assert_eq!(&source[span.range()], "aera");
+        assert_eq!(diagnostic.labels[0].span, span);
+        assert_eq!(diagnostic.fixes[0].replacement, "area");
+        assert_eq!(diagnostic.fixes[0].span, span);
+    }
+
+    #[test]
+    fn fixes_are_applied_together() {
+        let fix = |start, end, replacement: &str| FixSuggestion {
+            message: String::new(),
+            span: Span::new(start, end),
+            replacement: replacement.to_string(),
+        };
+        let insert = fix(0, 0, "(require \"a.scm\")\n");
+        let insert_b = fix(0, 0, "(require \"b.scm\")\n");
+        let rename = fix(8, 9, "_x");
+        let overlapping = fix(7, 10, "nope");
+
+        assert_eq!(
+            apply_fixes(
+                "(define x 1)",
+                &[&rename, &insert, &overlapping, &insert.clone(), &insert_b]
+            ),
+            "(require \"a.scm\")\n(require \"b.scm\")\n(define _x 1)"
+        );
+    }
+
+    #[test]
+    fn warnings_are_diagnostics_too() {
+        let mut vm = Engine::new();
+        let diagnostics = vm.diagnostics("(define (describe x) (match x [0 'zero]))");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert!(diagnostics[0].message.contains("exhaustive"));
+        assert!(vm.diagnostics("(+ 1 2)").is_empty());
+    }
+
+    #[test]
+    fn checked_programs_define_nothing() {
+        let mut vm = Engine::new();
+        assert!(vm
+            .diagnostics("(define (square x) (* x x)) (define zz 3)")
+            .is_empty());
+
+        let err = vm.run("(square 2)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FreeIdentifier);
+        assert_eq!(vm.run("(define zz 4) zz").unwrap()[1], SteelVal::IntV(4));
+    }
+}
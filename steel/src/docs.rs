@@ -0,0 +1,518 @@
+//! Documentation for globals, written as markdown. Builtins are documented when they're
+//! registered, and functions written in Steel by a string at the start of their body:
+//!
+//! ```scheme
+//! (define (area r)
+//!   "The area of a circle with radius `r`"
+//!   (* 3.14159 r r))
+//! ```
+//!
+//! `(doc area)` prints it at the REPL, and `steel doc` collects everything into a page, or with
+//! `--json` into an interface file for editors, with how many arguments each function takes.
+//! Code fenced as `scheme`, `steel` or `racket` in the documentation is an example, which
+//! `steel test --doc` runs to make sure it still works.
+//!
+//! ```
+//! # extern crate steel;
+//! let docs = steel::docs::file_docs(
+//!     "(provide area) (define (area r) \"The area of a circle\" (* 3.14 r r))",
+//!     "circles.scm",
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(docs[0].name, "area");
+//! assert!(steel::docs::markdown(&docs).contains("### `area`\n\nThe area of a circle\n"));
+//! ```
+
+use crate::literate::LiterateFile;
+use crate::parser::ast::{Define, ExprKind};
+use crate::parser::parser::{ParseError, Parser};
+use crate::parser::tokens::TokenType;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+use crate::steel_vm::engine::Engine;
+use crate::stop;
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The documentation of one global
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DocEntry {
+    pub name: String,
+    /// The module it comes from, like `steel/uuid` or the path of a file
+    pub module: String,
+    pub doc: String,
+    /// The ways it can be called, empty when that isn't known
+    pub arities: Vec<Arity>,
+    pub effect: Effect,
+}
+
+/// How many arguments a function takes, with no `max` when it takes any number after `min`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+/// Whether calling a function does anything besides giving back a value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    /// The result only depends on the arguments, and nothing else changes
+    Pure,
+    /// It reads or changes state outside of its arguments, like the time or a file
+    Effectful,
+    Unknown,
+}
+
+impl DocEntry {
+    /// An entry for a builtin, with its arities read from the usages its documentation shows,
+    /// like `` `(atan x)` `` or `` `(+ n ...)` ``
+    pub fn new(name: &str, module: &str, doc: &str) -> Self {
+        DocEntry {
+            name: name.to_string(),
+            module: module.to_string(),
+            doc: doc.to_string(),
+            arities: usages(name, doc),
+            effect: Effect::Unknown,
+        }
+    }
+
+    /// The entry for a function defined in Steel, if it has a doc string
+    pub(crate) fn from_define(define: &Define, module: &str) -> Option<Self> {
+        let name = define.name.atom_identifier_or_else(|| ()).ok()?;
+        let args = match &define.body {
+            ExprKind::LambdaFunction(l) => l.args.len(),
+            _ => return None,
+        };
+
+        Some(DocEntry {
+            name: name.to_string(),
+            module: module.to_string(),
+            doc: docstring(define)?.to_string(),
+            arities: vec![Arity {
+                min: args,
+                max: Some(args),
+            }],
+            effect: if define.is_pure() {
+                Effect::Pure
+            } else {
+                Effect::Unknown
+            },
+        })
+    }
+}
+
+// The arities of the calls to `name` in the code spans of `doc`, where `args ...` at the end
+// stands for any number of arguments
+fn usages(name: &str, doc: &str) -> Vec<Arity> {
+    let mut arities = Vec::new();
+
+    for span in doc.split('`').skip(1).step_by(2) {
+        let args = match span
+            .strip_prefix('(')
+            .and_then(|x| x.strip_prefix(name))
+            .and_then(|x| x.strip_suffix(')'))
+        {
+            Some(args) if args.is_empty() || args.starts_with(' ') => args,
+            _ => continue,
+        };
+
+        let args: Vec<_> = args.split_whitespace().collect();
+        let arity = match args.split_last() {
+            Some((&"...", rest)) => Arity {
+                min: rest.len().saturating_sub(1),
+                max: None,
+            },
+            _ => Arity {
+                min: args.len(),
+                max: Some(args.len()),
+            },
+        };
+        if !arities.contains(&arity) {
+            arities.push(arity);
+        }
+    }
+
+    arities.sort();
+    arities
+}
+
+/// Everything documented so far, by name. Later entries for a name replace earlier ones, the
+/// same way the definitions do.
+#[derive(Default)]
+pub struct Docs {
+    entries: HashMap<String, DocEntry>,
+}
+
+impl Docs {
+    pub fn insert(&mut self, entry: DocEntry) {
+        self.entries.insert(entry.name.clone(), entry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DocEntry> {
+        self.entries.get(name)
+    }
+
+    /// Every entry, sorted by module and then by name
+    pub fn entries(&self) -> Vec<DocEntry> {
+        let mut entries: Vec<_> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| (&a.module, &a.name).cmp(&(&b.module, &b.name)));
+        entries
+    }
+}
+
+/// `(%doc 'name)`, which the `doc` macro expands into, prints the documentation of `name`
+pub(crate) fn print_doc(docs: Rc<RefCell<Docs>>) -> SteelVal {
+    let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+        let name = match args {
+            [SteelVal::SymbolV(name)] => name,
+            _ => stop!(ArityMismatch => "doc expects the name of a global"),
+        };
+
+        match docs.borrow().get(name) {
+            Some(entry) => {
+                println!("{} ({})\n\n{}", entry.name, entry.module, entry.doc.trim());
+                Ok(SteelVal::Void)
+            }
+            None => stop!(Generic => "{} has no documentation", name.as_str()),
+        }
+    };
+
+    SteelVal::BoxedFunction(Rc::new(f))
+}
+
+/// The doc string of a function, the string its body starts with when there's more after it
+pub(crate) fn docstring(define: &Define) -> Option<&str> {
+    let body = match &define.body {
+        ExprKind::LambdaFunction(l) => &l.body,
+        _ => return None,
+    };
+
+    match body {
+        ExprKind::Begin(b) if b.exprs.len() > 1 => match &b.exprs[0] {
+            ExprKind::Atom(a) => match &a.syn.ty {
+                TokenType::StringLiteral(s) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The names given to `provide`, or `None` if there's nothing provided
+pub(crate) fn provided(exprs: &[ExprKind]) -> Option<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut found = false;
+
+    for expr in exprs {
+        let args = match expr {
+            ExprKind::List(l) if l.first_ident() == Some("provide") => &l.args[1..],
+            _ => continue,
+        };
+        found = true;
+
+        for arg in args {
+            let name = match arg {
+                ExprKind::List(l) if l.first_ident() == Some("contract/out") => l.args.get(1),
+                _ => Some(arg),
+            };
+            if let Some(Ok(name)) = name.map(|x| x.atom_identifier_or_else(|| ())) {
+                names.push(name);
+            }
+        }
+    }
+
+    if found {
+        Some(names)
+    } else {
+        None
+    }
+}
+
+/// The documented functions defined at the top level of `source`, read without running it.
+/// When the file provides names, as a module does, only those are included.
+pub fn file_docs(source: &str, module: &str) -> Result<Vec<DocEntry>> {
+    let mut intern = HashMap::new();
+    let exprs =
+        Parser::new(source, &mut intern).collect::<std::result::Result<Vec<_>, ParseError>>()?;
+    let provided = provided(&exprs);
+
+    let mut docs = Docs::default();
+    for expr in &exprs {
+        let entry = match expr {
+            ExprKind::Define(d) => DocEntry::from_define(d, module),
+            _ => None,
+        };
+
+        match entry {
+            Some(entry)
+                if provided
+                    .as_ref()
+                    .map(|x| x.contains(&entry.name.as_str()))
+                    .unwrap_or(true) =>
+            {
+                docs.insert(entry)
+            }
+            _ => {}
+        }
+    }
+
+    Ok(docs.entries())
+}
+
+/// Runs the examples in the documentation of each entry, each in its own fork of `vm`, giving
+/// back the entries whose examples failed along with the error. Errors are reported against
+/// the documentation of the entry.
+pub fn run_examples(vm: &Engine, entries: &[DocEntry]) -> Vec<(DocEntry, SteelErr)> {
+    let mut failed = Vec::new();
+
+    for entry in entries {
+        let examples = LiterateFile::parse(&entry.doc);
+        if examples.blocks() == 0 {
+            continue;
+        }
+
+        if let Err(e) = vm.fork().run(&examples.code()) {
+            failed.push((entry.clone(), e));
+        }
+    }
+
+    failed
+}
+
+/// The entries as a JSON array, for editors and other tools to complete and check calls with
+pub fn json(entries: &[DocEntry]) -> String {
+    serde_json::to_string_pretty(entries).unwrap()
+}
+
+/// The entries as a markdown page, with a section for each module
+pub fn markdown(entries: &[DocEntry]) -> String {
+    let mut out = String::from("# Documentation\n");
+    let mut module = None;
+
+    for entry in entries {
+        if module != Some(&entry.module) {
+            out.push_str(&format!("\n## {}\n", entry.module));
+            module = Some(&entry.module);
+        }
+        out.push_str(&format!("\n### `{}`\n\n{}\n", entry.name, entry.doc.trim()));
+    }
+
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Paragraphs, with `code` spans, and fenced code as it is
+fn doc_html(doc: &str) -> String {
+    doc.trim()
+        .split("\n\n")
+        .map(|paragraph| {
+            if let Some(fenced) = paragraph.strip_prefix("```") {
+                let code = fenced.split_once('\n').map(|x| x.1).unwrap_or_default();
+                let code = code.trim_end().trim_end_matches("```").trim_end();
+                return format!("<pre><code>{}</code></pre>", escape(code));
+            }
+
+            let text = escape(paragraph)
+                .split('`')
+                .enumerate()
+                .map(|(i, x)| {
+                    if i % 2 == 1 {
+                        format!("<code>{}</code>", x)
+                    } else {
+                        x.to_string()
+                    }
+                })
+                .collect::<String>();
+            format!("<p>{}</p>", text)
+        })
+        .collect()
+}
+
+const SEARCH_SCRIPT: &str = r#"document.getElementById("search").addEventListener("input", function (e) {
+  var query = e.target.value.toLowerCase();
+  document.querySelectorAll("section.entry").forEach(function (entry) {
+    entry.hidden = entry.textContent.toLowerCase().indexOf(query) < 0;
+  });
+});"#;
+
+/// The entries as a single HTML page, with a box for searching through them
+pub fn html(entries: &[DocEntry]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Documentation</title>\n\
+         </head>\n<body>\n<h1>Documentation</h1>\n\
+         <input id=\"search\" type=\"search\" placeholder=\"Search\" autofocus>\n",
+    );
+    let mut module = None;
+
+    for entry in entries {
+        if module != Some(&entry.module) {
+            out.push_str(&format!("<h2>{}</h2>\n", escape(&entry.module)));
+            module = Some(&entry.module);
+        }
+        out.push_str(&format!(
+            "<section class=\"entry\" id=\"{name}\">\n<h3><code>{name}</code></h3>\n{doc}\n</section>\n",
+            name = escape(&entry.name),
+            doc = doc_html(&entry.doc)
+        ));
+    }
+
+    out.push_str(&format!(
+        "<script>\n{}\n</script>\n</body>\n</html>\n",
+        SEARCH_SCRIPT
+    ));
+    out
+}
+
+#[cfg(test)]
+mod docs_tests {
+    use super::*;
+
+    #[test]
+    fn only_provided_functions_with_doc_strings_are_documented() {
+        let source = r#"
+            (provide area (contract/out perimeter (->/c number? number?)))
+            (define (area r) "The area" (* 3.14 r r))
+            (define perimeter (lambda (r) "The perimeter" (* 2 3.14 r)))
+            (define (helper r) "Not provided" r)
+            (define (diameter r) "Only a string, so this is what it returns")
+        "#;
+
+        let docs = file_docs(source, "circles.scm").unwrap();
+        let names: Vec<_> = docs.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["area", "perimeter"]);
+
+        let everything = file_docs("(define (helper r) \"Helps\" r)", "script.scm").unwrap();
+        assert_eq!(everything.len(), 1);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let page = html(&[DocEntry::new(
+            "string<?",
+            "builtins",
+            "Whether `a` < `b`\n\nSee also `string=?`",
+        )]);
+
+        assert!(page.contains("<h3><code>string&lt;?</code></h3>"));
+        assert!(page.contains("<p>Whether <code>a</code> &lt; <code>b</code></p><p>See also"));
+    }
+
+    #[test]
+    fn examples_are_kept_as_code_in_html() {
+        let page = html(&[DocEntry::new(
+            "add",
+            "builtins",
+            "Adds\n\n```scheme\n(< (add 1 2) 4)\n```",
+        )]);
+
+        assert!(page.contains("<p>Adds</p><pre><code>(&lt; (add 1 2) 4)</code></pre>"));
+    }
+
+    #[test]
+    fn arities_come_from_usages_and_lambdas() {
+        let arities = |min, max| vec![Arity { min, max }];
+
+        let atan = DocEntry::new(
+            "atan",
+            "math",
+            "`(atan x)` or `(atan y x)`, see `(atan2 y x)`",
+        );
+        assert_eq!(
+            atan.arities,
+            [
+                Arity {
+                    min: 1,
+                    max: Some(1)
+                },
+                Arity {
+                    min: 2,
+                    max: Some(2)
+                }
+            ]
+        );
+        assert_eq!(
+            DocEntry::new("+", "math", "`(+ n ...)`").arities,
+            arities(0, None)
+        );
+        assert_eq!(
+            DocEntry::new("now", "time", "`(now)`").arities,
+            arities(0, Some(0))
+        );
+        assert!(DocEntry::new("now", "time", "The time").arities.is_empty());
+
+        let source = r#"
+            (define (area r) "The area" (* 3.14 r r))
+            (define/pure (add a b) "Adds" (+ a b))
+        "#;
+        let docs = file_docs(source, "math.scm").unwrap();
+        assert_eq!(docs[0].arities, arities(2, Some(2)));
+        assert_eq!(docs[0].effect, Effect::Pure);
+        assert_eq!(docs[1].arities, arities(1, Some(1)));
+        assert_eq!(docs[1].effect, Effect::Unknown);
+    }
+
+    #[test]
+    fn builtins_are_written_as_json() {
+        let vm = Engine::new();
+        let interface: serde_json::Value = serde_json::from_str(&json(&vm.docs())).unwrap();
+        let bump = interface
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["name"] == "semver/bump")
+            .unwrap();
+
+        assert_eq!(bump["module"], "steel/semver");
+        assert_eq!(bump["arities"], serde_json::json!([{ "min": 2, "max": 2 }]));
+        assert_eq!(bump["effect"], "pure");
+    }
+
+    #[test]
+    fn builtin_examples_pass() {
+        let vm = Engine::new();
+        let failed = run_examples(&vm, &vm.docs());
+        assert!(failed.is_empty(), "{:?}", failed);
+    }
+
+    #[test]
+    fn failing_examples_are_reported_against_the_docs() {
+        let source = r#"
+            (define (double x)
+              "Doubles `x`
+
+            ```scheme
+            (assert! (= 4 (double 2)))
+            ```"
+              (* 2 x))
+            (define (triple x)
+              "Triples `x`
+
+            ```scheme
+            (assert! (= 6 (tripl 2)))
+            ```"
+              (* 3 x))
+        "#;
+
+        let mut vm = Engine::new();
+        vm.run(source).unwrap();
+        let failed = run_examples(&vm, &file_docs(source, "math.scm").unwrap());
+
+        assert_eq!(failed.len(), 1);
+        let (entry, err) = &failed[0];
+        assert_eq!(entry.name, "triple");
+        let span = err.span().unwrap();
+        assert_eq!(&entry.doc[span.start()..span.end()], "tripl");
+    }
+}
@@ -1,4 +1,7 @@
-use crate::rvals::{Result, SteelVal};
+use crate::compiler::map::SymbolMap;
+use crate::rvals::{FunctionSignature, Result, SteelVal};
+
+use std::rc::Rc;
 
 // TODO
 pub const fn _new_void() -> SteelVal {
@@ -17,7 +20,12 @@ pub const fn _new_false() -> SteelVal {
 
 #[derive(Debug)]
 pub struct Env {
-    pub(crate) bindings_vec: Vec<SteelVal>,
+    // Shared with forks of the engine until one of them binds a global
+    pub(crate) bindings_vec: Rc<Vec<SteelVal>>,
+    // The names of the globals, while programs can't define a global that is already bound
+    pub(crate) redefinitions_forbidden: Option<SymbolMap>,
+    // The builtins that specialized opcodes compute inline, by the global they were bound to
+    specialized: Rc<Vec<Option<FunctionSignature>>>,
 }
 
 pub trait MacroEnv {
@@ -32,7 +40,38 @@ impl Env {
     /// top level global env has no parent
     pub fn root() -> Self {
         Env {
-            bindings_vec: Vec::new(),
+            bindings_vec: Rc::new(Vec::new()),
+            redefinitions_forbidden: None,
+            specialized: Rc::new(Vec::new()),
+        }
+    }
+
+    /// An environment with the same bindings, where binding a global doesn't change this one
+    pub(crate) fn fork(&self) -> Self {
+        Env {
+            bindings_vec: Rc::clone(&self.bindings_vec),
+            redefinitions_forbidden: self.redefinitions_forbidden.clone(),
+            specialized: Rc::clone(&self.specialized),
+        }
+    }
+
+    /// Records that the global at `idx` holds the builtin `func`, which specialized opcodes
+    /// calling that global compute inline
+    pub(crate) fn mark_specialized(&mut self, idx: usize, func: FunctionSignature) {
+        let specialized = Rc::make_mut(&mut self.specialized);
+        if specialized.len() <= idx {
+            specialized.resize(idx + 1, None);
+        }
+        specialized[idx] = Some(func);
+    }
+
+    /// Whether `value`, looked up from the global at `idx`, is still the builtin recorded for
+    /// it. When it has been redefined, specialized opcodes have to call it instead.
+    #[inline(always)]
+    pub(crate) fn is_specialized(&self, idx: usize, value: &SteelVal) -> bool {
+        match (value, self.specialized.get(idx)) {
+            (SteelVal::FuncV(f), Some(Some(builtin))) => *f as usize == *builtin as usize,
+            _ => false,
         }
     }
 
@@ -49,17 +88,18 @@ impl Env {
 
     #[inline]
     pub fn repl_define_idx(&mut self, idx: usize, val: SteelVal) {
-        if idx < self.bindings_vec.len() {
-            self.bindings_vec[idx] = val;
+        let bindings = Rc::make_mut(&mut self.bindings_vec);
+        if idx < bindings.len() {
+            bindings[idx] = val;
         } else {
-            self.bindings_vec.push(val);
-            assert_eq!(self.bindings_vec.len() - 1, idx);
+            bindings.push(val);
+            assert_eq!(bindings.len() - 1, idx);
         }
     }
 
     pub fn repl_set_idx(&mut self, idx: usize, val: SteelVal) -> Result<SteelVal> {
-        let output = self.bindings_vec[idx].clone();
-        self.bindings_vec[idx] = val;
+        let bindings = Rc::make_mut(&mut self.bindings_vec);
+        let output = std::mem::replace(&mut bindings[idx], val);
         Ok(output)
     }
 
@@ -0,0 +1,282 @@
+//! Formats Steel source the one canonical way, keeping comments and blank lines where they were.
+//!
+//! Lists are kept on one line when they fit in [`WIDTH`] columns and hold no comments.
+//! Otherwise they're broken one element per line:
+//!
+//! * forms with a body, like `define`, `lambda` and `let`, keep their first argument on the
+//!   first line and indent the rest by two spaces
+//! * `begin` and `cond` indent everything after the head by two spaces
+//! * calls keep their first argument on the first line and line the rest up under it
+//! * lists that don't start with a name, like data and `cond` clauses, line everything up
+//!   under the first element
+//!
+//! ```
+//! # extern crate steel;
+//! let source = "(define (f x)\n(if (> x 0)\n'positive\n'not-positive)) ; sign\n";
+//!
+//! assert_eq!(
+//!     steel::fmt::format(source).unwrap(),
+//!     "(define (f x) (if (> x 0) 'positive 'not-positive)) ; sign\n"
+//! );
+//! ```
+
+use crate::parser::parser::{parse_with_trivia, RawSyntax};
+use crate::rvals::Result;
+
+/// The number of columns lines are kept within, where the code allows it
+pub const WIDTH: usize = 80;
+
+// Forms whose first argument stays on the first line, with the body indented under them
+const BODY_FORMS: &[&str] = &[
+    "define",
+    "define/pure",
+    "defn",
+    "define-syntax",
+    "define-values",
+    "lambda",
+    "λ",
+    "fn",
+    "let",
+    "let*",
+    "letrec",
+    "let-values",
+    "let-match",
+    "define-destructure",
+    "when",
+    "unless",
+    "struct",
+    "syntax-rules",
+    "case",
+    "match",
+    "parameterize",
+    "with-handler",
+];
+
+// Forms that put everything after the head on its own line
+const BLOCK_FORMS: &[&str] = &["begin", "cond"];
+
+// Calls with longer names than this indent their arguments rather than lining them up
+const ALIGN_LIMIT: usize = 12;
+
+/// Formats `source`, failing only if it can't be read
+pub fn format(source: &str) -> Result<String> {
+    let exprs = parse_with_trivia(source)?;
+
+    let mut writer = Writer::default();
+    writer.items(&exprs, 0, 0, 0);
+
+    let mut out = writer.out.trim_end().to_string();
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn closing(open: char) -> char {
+    if open == '[' {
+        ']'
+    } else {
+        ')'
+    }
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+// Literals and quoted data are laid out as data, rather than calls
+fn is_name(atom: &str) -> bool {
+    let mut chars = atom.chars();
+    match (chars.next(), chars.next()) {
+        (Some('+'), Some(c)) | (Some('-'), Some(c)) | (Some('.'), Some(c)) => !c.is_ascii_digit(),
+        (Some(c), _) => !c.is_ascii_digit() && c != '"' && c != '#',
+        (None, _) => false,
+    }
+}
+
+// The expression on one line, if it can go on one line
+fn flat(expr: &RawSyntax) -> Option<String> {
+    match expr {
+        RawSyntax::Atom(text) if !text.contains('\n') => Some(text.to_string()),
+        RawSyntax::Prefixed(prefix, inner) => flat(inner).map(|x| format!("{}{}", prefix, x)),
+        RawSyntax::List(open, items) => {
+            let items = items.iter().map(flat).collect::<Option<Vec<_>>>()?;
+            Some(format!("{}{}{}", open, items.join(" "), closing(*open)))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    column: usize,
+}
+
+impl Writer {
+    fn text(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.column = match s.rfind('\n') {
+            Some(i) => width(&s[i + 1..]),
+            None => self.column + width(s),
+        };
+    }
+
+    fn newline(&mut self, indent: usize, blank: bool) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    // `closers` is the number of closing parens that follow the expression on its line
+    fn expr(&mut self, expr: &RawSyntax, closers: usize) {
+        if let Some(flat) = flat(expr) {
+            if self.column + width(&flat) + closers <= WIDTH {
+                self.text(&flat);
+                return;
+            }
+        }
+
+        match expr {
+            RawSyntax::Atom(text) | RawSyntax::Comment(text, _) => self.text(text),
+            RawSyntax::Prefixed(prefix, inner) => {
+                self.text(prefix);
+                self.expr(inner, closers);
+            }
+            RawSyntax::List(open, items) => {
+                self.text(&open.to_string());
+                let start = self.column;
+
+                let (inline, indent) = match items.first() {
+                    Some(RawSyntax::Atom(head)) if BODY_FORMS.contains(head) => (1, start + 1),
+                    Some(RawSyntax::Atom(head)) if BLOCK_FORMS.contains(head) => (0, start + 1),
+                    Some(RawSyntax::Atom(head))
+                        if is_name(head)
+                            && width(head) <= ALIGN_LIMIT
+                            && start + width(head) < WIDTH / 2 =>
+                    {
+                        (1, start + width(head) + 1)
+                    }
+                    Some(RawSyntax::Atom(head)) if is_name(head) => (0, start + 1),
+                    _ => (0, start),
+                };
+
+                self.items(items, inline, indent, closers + 1);
+                self.text(&closing(*open).to_string());
+            }
+            RawSyntax::BlankLine => {}
+        }
+    }
+
+    // Writes the first item where the writer is, `inline` more after it on the same line, and
+    // the rest each on their own line at `indent`
+    fn items(&mut self, items: &[RawSyntax], inline: usize, indent: usize, closers: usize) {
+        let mut written = 0;
+        let mut after_comment = false;
+        let mut blank = false;
+
+        for (i, item) in items.iter().enumerate() {
+            match item {
+                RawSyntax::BlankLine => blank = written > 0,
+                RawSyntax::Comment(text, true) if written > 0 && !blank => {
+                    self.text(" ");
+                    self.text(text);
+                    after_comment = true;
+                }
+                _ => {
+                    let comment = matches!(item, RawSyntax::Comment(..));
+                    if written > 0 {
+                        if !after_comment && !blank && !comment && written <= inline {
+                            self.text(" ");
+                        } else {
+                            self.newline(indent, blank);
+                        }
+                    }
+
+                    let last = items[i + 1..]
+                        .iter()
+                        .all(|x| matches!(x, RawSyntax::BlankLine));
+                    self.expr(item, if last { closers } else { 0 });
+                    written += 1;
+                    after_comment = comment;
+                    blank = false;
+                }
+            }
+        }
+
+        // Nothing can follow a comment on its line, not even the closing paren
+        if after_comment {
+            self.newline(indent, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod fmt_tests {
+    use super::*;
+    use crate::rerrs::ErrorKind;
+
+    #[test]
+    fn long_forms_are_broken_by_kind() {
+        let source = r#"
+(define (fib n) (if (<= n 2) 'done-counting-down (+ (fib (- n 1)) (fib (- n 2)) (fib (- n 3)) (fib (- n 4)))))
+(cond [(empty? lst) (displayln "nothing to see here, move along please") 0] [else (length lst)])
+"#;
+
+        let expected = r#"(define (fib n)
+  (if (<= n 2)
+      'done-counting-down
+      (+ (fib (- n 1)) (fib (- n 2)) (fib (- n 3)) (fib (- n 4)))))
+(cond
+  [(empty? lst) (displayln "nothing to see here, move along please") 0]
+  [else (length lst)])
+"#;
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let source = r#";; Adds things up
+(define (sum lst)   ; the list
+      ;; Start at zero
+  (foldl + 0 lst))
+
+
+
+'(1 2 ; two
+  3)
+"#;
+
+        let expected = r#";; Adds things up
+(define (sum lst) ; the list
+  ;; Start at zero
+  (foldl + 0 lst))
+
+'(1
+  2 ; two
+  3)
+"#;
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn comments_at_the_end_of_a_list_keep_their_line() {
+        let formatted = format("(let ([x 1])\n  x ; the answer\n  )").unwrap();
+        assert_eq!(formatted, "(let ([x 1])\n  x ; the answer\n  )\n");
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn unreadable_source_is_an_error() {
+        assert_eq!(format("(define x").unwrap_err().kind(), ErrorKind::Parse);
+        assert_eq!(format(")").unwrap_err().kind(), ErrorKind::Parse);
+        assert_eq!(format("").unwrap(), "");
+    }
+}
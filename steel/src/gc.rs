@@ -2,7 +2,7 @@ use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::SteelVal;
 use crate::stop;
 use std::ops::Deref;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{ffi::OsStr, fmt};
 
@@ -76,6 +76,14 @@ impl<T: Clone> Gc<T> {
         Rc::as_ptr(&self.0)
     }
 
+    pub(crate) fn strong_count(this: &Self) -> usize {
+        Rc::strong_count(&this.0)
+    }
+
+    pub(crate) fn downgrade(this: &Self) -> WeakGc<T> {
+        WeakGc(Rc::downgrade(&this.0))
+    }
+
     // this does not match the original semantics of Rc::try_unwrap
     // in order to match this, we would need some unsafe rust
     // instead, I take a _slight_ performance hit in order to
@@ -85,7 +93,7 @@ impl<T: Clone> Gc<T> {
         let inner = Rc::clone(&this.0);
         drop(this);
         Rc::try_unwrap(inner)
-            .map_err(|_| SteelErr::new(ErrorKind::Generic, "value still has reference".to_string()))
+            .map_err(|_| SteelErr::new(ErrorKind::Generic, "value still has reference"))
             .map(|x| {
                 OBJECT_COUNT.fetch_sub(1, Ordering::SeqCst);
                 x
@@ -101,6 +109,22 @@ impl<T: Clone> Gc<T> {
     }
 }
 
+/// A reference to a `Gc` that doesn't keep the value alive
+pub(crate) struct WeakGc<T: Clone>(Weak<T>);
+
+impl<T: Clone> WeakGc<T> {
+    /// The value, if something else is still holding on to it
+    pub(crate) fn upgrade(&self) -> Option<Gc<T>> {
+        self.0.upgrade().map(Gc)
+    }
+}
+
+impl<T: Clone> Clone for WeakGc<T> {
+    fn clone(&self) -> Self {
+        WeakGc(Weak::clone(&self.0))
+    }
+}
+
 impl<T: Clone> AsRef<T> for Gc<T> {
     fn as_ref(&self) -> &T {
         self.0.as_ref()
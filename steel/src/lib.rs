@@ -1,9 +1,17 @@
+// Values are shared with plain `Rc`s, and native functions only get at custom values by cloning
+// them or borrowing through their `RefCell`, so there's no pointer erasure and nothing here
+// needs unsafe code. Keep it that way so embedders can rely on it.
+#![forbid(unsafe_code)]
+
 extern crate im_rc;
 #[macro_use]
 mod env;
 #[macro_use]
 pub mod core;
 pub mod compiler;
+pub mod diagnostics;
+pub mod docs;
+pub mod fmt;
 pub mod primitives;
 #[macro_use]
 pub mod rerrs;
@@ -12,10 +20,12 @@ pub mod stdlib;
 #[macro_use]
 pub(crate) mod gc;
 mod conversions;
+pub mod literate;
+pub mod lsp;
 pub(crate) mod parser;
 pub mod steel_vm;
 #[cfg(test)]
 mod tests;
 pub(crate) mod values;
 
-pub use self::{rerrs::SteelErr, rvals::SteelVal, stdlib::PRELUDE};
+pub use self::{parser::builder, rerrs::SteelErr, rvals::SteelVal, stdlib::PRELUDE};
@@ -0,0 +1,186 @@
+//! Literate Steel files (`.scmd`), which are markdown with the program in its code fences.
+//!
+//! Only fences tagged as `scheme`, `steel` or `racket` are run, in the order they appear, so
+//! other fences can still show shell commands or expected output.
+
+use crate::rvals::{Result, SteelVal};
+use crate::steel_vm::engine::Engine;
+
+/// The extension of literate Steel files
+pub const EXTENSION: &str = "scmd";
+
+const LANGUAGES: &[&str] = &["scheme", "steel", "racket"];
+
+/// A literate file split up into its lines, with the code blocks picked out
+pub struct LiterateFile<'a> {
+    /// Each line with its newline, and the code block it is part of
+    lines: Vec<(&'a str, Option<usize>)>,
+    /// The line each code block is closed on, if it is closed
+    closing_fences: Vec<Option<usize>>,
+}
+
+fn fence(line: &str) -> Option<&str> {
+    line.trim().strip_prefix("```").map(str::trim)
+}
+
+// Keeps the newline, so that errors are reported against the right line
+fn blank(line: &str) -> String {
+    line.chars()
+        .map(|c| match c {
+            '\n' | '\r' => c.to_string(),
+            c => " ".repeat(c.len_utf8()),
+        })
+        .collect()
+}
+
+impl<'a> LiterateFile<'a> {
+    pub fn parse(source: &'a str) -> Self {
+        let mut lines = Vec::new();
+        let mut closing_fences = Vec::new();
+        let mut current = None;
+
+        for (i, line) in source.split_inclusive('\n').enumerate() {
+            match (current, fence(line)) {
+                (None, Some(language)) if LANGUAGES.contains(&language) => {
+                    current = Some(closing_fences.len());
+                    closing_fences.push(None);
+                    lines.push((line, None));
+                }
+                (Some(block), Some("")) => {
+                    closing_fences[block] = Some(i);
+                    current = None;
+                    lines.push((line, None));
+                }
+                (block, _) => lines.push((line, block)),
+            }
+        }
+
+        LiterateFile {
+            lines,
+            closing_fences,
+        }
+    }
+
+    /// How many code blocks there are
+    pub fn blocks(&self) -> usize {
+        self.closing_fences.len()
+    }
+
+    /// The code of every block, with everything else blanked out so that the spans of
+    /// errors line up with the original file
+    pub fn code(&self) -> String {
+        self.code_where(|block| block.is_some())
+    }
+
+    fn code_where(&self, keep: impl Fn(Option<usize>) -> bool) -> String {
+        self.lines
+            .iter()
+            .map(|(line, block)| {
+                if keep(*block) {
+                    line.to_string()
+                } else {
+                    blank(line)
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the code blocks one after the other, and writes the file back out with what
+    /// each block returned in a `text` fence right after it. Stops at the first error, whose
+    /// span lines up with the original file.
+    pub fn render(&self, vm: &mut Engine) -> Result<String> {
+        let mut outputs = Vec::with_capacity(self.blocks());
+        for block in 0..self.blocks() {
+            let code = self.code_where(|x| x == Some(block));
+            outputs.push(vm.parse_and_execute_without_optimizations(&code)?);
+        }
+
+        let mut rendered = String::new();
+        for (i, (line, _)) in self.lines.iter().enumerate() {
+            rendered.push_str(line);
+
+            let block = match self.closing_fences.iter().position(|x| *x == Some(i)) {
+                Some(block) => block,
+                None => continue,
+            };
+
+            let results = outputs[block]
+                .iter()
+                .filter(|x| !matches!(x, SteelVal::Void))
+                .collect::<Vec<_>>();
+            if results.is_empty() {
+                continue;
+            }
+
+            if !line.ends_with('\n') {
+                rendered.push('\n');
+            }
+            rendered.push_str("```text\n");
+            for result in results {
+                rendered.push_str(&format!("=> {}\n", result));
+            }
+            rendered.push_str("```\n");
+        }
+
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod literate_tests {
+    use super::*;
+
+    const TUTORIAL: &str = r#"# Adding numbers
+
+Define a number first:
+
+```scheme
+(define x 10)
+```
+
+Then add to it, é
+
+```steel
+(+ x 1)
+(display "")
+```
+
+```sh
+steel tutorial.scmd
+```
+"#;
+
+    #[test]
+    fn only_the_code_is_kept() {
+        let file = LiterateFile::parse(TUTORIAL);
+        assert_eq!(file.blocks(), 2);
+
+        let code = file.code();
+        assert_eq!(code.len(), TUTORIAL.len());
+        assert_eq!(code.lines().count(), TUTORIAL.lines().count());
+        assert_eq!(
+            code.split_whitespace().collect::<Vec<_>>(),
+            vec!["(define", "x", "10)", "(+", "x", "1)", "(display", "\"\")"]
+        );
+    }
+
+    #[test]
+    fn outputs_follow_each_block() {
+        let mut vm = Engine::new();
+        let rendered = LiterateFile::parse(TUTORIAL).render(&mut vm).unwrap();
+
+        assert!(rendered.starts_with(TUTORIAL.split("```steel").next().unwrap()));
+        assert!(rendered.contains("(display \"\")\n```\n```text\n=> 11\n```\n"));
+        assert_eq!(rendered.matches("```text").count(), 1);
+    }
+
+    #[test]
+    fn errors_line_up_with_the_file() {
+        let source = "Some prose\n\n```scheme\n(undefined-function 1)\n```\n";
+        let mut vm = Engine::new();
+        let err = LiterateFile::parse(source).render(&mut vm).unwrap_err();
+
+        let span = err.span().unwrap();
+        assert_eq!(&source[span.start()..span.end()], "undefined-function");
+    }
+}
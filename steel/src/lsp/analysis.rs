@@ -0,0 +1,876 @@
+use crate::compiler::passes::VisitorMutUnit;
+use crate::diagnostics::{Diagnostic, FixSuggestion, Severity};
+use crate::docs::provided;
+use crate::parser::ast::{
+    Atom, Begin, Define, ExprKind, LambdaFunction, Macro, Quote, Require, Struct, SyntaxRules,
+};
+use crate::parser::lexer::TokenStream;
+use crate::parser::parser::{ParseError, Parser, SyntaxObject};
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::rvals::Result;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// When a module was last changed and how long it was then, along with what it provided
+type Provided = (SystemTime, u64, Option<Vec<String>>);
+
+thread_local! {
+    // What the modules read so far provide, so that they're only parsed again once they change
+    static PROVIDED: RefCell<HashMap<PathBuf, Provided>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingKind {
+    Function,
+    Variable,
+    Parameter,
+    Macro,
+    /// A struct, along with the predicate and accessors it defines
+    Struct,
+}
+
+/// A name bound somewhere in a document, along with everywhere it is referred to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+    /// Where the name is bound
+    pub span: Span,
+    /// Whether the name is bound at the top level of the document
+    pub top_level: bool,
+    pub references: Vec<Span>,
+}
+
+/// Which identifier in a document refers to which binding, worked out from the code as it is
+/// written. Macros aren't expanded, so identifiers in a macro use are taken to refer to
+/// whatever they would outside of it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// use steel::lsp::analysis::Analysis;
+///
+/// let source = "(define (square x) (* x x)) (square 3)";
+/// let analysis = Analysis::parse(source).unwrap();
+///
+/// // From the use of `square` to where it's defined
+/// let definition = analysis.definition(source.rfind("square").unwrap()).unwrap();
+/// assert_eq!(&source[definition.range()], "square");
+/// assert_eq!(definition.start(), source.find("square").unwrap());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Analysis {
+    bindings: Vec<Binding>,
+    // Every identifier that refers to a binding, including where it is bound
+    occurrences: Vec<(Span, usize)>,
+    // The names referred to that aren't bound in the document, like builtins and what
+    // required modules provide, along with where
+    free: HashMap<String, Vec<Span>>,
+    // Where each require is, along with the modules it names and where
+    requires: Vec<(Span, Vec<(String, Span)>)>,
+}
+
+impl Analysis {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut intern = HashMap::new();
+        let exprs = Parser::new(source, &mut intern)
+            .collect::<std::result::Result<Vec<_>, ParseError>>()?;
+        Ok(Analysis::new(&exprs))
+    }
+
+    pub(crate) fn new(exprs: &[ExprKind]) -> Self {
+        let mut resolver = Resolver {
+            analysis: Analysis::default(),
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            seen: HashSet::new(),
+        };
+
+        for expr in exprs {
+            resolver.declare_all(expr, true);
+        }
+        for expr in exprs {
+            resolver.visit(expr);
+        }
+
+        resolver.analysis
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// What is bound at the top level of the document, in the order it's bound
+    pub fn top_level_defines(&self) -> impl Iterator<Item = &Binding> {
+        self.bindings.iter().filter(|x| x.top_level)
+    }
+
+    /// The binding of the identifier at `offset`, whether the identifier binds it or refers to it
+    pub fn binding_at(&self, offset: usize) -> Option<&Binding> {
+        self.occurrences
+            .iter()
+            .find(|(span, _)| span.start() <= offset && offset <= span.end())
+            .map(|(_, binding)| &self.bindings[*binding])
+    }
+
+    /// Where the identifier at `offset` is bound
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        self.binding_at(offset).map(|x| x.span)
+    }
+
+    /// Everywhere the identifier at `offset` is referred to, along with where it is bound if
+    /// `include_definition` is set
+    pub fn references(&self, offset: usize, include_definition: bool) -> Vec<Span> {
+        match self.binding_at(offset) {
+            Some(binding) => {
+                let mut spans = Vec::new();
+                if include_definition {
+                    spans.push(binding.span);
+                }
+                spans.extend(binding.references.iter().copied());
+                spans
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Warnings for the local variables and parameters that are never referred to. Names
+    /// starting with `_` are taken to be unused on purpose.
+    pub fn unused(&self) -> Vec<Diagnostic> {
+        self.bindings
+            .iter()
+            .filter(|x| !x.top_level && x.references.is_empty() && !x.name.starts_with('_'))
+            .map(|x| Diagnostic {
+                severity: Severity::Warning,
+                code: None,
+                message: format!("`{}` is never used", x.name),
+                span: Some(x.span),
+                labels: Vec::new(),
+                notes: Vec::new(),
+                fixes: vec![FixSuggestion {
+                    message: "start the name with `_` if it's meant to be unused".to_string(),
+                    span: x.span,
+                    replacement: format!("_{}", x.name),
+                }],
+            })
+            .collect()
+    }
+
+    /// Everything [`Analysis::unused`] warns about, along with requires of modules that nothing
+    /// in the document uses, each with the edits that would fix it. Unused local defines can be
+    /// deleted as well as renamed, and unused requires removed.
+    ///
+    /// `source` is the document this was made from, and `path` is where it lives, which
+    /// required modules are read relative to. Modules that can't be read, like builtin ones,
+    /// and modules that don't provide anything are taken to be used.
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// use steel::lsp::analysis::Analysis;
+    ///
+    /// let source = "(define (f)\n  (define helper 10)\n  1)";
+    /// let actions = Analysis::parse(source).unwrap().code_actions(source, None);
+    ///
+    /// let delete = &actions[0].fixes[1];
+    /// assert_eq!(delete.message, "remove the definition of `helper`");
+    /// assert_eq!(&source[delete.span.range()], "  (define helper 10)\n");
+    /// assert_eq!(delete.replacement, "");
+    /// ```
+    pub fn code_actions(&self, source: &str, path: Option<&Path>) -> Vec<Diagnostic> {
+        let lists = lists(source);
+
+        let mut actions = self.unused();
+        for action in &mut actions {
+            let binding = match action.span.and_then(|x| self.binding_at(x.start())) {
+                Some(binding) => binding,
+                None => continue,
+            };
+            if binding.kind != BindingKind::Function && binding.kind != BindingKind::Variable {
+                continue;
+            }
+
+            if let Some(define) = defining_list(source, &lists, binding.span) {
+                action.fixes.push(FixSuggestion {
+                    message: format!("remove the definition of `{}`", binding.name),
+                    span: removal(source, define),
+                    replacement: String::new(),
+                });
+            }
+        }
+
+        let directory = match path.and_then(Path::parent) {
+            Some(directory) => directory,
+            None => return actions,
+        };
+
+        for (keyword, modules) in &self.requires {
+            let unused = modules
+                .iter()
+                .filter(|(module, _)| match provided_by(&directory.join(module)) {
+                    Some(names) => !names.iter().any(|x| self.free.contains_key(x)),
+                    None => false,
+                })
+                .collect::<Vec<_>>();
+
+            for (module, span) in &unused {
+                // With nothing else in it, the whole require goes
+                let remove = if unused.len() == modules.len() {
+                    enclosing(&lists, keyword.start())
+                } else {
+                    Some(*span)
+                };
+
+                actions.push(Diagnostic {
+                    severity: Severity::Warning,
+                    code: None,
+                    message: format!("nothing from {:?} is used", module),
+                    span: Some(*span),
+                    labels: Vec::new(),
+                    notes: Vec::new(),
+                    fixes: remove
+                        .map(|x| FixSuggestion {
+                            message: format!("remove the require of {:?}", module),
+                            span: removal(source, x),
+                            replacement: String::new(),
+                        })
+                        .into_iter()
+                        .collect(),
+                });
+            }
+        }
+
+        actions
+    }
+
+    /// Warnings for the names the document refers to without defining that one of `modules`
+    /// provides, when the document doesn't require it, each with the requires that would
+    /// define it. `modules` are the names modules are required by along with what they
+    /// provide, like those of [`Engine::module_exports`] or [`modules_near`].
+    ///
+    /// [`Engine::module_exports`]: crate::steel_vm::engine::Engine::module_exports
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// use steel::lsp::analysis::Analysis;
+    ///
+    /// let modules = vec![("steel/uuid".to_string(), vec!["uuid/v4".to_string()])];
+    /// let source = "(define id (uuid/v4))";
+    /// let missing = Analysis::parse(source).unwrap().missing_requires(source, &modules);
+    ///
+    /// assert_eq!(missing[0].fixes[0].replacement, "(require \"steel/uuid\")\n");
+    /// ```
+    pub fn missing_requires(
+        &self,
+        source: &str,
+        modules: &[(String, Vec<String>)],
+    ) -> Vec<Diagnostic> {
+        let required = self
+            .requires
+            .iter()
+            .flat_map(|(_, modules)| modules.iter().map(|(module, _)| module))
+            .collect::<Vec<_>>();
+
+        let mut free = self.free.iter().collect::<Vec<_>>();
+        free.sort_by_key(|(_, spans)| spans[0].start());
+
+        let mut missing = Vec::new();
+        for (name, spans) in free {
+            let providers = modules
+                .iter()
+                .filter(|(module, names)| names.contains(name) && !required.contains(&module))
+                .map(|(module, _)| module)
+                .collect::<Vec<_>>();
+            if providers.is_empty() {
+                continue;
+            }
+
+            missing.push(Diagnostic {
+                severity: Severity::Warning,
+                code: None,
+                message: format!(
+                    "`{}` comes from {}, which isn't required",
+                    name,
+                    providers
+                        .iter()
+                        .map(|x| format!("{:?}", x))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                ),
+                span: Some(spans[0]),
+                labels: Vec::new(),
+                notes: Vec::new(),
+                fixes: providers
+                    .iter()
+                    .map(|module| self.require_fix(source, module))
+                    .collect(),
+            });
+        }
+        missing
+    }
+
+    // Adds a require of `module` after the last require in the document, or at the start of it
+    // when there aren't any
+    fn require_fix(&self, source: &str, module: &str) -> FixSuggestion {
+        let require = format!("(require {:?})", module);
+        let last = self
+            .requires
+            .iter()
+            .map(|(keyword, _)| *keyword)
+            .max_by_key(|x| x.start())
+            .and_then(|x| enclosing(&lists(source), x.start()));
+
+        let (offset, replacement) = match last {
+            Some(list) => match source[list.end()..].find('\n') {
+                Some(i) => (list.end() + i + 1, format!("{}\n", require)),
+                None => (source.len(), format!("\n{}", require)),
+            },
+            None => (0, format!("{}\n", require)),
+        };
+
+        FixSuggestion {
+            message: format!("require {:?}", module),
+            span: Span::new(offset, offset),
+            replacement,
+        }
+    }
+}
+
+/// The Steel files in `directory` that provide names, by file name along with what they
+/// provide, for suggesting requires to the documents next to them
+pub fn modules_near(directory: &Path) -> Vec<(String, Vec<String>)> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut modules = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            match path.extension().and_then(|x| x.to_str()) {
+                Some("scm") | Some("stl") | Some("rkt") => {}
+                _ => return None,
+            }
+            let names = provided_by(&path)?;
+            Some((path.file_name()?.to_str()?.to_string(), names))
+        })
+        .collect::<Vec<_>>();
+    modules.sort();
+    modules
+}
+
+// The names the module at `path` provides, when it can be read and provides any
+fn provided_by(path: &Path) -> Option<Vec<String>> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let (modified, length) = (metadata.modified().ok()?, metadata.len());
+
+    let cached = PROVIDED.with(|x| match x.borrow().get(path) {
+        Some((at, len, names)) if *at == modified && *len == length => Some(names.clone()),
+        _ => None,
+    });
+    if let Some(names) = cached {
+        return names;
+    }
+
+    let names = read_provided(path);
+    PROVIDED.with(|x| {
+        x.borrow_mut()
+            .insert(path.to_path_buf(), (modified, length, names.clone()))
+    });
+    names
+}
+
+fn read_provided(path: &Path) -> Option<Vec<String>> {
+    let source = std::fs::read_to_string(path).ok()?;
+    let mut intern = HashMap::new();
+    let exprs = Parser::new(&source, &mut intern)
+        .collect::<std::result::Result<Vec<_>, ParseError>>()
+        .ok()?;
+    provided(&exprs).map(|names| names.into_iter().map(str::to_string).collect())
+}
+
+// Every list in the source, from its opening paren to just after its closing one
+fn lists(source: &str) -> Vec<Span> {
+    let mut open = Vec::new();
+    let mut lists = Vec::new();
+    for token in TokenStream::new(source, true) {
+        match token.ty {
+            TokenType::OpenParen => open.push(token.span.start()),
+            TokenType::CloseParen => {
+                if let Some(start) = open.pop() {
+                    lists.push(Span::new(start, token.span.end()));
+                }
+            }
+            _ => {}
+        }
+    }
+    lists
+}
+
+// The innermost list around `offset`
+fn enclosing(lists: &[Span], offset: usize) -> Option<Span> {
+    lists
+        .iter()
+        .filter(|x| x.start() < offset && offset < x.end())
+        .min_by_key(|x| x.end() - x.start())
+        .copied()
+}
+
+// The `define` that binds the name at `name`, either `(define name ...)` or
+// `(define (name args ...) ...)`
+fn defining_list(source: &str, lists: &[Span], name: Span) -> Option<Span> {
+    let is_define = |list: Span| {
+        let head = source[list.start() + 1..]
+            .trim_start()
+            .split(|c: char| c.is_whitespace() || c == '(' || c == '[')
+            .next();
+        matches!(head, Some("define") | Some("defn"))
+    };
+
+    let list = enclosing(lists, name.start())?;
+    if is_define(list) {
+        return Some(list);
+    }
+    let outer = enclosing(lists, list.start())?;
+    if is_define(outer) && source[list.start() + 1..name.start()].trim().is_empty() {
+        Some(outer)
+    } else {
+        None
+    }
+}
+
+// What to delete to take `span` out of the source: the whole line when nothing else is on it,
+// otherwise `span` along with the whitespace before it
+fn removal(source: &str, span: Span) -> Span {
+    let line_start = source[..span.start()]
+        .rfind('\n')
+        .map(|x| x + 1)
+        .unwrap_or(0);
+    let line_end = source[span.end()..]
+        .find('\n')
+        .map(|x| span.end() + x + 1)
+        .unwrap_or_else(|| source.len());
+
+    if source[line_start..span.start()].trim().is_empty()
+        && source[span.end()..line_end].trim().is_empty()
+    {
+        Span::new(line_start, line_end)
+    } else {
+        Span::new(source[..span.start()].trim_end().len(), span.end())
+    }
+}
+
+fn identifier(expr: &ExprKind) -> Option<(&str, Span)> {
+    match expr {
+        ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(name),
+                    span,
+                    ..
+                },
+        }) => Some((name, *span)),
+        _ => None,
+    }
+}
+
+struct Resolver {
+    analysis: Analysis,
+    globals: HashMap<String, usize>,
+    // The names bound by each function being looked at, innermost last
+    scopes: Vec<HashMap<String, usize>>,
+    // Where each of the occurrences is
+    seen: HashSet<Span>,
+}
+
+impl Resolver {
+    fn bind(&mut self, name: &str, span: Span, kind: BindingKind, top_level: bool) {
+        // The same identifier is only ever bound once, like the name of a named let which is
+        // repeated in what it lowers to
+        if !self.seen.insert(span) {
+            return;
+        }
+
+        let index = self.analysis.bindings.len();
+        self.analysis.bindings.push(Binding {
+            name: name.to_string(),
+            kind,
+            span,
+            top_level,
+            references: Vec::new(),
+        });
+        self.analysis.occurrences.push((span, index));
+
+        match self.scopes.last_mut() {
+            Some(scope) if !top_level => scope.insert(name.to_string(), index),
+            _ => self.globals.insert(name.to_string(), index),
+        };
+    }
+
+    // Defines are visible everywhere in the body they're in, so they're all bound before
+    // anything in the body is looked at
+    fn declare_all(&mut self, expr: &ExprKind, top_level: bool) {
+        match expr {
+            ExprKind::Begin(Begin { exprs, .. }) => {
+                for expr in exprs {
+                    self.declare_all(expr, top_level);
+                }
+            }
+            ExprKind::Define(define) => {
+                if let Some((name, span)) = identifier(&define.name) {
+                    let kind = match define.body {
+                        ExprKind::LambdaFunction(_) => BindingKind::Function,
+                        _ => BindingKind::Variable,
+                    };
+                    self.bind(name, span, kind, top_level);
+                }
+            }
+            ExprKind::Macro(Macro { name, .. }) => {
+                if let Some((name, span)) = identifier(name) {
+                    self.bind(name, span, BindingKind::Macro, top_level);
+                }
+            }
+            ExprKind::Struct(s) => {
+                if let Some((name, span)) = identifier(&s.name) {
+                    self.bind(name, span, BindingKind::Struct, top_level);
+                    self.bind_struct_functions(name, span, s, top_level);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The predicate and accessors of a struct point back at the struct's name
+    fn bind_struct_functions(&mut self, name: &str, span: Span, s: &Struct, top_level: bool) {
+        let mut functions = vec![format!("{}?", name)];
+        functions.extend(
+            s.fields
+                .iter()
+                .filter_map(identifier)
+                .map(|(field, _)| format!("{}-{}", name, field)),
+        );
+
+        for function in functions {
+            let index = self.analysis.bindings.len();
+            self.analysis.bindings.push(Binding {
+                name: function.clone(),
+                kind: BindingKind::Struct,
+                span,
+                top_level,
+                references: Vec::new(),
+            });
+
+            match self.scopes.last_mut() {
+                Some(scope) if !top_level => scope.insert(function, index),
+                _ => self.globals.insert(function, index),
+            };
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .copied()
+    }
+}
+
+impl VisitorMutUnit for Resolver {
+    fn visit_define(&mut self, define: &Define) {
+        self.visit(&define.body);
+    }
+
+    fn visit_lambda_function(&mut self, lambda_function: &LambdaFunction) {
+        self.scopes.push(HashMap::new());
+
+        for arg in &lambda_function.args {
+            match arg {
+                // `(name default)`, for optional and keyword arguments
+                ExprKind::List(l) if l.args.len() == 2 => {
+                    self.visit(&l.args[1]);
+                    if let Some((name, span)) = identifier(&l.args[0]) {
+                        self.bind(name, span, BindingKind::Parameter, false);
+                    }
+                }
+                arg => {
+                    if let Some((name, span)) = identifier(arg) {
+                        self.bind(name, span, BindingKind::Parameter, false);
+                    }
+                }
+            }
+        }
+
+        self.declare_all(&lambda_function.body, false);
+        self.visit(&lambda_function.body);
+        self.scopes.pop();
+    }
+
+    fn visit_atom(&mut self, a: &Atom) {
+        let (name, span) = match &a.syn {
+            SyntaxObject {
+                ty: TokenType::Identifier(name),
+                span,
+                ..
+            } => (name, *span),
+            _ => return,
+        };
+
+        // Where a name is bound isn't a reference to it
+        if self.seen.contains(&span) {
+            return;
+        }
+
+        match self.resolve(name) {
+            Some(index) => {
+                self.analysis.bindings[index].references.push(span);
+                self.analysis.occurrences.push((span, index));
+                self.seen.insert(span);
+            }
+            None => {
+                self.analysis
+                    .free
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(span);
+            }
+        }
+    }
+
+    fn visit_quote(&mut self, _quote: &Quote) {}
+
+    fn visit_syntax_rules(&mut self, _l: &SyntaxRules) {}
+
+    fn visit_require(&mut self, s: &Require) {
+        let modules = s
+            .modules
+            .iter()
+            .filter_map(|x| match &x.syn.ty {
+                TokenType::StringLiteral(module) => Some((module.to_string(), x.syn.span)),
+                _ => None,
+            })
+            .collect();
+        self.analysis.requires.push((s.location.span, modules));
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+
+    fn offset(source: &str, needle: &str, nth: usize) -> usize {
+        source.match_indices(needle).nth(nth).unwrap().0
+    }
+
+    #[test]
+    fn references_are_resolved_lexically() {
+        let source = r#"
+            (define x 10)
+            (define (f x) (+ x 1))
+            (define (g) (+ x (f x)))
+        "#;
+        let analysis = Analysis::parse(source).unwrap();
+
+        // The parameter shadows the global
+        let parameter = offset(source, "x", 1);
+        assert_eq!(
+            analysis.definition(offset(source, "x", 2)).unwrap().start(),
+            parameter
+        );
+
+        let global = offset(source, "x", 0);
+        let references = analysis.references(global, false);
+        assert_eq!(
+            references.iter().map(|x| x.start()).collect::<Vec<_>>(),
+            vec![offset(source, "x", 3), offset(source, "x", 4)]
+        );
+        assert_eq!(analysis.references(global, true).len(), 3);
+
+        // Builtins aren't bound in the document
+        assert!(analysis.definition(offset(source, "+", 0)).is_none());
+    }
+
+    #[test]
+    fn local_defines_are_visible_throughout_their_body() {
+        let source = r#"
+            (define (outer)
+              (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+              (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+              (let loop ([i 0]) (if (< i 3) (loop (+ i 1)) (even? i))))
+        "#;
+        let analysis = Analysis::parse(source).unwrap();
+
+        let odd = analysis.definition(offset(source, "odd?", 0)).unwrap();
+        assert_eq!(odd.start(), offset(source, "odd?", 1));
+        assert_eq!(
+            analysis.binding_at(odd.start()).unwrap().references.len(),
+            1
+        );
+
+        let top_level = analysis.top_level_defines().collect::<Vec<_>>();
+        assert_eq!(top_level.len(), 1);
+        assert_eq!(top_level[0].name, "outer");
+        assert_eq!(top_level[0].kind, BindingKind::Function);
+        assert!(analysis.unused().is_empty());
+    }
+
+    #[test]
+    fn unused_locals_are_warned_about() {
+        let source = r#"
+            (define unused-global 1)
+            (define (f a b _c)
+              (define helper 10)
+              (let ([y 2]) a))
+            (struct point (x y))
+            (point-x (point 1 2))
+        "#;
+        let analysis = Analysis::parse(source).unwrap();
+        let unused = analysis.unused();
+
+        let names = unused
+            .iter()
+            .map(|x| &source[x.span.unwrap().range()])
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b", "helper", "y"]);
+        assert_eq!(unused[0].fixes[0].replacement, "_b");
+
+        // Struct functions lead back to the struct
+        let point = analysis.definition(offset(source, "point-x", 0)).unwrap();
+        assert_eq!(point.start(), offset(source, "point", 0));
+    }
+
+    // The source after applying fix `which` of the diagnostic about `name`
+    fn fix(source: &str, actions: &[Diagnostic], name: &str, which: usize) -> String {
+        let action = actions
+            .iter()
+            .find(|x| &source[x.span.unwrap().range()] == name)
+            .unwrap();
+        let fix = &action.fixes[which];
+        let mut fixed = source.to_string();
+        fixed.replace_range(fix.span.range(), &fix.replacement);
+        fixed
+    }
+
+    #[test]
+    fn unused_local_defines_can_be_deleted() {
+        let source = "(define (f a)\n  (define helper 10)\n  (define (g) 1) 2)\n";
+        let actions = Analysis::parse(source).unwrap().code_actions(source, None);
+        assert_eq!(actions.len(), 3);
+
+        assert_eq!(
+            fix(source, &actions, "helper", 1),
+            "(define (f a)\n  (define (g) 1) 2)\n"
+        );
+        assert_eq!(
+            fix(source, &actions, "g", 1),
+            "(define (f a)\n  (define helper 10) 2)\n"
+        );
+
+        // Parameters can only be renamed
+        assert_eq!(
+            fix(source, &actions, "a", 0),
+            source.replace("(f a)", "(f _a)")
+        );
+        let parameter = actions.iter().find(|x| x.message == "`a` is never used");
+        assert_eq!(parameter.unwrap().fixes.len(), 1);
+    }
+
+    #[test]
+    fn unused_requires_can_be_removed() {
+        let directory =
+            std::env::temp_dir().join(format!("steel-analysis-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.scm"), "(provide a) (define (a) 1)").unwrap();
+        std::fs::write(directory.join("b.scm"), "(provide b) (define (b) 2)").unwrap();
+        let path = directory.join("main.scm");
+
+        let source = "(require \"a.scm\" \"b.scm\" \"steel/uuid\")\n(a)\n";
+        let actions = Analysis::parse(source)
+            .unwrap()
+            .code_actions(source, Some(&path));
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].message, "nothing from \"b.scm\" is used");
+        assert_eq!(
+            fix(source, &actions, "\"b.scm\"", 0),
+            "(require \"a.scm\" \"steel/uuid\")\n(a)\n"
+        );
+
+        // The whole require goes when nothing it names is used
+        let source = "(require \"b.scm\")\n(+ 1 2)\n";
+        let actions = Analysis::parse(source)
+            .unwrap()
+            .code_actions(source, Some(&path));
+        assert_eq!(fix(source, &actions, "\"b.scm\"", 0), "(+ 1 2)\n");
+
+        // Without knowing where the document is, requires are left alone
+        assert!(Analysis::parse(source)
+            .unwrap()
+            .code_actions(source, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn missing_requires_are_suggested() {
+        let directory =
+            std::env::temp_dir().join(format!("steel-missing-requires-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("shapes.scm"),
+            "(provide area) (define (area r) r)",
+        )
+        .unwrap();
+        std::fs::write(directory.join("notes.txt"), "(provide area)").unwrap();
+
+        let mut modules = vec![(
+            "steel/uuid".to_string(),
+            vec!["uuid/v4".to_string(), "uuid/nil".to_string()],
+        )];
+        modules.extend(modules_near(&directory));
+        assert_eq!(modules.len(), 2);
+
+        let source = "(require \"steel/semver\")\n(define (f) (area (uuid/v4)))";
+        let missing = Analysis::parse(source)
+            .unwrap()
+            .missing_requires(source, &modules);
+        let names = missing
+            .iter()
+            .map(|x| &source[x.span.unwrap().range()])
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["area", "uuid/v4"]);
+        assert_eq!(
+            missing[0].message,
+            "`area` comes from \"shapes.scm\", which isn't required"
+        );
+
+        // Requires go after the ones already there
+        assert_eq!(
+            fix(source, &missing, "uuid/v4", 0),
+            "(require \"steel/semver\")\n(require \"steel/uuid\")\n(define (f) (area (uuid/v4)))"
+        );
+
+        // Modules that are already required aren't suggested again
+        let source = "(require \"shapes.scm\") (area 1)";
+        assert!(Analysis::parse(source)
+            .unwrap()
+            .missing_requires(source, &modules)
+            .is_empty());
+
+        // Modules are read again once they change
+        std::fs::write(
+            directory.join("shapes.scm"),
+            "(provide area perimeter) (define (area r) r) (define (perimeter r) r)",
+        )
+        .unwrap();
+        assert_eq!(
+            modules_near(&directory),
+            vec![(
+                "shapes.scm".to_string(),
+                vec!["area".to_string(), "perimeter".to_string()]
+            )]
+        );
+    }
+}
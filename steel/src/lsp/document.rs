@@ -0,0 +1,496 @@
+use super::analysis::Analysis;
+use crate::parser::ast::{
+    Apply, Atom, Begin, CallCC, Define, Eval, Execute, ExprKind, If, LambdaFunction, List, Macro,
+    Panic, Quote, Read, Require, Return, Set, Struct, SyntaxRules, Transduce,
+};
+use crate::parser::lexer::TokenStream;
+use crate::parser::parser::{ParseError, Parser, SyntaxObject};
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::VisitorMutRef;
+use crate::rerrs::SteelErr;
+use crate::rvals::Result;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// The text of a document open in an editor, along with its top level forms. The forms are
+/// kept between edits, so an edit only parses the forms it touches again and the rest are
+/// moved to where they now are.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// use steel::lsp::document::Document;
+///
+/// let mut document = Document::new("(define (square x) (* x x))\n(square 3)");
+///
+/// // Only the comment is parsed, and the forms after it are moved down a line
+/// document.edit(0..0, "; squares a number\n");
+/// assert_eq!(document.text(), "; squares a number\n(define (square x) (* x x))\n(square 3)");
+///
+/// let text = document.text();
+/// let analysis = document.analysis().unwrap();
+/// let definition = analysis.definition(text.rfind("square").unwrap()).unwrap();
+/// assert_eq!(definition.start(), text.find("(square x)").unwrap() + 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Document {
+    text: String,
+    intern: HashMap<String, Rc<TokenType>>,
+    // Each top level form, along with where it is in the text. When the text doesn't parse
+    // these are empty and the error is kept instead.
+    forms: Vec<ExprKind>,
+    ranges: Vec<Range<usize>>,
+    error: Option<SteelErr>,
+}
+
+impl Document {
+    pub fn new(text: impl Into<String>) -> Self {
+        let mut document = Document {
+            text: text.into(),
+            intern: HashMap::new(),
+            forms: Vec::new(),
+            ranges: Vec::new(),
+            error: None,
+        };
+        document.parse_all();
+        document
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The top level forms of the document, or why it doesn't parse
+    pub(crate) fn forms(&self) -> Result<&[ExprKind]> {
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(&self.forms),
+        }
+    }
+
+    /// Works out what the identifiers in the document refer to, from the forms already parsed
+    pub fn analysis(&self) -> Result<Analysis> {
+        self.forms().map(Analysis::new)
+    }
+
+    /// Replaces the whole text of the document
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.parse_all();
+    }
+
+    /// Replaces the text in the byte `range` with `replacement`.
+    ///
+    /// The forms that end before the edit and those that start after it are kept. What's in
+    /// between is parsed again, going on into the forms after the edit until the text lines up
+    /// with one of them again, since an edit can open a list or a string that runs on. When the
+    /// document doesn't parse, before or after the edit, all of it is parsed again.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) {
+        self.text.replace_range(range.clone(), replacement);
+        if self.error.is_some() || self.ranges.len() != self.forms.len() {
+            self.parse_all();
+            return;
+        }
+
+        let delta = replacement.len() as isize - range.len() as isize;
+        let before = self
+            .ranges
+            .iter()
+            .take_while(|x| x.end < range.start)
+            .count();
+        let after = before
+            + self.ranges[before..]
+                .iter()
+                .take_while(|x| x.start <= range.end)
+                .count();
+
+        // From the end of the last form kept before the edit, so comments and whitespace
+        // between forms are parsed again along with the edit
+        let start = match before {
+            0 => 0,
+            i => self.ranges[i - 1].end,
+        };
+        let kept = self.ranges[after..]
+            .iter()
+            .map(|x| shift(x.start, delta))
+            .collect::<Vec<_>>();
+
+        let (ranges, lined_up) = match top_level_ranges(&self.text, start, &kept) {
+            Some(found) => found,
+            None => return self.parse_all(),
+        };
+        let end = kept.get(lined_up).copied().unwrap_or(self.text.len());
+
+        let mut forms = match self.parse(start, end) {
+            Ok(forms) if forms.len() == ranges.len() => forms,
+            _ => return self.parse_all(),
+        };
+        let mut rest = self.forms.split_off(after + lined_up);
+        for form in &mut rest {
+            shift_spans(form, delta);
+        }
+
+        self.forms.truncate(before);
+        self.forms.append(&mut forms);
+        self.forms.append(&mut rest);
+
+        let rest = self.ranges.split_off(after + lined_up);
+        self.ranges.truncate(before);
+        self.ranges.extend(ranges);
+        self.ranges.extend(
+            rest.into_iter()
+                .map(|x| shift(x.start, delta)..shift(x.end, delta)),
+        );
+    }
+
+    fn parse_all(&mut self) {
+        self.forms.clear();
+        self.ranges.clear();
+        self.error = None;
+
+        match self.parse(0, self.text.len()) {
+            Ok(forms) => match top_level_ranges(&self.text, 0, &[]) {
+                Some((ranges, _)) if ranges.len() == forms.len() => {
+                    self.forms = forms;
+                    self.ranges = ranges;
+                }
+                // Without a range for each form, the next edit parses everything again
+                _ => self.forms = forms,
+            },
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // The forms in the text between `start` and `end`, with their spans in the whole text
+    fn parse(&mut self, start: usize, end: usize) -> Result<Vec<ExprKind>> {
+        let mut forms = Parser::new(&self.text[start..end], &mut self.intern)
+            .collect::<std::result::Result<Vec<_>, ParseError>>()?;
+        if start != 0 {
+            for form in &mut forms {
+                shift_spans(form, start as isize);
+            }
+        }
+        Ok(forms)
+    }
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    (offset as isize + delta) as usize
+}
+
+// Where each top level form in `text` starting from `start` is, up to the first form that
+// starts at one of the offsets in `stop`, along with which of them that was. `None` if the
+// lists aren't balanced.
+fn top_level_ranges(
+    text: &str,
+    start: usize,
+    stop: &[usize],
+) -> Option<(Vec<Range<usize>>, usize)> {
+    let mut ranges = Vec::new();
+    let mut depth = 0usize;
+    // Whether a quote or `#` is waiting for the form it applies to
+    let mut prefixed = false;
+    let mut form_start = start;
+    let mut next_stop = 0;
+
+    for token in TokenStream::new(&text[start..], true) {
+        let span = Span::new(start + token.span.start(), start + token.span.end());
+
+        if depth == 0 && !prefixed {
+            while next_stop < stop.len() && stop[next_stop] < span.start() {
+                next_stop += 1;
+            }
+            if stop.get(next_stop) == Some(&span.start()) {
+                return Some((ranges, next_stop));
+            }
+            form_start = span.start();
+        }
+
+        match token.ty {
+            TokenType::QuoteTick
+            | TokenType::QuasiQuote
+            | TokenType::Unquote
+            | TokenType::UnquoteSplice
+            | TokenType::Hash => prefixed = true,
+            TokenType::OpenParen => {
+                depth += 1;
+                prefixed = false;
+            }
+            TokenType::CloseParen => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    ranges.push(form_start..span.end());
+                }
+            }
+            _ => {
+                prefixed = false;
+                if depth == 0 {
+                    ranges.push(form_start..span.end());
+                }
+            }
+        }
+    }
+
+    if depth == 0 && !prefixed {
+        Some((ranges, stop.len()))
+    } else {
+        None
+    }
+}
+
+// Moves every span in `expr` by `delta` bytes
+fn shift_spans(expr: &mut ExprKind, delta: isize) {
+    if delta != 0 {
+        SpanShifter { delta }.visit(expr);
+    }
+}
+
+struct SpanShifter {
+    delta: isize,
+}
+
+impl SpanShifter {
+    fn shift(&self, syn: &mut SyntaxObject) {
+        syn.set_span(Span::new(
+            shift(syn.span.start(), self.delta),
+            shift(syn.span.end(), self.delta),
+        ));
+    }
+}
+
+impl VisitorMutRef for SpanShifter {
+    type Output = ();
+
+    fn visit_if(&mut self, f: &mut If) {
+        self.shift(&mut f.location);
+        self.visit(&mut f.test_expr);
+        self.visit(&mut f.then_expr);
+        self.visit(&mut f.else_expr);
+    }
+
+    fn visit_define(&mut self, define: &mut Define) {
+        self.shift(&mut define.location);
+        self.visit(&mut define.name);
+        self.visit(&mut define.body);
+    }
+
+    fn visit_lambda_function(&mut self, lambda_function: &mut LambdaFunction) {
+        self.shift(&mut lambda_function.location);
+        for arg in &mut lambda_function.args {
+            self.visit(arg);
+        }
+        self.visit(&mut lambda_function.body);
+    }
+
+    fn visit_begin(&mut self, begin: &mut Begin) {
+        self.shift(&mut begin.location);
+        for expr in &mut begin.exprs {
+            self.visit(expr);
+        }
+    }
+
+    fn visit_return(&mut self, r: &mut Return) {
+        self.shift(&mut r.location);
+        self.visit(&mut r.expr);
+    }
+
+    fn visit_apply(&mut self, apply: &mut Apply) {
+        self.shift(&mut apply.location);
+        self.visit(&mut apply.func);
+        for arg in &mut apply.args {
+            self.visit(arg);
+        }
+        self.visit(&mut apply.list);
+    }
+
+    fn visit_panic(&mut self, p: &mut Panic) {
+        self.shift(&mut p.location);
+        self.visit(&mut p.message);
+    }
+
+    fn visit_transduce(&mut self, transduce: &mut Transduce) {
+        self.shift(&mut transduce.location);
+        self.visit(&mut transduce.transducer);
+        self.visit(&mut transduce.func);
+        self.visit(&mut transduce.initial_value);
+        self.visit(&mut transduce.iterable);
+    }
+
+    fn visit_read(&mut self, read: &mut Read) {
+        self.shift(&mut read.location);
+        self.visit(&mut read.expr);
+    }
+
+    fn visit_execute(&mut self, execute: &mut Execute) {
+        self.shift(&mut execute.location);
+        self.visit(&mut execute.transducer);
+        self.visit(&mut execute.collection);
+        if let Some(output_type) = &mut execute.output_type {
+            self.visit(output_type);
+        }
+    }
+
+    fn visit_quote(&mut self, quote: &mut Quote) {
+        self.shift(&mut quote.location);
+        self.visit(&mut quote.expr);
+    }
+
+    fn visit_struct(&mut self, s: &mut Struct) {
+        self.shift(&mut s.location);
+        self.visit(&mut s.name);
+        for field in &mut s.fields {
+            self.visit(field);
+        }
+    }
+
+    fn visit_macro(&mut self, m: &mut Macro) {
+        self.shift(&mut m.location);
+        self.visit(&mut m.name);
+        self.visit_syntax_rules(&mut m.syntax_rules);
+    }
+
+    fn visit_eval(&mut self, e: &mut Eval) {
+        self.shift(&mut e.location);
+        self.visit(&mut e.expr);
+    }
+
+    fn visit_atom(&mut self, a: &mut Atom) {
+        self.shift(&mut a.syn);
+    }
+
+    fn visit_list(&mut self, l: &mut List) {
+        for arg in &mut l.args {
+            self.visit(arg);
+        }
+    }
+
+    fn visit_syntax_rules(&mut self, l: &mut SyntaxRules) {
+        self.shift(&mut l.location);
+        for expr in &mut l.syntax {
+            self.visit(expr);
+        }
+        for pattern in &mut l.patterns {
+            self.visit(&mut pattern.pattern);
+            self.visit(&mut pattern.body);
+        }
+    }
+
+    fn visit_set(&mut self, s: &mut Set) {
+        self.shift(&mut s.location);
+        self.visit(&mut s.variable);
+        self.visit(&mut s.expr);
+    }
+
+    fn visit_require(&mut self, s: &mut Require) {
+        self.shift(&mut s.location);
+        for module in &mut s.modules {
+            self.shift(&mut module.syn);
+        }
+    }
+
+    fn visit_callcc(&mut self, cc: &mut CallCC) {
+        self.shift(&mut cc.location);
+        self.visit(&mut cc.expr);
+    }
+}
+
+#[cfg(test)]
+mod document_tests {
+    use super::*;
+
+    // The forms of a document edited in place, next to those of the same text parsed from
+    // scratch, spans and all
+    fn compare(document: &Document) {
+        let fresh = Document::new(document.text());
+        assert_eq!(
+            format!("{:?}", document.forms().unwrap()),
+            format!("{:?}", fresh.forms().unwrap()),
+            "{}",
+            document.text()
+        );
+        assert_eq!(document.ranges, fresh.ranges);
+    }
+
+    fn edit(document: &mut Document, needle: &str, replacement: &str) {
+        let start = document.text().find(needle).unwrap();
+        document.edit(start..start + needle.len(), replacement);
+    }
+
+    #[test]
+    fn edits_match_parsing_from_scratch() {
+        let mut document = Document::new(
+            "; squares\n(define (square x) (* x x))\n'(a b)\n\n(define-syntax swap (syntax-rules () [(swap a b) (list b a)]))\n(require \"lib.scm\")\n(square 3)",
+        );
+        compare(&document);
+
+        edit(&mut document, "(* x x)", "(* x x x)");
+        compare(&document);
+        edit(&mut document, "'(a b)", "'(a b c d)\n(define y 2)");
+        compare(&document);
+        edit(&mut document, "; squares", ";");
+        compare(&document);
+        edit(&mut document, "(square 3)", "");
+        compare(&document);
+        edit(&mut document, "(define y 2)", "y");
+        compare(&document);
+        // Typing onto the end of an atom
+        let end = document.text().rfind('y').unwrap() + 1;
+        document.edit(end..end, "z");
+        compare(&document);
+        // A quote in front of a form changes it without touching it
+        let start = document.text().find("(define-syntax").unwrap();
+        document.edit(start..start, "'");
+        compare(&document);
+    }
+
+    #[test]
+    fn parsing_stops_where_the_forms_line_up() {
+        let text = "(a) 'b (c ; d)\n e) f";
+        assert_eq!(
+            top_level_ranges(text, 3, &[8, 19]),
+            Some((vec![4..6, 7..18], 1))
+        );
+        assert_eq!(top_level_ranges(text, 0, &[]).unwrap().0.len(), 4);
+        assert_eq!(top_level_ranges("(a))", 0, &[]), None);
+    }
+
+    #[test]
+    fn edits_that_run_on_are_followed() {
+        let mut document = Document::new("(define x 1)\n(define y 2)\n(define z 3)");
+
+        // Commenting out the rest of a line takes the form after it along too
+        edit(&mut document, "\n(define y", " ; (define y");
+        compare(&document);
+        assert_eq!(document.forms().unwrap().len(), 2);
+
+        // An open list takes in everything after it until it's closed
+        let start = document.text().find("(define z").unwrap();
+        document.edit(start..start, "(begin ");
+        assert!(document.analysis().is_err());
+        let end = document.text().len();
+        document.edit(end..end, ")");
+        compare(&document);
+        assert_eq!(document.forms().unwrap().len(), 2);
+
+        // Unmatched quotes make a string that runs into what's after it
+        edit(&mut document, "(define x 1)", "(define x \"1)");
+        assert!(document.analysis().is_err());
+        edit(&mut document, "\"1)", "1)");
+        compare(&document);
+    }
+
+    #[test]
+    fn forms_after_the_edit_are_moved() {
+        let mut document = Document::new("(define (f) 1)\n(define (g) (f))");
+        edit(&mut document, "(define (f) 1)", "(define (f)\n  (+ 1 2))");
+
+        let analysis = document.analysis().unwrap();
+        let text = document.text();
+        let definition = analysis.definition(text.rfind('f').unwrap()).unwrap();
+        assert_eq!(definition.start(), text.find("(f)").unwrap() + 1);
+        assert_eq!(&text[definition.range()], "f");
+    }
+}
@@ -0,0 +1,11 @@
+//! A language server for editors, speaking the Language Server Protocol over stdio. It offers
+//! go to definition, find references, document symbols, and warnings about unused variables,
+//! unused requires and missing requires along with quick fixes for them, all worked out from
+//! the source of each open document. Editors send the parts of a document that change, and
+//! only the top level forms those touch are parsed again.
+
+pub mod analysis;
+pub mod document;
+mod server;
+
+pub use server::{serve, serve_stdio};
@@ -0,0 +1,754 @@
+use super::analysis::{modules_near, Analysis, BindingKind};
+use super::document::Document;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::docs::DocEntry;
+use crate::parser::span::Span;
+use crate::steel_vm::engine::Engine;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Incremental text document sync, editors send the ranges that changed
+const SYNC_INCREMENTAL: u8 = 2;
+
+/// Serves editors talking to the process over stdin and stdout, until the editor says to exit
+pub fn serve_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    serve(stdin.lock(), stdout.lock())
+}
+
+/// Serves the editor sending messages over `input`, writing responses to `output`, until the
+/// editor says to exit or `input` runs out
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let engine = Engine::new();
+    let mut server = Server {
+        documents: HashMap::new(),
+        builtin_modules: engine.module_exports(),
+        globals: engine.globals().into_iter().collect(),
+        docs: engine
+            .docs()
+            .into_iter()
+            .map(|x| (x.name.clone(), x))
+            .collect(),
+    };
+
+    while let Some(message) = read_message(&mut input)? {
+        let message = match serde_json::from_slice::<Value>(&message) {
+            Ok(message) => message,
+            Err(e) => {
+                let response = error(Value::Null, PARSE_ERROR, e.to_string());
+                write_message(&mut output, &response)?;
+                continue;
+            }
+        };
+
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+
+        let params = &message["params"];
+        let outgoing = match message.get("id") {
+            Some(id) => vec![server.request(id.clone(), method, params)],
+            None => server.notification(method, params),
+        };
+        for message in outgoing {
+            write_message(&mut output, &message)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Messages are JSON, preceded by headers saying how long it is
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message is missing its length")
+    })?;
+    let mut message = vec![0; length];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let message = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        message.len(),
+        message
+    )?;
+    output.flush()
+}
+
+fn error(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message }
+    })
+}
+
+struct Server {
+    // Each open document, by URI
+    documents: HashMap<String, Document>,
+    // What each builtin module provides, for suggesting requires
+    builtin_modules: Vec<(String, Vec<String>)>,
+    // The names every program starts out with, which never need a require
+    globals: HashSet<String>,
+    // The documentation of the globals that have it, for completion
+    docs: HashMap<String, DocEntry>,
+}
+
+impl Server {
+    fn request(&mut self, id: Value, method: &str, params: &Value) -> Value {
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_INCREMENTAL,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                    "codeActionProvider": true,
+                    "completionProvider": {}
+                },
+                "serverInfo": { "name": "steel" }
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/documentSymbol" => self.symbols(params),
+            "textDocument/codeAction" => self.code_actions(params),
+            "textDocument/completion" => self.completions(params),
+            _ => return error(id, METHOD_NOT_FOUND, format!("{} isn't supported", method)),
+        };
+
+        json!({ "jsonrpc": "2.0", "id": id, "result": result })
+    }
+
+    // Returns the notifications to send back
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), Document::new(text));
+            }
+            "textDocument/didChange" => {
+                let document = match self.documents.get_mut(&uri) {
+                    Some(document) => document,
+                    None => return Vec::new(),
+                };
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    match change.get("range") {
+                        Some(range) => {
+                            let offset = |position: &Value| {
+                                to_offset(
+                                    document.text(),
+                                    position["line"].as_u64().unwrap_or_default() as usize,
+                                    position["character"].as_u64().unwrap_or_default() as usize,
+                                )
+                            };
+                            let start = offset(&range["start"]);
+                            let end = offset(&range["end"]).max(start);
+                            document.edit(start..end, text);
+                        }
+                        None => document.set_text(text),
+                    }
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+
+        let document = &self.documents[&uri];
+        let text = document.text();
+        let diagnostics = match document.analysis() {
+            Ok(analysis) => self.problems(&uri, text, &analysis),
+            Err(e) => vec![Diagnostic::from_error(&e)],
+        };
+        let diagnostics = diagnostics
+            .iter()
+            .map(|x| to_lsp_diagnostic(text, x))
+            .collect();
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+
+    // What's wrong with the document that there are quick fixes for
+    fn problems(&self, uri: &str, text: &str, analysis: &Analysis) -> Vec<Diagnostic> {
+        let path = document_path(uri);
+        let mut modules = self.builtin_modules.clone();
+        if let Some(directory) = path.and_then(Path::parent) {
+            modules.extend(modules_near(directory));
+        }
+        for (_, names) in &mut modules {
+            names.retain(|x| !self.globals.contains(x));
+        }
+
+        let mut problems = analysis.code_actions(text, path);
+        problems.extend(analysis.missing_requires(text, &modules));
+        problems
+    }
+
+    // The document, its analysis, and the offset of the position the request is about
+    fn at_position<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a str, Analysis, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let text = document.text();
+        let analysis = document.analysis().ok()?;
+        let offset = to_offset(
+            text,
+            params["position"]["line"].as_u64()? as usize,
+            params["position"]["character"].as_u64()? as usize,
+        );
+        Some((uri, text, analysis, offset))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        match self.at_position(params) {
+            Some((uri, text, analysis, offset)) => analysis
+                .definition(offset)
+                .map(|span| location(uri, text, span))
+                .unwrap_or(Value::Null),
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let include_definition = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+
+        match self.at_position(params) {
+            Some((uri, text, analysis, offset)) => analysis
+                .references(offset, include_definition)
+                .into_iter()
+                .map(|span| location(uri, text, span))
+                .collect(),
+            None => Value::Null,
+        }
+    }
+
+    fn symbols(&self, params: &Value) -> Value {
+        let document = match params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+        {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        let text = document.text();
+        let analysis = match document.analysis() {
+            Ok(analysis) => analysis,
+            Err(_) => return Value::Null,
+        };
+
+        analysis
+            .top_level_defines()
+            // The functions a struct defines all point at the struct's name
+            .filter(|x| x.kind != BindingKind::Struct || text[x.span.range()] == x.name)
+            .map(|x| {
+                json!({
+                    "name": x.name,
+                    "kind": symbol_kind(x.kind),
+                    "range": range(text, x.span),
+                    "selectionRange": range(text, x.span)
+                })
+            })
+            .collect()
+    }
+
+    // Everything defined at the top level of the document along with the globals, leaving it to
+    // the editor to narrow them down to what's been typed
+    fn completions(&self, params: &Value) -> Value {
+        let mut items = Vec::new();
+        let mut seen = HashSet::new();
+
+        let analysis = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+            .and_then(|document| document.analysis().ok());
+        for binding in analysis.iter().flat_map(|x| x.top_level_defines()) {
+            if seen.insert(binding.name.clone()) {
+                items.push(json!({
+                    "label": binding.name,
+                    "kind": completion_kind(binding.kind)
+                }));
+            }
+        }
+
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .filter(|x| !x.starts_with('#') && !x.starts_with('%'))
+            .collect();
+        globals.sort();
+        for name in globals {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            items.push(match self.docs.get(name) {
+                Some(entry) => json!({
+                    "label": name,
+                    "kind": completion_kind(BindingKind::Function),
+                    "detail": entry.module,
+                    "documentation": { "kind": "markdown", "value": entry.doc }
+                }),
+                None => json!({ "label": name }),
+            });
+        }
+
+        Value::Array(items)
+    }
+
+    // Quick fixes for the problems in the range asked about
+    fn code_actions(&self, params: &Value) -> Value {
+        let (uri, document) = match params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| Some((uri, self.documents.get(uri)?)))
+        {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        let text = document.text();
+        let analysis = match document.analysis() {
+            Ok(analysis) => analysis,
+            Err(_) => return Value::Null,
+        };
+
+        let offset = |position: &Value| {
+            to_offset(
+                text,
+                position["line"].as_u64().unwrap_or_default() as usize,
+                position["character"].as_u64().unwrap_or_default() as usize,
+            )
+        };
+        let start = offset(&params["range"]["start"]);
+        let end = offset(&params["range"]["end"]);
+
+        let mut actions = Vec::new();
+        for diagnostic in self.problems(uri, text, &analysis) {
+            match diagnostic.span {
+                Some(span) if span.start() <= end && start <= span.end() => {}
+                _ => continue,
+            }
+
+            for fix in &diagnostic.fixes {
+                let mut changes = serde_json::Map::new();
+                changes.insert(
+                    uri.to_string(),
+                    json!([{ "range": range(text, fix.span), "newText": fix.replacement }]),
+                );
+                actions.push(json!({
+                    "title": fix.message,
+                    "kind": "quickfix",
+                    "diagnostics": [to_lsp_diagnostic(text, &diagnostic)],
+                    "edit": { "changes": changes }
+                }));
+            }
+        }
+        Value::Array(actions)
+    }
+}
+
+// Where the document is on disk, for documents that are files
+fn document_path(uri: &str) -> Option<&Path> {
+    uri.strip_prefix("file://").map(Path::new)
+}
+
+fn symbol_kind(kind: BindingKind) -> u8 {
+    match kind {
+        BindingKind::Function | BindingKind::Macro => 12,
+        BindingKind::Variable | BindingKind::Parameter => 13,
+        BindingKind::Struct => 23,
+    }
+}
+
+fn completion_kind(kind: BindingKind) -> u8 {
+    match kind {
+        BindingKind::Function => 3,
+        BindingKind::Variable | BindingKind::Parameter => 6,
+        BindingKind::Macro => 15,
+        BindingKind::Struct => 22,
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics }
+    })
+}
+
+fn to_lsp_diagnostic(text: &str, diagnostic: &Diagnostic) -> Value {
+    let mut value = json!({
+        "range": range(text, diagnostic.span.unwrap_or_else(|| Span::new(0, 0))),
+        "severity": match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "source": "steel",
+        "message": diagnostic.message,
+    });
+    if let Some(code) = &diagnostic.code {
+        value["code"] = json!(code);
+    }
+    value
+}
+
+fn location(uri: &str, text: &str, span: Span) -> Value {
+    json!({ "uri": uri, "range": range(text, span) })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": to_position(text, span.start()), "end": to_position(text, span.end()) })
+}
+
+// Positions count lines from 0, and characters in UTF-16 code units from the start of the line
+fn to_position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count()
+    })
+}
+
+fn to_offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let message = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+    }
+
+    fn responses(output: &[u8]) -> Vec<Value> {
+        let mut input = output;
+        std::iter::from_fn(|| read_message(&mut input).unwrap())
+            .map(|x| serde_json::from_slice(&x).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let text = "(define λ 1)\n(display \"😀\" λ)";
+        let offset = text.rfind('λ').unwrap();
+        let position = to_position(text, offset);
+        assert_eq!(position, json!({ "line": 1, "character": 14 }));
+        assert_eq!(to_offset(text, 1, 14), offset);
+        assert_eq!(to_offset(text, 0, 100), text.find('\n').unwrap());
+    }
+
+    #[test]
+    fn editors_are_offered_quick_fixes() {
+        let uri = "file:///helper.scm";
+        let text = "(define (f)\n  (define helper 10)\n  1)\n";
+        let input = [
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "scheme", "version": 1, "text": text } }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "textDocument/codeAction",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "range": { "start": { "line": 1, "character": 10 }, "end": { "line": 1, "character": 10 } },
+                    "context": { "diagnostics": [] }
+                }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/codeAction",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "range": { "start": { "line": 2, "character": 0 }, "end": { "line": 2, "character": 1 } },
+                    "context": { "diagnostics": [] }
+                }
+            }),
+        ]
+        .iter()
+        .map(|x| frame(x.clone()))
+        .collect::<String>();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses = responses(&output);
+
+        let actions = responses[1]["result"].as_array().unwrap();
+        let titles = actions.iter().map(|x| &x["title"]).collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec![
+                "start the name with `_` if it's meant to be unused",
+                "remove the definition of `helper`"
+            ]
+        );
+        assert_eq!(actions[1]["kind"], "quickfix");
+        assert_eq!(
+            actions[1]["edit"]["changes"][uri],
+            json!([{
+                "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } },
+                "newText": ""
+            }])
+        );
+
+        // Nothing to fix outside of the problem
+        assert_eq!(responses[2]["result"], json!([]));
+    }
+
+    #[test]
+    fn builtins_are_not_suggested_requires() {
+        let input = frame(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///id.scm", "languageId": "scheme", "version": 1, "text": "(display (uuid/v4))" } }
+        }));
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses = responses(&output);
+        assert_eq!(responses[0]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn completions_include_documented_builtins() {
+        let uri = "file:///id.scm";
+        let input = [
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "scheme", "version": 1, "text": "(define (new-id) (uuid/v4))" } }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "textDocument/completion",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 0 } }
+            }),
+        ]
+        .iter()
+        .map(|x| frame(x.clone()))
+        .collect::<String>();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses = responses(&output);
+
+        let items = responses[1]["result"].as_array().unwrap();
+        let item = |label: &str| items.iter().find(|x| x["label"] == label).cloned();
+        assert_eq!(
+            item("new-id"),
+            Some(json!({ "label": "new-id", "kind": 3 }))
+        );
+        let v4 = item("uuid/v4").unwrap();
+        assert_eq!(v4["detail"], "steel/uuid");
+        assert!(v4["documentation"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("`(uuid/v4)`"));
+        assert_eq!(item("map"), Some(json!({ "label": "map" })));
+        assert!(items
+            .iter()
+            .all(|x| !x["label"].as_str().unwrap().starts_with('%')));
+    }
+
+    #[test]
+    fn editors_can_find_definitions_and_references() {
+        let uri = "file:///square.scm";
+        let text = "(define (square x) (* x x))\n(define (f unused) (square 3))";
+        let input = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "scheme", "version": 1, "text": text } }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/definition",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 1, "character": 21 } }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "textDocument/references",
+                "params": {
+                    "textDocument": { "uri": uri },
+                    "position": { "line": 0, "character": 16 },
+                    "context": { "includeDeclaration": false }
+                }
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 4,
+                "method": "textDocument/documentSymbol",
+                "params": { "textDocument": { "uri": uri } }
+            }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/hover", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]
+        .iter()
+        .map(|x| frame(x.clone()))
+        .collect::<String>();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses = responses(&output);
+        assert_eq!(responses.len(), 7);
+
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["definitionProvider"],
+            true
+        );
+
+        let diagnostics = &responses[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["message"], "`unused` is never used");
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({ "line": 1, "character": 11 })
+        );
+
+        assert_eq!(
+            responses[2]["result"]["range"],
+            json!({ "start": { "line": 0, "character": 9 }, "end": { "line": 0, "character": 15 } })
+        );
+
+        let references = responses[3]["result"].as_array().unwrap();
+        assert_eq!(references.len(), 2);
+        assert_eq!(references[0]["range"]["start"]["character"], 22);
+
+        let symbols = responses[4]["result"].as_array().unwrap();
+        let names = symbols.iter().map(|x| &x["name"]).collect::<Vec<_>>();
+        assert_eq!(names, vec!["square", "f"]);
+
+        assert_eq!(responses[5]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[6]["result"], Value::Null);
+    }
+
+    #[test]
+    fn editors_send_the_ranges_that_change() {
+        let uri = "file:///square.scm";
+        let change = |version: u64, range: Value, text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": uri, "version": version },
+                    "contentChanges": [{ "range": range, "text": text }]
+                }
+            })
+        };
+        let input = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "scheme",
+                        "version": 1,
+                        "text": "(define (square x) (* x x))\n(square 3)"
+                    }
+                }
+            }),
+            change(
+                2,
+                json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } }),
+                "(define (f unused) 1)\n",
+            ),
+            change(
+                3,
+                json!({ "start": { "line": 0, "character": 11 }, "end": { "line": 0, "character": 11 } }),
+                "_",
+            ),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/definition",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 2, "character": 1 } }
+            }),
+        ]
+        .iter()
+        .map(|x| frame(x.clone()))
+        .collect::<String>();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let responses = responses(&output);
+
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["textDocumentSync"],
+            SYNC_INCREMENTAL
+        );
+        assert_eq!(responses[1]["params"]["diagnostics"], json!([]));
+        let diagnostics = &responses[2]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["message"], "`unused` is never used");
+        assert_eq!(responses[3]["params"]["diagnostics"], json!([]));
+
+        // The definition moved down a line along with everything after the first edit
+        assert_eq!(
+            responses[4]["result"]["range"],
+            json!({ "start": { "line": 1, "character": 9 }, "end": { "line": 1, "character": 15 } })
+        );
+    }
+}
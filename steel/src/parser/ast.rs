@@ -7,6 +7,7 @@ use std::convert::TryFrom;
 
 use itertools::Itertools;
 use pretty::RcDoc;
+use std::collections::HashSet;
 use std::fmt;
 use std::ops::Deref;
 
@@ -104,6 +105,9 @@ impl TryFrom<&SteelVal> for ExprKind {
             SymbolV(x) => Ok(ExprKind::Atom(Atom::new(SyntaxObject::default(
                 Identifier(x.unwrap()),
             )))),
+            KeywordV(x) => Ok(ExprKind::Atom(Atom::new(SyntaxObject::default(Keyword(
+                x.unwrap(),
+            ))))),
             Custom(_) => Err("Can't convert from Custom Type to expression!"),
             // Pair(_, _) => Err("Can't convert from pair"), // TODO
             Pair(_) => {
@@ -133,7 +137,9 @@ impl TryFrom<&SteelVal> for ExprKind {
             Contract(_) => Err("Can't convert from contract to expression!"),
             ContractedFunction(_) => Err("Can't convert from contracted function to expression!"),
             BoxedFunction(_) => Err("Can't convert from boxed function to expression!"),
+            BuiltIn(_) => Err("Can't convert from builtin function to expression!"),
             ContinuationFunction(_) => Err("Can't convert from continuation to expression!"),
+            Values(_) => Err("Can't convert from multiple values to expression!"),
         }
     }
 }
@@ -414,6 +420,12 @@ impl Define {
             location,
         }
     }
+
+    /// Whether this was declared with `define/pure`, which allows calls to it with
+    /// constant arguments to be evaluated at compile time
+    pub fn is_pure(&self) -> bool {
+        self.location.ty == TokenType::DefinePure
+    }
 }
 
 impl From<Define> for ExprKind {
@@ -466,6 +478,31 @@ impl LambdaFunction {
             location,
         }
     }
+
+    /// The variables the function binds in its body, its arguments along with the names its
+    /// body defines
+    pub fn locals(&self) -> HashSet<String> {
+        fn defines(expr: &ExprKind, locals: &mut HashSet<String>) {
+            match expr {
+                ExprKind::Define(d) => {
+                    if let Ok(name) = d.name.atom_identifier_or_else(|| ()) {
+                        locals.insert(name.to_string());
+                    }
+                }
+                ExprKind::Begin(b) => b.exprs.iter().for_each(|x| defines(x, locals)),
+                _ => {}
+            }
+        }
+
+        let mut locals = self
+            .args
+            .iter()
+            .filter_map(|x| x.atom_identifier_or_else(|| ()).ok())
+            .map(|x| x.to_string())
+            .collect();
+        defines(&self.body, &mut locals);
+        locals
+    }
 }
 
 impl From<LambdaFunction> for ExprKind {
@@ -662,14 +699,22 @@ impl IntoIterator for List {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Apply {
     pub func: ExprKind,
+    // The arguments passed before the ones in the list
+    pub args: Vec<ExprKind>,
     pub list: ExprKind,
     pub location: SyntaxObject,
 }
 
 impl Apply {
-    pub fn new(func: ExprKind, list: ExprKind, location: SyntaxObject) -> Self {
+    pub fn new(
+        func: ExprKind,
+        args: Vec<ExprKind>,
+        list: ExprKind,
+        location: SyntaxObject,
+    ) -> Self {
         Apply {
             func,
+            args,
             list,
             location,
         }
@@ -678,7 +723,11 @@ impl Apply {
 
 impl fmt::Display for Apply {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "(apply {} {})", self.func, self.list)
+        write!(f, "(apply {}", self.func)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        write!(f, " {})", self.list)
     }
 }
 
@@ -687,6 +736,9 @@ impl ToDoc for Apply {
         RcDoc::text("(apply")
             .append(RcDoc::line())
             .append(self.func.to_doc())
+            .append(RcDoc::concat(
+                self.args.iter().map(|x| RcDoc::line().append(x.to_doc())),
+            ))
             .append(RcDoc::line())
             .append(self.list.to_doc())
             .append(RcDoc::text(")"))
@@ -1320,14 +1372,31 @@ where
 {
     value_iter.next();
 
-    let let_pairs = if let ExprKind::List(l) = value_iter.next().ok_or_else(|| {
+    let mut second = value_iter.next().ok_or_else(|| {
         ParseError::SyntaxError(
             "let expected a list of variable bindings pairs in the second position, found none"
                 .to_string(),
             syn.span,
             None,
         )
-    })? {
+    })?;
+
+    // A named let, `(let loop ([i 0]) ...)`
+    let name = match second {
+        ExprKind::Atom(name) if matches!(name.syn.ty, TokenType::Identifier(_)) => {
+            second = value_iter.next().ok_or_else(|| {
+                ParseError::SyntaxError(
+                    "named let expected a list of variable bindings pairs, found none".to_string(),
+                    syn.span,
+                    None,
+                )
+            })?;
+            Some(name)
+        }
+        _ => None,
+    };
+
+    let let_pairs = if let ExprKind::List(l) = second {
         l.args
     } else {
         return Err(ParseError::SyntaxError(
@@ -1388,6 +1457,10 @@ where
         }
     }
 
+    if let Some(name) = name {
+        return Ok(named_let(name, arguments, application_args, body, syn));
+    }
+
     let mut function: Vec<ExprKind> = vec![LambdaFunction::new(arguments, body, syn).into()];
 
     function.append(&mut application_args);
@@ -1395,6 +1468,47 @@ where
     Ok(ExprKind::List(List::new(function)))
 }
 
+/// Lowers a named let, or a loop like `do` that is built out of one, into a local function
+/// definition
+///
+/// ```scheme
+/// (let loop ([i 0]) body ...)
+/// ;; =>
+/// (((lambda () (define loop (lambda (i) body ...)) loop)) 0)
+/// ```
+///
+/// Calls to `loop` in tail position in its body then jump back to the start of it, the same
+/// as any other function defined with `define`. The initial values are evaluated outside of
+/// the loop, where `loop` isn't bound.
+pub(crate) fn named_let(
+    name: Atom,
+    parameters: Vec<ExprKind>,
+    initial_values: Vec<ExprKind>,
+    body: ExprKind,
+    syn: SyntaxObject,
+) -> ExprKind {
+    let define = Define::new(
+        ExprKind::Atom(name.clone()),
+        LambdaFunction::new(parameters, body, syn.clone()).into(),
+        SyntaxObject::new(TokenType::Define, syn.span),
+    );
+
+    let scope = LambdaFunction::new(
+        Vec::new(),
+        ExprKind::Begin(Begin::new(
+            vec![ExprKind::Define(Box::new(define)), ExprKind::Atom(name)],
+            SyntaxObject::new(TokenType::Begin, syn.span),
+        )),
+        syn,
+    );
+
+    ExprKind::List(List::new(
+        std::iter::once(ExprKind::List(List::new(vec![scope.into()])))
+            .chain(initial_values)
+            .collect(),
+    ))
+}
+
 #[inline]
 fn parse_transduce<I>(
     mut value_iter: I,
@@ -1516,7 +1630,9 @@ impl TryFrom<Vec<ExprKind>> for ExprKind {
                     // let value = value.into_iter();
                     match &a.syn.ty {
                         TokenType::If => parse_if(value.into_iter(), a.syn.clone()),
-                        TokenType::Define => parse_define(value.into_iter(), a.syn.clone()),
+                        TokenType::Define | TokenType::DefinePure => {
+                            parse_define(value.into_iter(), a.syn.clone())
+                        }
                         TokenType::Let => parse_let(value.into_iter(), a.syn.clone()),
                         TokenType::Transduce => parse_transduce(value.into_iter(), a.syn.clone()),
                         TokenType::Quote => parse_single_argument(
@@ -1599,10 +1715,10 @@ impl TryFrom<Vec<ExprKind>> for ExprKind {
                         }
                         TokenType::Apply => {
                             let syn = a.syn.clone();
-                            if value.len() != 3 {
+                            if value.len() < 3 {
                                 return Err(ParseError::ArityMismatch(
                                     format!(
-                                        "apply expects a symbol (for a function) and a list of fields, found {} arguments instead",value.len()
+                                        "apply expects a function, any leading arguments and a list of arguments, found {} arguments instead",value.len()
                                     ), syn.span, None
                                 ));
                             }
@@ -1610,9 +1726,12 @@ impl TryFrom<Vec<ExprKind>> for ExprKind {
                             let mut value_iter = value.into_iter();
                             value_iter.next();
                             let function = value_iter.next().unwrap();
-                            let list = value_iter.next().unwrap();
+                            let mut args: Vec<ExprKind> = value_iter.collect();
+                            let list = args.pop().unwrap();
 
-                            Ok(ExprKind::Apply(Box::new(Apply::new(function, list, syn))))
+                            Ok(ExprKind::Apply(Box::new(Apply::new(
+                                function, args, list, syn,
+                            ))))
                         }
                         TokenType::Struct => {
                             let syn = a.syn.clone();
@@ -1673,8 +1792,13 @@ impl TryFrom<Vec<ExprKind>> for ExprKind {
                             if let Some(ExprKind::List(l)) = arguments {
                                 let args = l.args;
 
+                                // Optional and keyword arguments look like (name default)
+                                let mut after_keyword = false;
                                 for arg in &args {
-                                    if let ExprKind::Atom(_) = arg {
+                                    if let ExprKind::Atom(a) = arg {
+                                        after_keyword |= matches!(a.syn.ty, TokenType::Keyword(_));
+                                        continue;
+                                    } else if after_keyword && matches!(arg, ExprKind::List(_)) {
                                         continue;
                                     } else {
                                         return Err(ParseError::SyntaxError(
@@ -1728,17 +1852,26 @@ impl TryFrom<Vec<ExprKind>> for ExprKind {
 
                             // println!("{:?}", syntax);
 
-                            let syntax_rules = if let Some(ExprKind::SyntaxRules(s)) = syntax {
-                                s
-                            } else {
-                                return Err(ParseError::SyntaxError(
-                                    "define-syntax expected a syntax-rules object".to_string(),
+                            match syntax {
+                                Some(ExprKind::SyntaxRules(s)) => {
+                                    Ok(ExprKind::Macro(Macro::new(name, s, syn)))
+                                }
+                                // Anything else is the transformer of a procedural macro, which
+                                // the compiler defines in the kernel
+                                Some(transformer) if value_iter.next().is_none() => {
+                                    Ok(ExprKind::List(List::new(vec![
+                                        ExprKind::Atom(Atom::new(syn)),
+                                        name,
+                                        transformer,
+                                    ])))
+                                }
+                                _ => Err(ParseError::SyntaxError(
+                                    "define-syntax expected a syntax-rules object or a transformer"
+                                        .to_string(),
                                     syn.span,
                                     None,
-                                ));
-                            };
-
-                            Ok(ExprKind::Macro(Macro::new(name, syntax_rules, syn)))
+                                )),
+                            }
                         }
                         TokenType::SyntaxRules => {
                             let syn = a.syn.clone();
@@ -0,0 +1,384 @@
+//! Construct programs directly as syntax trees, for embedders generating code from their own
+//! languages rather than assembling Scheme source text.
+//!
+//! ```
+//! # extern crate steel;
+//! # use steel::steel_vm::engine::Engine;
+//! # use steel::SteelVal;
+//! use steel::builder::*;
+//!
+//! let program = vec![
+//!     define("square", lambda(&["x"], call("*", vec![symbol("x"), symbol("x")]))),
+//!     call("square", vec![int(12)]),
+//! ];
+//!
+//! let mut vm = Engine::new();
+//! let result = vm.run_exprs(program).unwrap();
+//! assert_eq!(result.last(), Some(&SteelVal::IntV(144)));
+//! ```
+//!
+//! Nodes built here have no location in any source. [`with_span`] fills in a span for a node
+//! and everything inside of it that doesn't have one yet, so errors can be reported against
+//! wherever the node came from in the embedder's language.
+
+use crate::parser::ast::{
+    Apply, Atom, Begin, CallCC, Define, Eval, Execute, If, LambdaFunction, List, Macro, Panic,
+    Quote, Read, Require, Return, Set, Struct, SyntaxRules, Transduce,
+};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::VisitorMutRef;
+use crate::rvals::Result;
+
+use logos::Logos;
+use std::convert::TryFrom;
+
+pub use crate::parser::ast::ExprKind;
+pub use crate::parser::span::Span;
+
+fn atom(ty: TokenType) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::default(ty)))
+}
+
+// The parser turns special form names like `if` or `lambda` into their own tokens,
+// so symbols have to go through the lexer to match what parsing the name would produce
+fn symbol_token(name: &str) -> TokenType {
+    let mut lexer = TokenType::lexer(name);
+    let token = lexer.next();
+    let whole_name = lexer.span().end == name.len();
+
+    match (token, whole_name, lexer.next()) {
+        (Some(token), true, None) if is_keyword(&token) => token,
+        _ => TokenType::Identifier(name.to_string()),
+    }
+}
+
+fn is_keyword(token: &TokenType) -> bool {
+    use TokenType::*;
+    !matches!(
+        token,
+        OpenParen
+            | CloseParen
+            | QuoteTick
+            | QuasiQuote
+            | Unquote
+            | UnquoteSplice
+            | Hash
+            | CharacterLiteral(_)
+            | Comment
+            | BooleanLiteral(_)
+            | Keyword(_)
+            | Identifier(_)
+            | NumberLiteral(_)
+            | IntegerLiteral(_)
+            | StringLiteral(_)
+            | Error
+    )
+}
+
+/// A reference to a variable, or the name of a special form
+pub fn symbol(name: &str) -> ExprKind {
+    atom(symbol_token(name))
+}
+
+pub fn int(value: isize) -> ExprKind {
+    atom(TokenType::IntegerLiteral(value))
+}
+
+pub fn number(value: f64) -> ExprKind {
+    atom(TokenType::NumberLiteral(value))
+}
+
+pub fn string(value: &str) -> ExprKind {
+    atom(TokenType::StringLiteral(value.to_string()))
+}
+
+pub fn boolean(value: bool) -> ExprKind {
+    atom(TokenType::BooleanLiteral(value))
+}
+
+pub fn character(value: char) -> ExprKind {
+    atom(TokenType::CharacterLiteral(value))
+}
+
+/// A list of expressions, checked the same way the parser would check it - so a list starting
+/// with `define` becomes a definition, and is an error if it isn't well formed
+pub fn list(exprs: Vec<ExprKind>) -> Result<ExprKind> {
+    Ok(ExprKind::try_from(exprs)?)
+}
+
+/// `(func args ...)`
+pub fn call(func: &str, args: Vec<ExprKind>) -> ExprKind {
+    apply_function(symbol(func), args)
+}
+
+/// `(func args ...)`, for when the function is itself an expression
+pub fn apply_function(func: ExprKind, mut args: Vec<ExprKind>) -> ExprKind {
+    args.insert(0, func);
+    ExprKind::List(List::new(args))
+}
+
+/// `(define name body)`
+pub fn define(name: &str, body: ExprKind) -> ExprKind {
+    Define::new(symbol(name), body, SyntaxObject::default(TokenType::Define)).into()
+}
+
+/// `(lambda (args ...) body)`
+pub fn lambda(args: &[&str], body: ExprKind) -> ExprKind {
+    LambdaFunction::new(
+        args.iter().map(|x| symbol(x)).collect(),
+        body,
+        SyntaxObject::default(TokenType::Lambda),
+    )
+    .into()
+}
+
+/// `(if test then else)`
+pub fn if_else(test: ExprKind, then: ExprKind, otherwise: ExprKind) -> ExprKind {
+    If::new(test, then, otherwise, SyntaxObject::default(TokenType::If)).into()
+}
+
+/// `(begin exprs ...)`
+pub fn begin(exprs: Vec<ExprKind>) -> ExprKind {
+    ExprKind::Begin(Begin::new(exprs, SyntaxObject::default(TokenType::Begin)))
+}
+
+/// `(set! name value)`
+pub fn set(name: &str, value: ExprKind) -> ExprKind {
+    Set::new(symbol(name), value, SyntaxObject::default(TokenType::Set)).into()
+}
+
+/// `(quote expr)`
+pub fn quote(expr: ExprKind) -> ExprKind {
+    Quote::new(expr, SyntaxObject::default(TokenType::Quote)).into()
+}
+
+/// Sets the span of `expr` and everything inside of it that doesn't already have one
+pub fn with_span(mut expr: ExprKind, span: Span) -> ExprKind {
+    SpanInjector { span }.visit(&mut expr);
+    expr
+}
+
+struct SpanInjector {
+    span: Span,
+}
+
+impl SpanInjector {
+    fn inject(&self, syn: &mut SyntaxObject) {
+        if syn.span == Span::new(0, 0) {
+            syn.set_span(self.span);
+        }
+    }
+}
+
+impl VisitorMutRef for SpanInjector {
+    type Output = ();
+
+    fn visit_if(&mut self, f: &mut If) {
+        self.inject(&mut f.location);
+        self.visit(&mut f.test_expr);
+        self.visit(&mut f.then_expr);
+        self.visit(&mut f.else_expr);
+    }
+
+    fn visit_define(&mut self, define: &mut Define) {
+        self.inject(&mut define.location);
+        self.visit(&mut define.name);
+        self.visit(&mut define.body);
+    }
+
+    fn visit_lambda_function(&mut self, lambda_function: &mut LambdaFunction) {
+        self.inject(&mut lambda_function.location);
+        for arg in &mut lambda_function.args {
+            self.visit(arg);
+        }
+        self.visit(&mut lambda_function.body);
+    }
+
+    fn visit_begin(&mut self, begin: &mut Begin) {
+        self.inject(&mut begin.location);
+        for expr in &mut begin.exprs {
+            self.visit(expr);
+        }
+    }
+
+    fn visit_return(&mut self, r: &mut Return) {
+        self.inject(&mut r.location);
+        self.visit(&mut r.expr);
+    }
+
+    fn visit_apply(&mut self, apply: &mut Apply) {
+        self.inject(&mut apply.location);
+        self.visit(&mut apply.func);
+        for arg in &mut apply.args {
+            self.visit(arg);
+        }
+        self.visit(&mut apply.list);
+    }
+
+    fn visit_panic(&mut self, p: &mut Panic) {
+        self.inject(&mut p.location);
+        self.visit(&mut p.message);
+    }
+
+    fn visit_transduce(&mut self, transduce: &mut Transduce) {
+        self.inject(&mut transduce.location);
+        self.visit(&mut transduce.transducer);
+        self.visit(&mut transduce.func);
+        self.visit(&mut transduce.initial_value);
+        self.visit(&mut transduce.iterable);
+    }
+
+    fn visit_read(&mut self, read: &mut Read) {
+        self.inject(&mut read.location);
+        self.visit(&mut read.expr);
+    }
+
+    fn visit_execute(&mut self, execute: &mut Execute) {
+        self.inject(&mut execute.location);
+        self.visit(&mut execute.transducer);
+        self.visit(&mut execute.collection);
+        if let Some(output_type) = &mut execute.output_type {
+            self.visit(output_type);
+        }
+    }
+
+    fn visit_quote(&mut self, quote: &mut Quote) {
+        self.inject(&mut quote.location);
+        self.visit(&mut quote.expr);
+    }
+
+    fn visit_struct(&mut self, s: &mut Struct) {
+        self.inject(&mut s.location);
+        self.visit(&mut s.name);
+        for field in &mut s.fields {
+            self.visit(field);
+        }
+    }
+
+    fn visit_macro(&mut self, m: &mut Macro) {
+        self.inject(&mut m.location);
+    }
+
+    fn visit_eval(&mut self, e: &mut Eval) {
+        self.inject(&mut e.location);
+        self.visit(&mut e.expr);
+    }
+
+    fn visit_atom(&mut self, a: &mut Atom) {
+        self.inject(&mut a.syn);
+    }
+
+    fn visit_list(&mut self, l: &mut List) {
+        for arg in &mut l.args {
+            self.visit(arg);
+        }
+    }
+
+    fn visit_syntax_rules(&mut self, l: &mut SyntaxRules) {
+        self.inject(&mut l.location);
+    }
+
+    fn visit_set(&mut self, s: &mut Set) {
+        self.inject(&mut s.location);
+        self.visit(&mut s.variable);
+        self.visit(&mut s.expr);
+    }
+
+    fn visit_require(&mut self, s: &mut Require) {
+        self.inject(&mut s.location);
+    }
+
+    fn visit_callcc(&mut self, cc: &mut CallCC) {
+        self.inject(&mut cc.location);
+        self.visit(&mut cc.expr);
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn run(exprs: Vec<ExprKind>) -> Result<Vec<SteelVal>> {
+        Engine::new().run_exprs(exprs)
+    }
+
+    #[test]
+    fn symbols_for_special_forms_match_the_parser() {
+        assert_eq!(symbol("if"), atom(TokenType::If));
+        assert_eq!(symbol("fn"), atom(TokenType::Lambda));
+        assert_eq!(
+            symbol("if-not"),
+            atom(TokenType::Identifier("if-not".to_string()))
+        );
+        assert_eq!(symbol("10"), atom(TokenType::Identifier("10".to_string())));
+    }
+
+    #[test]
+    fn built_program_runs() {
+        let program = vec![
+            define(
+                "fib",
+                lambda(
+                    &["n"],
+                    if_else(
+                        call("<", vec![symbol("n"), int(2)]),
+                        symbol("n"),
+                        call(
+                            "+",
+                            vec![
+                                call("fib", vec![call("-", vec![symbol("n"), int(1)])]),
+                                call("fib", vec![call("-", vec![symbol("n"), int(2)])]),
+                            ],
+                        ),
+                    ),
+                ),
+            ),
+            call("fib", vec![int(10)]),
+        ];
+
+        assert_eq!(run(program).unwrap().last(), Some(&SteelVal::IntV(55)));
+    }
+
+    #[test]
+    fn lists_are_checked_like_parsed_source() {
+        let define = list(vec![symbol("define"), symbol("x"), int(10)]).unwrap();
+        assert_eq!(
+            run(vec![define, symbol("x")]).unwrap().last(),
+            Some(&SteelVal::IntV(10))
+        );
+
+        assert!(list(vec![symbol("if"), boolean(true)]).is_err());
+    }
+
+    #[test]
+    fn injected_spans_show_up_in_errors() {
+        let span = Span::new(40, 52);
+        let program = vec![with_span(call("not-defined", vec![int(1)]), span)];
+
+        let err = run(program).unwrap_err();
+        assert_eq!(err.span(), Some(span));
+    }
+
+    #[test]
+    fn existing_spans_are_kept() {
+        let inner = with_span(symbol("x"), Span::new(5, 6));
+        let outer = with_span(call("f", vec![inner]), Span::new(0, 10));
+
+        let spans: Vec<_> = match outer {
+            ExprKind::List(l) => l
+                .args
+                .iter()
+                .map(|x| match x {
+                    ExprKind::Atom(a) => a.syn.span,
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(spans, vec![Span::new(0, 10), Span::new(5, 6)]);
+    }
+}
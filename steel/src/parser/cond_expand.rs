@@ -0,0 +1,415 @@
+use crate::parser::ast::{Atom, Begin, ExprKind, List};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::ConsumingVisitor;
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+
+use std::collections::HashSet;
+
+/// The features every engine declares: `steel`, along with the operating system,
+/// family and architecture of the host, i.e. `linux`, `unix`, `x86_64`,
+/// and any cargo features steel was compiled with
+pub(crate) fn default_features() -> HashSet<String> {
+    #[allow(unused_mut)]
+    let mut features = vec![
+        "steel",
+        std::env::consts::OS,
+        std::env::consts::FAMILY,
+        std::env::consts::ARCH,
+    ];
+
+    #[cfg(feature = "modules")]
+    features.push("modules");
+
+    #[cfg(feature = "threaded_dispatch")]
+    features.push("threaded_dispatch");
+
+    features.into_iter().map(|x| x.to_string()).collect()
+}
+
+/// Resolves `cond-expand` and `when-target` forms against the given feature set.
+///
+/// ```scheme
+/// (cond-expand
+///   [(and unix (not macos)) (define path-separator "/")]
+///   [windows (define path-separator "\\")]
+///   [else (define path-separator "/")])
+///
+/// (when-target windows
+///   (define line-ending "\r\n"))
+/// ```
+///
+/// A requirement is a feature identifier, or a combination of requirements with `and`, `or`
+/// and `not`. The body of the first clause with a satisfied requirement is spliced in place
+/// of the form at the top level, and wrapped in a `begin` elsewhere.
+pub fn resolve_conditionals(
+    exprs: Vec<ExprKind>,
+    features: &HashSet<String>,
+) -> Result<Vec<ExprKind>> {
+    let mut resolver = ConditionalResolver {
+        features,
+        locals: Vec::new(),
+    };
+    let mut output = Vec::with_capacity(exprs.len());
+
+    for expr in exprs {
+        match expr {
+            ExprKind::List(l) if resolver.is_conditional(&l) => {
+                let body = resolver.select(l)?;
+                output.append(&mut resolve_conditionals(body, features)?);
+            }
+            other => output.push(resolver.visit(other)?),
+        }
+    }
+
+    Ok(output)
+}
+
+struct ConditionalResolver<'a> {
+    features: &'a HashSet<String>,
+    // Variables bound by the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+impl<'a> ConditionalResolver<'a> {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    fn is_conditional(&self, l: &List) -> bool {
+        match l.first_ident() {
+            Some(name @ "cond-expand") | Some(name @ "when-target") => !self.is_local(name),
+            _ => false,
+        }
+    }
+
+    fn satisfies(&self, requirement: &ExprKind, span: Span) -> Result<bool> {
+        match requirement {
+            ExprKind::Atom(Atom {
+                syn:
+                    SyntaxObject {
+                        ty: TokenType::Identifier(feature),
+                        ..
+                    },
+            }) => Ok(self.features.contains(feature)),
+            ExprKind::List(l) => {
+                let args = &l.args[1..];
+                match l.first_ident() {
+                    Some("and") => {
+                        for arg in args {
+                            if !self.satisfies(arg, span)? {
+                                return Ok(false);
+                            }
+                        }
+                        Ok(true)
+                    }
+                    Some("or") => {
+                        for arg in args {
+                            if self.satisfies(arg, span)? {
+                                return Ok(true);
+                            }
+                        }
+                        Ok(false)
+                    }
+                    Some("not") if args.len() == 1 => Ok(!self.satisfies(&args[0], span)?),
+                    _ => {
+                        stop!(BadSyntax => "unexpected feature requirement: {}", requirement; span)
+                    }
+                }
+            }
+            _ => {
+                stop!(BadSyntax => "unexpected feature requirement: {}", requirement; span)
+            }
+        }
+    }
+
+    // Pick out the body of the first clause whose requirement is satisfied
+    fn select(&self, l: List) -> Result<Vec<ExprKind>> {
+        let mut args = l.args.into_iter();
+
+        let (name, span) = match args.next() {
+            Some(ExprKind::Atom(a)) => (a.syn.ty.to_string(), a.syn.span),
+            _ => unreachable!(),
+        };
+
+        if name == "when-target" {
+            let requirement = args.next().ok_or_else(
+                throw!(BadSyntax => "when-target expects a target requirement"; span),
+            )?;
+
+            return if self.satisfies(&requirement, span)? {
+                Ok(args.collect())
+            } else {
+                Ok(Vec::new())
+            };
+        }
+
+        for clause in args {
+            if let ExprKind::List(clause) = clause {
+                let mut clause = clause.args.into_iter();
+
+                let requirement = clause.next().ok_or_else(
+                    throw!(BadSyntax => "cond-expand clause expects a feature requirement"; span),
+                )?;
+
+                let satisfied = match &requirement {
+                    ExprKind::Atom(Atom {
+                        syn:
+                            SyntaxObject {
+                                ty: TokenType::Identifier(s),
+                                ..
+                            },
+                    }) if s == "else" => true,
+                    _ => self.satisfies(&requirement, span)?,
+                };
+
+                if satisfied {
+                    return Ok(clause.collect());
+                }
+            } else {
+                stop!(BadSyntax => "cond-expand expects clauses of the form (requirement body ...)"; span);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+impl<'a> ConsumingVisitor for ConditionalResolver<'a> {
+    type Output = Result<ExprKind>;
+
+    fn visit_if(&mut self, mut f: Box<super::ast::If>) -> Self::Output {
+        f.test_expr = self.visit(f.test_expr)?;
+        f.then_expr = self.visit(f.then_expr)?;
+        f.else_expr = self.visit(f.else_expr)?;
+        Ok(ExprKind::If(f))
+    }
+
+    fn visit_define(&mut self, mut define: Box<super::ast::Define>) -> Self::Output {
+        define.body = self.visit(define.body)?;
+        Ok(ExprKind::Define(define))
+    }
+
+    fn visit_lambda_function(
+        &mut self,
+        mut lambda_function: Box<super::ast::LambdaFunction>,
+    ) -> Self::Output {
+        self.locals.push(lambda_function.locals());
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
+        Ok(ExprKind::LambdaFunction(lambda_function))
+    }
+
+    fn visit_begin(&mut self, mut begin: Begin) -> Self::Output {
+        begin.exprs = begin
+            .exprs
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::Begin(begin))
+    }
+
+    fn visit_return(&mut self, mut r: Box<super::ast::Return>) -> Self::Output {
+        r.expr = self.visit(r.expr)?;
+        Ok(ExprKind::Return(r))
+    }
+
+    fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
+        apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        apply.list = self.visit(apply.list)?;
+        Ok(ExprKind::Apply(apply))
+    }
+
+    fn visit_panic(&mut self, mut p: Box<super::ast::Panic>) -> Self::Output {
+        p.message = self.visit(p.message)?;
+        Ok(ExprKind::Panic(p))
+    }
+
+    fn visit_transduce(&mut self, mut transduce: Box<super::ast::Transduce>) -> Self::Output {
+        transduce.transducer = self.visit(transduce.transducer)?;
+        transduce.func = self.visit(transduce.func)?;
+        transduce.initial_value = self.visit(transduce.initial_value)?;
+        transduce.iterable = self.visit(transduce.iterable)?;
+        Ok(ExprKind::Transduce(transduce))
+    }
+
+    fn visit_read(&mut self, mut read: Box<super::ast::Read>) -> Self::Output {
+        read.expr = self.visit(read.expr)?;
+        Ok(ExprKind::Read(read))
+    }
+
+    fn visit_execute(&mut self, mut execute: Box<super::ast::Execute>) -> Self::Output {
+        execute.transducer = self.visit(execute.transducer)?;
+        execute.collection = self.visit(execute.collection)?;
+        execute.output_type = execute.output_type.map(|x| self.visit(x)).transpose()?;
+        Ok(ExprKind::Execute(execute))
+    }
+
+    fn visit_quote(&mut self, quote: Box<super::ast::Quote>) -> Self::Output {
+        Ok(ExprKind::Quote(quote))
+    }
+
+    fn visit_struct(&mut self, s: Box<super::ast::Struct>) -> Self::Output {
+        Ok(ExprKind::Struct(s))
+    }
+
+    fn visit_macro(&mut self, m: super::ast::Macro) -> Self::Output {
+        Ok(ExprKind::Macro(m))
+    }
+
+    fn visit_eval(&mut self, mut e: Box<super::ast::Eval>) -> Self::Output {
+        e.expr = self.visit(e.expr)?;
+        Ok(ExprKind::Eval(e))
+    }
+
+    fn visit_atom(&mut self, a: Atom) -> Self::Output {
+        Ok(ExprKind::Atom(a))
+    }
+
+    fn visit_list(&mut self, mut l: List) -> Self::Output {
+        if self.is_conditional(&l) {
+            let span = match l.first() {
+                Some(ExprKind::Atom(a)) => a.syn.span,
+                _ => unreachable!(),
+            };
+
+            let mut body = self
+                .select(l)?
+                .into_iter()
+                .map(|e| self.visit(e))
+                .collect::<Result<Vec<_>>>()?;
+
+            // Nothing matched - this still needs to evaluate to something
+            if body.is_empty() {
+                return Ok(ExprKind::List(List::new(vec![ExprKind::Atom(Atom::new(
+                    SyntaxObject::new(TokenType::Identifier("void".to_string()), span),
+                ))])));
+            }
+
+            if body.len() == 1 {
+                return Ok(body.pop().unwrap());
+            }
+
+            return Ok(ExprKind::Begin(Begin::new(
+                body,
+                SyntaxObject::new(TokenType::Begin, span),
+            )));
+        }
+
+        l.args = l
+            .args
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ExprKind::List(l))
+    }
+
+    fn visit_syntax_rules(&mut self, l: super::ast::SyntaxRules) -> Self::Output {
+        Ok(ExprKind::SyntaxRules(l))
+    }
+
+    fn visit_set(&mut self, mut s: Box<super::ast::Set>) -> Self::Output {
+        s.expr = self.visit(s.expr)?;
+        Ok(ExprKind::Set(s))
+    }
+
+    fn visit_require(&mut self, s: super::ast::Require) -> Self::Output {
+        Ok(ExprKind::Require(s))
+    }
+
+    fn visit_callcc(&mut self, mut cc: Box<super::ast::CallCC>) -> Self::Output {
+        cc.expr = self.visit(cc.expr)?;
+        Ok(ExprKind::CallCC(cc))
+    }
+}
+
+#[cfg(test)]
+mod cond_expand_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn run(program: &str) -> SteelVal {
+        Engine::new().run(program).unwrap().pop().unwrap()
+    }
+
+    #[test]
+    fn selects_first_matching_clause() {
+        assert_eq!(
+            run("(cond-expand [(not steel) 1] [(or foo steel) 2] [else 3])"),
+            SteelVal::IntV(2)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_else() {
+        assert_eq!(
+            run("(cond-expand [(and steel not-a-feature) 1] [else 3])"),
+            SteelVal::IntV(3)
+        );
+    }
+
+    #[test]
+    fn top_level_defines_are_spliced() {
+        let program = r#"
+            (cond-expand
+                [steel (define x 10) (define y 20)]
+                [else (define x 0) (define y 0)])
+            (+ x y)
+        "#;
+        assert_eq!(run(program), SteelVal::IntV(30));
+    }
+
+    #[test]
+    fn nested_in_function_body() {
+        let program = r#"
+            (define (f x) (cond-expand [steel (+ x 1)] [else x]))
+            (f 10)
+        "#;
+        assert_eq!(run(program), SteelVal::IntV(11));
+    }
+
+    #[test]
+    fn when_target_matches_host() {
+        let program = format!(
+            "(define x 1) (when-target {} (set! x 2)) x",
+            std::env::consts::FAMILY
+        );
+        assert_eq!(run(&program), SteelVal::IntV(2));
+
+        assert_eq!(
+            run("(define x 1) (when-target not-a-target (set! x 2)) x"),
+            SteelVal::IntV(1)
+        );
+    }
+
+    #[test]
+    fn local_variables_named_like_conditionals_are_left_alone() {
+        let program = r#"
+            (define (f when-target) (when-target 1 2))
+            (define (g cond-expand) (cond-expand 3))
+            (+ (f +) (g (lambda (x) x)))
+        "#;
+        assert_eq!(run(program), SteelVal::IntV(6));
+    }
+
+    #[test]
+    fn sandboxed_engine_declares_sandbox() {
+        let program = "(cond-expand [sandbox 1] [else 2])";
+        assert_eq!(
+            Engine::new_sandboxed().run(program).unwrap().pop().unwrap(),
+            SteelVal::IntV(1)
+        );
+        assert_eq!(run(program), SteelVal::IntV(2));
+    }
+}
@@ -1,17 +1,21 @@
 use crate::parser::ast::ExprKind;
 use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
 use crate::parser::tokens::TokenType;
 use crate::parser::visitors::ConsumingVisitor;
-// use crate::parser::span::Span;
 
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::Result;
 
 use super::ast::Atom;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-use crate::parser::expander::SteelMacro;
+use crate::parser::expander::{lower_define_values, lower_do, lower_record_type, SteelMacro};
+use crate::parser::lambda_signature::{has_signature, lower_lambda_signature};
+use crate::steel_vm::kernel::Kernel;
 
 pub fn extract_macro_defs(
     exprs: Vec<ExprKind>,
@@ -30,12 +34,85 @@ pub fn extract_macro_defs(
     Ok(non_macros)
 }
 
+// The span of the `define-syntax` a procedural macro is defined with, which the parser leaves
+// as a list for the compiler to evaluate
+fn procedural_macro_span(l: &super::ast::List) -> Option<Span> {
+    match l.first() {
+        Some(ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::DefineSyntax,
+                    span,
+                    ..
+                },
+        })) => Some(*span),
+        _ => None,
+    }
+}
+
+/// Defines the procedural macros at the top level of a program in the kernel, which is started
+/// the first time one is found
+pub fn extract_procedural_macros(
+    exprs: Vec<ExprKind>,
+    macro_map: &mut HashMap<String, SteelMacro>,
+    kernel: &mut Option<Rc<RefCell<Kernel>>>,
+) -> Result<Vec<ExprKind>> {
+    let mut non_macros = Vec::new();
+    for expr in exprs {
+        let (l, span) = match expr {
+            ExprKind::List(l) => match procedural_macro_span(&l) {
+                Some(span) => (l, span),
+                None => {
+                    non_macros.push(ExprKind::List(l));
+                    continue;
+                }
+            },
+            _ => {
+                non_macros.push(expr);
+                continue;
+            }
+        };
+
+        let mut args = l.args.into_iter().skip(1);
+        let name = args
+            .next()
+            .unwrap()
+            .atom_identifier_or_else(
+                throw!(BadSyntax => "macros only currently support identifiers as the name"; span),
+            )?
+            .to_string();
+        let transformer = args.next().unwrap();
+
+        let kernel = kernel.get_or_insert_with(|| Rc::new(RefCell::new(Kernel::new())));
+        kernel
+            .borrow_mut()
+            .define_transformer(&name, transformer.clone())?;
+        macro_map.insert(
+            name.clone(),
+            SteelMacro::procedural(name, transformer, Rc::clone(kernel)),
+        );
+    }
+    Ok(non_macros)
+}
+
 pub fn expand(expr: ExprKind, map: &HashMap<String, SteelMacro>) -> Result<ExprKind> {
-    Expander { map }.visit(expr)
+    Expander {
+        map,
+        locals: Vec::new(),
+    }
+    .visit(expr)
 }
 
 pub struct Expander<'a> {
     map: &'a HashMap<String, SteelMacro>,
+    // Variables bound by the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+impl<'a> Expander<'a> {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
 }
 
 impl<'a> ConsumingVisitor for Expander<'a> {
@@ -57,7 +134,15 @@ impl<'a> ConsumingVisitor for Expander<'a> {
         &mut self,
         mut lambda_function: Box<super::ast::LambdaFunction>,
     ) -> Self::Output {
-        lambda_function.body = self.visit(lambda_function.body)?;
+        if has_signature(&lambda_function) {
+            return self.visit(lower_lambda_signature(lambda_function)?);
+        }
+
+        self.locals.push(lambda_function.locals());
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
         Ok(ExprKind::LambdaFunction(lambda_function))
     }
 
@@ -77,6 +162,11 @@ impl<'a> ConsumingVisitor for Expander<'a> {
 
     fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
         apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
         apply.list = self.visit(apply.list)?;
         Ok(ExprKind::Apply(apply))
     }
@@ -129,7 +219,10 @@ impl<'a> ConsumingVisitor for Expander<'a> {
     }
 
     fn visit_list(&mut self, mut l: super::ast::List) -> Self::Output {
-        // todo!()
+        if let Some(span) = procedural_macro_span(&l) {
+            stop!(BadSyntax => "procedural macros can only be defined at the top level of a program"; span);
+        }
+
         if let Some(ExprKind::Atom(Atom {
             syn:
                 SyntaxObject {
@@ -143,6 +236,49 @@ impl<'a> ConsumingVisitor for Expander<'a> {
                 let expanded = m.expand(l.clone(), *sp)?;
                 return self.visit(expanded);
             }
+
+            // The forms below are built in, unless a function binds the name to something else
+            let builtin = !self.is_local(s);
+
+            if builtin && s == "define-record-type" {
+                let span = *sp;
+                return lower_record_type(l, span);
+            }
+
+            if builtin && s == "do" {
+                let span = *sp;
+                return self.visit(lower_do(l, span)?);
+            }
+
+            if builtin && s == "define-values" {
+                let span = *sp;
+                return self.visit(lower_define_values(l, span)?);
+            }
+
+            // `match` is lowered after expansion, so the patterns are left alone in case
+            // they look like macros, e.g. `(and (? integer?) n)`
+            if builtin && s == "match" {
+                l.args = l
+                    .args
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, e)| match e {
+                        ExprKind::List(mut clause) if i > 1 && !clause.is_empty() => {
+                            let pattern = clause.args.remove(0);
+                            let rest = clause
+                                .args
+                                .into_iter()
+                                .map(|e| self.visit(e))
+                                .collect::<Result<Vec<_>>>()?;
+                            clause.args = std::iter::once(pattern).chain(rest).collect();
+                            Ok(ExprKind::List(clause))
+                        }
+                        e => self.visit(e),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                return Ok(ExprKind::List(l));
+            }
         }
 
         l.args = l
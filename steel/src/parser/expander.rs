@@ -1,4 +1,6 @@
-use crate::parser::ast::{Atom, ExprKind, List, Macro, PatternPair};
+use crate::parser::ast::{
+    named_let, Atom, Begin, Define, ExprKind, If, LambdaFunction, List, Macro, PatternPair, Struct,
+};
 use crate::parser::parser::SyntaxObject;
 use crate::parser::rename_idents::RenameIdentifiersVisitor;
 use crate::parser::replace_idents::replace_identifiers;
@@ -8,17 +10,53 @@ use crate::parser::span::Span;
 
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::Result;
-use std::collections::HashMap;
+use crate::steel_vm::kernel::Kernel;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
 use log::{debug, error, info};
 
 use super::ast::Quote;
 
+thread_local! {
+    // How many macros have been expanded, used to give each expansion its own scope
+    static EXPANSIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn fresh_scope() -> usize {
+    EXPANSIONS.with(|x| {
+        x.set(x.get() + 1);
+        x.get()
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SteelMacro {
     name: String,
     special_forms: Vec<String>,
     cases: Vec<MacroCase>,
+    transformer: Option<Transformer>,
+}
+
+// A macro defined by a function the kernel runs, rather than by `syntax-rules`
+#[derive(Clone)]
+struct Transformer {
+    source: ExprKind,
+    kernel: Rc<RefCell<Kernel>>,
+}
+
+impl fmt::Debug for Transformer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Transformer").field(&self.source).finish()
+    }
+}
+
+impl PartialEq for Transformer {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source && Rc::ptr_eq(&self.kernel, &other.kernel)
+    }
 }
 
 impl SteelMacro {
@@ -28,6 +66,17 @@ impl SteelMacro {
             name,
             special_forms,
             cases,
+            transformer: None,
+        }
+    }
+
+    /// A procedural macro, expanded by calling the transformer it was defined with in the kernel
+    pub fn procedural(name: String, source: ExprKind, kernel: Rc<RefCell<Kernel>>) -> Self {
+        SteelMacro {
+            name,
+            special_forms: Vec::new(),
+            cases: Vec::new(),
+            transformer: Some(Transformer { source, kernel }),
         }
     }
 
@@ -68,6 +117,7 @@ impl SteelMacro {
             name,
             special_forms,
             cases,
+            transformer: None,
         })
     }
 
@@ -96,6 +146,13 @@ impl SteelMacro {
     pub fn expand(&self, expr: List, span: Span) -> Result<ExprKind> {
         debug!("Expanding macro with tokens: {}", expr);
 
+        if let Some(transformer) = &self.transformer {
+            return transformer
+                .kernel
+                .borrow_mut()
+                .expand(&self.name, expr, span);
+        }
+
         let case_to_expand = self.match_case(&expr)?;
         let expanded_expr = case_to_expand.expand(expr, span)?;
 
@@ -109,12 +166,18 @@ impl SteelMacro {
 pub struct MacroCase {
     args: Vec<MacroPattern>,
     body: ExprKind,
+    // The identifiers the template binds itself, which get a fresh scope on each expansion
+    introduced: HashSet<String>,
 }
 
 impl MacroCase {
     #[cfg(test)]
     pub fn new(args: Vec<MacroPattern>, body: ExprKind) -> Self {
-        MacroCase { args, body }
+        MacroCase {
+            args,
+            body,
+            introduced: HashSet::new(),
+        }
     }
 
     fn parse_from_pattern_pair(
@@ -144,14 +207,20 @@ impl MacroCase {
 
         // println!("Args pre mangle: {:?}", &args_str);
 
-        RenameIdentifiersVisitor::new(&args_str, &special_forms).rename_identifiers(&mut body);
+        let mut renamer = RenameIdentifiersVisitor::new(&args_str, special_forms);
+        renamer.rename_identifiers(&mut body);
+        let introduced = renamer.introduced();
 
         let args = args.into_iter().map(|x| x.mangle(&special_forms)).collect();
 
         // println!("Found args: {:?}", args);
         // println!("Renamed body: {:?}", &body);
 
-        Ok(MacroCase { args, body })
+        Ok(MacroCase {
+            args,
+            body,
+            introduced,
+        })
     }
 
     fn has_ellipses(&self) -> bool {
@@ -172,7 +241,12 @@ impl MacroCase {
     fn expand(&self, expr: List, span: Span) -> Result<ExprKind> {
         let mut bindings = HashMap::new();
         collect_bindings(&self.args, &expr, &mut bindings)?;
-        replace_identifiers(self.body.clone(), &bindings, span)
+
+        // Each expansion binds its own copies of the identifiers the template introduces, so
+        // they can't capture the identifiers passed in from the use site, even when those come
+        // from another expansion of a macro introducing the same names
+        let scope = (&self.introduced, fresh_scope());
+        replace_identifiers(self.body.clone(), &bindings, Some(scope), span)
     }
 }
 
@@ -187,6 +261,7 @@ pub enum MacroPattern {
     StringLiteral(String),
     FloatLiteral(f64),
     BooleanLiteral(bool),
+    Keyword(String),
     QuotedExpr(Box<Quote>),
 }
 
@@ -269,6 +344,9 @@ impl MacroPattern {
                     TokenType::StringLiteral(s) => {
                         pattern_vec.push(MacroPattern::StringLiteral(s));
                     }
+                    TokenType::Keyword(k) => {
+                        pattern_vec.push(MacroPattern::Keyword(k));
+                    }
                     _ => {
                         stop!(BadSyntax => "syntax-rules requires identifiers in the pattern"; span);
                     }
@@ -392,6 +470,16 @@ pub fn match_vec_pattern(args: &[MacroPattern], list: &List) -> bool {
                     }) if s == b => continue,
                     _ => return false,
                 },
+                MacroPattern::Keyword(k) => match val {
+                    ExprKind::Atom(Atom {
+                        syn:
+                            SyntaxObject {
+                                ty: TokenType::Keyword(s),
+                                ..
+                            },
+                    }) if s == k => continue,
+                    _ => return false,
+                },
                 MacroPattern::QuotedExpr(q) => match val {
                     ExprKind::Quote(boxed_q) if q == boxed_q => continue,
                     _ => return false,
@@ -444,6 +532,27 @@ pub fn match_vec_pattern(args: &[MacroPattern], list: &List) -> bool {
     true
 }
 
+/// Matches a `syntax-case` pattern against some syntax, returning what the pattern variables
+/// are bound to. Patterns are the same as in `syntax-rules`, except that the first element
+/// of a list is matched like any other rather than being the name of the macro.
+pub(crate) fn match_syntax(
+    pattern: &ExprKind,
+    literals: &[String],
+    syntax: &ExprKind,
+) -> Result<Option<HashMap<String, ExprKind>>> {
+    let args = MacroPattern::parse_from_list(List::new(vec![pattern.clone()]), "", literals)?;
+    let syntax = List::new(vec![syntax.clone()]);
+
+    if !match_vec_pattern(&args, &syntax) {
+        return Ok(None);
+    }
+
+    let mut bindings = HashMap::new();
+    collect_bindings(&args, &syntax, &mut bindings)?;
+    bindings.remove("_");
+    Ok(Some(bindings))
+}
+
 pub fn collect_bindings(
     args: &[MacroPattern],
     list: &List,
@@ -463,9 +572,9 @@ pub fn collect_bindings(
             }
             // actually check if the syntax matches
             MacroPattern::Syntax(s) => {
-                let e = token_iter.next().ok_or_else(
-                    throw!(BadSyntax => format!("macro expand expected keyword {}", s)),
-                )?;
+                let e = token_iter
+                    .next()
+                    .ok_or_else(throw!(BadSyntax => "macro expand expected keyword {}", s))?;
 
                 if let ExprKind::Atom(Atom {
                     syn:
@@ -497,13 +606,333 @@ pub fn collect_bindings(
                     stop!(BadSyntax => "macro expected a list of values, not including keywords")
                 }
             }
-            _ => {}
+            // Literals were already checked when matching, they just need to be skipped over
+            _ => {
+                token_iter.next();
+            }
         }
     }
 
     Ok(())
 }
 
+/// Lowers a SRFI-9 record type definition, e.g.
+///
+/// ```scheme
+/// (define-record-type <point>
+///   (make-point x y)
+///   point?
+///   (x point-x set-point-x!)
+///   (y point-y))
+/// ```
+///
+/// into a `struct` named after the type, so that records are built and checked by the
+/// struct instructions, along with definitions for the names given to the constructor,
+/// predicate, accessors and modifiers. The constructor can also be a bare name, which takes
+/// every field in order, or `#f` for no constructor.
+pub fn lower_record_type(expr: List, span: Span) -> Result<ExprKind> {
+    if expr.len() < 4 {
+        stop!(BadSyntax => "define-record-type expects a type name, a constructor, a predicate and fields"; span);
+    }
+
+    let mut args = expr.args.into_iter().skip(1);
+    let type_name = args.next().unwrap();
+    let type_name = record_identifier(&type_name, "a type name", span)?;
+    let constructor = args.next().unwrap();
+    let predicate = args.next().unwrap();
+
+    let mut fields: Vec<String> = Vec::new();
+    let mut definitions = Vec::new();
+
+    for (idx, field) in args.enumerate() {
+        let parts = match &field {
+            ExprKind::List(l) if !l.is_empty() && l.len() <= 3 => l.args.iter().collect(),
+            ExprKind::Atom(_) => vec![&field],
+            _ => {
+                stop!(BadSyntax => "define-record-type expects fields like (field accessor modifier)"; span)
+            }
+        };
+
+        let names = parts
+            .into_iter()
+            .map(|x| record_identifier(x, "a field", span))
+            .collect::<Result<Vec<_>>>()?;
+
+        if fields.iter().any(|x| x == names[0]) {
+            stop!(BadSyntax => "define-record-type has the field {} more than once", names[0]; span);
+        }
+        fields.push(names[0].to_string());
+
+        if let Some(accessor) = names.get(1) {
+            let getter = format!("{}-{}", type_name, names[0]);
+            definitions.push(record_define(accessor, record_ident(&getter, span), span));
+        }
+
+        if let Some(modifier) = names.get(2) {
+            let body = ExprKind::List(List::new(vec![
+                record_ident("%struct-modifier", span),
+                ExprKind::Atom(Atom::new(SyntaxObject::new(
+                    TokenType::StringLiteral(type_name.to_string()),
+                    span,
+                ))),
+                ExprKind::Atom(Atom::new(SyntaxObject::new(
+                    TokenType::IntegerLiteral(idx as isize),
+                    span,
+                ))),
+            ]));
+            definitions.push(record_define(modifier, body, span));
+        }
+    }
+
+    match &constructor {
+        ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::BooleanLiteral(false),
+                    ..
+                },
+        }) => {}
+        ExprKind::Atom(_) => {
+            let name = record_identifier(&constructor, "a constructor", span)?;
+            definitions.push(record_define(name, record_ident(type_name, span), span));
+        }
+        ExprKind::List(l) if !l.is_empty() => {
+            let names = l
+                .args
+                .iter()
+                .map(|x| record_identifier(x, "a constructor", span))
+                .collect::<Result<Vec<_>>>()?;
+
+            for arg in &names[1..] {
+                if !fields.iter().any(|x| x == arg) {
+                    stop!(BadSyntax => "define-record-type constructor takes {}, which isn't a field", *arg; span);
+                }
+            }
+
+            let body = if names[1..] == fields[..] {
+                record_ident(type_name, span)
+            } else {
+                // Fields that aren't given to the constructor start out as void
+                let struct_args = fields.iter().map(|field| {
+                    if names[1..].contains(&field.as_str()) {
+                        record_ident(field, span)
+                    } else {
+                        record_ident("void", span)
+                    }
+                });
+
+                ExprKind::LambdaFunction(Box::new(LambdaFunction::new(
+                    names[1..].iter().map(|x| record_ident(x, span)).collect(),
+                    ExprKind::List(List::new(
+                        std::iter::once(record_ident(type_name, span))
+                            .chain(struct_args)
+                            .collect(),
+                    )),
+                    SyntaxObject::new(TokenType::Lambda, span),
+                )))
+            };
+            definitions.push(record_define(names[0], body, span));
+        }
+        _ => {
+            stop!(BadSyntax => "define-record-type expects a constructor like (make-point x y)"; span)
+        }
+    }
+
+    let predicate = record_identifier(&predicate, "a predicate", span)?;
+    let checker = format!("{}?", type_name);
+    definitions.push(record_define(predicate, record_ident(&checker, span), span));
+
+    let record = Struct::new(
+        record_ident(type_name, span),
+        fields.iter().map(|x| record_ident(x, span)).collect(),
+        SyntaxObject::new(TokenType::Struct, span),
+    );
+
+    // Names that are the same as the ones the struct binds don't need defining again
+    let struct_names = std::iter::once(type_name.to_string())
+        .chain(std::iter::once(checker))
+        .chain(fields.iter().map(|x| format!("{}-{}", type_name, x)))
+        .collect::<Vec<_>>();
+    definitions.retain(|define| match define {
+        ExprKind::Define(d) => !matches!(
+            (d.name.atom_identifier_or_else(|| ()), d.body.atom_identifier_or_else(|| ())),
+            (Ok(name), Ok(body)) if name == body && struct_names.iter().any(|x| x == name)
+        ),
+        _ => true,
+    });
+
+    Ok(ExprKind::Begin(Begin::new(
+        std::iter::once(ExprKind::Struct(Box::new(record)))
+            .chain(definitions)
+            .collect(),
+        SyntaxObject::new(TokenType::Begin, span),
+    )))
+}
+
+fn record_identifier<'a>(expr: &'a ExprKind, what: &str, span: Span) -> Result<&'a str> {
+    expr.atom_identifier_or_else(
+        throw!(BadSyntax => "define-record-type expected {}, found {}", what, expr; span),
+    )
+}
+
+fn record_ident(name: &str, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::Identifier(name.to_string()),
+        span,
+    )))
+}
+
+fn record_define(name: &str, body: ExprKind, span: Span) -> ExprKind {
+    ExprKind::Define(Box::new(Define::new(
+        record_ident(name, span),
+        body,
+        SyntaxObject::new(TokenType::Define, span),
+    )))
+}
+
+/// Lowers `(define-values (q r) expr)` into
+///
+/// ```scheme
+/// (begin
+///   (define ##values-q-r (#%values->vector (lambda () expr)))
+///   (define q (#%values-ref ##values-q-r 0 2))
+///   (define r (#%values-ref ##values-q-r 1 2)))
+/// ```
+///
+/// where `expr` returns its values with `values`, and it is an error for it to return
+/// a different number of them.
+pub fn lower_define_values(expr: List, span: Span) -> Result<ExprKind> {
+    let (names, body) = match expr.args.as_slice() {
+        [_, ExprKind::List(names), body] => (names, body.clone()),
+        _ => stop!(BadSyntax => "define-values expects a list of names and an expression"; span),
+    };
+
+    let names = names
+        .args
+        .iter()
+        .map(|name| {
+            name.atom_identifier_or_else(
+                throw!(BadSyntax => "define-values expects names, found {}", name; span),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let values = format!("##values-{}", names.join("-"));
+    let integer = |n: usize| {
+        ExprKind::Atom(Atom::new(SyntaxObject::new(
+            TokenType::IntegerLiteral(n as isize),
+            span,
+        )))
+    };
+
+    let definitions = names.iter().enumerate().map(|(i, name)| {
+        let value = ExprKind::List(List::new(vec![
+            record_ident("#%values-ref", span),
+            record_ident(&values, span),
+            integer(i),
+            integer(names.len()),
+        ]));
+        record_define(name, value, span)
+    });
+
+    // Multiple values can't be bound as they are, so they're collected into a vector first
+    let thunk = ExprKind::LambdaFunction(Box::new(LambdaFunction::new(
+        Vec::new(),
+        body,
+        SyntaxObject::new(TokenType::Lambda, span),
+    )));
+    let collected = ExprKind::List(List::new(vec![
+        record_ident("#%values->vector", span),
+        thunk,
+    ]));
+
+    Ok(ExprKind::Begin(Begin::new(
+        std::iter::once(record_define(&values, collected, span))
+            .chain(definitions)
+            .collect(),
+        SyntaxObject::new(TokenType::Begin, span),
+    )))
+}
+
+/// Lowers `(do ([var init step] ...) (test result ...) command ...)` into a named let
+///
+/// ```scheme
+/// (let ##do-loop ([var init] ...)
+///   (if test
+///       (begin void result ...)
+///       (begin command ... (##do-loop step ...))))
+/// ```
+///
+/// Variables without a step keep their value from one iteration to the next.
+pub fn lower_do(expr: List, span: Span) -> Result<ExprKind> {
+    let mut args = expr.args.into_iter().skip(1);
+    let (bindings, exit) = match (args.next(), args.next()) {
+        (Some(ExprKind::List(bindings)), Some(ExprKind::List(exit))) if !exit.is_empty() => {
+            (bindings, exit)
+        }
+        _ => {
+            stop!(BadSyntax => "do expects a list of variables and a list with the test to stop at"; span)
+        }
+    };
+    let commands = args.collect::<Vec<_>>();
+
+    let mut parameters = Vec::new();
+    let mut initial_values = Vec::new();
+    let mut steps = Vec::new();
+    for binding in bindings.args {
+        let mut parts = match binding {
+            ExprKind::List(l) if l.len() == 2 || l.len() == 3 => l.args.into_iter(),
+            other => {
+                stop!(BadSyntax => "do expects variables like (var init step), found {}", other; span)
+            }
+        };
+        let (var, init) = (parts.next().unwrap(), parts.next().unwrap());
+        var.atom_identifier_or_else(
+            throw!(BadSyntax => "do expects a variable name, found {}", var; span),
+        )?;
+
+        steps.push(parts.next().unwrap_or_else(|| var.clone()));
+        parameters.push(var);
+        initial_values.push(init);
+    }
+
+    let begin = |exprs: Vec<ExprKind>| {
+        ExprKind::Begin(Begin::new(exprs, SyntaxObject::new(TokenType::Begin, span)))
+    };
+
+    let name = Atom::new(SyntaxObject::new(
+        TokenType::Identifier("##do-loop".to_string()),
+        span,
+    ));
+    let next = ExprKind::List(List::new(
+        std::iter::once(ExprKind::Atom(name.clone()))
+            .chain(steps)
+            .collect(),
+    ));
+
+    let mut exit = exit.args.into_iter();
+    let test = exit.next().unwrap();
+    let result = begin(
+        std::iter::once(record_ident("void", span))
+            .chain(exit)
+            .collect(),
+    );
+    let body = ExprKind::If(Box::new(If::new(
+        test,
+        result,
+        begin(commands.into_iter().chain(std::iter::once(next)).collect()),
+        SyntaxObject::new(TokenType::If, span),
+    )));
+
+    Ok(named_let(
+        name,
+        parameters,
+        initial_values,
+        body,
+        SyntaxObject::new(TokenType::Let, span),
+    ))
+}
+
 #[cfg(test)]
 mod match_vec_pattern_tests {
     use super::*;
@@ -890,6 +1319,7 @@ mod macro_case_expand_test {
                 atom_identifier("c"),
             ])
             .into(),
+            introduced: HashSet::new(),
         };
 
         let input = List::new(vec![
@@ -912,4 +1342,32 @@ mod macro_case_expand_test {
 
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn introduced_identifiers_get_a_fresh_scope() {
+        let case = MacroCase {
+            args: vec![
+                MacroPattern::Syntax("test".to_string()),
+                MacroPattern::Single("##a".to_string()),
+            ],
+            body: List::new(vec![atom_identifier("##tmp"), atom_identifier("##a")]).into(),
+            introduced: vec!["##tmp".to_string()].into_iter().collect(),
+        };
+
+        let input = || List::new(vec![atom_identifier("test"), atom_identifier("tmp")]);
+        let first = case.expand(input(), Span::new(0, 0)).unwrap();
+        let second = case.expand(input(), Span::new(0, 0)).unwrap();
+
+        let names = |expr: &ExprKind| match expr {
+            ExprKind::List(l) => (l.args[0].to_string(), l.args[1].to_string()),
+            _ => panic!("expected a list"),
+        };
+
+        let (first_tmp, first_arg) = names(&first);
+        let (second_tmp, _) = names(&second);
+        assert!(first_tmp.starts_with("##tmp#"));
+        assert_ne!(first_tmp, second_tmp);
+        // The identifier from the use site is left alone
+        assert_eq!(first_arg, "tmp");
+    }
 }
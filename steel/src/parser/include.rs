@@ -0,0 +1,387 @@
+use crate::parser::ast::{Atom, Begin, ExprKind, List};
+use crate::parser::parser::{ParseError, Parser, SyntaxObject};
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::ConsumingVisitor;
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Resolves `(include "file.scm")` and `(include-str "asset.txt")` forms, relative to the
+/// file at `path` (or the current directory if there isn't one).
+///
+/// `include` at the top level splices in the expressions from the other file, elsewhere
+/// they get wrapped in a `begin`. `include-str` is replaced with the contents of the file
+/// as a string literal, so the asset is embedded directly in the compiled program.
+pub fn expand_includes(exprs: Vec<ExprKind>, path: Option<&Path>) -> Result<Vec<ExprKind>> {
+    let directory = match path {
+        Some(p) if p.is_file() => p.parent().map(Path::to_path_buf).unwrap_or_default(),
+        Some(p) => p.to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+
+    IncludeExpander {
+        directory,
+        including: Vec::new(),
+        locals: Vec::new(),
+    }
+    .expand_top_level(exprs)
+}
+
+struct IncludeExpander {
+    directory: PathBuf,
+    // The stack of files currently being included, to catch cycles
+    including: Vec<PathBuf>,
+    // Variables bound by the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+impl IncludeExpander {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    // The name of the function being called, unless a local variable has taken it over
+    fn head<'a>(&self, l: &'a List) -> Option<&'a str> {
+        l.first_ident().filter(|name| !self.is_local(name))
+    }
+
+    fn expand_top_level(&mut self, exprs: Vec<ExprKind>) -> Result<Vec<ExprKind>> {
+        let mut output = Vec::with_capacity(exprs.len());
+
+        for expr in exprs {
+            if let ExprKind::List(l) = &expr {
+                if self.head(l) == Some("include") {
+                    let (path, span) = include_path(&l.args, "include")?;
+                    output.append(&mut self.include(path, span)?);
+                    continue;
+                }
+            }
+
+            output.push(self.visit(expr)?);
+        }
+
+        Ok(output)
+    }
+
+    fn resolve(&self, path: &str, span: Span) -> Result<PathBuf> {
+        let resolved = self.directory.join(path);
+        std::fs::canonicalize(&resolved).map_err(|e| {
+            SteelErr::new(
+                ErrorKind::Io,
+                format!("unable to find included file {:?}: {}", resolved, e),
+            )
+            .with_span(span)
+        })
+    }
+
+    fn read(&self, path: &Path, span: Span) -> Result<String> {
+        std::fs::read_to_string(path).map_err(|e| {
+            SteelErr::new(
+                ErrorKind::Io,
+                format!("unable to read included file {:?}: {}", path, e),
+            )
+            .with_span(span)
+        })
+    }
+
+    fn include(&mut self, path: &str, span: Span) -> Result<Vec<ExprKind>> {
+        let path = self.resolve(path, span)?;
+
+        if self.including.contains(&path) {
+            stop!(Generic => "circular include found with: {:?}", path; span);
+        }
+
+        let source = self.read(&path, span)?;
+
+        let mut intern = HashMap::new();
+        let parsed = Parser::new_from_source(&source, &mut intern, path.clone())
+            .collect::<std::result::Result<Vec<_>, ParseError>>()?;
+
+        // Includes inside of the included file are relative to that file
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let parent_directory = std::mem::replace(&mut self.directory, directory);
+        self.including.push(path);
+
+        let output = self.expand_top_level(parsed);
+
+        self.including.pop();
+        self.directory = parent_directory;
+
+        output
+    }
+}
+
+fn include_path<'a>(args: &'a [ExprKind], name: &str) -> Result<(&'a str, Span)> {
+    let span = if let Some(ExprKind::Atom(a)) = args.first() {
+        a.syn.span
+    } else {
+        Span::new(0, 0)
+    };
+
+    match args {
+        [_, ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::StringLiteral(path),
+                    ..
+                },
+        })] => Ok((path, span)),
+        _ => {
+            stop!(BadSyntax => "{} expects a single string literal referring to a file", name; span)
+        }
+    }
+}
+
+impl ConsumingVisitor for IncludeExpander {
+    type Output = Result<ExprKind>;
+
+    fn visit_if(&mut self, mut f: Box<super::ast::If>) -> Self::Output {
+        f.test_expr = self.visit(f.test_expr)?;
+        f.then_expr = self.visit(f.then_expr)?;
+        f.else_expr = self.visit(f.else_expr)?;
+        Ok(ExprKind::If(f))
+    }
+
+    fn visit_define(&mut self, mut define: Box<super::ast::Define>) -> Self::Output {
+        define.body = self.visit(define.body)?;
+        Ok(ExprKind::Define(define))
+    }
+
+    fn visit_lambda_function(
+        &mut self,
+        mut lambda_function: Box<super::ast::LambdaFunction>,
+    ) -> Self::Output {
+        self.locals.push(lambda_function.locals());
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
+        Ok(ExprKind::LambdaFunction(lambda_function))
+    }
+
+    fn visit_begin(&mut self, mut begin: Begin) -> Self::Output {
+        begin.exprs = begin
+            .exprs
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::Begin(begin))
+    }
+
+    fn visit_return(&mut self, mut r: Box<super::ast::Return>) -> Self::Output {
+        r.expr = self.visit(r.expr)?;
+        Ok(ExprKind::Return(r))
+    }
+
+    fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
+        apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        apply.list = self.visit(apply.list)?;
+        Ok(ExprKind::Apply(apply))
+    }
+
+    fn visit_panic(&mut self, mut p: Box<super::ast::Panic>) -> Self::Output {
+        p.message = self.visit(p.message)?;
+        Ok(ExprKind::Panic(p))
+    }
+
+    fn visit_transduce(&mut self, mut transduce: Box<super::ast::Transduce>) -> Self::Output {
+        transduce.transducer = self.visit(transduce.transducer)?;
+        transduce.func = self.visit(transduce.func)?;
+        transduce.initial_value = self.visit(transduce.initial_value)?;
+        transduce.iterable = self.visit(transduce.iterable)?;
+        Ok(ExprKind::Transduce(transduce))
+    }
+
+    fn visit_read(&mut self, mut read: Box<super::ast::Read>) -> Self::Output {
+        read.expr = self.visit(read.expr)?;
+        Ok(ExprKind::Read(read))
+    }
+
+    fn visit_execute(&mut self, mut execute: Box<super::ast::Execute>) -> Self::Output {
+        execute.transducer = self.visit(execute.transducer)?;
+        execute.collection = self.visit(execute.collection)?;
+        execute.output_type = execute.output_type.map(|x| self.visit(x)).transpose()?;
+        Ok(ExprKind::Execute(execute))
+    }
+
+    // Quoted includes are left as data
+    fn visit_quote(&mut self, quote: Box<super::ast::Quote>) -> Self::Output {
+        Ok(ExprKind::Quote(quote))
+    }
+
+    fn visit_struct(&mut self, s: Box<super::ast::Struct>) -> Self::Output {
+        Ok(ExprKind::Struct(s))
+    }
+
+    fn visit_macro(&mut self, m: super::ast::Macro) -> Self::Output {
+        Ok(ExprKind::Macro(m))
+    }
+
+    fn visit_eval(&mut self, mut e: Box<super::ast::Eval>) -> Self::Output {
+        e.expr = self.visit(e.expr)?;
+        Ok(ExprKind::Eval(e))
+    }
+
+    fn visit_atom(&mut self, a: Atom) -> Self::Output {
+        Ok(ExprKind::Atom(a))
+    }
+
+    fn visit_list(&mut self, mut l: List) -> Self::Output {
+        match self.head(&l) {
+            Some("include") => {
+                let (path, span) = include_path(&l.args, "include")?;
+                let exprs = self.include(path, span)?;
+                return Ok(ExprKind::Begin(Begin::new(
+                    exprs,
+                    SyntaxObject::new(TokenType::Begin, span),
+                )));
+            }
+            Some("include-str") => {
+                let (path, span) = include_path(&l.args, "include-str")?;
+                let path = self.resolve(path, span)?;
+                let contents = self.read(&path, span)?;
+                return Ok(ExprKind::Atom(Atom::new(SyntaxObject::new(
+                    TokenType::StringLiteral(contents),
+                    span,
+                ))));
+            }
+            _ => {}
+        }
+
+        l.args = l
+            .args
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ExprKind::List(l))
+    }
+
+    fn visit_syntax_rules(&mut self, l: super::ast::SyntaxRules) -> Self::Output {
+        Ok(ExprKind::SyntaxRules(l))
+    }
+
+    fn visit_set(&mut self, mut s: Box<super::ast::Set>) -> Self::Output {
+        s.expr = self.visit(s.expr)?;
+        Ok(ExprKind::Set(s))
+    }
+
+    fn visit_require(&mut self, s: super::ast::Require) -> Self::Output {
+        Ok(ExprKind::Require(s))
+    }
+
+    fn visit_callcc(&mut self, mut cc: Box<super::ast::CallCC>) -> Self::Output {
+        cc.expr = self.visit(cc.expr)?;
+        Ok(ExprKind::CallCC(cc))
+    }
+}
+
+#[cfg(test)]
+mod include_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    // Each test gets its own scratch directory, since the tests run in parallel
+    fn scratch_directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir()
+            .join(format!("steel-include-tests-{}", std::process::id()))
+            .join(name);
+        for (file, contents) in files {
+            let path = directory.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn include_splices_nested_files() {
+        let directory = scratch_directory(
+            "nested",
+            &[
+                ("main.scm", "(include \"lib/a.scm\") (+ (a) (b))"),
+                ("lib/a.scm", "(define (a) 10) (include \"b.scm\")"),
+                ("lib/b.scm", "(define (b) 20)"),
+            ],
+        );
+
+        let mut engine = Engine::new();
+        let result = engine
+            .parse_and_execute_from_path(directory.join("main.scm"))
+            .unwrap();
+
+        assert_eq!(result.last(), Some(&SteelVal::IntV(30)));
+    }
+
+    #[test]
+    fn include_str_embeds_file_contents() {
+        let directory = scratch_directory(
+            "include-str",
+            &[
+                (
+                    "main.scm",
+                    "(define message (include-str \"assets/message.txt\"))",
+                ),
+                ("assets/message.txt", "hello\nworld"),
+            ],
+        );
+
+        let mut engine = Engine::new();
+        engine
+            .parse_and_execute_from_path(directory.join("main.scm"))
+            .unwrap();
+
+        assert_eq!(
+            engine.extract_value("message").unwrap(),
+            SteelVal::StringV("hello\nworld".into())
+        );
+    }
+
+    #[test]
+    fn circular_include_is_an_error() {
+        let directory = scratch_directory(
+            "circular",
+            &[
+                ("a.scm", "(include \"b.scm\")"),
+                ("b.scm", "(include \"a.scm\")"),
+            ],
+        );
+
+        let mut engine = Engine::new();
+        assert!(engine
+            .parse_and_execute_from_path(directory.join("a.scm"))
+            .is_err());
+    }
+
+    #[test]
+    fn local_variables_named_include_are_left_alone() {
+        let mut engine = Engine::new();
+        let result = engine
+            .run(
+                r#"
+                (define (f include) (include "x"))
+                (define (g include-str) (include-str "y"))
+                (string-append (f (lambda (x) x)) (g (lambda (y) y)))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(result.last(), Some(&SteelVal::StringV("xy".into())));
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        let mut engine = Engine::new();
+        assert!(engine.run("(include-str \"does-not-exist.txt\")").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::parser::ast::{Atom, Begin, ExprKind, If, LambdaFunction, List, Set};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+
+/// The builtin each lambda with optional or keyword arguments turns into a call to, which
+/// attaches the signature to the closure
+pub(crate) const LAMBDA_SIGNATURE: &str = "#%lambda-signature";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Required,
+    Optional,
+    Key,
+}
+
+fn identifier(name: &str, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::Identifier(name.to_string()),
+        span,
+    )))
+}
+
+fn keyword(expr: &ExprKind) -> Option<&str> {
+    match expr {
+        ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Keyword(k),
+                    ..
+                },
+        }) => Some(k),
+        _ => None,
+    }
+}
+
+/// Whether the lambda takes `#:optional` or `#:key` arguments
+pub fn has_signature(lambda: &LambdaFunction) -> bool {
+    lambda.args.iter().any(|x| keyword(x).is_some())
+}
+
+/// Lowers a lambda with optional or keyword arguments into one that takes every parameter
+/// in order, with the defaults filled in at the start of the body.
+///
+/// ```scheme
+/// (lambda (a #:optional (b 10) #:key (c 20)) (list a b c))
+/// ;; =>
+/// (#%lambda-signature
+///   (lambda (a b c)
+///     (set! b (if (void? b) 10 b))
+///     (set! c (if (void? c) 20 c))
+///     (list a b c))
+///   1 1 #:c)
+/// ```
+///
+/// Callers pass keyword arguments after the positional ones, as `(f 1 #:c 30)`. Arguments
+/// that aren't given are `void`, so passing `void` explicitly also gets the default. Defaults
+/// can refer to the parameters before them.
+pub fn lower_lambda_signature(lambda: Box<LambdaFunction>) -> Result<ExprKind> {
+    let LambdaFunction {
+        args,
+        body,
+        location,
+    } = *lambda;
+    let span = location.span;
+
+    let mut section = Section::Required;
+    let mut parameters = Vec::new();
+    let mut defaults = Vec::new();
+    let mut required = 0;
+    let mut optional = 0;
+    let mut keywords = Vec::new();
+
+    for arg in args {
+        match keyword(&arg) {
+            Some("optional") if section == Section::Required => {
+                section = Section::Optional;
+                continue;
+            }
+            Some("key") if section != Section::Key => {
+                section = Section::Key;
+                continue;
+            }
+            Some(other) => {
+                stop!(BadSyntax => "lambda doesn't expect #:{} here, #:optional arguments come before #:key arguments", other; span)
+            }
+            None => {}
+        }
+
+        let (name, default) = match (section, arg) {
+            (Section::Required, ExprKind::Atom(a)) => (a, None),
+            (Section::Optional, ExprKind::List(l)) | (Section::Key, ExprKind::List(l))
+                if l.len() == 2 =>
+            {
+                let mut pair = l.args.into_iter();
+                match (pair.next(), pair.next()) {
+                    (Some(ExprKind::Atom(a)), Some(default)) => (a, Some(default)),
+                    _ => {
+                        stop!(BadSyntax => "lambda expects optional and keyword arguments to look like (name default)"; span)
+                    }
+                }
+            }
+            _ => {
+                stop!(BadSyntax => "lambda expects optional and keyword arguments to look like (name default)"; span)
+            }
+        };
+
+        let name = match &name.syn.ty {
+            TokenType::Identifier(s) => s.clone(),
+            _ => stop!(BadSyntax => "lambda function expects a list of identifiers"; span),
+        };
+
+        match section {
+            Section::Required => required += 1,
+            Section::Optional => optional += 1,
+            Section::Key => keywords.push(ExprKind::Atom(Atom::new(SyntaxObject::new(
+                TokenType::Keyword(name.clone()),
+                span,
+            )))),
+        }
+
+        parameters.push(identifier(&name, span));
+        if let Some(default) = default {
+            defaults.push((name, default));
+        }
+    }
+
+    // The defaults are filled in with `set!` rather than `let`, which keeps the body in tail
+    // position so that calls back to the function are still turned into jumps
+    let body = if defaults.is_empty() {
+        body
+    } else {
+        let mut exprs: Vec<ExprKind> = defaults
+            .into_iter()
+            .map(|(name, default)| {
+                let value = ExprKind::If(Box::new(If::new(
+                    ExprKind::List(List::new(vec![
+                        identifier("void?", span),
+                        identifier(&name, span),
+                    ])),
+                    default,
+                    identifier(&name, span),
+                    SyntaxObject::new(TokenType::If, span),
+                )));
+
+                ExprKind::Set(Box::new(Set::new(
+                    identifier(&name, span),
+                    value,
+                    SyntaxObject::new(TokenType::Set, span),
+                )))
+            })
+            .collect();
+        exprs.push(body);
+        ExprKind::Begin(Begin::new(exprs, SyntaxObject::new(TokenType::Begin, span)))
+    };
+
+    let integer = |n: usize| {
+        ExprKind::Atom(Atom::new(SyntaxObject::new(
+            TokenType::IntegerLiteral(n as isize),
+            span,
+        )))
+    };
+
+    let lambda =
+        ExprKind::LambdaFunction(Box::new(LambdaFunction::new(parameters, body, location)));
+
+    Ok(ExprKind::List(List::new(
+        vec![
+            identifier(LAMBDA_SIGNATURE, span),
+            lambda,
+            integer(required),
+            integer(optional),
+        ]
+        .into_iter()
+        .chain(keywords)
+        .collect(),
+    )))
+}
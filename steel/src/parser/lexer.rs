@@ -234,6 +234,140 @@ mod tests {
         assert_eq!(s.next(), None);
     }
 
+    fn token_types(input: &str) -> Vec<TokenType> {
+        TokenStream::new(input, true).map(|x| x.ty).collect()
+    }
+
+    #[test]
+    fn test_named_and_hex_chars() {
+        assert_eq!(
+            token_types(
+                r"#\space #\SPACE #\newline #\null #\alarm #\delete #\x41 #\u{3bb} #\x #\("
+            ),
+            vec![
+                CharacterLiteral(' '),
+                CharacterLiteral(' '),
+                CharacterLiteral('\n'),
+                CharacterLiteral('\0'),
+                CharacterLiteral('\u{7}'),
+                CharacterLiteral('\u{7f}'),
+                CharacterLiteral('A'),
+                CharacterLiteral('λ'),
+                CharacterLiteral('x'),
+                CharacterLiteral('('),
+            ]
+        );
+
+        assert_eq!(token_types(r"#\applesauce"), vec![Error]);
+    }
+
+    #[test]
+    fn test_chars_display_as_they_are_read() {
+        for c in [' ', '\n', '\0', '\u{1b}', '\u{85}', 'a', 'λ'].iter() {
+            let written = CharacterLiteral(*c).to_string();
+            assert_eq!(token_types(&written), vec![CharacterLiteral(*c)]);
+        }
+    }
+
+    #[test]
+    fn test_radix_and_exactness_prefixes() {
+        assert_eq!(
+            token_types("#b1010 #o17 #xFF #x-1f #d99 0x10 -0b11 1_000"),
+            vec![
+                IntegerLiteral(10),
+                IntegerLiteral(15),
+                IntegerLiteral(255),
+                IntegerLiteral(-31),
+                IntegerLiteral(99),
+                IntegerLiteral(16),
+                IntegerLiteral(-3),
+                IntegerLiteral(1000),
+            ]
+        );
+
+        assert_eq!(
+            token_types("#e1.0 #e1e3 #e#x10 #x#e10 #i10 #i#b11 #x#i10 #d1.5"),
+            vec![
+                IntegerLiteral(1),
+                IntegerLiteral(1000),
+                IntegerLiteral(16),
+                IntegerLiteral(16),
+                NumberLiteral(10.0),
+                NumberLiteral(3.0),
+                NumberLiteral(16.0),
+                NumberLiteral(1.5),
+            ]
+        );
+
+        // Without rationals there's no exact version of 1.5
+        assert_eq!(token_types("#e1.5"), vec![Error]);
+    }
+
+    #[test]
+    fn test_smallest_integer() {
+        let smallest = format!(
+            "-9223372036854775808 -0x8000000000000000 -0b1{zeros} #x-8000000000000000 \
+             #b-1{zeros} #o-1000000000000000000000 #d-9223372036854775808 #e-9223372036854775808",
+            zeros = "0".repeat(63)
+        );
+        assert_eq!(token_types(&smallest), vec![IntegerLiteral(isize::MIN); 8]);
+
+        // One past it still doesn't fit
+        assert_eq!(token_types("-9223372036854775809"), vec![Error]);
+        assert_eq!(token_types("#x-8000000000000001"), vec![Error]);
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        assert_eq!(
+            token_types("1e3 1E-3 -2e+2 1.e2 .5 -.5e1 1.5e2 1_0.0_1"),
+            vec![
+                NumberLiteral(1000.0),
+                NumberLiteral(0.001),
+                NumberLiteral(-200.0),
+                NumberLiteral(100.0),
+                NumberLiteral(0.5),
+                NumberLiteral(-5.0),
+                NumberLiteral(150.0),
+                NumberLiteral(10.01),
+            ]
+        );
+
+        assert_eq!(
+            token_types("+inf.0 -inf.0"),
+            vec![
+                NumberLiteral(f64::INFINITY),
+                NumberLiteral(f64::NEG_INFINITY)
+            ]
+        );
+        assert!(matches!(token_types("+nan.0")[..], [NumberLiteral(x)] if x.is_nan()));
+
+        // Still identifiers
+        assert_eq!(
+            token_types("e1 . ..."),
+            vec![
+                Identifier("e1".to_string()),
+                Identifier(".".to_string()),
+                Ellipses
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            token_types("(f #:name 10 #:with-dashes?)"),
+            vec![
+                OpenParen,
+                Identifier("f".to_string()),
+                Keyword("name".to_string()),
+                IntegerLiteral(10),
+                Keyword("with-dashes?".to_string()),
+                CloseParen,
+            ]
+        );
+    }
+
     #[test]
     fn test_string() {
         let mut s = TokenStream::new(r#" "" "Foo bar" "\"\\" "#, true);
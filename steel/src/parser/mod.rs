@@ -1,13 +1,19 @@
 pub mod ast;
+pub mod builder;
+pub mod cond_expand;
 pub mod expand_visitor;
 pub mod expander;
+pub mod include;
+pub mod lambda_signature;
 pub mod lexer;
 pub mod parser;
 pub mod rename_idents;
 pub mod replace_idents;
+pub mod serializable_lambda;
 pub mod span;
 pub mod span_visitor;
 pub mod tokens;
+pub mod tracing;
 pub mod tryfrom_visitor;
 pub mod visitors;
 
@@ -74,39 +74,26 @@ impl TryFrom<SyntaxObject> for SteelVal {
     fn try_from(e: SyntaxObject) -> std::result::Result<Self, Self::Error> {
         let span = e.span;
         match e.ty {
-            OpenParen => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, "(".to_string()).with_span(span))
-            }
-            CloseParen => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, ")".to_string()).with_span(span))
-            }
+            OpenParen => Err(SteelErr::new(ErrorKind::UnexpectedToken, "(").with_span(span)),
+            CloseParen => Err(SteelErr::new(ErrorKind::UnexpectedToken, ")").with_span(span)),
             CharacterLiteral(x) => Ok(CharV(x)),
             BooleanLiteral(x) => Ok(BoolV(x)),
+            // Keywords evaluate to themselves
+            Keyword(x) => Ok(KeywordV(x.into())),
             Identifier(x) => Ok(SymbolV(x.into())),
             NumberLiteral(x) => Ok(NumV(x)),
             IntegerLiteral(x) => Ok(IntV(x)),
             StringLiteral(x) => Ok(StringV(x.into())),
-            QuoteTick => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, "'".to_string()).with_span(span))
-            }
-            Unquote => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, ",".to_string()).with_span(span))
-            }
-            QuasiQuote => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, "`".to_string()).with_span(span))
-            }
-            UnquoteSplice => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, ",@".to_string()).with_span(span))
-            }
-            Error => {
-                Err(SteelErr::new(ErrorKind::UnexpectedToken, "error".to_string()).with_span(span))
-            }
-            Comment => Err(
-                SteelErr::new(ErrorKind::UnexpectedToken, "comment".to_string()).with_span(span),
-            ),
-            Hash => Err(SteelErr::new(ErrorKind::UnexpectedToken, "#".to_string()).with_span(span)),
+            QuoteTick => Err(SteelErr::new(ErrorKind::UnexpectedToken, "'").with_span(span)),
+            Unquote => Err(SteelErr::new(ErrorKind::UnexpectedToken, ",").with_span(span)),
+            QuasiQuote => Err(SteelErr::new(ErrorKind::UnexpectedToken, "`").with_span(span)),
+            UnquoteSplice => Err(SteelErr::new(ErrorKind::UnexpectedToken, ",@").with_span(span)),
+            Error => Err(SteelErr::new(ErrorKind::UnexpectedToken, "error").with_span(span)),
+            Comment => Err(SteelErr::new(ErrorKind::UnexpectedToken, "comment").with_span(span)),
+            Hash => Err(SteelErr::new(ErrorKind::UnexpectedToken, "#").with_span(span)),
             If => Ok(SymbolV("if".into())),
             Define => Ok(SymbolV("define".into())),
+            DefinePure => Ok(SymbolV("define/pure".into())),
             Let => Ok(SymbolV("let".into())),
             Transduce => Ok(SymbolV("transduce".into())),
             Execute => Ok(SymbolV("execute".into())),
@@ -194,8 +181,6 @@ pub type Result<T> = result::Result<T, ParseError>;
 
 fn tokentype_error_to_parse_error(t: &Token) -> ParseError {
     if let TokenType::Error = t.ty {
-        println!("Found an error: {}", t);
-
         if t.source.starts_with('\"') {
             ParseError::IncompleteString(t.source.to_string(), t.span, None)
         } else {
@@ -496,6 +481,91 @@ impl<'a> Iterator for Parser<'a> {
     }
 }
 
+/// Source as it was written, for tools like the formatter that have to give it back. Nothing is
+/// lowered, and comments and blank lines are kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RawSyntax<'a> {
+    Atom(&'a str),
+    /// A list, along with the bracket it was opened with
+    List(char, Vec<RawSyntax<'a>>),
+    /// An expression with `'`, `` ` ``, `,`, `,@` or `#` in front of it
+    Prefixed(&'a str, Box<RawSyntax<'a>>),
+    /// A comment, and whether it follows something else on the same line
+    Comment(&'a str, bool),
+    /// One or more empty lines between expressions
+    BlankLine,
+}
+
+// A list that is still being read, and the prefixes waiting for the next expression in it
+struct RawFrame<'a> {
+    open: char,
+    items: Vec<RawSyntax<'a>>,
+    prefixes: Vec<&'a str>,
+}
+
+impl<'a> RawFrame<'a> {
+    fn new(open: char) -> Self {
+        RawFrame {
+            open,
+            items: Vec::new(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, mut item: RawSyntax<'a>) {
+        while let Some(prefix) = self.prefixes.pop() {
+            item = RawSyntax::Prefixed(prefix, Box::new(item));
+        }
+        self.items.push(item);
+    }
+}
+
+/// Reads `input` into [`RawSyntax`], keeping everything needed to write it back out
+pub fn parse_with_trivia(input: &str) -> Result<Vec<RawSyntax<'_>>> {
+    let mut stack: Vec<RawFrame> = Vec::new();
+    let mut current = RawFrame::new('(');
+    let mut last_end = None;
+
+    for token in TokenStream::new(input, false) {
+        let newlines = match last_end {
+            Some(end) => input[end..token.span.start()].matches('\n').count(),
+            None => 1,
+        };
+        if newlines > 1 && !current.items.is_empty() && token.ty != CloseParen {
+            current.items.push(RawSyntax::BlankLine);
+        }
+        last_end = Some(token.span.end());
+
+        match token.ty {
+            TokenType::Error => return Err(tokentype_error_to_parse_error(&token)),
+            TokenType::Comment => current
+                .items
+                .push(RawSyntax::Comment(token.source.trim_end(), newlines == 0)),
+            QuoteTick | QuasiQuote | Unquote | UnquoteSplice | Hash => {
+                current.prefixes.push(token.source)
+            }
+            OpenParen => {
+                let open = token.source.chars().next().unwrap_or('(');
+                stack.push(std::mem::replace(&mut current, RawFrame::new(open)));
+            }
+            CloseParen => match stack.pop() {
+                Some(mut parent) if current.prefixes.is_empty() => {
+                    let list = std::mem::take(&mut current.items);
+                    parent.push(RawSyntax::List(current.open, list));
+                    current = parent;
+                }
+                _ => return Err(ParseError::Unexpected(CloseParen, None)),
+            },
+            _ => current.push(RawSyntax::Atom(token.source)),
+        }
+    }
+
+    if !stack.is_empty() || !current.prefixes.is_empty() {
+        return Err(ParseError::UnexpectedEOF(None));
+    }
+    Ok(current.items)
+}
+
 #[cfg(test)]
 mod parser_tests {
     // use super::TokenType::*;
@@ -524,6 +594,35 @@ mod parser_tests {
         assert!(a.is_err());
     }
 
+    #[test]
+    fn trivia_is_kept_when_asked_for() {
+        let source = "; top\n(define [x] 'y) ; after\n\n\n`(,@z)";
+        assert_eq!(
+            parse_with_trivia(source).unwrap(),
+            vec![
+                RawSyntax::Comment("; top", false),
+                RawSyntax::List(
+                    '(',
+                    vec![
+                        RawSyntax::Atom("define"),
+                        RawSyntax::List('[', vec![RawSyntax::Atom("x")]),
+                        RawSyntax::Prefixed("'", Box::new(RawSyntax::Atom("y"))),
+                    ]
+                ),
+                RawSyntax::Comment("; after", true),
+                RawSyntax::BlankLine,
+                RawSyntax::Prefixed(
+                    "`",
+                    Box::new(RawSyntax::List(
+                        '(',
+                        vec![RawSyntax::Prefixed(",@", Box::new(RawSyntax::Atom("z")))]
+                    ))
+                ),
+            ]
+        );
+        assert!(parse_with_trivia("(a 'b").is_err());
+    }
+
     #[test]
     fn parse_unicode() {
         assert_parse(
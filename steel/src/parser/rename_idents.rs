@@ -9,6 +9,8 @@ pub struct RenameIdentifiersVisitor<'a> {
     introduced_identifiers: HashSet<String>,
     pattern_variables: &'a [&'a str],
     syntax: &'a [String],
+    // Quoted identifiers are data, only pattern variables get substituted into them
+    in_quote: bool,
 }
 
 impl<'a> RenameIdentifiersVisitor<'a> {
@@ -17,9 +19,18 @@ impl<'a> RenameIdentifiersVisitor<'a> {
             introduced_identifiers: HashSet::new(),
             pattern_variables,
             syntax,
+            in_quote: false,
         }
     }
 
+    /// The identifiers bound by the template itself, as they're named after renaming
+    pub fn introduced(&self) -> HashSet<String> {
+        self.introduced_identifiers
+            .iter()
+            .map(|x| "##".to_string() + x)
+            .collect()
+    }
+
     pub fn add(&mut self, ident: &str) {
         self.introduced_identifiers.insert(ident.to_string());
     }
@@ -29,6 +40,9 @@ impl<'a> RenameIdentifiersVisitor<'a> {
     }
 
     pub fn rename_identifiers(&mut self, expr: &mut ExprKind) {
+        // Find every binder first, so references that come before their definition are
+        // renamed along with it
+        self.visit(&mut expr.clone());
         self.visit(expr);
     }
 }
@@ -43,6 +57,11 @@ impl<'a> VisitorMutRef for RenameIdentifiersVisitor<'a> {
     }
 
     fn visit_define(&mut self, define: &mut super::ast::Define) -> Self::Output {
+        if self.in_quote {
+            self.visit(&mut define.name);
+            return self.visit(&mut define.body);
+        }
+
         if let ExprKind::Atom(a) = &mut define.name {
             if let SyntaxObject {
                 ty: TokenType::Identifier(ref s),
@@ -66,7 +85,11 @@ impl<'a> VisitorMutRef for RenameIdentifiersVisitor<'a> {
         &mut self,
         lambda_function: &mut super::ast::LambdaFunction,
     ) -> Self::Output {
-        // todo!()
+        if self.in_quote {
+            lambda_function.args.iter_mut().for_each(|x| self.visit(x));
+            return self.visit(&mut lambda_function.body);
+        }
+
         for arg in &mut lambda_function.args {
             if let ExprKind::Atom(a) = arg {
                 if let SyntaxObject {
@@ -99,6 +122,9 @@ impl<'a> VisitorMutRef for RenameIdentifiersVisitor<'a> {
 
     fn visit_apply(&mut self, apply: &mut super::ast::Apply) -> Self::Output {
         self.visit(&mut apply.func);
+        for arg in &mut apply.args {
+            self.visit(arg);
+        }
         self.visit(&mut apply.list);
     }
 
@@ -128,7 +154,9 @@ impl<'a> VisitorMutRef for RenameIdentifiersVisitor<'a> {
     }
 
     fn visit_quote(&mut self, quote: &mut super::ast::Quote) -> Self::Output {
+        let in_quote = std::mem::replace(&mut self.in_quote, true);
         self.visit(&mut quote.expr);
+        self.in_quote = in_quote;
     }
 
     fn visit_struct(&mut self, _s: &mut super::ast::Struct) -> Self::Output {
@@ -155,6 +183,10 @@ impl<'a> VisitorMutRef for RenameIdentifiersVisitor<'a> {
                 return;
             }
 
+            if self.in_quote && !self.pattern_variables.contains(&s.as_str()) {
+                return;
+            }
+
             if self.is_gensym(&s) {
                 // println!("Found gen sym: {}", &s);
                 // println!("Syntax forms: {:?}", self.syntax);
@@ -9,22 +9,26 @@ use crate::rvals::Result;
 
 use super::ast::Atom;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 const DATUM_TO_SYNTAX: &str = "datum->syntax";
 const SYNTAX_CONST_IF: &str = "syntax-const-if";
 
+/// Substitutes the pattern variables of a macro template. With a scope, the identifiers the
+/// template introduces itself are renamed into that scope.
 pub fn replace_identifiers(
     expr: ExprKind,
     bindings: &HashMap<String, ExprKind>,
+    scope: Option<(&HashSet<String>, usize)>,
     span: Span,
 ) -> Result<ExprKind> {
     let rewrite_spans = RewriteSpan::new(span).visit(expr)?;
-    ReplaceExpressions::new(bindings).visit(rewrite_spans)
+    ReplaceExpressions { bindings, scope }.visit(rewrite_spans)
 }
 
 pub struct ReplaceExpressions<'a> {
     bindings: &'a HashMap<String, ExprKind>,
+    scope: Option<(&'a HashSet<String>, usize)>,
     // span: Span,
 }
 
@@ -42,7 +46,10 @@ fn check_ellipses(expr: &ExprKind) -> bool {
 
 impl<'a> ReplaceExpressions<'a> {
     pub fn new(bindings: &'a HashMap<String, ExprKind>) -> Self {
-        ReplaceExpressions { bindings }
+        ReplaceExpressions {
+            bindings,
+            scope: None,
+        }
     }
 
     fn expand_atom(&self, expr: Atom) -> ExprKind {
@@ -53,6 +60,14 @@ impl<'a> ReplaceExpressions<'a> {
             if let Some(body) = self.bindings.get(s) {
                 return body.clone();
             }
+
+            if let Some((introduced, scope)) = self.scope {
+                if introduced.contains(s) {
+                    let mut expr = expr.clone();
+                    expr.syn.ty = TokenType::Identifier(format!("{}#{}", s, scope));
+                    return ExprKind::Atom(expr);
+                }
+            }
         }
 
         ExprKind::Atom(expr)
@@ -70,7 +85,7 @@ impl<'a> ReplaceExpressions<'a> {
 
             let rest = self.bindings
                 .get(var)
-                .ok_or_else(throw!(BadSyntax => format!("macro expansion failed at finding the variable when expanding ellipses: {}", var)))?;
+                .ok_or_else(throw!(BadSyntax => "macro expansion failed at finding the variable when expanding ellipses: {}", var))?;
 
             let list_of_exprs = rest.list_or_else(
                 throw!(BadSyntax => "macro expansion failed, expected list of expressions"),
@@ -244,6 +259,12 @@ impl<'a> ConsumingVisitor for ReplaceExpressions<'a> {
 
     fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
         apply.func = self.visit(apply.func)?;
+        apply.args = self.expand_ellipses(apply.args)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
         apply.list = self.visit(apply.list)?;
         Ok(ExprKind::Apply(apply))
     }
@@ -401,6 +422,11 @@ impl ConsumingVisitor for RewriteSpan {
 
     fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
         apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
         apply.list = self.visit(apply.list)?;
         apply.location.set_span(self.span);
         Ok(ExprKind::Apply(apply))
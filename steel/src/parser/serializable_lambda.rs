@@ -0,0 +1,386 @@
+use crate::parser::ast::{Atom, Begin, ExprKind, List};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::ConsumingVisitor;
+
+use crate::rvals::{Result, SteelVal};
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// The builtin each `serializable-lambda` turns into a call to, which attaches the serialized
+/// form to the closure
+pub(crate) const SERIALIZABLE_CLOSURE: &str = "#%serializable-closure";
+
+/// Expands `(serializable-lambda (args ...) body ...)` into a regular lambda, along with the
+/// source needed to rebuild it somewhere else.
+///
+/// ```scheme
+/// (define (make-adder n)
+///   (serializable-lambda (x) (+ x n)))
+///
+/// (serialize-closure (make-adder 10))
+/// ;; => "((lambda (n) (lambda (x) (+ x n))) 10)"
+/// ```
+///
+/// Local variables the lambda refers to are captured by value when the closure is created,
+/// and have to hold serializable data - numbers, strings, characters, booleans, symbols and
+/// lists of those. Anything else is referred to by name, and gets resolved against the
+/// globals of whichever engine rebuilds the closure.
+pub fn expand_serializable_lambdas(exprs: Vec<ExprKind>) -> Result<Vec<ExprKind>> {
+    let mut expander = SerializableLambdaExpander { locals: Vec::new() };
+    exprs.into_iter().map(|e| expander.visit(e)).collect()
+}
+
+struct SerializableLambdaExpander {
+    // Variables bound by the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+fn identifier(name: &str, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::Identifier(name.to_string()),
+        span,
+    )))
+}
+
+fn identifier_name(expr: &ExprKind) -> Option<&str> {
+    if let ExprKind::Atom(Atom {
+        syn: SyntaxObject {
+            ty: TokenType::Identifier(s),
+            ..
+        },
+    }) = expr
+    {
+        Some(s)
+    } else {
+        None
+    }
+}
+
+// Every symbol mentioned anywhere in the expression, found by quoting it
+fn collect_symbols(value: &SteelVal, symbols: &mut HashSet<String>) {
+    match value {
+        SteelVal::SymbolV(s) => {
+            symbols.insert(s.unwrap());
+        }
+        SteelVal::VectorV(v) => v.iter().for_each(|x| collect_symbols(x, symbols)),
+        SteelVal::Pair(_) => {
+            SteelVal::iter(value.clone()).for_each(|x| collect_symbols(&x, symbols))
+        }
+        _ => {}
+    }
+}
+
+impl SerializableLambdaExpander {
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    fn expand(&mut self, l: List) -> Result<ExprKind> {
+        let mut args = l.args.into_iter();
+
+        let span = match args.next() {
+            Some(ExprKind::Atom(a)) => a.syn.span,
+            _ => unreachable!(),
+        };
+
+        // Parse it the same way as a lambda, so malformed ones get the usual errors
+        let lambda = ExprKind::try_from(
+            std::iter::once(ExprKind::Atom(Atom::new(SyntaxObject::new(
+                TokenType::Lambda,
+                span,
+            ))))
+            .chain(args)
+            .collect::<Vec<_>>(),
+        )?;
+
+        let parameters: HashSet<&str> = match &lambda {
+            ExprKind::LambdaFunction(l) => l.args.iter().filter_map(identifier_name).collect(),
+            _ => unreachable!(),
+        };
+
+        let mut symbols = HashSet::new();
+        collect_symbols(&SteelVal::try_from(lambda.clone())?, &mut symbols);
+
+        let mut captured: Vec<String> = symbols
+            .into_iter()
+            .filter(|x| !parameters.contains(x.as_str()) && self.is_local(x))
+            .collect();
+        captured.sort();
+
+        let source = lambda.to_string();
+        let lambda = self.visit(lambda)?;
+
+        let mut call = vec![
+            identifier(SERIALIZABLE_CLOSURE, span),
+            lambda,
+            ExprKind::Atom(Atom::new(SyntaxObject::new(
+                TokenType::StringLiteral(source),
+                span,
+            ))),
+        ];
+
+        if !captured.is_empty() {
+            call.push(ExprKind::List(List::new(
+                std::iter::once(identifier("list", span))
+                    .chain(captured.iter().map(|x| {
+                        ExprKind::Atom(Atom::new(SyntaxObject::new(
+                            TokenType::StringLiteral(x.clone()),
+                            span,
+                        )))
+                    }))
+                    .collect(),
+            )));
+            call.push(ExprKind::List(List::new(
+                std::iter::once(identifier("list", span))
+                    .chain(captured.iter().map(|x| identifier(x, span)))
+                    .collect(),
+            )));
+        }
+
+        Ok(ExprKind::List(List::new(call)))
+    }
+}
+
+impl ConsumingVisitor for SerializableLambdaExpander {
+    type Output = Result<ExprKind>;
+
+    fn visit_if(&mut self, mut f: Box<super::ast::If>) -> Self::Output {
+        f.test_expr = self.visit(f.test_expr)?;
+        f.then_expr = self.visit(f.then_expr)?;
+        f.else_expr = self.visit(f.else_expr)?;
+        Ok(ExprKind::If(f))
+    }
+
+    fn visit_define(&mut self, mut define: Box<super::ast::Define>) -> Self::Output {
+        // Defines inside of a function body are local to it
+        if let (Some(scope), Some(name)) = (self.locals.last_mut(), identifier_name(&define.name)) {
+            scope.insert(name.to_string());
+        }
+        define.body = self.visit(define.body)?;
+        Ok(ExprKind::Define(define))
+    }
+
+    fn visit_lambda_function(
+        &mut self,
+        mut lambda_function: Box<super::ast::LambdaFunction>,
+    ) -> Self::Output {
+        self.locals.push(
+            lambda_function
+                .args
+                .iter()
+                .filter_map(identifier_name)
+                .map(|x| x.to_string())
+                .collect(),
+        );
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
+        Ok(ExprKind::LambdaFunction(lambda_function))
+    }
+
+    fn visit_begin(&mut self, mut begin: Begin) -> Self::Output {
+        begin.exprs = begin
+            .exprs
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::Begin(begin))
+    }
+
+    fn visit_return(&mut self, mut r: Box<super::ast::Return>) -> Self::Output {
+        r.expr = self.visit(r.expr)?;
+        Ok(ExprKind::Return(r))
+    }
+
+    fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
+        apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        apply.list = self.visit(apply.list)?;
+        Ok(ExprKind::Apply(apply))
+    }
+
+    fn visit_panic(&mut self, mut p: Box<super::ast::Panic>) -> Self::Output {
+        p.message = self.visit(p.message)?;
+        Ok(ExprKind::Panic(p))
+    }
+
+    fn visit_transduce(&mut self, mut transduce: Box<super::ast::Transduce>) -> Self::Output {
+        transduce.transducer = self.visit(transduce.transducer)?;
+        transduce.func = self.visit(transduce.func)?;
+        transduce.initial_value = self.visit(transduce.initial_value)?;
+        transduce.iterable = self.visit(transduce.iterable)?;
+        Ok(ExprKind::Transduce(transduce))
+    }
+
+    fn visit_read(&mut self, mut read: Box<super::ast::Read>) -> Self::Output {
+        read.expr = self.visit(read.expr)?;
+        Ok(ExprKind::Read(read))
+    }
+
+    fn visit_execute(&mut self, mut execute: Box<super::ast::Execute>) -> Self::Output {
+        execute.transducer = self.visit(execute.transducer)?;
+        execute.collection = self.visit(execute.collection)?;
+        execute.output_type = execute.output_type.map(|x| self.visit(x)).transpose()?;
+        Ok(ExprKind::Execute(execute))
+    }
+
+    fn visit_quote(&mut self, quote: Box<super::ast::Quote>) -> Self::Output {
+        Ok(ExprKind::Quote(quote))
+    }
+
+    fn visit_struct(&mut self, s: Box<super::ast::Struct>) -> Self::Output {
+        Ok(ExprKind::Struct(s))
+    }
+
+    fn visit_macro(&mut self, m: super::ast::Macro) -> Self::Output {
+        Ok(ExprKind::Macro(m))
+    }
+
+    fn visit_eval(&mut self, mut e: Box<super::ast::Eval>) -> Self::Output {
+        e.expr = self.visit(e.expr)?;
+        Ok(ExprKind::Eval(e))
+    }
+
+    fn visit_atom(&mut self, a: Atom) -> Self::Output {
+        Ok(ExprKind::Atom(a))
+    }
+
+    fn visit_list(&mut self, mut l: List) -> Self::Output {
+        if l.first_ident() == Some("serializable-lambda") {
+            return self.expand(l);
+        }
+
+        l.args = l
+            .args
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ExprKind::List(l))
+    }
+
+    fn visit_syntax_rules(&mut self, l: super::ast::SyntaxRules) -> Self::Output {
+        Ok(ExprKind::SyntaxRules(l))
+    }
+
+    fn visit_set(&mut self, mut s: Box<super::ast::Set>) -> Self::Output {
+        s.expr = self.visit(s.expr)?;
+        Ok(ExprKind::Set(s))
+    }
+
+    fn visit_require(&mut self, s: super::ast::Require) -> Self::Output {
+        Ok(ExprKind::Require(s))
+    }
+
+    fn visit_callcc(&mut self, mut cc: Box<super::ast::CallCC>) -> Self::Output {
+        cc.expr = self.visit(cc.expr)?;
+        Ok(ExprKind::CallCC(cc))
+    }
+}
+
+#[cfg(test)]
+mod serializable_lambda_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn serialize(engine: &mut Engine, program: &str) -> String {
+        match engine.run(program).unwrap().pop().unwrap() {
+            SteelVal::StringV(s) => s.unwrap(),
+            other => panic!("expected a string, found {}", other),
+        }
+    }
+
+    #[test]
+    fn closure_without_captures_round_trips() {
+        let mut sender = Engine::new();
+        let serialized = serialize(
+            &mut sender,
+            "(define f (serializable-lambda (x y) (+ x y))) (serialize-closure f)",
+        );
+        assert_eq!(serialized, "(lambda (x y) (+ x y))");
+
+        let mut receiver = Engine::new();
+        let f = receiver.deserialize_closure(&serialized).unwrap();
+        receiver.register_value("f", f);
+        assert_eq!(
+            receiver.run("(f 1 2)").unwrap().pop(),
+            Some(SteelVal::IntV(3))
+        );
+    }
+
+    #[test]
+    fn captured_values_are_serialized() {
+        let mut sender = Engine::new();
+        let program = r#"
+            (define (make-greeter greeting punctuation names)
+              (serializable-lambda (name)
+                (if (member name names)
+                    (string-append greeting (string-append name punctuation))
+                    "who?")))
+            (define greet (make-greeter "hello " "!" (list "alice" "bob")))
+            (greet "alice")
+        "#;
+        assert_eq!(
+            sender.run(program).unwrap().pop(),
+            Some(SteelVal::StringV("hello alice!".into()))
+        );
+
+        let serialized = serialize(&mut sender, "(serialize-closure greet)");
+
+        let mut receiver = Engine::new();
+        let greet = receiver.deserialize_closure(&serialized).unwrap();
+        receiver.register_value("greet", greet);
+        assert_eq!(
+            receiver.run("(greet \"bob\")").unwrap().pop(),
+            Some(SteelVal::StringV("hello bob!".into()))
+        );
+
+        // The rebuilt closure can be sent along again
+        assert_eq!(
+            serialize(&mut receiver, "(serialize-closure greet)"),
+            serialized
+        );
+    }
+
+    #[test]
+    fn globals_are_resolved_by_the_receiver() {
+        let mut sender = Engine::new();
+        let serialized = serialize(
+            &mut sender,
+            "(define (scale x) (* x 2)) (serialize-closure (serializable-lambda (x) (scale x)))",
+        );
+
+        let mut receiver = Engine::new();
+        receiver.run("(define (scale x) (* x 100))").unwrap();
+        let f = receiver.deserialize_closure(&serialized).unwrap();
+        receiver.register_value("f", f);
+        assert_eq!(
+            receiver.run("(f 3)").unwrap().pop(),
+            Some(SteelVal::IntV(300))
+        );
+    }
+
+    #[test]
+    fn capturing_a_function_is_an_error() {
+        let mut engine = Engine::new();
+        let program = r#"
+            (define (compose f g) (serializable-lambda (x) (f (g x))))
+            (compose (lambda (x) x) (lambda (x) x))
+        "#;
+        assert!(engine.run(program).is_err());
+    }
+
+    #[test]
+    fn plain_closures_are_not_serializable() {
+        let mut engine = Engine::new();
+        assert!(engine.run("(serialize-closure (lambda (x) x))").is_err());
+    }
+}
@@ -7,10 +7,6 @@ use logos::{Lexer, Logos};
 use crate::parser::span::Span;
 
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
-
-use std::convert::TryFrom;
-use std::num::ParseIntError;
 
 fn gen_bool(lex: &mut Lexer<TokenType>) -> Option<bool> {
     let slice = lex.slice();
@@ -21,84 +17,138 @@ fn gen_bool(lex: &mut Lexer<TokenType>) -> Option<bool> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum DecodeHexError {
-    OddLength,
-    ParseInt(ParseIntError),
+// Names are matched regardless of case, so both `#\space` and `#\SPACE` work
+fn named_char(name: &str) -> Option<char> {
+    match name.to_lowercase().as_str() {
+        "space" => Some(' '),
+        "newline" | "linefeed" => Some('\n'),
+        "tab" => Some('\t'),
+        "return" => Some('\r'),
+        "null" | "nul" => Some('\0'),
+        "alarm" => Some('\u{7}'),
+        "backspace" => Some('\u{8}'),
+        "delete" => Some('\u{7f}'),
+        "escape" => Some('\u{1b}'),
+        _ => None,
+    }
 }
 
-impl From<ParseIntError> for DecodeHexError {
-    fn from(e: ParseIntError) -> Self {
-        DecodeHexError::ParseInt(e)
-    }
+// `#\x41`, `#\u41` and `#\u{41}` are all `A`
+fn hex_char(name: &str) -> Option<char> {
+    let digits = name.strip_prefix('x').or_else(|| name.strip_prefix('u'))?;
+    let digits = digits
+        .strip_prefix('{')
+        .and_then(|x| x.strip_suffix('}'))
+        .unwrap_or(digits);
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(std::char::from_u32)
 }
 
-impl fmt::Display for DecodeHexError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            DecodeHexError::OddLength => "input string has an odd number of bytes".fmt(f),
-            DecodeHexError::ParseInt(e) => e.fmt(f),
-        }
+fn parse_char(lex: &mut Lexer<TokenType>) -> Option<char> {
+    let name = lex.slice().strip_prefix("#\\")?;
+
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        // An extra backslash before the hex escape is also accepted, i.e. `#\\u{41}`
+        _ => named_char(name).or_else(|| hex_char(name.strip_prefix('\\').unwrap_or(name))),
     }
 }
 
-impl std::error::Error for DecodeHexError {}
+// Digits can be separated with underscores, e.g. 1_000_000
+fn without_underscores(slice: &str) -> String {
+    slice.chars().filter(|c| *c != '_').collect()
+}
 
-pub fn decode_hex(s: &str) -> Result<Vec<u8>, DecodeHexError> {
-    if s.len() % 2 != 0 {
-        Err(DecodeHexError::OddLength)
-    } else {
-        (0..s.len())
-            .step_by(2)
-            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.into()))
-            .collect()
+fn split_sign(slice: &str) -> (bool, &str) {
+    match slice.as_bytes().first() {
+        Some(b'-') => (true, &slice[1..]),
+        Some(b'+') => (false, &slice[1..]),
+        _ => (false, slice),
     }
 }
 
-fn parse_unicode_str(slice: &str) -> Option<char> {
-    if slice.starts_with("#\\\\u") && slice.contains('{') && slice.contains('}') {
-        let rest = slice
-            .trim_start_matches("#\\\\u")
-            .trim_start_matches('{')
-            .trim_end_matches('}')
-            .to_lowercase();
-
-        let rest = match rest.len() {
-            0 => panic!("length of 0"),
-            1 => "000".to_string() + &rest,
-            2 => "00".to_string() + &rest,
-            3 => "0".to_string() + &rest,
-            _ => return None,
-        };
+// The sign is parsed along with the digits rather than negated afterwards, since the
+// smallest integer has no positive counterpart
+fn signed_integer(negative: bool, digits: &str, radix: u32) -> Option<isize> {
+    let sign = if negative { "-" } else { "" };
+    isize::from_str_radix(&format!("{}{}", sign, without_underscores(digits)), radix).ok()
+}
 
-        let decoded: u8 = decode_hex(&rest).ok()?.into_iter().sum();
-        let uinitial: u32 = decoded.into();
-        let result = char::try_from(uinitial).ok();
+fn parse_radix(digits: &str, radix: u32) -> Option<isize> {
+    let (negative, digits) = split_sign(digits);
+    signed_integer(negative, digits, radix)
+}
 
-        println!("{:?}", result);
-        return result;
+fn parse_integer(lex: &mut Lexer<TokenType>) -> Option<isize> {
+    let (negative, digits) = split_sign(lex.slice());
+    if let Some(hex) = digits.strip_prefix("0x") {
+        signed_integer(negative, hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        signed_integer(negative, binary, 2)
     } else {
-        None
+        signed_integer(negative, digits, 10)
     }
 }
 
-fn parse_char(lex: &mut Lexer<TokenType>) -> Option<char> {
-    let slice = lex.slice();
+fn parse_float(lex: &mut Lexer<TokenType>) -> Option<f64> {
+    without_underscores(lex.slice()).parse().ok()
+}
 
-    match slice {
-        "#\\SPACE" => Some(' '),
-        character if character.starts_with("#\\") => {
-            let parsed_unicode = parse_unicode_str(character);
+// Splits the `#x`, `#b`, `#o`, `#d`, `#e` and `#i` prefixes off of a number, in either order,
+// returning the radix, the exactness if one was given, and the rest of the number
+fn number_prefixes(slice: &str) -> Option<(u32, Option<bool>, &str)> {
+    let mut radix = None;
+    let mut exact = None;
+    let mut rest = slice;
+
+    while let Some(prefix) = rest.strip_prefix('#') {
+        let mut chars = prefix.chars();
+        match chars.next()?.to_ascii_lowercase() {
+            'x' if radix.is_none() => radix = Some(16),
+            'b' if radix.is_none() => radix = Some(2),
+            'o' if radix.is_none() => radix = Some(8),
+            'd' if radix.is_none() => radix = Some(10),
+            'e' if exact.is_none() => exact = Some(true),
+            'i' if exact.is_none() => exact = Some(false),
+            _ => return None,
+        }
+        rest = chars.as_str();
+    }
+
+    Some((radix.unwrap_or(10), exact, rest))
+}
 
-            if parsed_unicode.is_some() {
-                return parsed_unicode;
+// Exact numbers are integers here, so an exact decimal is only allowed if it has no fractional part
+fn parse_exact(lex: &mut Lexer<TokenType>) -> Option<isize> {
+    let (radix, _, rest) = number_prefixes(lex.slice())?;
+    if radix != 10 {
+        return parse_radix(rest, radix);
+    }
+
+    match without_underscores(rest).parse::<isize>() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            let value: f64 = without_underscores(rest).parse().ok()?;
+            if value.fract() == 0.0 && value.abs() < isize::MAX as f64 {
+                Some(value as isize)
+            } else {
+                None
             }
-            char::from_str(character.trim_start_matches("#\\")).ok()
         }
-        _ => None,
     }
 }
 
+fn parse_inexact(lex: &mut Lexer<TokenType>) -> Option<f64> {
+    let (radix, _, rest) = number_prefixes(lex.slice())?;
+    if radix != 10 {
+        return parse_radix(rest, radix).map(|x| x as f64);
+    }
+
+    without_underscores(rest).parse().ok()
+}
+
 fn parse_str(lex: &mut Lexer<TokenType>) -> Option<String> {
     let slice = lex.slice();
     // println!("Slice: {:?}", slice);
@@ -140,6 +190,8 @@ pub enum TokenType {
     If,
     #[regex("(define)|(defn)")]
     Define,
+    #[token("define/pure")]
+    DefinePure,
     #[token("let")]
     Let,
     #[token("transduce")]
@@ -209,6 +261,10 @@ pub enum TokenType {
     #[token("#f", gen_bool)]
     BooleanLiteral(bool),
 
+    /// A keyword like `#:name`, which evaluates to itself. Only the name is kept.
+    #[regex(r#"#:[^\s\(\)\[\]'`,;"]+"#, |lex| lex.slice()[2..].to_string())]
+    Keyword(String),
+
     // /// An identifier literal.
     // #[regex(r#"(?&ident)"#)]
     // Identifier(String),
@@ -218,16 +274,37 @@ pub enum TokenType {
 
     // #[token("inf")]
     // #[token("NaN")]
-    #[regex(r#"[+-]?[0-9][0-9_]*\.[0-9][0-9_]*([eE][+-]?[0-9][0-9_]*)?"#, |lex| lex.slice().parse())] // "
+    #[regex(
+        r#"[+-]?[0-9][0-9_]*\.[0-9][0-9_]*([eE][+-]?[0-9][0-9_]*)?"#,
+        parse_float
+    )] // "
     #[regex(
         r#"[+-]?0x[0-9a-fA-F][0-9a-fA-F_]*\.[0-9a-fA-F][0-9a-fA-F_]*([pP][+-]?[0-9][0-9_]?)?"#, |lex| lex.slice().parse()
     )]
-    #[regex(r#"[+-]?[0-9][0-9_]*\."#, |lex| lex.slice().parse())] // "
+    #[regex(r#"[+-]?[0-9][0-9_]*\.([eE][+-]?[0-9][0-9_]*)?"#, parse_float)] // "
+    #[regex(r#"[+-]?[0-9][0-9_]*[eE][+-]?[0-9][0-9_]*"#, parse_float)] // "
+    #[regex(r#"[+-]?\.[0-9][0-9_]*([eE][+-]?[0-9][0-9_]*)?"#, priority = 3, callback = parse_float)] // "
+    #[token("+inf.0", |_| f64::INFINITY)]
+    #[token("-inf.0", |_| f64::NEG_INFINITY)]
+    #[token("+nan.0", |_| f64::NAN)]
+    #[token("-nan.0", |_| f64::NAN)]
+    // Inexact numbers, with an optional radix
+    #[regex(
+        r#"(#[iI](#[xXbBoOdD])?|#[xXbBoOdD]#[iI])[+-]?[0-9a-fA-F_.]+([eE][+-]?[0-9]+)?"#,
+        parse_inexact
+    )] // "
+    #[regex(r#"#[dD][+-]?[0-9_]*\.[0-9_]*([eE][+-]?[0-9]+)?"#, parse_inexact)] // "
     NumberLiteral(f64),
 
-    #[regex("[+-]?[0-9][0-9_]*", priority = 2, callback = |lex| lex.slice().parse())] // "
-    #[regex("[+-]?0b[0-1][0-1_]*", |lex| lex.slice().parse())] // "
-    #[regex("[+-]?0x[0-9a-fA-F][0-9a-fA-F_]*", |lex| lex.slice().parse())] // "
+    #[regex("[+-]?[0-9][0-9_]*", priority = 2, callback = parse_integer)] // "
+    #[regex("[+-]?0b[0-1][0-1_]*", parse_integer)] // "
+    #[regex("[+-]?0x[0-9a-fA-F][0-9a-fA-F_]*", parse_integer)] // "
+    // Exact numbers, with an optional radix
+    #[regex(
+        r#"(#[eE](#[xXbBoOdD])?|#[xXbBoOdD]#[eE])[+-]?[0-9a-fA-F_.]+([eE][+-]?[0-9]+)?"#,
+        parse_exact
+    )] // "
+    #[regex(r#"#[xXbBoOdD][+-]?[0-9a-fA-F_]+"#, parse_exact)] // "
     IntegerLiteral(isize),
 
     // #[regex(r#"b?"(\\.|[^\\"])*""#, parse_str)] // "
@@ -246,9 +323,13 @@ fn character_special_display(c: char, f: &mut fmt::Formatter) -> fmt::Result {
         '\t' => write!(f, "#\\TAB"),
         '\n' => write!(f, "#\\NEWLINE"),
         '\r' => write!(f, "#\\RETURN"),
-        c if c.is_control() || c.is_whitespace() => {
-            write!(f, "#\\{}", c.escape_unicode().to_string())
-        }
+        '\0' => write!(f, "#\\null"),
+        '\u{7}' => write!(f, "#\\alarm"),
+        '\u{8}' => write!(f, "#\\backspace"),
+        '\u{7f}' => write!(f, "#\\delete"),
+        '\u{1b}' => write!(f, "#\\escape"),
+        // Written so that reading it back produces the same character
+        c if c.is_control() || c.is_whitespace() => write!(f, "#\\x{:x}", c as u32),
         // '\"' => write!(f, "#\\DOUBLE-QUOTE"),
         // '\'' => write!(f, "#\\QUOTE"),
         // '\\' => write!(f, "#\\BACKSLASH"),
@@ -263,7 +344,12 @@ impl fmt::Display for TokenType {
             CloseParen => write!(f, "("),
             CharacterLiteral(x) => character_special_display(*x, f),
             BooleanLiteral(x) => write!(f, "#{}", x),
+            Keyword(x) => write!(f, "#:{}", x),
             Identifier(x) => write!(f, "{}", x),
+            NumberLiteral(x) if x.is_nan() => write!(f, "+nan.0"),
+            NumberLiteral(x) if x.is_infinite() => {
+                write!(f, "{}inf.0", if *x > 0.0 { "+" } else { "-" })
+            }
             NumberLiteral(x) => write!(f, "{:?}", x),
             IntegerLiteral(x) => write!(f, "{}", x),
             StringLiteral(x) => write!(f, "\"{}\"", x),
@@ -276,6 +362,7 @@ impl fmt::Display for TokenType {
             Hash => write!(f, "#"),
             If => write!(f, "if"),
             Define => write!(f, "define"),
+            DefinePure => write!(f, "define/pure"),
             Let => write!(f, "let"),
             Transduce => write!(f, "transduce"),
             Execute => write!(f, "execute"),
@@ -0,0 +1,349 @@
+use crate::parser::ast::{Atom, Begin, Define, ExprKind, List, Set};
+use crate::parser::parser::SyntaxObject;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::parser::visitors::ConsumingVisitor;
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// The feature that turns tracing on, see
+/// [`Engine::set_tracing`](crate::steel_vm::engine::Engine::set_tracing)
+pub(crate) const TRACING: &str = "tracing";
+
+/// The builtin traced functions are wrapped with
+pub(crate) const TRACE: &str = "%trace";
+
+/// Lowers `trace-define` and `trace`, which log the calls to a function along with its
+/// arguments, what it returned and how long it took.
+///
+/// ```scheme
+/// (trace-define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+/// ;; => (define fib (%trace "fib" (lambda (n) ...)))
+///
+/// (trace f g)
+/// ;; => (begin (set! f (%trace "f" f)) (set! g (%trace "g" g)))
+/// ```
+///
+/// When tracing is turned off `trace-define` is just `define` and `trace` does nothing, so
+/// the traces can be left in without slowing anything down.
+///
+/// Programs that bind one of these names themselves, either at the top level, in an earlier
+/// run (`already_defined`) or in a function, keep their own.
+pub fn lower_tracing(
+    exprs: Vec<ExprKind>,
+    enabled: bool,
+    already_defined: &[&str],
+) -> Result<Vec<ExprKind>> {
+    let globals = exprs
+        .iter()
+        .filter_map(|x| match x {
+            ExprKind::Define(d) => d.name.atom_identifier_or_else(|| ()).ok(),
+            _ => None,
+        })
+        .chain(already_defined.iter().copied())
+        .map(|x| x.to_string())
+        .collect();
+
+    let mut lowerer = TraceLowerer {
+        enabled,
+        locals: vec![globals],
+    };
+    exprs.into_iter().map(|e| lowerer.visit(e)).collect()
+}
+
+/// The forms [`lower_tracing`] lowers
+pub const TRACING_FORMS: &[&str] = &["trace-define", "trace"];
+
+struct TraceLowerer {
+    enabled: bool,
+    // Variables bound by the program and the enclosing functions, innermost last
+    locals: Vec<HashSet<String>>,
+}
+
+fn identifier(name: &str, span: Span) -> ExprKind {
+    ExprKind::Atom(Atom::new(SyntaxObject::new(
+        TokenType::Identifier(name.to_string()),
+        span,
+    )))
+}
+
+// (%trace "name" f)
+fn traced(name: &ExprKind, f: ExprKind, span: Span) -> ExprKind {
+    ExprKind::List(List::new(vec![
+        identifier(TRACE, span),
+        ExprKind::Atom(Atom::new(SyntaxObject::new(
+            TokenType::StringLiteral(name.to_string()),
+            span,
+        ))),
+        f,
+    ]))
+}
+
+impl TraceLowerer {
+    fn is_bound(&self, name: &str) -> bool {
+        self.locals.iter().any(|scope| scope.contains(name))
+    }
+
+    fn lower_trace_define(&mut self, l: List) -> Result<ExprKind> {
+        let mut args = l.args.into_iter();
+        let span = match args.next() {
+            Some(ExprKind::Atom(a)) => a.syn.span,
+            _ => unreachable!(),
+        };
+
+        // Parse it the same way as a define, so malformed ones get the usual errors
+        let define = ExprKind::try_from(
+            std::iter::once(ExprKind::Atom(Atom::new(SyntaxObject::new(
+                TokenType::Define,
+                span,
+            ))))
+            .chain(args)
+            .collect::<Vec<_>>(),
+        )?;
+
+        let define = match self.visit(define)? {
+            ExprKind::Define(d) if self.enabled => d,
+            other => return Ok(other),
+        };
+
+        let Define {
+            name,
+            body,
+            location,
+        } = *define;
+        let body = traced(&name, body, span);
+        Ok(ExprKind::Define(Box::new(Define::new(
+            name, body, location,
+        ))))
+    }
+
+    fn lower_trace(&mut self, l: List) -> Result<ExprKind> {
+        let span = match l.args.first() {
+            Some(ExprKind::Atom(a)) => a.syn.span,
+            _ => unreachable!(),
+        };
+
+        let mut sets = Vec::new();
+        for name in &l.args[1..] {
+            if !matches!(
+                name,
+                ExprKind::Atom(Atom {
+                    syn: SyntaxObject {
+                        ty: TokenType::Identifier(_),
+                        ..
+                    }
+                })
+            ) {
+                stop!(BadSyntax => "trace expects the names of the functions to trace"; span);
+            }
+
+            if self.enabled {
+                sets.push(ExprKind::Set(Box::new(Set::new(
+                    name.clone(),
+                    traced(name, name.clone(), span),
+                    SyntaxObject::new(TokenType::Set, span),
+                ))));
+            }
+        }
+
+        if sets.is_empty() {
+            return Ok(identifier("void", span));
+        }
+
+        Ok(ExprKind::Begin(Begin::new(
+            sets,
+            SyntaxObject::new(TokenType::Begin, span),
+        )))
+    }
+}
+
+impl ConsumingVisitor for TraceLowerer {
+    type Output = Result<ExprKind>;
+
+    fn visit_if(&mut self, mut f: Box<super::ast::If>) -> Self::Output {
+        f.test_expr = self.visit(f.test_expr)?;
+        f.then_expr = self.visit(f.then_expr)?;
+        f.else_expr = self.visit(f.else_expr)?;
+        Ok(ExprKind::If(f))
+    }
+
+    fn visit_define(&mut self, mut define: Box<super::ast::Define>) -> Self::Output {
+        define.body = self.visit(define.body)?;
+        Ok(ExprKind::Define(define))
+    }
+
+    fn visit_lambda_function(
+        &mut self,
+        mut lambda_function: Box<super::ast::LambdaFunction>,
+    ) -> Self::Output {
+        self.locals.push(lambda_function.locals());
+        let body = self.visit(lambda_function.body);
+        self.locals.pop();
+
+        lambda_function.body = body?;
+        Ok(ExprKind::LambdaFunction(lambda_function))
+    }
+
+    fn visit_begin(&mut self, mut begin: Begin) -> Self::Output {
+        begin.exprs = begin
+            .exprs
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ExprKind::Begin(begin))
+    }
+
+    fn visit_return(&mut self, mut r: Box<super::ast::Return>) -> Self::Output {
+        r.expr = self.visit(r.expr)?;
+        Ok(ExprKind::Return(r))
+    }
+
+    fn visit_apply(&mut self, mut apply: Box<super::ast::Apply>) -> Self::Output {
+        apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
+        apply.list = self.visit(apply.list)?;
+        Ok(ExprKind::Apply(apply))
+    }
+
+    fn visit_panic(&mut self, mut p: Box<super::ast::Panic>) -> Self::Output {
+        p.message = self.visit(p.message)?;
+        Ok(ExprKind::Panic(p))
+    }
+
+    fn visit_transduce(&mut self, mut transduce: Box<super::ast::Transduce>) -> Self::Output {
+        transduce.transducer = self.visit(transduce.transducer)?;
+        transduce.func = self.visit(transduce.func)?;
+        transduce.initial_value = self.visit(transduce.initial_value)?;
+        transduce.iterable = self.visit(transduce.iterable)?;
+        Ok(ExprKind::Transduce(transduce))
+    }
+
+    fn visit_read(&mut self, mut read: Box<super::ast::Read>) -> Self::Output {
+        read.expr = self.visit(read.expr)?;
+        Ok(ExprKind::Read(read))
+    }
+
+    fn visit_execute(&mut self, mut execute: Box<super::ast::Execute>) -> Self::Output {
+        execute.transducer = self.visit(execute.transducer)?;
+        execute.collection = self.visit(execute.collection)?;
+        execute.output_type = execute.output_type.map(|x| self.visit(x)).transpose()?;
+        Ok(ExprKind::Execute(execute))
+    }
+
+    fn visit_quote(&mut self, quote: Box<super::ast::Quote>) -> Self::Output {
+        Ok(ExprKind::Quote(quote))
+    }
+
+    fn visit_struct(&mut self, s: Box<super::ast::Struct>) -> Self::Output {
+        Ok(ExprKind::Struct(s))
+    }
+
+    fn visit_macro(&mut self, m: super::ast::Macro) -> Self::Output {
+        Ok(ExprKind::Macro(m))
+    }
+
+    fn visit_eval(&mut self, mut e: Box<super::ast::Eval>) -> Self::Output {
+        e.expr = self.visit(e.expr)?;
+        Ok(ExprKind::Eval(e))
+    }
+
+    fn visit_atom(&mut self, a: Atom) -> Self::Output {
+        Ok(ExprKind::Atom(a))
+    }
+
+    fn visit_list(&mut self, mut l: List) -> Self::Output {
+        match l.first_ident() {
+            Some("trace-define") if !self.is_bound("trace-define") => {
+                return self.lower_trace_define(l)
+            }
+            Some("trace") if !self.is_bound("trace") => return self.lower_trace(l),
+            _ => {}
+        }
+
+        l.args = l
+            .args
+            .into_iter()
+            .map(|e| self.visit(e))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ExprKind::List(l))
+    }
+
+    fn visit_syntax_rules(&mut self, l: super::ast::SyntaxRules) -> Self::Output {
+        Ok(ExprKind::SyntaxRules(l))
+    }
+
+    fn visit_set(&mut self, mut s: Box<super::ast::Set>) -> Self::Output {
+        s.expr = self.visit(s.expr)?;
+        Ok(ExprKind::Set(s))
+    }
+
+    fn visit_require(&mut self, s: super::ast::Require) -> Self::Output {
+        Ok(ExprKind::Require(s))
+    }
+
+    fn visit_callcc(&mut self, mut cc: Box<super::ast::CallCC>) -> Self::Output {
+        cc.expr = self.visit(cc.expr)?;
+        Ok(ExprKind::CallCC(cc))
+    }
+}
+
+#[cfg(test)]
+mod tracing_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    const FIB: &str = r#"
+        (trace-define (fib n)
+          (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+        (define (double x) (* x 2))
+        (trace double)
+        (double (fib 5))
+    "#;
+
+    #[test]
+    fn traced_functions_still_return_their_values() {
+        let mut vm = Engine::new();
+        vm.set_tracing(true);
+        assert_eq!(vm.run(FIB).unwrap().pop(), Some(SteelVal::IntV(10)));
+    }
+
+    #[test]
+    fn tracing_compiles_away_when_disabled() {
+        let mut vm = Engine::new();
+        assert_eq!(vm.run(FIB).unwrap().pop(), Some(SteelVal::IntV(10)));
+
+        let expanded = vm.emit_fully_expanded_ast_to_string(FIB).unwrap();
+        assert!(!expanded.contains("%trace"));
+    }
+
+    #[test]
+    fn trace_expects_names() {
+        let mut vm = Engine::new();
+        assert!(vm.run("(trace (lambda (x) x))").is_err());
+    }
+
+    #[test]
+    fn programs_can_bind_trace_themselves() {
+        let mut vm = Engine::new();
+        let results = vm
+            .run(
+                r#"
+                (define (trace x) (* x 10))
+                (define (f trace) (trace 1))
+                (+ (trace 2) (f (lambda (x) (+ x 1))))
+                "#,
+            )
+            .unwrap();
+        assert_eq!(results.last(), Some(&SteelVal::IntV(22)));
+        assert_eq!(vm.run("(trace 3)").unwrap(), vec![SteelVal::IntV(30)]);
+    }
+}
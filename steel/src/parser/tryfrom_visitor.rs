@@ -73,11 +73,11 @@ impl ConsumingVisitorRef for TryFromExprKindForSteelVal {
     }
 
     fn visit_apply(&self, apply: Box<super::ast::Apply>) -> Self::Output {
-        let expr = [
-            SteelVal::try_from(apply.location)?,
-            self.visit(apply.func)?,
-            self.visit(apply.list)?,
-        ];
+        let mut expr = vec![SteelVal::try_from(apply.location)?, self.visit(apply.func)?];
+        for arg in apply.args {
+            expr.push(self.visit(arg)?);
+        }
+        expr.push(self.visit(apply.list)?);
         ListOperations::built_in_list_func_flat(&expr)
     }
 
@@ -1,35 +1,60 @@
+mod arrays;
+mod chars;
 mod contracts;
 mod control;
+mod ffi;
 mod fs;
 mod hashmaps;
 mod hashsets;
 mod io;
 mod lists;
+mod math;
+mod memoize;
 mod meta_ops;
 mod nums;
 mod ports;
+mod semver;
 mod streams;
 mod strings;
 mod symbols;
+mod sync;
+mod threads;
 mod transducers;
 mod utils;
+mod uuid;
 mod vectors;
+mod weak;
 
+pub use arrays::{Array, ArrayOperations};
+pub use chars::CharOperations;
 pub use contracts::ContractOperations;
 pub use control::ControlOperations;
+pub use ffi::{FfiBuffer, FfiOperations};
 pub use fs::FsFunctions;
 pub use hashmaps::HashMapOperations;
 pub use hashsets::HashSetOperations;
 pub use io::IoFunctions;
 pub use lists::ListOperations;
+pub use math::MathOperations;
+pub use memoize::MemoizeOperations;
+pub(crate) use memoize::MemoizedFunctions;
 pub use meta_ops::MetaOperations;
+pub(crate) use meta_ops::{block_on_future, serialize_datum};
 pub use nums::NumOperations;
+pub(crate) use nums::{float_modulo, int_modulo};
 pub use ports::PortOperations;
+pub use semver::SemverOperations;
 pub use streams::StreamOperations;
 pub use strings::StringOperations;
 pub use symbols::SymbolOperations;
+pub use sync::{Channel, SyncOperations};
+pub use threads::ThreadOperations;
+pub(crate) use threads::ThreadScopes;
 pub use transducers::TransducerOperations;
+pub use uuid::UuidOperations;
 pub use vectors::VectorOperations;
+pub(crate) use weak::Finalizers;
+pub use weak::WeakOperations;
 
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{FunctionSignature, SteelVal};
@@ -50,7 +75,7 @@ macro_rules! try_from_impl {
                 fn try_from(value: SteelVal) -> result::Result<Self, Self::Error> {
                     match value {
                         SteelVal::$type(x) => Ok(x.clone() as $body),
-                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number".to_string())),
+                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number")),
                     }
                 }
             }
@@ -60,7 +85,7 @@ macro_rules! try_from_impl {
                 fn try_from(value: &SteelVal) -> result::Result<Self, Self::Error> {
                     match value {
                         SteelVal::$type(x) => Ok(x.clone() as $body),
-                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number".to_string())),
+                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number")),
                     }
                 }
             }
@@ -69,7 +94,7 @@ macro_rules! try_from_impl {
                 fn from_steelval(value: SteelVal) -> result::Result<Self, SteelErr> {
                     match value {
                         SteelVal::$type(x) => Ok(x.clone() as $body),
-                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number".to_string())),
+                        _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected number")),
                     }
                 }
             }
@@ -133,7 +158,7 @@ impl FromSteelVal for char {
         } else {
             Err(SteelErr::new(
                 ErrorKind::ConversionError,
-                "Expected character".to_string(),
+                "Expected character",
             ))
         }
     }
@@ -172,11 +197,20 @@ impl<T: FromSteelVal> FromSteelVal for Option<T> {
 // TODO make intosteelval return a result type
 // This allows errors to propagate
 
-impl<T: IntoSteelVal, E: std::fmt::Debug> IntoSteelVal for Result<T, E> {
+impl<T: IntoSteelVal, E: std::fmt::Debug + 'static> IntoSteelVal for Result<T, E> {
     fn into_steelval(self) -> Result<SteelVal, SteelErr> {
         match self {
             Ok(s) => s.into_steelval(),
-            Err(e) => crate::stop!(Generic => format!("{:?}", e)),
+            // A `SteelErr` keeps its kind and span, anything else only has its message
+            Err(e) => {
+                let mut e = Some(e);
+                if let Some(e) =
+                    (&mut e as &mut dyn std::any::Any).downcast_mut::<Option<SteelErr>>()
+                {
+                    return Err(e.take().unwrap());
+                }
+                crate::stop!(Generic => format!("{:?}", e.unwrap()))
+            }
         }
     }
 }
@@ -214,10 +248,7 @@ impl TryFrom<SteelVal> for String {
         match value {
             SteelVal::StringV(ref x) => Ok(x.unwrap()),
             SteelVal::SymbolV(ref x) => Ok(x.unwrap()),
-            _ => Err(SteelErr::new(
-                ErrorKind::ConversionError,
-                "Expected string".to_string(),
-            )),
+            _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected string")),
         }
     }
 }
@@ -239,10 +270,7 @@ impl FromSteelVal for String {
         if let SteelVal::StringV(s) = val {
             Ok(s.unwrap())
         } else {
-            Err(SteelErr::new(
-                ErrorKind::ConversionError,
-                "Expected string".to_string(),
-            ))
+            Err(SteelErr::new(ErrorKind::ConversionError, "Expected string"))
         }
     }
 }
@@ -253,10 +281,7 @@ impl TryFrom<&SteelVal> for String {
         match value {
             SteelVal::StringV(x) => Ok(x.unwrap()),
             SteelVal::SymbolV(x) => Ok(x.unwrap()),
-            _ => Err(SteelErr::new(
-                ErrorKind::ConversionError,
-                "Expected string".to_string(),
-            )),
+            _ => Err(SteelErr::new(ErrorKind::ConversionError, "Expected string")),
         }
     }
 }
@@ -291,6 +316,16 @@ impl IntoSteelVal for bool {
     }
 }
 
+impl FromSteelVal for bool {
+    fn from_steelval(val: SteelVal) -> Result<Self, SteelErr> {
+        if let SteelVal::BoolV(b) = val {
+            Ok(b)
+        } else {
+            crate::stop!(ConversionError => "expected a boolean, found: {}", val)
+        }
+    }
+}
+
 impl From<Vector<SteelVal>> for SteelVal {
     fn from(val: Vector<SteelVal>) -> SteelVal {
         SteelVal::VectorV(Gc::new(val))
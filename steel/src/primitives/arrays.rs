@@ -0,0 +1,638 @@
+use crate::primitives::ListOperations;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Custom, IntoSteelVal, Result, SteelVal};
+use crate::steel_vm::thread::SteelThread;
+use crate::{stop, throw};
+
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Shape {
+    Vector(usize),
+    Matrix(usize, usize),
+}
+
+impl Shape {
+    fn len(self) -> usize {
+        match self {
+            Shape::Vector(n) => n,
+            Shape::Matrix(rows, cols) => rows * cols,
+        }
+    }
+}
+
+/// A vector or matrix of floats, with the elements stored contiguously in row-major order.
+/// Arrays are never changed in place, so the elements are shared between copies.
+#[derive(Clone, PartialEq)]
+pub struct Array {
+    shape: Shape,
+    data: Rc<Vec<f64>>,
+}
+
+impl Custom for Array {
+    fn heap_bytes(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f64>()
+    }
+}
+
+impl fmt::Debug for Array {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |f: &mut fmt::Formatter<'_>, values: &[f64]| -> fmt::Result {
+            write!(f, "(")?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{:?}", value)?;
+            }
+            write!(f, ")")
+        };
+
+        // Custom values are already shown inside of #<...>
+        write!(f, "array ")?;
+        match self.shape {
+            Shape::Vector(_) => row(f, &self.data)?,
+            Shape::Matrix(_, cols) => {
+                write!(f, "(")?;
+                for (i, values) in self.data.chunks(cols.max(1)).enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    row(f, values)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Array {
+    fn new(shape: Shape, data: Vec<f64>) -> Self {
+        debug_assert_eq!(shape.len(), data.len());
+        Array {
+            shape,
+            data: Rc::new(data),
+        }
+    }
+
+    pub fn vector(data: Vec<f64>) -> Self {
+        Array::new(Shape::Vector(data.len()), data)
+    }
+
+    /// Makes a matrix out of `data` laid out row by row, `None` if it doesn't hold
+    /// `rows * cols` elements
+    pub fn matrix(rows: usize, cols: usize, data: Vec<f64>) -> Option<Self> {
+        if rows.checked_mul(cols) == Some(data.len()) {
+            Some(Array::new(Shape::Matrix(rows, cols), data))
+        } else {
+            None
+        }
+    }
+
+    /// The length of a vector, or the number of rows and columns of a matrix
+    pub fn shape(&self) -> Vec<usize> {
+        match self.shape {
+            Shape::Vector(n) => vec![n],
+            Shape::Matrix(rows, cols) => vec![rows, cols],
+        }
+    }
+
+    /// The elements, row by row
+    pub fn as_slice(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub(crate) fn from_value(value: &SteelVal) -> Option<Self> {
+        if let SteelVal::Custom(c) = value {
+            return c.borrow().as_any().downcast_ref::<Array>().cloned();
+        }
+        None
+    }
+
+    // The elements of a vector, or the rows of a matrix as lists
+    pub(crate) fn rows(&self) -> Result<Vec<SteelVal>> {
+        match self.shape {
+            Shape::Vector(_) => Ok(self.data.iter().map(|x| SteelVal::NumV(*x)).collect()),
+            Shape::Matrix(rows, cols) => (0..rows)
+                .map(|i| self.data[i * cols..(i + 1) * cols].to_vec().into_steelval())
+                .collect(),
+        }
+    }
+
+    fn with_data(&self, data: Vec<f64>) -> Self {
+        Array::new(self.shape, data)
+    }
+}
+
+fn array(value: &SteelVal, name: &str) -> Result<Array> {
+    match Array::from_value(value) {
+        Some(array) => Ok(array),
+        None => stop!(TypeMismatch => "{} expects an array, found: {}", name, value),
+    }
+}
+
+fn number(value: &SteelVal, name: &str) -> Result<f64> {
+    match value {
+        SteelVal::IntV(n) => Ok(*n as f64),
+        SteelVal::NumV(n) => Ok(*n),
+        _ => stop!(TypeMismatch => "{} expects a number, found: {}", name, value),
+    }
+}
+
+fn index(value: &SteelVal, len: usize, name: &str) -> Result<usize> {
+    match value {
+        SteelVal::IntV(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+        SteelVal::IntV(i) => {
+            stop!(ContractViolation => "{}: index {} is out of range for length {}", name, i, len)
+        }
+        _ => stop!(TypeMismatch => "{} expects an integer index, found: {}", name, value),
+    }
+}
+
+fn numbers(values: &[SteelVal], name: &str) -> Result<Vec<f64>> {
+    values.iter().map(|x| number(x, name)).collect()
+}
+
+// Each side of an elementwise operation is either an array or a number, which is used
+// for every element
+fn elementwise(args: &[SteelVal], name: &str, op: fn(f64, f64) -> f64) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "{} takes two arguments", name);
+    }
+
+    let result = match (Array::from_value(&args[0]), Array::from_value(&args[1])) {
+        (Some(l), Some(r)) => {
+            if l.shape != r.shape {
+                stop!(ContractViolation => "{}: the arrays have different shapes, {:?} and {:?}", name, l.shape(), r.shape());
+            }
+            let data = l.data.iter().zip(r.data.iter()).map(|(l, r)| op(*l, *r));
+            l.with_data(data.collect())
+        }
+        (Some(l), None) => {
+            let r = number(&args[1], name)?;
+            l.with_data(l.data.iter().map(|l| op(*l, r)).collect())
+        }
+        (None, Some(r)) => {
+            let l = number(&args[0], name)?;
+            r.with_data(r.data.iter().map(|r| op(l, *r)).collect())
+        }
+        (None, None) => stop!(TypeMismatch => "{} expects at least one array", name),
+    };
+
+    result.into_steelval()
+}
+
+fn matmul(l: &Array, r: &Array) -> Result<SteelVal> {
+    let mismatch = || {
+        SteelErr::new(
+            ErrorKind::ContractViolation,
+            format!(
+                "matmul: can't multiply arrays of shapes {:?} and {:?}",
+                l.shape(),
+                r.shape()
+            ),
+        )
+    };
+
+    // Vectors are treated as rows on the left and as columns on the right
+    let (rows, inner, cols) = match (l.shape, r.shape) {
+        (Shape::Vector(n), Shape::Vector(m)) if n == m => (1, n, 1),
+        (Shape::Vector(n), Shape::Matrix(m, cols)) if n == m => (1, n, cols),
+        (Shape::Matrix(rows, n), Shape::Vector(m)) if n == m => (rows, n, 1),
+        (Shape::Matrix(rows, n), Shape::Matrix(m, cols)) if n == m => (rows, n, cols),
+        _ => return Err(mismatch()),
+    };
+
+    let len = rows.checked_mul(cols).ok_or_else(throw!(
+        ContractViolation => "matmul: a {} by {} result is too large", rows, cols
+    ))?;
+
+    // Going through `r` a row at a time keeps the inner loop on contiguous memory
+    let mut data = vec![0.0; len];
+    for i in 0..rows {
+        let out = &mut data[i * cols..(i + 1) * cols];
+        for k in 0..inner {
+            let scale = l.data[i * inner + k];
+            for (out, r) in out.iter_mut().zip(&r.data[k * cols..(k + 1) * cols]) {
+                *out += scale * r;
+            }
+        }
+    }
+
+    match (l.shape, r.shape) {
+        (Shape::Vector(_), Shape::Vector(_)) => Ok(SteelVal::NumV(data[0])),
+        (Shape::Matrix(..), Shape::Matrix(..)) => {
+            Array::new(Shape::Matrix(rows, cols), data).into_steelval()
+        }
+        _ => Array::vector(data).into_steelval(),
+    }
+}
+
+pub struct ArrayOperations {}
+impl ArrayOperations {
+    /// (array 1 2 3) makes a vector
+    pub fn array() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            Array::vector(numbers(args, "array")?).into_steelval()
+        })
+    }
+
+    /// A list of numbers becomes a vector, and a list of lists of numbers becomes a matrix
+    pub fn list_to_array() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "list->array takes one argument");
+            }
+
+            let items = match &args[0] {
+                SteelVal::Pair(_) => ListOperations::collect_into_vec(&args[0])?,
+                SteelVal::VectorV(v) if v.is_empty() => Vec::new(),
+                _ => stop!(TypeMismatch => "list->array expects a list, found: {}", &args[0]),
+            };
+
+            if !matches!(items.first(), Some(SteelVal::Pair(_))) {
+                return Array::vector(numbers(&items, "list->array")?).into_steelval();
+            }
+
+            let mut data = Vec::new();
+            let mut cols = None;
+            for row in &items {
+                let row = match row {
+                    SteelVal::Pair(_) => {
+                        numbers(&ListOperations::collect_into_vec(row)?, "list->array")?
+                    }
+                    _ => {
+                        stop!(TypeMismatch => "list->array expects every row to be a list, found: {}", row)
+                    }
+                };
+                if *cols.get_or_insert(row.len()) != row.len() {
+                    stop!(ContractViolation => "list->array: the rows have different lengths");
+                }
+                data.extend(row);
+            }
+
+            Array::new(Shape::Matrix(items.len(), cols.unwrap()), data).into_steelval()
+        })
+    }
+
+    /// (make-array n fill) makes a vector, (make-array rows cols fill) makes a matrix
+    pub fn make_array() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let size = |value: &SteelVal| match value {
+                SteelVal::IntV(n) if *n >= 0 => Ok(*n as usize),
+                _ => {
+                    stop!(TypeMismatch => "make-array expects a non-negative size, found: {}", value)
+                }
+            };
+
+            let shape = match args {
+                [n, _] => Shape::Vector(size(n)?),
+                [rows, cols, _] => Shape::Matrix(size(rows)?, size(cols)?),
+                _ => stop!(ArityMismatch => "make-array takes two or three arguments"),
+            };
+
+            let len = match shape {
+                Shape::Vector(n) => n,
+                Shape::Matrix(rows, cols) => rows.checked_mul(cols).ok_or_else(throw!(
+                    ContractViolation => "make-array: a {} by {} matrix is too large", rows, cols
+                ))?,
+            };
+
+            let fill = number(args.last().unwrap(), "make-array")?;
+            Array::new(shape, vec![fill; len]).into_steelval()
+        })
+    }
+
+    /// A vector becomes a list of numbers, and a matrix a list of rows
+    pub fn array_to_list() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "array->list takes one argument");
+            }
+
+            let rows = array(&args[0], "array->list")?.rows()?;
+            ListOperations::built_in_list_func_flat(&rows)
+        })
+    }
+
+    pub fn array_shape() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "array-shape takes one argument");
+            }
+
+            array(&args[0], "array-shape")?.shape().into_steelval()
+        })
+    }
+
+    /// (array-ref vector i) or (array-ref matrix row col)
+    pub fn array_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "array-ref takes two or three arguments");
+            }
+
+            let array = array(&args[0], "array-ref")?;
+            let i = match (array.shape, &args[1..]) {
+                (Shape::Vector(n), [i]) => index(i, n, "array-ref")?,
+                (Shape::Matrix(rows, cols), [i, j]) => {
+                    index(i, rows, "array-ref")? * cols + index(j, cols, "array-ref")?
+                }
+                (Shape::Vector(_), _) => {
+                    stop!(ArityMismatch => "array-ref takes one index into a vector")
+                }
+                (Shape::Matrix(..), _) => {
+                    stop!(ArityMismatch => "array-ref takes a row and a column index into a matrix")
+                }
+            };
+
+            Ok(SteelVal::NumV(array.data[i]))
+        })
+    }
+
+    /// (array-slice array start end) takes the elements of a vector, or the rows of a matrix,
+    /// from `start` up to but not including `end`
+    pub fn array_slice() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "array-slice takes three arguments");
+            }
+
+            let array = array(&args[0], "array-slice")?;
+            let (len, width) = match array.shape {
+                Shape::Vector(n) => (n, 1),
+                Shape::Matrix(rows, cols) => (rows, cols),
+            };
+
+            let start = index(&args[1], len + 1, "array-slice")?;
+            let end = index(&args[2], len + 1, "array-slice")?;
+            if start > end {
+                stop!(ContractViolation => "array-slice: the start {} is after the end {}", start, end);
+            }
+
+            let data = array.data[start * width..end * width].to_vec();
+            let shape = match array.shape {
+                Shape::Vector(_) => Shape::Vector(end - start),
+                Shape::Matrix(_, cols) => Shape::Matrix(end - start, cols),
+            };
+            Array::new(shape, data).into_steelval()
+        })
+    }
+
+    /// Vectors are left as they are
+    pub fn array_transpose() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "array-transpose takes one argument");
+            }
+
+            let array = array(&args[0], "array-transpose")?;
+            match array.shape {
+                Shape::Vector(_) => array.into_steelval(),
+                Shape::Matrix(rows, cols) => {
+                    let mut data = Vec::with_capacity(array.data.len());
+                    for j in 0..cols {
+                        data.extend((0..rows).map(|i| array.data[i * cols + j]));
+                    }
+                    Array::new(Shape::Matrix(cols, rows), data).into_steelval()
+                }
+            }
+        })
+    }
+
+    pub fn array_sum() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "array-sum takes one argument");
+            }
+
+            Ok(SteelVal::NumV(
+                array(&args[0], "array-sum")?.data.iter().sum(),
+            ))
+        })
+    }
+
+    pub fn add() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| elementwise(args, "array+", |l, r| l + r))
+    }
+
+    pub fn subtract() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| elementwise(args, "array-", |l, r| l - r))
+    }
+
+    pub fn multiply() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| elementwise(args, "array*", |l, r| l * r))
+    }
+
+    pub fn divide() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| elementwise(args, "array/", |l, r| l / r))
+    }
+
+    /// (array-map f array), `f` has to return a number for every element
+    pub fn array_map() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 2 {
+                    stop!(ArityMismatch => "array-map takes two arguments");
+                }
+
+                let array = array(&args[1], "array-map")?;
+                let mut data = Vec::with_capacity(array.data.len());
+                for value in array.data.iter() {
+                    let result = thread.call_function(&args[0], vec![SteelVal::NumV(*value)])?;
+                    data.push(number(&result, "array-map")?);
+                }
+
+                array.with_data(data).into_steelval()
+            },
+        ))
+    }
+
+    /// Multiplies two matrices, a matrix and a vector, or takes the dot product of two vectors
+    pub fn matmul() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "matmul takes two arguments");
+            }
+
+            matmul(&array(&args[0], "matmul")?, &array(&args[1], "matmul")?)
+        })
+    }
+}
+
+#[cfg(feature = "ndarray")]
+mod ndarray_conversions {
+    use super::*;
+    use crate::rvals::FromSteelVal;
+    use ndarray::{Array1, Array2};
+
+    impl IntoSteelVal for Array1<f64> {
+        fn into_steelval(self) -> Result<SteelVal> {
+            Array::vector(self.to_vec()).into_steelval()
+        }
+    }
+
+    impl FromSteelVal for Array1<f64> {
+        fn from_steelval(val: SteelVal) -> Result<Self> {
+            match Array::from_value(&val) {
+                Some(array) if matches!(array.shape, Shape::Vector(_)) => {
+                    Ok(Array1::from(array.data.to_vec()))
+                }
+                _ => Vec::<f64>::from_steelval(val).map(Array1::from),
+            }
+        }
+    }
+
+    impl IntoSteelVal for Array2<f64> {
+        fn into_steelval(self) -> Result<SteelVal> {
+            let (rows, cols) = self.dim();
+            Array::new(Shape::Matrix(rows, cols), self.iter().copied().collect()).into_steelval()
+        }
+    }
+
+    impl FromSteelVal for Array2<f64> {
+        fn from_steelval(val: SteelVal) -> Result<Self> {
+            match Array::from_value(&val) {
+                Some(Array {
+                    shape: Shape::Matrix(rows, cols),
+                    data,
+                }) => Ok(Array2::from_shape_vec((rows, cols), data.to_vec()).unwrap()),
+                _ => stop!(ConversionError => "expected a matrix, found: {}", val),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod array_tests {
+    use super::*;
+    use crate::rvals::FromSteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    fn last(vm: &mut Engine, program: &str) -> String {
+        vm.run(program).unwrap().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn arrays_are_built_from_lists_and_back() {
+        let mut vm = Engine::new();
+        vm.run("(define m (list->array (list (list 1 2 3) (list 4 5 6))))")
+            .unwrap();
+
+        assert_eq!(last(&mut vm, "(array-shape m)"), "'(2 3)");
+        assert_eq!(last(&mut vm, "(array-ref m 1 2)"), "6.0");
+        assert_eq!(last(&mut vm, "m"), "#<array ((1.0 2.0 3.0) (4.0 5.0 6.0))>");
+        assert_eq!(
+            last(&mut vm, "(array->list (array-transpose m))"),
+            "'((1.0 4.0) (2.0 5.0) (3.0 6.0))"
+        );
+        assert_eq!(
+            last(&mut vm, "(array->list (array-slice (array 1 2 3 4) 1 3))"),
+            "'(2.0 3.0)"
+        );
+        assert_eq!(last(&mut vm, "(array-shape (array-slice m 1 2))"), "'(1 3)");
+
+        let err = vm.run("(array-ref m 2 0)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+        let err = vm
+            .run("(list->array (list (list 1 2) (list 3)))")
+            .unwrap_err();
+        assert!(err.to_string().contains("different lengths"));
+
+        let err = vm.run("(make-array 4294967296 4294967296 0)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+        assert!(err.to_string().contains("too large"));
+    }
+
+    #[test]
+    fn elementwise_operations_broadcast_numbers() {
+        let mut vm = Engine::new();
+        vm.run("(define v (array 1 2 3))").unwrap();
+
+        assert_eq!(
+            last(&mut vm, "(array->list (array+ v v))"),
+            "'(2.0 4.0 6.0)"
+        );
+        assert_eq!(
+            last(&mut vm, "(array->list (array- 10 v))"),
+            "'(9.0 8.0 7.0)"
+        );
+        assert_eq!(
+            last(&mut vm, "(array->list (array-map (lambda (x) (* x x)) v))"),
+            "'(1.0 4.0 9.0)"
+        );
+        assert_eq!(last(&mut vm, "(array-sum (array* v 2))"), "12.0");
+
+        let err = vm.run("(array+ v (array 1 2))").unwrap_err();
+        assert!(err.to_string().contains("different shapes"));
+    }
+
+    #[test]
+    fn matrices_are_multiplied() {
+        let mut vm = Engine::new();
+        vm.run(
+            r#"
+            (define a (list->array (list (list 1 2) (list 3 4) (list 5 6))))
+            (define b (list->array (list (list 1 0 2) (list 0 1 3))))
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            last(&mut vm, "(array->list (matmul a b))"),
+            "'((1.0 2.0 8.0) (3.0 4.0 18.0) (5.0 6.0 28.0))"
+        );
+        assert_eq!(
+            last(&mut vm, "(array->list (matmul a (array 1 1)))"),
+            "'(3.0 7.0 11.0)"
+        );
+        assert_eq!(
+            last(&mut vm, "(matmul (array 1 2 3) (array 4 5 6))"),
+            "32.0"
+        );
+
+        let err = vm.run("(matmul a a)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+    }
+
+    #[test]
+    fn arrays_convert_to_vectors_of_floats() {
+        let mut vm = Engine::new();
+        let output = vm.run("(array 1 2 3)").unwrap();
+        assert_eq!(
+            Vec::<f64>::from_steelval(output[0].clone()).unwrap(),
+            vec![1.0, 2.0, 3.0]
+        );
+
+        let output = vm
+            .run("(list->array (list (list 1 2) (list 3 4)))")
+            .unwrap();
+        assert_eq!(
+            Vec::<Vec<f64>>::from_steelval(output[0].clone()).unwrap(),
+            vec![vec![1.0, 2.0], vec![3.0, 4.0]]
+        );
+
+        let array = Array::from_value(&output[0]).unwrap();
+        assert_eq!(array.shape(), vec![2, 2]);
+        assert_eq!(array.as_slice(), &[1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn arrays_convert_to_ndarray() {
+        use ndarray::{arr1, arr2, Array1, Array2};
+
+        let matrix = arr2(&[[1.0, 2.0], [3.0, 4.0]]);
+        let value = matrix.clone().into_steelval().unwrap();
+        assert_eq!(Array2::<f64>::from_steelval(value).unwrap(), matrix);
+
+        let mut vm = Engine::new();
+        let output = vm.run("(array 1 2 3)").unwrap();
+        assert_eq!(
+            Array1::<f64>::from_steelval(output[0].clone()).unwrap(),
+            arr1(&[1.0, 2.0, 3.0])
+        );
+    }
+}
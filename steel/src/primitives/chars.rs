@@ -0,0 +1,291 @@
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+use crate::stop;
+
+use std::convert::TryFrom;
+
+fn char_arg(name: &str, arg: &SteelVal) -> Result<char> {
+    if let SteelVal::CharV(c) = arg {
+        Ok(*c)
+    } else {
+        stop!(TypeMismatch => "{} expected a character, found {}", name, arg)
+    }
+}
+
+fn single_char_arg(name: &str, args: &[SteelVal]) -> Result<char> {
+    if args.len() == 1 {
+        char_arg(name, &args[0])
+    } else {
+        stop!(ArityMismatch => "{} takes one argument", name)
+    }
+}
+
+// Upper and lower casing can produce more than one character, in which case the character is left as is
+fn single_char(mut chars: impl Iterator<Item = char>, original: char) -> char {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => original,
+    }
+}
+
+fn compare_chars(
+    name: &str,
+    args: &[SteelVal],
+    fold_case: bool,
+    check: fn(char, char) -> bool,
+) -> Result<SteelVal> {
+    if args.is_empty() {
+        stop!(ArityMismatch => "{} expected at least one argument", name);
+    }
+
+    let chars = args
+        .iter()
+        .map(|x| {
+            let c = char_arg(name, x)?;
+            Ok(if fold_case {
+                single_char(c.to_lowercase(), c)
+            } else {
+                c
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(SteelVal::BoolV(chars.windows(2).all(|x| check(x[0], x[1]))))
+}
+
+macro_rules! char_comparison {
+    ($name:expr, $fold_case:expr, $check:expr) => {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            compare_chars($name, args, $fold_case, $check)
+        })
+    };
+}
+
+macro_rules! char_predicate {
+    ($name:expr, $check:expr) => {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let c = single_char_arg($name, args)?;
+            Ok(SteelVal::BoolV($check(c)))
+        })
+    };
+}
+
+pub struct CharOperations {}
+impl CharOperations {
+    pub fn char_equals() -> SteelVal {
+        char_comparison!("char=?", false, |a, b| a == b)
+    }
+
+    pub fn char_less_than() -> SteelVal {
+        char_comparison!("char<?", false, |a, b| a < b)
+    }
+
+    pub fn char_greater_than() -> SteelVal {
+        char_comparison!("char>?", false, |a, b| a > b)
+    }
+
+    pub fn char_less_than_equals() -> SteelVal {
+        char_comparison!("char<=?", false, |a, b| a <= b)
+    }
+
+    pub fn char_greater_than_equals() -> SteelVal {
+        char_comparison!("char>=?", false, |a, b| a >= b)
+    }
+
+    pub fn char_ci_equals() -> SteelVal {
+        char_comparison!("char-ci=?", true, |a, b| a == b)
+    }
+
+    pub fn char_ci_less_than() -> SteelVal {
+        char_comparison!("char-ci<?", true, |a, b| a < b)
+    }
+
+    pub fn char_ci_greater_than() -> SteelVal {
+        char_comparison!("char-ci>?", true, |a, b| a > b)
+    }
+
+    pub fn char_ci_less_than_equals() -> SteelVal {
+        char_comparison!("char-ci<=?", true, |a, b| a <= b)
+    }
+
+    pub fn char_ci_greater_than_equals() -> SteelVal {
+        char_comparison!("char-ci>=?", true, |a, b| a >= b)
+    }
+
+    pub fn is_alphabetic() -> SteelVal {
+        char_predicate!("char-alphabetic?", char::is_alphabetic)
+    }
+
+    pub fn is_numeric() -> SteelVal {
+        char_predicate!("char-numeric?", char::is_numeric)
+    }
+
+    pub fn is_whitespace() -> SteelVal {
+        char_predicate!("char-whitespace?", char::is_whitespace)
+    }
+
+    pub fn is_upper_case() -> SteelVal {
+        char_predicate!("char-upper-case?", char::is_uppercase)
+    }
+
+    pub fn is_lower_case() -> SteelVal {
+        char_predicate!("char-lower-case?", char::is_lowercase)
+    }
+
+    pub fn char_upcase() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let c = single_char_arg("char-upcase", args)?;
+            Ok(SteelVal::CharV(single_char(c.to_uppercase(), c)))
+        })
+    }
+
+    pub fn char_downcase() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let c = single_char_arg("char-downcase", args)?;
+            Ok(SteelVal::CharV(single_char(c.to_lowercase(), c)))
+        })
+    }
+
+    /// Returns the value of a decimal digit, or `#false` if the character isn't one
+    pub fn digit_value() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let c = single_char_arg("digit-value", args)?;
+            Ok(c.to_digit(10)
+                .map(|x| SteelVal::IntV(x as isize))
+                .unwrap_or(SteelVal::BoolV(false)))
+        })
+    }
+
+    pub fn char_to_integer() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let c = single_char_arg("char->integer", args)?;
+            Ok(SteelVal::IntV(c as u32 as isize))
+        })
+    }
+
+    pub fn integer_to_char() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "integer->char takes one argument");
+            }
+
+            if let SteelVal::IntV(i) = &args[0] {
+                match u32::try_from(*i).ok().and_then(std::char::from_u32) {
+                    Some(c) => Ok(SteelVal::CharV(c)),
+                    None => {
+                        stop!(Generic => "integer->char: {} is not a valid character", i)
+                    }
+                }
+            } else {
+                stop!(TypeMismatch => "integer->char expected an integer, found {}", &args[0])
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod char_tests {
+    use super::*;
+    use crate::throw;
+
+    use crate::rvals::SteelVal::*;
+
+    fn apply_function(func: SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        func.func_or_else(throw!(BadSyntax => "char tests"))
+            .unwrap()(&args)
+    }
+
+    #[test]
+    fn comparisons_check_every_pair() {
+        let args = vec![CharV('a'), CharV('b'), CharV('c')];
+        assert_eq!(
+            apply_function(CharOperations::char_less_than(), args.clone()).unwrap(),
+            BoolV(true)
+        );
+        assert_eq!(
+            apply_function(CharOperations::char_greater_than(), args).unwrap(),
+            BoolV(false)
+        );
+
+        let args = vec![CharV('a'), CharV('A')];
+        assert_eq!(
+            apply_function(CharOperations::char_equals(), args.clone()).unwrap(),
+            BoolV(false)
+        );
+        assert_eq!(
+            apply_function(CharOperations::char_ci_equals(), args.clone()).unwrap(),
+            BoolV(true)
+        );
+        assert_eq!(
+            apply_function(CharOperations::char_ci_less_than_equals(), args.clone()).unwrap(),
+            BoolV(true)
+        );
+        assert_eq!(
+            apply_function(CharOperations::char_ci_greater_than_equals(), args).unwrap(),
+            BoolV(true)
+        );
+
+        let args = vec![CharV('B'), CharV('a')];
+        assert_eq!(
+            apply_function(CharOperations::char_ci_less_than_equals(), args.clone()).unwrap(),
+            BoolV(false)
+        );
+        assert_eq!(
+            apply_function(CharOperations::char_ci_greater_than_equals(), args).unwrap(),
+            BoolV(true)
+        );
+    }
+
+    #[test]
+    fn comparisons_take_only_chars() {
+        let args = vec![CharV('a'), StringV("a".into())];
+        let res = apply_function(CharOperations::char_equals(), args);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::TypeMismatch);
+
+        let res = apply_function(CharOperations::char_equals(), vec![]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ArityMismatch);
+    }
+
+    #[test]
+    fn case_conversion_keeps_multi_char_results_unchanged() {
+        assert_eq!(
+            apply_function(CharOperations::char_upcase(), vec![CharV('a')]).unwrap(),
+            CharV('A')
+        );
+        // Uppercases to "SS"
+        assert_eq!(
+            apply_function(CharOperations::char_upcase(), vec![CharV('ß')]).unwrap(),
+            CharV('ß')
+        );
+    }
+
+    #[test]
+    fn integer_conversions() {
+        assert_eq!(
+            apply_function(CharOperations::char_to_integer(), vec![CharV('A')]).unwrap(),
+            IntV(65)
+        );
+        assert_eq!(
+            apply_function(CharOperations::integer_to_char(), vec![IntV(955)]).unwrap(),
+            CharV('λ')
+        );
+
+        // Surrogates aren't characters
+        let res = apply_function(CharOperations::integer_to_char(), vec![IntV(0xD800)]);
+        assert!(res.is_err());
+        let res = apply_function(CharOperations::integer_to_char(), vec![IntV(-1)]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn digit_value() {
+        assert_eq!(
+            apply_function(CharOperations::digit_value(), vec![CharV('7')]).unwrap(),
+            IntV(7)
+        );
+        assert_eq!(
+            apply_function(CharOperations::digit_value(), vec![CharV('a')]).unwrap(),
+            BoolV(false)
+        );
+    }
+}
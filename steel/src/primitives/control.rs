@@ -1,7 +1,14 @@
+use crate::gc::Gc;
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{Result, SteelVal};
+use crate::steel_vm::thread::SteelThread;
 use crate::stop;
 
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::time::Instant;
+
 pub struct ControlOperations {}
 impl ControlOperations {
     pub fn error() -> SteelVal {
@@ -21,4 +28,149 @@ impl ControlOperations {
             }
         })
     }
+
+    /// (%push-winder! before after), used by `dynamic-wind` right before it calls its thunk
+    pub fn push_winder() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 2 {
+                    stop!(ArityMismatch => "%push-winder! takes two arguments");
+                }
+                if !args.iter().all(SteelVal::is_function) {
+                    stop!(TypeMismatch => "%push-winder! expects two thunks");
+                }
+
+                thread.push_winder(args[0].clone(), args[1].clone());
+                Ok(SteelVal::Void)
+            },
+        ))
+    }
+
+    /// (%pop-winder!), used by `dynamic-wind` once its thunk returns
+    pub fn pop_winder() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if !args.is_empty() {
+                    stop!(ArityMismatch => "%pop-winder! takes no arguments");
+                }
+
+                thread.pop_winder();
+                Ok(SteelVal::Void)
+            },
+        ))
+    }
+
+    /// (values v ...) returns every argument at once. A single value is returned as is.
+    pub fn values() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() == 1 {
+                return Ok(args[0].clone());
+            }
+            Ok(SteelVal::Values(Gc::new(args.to_vec())))
+        })
+    }
+
+    /// (call-with-values producer consumer) calls `consumer` with the values `producer` returns
+    pub fn call_with_values() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 2 {
+                    stop!(ArityMismatch => "call-with-values takes two arguments");
+                }
+                if !args.iter().all(SteelVal::is_function) {
+                    stop!(TypeMismatch => "call-with-values expects a producer and a consumer");
+                }
+
+                let values = match thread.call_function(&args[0], Vec::new())? {
+                    SteelVal::Values(values) => values.unwrap(),
+                    value => vec![value],
+                };
+                thread.call_function(&args[1], values)
+            },
+        ))
+    }
+
+    /// (#%values->vector thunk), used by `define-values` to hold on to every value `thunk`
+    /// returns, since multiple values can't be bound to a variable as they are
+    pub fn values_to_vector() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 1 || !args[0].is_function() {
+                    stop!(TypeMismatch => "#%values->vector expects a thunk");
+                }
+
+                let values = match thread.call_function(&args[0], Vec::new())? {
+                    SteelVal::Values(values) => values.unwrap(),
+                    value => vec![value],
+                };
+                Ok(SteelVal::VectorV(Gc::new(values.into_iter().collect())))
+            },
+        ))
+    }
+
+    /// (#%values-ref values index count), used by `define-values` to pull out each of the
+    /// `count` values it expects from the vector `#%values->vector` made
+    pub fn values_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let (values, index, count) = match args {
+                [SteelVal::VectorV(values), SteelVal::IntV(index), SteelVal::IntV(count)] => {
+                    (values, *index, *count)
+                }
+                _ => stop!(ArityMismatch => "#%values-ref takes a vector and two integers"),
+            };
+
+            if values.len() as isize != count {
+                stop!(ArityMismatch => "define-values expected {} values, found {}", count, values.len());
+            }
+            match usize::try_from(index)
+                .ok()
+                .and_then(|index| values.get(index))
+            {
+                Some(value) => Ok(value.clone()),
+                None => {
+                    stop!(Generic => "#%values-ref: index {} is out of range for {} values", index, count)
+                }
+            }
+        })
+    }
+
+    /// (%trace name f), which `trace-define` and `trace` expand into when tracing is turned
+    /// on. Returns a function that calls `f`, and logs each call and what it returned to
+    /// stderr, indented by how deeply the traced calls are nested.
+    pub fn trace() -> SteelVal {
+        let depth = Rc::new(Cell::new(0usize));
+
+        SteelVal::BuiltIn(Rc::new(
+            move |_: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                let (name, f) = match args {
+                    [SteelVal::StringV(name), f] if f.is_function() => (name.unwrap(), f.clone()),
+                    _ => stop!(TypeMismatch => "%trace expects a name and a function"),
+                };
+                let depth = Rc::clone(&depth);
+
+                Ok(SteelVal::BuiltIn(Rc::new(
+                    move |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                        let indent = " ".repeat(depth.get());
+                        let call = std::iter::once(name.clone())
+                            .chain(args.iter().map(|x| x.to_string()))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        eprintln!("{}> ({})", indent, call);
+
+                        depth.set(depth.get() + 1);
+                        let start = Instant::now();
+                        let result = thread.call_function(&f, args.to_vec());
+                        let elapsed = start.elapsed();
+                        depth.set(depth.get() - 1);
+
+                        match &result {
+                            Ok(value) => eprintln!("{}< {} ({:?})", indent, value, elapsed),
+                            Err(e) => eprintln!("{}< error: {} ({:?})", indent, e, elapsed),
+                        }
+                        result
+                    },
+                )))
+            },
+        ))
+    }
 }
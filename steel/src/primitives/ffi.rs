@@ -0,0 +1,400 @@
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Custom, IntoSteelVal, Result, SteelVal};
+use crate::stop;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Bytes shared between a script and the host without copying them through lists. The host
+/// keeps a handle on the same bytes it hands to the script, and a slice of a buffer is a view
+/// into the bytes of the buffer it was taken from. Every read and write is checked against the
+/// bounds of the view.
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// # use steel::rvals::IntoSteelVal;
+/// use steel::primitives::FfiBuffer;
+///
+/// let buffer = FfiBuffer::new(vec![1, 2, 3, 4]);
+///
+/// let mut vm = Engine::new();
+/// vm.register_value("buffer", buffer.clone().into_steelval().unwrap());
+/// vm.run("(ffi-buffer-set! (ffi-buffer-slice buffer 2 4) 0 30)").unwrap();
+///
+/// assert_eq!(buffer.to_vec(), vec![1, 2, 30, 4]);
+/// ```
+#[derive(Clone)]
+pub struct FfiBuffer {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    offset: usize,
+    len: usize,
+    writable: bool,
+}
+
+impl Custom for FfiBuffer {
+    fn heap_bytes(&self) -> usize {
+        self.len
+    }
+}
+
+impl fmt::Debug for FfiBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Custom values are already shown inside of #<...>
+        write!(f, "ffi-buffer {}", self.len)
+    }
+}
+
+impl FfiBuffer {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        FfiBuffer {
+            len: bytes.len(),
+            bytes: Rc::new(RefCell::new(bytes)),
+            offset: 0,
+            writable: true,
+        }
+    }
+
+    /// A buffer scripts can read but not write, for handing them input
+    pub fn read_only(bytes: Vec<u8>) -> Self {
+        FfiBuffer {
+            writable: false,
+            ..FfiBuffer::new(bytes)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Calls `f` with the bytes of the buffer, without copying them
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        f(&self.bytes.borrow()[self.offset..self.offset + self.len])
+    }
+
+    /// Calls `f` with the bytes of the buffer to change them in place, if the buffer is writable
+    pub fn with_bytes_mut<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+        if !self.writable {
+            stop!(ContractViolation => "the ffi buffer is read only");
+        }
+        Ok(f(
+            &mut self.bytes.borrow_mut()[self.offset..self.offset + self.len]
+        ))
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.with_bytes(|x| x.to_vec())
+    }
+
+    /// A view of the bytes from `start` up to `end`, sharing them with this buffer. `None` if
+    /// the range isn't inside of the buffer.
+    pub fn slice(&self, start: usize, end: usize) -> Option<Self> {
+        if start > end || end > self.len {
+            return None;
+        }
+        Some(FfiBuffer {
+            bytes: Rc::clone(&self.bytes),
+            offset: self.offset + start,
+            len: end - start,
+            writable: self.writable,
+        })
+    }
+
+    pub(crate) fn from_value(value: &SteelVal) -> Option<Self> {
+        if let SteelVal::Custom(c) = value {
+            return c.borrow().as_any().downcast_ref::<FfiBuffer>().cloned();
+        }
+        None
+    }
+}
+
+fn buffer(value: &SteelVal, function: &str) -> Result<FfiBuffer> {
+    match FfiBuffer::from_value(value) {
+        Some(buffer) => Ok(buffer),
+        None => stop!(TypeMismatch => "{} expects an ffi buffer, found: {}", function, value),
+    }
+}
+
+fn index(value: &SteelVal, len: usize, function: &str) -> Result<usize> {
+    match value {
+        SteelVal::IntV(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+        SteelVal::IntV(i) => {
+            stop!(ContractViolation => "{} index {} is out of bounds for an ffi buffer of length {}", function, i, len)
+        }
+        _ => stop!(TypeMismatch => "{} expects an integer index, found: {}", function, value),
+    }
+}
+
+fn byte(value: &SteelVal, function: &str) -> Result<u8> {
+    match value {
+        SteelVal::IntV(b) if (0..=255).contains(b) => Ok(*b as u8),
+        _ => {
+            stop!(TypeMismatch => "{} expects a byte between 0 and 255, found: {}", function, value)
+        }
+    }
+}
+
+pub struct FfiOperations {}
+impl FfiOperations {
+    /// (make-ffi-buffer length) makes a buffer of zeroes, (make-ffi-buffer length fill) fills it
+    /// with `fill` instead
+    pub fn make_ffi_buffer() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() || args.len() > 2 {
+                stop!(ArityMismatch => "make-ffi-buffer takes one or two arguments");
+            }
+
+            let len = match &args[0] {
+                SteelVal::IntV(n) if *n >= 0 => *n as usize,
+                other => {
+                    stop!(TypeMismatch => "make-ffi-buffer expects a length, found: {}", other)
+                }
+            };
+            let fill = match args.get(1) {
+                Some(fill) => byte(fill, "make-ffi-buffer")?,
+                None => 0,
+            };
+
+            FfiBuffer::new(vec![fill; len]).into_steelval()
+        })
+    }
+
+    pub fn is_ffi_buffer() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "ffi-buffer? takes one argument");
+            }
+
+            Ok(SteelVal::BoolV(FfiBuffer::from_value(&args[0]).is_some()))
+        })
+    }
+
+    pub fn ffi_buffer_length() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "ffi-buffer-length takes one argument");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer-length")?;
+            Ok(SteelVal::IntV(buffer.len() as isize))
+        })
+    }
+
+    pub fn is_ffi_buffer_writable() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "ffi-buffer-writable? takes one argument");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer-writable?")?;
+            Ok(SteelVal::BoolV(buffer.is_writable()))
+        })
+    }
+
+    /// (ffi-buffer-ref buffer index) returns the byte at `index`
+    pub fn ffi_buffer_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "ffi-buffer-ref takes two arguments");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer-ref")?;
+            let i = index(&args[1], buffer.len(), "ffi-buffer-ref")?;
+            Ok(SteelVal::IntV(buffer.with_bytes(|x| x[i]) as isize))
+        })
+    }
+
+    /// (ffi-buffer-set! buffer index byte) changes the byte at `index`
+    pub fn ffi_buffer_set() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "ffi-buffer-set! takes three arguments");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer-set!")?;
+            let i = index(&args[1], buffer.len(), "ffi-buffer-set!")?;
+            let b = byte(&args[2], "ffi-buffer-set!")?;
+            buffer.with_bytes_mut(|x| x[i] = b)?;
+            Ok(SteelVal::Void)
+        })
+    }
+
+    /// (ffi-buffer-slice buffer start end) returns a view of the bytes from `start` up to `end`,
+    /// which writes through to `buffer`
+    pub fn ffi_buffer_slice() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "ffi-buffer-slice takes three arguments");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer-slice")?;
+            let (start, end) = match (&args[1], &args[2]) {
+                (SteelVal::IntV(start), SteelVal::IntV(end)) if *start >= 0 && *end >= 0 => {
+                    (*start as usize, *end as usize)
+                }
+                _ => {
+                    stop!(TypeMismatch => "ffi-buffer-slice expects a start and an end index, found: {} {}", &args[1], &args[2])
+                }
+            };
+
+            match buffer.slice(start, end) {
+                Some(slice) => slice.into_steelval(),
+                None => {
+                    stop!(ContractViolation => "ffi-buffer-slice range {}..{} is out of bounds for an ffi buffer of length {}", start, end, buffer.len())
+                }
+            }
+        })
+    }
+
+    /// (ffi-buffer-copy! to at from) copies the bytes of `from` into `to`, starting at `at`
+    pub fn ffi_buffer_copy() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "ffi-buffer-copy! takes three arguments");
+            }
+
+            let to = buffer(&args[0], "ffi-buffer-copy!")?;
+            let from = buffer(&args[2], "ffi-buffer-copy!")?;
+            let at = match &args[1] {
+                SteelVal::IntV(at) if *at >= 0 && *at as usize + from.len() <= to.len() => {
+                    *at as usize
+                }
+                other => {
+                    stop!(ContractViolation => "ffi-buffer-copy! can't fit {} bytes at {} in an ffi buffer of length {}", from.len(), other, to.len())
+                }
+            };
+
+            // The buffers can be views of the same bytes, so the source is read out first
+            let bytes = from.to_vec();
+            to.with_bytes_mut(|x| x[at..at + bytes.len()].copy_from_slice(&bytes))?;
+            Ok(SteelVal::Void)
+        })
+    }
+
+    pub fn ffi_buffer_to_list() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "ffi-buffer->list takes one argument");
+            }
+
+            let buffer = buffer(&args[0], "ffi-buffer->list")?;
+            buffer
+                .to_vec()
+                .into_iter()
+                .map(|x| x as isize)
+                .collect::<Vec<_>>()
+                .into_steelval()
+        })
+    }
+
+    pub fn list_to_ffi_buffer() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "list->ffi-buffer takes one argument");
+            }
+
+            let bytes = match &args[0] {
+                SteelVal::Pair(_) => SteelVal::iter(args[0].clone())
+                    .map(|x| byte(&x, "list->ffi-buffer"))
+                    .collect::<Result<Vec<_>>>()?,
+                SteelVal::VectorV(items) => items
+                    .iter()
+                    .map(|x| byte(x, "list->ffi-buffer"))
+                    .collect::<Result<Vec<_>>>()?,
+                other => stop!(TypeMismatch => "list->ffi-buffer expects a list, found: {}", other),
+            };
+
+            FfiBuffer::new(bytes).into_steelval()
+        })
+    }
+}
+
+#[cfg(test)]
+mod ffi_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    fn last(vm: &mut Engine, program: &str) -> String {
+        vm.run(program).unwrap().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn scripts_read_and_write_host_bytes_in_place() {
+        let buffer = FfiBuffer::new(vec![0; 8]);
+        let mut vm = Engine::new();
+        vm.register_value("buffer", buffer.clone().into_steelval().unwrap());
+
+        vm.run(
+            r#"
+            (define tail (ffi-buffer-slice buffer 4 8))
+            (ffi-buffer-set! buffer 0 1)
+            (ffi-buffer-set! tail 1 255)
+            (ffi-buffer-copy! buffer 1 (list->ffi-buffer '(7 8)))
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(buffer.to_vec(), vec![1, 7, 8, 0, 0, 255, 0, 0]);
+        assert_eq!(last(&mut vm, "(ffi-buffer->list tail)"), "'(0 255 0 0)");
+        assert_eq!(last(&mut vm, "(ffi-buffer-length tail)"), "4");
+        assert_eq!(last(&mut vm, "(ffi-buffer? tail)"), "#true");
+        assert_eq!(last(&mut vm, "(ffi-buffer? '(1 2))"), "#false");
+
+        // Changes made by the host show up in the script too
+        buffer.with_bytes_mut(|x| x[4] = 9).unwrap();
+        assert_eq!(last(&mut vm, "(ffi-buffer-ref tail 0)"), "9");
+    }
+
+    #[test]
+    fn reads_and_writes_are_bounds_checked() {
+        let mut vm = Engine::new();
+        vm.run("(define buffer (make-ffi-buffer 4 1))").unwrap();
+        vm.run("(define view (ffi-buffer-slice buffer 1 3))")
+            .unwrap();
+
+        let errors = [
+            "(ffi-buffer-ref buffer 4)",
+            "(ffi-buffer-ref view 2)",
+            "(ffi-buffer-ref view -1)",
+            "(ffi-buffer-slice view 1 3)",
+            "(ffi-buffer-copy! view 1 buffer)",
+        ];
+        for program in errors.iter() {
+            let err = vm.run(program).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ContractViolation, "{}", program);
+        }
+
+        let err = vm.run("(ffi-buffer-set! buffer 0 256)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(last(&mut vm, "(ffi-buffer->list buffer)"), "'(1 1 1 1)");
+    }
+
+    #[test]
+    fn read_only_buffers_reject_writes() {
+        let input = FfiBuffer::read_only(vec![1, 2, 3]);
+        let mut vm = Engine::new();
+        vm.register_value("input", input.into_steelval().unwrap());
+
+        assert_eq!(last(&mut vm, "(ffi-buffer-ref input 2)"), "3");
+        assert_eq!(last(&mut vm, "(ffi-buffer-writable? input)"), "#false");
+        assert_eq!(
+            last(
+                &mut vm,
+                "(ffi-buffer-writable? (ffi-buffer-slice input 0 1))"
+            ),
+            "#false"
+        );
+
+        let err = vm.run("(ffi-buffer-set! input 0 5)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+    }
+}
@@ -1,30 +1,76 @@
 use colored::Colorize;
 
 use crate::rerrs::{ErrorKind, SteelErr};
-use crate::rvals::{Result, SteelVal};
+use crate::rvals::{FloatFormat, PrintLimits, Result, SteelVal};
 use crate::stop;
+use std::cell::Cell;
 use std::io;
-// use std::rc::Rc;
+use std::rc::Rc;
 
 // mod primitives;
 
 pub struct IoFunctions {}
 impl IoFunctions {
-    pub fn display() -> SteelVal {
-        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
-            if args.len() == 1 {
-                let print_val = &args[0];
+    /// Prints a value, strings without their quotes. Nested values are cut off at the
+    /// engine's print limits unless `#:depth` or `#:length` are given for this call
+    pub fn display(limits: Rc<Cell<PrintLimits>>, floats: Rc<Cell<FloatFormat>>) -> SteelVal {
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "display takes one argument, and then print options");
+            }
 
-                match &print_val {
-                    SteelVal::StringV(s) => print!("{}", s),
-                    _ => print!("{}", print_val),
-                }
+            let limits = call_print_limits("display", limits.get(), &args[1..])?;
 
-                Ok(SteelVal::Void)
-            } else {
-                stop!(ArityMismatch => "display takes one argument");
+            match &args[0] {
+                SteelVal::StringV(s) => print!("{}", s),
+                print_val => print!("{}", print_val.limited(limits).floats(floats.get())),
             }
-        })
+
+            Ok(SteelVal::Void)
+        };
+
+        SteelVal::BoxedFunction(Rc::new(f))
+    }
+
+    /// Like `display`, but strings keep their quotes
+    pub fn write(limits: Rc<Cell<PrintLimits>>, floats: Rc<Cell<FloatFormat>>) -> SteelVal {
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "write takes one argument, and then print options");
+            }
+
+            let limits = call_print_limits("write", limits.get(), &args[1..])?;
+            print!("{}", args[0].limited(limits).floats(floats.get()));
+
+            Ok(SteelVal::Void)
+        };
+
+        SteelVal::BoxedFunction(Rc::new(f))
+    }
+
+    /// With no arguments returns how deeply nested values get printed, `#false` if there's
+    /// no limit. Given a limit or `#false`, sets it instead.
+    pub fn print_depth(limits: Rc<Cell<PrintLimits>>) -> SteelVal {
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            let mut current = limits.get();
+            let res = print_limit_parameter("print-depth", &mut current.depth, args)?;
+            limits.set(current);
+            Ok(res)
+        };
+
+        SteelVal::BoxedFunction(Rc::new(f))
+    }
+
+    /// The same as `print-depth`, for how many elements of each value get printed
+    pub fn print_length(limits: Rc<Cell<PrintLimits>>) -> SteelVal {
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            let mut current = limits.get();
+            let res = print_limit_parameter("print-length", &mut current.length, args)?;
+            limits.set(current);
+            Ok(res)
+        };
+
+        SteelVal::BoxedFunction(Rc::new(f))
     }
 
     pub fn display_color() -> SteelVal {
@@ -76,3 +122,54 @@ impl IoFunctions {
         })
     }
 }
+
+fn limit_arg(name: &str, arg: &SteelVal) -> Result<Option<usize>> {
+    match arg {
+        SteelVal::IntV(n) if *n >= 0 => Ok(Some(*n as usize)),
+        SteelVal::BoolV(false) => Ok(None),
+        _ => {
+            stop!(TypeMismatch => "{} expected a non negative integer or #false, found {}", name, arg)
+        }
+    }
+}
+
+// Print options come after the value as keyword arguments
+fn call_print_limits(
+    name: &str,
+    mut limits: PrintLimits,
+    options: &[SteelVal],
+) -> Result<PrintLimits> {
+    for option in options.chunks(2) {
+        match option {
+            [SteelVal::KeywordV(k), value] if k.as_str() == "depth" => {
+                limits.depth = limit_arg(name, value)?
+            }
+            [SteelVal::KeywordV(k), value] if k.as_str() == "length" => {
+                limits.length = limit_arg(name, value)?
+            }
+            [other] => {
+                stop!(ArityMismatch => "{} expected a value for the print option {}", name, other)
+            }
+            _ => stop!(Generic => "{} got an unknown print option: {}", name, option[0]),
+        }
+    }
+
+    Ok(limits)
+}
+
+fn print_limit_parameter(
+    name: &str,
+    limit: &mut Option<usize>,
+    args: &[SteelVal],
+) -> Result<SteelVal> {
+    match args {
+        [] => Ok(limit
+            .map(|x| SteelVal::IntV(x as isize))
+            .unwrap_or(SteelVal::BoolV(false))),
+        [value] => {
+            *limit = limit_arg(name, value)?;
+            Ok(SteelVal::Void)
+        }
+        _ => stop!(ArityMismatch => "{} takes at most one argument", name),
+    }
+}
@@ -399,7 +399,7 @@ impl ListOperations {
         pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
             SteelErr::new(
                 ErrorKind::Generic,
-                "list-pair broke inside build_in_list_normal_ier".to_string(),
+                "list-pair broke inside build_in_list_normal_ier",
             )
         })
 
@@ -433,7 +433,7 @@ impl ListOperations {
         pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
             SteelErr::new(
                 ErrorKind::Generic,
-                "list-pair broke inside built_in_list_func_iter".to_string(),
+                "list-pair broke inside built_in_list_func_iter",
             )
         })
         // unimplemented!()
@@ -460,7 +460,7 @@ impl ListOperations {
         pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
             SteelErr::new(
                 ErrorKind::Generic,
-                "list-pair broke in built_in_list_func_iter_result".to_string(),
+                "list-pair broke in built_in_list_func_iter_result",
             )
         })
         // unimplemented!()
@@ -522,7 +522,7 @@ impl ListOperations {
             pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
                 SteelErr::new(
                     ErrorKind::Generic,
-                    "list-pair broke inside built_in_list_func".to_string(),
+                    "list-pair broke inside built_in_list_func",
                 )
             })
         }
@@ -551,7 +551,7 @@ impl ListOperations {
         pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
             SteelErr::new(
                 ErrorKind::Generic,
-                "list-pair broke inside built_in_list_func_flat".to_string(),
+                "list-pair broke inside built_in_list_func_flat",
             )
         })
     }
@@ -596,7 +596,7 @@ impl ListOperations {
         pairs.pop().map(SteelVal::Pair).ok_or_else(|| {
             SteelErr::new(
                 ErrorKind::Generic,
-                "list-pair broke inside built_in_list_func_flat_non_gc".to_string(),
+                "list-pair broke inside built_in_list_func_flat_non_gc",
             )
         })
     }
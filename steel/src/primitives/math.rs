@@ -0,0 +1,459 @@
+use crate::primitives::{float_modulo, ListOperations};
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+use crate::stop;
+
+use std::convert::TryFrom;
+
+fn float(value: &SteelVal, name: &str) -> Result<f64> {
+    match value {
+        SteelVal::IntV(n) => Ok(*n as f64),
+        SteelVal::NumV(n) => Ok(*n),
+        _ => stop!(TypeMismatch => "{} expected a number, found {:?}", name, value),
+    }
+}
+
+// Functions of one number that always give back a float
+macro_rules! float_function {
+    ($name:expr, $f:expr) => {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "{} takes one argument", $name)
+            }
+
+            let f: fn(f64) -> f64 = $f;
+            Ok(SteelVal::NumV(f(float(&args[0], $name)?)))
+        })
+    };
+}
+
+// Integers are already whole, so rounding only has to do anything for floats
+macro_rules! rounding_function {
+    ($name:expr, $f:expr) => {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "{} takes one argument", $name)
+            }
+
+            let f: fn(f64) -> f64 = $f;
+            match &args[0] {
+                SteelVal::IntV(n) => Ok(SteelVal::IntV(*n)),
+                SteelVal::NumV(n) => Ok(SteelVal::NumV(f(*n))),
+                _ => stop!(TypeMismatch => "{} expected a number, found {:?}", $name, &args[0]),
+            }
+        })
+    };
+}
+
+/// The IEEE 754 rounding modes `round` can be given
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RoundingMode {
+    TiesToEven,
+    TiesToAway,
+    TowardZero,
+    TowardPositive,
+    TowardNegative,
+}
+
+impl RoundingMode {
+    fn from_steelval(value: &SteelVal) -> Result<Self> {
+        let name = match value {
+            SteelVal::SymbolV(s) | SteelVal::StringV(s) => s.as_str(),
+            _ => stop!(TypeMismatch => "round expected a rounding mode, found {:?}", value),
+        };
+
+        match name {
+            "ties-to-even" => Ok(RoundingMode::TiesToEven),
+            "ties-to-away" => Ok(RoundingMode::TiesToAway),
+            "toward-zero" => Ok(RoundingMode::TowardZero),
+            "toward-positive" => Ok(RoundingMode::TowardPositive),
+            "toward-negative" => Ok(RoundingMode::TowardNegative),
+            _ => {
+                stop!(ContractViolation => "round: unknown rounding mode {}, expected one of ties-to-even, ties-to-away, toward-zero, toward-positive or toward-negative", name)
+            }
+        }
+    }
+
+    fn round(self, n: f64) -> f64 {
+        match self {
+            RoundingMode::TiesToEven => n.round_ties_even(),
+            RoundingMode::TiesToAway => n.round(),
+            RoundingMode::TowardZero => n.trunc(),
+            RoundingMode::TowardPositive => n.ceil(),
+            RoundingMode::TowardNegative => n.floor(),
+        }
+    }
+}
+
+// The largest integer whose square is at most `n`, which can't be negative
+fn integer_sqrt(n: isize) -> isize {
+    // The float square root is close, but can be off by one for large numbers
+    let mut root = (n as f64).sqrt() as isize;
+    while root.checked_mul(root).map(|x| x > n).unwrap_or(true) {
+        root -= 1;
+    }
+    while (root + 1)
+        .checked_mul(root + 1)
+        .map(|x| x <= n)
+        .unwrap_or(false)
+    {
+        root += 1;
+    }
+    root
+}
+
+// Shared by floor/ and truncate/, which return the quotient and the remainder as a list.
+// Integers whose quotient overflows, like the most negative integer over -1, are divided
+// as floats instead
+fn division(
+    args: &[SteelVal],
+    name: &str,
+    ints: fn(isize, isize) -> Option<(isize, isize)>,
+    floats: fn(f64, f64) -> (f64, f64),
+) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "{} takes 2 arguments", name)
+    }
+
+    let exact = match (&args[0], &args[1]) {
+        (SteelVal::IntV(_), SteelVal::IntV(0)) => stop!(Generic => "{}: division by zero", name),
+        (SteelVal::IntV(l), SteelVal::IntV(r)) => ints(*l, *r),
+        _ => None,
+    };
+
+    let (quotient, remainder) = match exact {
+        Some((q, r)) => (SteelVal::IntV(q), SteelVal::IntV(r)),
+        None => {
+            let (q, r) = floats(float(&args[0], name)?, float(&args[1], name)?);
+            (SteelVal::NumV(q), SteelVal::NumV(r))
+        }
+    };
+
+    ListOperations::built_in_list_func_flat(&[quotient, remainder])
+}
+
+pub struct MathOperations {}
+impl MathOperations {
+    pub fn sin() -> SteelVal {
+        float_function!("sin", f64::sin)
+    }
+
+    pub fn cos() -> SteelVal {
+        float_function!("cos", f64::cos)
+    }
+
+    pub fn tan() -> SteelVal {
+        float_function!("tan", f64::tan)
+    }
+
+    pub fn asin() -> SteelVal {
+        float_function!("asin", f64::asin)
+    }
+
+    pub fn acos() -> SteelVal {
+        float_function!("acos", f64::acos)
+    }
+
+    /// (atan y) or (atan y x), which takes the signs of both into account
+    pub fn atan() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            match args {
+                [y] => Ok(SteelVal::NumV(float(y, "atan")?.atan())),
+                [y, x] => Ok(SteelVal::NumV(float(y, "atan")?.atan2(float(x, "atan")?))),
+                _ => stop!(ArityMismatch => "atan takes one or two arguments"),
+            }
+        })
+    }
+
+    pub fn exp() -> SteelVal {
+        float_function!("exp", f64::exp)
+    }
+
+    /// (log n) is the natural logarithm, (log n base) takes the logarithm in `base`
+    pub fn log() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            match args {
+                [n] => Ok(SteelVal::NumV(float(n, "log")?.ln())),
+                [n, base] => Ok(SteelVal::NumV(float(n, "log")?.log(float(base, "log")?))),
+                _ => stop!(ArityMismatch => "log takes one or two arguments"),
+            }
+        })
+    }
+
+    /// The square root of a perfect square is exact
+    pub fn sqrt() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "sqrt takes one argument")
+            }
+
+            match &args[0] {
+                SteelVal::IntV(n) if *n < 0 => {
+                    stop!(ContractViolation => "sqrt: {} has no real square root", n)
+                }
+                SteelVal::IntV(n) => {
+                    let root = integer_sqrt(*n);
+                    if root * root == *n {
+                        Ok(SteelVal::IntV(root))
+                    } else {
+                        Ok(SteelVal::NumV((*n as f64).sqrt()))
+                    }
+                }
+                SteelVal::NumV(n) if *n < 0.0 => {
+                    stop!(ContractViolation => "sqrt: {} has no real square root", n)
+                }
+                SteelVal::NumV(n) => Ok(SteelVal::NumV(n.sqrt())),
+                _ => stop!(TypeMismatch => "sqrt expected a number, found {:?}", &args[0]),
+            }
+        })
+    }
+
+    /// (exact-integer-sqrt n) returns the list (s r) where s * s + r = n
+    pub fn exact_integer_sqrt() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "exact-integer-sqrt takes one argument")
+            }
+
+            match &args[0] {
+                SteelVal::IntV(n) if *n >= 0 => {
+                    let root = integer_sqrt(*n);
+                    ListOperations::built_in_list_func_flat(&[
+                        SteelVal::IntV(root),
+                        SteelVal::IntV(n - root * root),
+                    ])
+                }
+                _ => {
+                    stop!(TypeMismatch => "exact-integer-sqrt expected a non-negative integer, found {:?}", &args[0])
+                }
+            }
+        })
+    }
+
+    /// An integer raised to a non-negative integer stays exact, unless it overflows
+    pub fn expt() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "expt takes 2 arguments")
+            }
+
+            match (&args[0], &args[1]) {
+                (SteelVal::IntV(base), SteelVal::IntV(power)) if *power >= 0 => {
+                    let exact = u32::try_from(*power)
+                        .ok()
+                        .and_then(|power| base.checked_pow(power));
+                    match exact {
+                        Some(result) => Ok(SteelVal::IntV(result)),
+                        None => Ok(SteelVal::NumV((*base as f64).powf(*power as f64))),
+                    }
+                }
+                (base, SteelVal::IntV(power)) if i32::try_from(*power).is_ok() => {
+                    Ok(SteelVal::NumV(float(base, "expt")?.powi(*power as i32)))
+                }
+                (base, power) => Ok(SteelVal::NumV(
+                    float(base, "expt")?.powf(float(power, "expt")?),
+                )),
+            }
+        })
+    }
+
+    pub fn floor() -> SteelVal {
+        rounding_function!("floor", f64::floor)
+    }
+
+    pub fn ceiling() -> SteelVal {
+        rounding_function!("ceiling", f64::ceil)
+    }
+
+    pub fn truncate() -> SteelVal {
+        rounding_function!("truncate", f64::trunc)
+    }
+
+    /// (round n) rounds halfway cases to even, (round n mode) rounds with one of the
+    /// IEEE 754 rounding modes, given as a symbol or a string
+    pub fn round() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let mode = match args {
+                [_] => RoundingMode::TiesToEven,
+                [_, mode] => RoundingMode::from_steelval(mode)?,
+                _ => stop!(ArityMismatch => "round takes one or two arguments"),
+            };
+
+            match &args[0] {
+                SteelVal::IntV(n) => Ok(SteelVal::IntV(*n)),
+                SteelVal::NumV(n) => Ok(SteelVal::NumV(mode.round(*n))),
+                _ => stop!(TypeMismatch => "round expected a number, found {:?}", &args[0]),
+            }
+        })
+    }
+
+    /// (floor/ n d) returns the list (q r), with the quotient rounded toward negative
+    /// infinity, so the remainder takes the sign of `d`
+    pub fn floor_division() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            division(
+                args,
+                "floor/",
+                |l, r| {
+                    let (q, m) = (l.checked_div(r)?, l.checked_rem(r)?);
+                    if m != 0 && (m < 0) != (r < 0) {
+                        Some((q - 1, m + r))
+                    } else {
+                        Some((q, m))
+                    }
+                },
+                |l, r| ((l / r).floor(), float_modulo(l, r)),
+            )
+        })
+    }
+
+    /// (truncate/ n d) returns the list (q r), with the quotient rounded toward zero,
+    /// so the remainder takes the sign of `n`
+    pub fn truncate_division() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            division(
+                args,
+                "truncate/",
+                |l, r| Some((l.checked_div(r)?, l.checked_rem(r)?)),
+                |l, r| ((l / r).trunc(), l % r),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod math_tests {
+    use super::*;
+    use crate::rvals::SteelVal::*;
+    use crate::throw;
+
+    fn apply_function(func: SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        func.func_or_else(throw!(BadSyntax => "math tests"))
+            .unwrap()(&args)
+    }
+
+    fn apply(func: SteelVal, args: Vec<SteelVal>) -> String {
+        apply_function(func, args).unwrap().to_string()
+    }
+
+    #[test]
+    fn exact_results_stay_exact() {
+        assert_eq!(
+            apply(MathOperations::expt(), vec![IntV(2), IntV(10)]),
+            "1024"
+        );
+        assert_eq!(
+            apply(MathOperations::expt(), vec![IntV(2), IntV(-1)]),
+            "0.5"
+        );
+        assert_eq!(
+            apply(MathOperations::expt(), vec![IntV(2), IntV(70)]),
+            "1.1805916207174113e21"
+        );
+        assert_eq!(
+            apply(MathOperations::expt(), vec![NumV(4.0), NumV(0.5)]),
+            "2.0"
+        );
+
+        assert_eq!(apply(MathOperations::sqrt(), vec![IntV(144)]), "12");
+        assert_eq!(
+            apply(MathOperations::sqrt(), vec![IntV(2)]),
+            "1.4142135623730951"
+        );
+        let err = apply_function(MathOperations::sqrt(), vec![IntV(-4)]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+
+        assert_eq!(
+            apply(MathOperations::exact_integer_sqrt(), vec![IntV(17)]),
+            "'(4 1)"
+        );
+        assert_eq!(
+            apply(MathOperations::exact_integer_sqrt(), vec![IntV(isize::MAX)]),
+            format!(
+                "'({} {})",
+                3037000499isize,
+                isize::MAX - 3037000499 * 3037000499
+            )
+        );
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let round = |n: f64, mode: Option<&str>| {
+            let mut args = vec![NumV(n)];
+            args.extend(mode.map(|x| SymbolV(x.into())));
+            apply(MathOperations::round(), args)
+        };
+
+        assert_eq!(round(2.5, None), "2.0");
+        assert_eq!(round(3.5, None), "4.0");
+        assert_eq!(round(2.5, Some("ties-to-away")), "3.0");
+        assert_eq!(round(-2.7, Some("toward-zero")), "-2.0");
+        assert_eq!(round(2.1, Some("toward-positive")), "3.0");
+        assert_eq!(round(-2.1, Some("toward-negative")), "-3.0");
+        assert_eq!(apply(MathOperations::round(), vec![IntV(7)]), "7");
+
+        let err = apply_function(
+            MathOperations::round(),
+            vec![NumV(1.5), StringV("sideways".into())],
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+
+        assert_eq!(apply(MathOperations::floor(), vec![NumV(-1.5)]), "-2.0");
+        assert_eq!(apply(MathOperations::ceiling(), vec![NumV(-1.5)]), "-1.0");
+        assert_eq!(apply(MathOperations::truncate(), vec![IntV(3)]), "3");
+    }
+
+    #[test]
+    fn integer_division() {
+        assert_eq!(
+            apply(MathOperations::floor_division(), vec![IntV(-7), IntV(2)]),
+            "'(-4 1)"
+        );
+        assert_eq!(
+            apply(MathOperations::truncate_division(), vec![IntV(-7), IntV(2)]),
+            "'(-3 -1)"
+        );
+        assert_eq!(
+            apply(MathOperations::floor_division(), vec![NumV(7.0), IntV(-2)]),
+            "'(-4.0 -1.0)"
+        );
+        assert_eq!(
+            apply(
+                MathOperations::floor_division(),
+                vec![IntV(isize::MIN), IntV(3)]
+            ),
+            format!("'({} 1)", isize::MIN / 3 - 1)
+        );
+        assert_eq!(
+            apply(
+                MathOperations::floor_division(),
+                vec![IntV(isize::MIN), IntV(-1)]
+            ),
+            format!("'({:?} -0.0)", -(isize::MIN as f64))
+        );
+        assert_eq!(
+            apply(
+                MathOperations::truncate_division(),
+                vec![IntV(isize::MIN), IntV(-1)]
+            ),
+            format!("'({:?} -0.0)", -(isize::MIN as f64))
+        );
+        let err = apply_function(MathOperations::floor_division(), vec![IntV(1), IntV(0)]);
+        assert!(err.unwrap_err().to_string().contains("division by zero"));
+    }
+
+    #[test]
+    fn trigonometry_and_logarithms() {
+        assert_eq!(apply(MathOperations::sin(), vec![IntV(0)]), "0.0");
+        assert_eq!(
+            apply(MathOperations::atan(), vec![IntV(1), IntV(-1)]),
+            NumV(3.0 * std::f64::consts::FRAC_PI_4).to_string()
+        );
+        assert_eq!(apply(MathOperations::exp(), vec![IntV(0)]), "1.0");
+        assert_eq!(apply(MathOperations::log(), vec![IntV(8), IntV(2)]), "3.0");
+        let err = apply_function(MathOperations::cos(), vec![StringV("0".into())]);
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::TypeMismatch);
+    }
+}
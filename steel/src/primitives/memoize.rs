@@ -0,0 +1,281 @@
+use crate::gc::Gc;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{BuiltInSignature, Result, SteelVal};
+use crate::steel_vm::thread::SteelThread;
+use crate::stop;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::{Rc, Weak};
+
+/// The results a memoized function has seen, along with how well the cache is doing
+#[derive(Default)]
+struct Cache {
+    /// Each result, along with when it was last used
+    entries: HashMap<Vec<SteelVal>, (SteelVal, u64)>,
+    /// The arguments of each entry, least recently used first
+    order: BTreeMap<u64, Vec<SteelVal>>,
+    clock: u64,
+    max_size: Option<usize>,
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+}
+
+impl Cache {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, args: &[SteelVal]) -> Option<SteelVal> {
+        let now = self.tick();
+        let (value, used) = self.entries.get_mut(args)?;
+        let key = self.order.remove(used).unwrap();
+        *used = now;
+        self.order.insert(now, key);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, args: Vec<SteelVal>, value: SteelVal) {
+        let now = self.tick();
+        if let Some((_, used)) = self.entries.insert(args.clone(), (value, now)) {
+            // Computing the value called the function again with the same arguments
+            self.order.remove(&used);
+        }
+        self.order.insert(now, args);
+
+        if let Some(max_size) = self.max_size {
+            while self.entries.len() > max_size {
+                let oldest = *self.order.keys().next().unwrap();
+                let args = self.order.remove(&oldest).unwrap();
+                self.entries.remove(&args);
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn stats(&self) -> SteelVal {
+        let count = |n: usize| SteelVal::IntV(n as isize);
+        let stats = vec![
+            ("hits", count(self.hits)),
+            ("misses", count(self.misses)),
+            ("size", count(self.entries.len())),
+            ("evictions", count(self.evictions)),
+            (
+                "max-size",
+                self.max_size.map(count).unwrap_or(SteelVal::BoolV(false)),
+            ),
+        ];
+
+        SteelVal::HashMapV(Gc::new(
+            stats
+                .into_iter()
+                .map(|(name, value)| (SteelVal::SymbolV(name.into()), value))
+                .collect(),
+        ))
+    }
+}
+
+/// The caches of the functions made by `memoize`, so that `memoize-stats` can find them
+#[derive(Clone, Default)]
+pub(crate) struct MemoizedFunctions(Rc<RefCell<Vec<MemoizedFunction>>>);
+
+type MemoizedFunction = (
+    Weak<dyn Fn(&mut SteelThread, &[SteelVal]) -> Result<SteelVal>>,
+    Rc<RefCell<Cache>>,
+);
+
+impl MemoizedFunctions {
+    fn add(&self, function: &BuiltInSignature, cache: Rc<RefCell<Cache>>) {
+        let mut functions = self.0.borrow_mut();
+        functions.retain(|(f, _)| f.strong_count() > 0);
+        functions.push((Rc::downgrade(function), cache));
+    }
+
+    fn find(&self, function: &SteelVal) -> Option<Rc<RefCell<Cache>>> {
+        let function = match function {
+            SteelVal::BuiltIn(f) => f,
+            _ => return None,
+        };
+
+        self.0
+            .borrow()
+            .iter()
+            .find(|(f, _)| f.upgrade().is_some_and(|f| Rc::ptr_eq(&f, function)))
+            .map(|(_, cache)| Rc::clone(cache))
+    }
+}
+
+pub struct MemoizeOperations {}
+impl MemoizeOperations {
+    /// (memoize f #:max-size n) returns a function that remembers what `f` returned for each
+    /// set of arguments, comparing them with `equal?`. With a `#:max-size` only the `n` most
+    /// recently used results are kept. Calls with arguments that can't be hashed, like floats,
+    /// always go through to `f`. Calls that miss run `f` on the VM in place of the memoized
+    /// function, so recursion through it goes as deep as any other.
+    pub(crate) fn memoize(memoized: MemoizedFunctions) -> SteelVal {
+        let f = move |_: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+            let function = match args.first() {
+                Some(f) if f.is_function() => f.clone(),
+                _ => stop!(TypeMismatch => "memoize expects a function"),
+            };
+
+            let max_size = match &args[1..] {
+                [] => None,
+                [SteelVal::KeywordV(k), SteelVal::IntV(n)] if k.as_str() == "max-size" => {
+                    if *n <= 0 {
+                        stop!(ContractViolation => "memoize expects a positive #:max-size, found: {}", n);
+                    }
+                    Some(*n as usize)
+                }
+                _ => stop!(ArityMismatch => "memoize takes a function and an optional #:max-size"),
+            };
+
+            let cache = Rc::new(RefCell::new(Cache {
+                max_size,
+                ..Cache::default()
+            }));
+
+            let wrapper: BuiltInSignature = {
+                let cache = Rc::clone(&cache);
+                Rc::new(
+                    move |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                        if !args.iter().all(SteelVal::is_hashable) {
+                            thread.call_then(function.clone(), args.to_vec(), Ok);
+                            return Ok(SteelVal::Void);
+                        }
+
+                        let cached = cache.borrow_mut().get(args);
+                        if let Some(value) = cached {
+                            cache.borrow_mut().hits += 1;
+                            return Ok(value);
+                        }
+
+                        cache.borrow_mut().misses += 1;
+                        let (cache, key) = (Rc::clone(&cache), args.to_vec());
+                        thread.call_then(function.clone(), args.to_vec(), move |value| {
+                            cache.borrow_mut().insert(key.clone(), value.clone());
+                            Ok(value)
+                        });
+                        Ok(SteelVal::Void)
+                    },
+                )
+            };
+
+            memoized.add(&wrapper, cache);
+            Ok(SteelVal::BuiltIn(wrapper))
+        };
+
+        SteelVal::BuiltIn(Rc::new(f))
+    }
+
+    /// (memoize-stats f) returns how many calls to a memoized function were hits and misses,
+    /// how many results it holds on to and how many have been evicted
+    pub(crate) fn memoize_stats(memoized: MemoizedFunctions) -> SteelVal {
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "memoize-stats takes one argument");
+            }
+
+            match memoized.find(&args[0]) {
+                Some(cache) => Ok(cache.borrow().stats()),
+                None => {
+                    stop!(TypeMismatch => "memoize-stats expects a memoized function, found: {}", &args[0])
+                }
+            }
+        };
+
+        SteelVal::BoxedFunction(Rc::new(f))
+    }
+}
+
+#[cfg(test)]
+mod memoize_tests {
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    fn run(vm: &mut Engine, program: &str) -> SteelVal {
+        vm.run(program).unwrap().pop().unwrap()
+    }
+
+    #[test]
+    fn recursive_calls_hit_the_cache() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define calls 0)
+            (define fib
+              (memoize
+                (lambda (n)
+                  (set! calls (+ calls 1))
+                  (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))
+            (fib 12)
+        "#;
+        assert_eq!(run(&mut vm, program), SteelVal::IntV(144));
+        assert_eq!(run(&mut vm, "calls"), SteelVal::IntV(13));
+        assert_eq!(
+            run(&mut vm, "(hash-get (memoize-stats fib) 'hits)"),
+            SteelVal::IntV(10)
+        );
+    }
+
+    #[test]
+    fn recursion_through_memoized_functions_goes_deep() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define fib
+              (memoize
+                (lambda (n)
+                  (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))
+            (define count-down
+              (memoize (lambda (n) (if (= n 0) 0 (+ 1 (count-down (- n 1)))))))
+            (list (fib 30) (count-down 900))
+        "#;
+        assert_eq!(run(&mut vm, program).to_string(), "'(832040 900)");
+    }
+
+    #[test]
+    fn least_recently_used_results_are_evicted() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define calls 0)
+            (define f (memoize (lambda (x) (set! calls (+ calls 1)) x) #:max-size 2))
+            (f 1) (f 2) (f 1) (f 3) (f 1) (f 2)
+        "#;
+        vm.run(program).unwrap();
+        // 2 was the least recently used when 3 came in, so it had to be computed again
+        assert_eq!(run(&mut vm, "calls"), SteelVal::IntV(4));
+        assert_eq!(
+            run(&mut vm, "(hash-get (memoize-stats f) 'evictions)"),
+            SteelVal::IntV(2)
+        );
+        assert_eq!(
+            run(&mut vm, "(hash-get (memoize-stats f) 'size)"),
+            SteelVal::IntV(2)
+        );
+    }
+
+    #[test]
+    fn arguments_are_compared_with_equal() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define f (memoize (lambda (x) (list x))))
+            (f (list 1 2 3))
+            (f (list 1 2 3))
+            (f 1.5)
+            (hash-get (memoize-stats f) 'hits)
+        "#;
+        assert_eq!(run(&mut vm, program), SteelVal::IntV(1));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let mut vm = Engine::new();
+        assert!(vm.run("(memoize 1)").is_err());
+        assert!(vm.run("(memoize (lambda (x) x) #:max-size 0)").is_err());
+        assert!(vm
+            .run("(memoize (lambda (x) x) (string->symbol \"#:max-size\") 2)")
+            .is_err());
+        assert!(vm.run("(memoize-stats (lambda (x) x))").is_err());
+    }
+}
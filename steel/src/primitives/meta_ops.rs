@@ -1,6 +1,11 @@
+use crate::parser::tokens::TokenType;
+use crate::primitives::ListOperations;
 use crate::rerrs::{ErrorKind, SteelErr};
-use crate::rvals::{poll_future, Result, SteelVal};
+use crate::rvals::{poll_future, BoxedFunctionSignature, Result, SteelVal};
+use crate::steel_vm::evaluation_progress::PendingAwait;
 use crate::stop;
+use crate::throw;
+use crate::values::signatures::Signature;
 use crate::{
     gc::{get_object_count, Gc},
     rvals::FutureResult,
@@ -12,6 +17,17 @@ use async_compat::Compat;
 
 use futures::FutureExt;
 use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Blocks until `future` is done
+pub(crate) fn block_on_future(future: FutureResult) -> Result<SteelVal> {
+    // Blocking from inside of an executor would deadlock, or panic
+    if futures::executor::enter().is_err() {
+        stop!(Generic => "await can only suspend the program when the program calls it directly from the top level of `run_async`, and can't block inside of an executor");
+    }
+
+    LocalPool::new().run_until(Compat::new(future.into_shared()))
+}
 
 pub struct MetaOperations {}
 impl MetaOperations {
@@ -140,6 +156,38 @@ impl MetaOperations {
         })
     }
 
+    /// Returns the value of a future once it is done. Futures that aren't done yet are handed to
+    /// the VM through `pending`, and it either suspends the program until the future is done or
+    /// blocks on it, depending on how the program is being run. When `await` isn't called by the
+    /// VM itself there's no way for it to pick the value back up, so those calls block.
+    pub(crate) fn await_future(pending: PendingAwait) -> SteelVal {
+        let slot = Rc::clone(&pending);
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "await expected 1 argument, got {}", args.len());
+            }
+
+            if let SteelVal::FutureV(fut) = &args[0] {
+                let fut = fut.unwrap();
+                match poll_future(fut.clone().into_shared()) {
+                    Some(value) => value,
+                    None if slot.called_directly() => {
+                        slot.hand_off(fut);
+                        // Stands in for the value until the VM has it
+                        Ok(SteelVal::Void)
+                    }
+                    None => block_on_future(fut),
+                }
+            } else {
+                stop!(TypeMismatch => "await expected a future, found {}", args[0])
+            }
+        };
+
+        let f: BoxedFunctionSignature = Rc::new(f);
+        pending.set_function(&f);
+        SteelVal::BoxedFunction(f)
+    }
+
     pub fn poll_value() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() != 1 {
@@ -187,4 +235,130 @@ impl MetaOperations {
             )))))
         })
     }
+
+    pub fn serializable_closure() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let (lambda, source) = match args {
+                [SteelVal::Closure(lambda), SteelVal::StringV(source), ..]
+                    if args.len() == 2 || args.len() == 4 =>
+                {
+                    (lambda, source.unwrap())
+                }
+                _ => stop!(Generic => "serializable-lambda was not expanded correctly"),
+            };
+
+            // Captured variables get bound around the lambda by value
+            let serialized = if let [_, _, names, values] = args {
+                let mut parameters = Vec::new();
+                let mut bindings = Vec::new();
+
+                let names = ListOperations::collect_into_vec(names)?;
+                let values = ListOperations::collect_into_vec(values)?;
+
+                for (name, value) in names.iter().zip(values.iter()) {
+                    let name = name.string_or_else(
+                        throw!(Generic => "serializable-lambda was not expanded correctly"),
+                    )?;
+
+                    // Atoms evaluate to themselves, and the parser won't take a quoted one as an argument
+                    let quoted = matches!(
+                        value,
+                        SteelVal::SymbolV(_) | SteelVal::VectorV(_) | SteelVal::Pair(_)
+                    );
+
+                    match serialize_datum(value) {
+                        Some(datum) if quoted => bindings.push(format!("(quote {})", datum)),
+                        Some(datum) => bindings.push(datum),
+                        None => {
+                            stop!(TypeMismatch => "serializable-lambda can only capture serializable values, {} is {}",
+                                name, value)
+                        }
+                    }
+
+                    parameters.push(name);
+                }
+
+                format!(
+                    "((lambda ({}) {}) {})",
+                    parameters.join(" "),
+                    source,
+                    bindings.join(" ")
+                )
+            } else {
+                source
+            };
+
+            let mut lambda = lambda.unwrap();
+            lambda.set_serialized(serialized);
+            Ok(SteelVal::Closure(Gc::new(lambda)))
+        })
+    }
+
+    pub fn lambda_signature() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            match args {
+                [SteelVal::Closure(lambda), SteelVal::IntV(required), SteelVal::IntV(optional), keywords @ ..] =>
+                {
+                    let keywords = keywords
+                        .iter()
+                        .map(|x| match x {
+                            SteelVal::KeywordV(k) => Ok(k.as_str().into()),
+                            _ => stop!(Generic => "lambda was not expanded correctly"),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+
+                    let mut lambda = lambda.unwrap();
+                    lambda.set_signature(Signature::new(
+                        *required as usize,
+                        *optional as usize,
+                        keywords,
+                    ));
+                    Ok(SteelVal::Closure(Gc::new(lambda)))
+                }
+                _ => stop!(Generic => "lambda was not expanded correctly"),
+            }
+        })
+    }
+
+    pub fn serialize_closure() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "serialize-closure takes one argument");
+            }
+
+            match &args[0] {
+                SteelVal::Closure(lambda) => match lambda.serialized() {
+                    Some(serialized) => Ok(SteelVal::StringV(serialized.into())),
+                    None => {
+                        stop!(TypeMismatch => "serialize-closure expects a closure made with serializable-lambda")
+                    }
+                },
+                _ => stop!(TypeMismatch => "serialize-closure expects a closure"),
+            }
+        })
+    }
+}
+
+// Writes out a value so that reading it back inside of a quote produces the same value
+pub(crate) fn serialize_datum(value: &SteelVal) -> Option<String> {
+    match value {
+        SteelVal::BoolV(true) => Some("#true".to_string()),
+        SteelVal::BoolV(false) => Some("#false".to_string()),
+        SteelVal::IntV(i) => Some(i.to_string()),
+        SteelVal::NumV(n) if n.is_finite() => Some(format!("{:?}", n)),
+        SteelVal::CharV(c) => Some(TokenType::CharacterLiteral(*c).to_string()),
+        // String literals have no way of escaping either of these
+        SteelVal::StringV(s) if !s.contains('"') && !s.contains('\\') => Some(format!("\"{}\"", s)),
+        SteelVal::SymbolV(s) => Some(s.unwrap()),
+        SteelVal::VectorV(v) => serialize_list(v.iter().cloned()),
+        SteelVal::Pair(_) => serialize_list(SteelVal::iter(value.clone())),
+        _ => None,
+    }
+}
+
+fn serialize_list(items: impl Iterator<Item = SteelVal>) -> Option<String> {
+    let items = items
+        .map(|x| serialize_datum(&x))
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("({})", items.join(" ")))
 }
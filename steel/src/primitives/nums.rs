@@ -3,6 +3,7 @@ use crate::rerrs::{ErrorKind, SteelErr};
 // use crate::rvals::SteelVal::*;
 use crate::rvals::{Result, SteelVal};
 use crate::stop;
+use std::cmp::Ordering;
 // use rand::Rng;
 
 pub struct NumOperations {}
@@ -293,6 +294,190 @@ impl NumOperations {
             }
         })
     }
+
+    pub fn modulo() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "modulo takes 2 arguments")
+            }
+
+            match (&args[0], &args[1]) {
+                (SteelVal::IntV(_), SteelVal::IntV(0)) => {
+                    stop!(Generic => "modulo: division by zero")
+                }
+                (SteelVal::IntV(l), SteelVal::IntV(r)) => Ok(SteelVal::IntV(int_modulo(*l, *r))),
+                (SteelVal::NumV(l), SteelVal::NumV(r)) => Ok(SteelVal::NumV(float_modulo(*l, *r))),
+                (SteelVal::IntV(l), SteelVal::NumV(r)) => {
+                    Ok(SteelVal::NumV(float_modulo(*l as f64, *r)))
+                }
+                (SteelVal::NumV(l), SteelVal::IntV(r)) => {
+                    Ok(SteelVal::NumV(float_modulo(*l, *r as f64)))
+                }
+                _ => stop!(TypeMismatch => "modulo expected 2 numbers"),
+            }
+        })
+    }
+
+    pub fn remainder() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "remainder takes 2 arguments")
+            }
+
+            match (&args[0], &args[1]) {
+                (SteelVal::IntV(_), SteelVal::IntV(0)) => {
+                    stop!(Generic => "remainder: division by zero")
+                }
+                (SteelVal::IntV(l), SteelVal::IntV(r)) => Ok(SteelVal::IntV(l.wrapping_rem(*r))),
+                (SteelVal::NumV(l), SteelVal::NumV(r)) => Ok(SteelVal::NumV(l % r)),
+                (SteelVal::IntV(l), SteelVal::NumV(r)) => Ok(SteelVal::NumV(*l as f64 % r)),
+                (SteelVal::NumV(l), SteelVal::IntV(r)) => Ok(SteelVal::NumV(l % *r as f64)),
+                _ => stop!(TypeMismatch => "remainder expected 2 numbers"),
+            }
+        })
+    }
+
+    pub fn abs() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "abs takes one argument")
+            }
+
+            match &args[0] {
+                SteelVal::IntV(n) => {
+                    if let Some(res) = n.checked_abs() {
+                        Ok(SteelVal::IntV(res))
+                    } else {
+                        Ok(SteelVal::NumV((*n as f64).abs()))
+                    }
+                }
+                SteelVal::NumV(n) => Ok(SteelVal::NumV(n.abs())),
+                _ => stop!(TypeMismatch => "abs expected a number, found {:?}", &args[0]),
+            }
+        })
+    }
+
+    pub fn min() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "min requires at least one argument")
+            }
+
+            fold_extremum(args, "min", Ordering::Less)
+        })
+    }
+
+    pub fn max() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "max requires at least one argument")
+            }
+
+            fold_extremum(args, "max", Ordering::Greater)
+        })
+    }
+
+    pub fn exact_to_inexact() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "exact->inexact takes one argument")
+            }
+
+            match &args[0] {
+                SteelVal::IntV(n) => Ok(SteelVal::NumV(*n as f64)),
+                SteelVal::NumV(n) => Ok(SteelVal::NumV(*n)),
+                _ => {
+                    stop!(TypeMismatch => "exact->inexact expected a number, found {:?}", &args[0])
+                }
+            }
+        })
+    }
+
+    /// There are no exact fractions, so only floats holding a whole number can be made exact
+    pub fn inexact_to_exact() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "inexact->exact takes one argument")
+            }
+
+            match &args[0] {
+                SteelVal::IntV(n) => Ok(SteelVal::IntV(*n)),
+                SteelVal::NumV(n)
+                    if n.fract() == 0.0 && *n >= isize::MIN as f64 && *n < isize::MAX as f64 =>
+                {
+                    Ok(SteelVal::IntV(*n as isize))
+                }
+                SteelVal::NumV(_) => {
+                    stop!(ContractViolation => "inexact->exact: {} has no exact representation", &args[0])
+                }
+                _ => {
+                    stop!(TypeMismatch => "inexact->exact expected a number, found {:?}", &args[0])
+                }
+            }
+        })
+    }
+}
+
+// Scheme's `modulo` takes the sign of the divisor, unlike `%` in Rust
+#[inline(always)]
+pub(crate) fn int_modulo(l: isize, r: isize) -> isize {
+    let m = l.wrapping_rem(r);
+    if m != 0 && (m < 0) != (r < 0) {
+        m + r
+    } else {
+        m
+    }
+}
+
+#[inline(always)]
+pub(crate) fn float_modulo(l: f64, r: f64) -> f64 {
+    let m = l % r;
+    if m != 0.0 && (m < 0.0) != (r < 0.0) {
+        m + r
+    } else {
+        m
+    }
+}
+
+// Shared by min and max - if any of the arguments are floats, the result is a float.
+// Integers are compared with each other exactly, since not all of them fit in a float.
+fn fold_extremum(args: &[SteelVal], name: &str, pick: Ordering) -> Result<SteelVal> {
+    let mut found_float = false;
+    let mut best: Option<&SteelVal> = None;
+
+    for arg in args {
+        match arg {
+            SteelVal::IntV(_) => {}
+            SteelVal::NumV(_) => found_float = true,
+            _ => stop!(TypeMismatch => "{} expected a number, found {:?}", name, arg),
+        }
+
+        let ordering = match (arg, best) {
+            (_, None) => Some(pick),
+            (SteelVal::IntV(l), Some(SteelVal::IntV(r))) => Some(l.cmp(r)),
+            (l, Some(r)) => as_float(l).partial_cmp(&as_float(r)),
+        };
+
+        if ordering == Some(pick) {
+            best = Some(arg);
+        }
+    }
+
+    let value = best.unwrap();
+
+    if found_float {
+        Ok(SteelVal::NumV(as_float(value)))
+    } else {
+        Ok(value.clone())
+    }
+}
+
+fn as_float(value: &SteelVal) -> f64 {
+    match value {
+        SteelVal::IntV(n) => *n as f64,
+        SteelVal::NumV(n) => *n,
+        _ => unreachable!(),
+    }
 }
 
 #[cfg(test)]
@@ -369,4 +554,70 @@ mod num_op_tests {
         let expected = IntV(8);
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn modulo_takes_sign_of_divisor() {
+        let output = apply_function(NumOperations::modulo(), vec![IntV(-7), IntV(3)]).unwrap();
+        assert_eq!(output, IntV(2));
+
+        let output = apply_function(NumOperations::modulo(), vec![IntV(7), IntV(-3)]).unwrap();
+        assert_eq!(output, IntV(-2));
+    }
+
+    #[test]
+    fn remainder_takes_sign_of_dividend() {
+        let output = apply_function(NumOperations::remainder(), vec![IntV(-7), IntV(3)]).unwrap();
+        assert_eq!(output, IntV(-1));
+    }
+
+    #[test]
+    fn modulo_by_zero_errors() {
+        assert!(apply_function(NumOperations::modulo(), vec![IntV(7), IntV(0)]).is_err());
+        assert!(apply_function(NumOperations::remainder(), vec![IntV(7), IntV(0)]).is_err());
+    }
+
+    #[test]
+    fn abs_test() {
+        let output = apply_function(NumOperations::abs(), vec![IntV(-10)]).unwrap();
+        assert_eq!(output, IntV(10));
+
+        let output = apply_function(NumOperations::abs(), vec![NumV(-2.5)]).unwrap();
+        assert_eq!(output.to_string(), NumV(2.5).to_string());
+    }
+
+    #[test]
+    fn min_max_test() {
+        let output = apply_function(NumOperations::min(), vec![IntV(3), IntV(1), IntV(2)]).unwrap();
+        assert_eq!(output, IntV(1));
+
+        let output = apply_function(NumOperations::max(), vec![IntV(3), NumV(1.0)]).unwrap();
+        assert_eq!(output.to_string(), NumV(3.0).to_string());
+
+        // Both of these round to the same float
+        let big = IntV(9007199254740992);
+        let bigger = IntV(9007199254740993);
+
+        let output = apply_function(NumOperations::max(), vec![big.clone(), bigger.clone()]);
+        assert_eq!(output.unwrap(), bigger);
+
+        let output = apply_function(NumOperations::min(), vec![bigger, big.clone()]);
+        assert_eq!(output.unwrap(), big);
+    }
+
+    #[test]
+    fn exactness_conversions() {
+        let output = apply_function(NumOperations::exact_to_inexact(), vec![IntV(3)]).unwrap();
+        assert_eq!(output.to_string(), "3.0");
+
+        let output = apply_function(NumOperations::inexact_to_exact(), vec![NumV(-4.0)]).unwrap();
+        assert_eq!(output, IntV(-4));
+
+        let output = apply_function(NumOperations::inexact_to_exact(), vec![IntV(7)]).unwrap();
+        assert_eq!(output, IntV(7));
+
+        for float in [2.5, f64::NAN, f64::INFINITY, 1e300] {
+            let err = apply_function(NumOperations::inexact_to_exact(), vec![NumV(float)]);
+            assert_eq!(err.unwrap_err().kind(), ErrorKind::ContractViolation);
+        }
+    }
 }
@@ -1,9 +1,13 @@
 use crate::gc::Gc;
+use crate::primitives::StreamOperations;
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{Result, SteelVal};
+use crate::steel_vm::thread::SteelThread;
 use crate::stop;
 use crate::values::port::SteelPort;
 
+use std::rc::Rc;
+
 pub struct PortOperations {}
 impl PortOperations {
     pub fn open_input_file() -> SteelVal {
@@ -65,4 +69,141 @@ impl PortOperations {
             }
         })
     }
+
+    /// (lines path) makes a lazy stream of the lines in the file at `path`, reading
+    /// one line at a time as the stream is walked. Lines that have been walked past aren't
+    /// kept around, so the stream can only be walked once. Also takes an input port.
+    pub fn lines() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "lines expected one argument");
+            }
+
+            StreamOperations::from_iter_once(lines(&args[0], "lines")?)
+        })
+    }
+
+    /// (for-each-line path f) calls `f` with each line in the file at `path`, without
+    /// keeping more than the current line around. Also takes an input port.
+    pub fn for_each_line() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 2 {
+                    stop!(ArityMismatch => "for-each-line expected two arguments");
+                }
+
+                for line in lines(&args[0], "for-each-line")? {
+                    thread.call_function(&args[1], vec![SteelVal::StringV(line?.into())])?;
+                }
+
+                Ok(SteelVal::Void)
+            },
+        ))
+    }
+}
+
+// The lines read from a path or an input port, with the line endings taken off
+fn lines(source: &SteelVal, name: &str) -> Result<impl Iterator<Item = Result<String>>> {
+    let port = match source {
+        SteelVal::StringV(path) => SteelPort::new_textual_file_input(path)?,
+        SteelVal::PortV(port) if port.is_input() => port.unwrap(),
+        _ => stop!(TypeMismatch => "{} expected a path or an input port, found: {}", name, source),
+    };
+
+    Ok(std::iter::from_fn(move || match port.read_line() {
+        Ok((0, _)) => None,
+        Ok((_, mut line)) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Some(Ok(line))
+        }
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+#[cfg(test)]
+mod port_tests {
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+    use std::path::PathBuf;
+
+    fn log_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("steel-lines-{}-{}.log", name, std::process::id()));
+        std::fs::write(
+            &path,
+            "INFO start\r\nERROR disk full\nINFO retry\nERROR disk full\n",
+        )
+        .unwrap();
+        path
+    }
+
+    fn last(vm: &mut Engine, program: &str) -> String {
+        vm.run(program).unwrap().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn lines_are_a_stream_that_transducers_can_walk() {
+        let path = log_file("stream");
+        let mut vm = Engine::new();
+        vm.register_value("path", SteelVal::StringV(path.to_str().unwrap().into()));
+
+        assert_eq!(last(&mut vm, "(stream-car (lines path))"), "\"INFO start\"");
+        assert_eq!(
+            last(
+                &mut vm,
+                r#"(execute (filtering (lambda (line) (starts-with? line "ERROR"))) (lines path))"#
+            ),
+            "'(\"ERROR disk full\" \"ERROR disk full\")"
+        );
+        assert_eq!(
+            last(
+                &mut vm,
+                "(transduce (taking 3) (lambda (n line) (+ n 1)) 0 (lines path))"
+            ),
+            "3"
+        );
+
+        // Reading from a port carries on from where it is
+        assert_eq!(
+            last(
+                &mut vm,
+                "(define port (open-input-file path)) (read-line-from-port port) (stream-car (lines port))"
+            ),
+            "\"ERROR disk full\""
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn for_each_line_calls_the_function_with_every_line() {
+        let path = log_file("for-each");
+        let mut vm = Engine::new();
+        vm.register_value("path", SteelVal::StringV(path.to_str().unwrap().into()));
+
+        assert_eq!(
+            last(
+                &mut vm,
+                r#"
+                (define errors 0)
+                (for-each-line path
+                  (lambda (line)
+                    (when (starts-with? line "ERROR") (set! errors (+ errors 1)))))
+                errors
+                "#
+            ),
+            "2"
+        );
+
+        assert!(vm
+            .run("(for-each-line \"/does/not/exist\" displayln)")
+            .is_err());
+        assert!(vm.run("(lines 10)").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,307 @@
+use crate::primitives::ListOperations;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+use crate::stop;
+
+use std::cmp::Ordering;
+
+// A semantic version, `MAJOR.MINOR.PATCH[-PRERELEASE][+BUILD]`, see https://semver.org
+#[derive(Debug, PartialEq)]
+struct Version<'a> {
+    major: isize,
+    minor: isize,
+    patch: isize,
+    prerelease: Option<&'a str>,
+    build: Option<&'a str>,
+}
+
+// Numbers can't have leading zeros, identifiers are made of letters, digits and hyphens
+fn numeric(part: &str) -> Option<isize> {
+    if part.is_empty()
+        || !part.bytes().all(|b| b.is_ascii_digit())
+        || (part.len() > 1 && part.starts_with('0'))
+    {
+        return None;
+    }
+    part.parse().ok()
+}
+
+fn identifiers_valid(s: &str, numbers_checked: bool) -> bool {
+    s.split('.').all(|x| {
+        !x.is_empty()
+            && x.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            && (!numbers_checked || !x.bytes().all(|b| b.is_ascii_digit()) || numeric(x).is_some())
+    })
+}
+
+impl<'a> Version<'a> {
+    fn parse(s: &'a str) -> Option<Self> {
+        let (rest, build) = match s.find('+') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let (core, prerelease) = match rest.find('-') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let mut parts = core.split('.');
+        let version = Version {
+            major: numeric(parts.next()?)?,
+            minor: numeric(parts.next()?)?,
+            patch: numeric(parts.next()?)?,
+            prerelease,
+            build,
+        };
+
+        let valid = |part: Option<&str>, numbers_checked| {
+            part.map(|x| identifiers_valid(x, numbers_checked))
+                .unwrap_or(true)
+        };
+        if parts.next().is_some() || !valid(prerelease, true) || !valid(build, false) {
+            return None;
+        }
+        Some(version)
+    }
+
+    // Build metadata doesn't count, and a prerelease comes before the release
+    fn precedence(&self, other: &Version) -> Ordering {
+        let core =
+            (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch));
+        if core != Ordering::Equal {
+            return core;
+        }
+
+        match (self.prerelease, other.prerelease) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => {
+                let mut a = a.split('.');
+                let mut b = b.split('.');
+                loop {
+                    let ordering = match (a.next(), b.next()) {
+                        (None, None) => return Ordering::Equal,
+                        (None, Some(_)) => return Ordering::Less,
+                        (Some(_), None) => return Ordering::Greater,
+                        // Numbers come before anything else, and are compared as numbers
+                        (Some(x), Some(y)) => match (numeric(x), numeric(y)) {
+                            (Some(x), Some(y)) => x.cmp(&y),
+                            (Some(_), None) => Ordering::Less,
+                            (None, Some(_)) => Ordering::Greater,
+                            (None, None) => x.cmp(y),
+                        },
+                    };
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn version_arg<'a>(name: &str, arg: &'a SteelVal) -> Result<Version<'a>> {
+    match arg {
+        SteelVal::StringV(s) => match Version::parse(s) {
+            Some(version) => Ok(version),
+            None => {
+                stop!(ConversionError => "{} expected a semantic version, found {:?}", name, s.as_str())
+            }
+        },
+        other => stop!(TypeMismatch => "{} expected a string, found {}", name, other),
+    }
+}
+
+fn compare(name: &str, args: &[SteelVal]) -> Result<Ordering> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "{} takes two arguments", name);
+    }
+    let a = version_arg(name, &args[0])?;
+    let b = version_arg(name, &args[1])?;
+    Ok(a.precedence(&b))
+}
+
+fn optional_string(s: Option<&str>) -> SteelVal {
+    s.map_or(SteelVal::BoolV(false), |x| SteelVal::StringV(x.into()))
+}
+
+/// The functions of the `steel/semver` module. Versions are strings, like `"1.4.0-beta.2"`.
+pub struct SemverOperations {}
+impl SemverOperations {
+    /// `(semver/parse s)` splits a version into a list of its major, minor and patch numbers,
+    /// prerelease and build metadata, with `#f` for the parts that aren't there
+    pub fn parse() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "semver/parse takes one argument");
+            }
+            let version = version_arg("semver/parse", &args[0])?;
+            Ok(ListOperations::built_in_list_normal_iter_non_result(
+                vec![
+                    SteelVal::IntV(version.major),
+                    SteelVal::IntV(version.minor),
+                    SteelVal::IntV(version.patch),
+                    optional_string(version.prerelease),
+                    optional_string(version.build),
+                ]
+                .into_iter(),
+            ))
+        })
+    }
+
+    /// `(semver/valid? s)` checks whether `s` is a semantic version
+    pub fn is_valid() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "semver/valid? takes one argument");
+            }
+            Ok(SteelVal::BoolV(match &args[0] {
+                SteelVal::StringV(s) => Version::parse(s).is_some(),
+                _ => false,
+            }))
+        })
+    }
+
+    /// `(semver/compare a b)` is -1, 0 or 1 as `a` comes before, alongside or after `b`
+    pub fn compare() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let ordering = compare("semver/compare", args)?;
+            Ok(SteelVal::IntV(ordering as isize))
+        })
+    }
+
+    /// `(semver<? a b)` checks whether `a` comes before `b`
+    pub fn less_than() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            Ok(SteelVal::BoolV(
+                compare("semver<?", args)? == Ordering::Less,
+            ))
+        })
+    }
+
+    /// `(semver=? a b)` ignores build metadata, which doesn't change what a version is
+    pub fn equals() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            Ok(SteelVal::BoolV(
+                compare("semver=?", args)? == Ordering::Equal,
+            ))
+        })
+    }
+
+    /// `(semver/bump version part)` goes to the next major, minor or patch release, given by the
+    /// symbol `part`
+    pub fn bump() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "semver/bump takes two arguments");
+            }
+            let version = version_arg("semver/bump", &args[0])?;
+            let (major, minor, patch) = (version.major, version.minor, version.patch);
+
+            let bumped = match &args[1] {
+                SteelVal::SymbolV(s) if s.as_str() == "major" => (major + 1, 0, 0),
+                SteelVal::SymbolV(s) if s.as_str() == "minor" => (major, minor + 1, 0),
+                // A prerelease of a patch is released by dropping the prerelease
+                SteelVal::SymbolV(s) if s.as_str() == "patch" => match version.prerelease {
+                    Some(_) => (major, minor, patch),
+                    None => (major, minor, patch + 1),
+                },
+                other => {
+                    stop!(TypeMismatch => "semver/bump expected 'major, 'minor or 'patch, found {}", other)
+                }
+            };
+            Ok(SteelVal::StringV(
+                format!("{}.{}.{}", bumped.0, bumped.1, bumped.2).into(),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod semver_tests {
+    use super::*;
+    use crate::throw;
+
+    use crate::rvals::SteelVal::*;
+
+    fn apply_function(func: SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        func.func_or_else(throw!(BadSyntax => "semver tests"))
+            .unwrap()(&args)
+    }
+
+    fn compare(a: &str, b: &str) -> SteelVal {
+        apply_function(
+            SemverOperations::compare(),
+            vec![StringV(a.into()), StringV(b.into())],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn versions_are_parsed_into_parts() {
+        let parsed = apply_function(
+            SemverOperations::parse(),
+            vec![StringV("1.4.10-beta.2+sha.5114f85".into())],
+        )
+        .unwrap();
+        assert_eq!(parsed.to_string(), "'(1 4 10 \"beta.2\" \"sha.5114f85\")");
+
+        for s in &[
+            "1.2",
+            "1.2.3.4",
+            "01.2.3",
+            "1.2.3-",
+            "1.2.3-beta..1",
+            "1.2.3-01",
+            "v1.2.3",
+        ] {
+            let res = apply_function(SemverOperations::parse(), vec![StringV((*s).into())]);
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::ConversionError, "{}", s);
+        }
+    }
+
+    #[test]
+    fn versions_are_ordered_by_precedence() {
+        // The order given by the spec
+        let versions = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in versions.windows(2) {
+            assert_eq!(
+                compare(pair[0], pair[1]),
+                IntV(-1),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(compare(pair[1], pair[0]), IntV(1));
+        }
+        assert_eq!(compare("1.0.0+build.1", "1.0.0+build.2"), IntV(0));
+    }
+
+    #[test]
+    fn versions_are_bumped() {
+        let bump = |version: &str, part: &str| {
+            apply_function(
+                SemverOperations::bump(),
+                vec![StringV(version.into()), SymbolV(part.into())],
+            )
+            .unwrap()
+        };
+        assert_eq!(bump("1.2.3", "major"), StringV("2.0.0".into()));
+        assert_eq!(bump("1.2.3", "minor"), StringV("1.3.0".into()));
+        assert_eq!(bump("1.2.3", "patch"), StringV("1.2.4".into()));
+        assert_eq!(bump("1.2.3-rc.1", "patch"), StringV("1.2.3".into()));
+    }
+}
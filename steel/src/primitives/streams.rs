@@ -1,12 +1,38 @@
 use crate::gc::Gc;
 use crate::rerrs::{ErrorKind, SteelErr};
-use crate::rvals::{Result, SteelVal};
+use crate::rvals::{IntoSteelVal, Result, SteelVal};
 use crate::stop;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::values::lazy_stream::LazyStream;
 
 pub struct StreamOperations {}
 impl StreamOperations {
+    /// Makes a stream that pulls its values from `iter` as it is walked. A value that is an
+    /// error is raised when the stream gets to it, and the stream ends there.
+    pub(crate) fn from_iter<T, E, I>(iter: I) -> Result<SteelVal>
+    where
+        T: IntoSteelVal,
+        E: std::fmt::Debug + 'static,
+        I: Iterator<Item = std::result::Result<T, E>> + 'static,
+    {
+        next_in_stream(Rc::new(RefCell::new(iter)), true)
+    }
+
+    /// Like `from_iter`, except that the rest of the stream isn't remembered, so walking it
+    /// only ever holds on to one value at a time. Walking the same stream again carries on
+    /// pulling from `iter` instead of starting over.
+    pub(crate) fn from_iter_once<T, E, I>(iter: I) -> Result<SteelVal>
+    where
+        T: IntoSteelVal,
+        E: std::fmt::Debug + 'static,
+        I: Iterator<Item = std::result::Result<T, E>> + 'static,
+    {
+        next_in_stream(Rc::new(RefCell::new(iter)), false)
+    }
+
     pub fn stream_cons() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() != 2 {
@@ -70,3 +96,40 @@ impl StreamOperations {
         })
     }
 }
+
+// Pulls the next value out of `iter`, with a thunk for the rest of the stream after it.
+// Streams can be walked more than once, so unless told otherwise each thunk remembers
+// what it made the first time.
+fn next_in_stream<T, E, I>(iter: Rc<RefCell<I>>, remember: bool) -> Result<SteelVal>
+where
+    T: IntoSteelVal,
+    E: std::fmt::Debug + 'static,
+    I: Iterator<Item = std::result::Result<T, E>> + 'static,
+{
+    let value = match iter.borrow_mut().next() {
+        Some(value) => value.into_steelval()?,
+        None => return Ok(StreamOperations::empty_stream()),
+    };
+
+    let rest: RefCell<Option<Result<SteelVal>>> = RefCell::new(None);
+    let stream_thunk = move |args: &[SteelVal]| -> Result<SteelVal> {
+        if !args.is_empty() {
+            stop!(ArityMismatch => "the rest of a stream takes no arguments");
+        }
+
+        if !remember {
+            return next_in_stream(Rc::clone(&iter), false);
+        }
+
+        if rest.borrow().is_none() {
+            let next = next_in_stream(Rc::clone(&iter), true);
+            *rest.borrow_mut() = Some(next);
+        }
+        rest.borrow().clone().unwrap()
+    };
+
+    Ok(SteelVal::StreamV(Gc::new(LazyStream::new(
+        value,
+        SteelVal::BoxedFunction(Rc::new(stream_thunk)),
+    ))))
+}
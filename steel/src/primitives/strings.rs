@@ -1,15 +1,95 @@
 use crate::rerrs::{ErrorKind, SteelErr};
-use crate::rvals::{Result, SteelVal};
+use crate::rvals::{Custom, IntoSteelVal, Result, SteelVal};
 use crate::stop;
 
 use crate::primitives::lists::ListOperations;
 
+use memchr::memmem;
+use std::fmt;
+
 macro_rules! ok_string {
     ($string:expr) => {
         Ok(SteelVal::StringV($string.into()))
     };
 }
 
+/// A compiled regular expression, made with `(regex "pattern")`
+#[derive(Clone)]
+pub struct Regex(regex::Regex);
+
+impl Custom for Regex {}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "regex {:?}", self.0.as_str())
+    }
+}
+
+fn as_regex(value: &SteelVal) -> Option<Regex> {
+    if let SteelVal::Custom(c) = value {
+        return c.borrow().as_any().downcast_ref::<Regex>().cloned();
+    }
+    None
+}
+
+// What the search functions look for, either a plain string or a regex
+enum Needle<'a> {
+    Text(&'a str),
+    Regex(regex::Regex),
+}
+
+impl<'a> Needle<'a> {
+    fn new(name: &str, value: &'a SteelVal) -> Result<Self> {
+        match value {
+            SteelVal::StringV(s) => Ok(Needle::Text(s.as_str())),
+            other => match as_regex(other) {
+                Some(r) => Ok(Needle::Regex(r.0)),
+                None => {
+                    stop!(TypeMismatch => "{} expected a string or a regex, found: {}", name, other)
+                }
+            },
+        }
+    }
+
+    // Matching the empty string everywhere would never make progress when splitting
+    fn non_empty(self, name: &str) -> Result<Self> {
+        match self {
+            Needle::Text("") => {
+                stop!(ContractViolation => "{} can't search for an empty string", name)
+            }
+            needle => Ok(needle),
+        }
+    }
+
+    /// The byte offset of the first match
+    fn find(&self, haystack: &str) -> Option<usize> {
+        match self {
+            Needle::Text(needle) => memmem::find(haystack.as_bytes(), needle.as_bytes()),
+            Needle::Regex(r) => r.find(haystack).map(|m| m.start()),
+        }
+    }
+
+    /// The byte ranges of every match, which never overlap
+    fn ranges(&self, haystack: &str) -> Vec<(usize, usize)> {
+        match self {
+            Needle::Text(needle) => memmem::find_iter(haystack.as_bytes(), needle.as_bytes())
+                .map(|start| (start, start + needle.len()))
+                .collect(),
+            Needle::Regex(r) => r
+                .find_iter(haystack)
+                .map(|m| (m.start(), m.end()))
+                .collect(),
+        }
+    }
+}
+
+fn string_arg<'a>(name: &str, value: &'a SteelVal) -> Result<&'a str> {
+    match value {
+        SteelVal::StringV(s) => Ok(s.as_str()),
+        other => stop!(TypeMismatch => "{} expected a string, found: {}", name, other),
+    }
+}
+
 pub struct StringOperations {}
 impl StringOperations {
     pub fn string_append() -> SteelVal {
@@ -220,6 +300,106 @@ impl StringOperations {
         })
     }
 
+    pub fn regex() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "regex takes one argument");
+            }
+
+            match regex::Regex::new(string_arg("regex", &args[0])?) {
+                Ok(r) => Regex(r).into_steelval(),
+                Err(e) => stop!(ContractViolation => "regex could not compile the pattern: {}", e),
+            }
+        })
+    }
+
+    pub fn is_regex() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "regex? takes one argument");
+            }
+
+            Ok(SteelVal::BoolV(as_regex(&args[0]).is_some()))
+        })
+    }
+
+    /// (string-contains? string needle) where the needle is a string or a regex
+    pub fn string_contains() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "string-contains? takes two arguments");
+            }
+
+            let haystack = string_arg("string-contains?", &args[0])?;
+            let needle = Needle::new("string-contains?", &args[1])?;
+            Ok(SteelVal::BoolV(needle.find(haystack).is_some()))
+        })
+    }
+
+    /// (string-index-of string needle) returns the byte offset of the first match, or #false
+    pub fn string_index_of() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "string-index-of takes two arguments");
+            }
+
+            let haystack = string_arg("string-index-of", &args[0])?;
+            let needle = Needle::new("string-index-of", &args[1])?;
+            Ok(needle
+                .find(haystack)
+                .map(|i| SteelVal::IntV(i as isize))
+                .unwrap_or(SteelVal::BoolV(false)))
+        })
+    }
+
+    /// (string-split string delimiter) returns the list of strings between the delimiters
+    pub fn string_split() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "string-split takes two arguments");
+            }
+
+            let haystack = string_arg("string-split", &args[0])?;
+            let delimiter = Needle::new("string-split", &args[1])?.non_empty("string-split")?;
+
+            let mut start = 0;
+            let mut pieces = Vec::new();
+            for (from, to) in delimiter.ranges(haystack) {
+                pieces.push(SteelVal::StringV(haystack[start..from].to_string().into()));
+                start = to;
+            }
+            pieces.push(SteelVal::StringV(haystack[start..].to_string().into()));
+
+            ListOperations::built_in_list_func()(&pieces)
+        })
+    }
+
+    /// (string-replace-all string pattern replacement), where the replacement is used as is,
+    /// even when the pattern is a regex
+    pub fn string_replace_all() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "string-replace-all takes three arguments");
+            }
+
+            let haystack = string_arg("string-replace-all", &args[0])?;
+            let pattern =
+                Needle::new("string-replace-all", &args[1])?.non_empty("string-replace-all")?;
+            let replacement = string_arg("string-replace-all", &args[2])?;
+
+            let mut start = 0;
+            let mut replaced = String::with_capacity(haystack.len());
+            for (from, to) in pattern.ranges(haystack) {
+                replaced.push_str(&haystack[start..from]);
+                replaced.push_str(replacement);
+                start = to;
+            }
+            replaced.push_str(&haystack[start..]);
+
+            ok_string!(replaced)
+        })
+    }
+
     pub fn ends_with() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() == 2 {
@@ -500,4 +680,79 @@ mod string_operation_tests {
         )));
         assert_eq!(res.unwrap(), expected);
     }
+
+    fn string(s: &str) -> SteelVal {
+        SteelVal::StringV(s.into())
+    }
+
+    fn regex(pattern: &str) -> SteelVal {
+        apply_function(StringOperations::regex(), vec![string(pattern)]).unwrap()
+    }
+
+    #[test]
+    fn contains_and_index_of_take_strings_or_regexes() {
+        let contains = |needle| {
+            apply_function(
+                StringOperations::string_contains(),
+                vec![string("hello world"), needle],
+            )
+            .unwrap()
+        };
+        assert_eq!(contains(string("o w")), SteelVal::BoolV(true));
+        assert_eq!(contains(string("xyz")), SteelVal::BoolV(false));
+        assert_eq!(contains(regex("w.r")), SteelVal::BoolV(true));
+
+        let index_of = |needle| {
+            apply_function(
+                StringOperations::string_index_of(),
+                vec![string("hello world"), needle],
+            )
+            .unwrap()
+        };
+        assert_eq!(index_of(string("world")), SteelVal::IntV(6));
+        assert_eq!(index_of(regex("l+")), SteelVal::IntV(2));
+        assert_eq!(index_of(string("xyz")), SteelVal::BoolV(false));
+    }
+
+    #[test]
+    fn split_on_strings_and_regexes() {
+        let split = |s, delimiter| {
+            apply_function(StringOperations::string_split(), vec![string(s), delimiter])
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(split("a,b,,c", string(",")), r#"'("a" "b" "" "c")"#);
+        assert_eq!(split("a, b ,c", regex(r"\s*,\s*")), r#"'("a" "b" "c")"#);
+        assert_eq!(split("abc", string("::")), r#"'("abc")"#);
+
+        let res = apply_function(
+            StringOperations::string_split(),
+            vec![string("abc"), string("")],
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ContractViolation);
+        let res = apply_function(
+            StringOperations::string_split(),
+            vec![string("abc"), SteelVal::IntV(1)],
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn replace_all_matches() {
+        let replace = |pattern| {
+            apply_function(
+                StringOperations::string_replace_all(),
+                vec![string("one fish two fish"), pattern, string("cat")],
+            )
+            .unwrap()
+        };
+        assert_eq!(replace(string("fish")), string("one cat two cat"));
+        assert_eq!(replace(regex("[a-z]+o")), string("one fish cat fish"));
+    }
+
+    #[test]
+    fn bad_regexes_are_contract_violations() {
+        let res = apply_function(StringOperations::regex(), vec![string("(")]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::ContractViolation);
+    }
 }
@@ -38,6 +38,32 @@ impl SymbolOperations {
             }
         })
     }
+
+    /// The name of a keyword, without the `#:`
+    pub fn keyword_to_string() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            match args {
+                [SteelVal::KeywordV(k)] => Ok(SteelVal::StringV(Gc::clone(k))),
+                [other] => {
+                    stop!(TypeMismatch => "keyword->string expected a keyword, found {}", other)
+                }
+                _ => stop!(ArityMismatch => "keyword->string expects only one argument"),
+            }
+        })
+    }
+
+    /// The keyword with the given name, e.g. `(string->keyword "name")` is `#:name`
+    pub fn string_to_keyword() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            match args {
+                [SteelVal::StringV(s)] => Ok(SteelVal::KeywordV(Gc::clone(s))),
+                [other] => {
+                    stop!(TypeMismatch => "string->keyword expected a string, found {}", other)
+                }
+                _ => stop!(ArityMismatch => "string->keyword expects only one argument"),
+            }
+        })
+    }
 }
 
 #[cfg(test)]
@@ -71,4 +97,24 @@ mod symbol_tests {
         let expected = StringV("foo".into());
         assert_eq!(result.unwrap(), expected);
     }
+
+    #[test]
+    fn keywords_round_trip_through_strings() {
+        let keyword = apply_function(
+            SymbolOperations::string_to_keyword(),
+            vec![StringV("name".into())],
+        )
+        .unwrap();
+        assert_eq!(keyword, KeywordV("name".into()));
+        assert_eq!(keyword.to_string(), "#:name");
+
+        let name = apply_function(SymbolOperations::keyword_to_string(), vec![keyword]);
+        assert_eq!(name.unwrap(), StringV("name".into()));
+
+        let symbol = apply_function(
+            SymbolOperations::keyword_to_string(),
+            vec![SymbolV("name".into())],
+        );
+        assert!(symbol.is_err());
+    }
 }
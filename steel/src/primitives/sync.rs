@@ -0,0 +1,641 @@
+use super::threads;
+use crate::compiler::cache::CachedConstant;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Custom, IntoSteelVal, Result, SteelVal};
+use crate::steel_vm::thread::SteelThread;
+use crate::stop;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+// Values can't leave the engine they were made in, so atoms and mutexes hold plain data
+type Cell = Arc<Mutex<CachedConstant>>;
+
+fn data(value: &SteelVal, name: &str) -> Result<CachedConstant> {
+    match CachedConstant::try_from(value) {
+        Ok(data) => Ok(data),
+        Err(_) => stop!(TypeMismatch => "{} can only hold data, found: {}", name, value),
+    }
+}
+
+/// A value that can be changed from any thread. Changes are made by `swap!` and
+/// `compare-and-set!`, which never leave the atom half updated.
+#[derive(Clone)]
+pub(crate) struct Atom(Cell);
+
+impl Custom for Atom {}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<atom>")
+    }
+}
+
+/// A value that one thread at a time can read and change with `with-lock`
+#[derive(Clone)]
+pub(crate) struct SyncMutex(Cell);
+
+impl Custom for SyncMutex {}
+
+impl fmt::Debug for SyncMutex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<mutex>")
+    }
+}
+
+thread_local! {
+    // The mutexes this thread is inside of `with-lock` for, locking one of them again
+    // would wait on itself forever
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Mailbox {
+    queue: Mutex<VecDeque<Shared>>,
+    ready: Condvar,
+}
+
+/// A queue of values between threads, or between engines the host runs side by side. Any
+/// number of them can send and receive, and each value sent is received once, in the order
+/// it was sent.
+///
+/// Values are copied as they're sent, so only data can go through a channel, along with
+/// atoms, mutexes and other channels, which are shared rather than copied.
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// # use steel::rvals::IntoSteelVal;
+/// use steel::primitives::Channel;
+///
+/// let jobs = Channel::new();
+/// let results = Channel::new();
+///
+/// let worker = {
+///     let (jobs, results) = (jobs.clone(), results.clone());
+///     std::thread::spawn(move || {
+///         let mut vm = Engine::new();
+///         vm.register_value("jobs", jobs.into_steelval().unwrap());
+///         vm.register_value("results", results.into_steelval().unwrap());
+///         vm.run("(channel-send! results (* 2 (channel-recv! jobs)))").unwrap();
+///     })
+/// };
+///
+/// let mut vm = Engine::new();
+/// vm.register_value("jobs", jobs.into_steelval().unwrap());
+/// vm.run("(channel-send! jobs 21)").unwrap();
+///
+/// worker.join().unwrap();
+/// assert_eq!(results.recv().unwrap().to_string(), "42");
+/// ```
+#[derive(Clone)]
+pub struct Channel(Arc<Mailbox>);
+
+impl Custom for Channel {}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<channel>")
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel::new()
+    }
+}
+
+// How often a thread waiting on a channel checks whether it was cancelled or interrupted
+const CANCEL_CHECK: Duration = Duration::from_millis(20);
+
+impl Channel {
+    pub fn new() -> Self {
+        Channel(Arc::new(Mailbox {
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+        }))
+    }
+
+    fn queue(&self) -> MutexGuard<'_, VecDeque<Shared>> {
+        self.0.queue.lock()
+    }
+
+    /// Sends a copy of `value`, failing if it isn't something a channel can carry
+    pub fn send(&self, value: &SteelVal) -> Result<()> {
+        let value = match Shared::from_steelval(value) {
+            Some(value) => value,
+            None => {
+                stop!(TypeMismatch => "a channel can only carry data, atoms, mutexes or channels, found: {}", value)
+            }
+        };
+
+        self.queue().push_back(value);
+        self.0.ready.notify_one();
+        Ok(())
+    }
+
+    /// Waits for a value to be sent, and takes it. A thread started by `spawn` stops waiting
+    /// when it's cancelled.
+    pub fn recv(&self) -> Result<SteelVal> {
+        self.recv_or_interrupt(|| false)
+    }
+
+    // Like `recv`, and also stops waiting once `interrupted` says so
+    fn recv_or_interrupt(&self, mut interrupted: impl FnMut() -> bool) -> Result<SteelVal> {
+        let mut queue = self.queue();
+        loop {
+            if let Some(value) = queue.pop_front() {
+                drop(queue);
+                return value.into_steelval();
+            }
+            if threads::cancelled() {
+                stop!(Generic => "thread was cancelled");
+            }
+            if interrupted() {
+                stop!(Interrupted => "evaluation was interrupted");
+            }
+
+            self.0.ready.wait_for(&mut queue, CANCEL_CHECK);
+        }
+    }
+
+    /// Takes the next value if there is one, without waiting
+    pub fn try_recv(&self) -> Result<Option<SteelVal>> {
+        let value = self.queue().pop_front();
+        value.map(Shared::into_steelval).transpose()
+    }
+
+    /// The number of values sent that haven't been received yet
+    pub fn len(&self) -> usize {
+        self.queue().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What can be handed from one thread to another: data, or an atom, mutex or channel shared
+/// by both
+#[derive(Clone)]
+pub(crate) enum Shared {
+    Data(CachedConstant),
+    Atom(Atom),
+    Mutex(SyncMutex),
+    Channel(Channel),
+}
+
+impl Shared {
+    pub(crate) fn from_steelval(value: &SteelVal) -> Option<Self> {
+        if let SteelVal::Custom(c) = value {
+            let any = c.borrow().as_any();
+            if let Some(atom) = any.downcast_ref::<Atom>() {
+                return Some(Shared::Atom(atom.clone()));
+            }
+            if let Some(mutex) = any.downcast_ref::<SyncMutex>() {
+                return Some(Shared::Mutex(mutex.clone()));
+            }
+            if let Some(channel) = any.downcast_ref::<Channel>() {
+                return Some(Shared::Channel(channel.clone()));
+            }
+        }
+
+        CachedConstant::try_from(value).ok().map(Shared::Data)
+    }
+
+    pub(crate) fn into_steelval(self) -> Result<SteelVal> {
+        match self {
+            Shared::Data(data) => data.into_steelval(),
+            Shared::Atom(atom) => atom.into_steelval(),
+            Shared::Mutex(mutex) => mutex.into_steelval(),
+            Shared::Channel(channel) => channel.into_steelval(),
+        }
+    }
+}
+
+fn atom(value: &SteelVal, name: &str) -> Result<Atom> {
+    if let SteelVal::Custom(c) = value {
+        if let Some(atom) = c.borrow().as_any().downcast_ref::<Atom>() {
+            return Ok(atom.clone());
+        }
+    }
+
+    stop!(TypeMismatch => "{} expects an atom, found: {}", name, value)
+}
+
+fn channel(value: &SteelVal, name: &str) -> Result<Channel> {
+    if let SteelVal::Custom(c) = value {
+        if let Some(channel) = c.borrow().as_any().downcast_ref::<Channel>() {
+            return Ok(channel.clone());
+        }
+    }
+
+    stop!(TypeMismatch => "{} expects a channel, found: {}", name, value)
+}
+
+fn mutex(value: &SteelVal, name: &str) -> Result<SyncMutex> {
+    if let SteelVal::Custom(c) = value {
+        if let Some(mutex) = c.borrow().as_any().downcast_ref::<SyncMutex>() {
+            return Ok(mutex.clone());
+        }
+    }
+
+    stop!(TypeMismatch => "{} expects a mutex, found: {}", name, value)
+}
+
+// (swap! atom f args ...)
+// Calls `f` with the current value and `args`, and stores the result if nothing changed the atom
+// in the meantime. Otherwise `f` is called again with the new value, so it shouldn't have effects.
+fn swap(thread: &mut SteelThread, args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() < 2 {
+        stop!(ArityMismatch => "swap! takes at least two arguments");
+    }
+
+    let atom = atom(&args[0], "swap!")?;
+
+    loop {
+        let current = atom.0.lock().clone();
+
+        let mut call_args = vec![current.clone().into_steelval()?];
+        call_args.extend_from_slice(&args[2..]);
+        let result = thread.call_function(&args[1], call_args)?;
+        let next = data(&result, "an atom")?;
+
+        let mut value = atom.0.lock();
+        if *value == current {
+            *value = next;
+            return Ok(result);
+        }
+    }
+}
+
+// (with-lock mutex f)
+// Calls `f` with the value while holding the lock, and stores what it returns.
+// If `f` fails, the value is left as it was.
+fn with_lock(thread: &mut SteelThread, args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "with-lock takes two arguments");
+    }
+
+    let mutex = mutex(&args[0], "with-lock")?;
+    let key = Arc::as_ptr(&mutex.0) as usize;
+
+    if HELD.with(|x| x.borrow().contains(&key)) {
+        stop!(Generic => "with-lock: this thread already holds the lock on the mutex");
+    }
+
+    let mut value = mutex.0.lock();
+    HELD.with(|x| x.borrow_mut().push(key));
+    let result = value
+        .clone()
+        .into_steelval()
+        .and_then(|current| thread.call_function(&args[1], vec![current]))
+        .and_then(|result| data(&result, "a mutex").map(|next| (result, next)));
+    HELD.with(|x| x.borrow_mut().retain(|held| *held != key));
+
+    let (result, next) = result?;
+    *value = next;
+    Ok(result)
+}
+
+pub struct SyncOperations {}
+impl SyncOperations {
+    pub fn atom() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "atom takes one argument");
+            }
+
+            Atom(Arc::new(Mutex::new(data(&args[0], "an atom")?))).into_steelval()
+        })
+    }
+
+    pub fn atom_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "atom-ref takes one argument");
+            }
+
+            let atom = atom(&args[0], "atom-ref")?;
+            let value = atom.0.lock().clone();
+            value.into_steelval()
+        })
+    }
+
+    pub fn swap() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(swap))
+    }
+
+    /// (compare-and-set! atom old new), sets the atom to `new` if it is `equal?` to `old`,
+    /// and returns whether it did
+    pub fn compare_and_set() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "compare-and-set! takes three arguments");
+            }
+
+            let atom = atom(&args[0], "compare-and-set!")?;
+            let next = data(&args[2], "an atom")?;
+            // Nothing that isn't data can be equal to what the atom holds
+            let expected = match CachedConstant::try_from(&args[1]) {
+                Ok(expected) => expected,
+                Err(_) => return Ok(SteelVal::BoolV(false)),
+            };
+
+            let mut value = atom.0.lock();
+            if *value == expected {
+                *value = next;
+                Ok(SteelVal::BoolV(true))
+            } else {
+                Ok(SteelVal::BoolV(false))
+            }
+        })
+    }
+
+    /// (compare-and-swap! atom old new), sets the atom to `new` if it is `equal?` to `old`,
+    /// and returns what the atom held before either way
+    pub fn compare_and_swap() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "compare-and-swap! takes three arguments");
+            }
+
+            let atom = atom(&args[0], "compare-and-swap!")?;
+            let next = data(&args[2], "an atom")?;
+            let expected = CachedConstant::try_from(&args[1]).ok();
+
+            let mut value = atom.0.lock();
+            let previous = value.clone();
+            if expected.as_ref() == Some(&*value) {
+                *value = next;
+            }
+            drop(value);
+            previous.into_steelval()
+        })
+    }
+
+    pub fn mutex() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "mutex takes one argument");
+            }
+
+            SyncMutex(Arc::new(Mutex::new(data(&args[0], "a mutex")?))).into_steelval()
+        })
+    }
+
+    pub fn with_lock() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(with_lock))
+    }
+
+    pub fn make_channel() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if !args.is_empty() {
+                stop!(ArityMismatch => "make-channel takes no arguments");
+            }
+
+            Channel::new().into_steelval()
+        })
+    }
+
+    pub fn channel_send() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "channel-send! takes two arguments");
+            }
+
+            channel(&args[0], "channel-send!")?.send(&args[1])?;
+            Ok(SteelVal::Void)
+        })
+    }
+
+    /// (channel-recv! channel), waits for the next value sent on the channel, or until the
+    /// engine is interrupted
+    pub fn channel_recv() -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(
+            |thread: &mut SteelThread, args: &[SteelVal]| -> Result<SteelVal> {
+                if args.len() != 1 {
+                    stop!(ArityMismatch => "channel-recv! takes one argument");
+                }
+
+                channel(&args[0], "channel-recv!")?.recv_or_interrupt(|| thread.take_interrupt())
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod sync_tests {
+    use super::Channel;
+    use crate::rerrs::ErrorKind;
+    use crate::rvals::IntoSteelVal;
+    use crate::steel_vm::engine::Engine;
+    use std::time::Duration;
+
+    fn last(vm: &mut Engine, program: &str) -> String {
+        vm.run(program).unwrap().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn atoms_are_swapped_and_set() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define counter (atom 0))
+            (swap! counter + 5)
+            (swap! counter (lambda (x) (* x 2)))
+            (define first (compare-and-set! counter 10 (list 1 2)))
+            (define second (compare-and-set! counter 10 3))
+            (list first second (atom-ref counter))
+        "#;
+
+        assert_eq!(last(&mut vm, program), "'(#true #false (1 2))");
+    }
+
+    #[test]
+    fn atoms_and_mutexes_only_hold_data() {
+        let mut vm = Engine::new();
+        let err = vm.run("(atom (lambda (x) x))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        let err = vm
+            .run("(define a (atom 1)) (swap! a (lambda (x) (lambda () x)))")
+            .unwrap_err();
+        assert!(err.to_string().contains("can only hold data"));
+        assert_eq!(last(&mut vm, "(atom-ref a)"), "1");
+    }
+
+    #[test]
+    fn failed_updates_leave_the_mutex_unchanged() {
+        let mut vm = Engine::new();
+        vm.run("(define m (mutex 1)) (define (fail x) (car x))")
+            .unwrap();
+
+        assert_eq!(last(&mut vm, "(with-lock m (lambda (x) (+ x 1)))"), "2");
+        let err = vm.run("(with-lock m fail)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(last(&mut vm, "(with-lock m (lambda (x) x))"), "2");
+    }
+
+    #[test]
+    fn locking_a_mutex_twice_is_an_error() {
+        let mut vm = Engine::new();
+        vm.run("(define m (mutex 1))").unwrap();
+
+        let err = vm
+            .run("(with-lock m (lambda (x) (with-lock m (lambda (y) y))))")
+            .unwrap_err();
+        assert!(err.to_string().contains("already holds the lock"));
+
+        // The lock was given up by the failed call
+        assert_eq!(last(&mut vm, "(with-lock m (lambda (x) (+ x 1)))"), "2");
+    }
+
+    #[test]
+    fn threads_share_atoms_and_mutexes() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define counter (atom 0))
+            (define total (mutex 0))
+            ;; Atoms and mutexes can't be captured by a serializable-lambda, so they are
+            ;; handed to each thread as arguments
+            (define work
+                (serializable-lambda (counter total)
+                    (define (loop i)
+                        (when (< i 100)
+                            (swap! counter (lambda (x) (+ x 1)))
+                            (with-lock total (lambda (x) (+ x i)))
+                            (loop (+ i 1))))
+                    (loop 0)))
+            (with-threads
+                (spawn work counter total)
+                (spawn work counter total)
+                (spawn work counter total)
+                (spawn work counter total))
+            (list (atom-ref counter) (with-lock total (lambda (x) x)))
+        "#;
+
+        assert_eq!(last(&mut vm, program), "'(400 19800)");
+    }
+
+    #[test]
+    fn channels_carry_copies_between_threads() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define jobs (make-channel))
+            (define results (make-channel))
+            (define work
+                (serializable-lambda (jobs results)
+                    (define (loop)
+                        (define job (channel-recv! jobs))
+                        (when job
+                            (channel-send! results (* job job))
+                            (loop)))
+                    (loop)))
+            (define (collect n acc)
+                (if (= n 0) acc (collect (- n 1) (+ acc (channel-recv! results)))))
+            (with-threads
+                (spawn work jobs results)
+                (spawn work jobs results)
+                (map (lambda (x) (channel-send! jobs x)) (list 1 2 3 4))
+                (channel-send! jobs #false)
+                (channel-send! jobs #false)
+                (collect 4 0))
+        "#;
+
+        assert_eq!(last(&mut vm, program), "30");
+    }
+
+    #[test]
+    fn channels_connect_engines() {
+        let channel = Channel::new();
+        let mut sender = Engine::new();
+        let mut receiver = Engine::new();
+        sender.register_value("ch", channel.clone().into_steelval().unwrap());
+        receiver.register_value("ch", channel.clone().into_steelval().unwrap());
+
+        sender
+            .run("(channel-send! ch (list 1 \"two\" 'three)) (channel-send! ch (atom 4))")
+            .unwrap();
+        assert_eq!(channel.len(), 2);
+
+        assert_eq!(
+            last(&mut receiver, "(channel-recv! ch)"),
+            "'(1 \"two\" three)"
+        );
+        // Atoms are shared rather than copied
+        receiver
+            .run("(swap! (channel-recv! ch) (lambda (x) (+ x 1)))")
+            .unwrap();
+        assert!(channel.try_recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn channels_only_carry_data() {
+        let mut vm = Engine::new();
+        let err = vm
+            .run("(channel-send! (make-channel) (lambda (x) x))")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert!(err.to_string().contains("can only carry data"));
+
+        let err = vm.run("(channel-recv! (atom 1))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn cancelled_threads_stop_waiting_on_channels() {
+        let mut vm = Engine::new();
+        let err = vm
+            .run(
+                r#"
+                (define ch (make-channel))
+                (with-threads
+                    (define t (spawn (serializable-lambda (ch) (channel-recv! ch)) ch))
+                    (thread-cancel t)
+                    (thread-join t))
+                "#,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("thread was cancelled"));
+    }
+
+    #[test]
+    fn interrupted_engines_stop_waiting_on_channels() {
+        let mut vm = Engine::new();
+        let handle = vm.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+
+        let err = vm.run("(channel-recv! (make-channel))").unwrap_err();
+        interrupter.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+
+        // The interrupt was used up by the wait
+        assert_eq!(last(&mut vm, "(+ 1 2)"), "3");
+    }
+
+    #[test]
+    fn compare_and_swap_returns_the_previous_value() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (require "steel/sync")
+            (define box (atomic-box 1))
+            (define first (compare-and-swap! box 1 2))
+            (define second (compare-and-swap! box 1 3))
+            (define third (compare-and-swap! box (lambda () 2) 4))
+            (define m (mutex 10))
+            (lock! m (lambda (x) (+ x 1)))
+            (list first second third (atom-ref box) (with-lock m (lambda (x) x)))
+        "#;
+
+        assert_eq!(last(&mut vm, program), "'(1 2 2 2 11)");
+    }
+}
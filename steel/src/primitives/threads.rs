@@ -0,0 +1,328 @@
+use super::sync::Shared;
+use crate::rerrs::{ErrorKind, SendableErr, SteelErr};
+use crate::rvals::{Custom, IntoSteelVal, Result, SteelVal};
+use crate::steel_vm::engine::Engine;
+use crate::stop;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+// Values can't be shared between engines, so threads hand back plain data, atoms, mutexes or
+// channels
+type Outcome = std::result::Result<Shared, SendableErr>;
+
+/// The threads started inside each `with-threads` that hasn't exited yet, innermost last
+pub(crate) type ThreadScopes = Rc<RefCell<Vec<Vec<ThreadHandle>>>>;
+
+thread_local! {
+    // Set on the threads started by `spawn`, so that waiting on a channel can be cancelled
+    static CANCELLED: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Whether this is a thread started by `spawn` that has been cancelled
+pub(crate) fn cancelled() -> bool {
+    CANCELLED.with(|x| {
+        x.borrow()
+            .as_ref()
+            .map(|x| x.load(Ordering::Relaxed))
+            .unwrap_or(false)
+    })
+}
+
+struct Child {
+    cancelled: Arc<AtomicBool>,
+    receiver: Receiver<Outcome>,
+    worker: Option<JoinHandle<()>>,
+    // Kept once the thread is joined, so that it can be joined again
+    outcome: Option<Outcome>,
+}
+
+/// A thread started by `spawn`, running a closure in an engine of its own
+#[derive(Clone)]
+pub(crate) struct ThreadHandle(Rc<RefCell<Child>>);
+
+impl Custom for ThreadHandle {}
+
+impl fmt::Debug for ThreadHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<thread>")
+    }
+}
+
+impl ThreadHandle {
+    fn spawn(source: String, args: Vec<Shared>) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancelled);
+        let (sender, receiver) = mpsc::sync_channel(1);
+
+        let worker = thread::spawn(move || {
+            // The parent is gone if nothing is listening, so there is no one to tell
+            let _ = sender.send(run_thread(&source, args, flag));
+        });
+
+        ThreadHandle(Rc::new(RefCell::new(Child {
+            cancelled,
+            receiver,
+            worker: Some(worker),
+            outcome: None,
+        })))
+    }
+
+    /// Asks the thread to stop, it stops before its next instruction
+    fn cancel(&self) {
+        self.0.borrow().cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the thread to finish, and returns what it returned
+    fn join(&self) -> Result<SteelVal> {
+        let mut child = self.0.borrow_mut();
+
+        if child.outcome.is_none() {
+            let outcome = child.receiver.recv().unwrap_or_else(|_| {
+                Err(SteelErr::new(ErrorKind::Generic, "thread panicked").into())
+            });
+            if let Some(worker) = child.worker.take() {
+                let _ = worker.join();
+            }
+            child.outcome = Some(outcome);
+        }
+
+        match child.outcome.clone().unwrap() {
+            Ok(value) => value.into_steelval(),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn run_thread(source: &str, args: Vec<Shared>, cancelled: Arc<AtomicBool>) -> Outcome {
+    CANCELLED.with(|x| *x.borrow_mut() = Some(Arc::clone(&cancelled)));
+
+    let mut engine = Engine::new();
+    let flag = Arc::clone(&cancelled);
+    engine.on_progress(move |_| !flag.load(Ordering::Relaxed));
+
+    let result = args
+        .into_iter()
+        .map(Shared::into_steelval)
+        .collect::<Result<Vec<_>>>()
+        .and_then(|args| {
+            let function = engine.deserialize_closure(source)?;
+            engine.call_function(&function, args)
+        })
+        .and_then(|value| match Shared::from_steelval(&value) {
+            Some(value) => Ok(value),
+            None => {
+                stop!(TypeMismatch => "a thread can only return data, atoms, mutexes or channels, found: {}", value)
+            }
+        });
+
+    match result {
+        Err(_) if cancelled.load(Ordering::Relaxed) => {
+            Err(SteelErr::new(ErrorKind::Generic, "thread was cancelled").into())
+        }
+        result => result.map_err(SendableErr::from),
+    }
+}
+
+// Waits for every thread started in a scope. If the scope or any of its threads failed,
+// the threads still running are cancelled, and the first failure is what the scope returns.
+fn close_scope(children: Vec<ThreadHandle>, mut result: Result<SteelVal>) -> Result<SteelVal> {
+    if result.is_err() {
+        children.iter().for_each(ThreadHandle::cancel);
+    }
+
+    for child in &children {
+        if let Err(e) = child.join() {
+            if result.is_ok() {
+                children.iter().for_each(ThreadHandle::cancel);
+                result = Err(e);
+            }
+        }
+    }
+
+    result
+}
+
+pub struct ThreadOperations {}
+impl ThreadOperations {
+    pub(crate) fn spawn(scopes: ThreadScopes) -> SteelVal {
+        SteelVal::BoxedFunction(Rc::new(move |args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() {
+                stop!(ArityMismatch => "spawn takes at least one argument");
+            }
+
+            // The closure is rebuilt in the thread's engine from its source
+            let source = match &args[0] {
+                SteelVal::Closure(lambda) if lambda.serialized().is_some() => {
+                    lambda.serialized().unwrap().to_string()
+                }
+                _ => stop!(TypeMismatch => "spawn expects a closure made with serializable-lambda"),
+            };
+
+            let mut shared = Vec::with_capacity(args.len() - 1);
+            for arg in &args[1..] {
+                match Shared::from_steelval(arg) {
+                    Some(arg) => shared.push(arg),
+                    None => {
+                        stop!(TypeMismatch => "spawn can only pass data, atoms, mutexes or channels to a thread, found: {}", arg)
+                    }
+                }
+            }
+
+            let mut scopes = scopes.borrow_mut();
+            let scope = match scopes.last_mut() {
+                Some(scope) => scope,
+                None => stop!(Generic => "spawn can only be used inside of with-threads"),
+            };
+
+            let handle = ThreadHandle::spawn(source, shared);
+            scope.push(handle.clone());
+            handle.into_steelval()
+        }))
+    }
+
+    /// Runs a thunk, then joins every thread it spawned before returning
+    pub(crate) fn call_with_threads(scopes: ThreadScopes) -> SteelVal {
+        SteelVal::BuiltIn(Rc::new(move |thread, args| {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "call-with-threads takes one argument");
+            }
+
+            scopes.borrow_mut().push(Vec::new());
+            let result = thread.call_function(&args[0], Vec::new());
+            let children = scopes.borrow_mut().pop().unwrap_or_default();
+
+            close_scope(children, result)
+        }))
+    }
+
+    pub fn thread_join() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "thread-join takes one argument");
+            }
+
+            thread_handle(&args[0], "thread-join")?.join()
+        })
+    }
+
+    pub fn thread_cancel() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "thread-cancel takes one argument");
+            }
+
+            thread_handle(&args[0], "thread-cancel")?.cancel();
+            Ok(SteelVal::Void)
+        })
+    }
+}
+
+fn thread_handle(value: &SteelVal, name: &str) -> Result<ThreadHandle> {
+    if let SteelVal::Custom(c) = value {
+        if let Some(handle) = c.borrow().as_any().downcast_ref::<ThreadHandle>() {
+            return Ok(handle.clone());
+        }
+    }
+
+    stop!(TypeMismatch => "{} expects a thread, found: {}", name, value)
+}
+
+#[cfg(test)]
+mod threads_tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn threads_are_joined_when_the_scope_exits() {
+        let mut vm = Engine::new();
+        let program = r#"
+            (define results
+                (with-threads
+                    (define a (spawn (serializable-lambda () (* 6 7))))
+                    (define b (spawn (serializable-lambda () (list 1 "two" (list 3)))))
+                    (list (thread-join a) (thread-join b))))
+            results
+        "#;
+
+        let output = vm.run(program).unwrap();
+        assert_eq!(output.last().unwrap().to_string(), "'(42 (1 \"two\" (3)))");
+    }
+
+    #[test]
+    fn failed_threads_fail_the_scope() {
+        let mut vm = Engine::new();
+        // The failing thread is never joined by the body
+        let err = vm
+            .run("(with-threads (spawn (serializable-lambda () (car (list)))) 10)")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        let err = vm
+            .run("(with-threads (spawn (serializable-lambda () (lambda (x) x))))")
+            .unwrap_err();
+        assert!(err.to_string().contains("can only return data"));
+    }
+
+    #[test]
+    fn errors_in_the_scope_cancel_runaway_threads() {
+        let mut vm = Engine::new();
+        vm.run("(define (fail x) (car x))").unwrap();
+
+        let started = Instant::now();
+        let err = vm
+            .run(
+                r#"
+                (with-threads
+                    (spawn (serializable-lambda () (define (spin) (spin)) (spin)))
+                    (fail 1))
+                "#,
+            )
+            .unwrap_err();
+
+        // The error from the scope wins over the cancelled thread
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn cancelled_threads_say_so_when_joined() {
+        let mut vm = Engine::new();
+        let err = vm
+            .run(
+                r#"
+                (with-threads
+                    (define t (spawn (serializable-lambda () (define (spin) (spin)) (spin))))
+                    (thread-cancel t)
+                    (thread-join t))
+                "#,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("thread was cancelled"));
+    }
+
+    #[test]
+    fn spawn_needs_a_scope_and_a_serializable_closure() {
+        let mut vm = Engine::new();
+
+        let err = vm.run("(spawn (serializable-lambda () 1))").unwrap_err();
+        assert!(err.to_string().contains("inside of with-threads"));
+
+        let err = vm.run("(with-threads (spawn (lambda () 1)))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        let err = vm
+            .run("(with-threads (spawn (serializable-lambda (f) (f)) (lambda () 1)))")
+            .unwrap_err();
+        assert!(err.to_string().contains("can only pass data"));
+
+        // The scopes are still balanced after the errors
+        let err = vm.run("(spawn (serializable-lambda () 1))").unwrap_err();
+        assert!(err.to_string().contains("inside of with-threads"));
+    }
+}
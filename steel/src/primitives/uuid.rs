@@ -0,0 +1,211 @@
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+use crate::stop;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+// UUIDs are handed to scripts as strings in the usual lower case, hyphenated form
+fn format_uuid(bytes: [u8; 16]) -> String {
+    let mut out = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            out.push('-');
+        }
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+// Accepts the hyphenated form, the 32 digits without hyphens, and either of those in braces
+// or after `urn:uuid:`, in any case
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let s = s.strip_prefix("urn:uuid:").unwrap_or(s);
+    let s = s
+        .strip_prefix('{')
+        .and_then(|x| x.strip_suffix('}'))
+        .unwrap_or(s);
+
+    let digits = match s.len() {
+        32 => s.to_string(),
+        36 => {
+            let groups = s.split('-').map(str::len).collect::<Vec<_>>();
+            if groups != [8, 4, 4, 4, 12] {
+                return None;
+            }
+            s.replace('-', "")
+        }
+        _ => return None,
+    };
+
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let pair = digits.get(2 * i..2 * i + 2)?;
+        if !pair.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+// Every `RandomState` is keyed differently, so hashing a counter and the time with a fresh one
+// gives bits nobody can guess ahead of time. They're fine for ids, not for secrets.
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+fn random_uuid() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&random_u64().to_be_bytes());
+    bytes[8..].copy_from_slice(&random_u64().to_be_bytes());
+
+    // Version 4, variant 1
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes
+}
+
+fn uuid_arg(name: &str, args: &[SteelVal]) -> Result<[u8; 16]> {
+    if args.len() != 1 {
+        stop!(ArityMismatch => "{} takes one argument", name);
+    }
+
+    match &args[0] {
+        SteelVal::StringV(s) => match parse_uuid(s) {
+            Some(bytes) => Ok(bytes),
+            None => {
+                stop!(ConversionError => "{} expected a uuid, found {:?}", name, s.as_str())
+            }
+        },
+        other => stop!(TypeMismatch => "{} expected a string, found {}", name, other),
+    }
+}
+
+/// The functions of the `steel/uuid` module
+pub struct UuidOperations {}
+impl UuidOperations {
+    /// `(uuid/v4)` makes a new random uuid
+    pub fn v4() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if !args.is_empty() {
+                stop!(ArityMismatch => "uuid/v4 takes no arguments");
+            }
+            Ok(SteelVal::StringV(format_uuid(random_uuid()).into()))
+        })
+    }
+
+    /// `(uuid/nil)` is the uuid with every bit unset
+    pub fn nil() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if !args.is_empty() {
+                stop!(ArityMismatch => "uuid/nil takes no arguments");
+            }
+            Ok(SteelVal::StringV(format_uuid([0; 16]).into()))
+        })
+    }
+
+    /// `(uuid/parse s)` reads a uuid written any of the usual ways, giving it back in the lower
+    /// case, hyphenated form
+    pub fn parse() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let bytes = uuid_arg("uuid/parse", args)?;
+            Ok(SteelVal::StringV(format_uuid(bytes).into()))
+        })
+    }
+
+    /// `(uuid/valid? s)` checks whether `uuid/parse` would accept `s`
+    pub fn is_valid() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "uuid/valid? takes one argument");
+            }
+            Ok(SteelVal::BoolV(match &args[0] {
+                SteelVal::StringV(s) => parse_uuid(s).is_some(),
+                _ => false,
+            }))
+        })
+    }
+
+    /// `(uuid/version s)` is the version of the uuid, 4 for random ones
+    pub fn version() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            let bytes = uuid_arg("uuid/version", args)?;
+            Ok(SteelVal::IntV((bytes[6] >> 4) as isize))
+        })
+    }
+}
+
+#[cfg(test)]
+mod uuid_tests {
+    use super::*;
+    use crate::throw;
+
+    use crate::rvals::SteelVal::*;
+
+    fn apply_function(func: SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        func.func_or_else(throw!(BadSyntax => "uuid tests"))
+            .unwrap()(&args)
+    }
+
+    #[test]
+    fn random_uuids_are_version_4() {
+        let first = apply_function(UuidOperations::v4(), vec![]).unwrap();
+        let second = apply_function(UuidOperations::v4(), vec![]).unwrap();
+        assert_ne!(first, second);
+
+        let s = match &first {
+            StringV(s) => s.to_string(),
+            _ => panic!("uuid/v4 should make a string"),
+        };
+        assert_eq!(s.len(), 36);
+        assert!(matches!(&s[19..20], "8" | "9" | "a" | "b"));
+        assert_eq!(
+            apply_function(UuidOperations::version(), vec![first]).unwrap(),
+            IntV(4)
+        );
+    }
+
+    #[test]
+    fn uuids_are_parsed_into_one_form() {
+        let canonical = StringV("67e55044-10b1-426f-9247-bb680e5fe0c8".into());
+        for s in &[
+            "67E55044-10B1-426F-9247-BB680E5FE0C8",
+            "67e5504410b1426f9247bb680e5fe0c8",
+            "{67e55044-10b1-426f-9247-bb680e5fe0c8}",
+            "urn:uuid:67e55044-10b1-426f-9247-bb680e5fe0c8",
+        ] {
+            assert_eq!(
+                apply_function(UuidOperations::parse(), vec![StringV((*s).into())]).unwrap(),
+                canonical
+            );
+        }
+
+        for s in &[
+            "",
+            "67e55044-10b1-426f-9247",
+            "67e5504410b1-426f-9247-bb680e5fe0c8x",
+        ] {
+            let res = apply_function(UuidOperations::parse(), vec![StringV((*s).into())]);
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::ConversionError);
+            assert_eq!(
+                apply_function(UuidOperations::is_valid(), vec![StringV((*s).into())]).unwrap(),
+                BoolV(false)
+            );
+        }
+
+        assert_eq!(
+            apply_function(UuidOperations::nil(), vec![]).unwrap(),
+            StringV("00000000-0000-0000-0000-000000000000".into())
+        );
+    }
+}
@@ -186,6 +186,37 @@ impl VectorOperations {
         })
     }
 
+    pub fn vec_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 {
+                stop!(ArityMismatch => "vector-ref takes two arguments");
+            }
+            match (&args[0], &args[1]) {
+                (SteelVal::VectorV(v), SteelVal::IntV(idx)) => {
+                    if *idx < 0 || *idx as usize >= v.len() {
+                        stop!(ContractViolation => "vector-ref index {} is out of bounds for a vector of length {}", idx, v.len());
+                    }
+                    Ok(v[*idx as usize].clone())
+                }
+                (v, idx) => {
+                    stop!(TypeMismatch => "vector-ref expects a vector and an index, given: {} {}", v, idx)
+                }
+            }
+        })
+    }
+
+    pub fn vec_length() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "vector-length takes one argument");
+            }
+            match &args[0] {
+                SteelVal::VectorV(v) => Ok(SteelVal::IntV(v.len() as isize)),
+                e => stop!(TypeMismatch => "vector-length takes a vector, given: {}", e),
+            }
+        })
+    }
+
     pub fn list_vec_null() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() == 1 {
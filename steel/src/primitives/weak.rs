@@ -0,0 +1,382 @@
+use crate::gc::{Gc, WeakGc};
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{ByteCodeLambda, ConsCell, Custom, CustomType, IntoSteelVal, Result, SteelVal};
+use crate::stop;
+use crate::values::structs::SteelStruct;
+
+use im_rc::{HashMap, HashSet, Vector};
+use std::cell::RefCell;
+use std::collections::HashMap as StdHashMap;
+use std::fmt;
+use std::rc::Rc;
+
+macro_rules! weak_values {
+    ($($variant:ident($ty:ty)),* $(,)?) => {
+        /// A value that is only held on to as long as something else holds on to it
+        #[derive(Clone)]
+        pub(crate) enum WeakValue {
+            // Numbers, symbols and the like are never collected, so they are held on to
+            Strong(SteelVal),
+            $($variant(WeakGc<$ty>),)*
+        }
+
+        impl WeakValue {
+            pub(crate) fn new(value: &SteelVal) -> Self {
+                match value {
+                    $(SteelVal::$variant(x) => WeakValue::$variant(Gc::downgrade(x)),)*
+                    other => WeakValue::Strong(other.clone()),
+                }
+            }
+
+            /// The value, or `None` once it has been collected
+            pub(crate) fn upgrade(&self) -> Option<SteelVal> {
+                match self {
+                    WeakValue::Strong(value) => Some(value.clone()),
+                    $(WeakValue::$variant(x) => x.upgrade().map(SteelVal::$variant),)*
+                }
+            }
+        }
+    };
+}
+
+weak_values! {
+    Pair(ConsCell),
+    VectorV(Vector<SteelVal>),
+    StringV(String),
+    Custom(CustomCell),
+    HashMapV(HashMap<SteelVal, SteelVal>),
+    HashSetV(HashSet<SteelVal>),
+    StructV(SteelStruct),
+    Closure(ByteCodeLambda),
+    BoxV(RefCell<SteelVal>),
+}
+
+#[derive(Clone)]
+pub(crate) struct WeakBox(WeakValue);
+
+impl Custom for WeakBox {}
+
+impl fmt::Debug for WeakBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "weak-box")
+    }
+}
+
+fn weak_box(value: &SteelVal) -> Option<WeakBox> {
+    if let SteelVal::Custom(c) = value {
+        return c.borrow().as_any().downcast_ref::<WeakBox>().cloned();
+    }
+    None
+}
+
+type CustomCell = RefCell<Box<dyn CustomType>>;
+
+/// A cache of values derived from custom values, which doesn't keep the custom values alive.
+/// Each entry goes away once its key is collected. Keys are compared by identity.
+#[derive(Clone, Default)]
+pub(crate) struct WeakCache(Rc<RefCell<WeakEntries>>);
+
+#[derive(Default)]
+struct WeakEntries {
+    // Keyed by the address of the custom value, which the weak reference keeps from being
+    // handed out again until the entry is gone
+    entries: StdHashMap<usize, (WeakGc<CustomCell>, SteelVal)>,
+    // How many entries were alive when the collected ones were last cleared out
+    live: usize,
+}
+
+impl WeakEntries {
+    fn remove_collected(&mut self) {
+        self.entries.retain(|_, (key, _)| key.upgrade().is_some());
+        self.live = self.entries.len();
+    }
+}
+
+impl Custom for WeakCache {}
+
+impl fmt::Debug for WeakCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "weak-cache")
+    }
+}
+
+fn weak_cache(value: &SteelVal, function: &str) -> Result<WeakCache> {
+    if let SteelVal::Custom(c) = value {
+        if let Some(cache) = c.borrow().as_any().downcast_ref::<WeakCache>() {
+            return Ok(cache.clone());
+        }
+    }
+    stop!(TypeMismatch => "{} expects a weak cache, found: {}", function, value)
+}
+
+fn weak_cache_key<'a>(value: &'a SteelVal, function: &str) -> Result<&'a Gc<CustomCell>> {
+    match value {
+        SteelVal::Custom(c) => Ok(c),
+        _ => {
+            stop!(TypeMismatch => "{} expects a custom value as the key, found: {}", function, value)
+        }
+    }
+}
+type Finalizer = Box<dyn FnOnce()>;
+
+/// Functions given to [`Engine::register_finalizer`](crate::steel_vm::engine::Engine::register_finalizer),
+/// waiting for their custom value to be collected
+#[derive(Default)]
+pub(crate) struct Finalizers {
+    pending: Vec<(WeakGc<CustomCell>, Finalizer)>,
+}
+
+impl Finalizers {
+    pub(crate) fn register(&mut self, value: &SteelVal, finalizer: Finalizer) -> Result<()> {
+        match value {
+            SteelVal::Custom(c) => {
+                self.pending.push((Gc::downgrade(c), finalizer));
+                Ok(())
+            }
+            _ => {
+                stop!(TypeMismatch => "finalizers can only be registered for custom values, found: {}", value)
+            }
+        }
+    }
+
+    /// Runs the finalizers of the values that have been collected, returning how many ran
+    pub(crate) fn run_collected(&mut self) -> usize {
+        let (collected, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(value, _)| value.upgrade().is_none());
+        self.pending = pending;
+
+        let count = collected.len();
+        for (_, finalizer) in collected {
+            finalizer();
+        }
+        count
+    }
+}
+
+pub struct WeakOperations {}
+impl WeakOperations {
+    pub fn weak_box() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "weak-box takes one argument");
+            }
+
+            WeakBox(WeakValue::new(&args[0])).into_steelval()
+        })
+    }
+
+    /// (weak-box-value box) returns the value, or #false once it has been collected.
+    /// (weak-box-value box default) returns `default` instead.
+    pub fn weak_box_value() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.is_empty() || args.len() > 2 {
+                stop!(ArityMismatch => "weak-box-value takes one or two arguments");
+            }
+
+            let weak = match weak_box(&args[0]) {
+                Some(weak) => weak,
+                None => {
+                    stop!(TypeMismatch => "weak-box-value expects a weak box, found: {}", &args[0])
+                }
+            };
+
+            Ok(weak
+                .0
+                .upgrade()
+                .unwrap_or_else(|| args.get(1).cloned().unwrap_or(SteelVal::BoolV(false))))
+        })
+    }
+
+    pub fn is_weak_box() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "weak-box? takes one argument");
+            }
+
+            Ok(SteelVal::BoolV(weak_box(&args[0]).is_some()))
+        })
+    }
+
+    pub fn make_weak_cache() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if !args.is_empty() {
+                stop!(ArityMismatch => "make-weak-cache takes no arguments");
+            }
+
+            WeakCache::default().into_steelval()
+        })
+    }
+
+    /// (weak-cache-ref cache key) returns the value stored for `key`, or #false if there isn't
+    /// one. (weak-cache-ref cache key default) returns `default` instead.
+    pub fn weak_cache_ref() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 2 && args.len() != 3 {
+                stop!(ArityMismatch => "weak-cache-ref takes two or three arguments");
+            }
+
+            let cache = weak_cache(&args[0], "weak-cache-ref")?;
+            let key = weak_cache_key(&args[1], "weak-cache-ref")?;
+
+            let value = match cache.0.borrow().entries.get(&(key.as_ptr() as usize)) {
+                Some((_, value)) => value.clone(),
+                None => args.get(2).cloned().unwrap_or(SteelVal::BoolV(false)),
+            };
+            Ok(value)
+        })
+    }
+
+    /// (weak-cache-set! cache key value) stores `value` for `key`, until `key` is collected.
+    /// The value itself is held on to, so it shouldn't refer back to the key.
+    pub fn weak_cache_set() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 3 {
+                stop!(ArityMismatch => "weak-cache-set! takes three arguments");
+            }
+
+            let cache = weak_cache(&args[0], "weak-cache-set!")?;
+            let key = weak_cache_key(&args[1], "weak-cache-set!")?;
+
+            let mut cache = cache.0.borrow_mut();
+            // Collected keys are only cleared out once the cache has doubled, so that setting
+            // stays cheap on average
+            if cache.entries.len() >= 2 * cache.live.max(16) {
+                cache.remove_collected();
+            }
+            cache
+                .entries
+                .insert(key.as_ptr() as usize, (Gc::downgrade(key), args[2].clone()));
+            Ok(SteelVal::Void)
+        })
+    }
+
+    /// (weak-cache-count cache) returns how many entries have keys that are still alive
+    pub fn weak_cache_count() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() != 1 {
+                stop!(ArityMismatch => "weak-cache-count takes one argument");
+            }
+
+            let cache = weak_cache(&args[0], "weak-cache-count")?;
+            let mut cache = cache.0.borrow_mut();
+            cache.remove_collected();
+            Ok(SteelVal::IntV(cache.live as isize))
+        })
+    }
+}
+
+#[cfg(test)]
+mod weak_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn last(vm: &mut Engine, program: &str) -> String {
+        vm.run(program).unwrap().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn weak_boxes_let_go_of_their_values() {
+        let mut vm = Engine::new();
+        // `n` comes from an earlier run so that the list isn't folded into a constant,
+        // which would keep it alive for as long as the program is around
+        vm.run("(define n 3)").unwrap();
+        vm.run(
+            r#"
+            (define value (list 1 2 n))
+            (define weak (weak-box value))
+            (define number (weak-box 10))
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(last(&mut vm, "(weak-box-value weak)"), "'(1 2 3)");
+        vm.run("(set! value 0)").unwrap();
+        assert_eq!(last(&mut vm, "(weak-box-value weak)"), "#false");
+        assert_eq!(last(&mut vm, "(weak-box-value weak 5)"), "5");
+
+        // Numbers are never collected
+        assert_eq!(last(&mut vm, "(weak-box-value number)"), "10");
+        assert_eq!(last(&mut vm, "(weak-box? weak)"), "#true");
+        assert_eq!(last(&mut vm, "(weak-box? value)"), "#false");
+    }
+
+    #[derive(Clone, Debug)]
+    struct Handle;
+    impl Custom for Handle {}
+
+    #[test]
+    fn finalizers_run_once_the_value_is_collected() {
+        let mut vm = Engine::new();
+        let closed = Rc::new(Cell::new(0));
+
+        let handle = Handle.into_steelval().unwrap();
+        let counter = Rc::clone(&closed);
+        vm.register_finalizer(&handle, move || counter.set(counter.get() + 1))
+            .unwrap();
+        vm.register_value("handle", handle);
+
+        vm.run("(define kept (list handle)) (set! handle 0)")
+            .unwrap();
+        assert_eq!(vm.run_finalizers(), 0);
+        assert_eq!(closed.get(), 0);
+
+        // Finalizers run once the top level expression that let go of the value is done
+        vm.run("(begin (set! kept 0) 0)").unwrap();
+        assert_eq!(closed.get(), 1);
+        assert_eq!(vm.run_finalizers(), 0);
+        assert_eq!(closed.get(), 1);
+
+        // `set!` hands back the old value, so it is only collected once the result is dropped
+        let handle = Handle.into_steelval().unwrap();
+        let counter = Rc::clone(&closed);
+        vm.register_finalizer(&handle, move || counter.set(counter.get() + 1))
+            .unwrap();
+        vm.register_value("other", handle);
+        vm.run("(set! other 0)").unwrap();
+        assert_eq!(vm.run_finalizers(), 1);
+        assert_eq!(closed.get(), 2);
+
+        let err = vm
+            .register_finalizer(&SteelVal::IntV(1), || {})
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn weak_caches_drop_entries_with_their_keys() {
+        let mut vm = Engine::new();
+        vm.register_value("first", Handle.into_steelval().unwrap());
+        vm.register_value("second", Handle.into_steelval().unwrap());
+
+        vm.run(
+            r#"
+            (define cache (make-weak-cache))
+            (weak-cache-set! cache first "derived from first")
+            (weak-cache-set! cache second (list 1 2))
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            last(&mut vm, "(weak-cache-ref cache first)"),
+            "\"derived from first\""
+        );
+        assert_eq!(last(&mut vm, "(weak-cache-count cache)"), "2");
+
+        vm.run("(begin (set! first 0) 0)").unwrap();
+        assert_eq!(last(&mut vm, "(weak-cache-count cache)"), "1");
+        assert_eq!(last(&mut vm, "(weak-cache-ref cache second)"), "'(1 2)");
+
+        vm.register_value("third", Handle.into_steelval().unwrap());
+        assert_eq!(
+            last(&mut vm, "(weak-cache-ref cache third 'missing)"),
+            "'missing"
+        );
+
+        let err = vm.run("(weak-cache-set! cache 1 2)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+}
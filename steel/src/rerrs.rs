@@ -3,21 +3,102 @@ use std::{convert::Infallible, fmt::Formatter, path::PathBuf};
 use thiserror::Error;
 
 use codespan_reporting::diagnostic::{Diagnostic, Label};
-use codespan_reporting::files::SimpleFile;
+use codespan_reporting::files::{Files, SimpleFile};
 use codespan_reporting::term;
 use codespan_reporting::term::termcolor::{ColorChoice, NoColor, StandardStream};
 
 use crate::parser::span::Span;
 
+use serde_json::json;
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::fmt;
+use std::io::Write;
 use std::rc::Rc;
+use std::str::FromStr;
+
+/// The message an error carries. Errors are often caught and thrown away without ever being
+/// shown, so messages are kept as cheap as possible until something asks for the text.
+#[derive(Clone)]
+pub enum ErrorMessage {
+    /// A fixed message, which doesn't need allocating
+    Static(&'static str),
+    Owned(String),
+    /// Formatted the first time anything asks for it, and kept for every time after that
+    Deferred(Rc<DeferredMessage>),
+}
+
+impl ErrorMessage {
+    pub fn deferred<F: Fn() -> String + 'static>(format: F) -> Self {
+        ErrorMessage::Deferred(Rc::new(DeferredMessage {
+            format: Box::new(format),
+            text: OnceCell::new(),
+        }))
+    }
+
+    pub fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            ErrorMessage::Static(message) => Cow::Borrowed(message),
+            ErrorMessage::Owned(message) => Cow::Borrowed(message),
+            ErrorMessage::Deferred(message) => Cow::Borrowed(message.text()),
+        }
+    }
+}
+
+/// The text of an [`ErrorMessage::Deferred`], along with how to format it
+pub struct DeferredMessage {
+    format: Box<dyn Fn() -> String>,
+    text: OnceCell<String>,
+}
+
+impl DeferredMessage {
+    fn text(&self) -> &str {
+        self.text.get_or_init(|| (self.format)())
+    }
+}
+
+impl From<&'static str> for ErrorMessage {
+    fn from(message: &'static str) -> Self {
+        ErrorMessage::Static(message)
+    }
+}
+
+impl From<String> for ErrorMessage {
+    fn from(message: String) -> Self {
+        ErrorMessage::Owned(message)
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorMessage::Static(message) => write!(f, "{}", message),
+            ErrorMessage::Owned(message) => write!(f, "{}", message),
+            ErrorMessage::Deferred(message) => write!(f, "{}", message.text()),
+        }
+    }
+}
+
+impl fmt::Debug for ErrorMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Two messages are the same if they read the same, however they are stored
+impl PartialEq for ErrorMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 struct Repr {
     pub kind: ErrorKind,
-    pub message: String,
+    pub message: ErrorMessage,
     pub span: Option<Span>,
     pub source: Option<Rc<PathBuf>>,
+    pub call: Option<Box<CallError>>,
 }
 
 impl Repr {
@@ -32,6 +113,63 @@ impl fmt::Display for Repr {
     }
 }
 
+/// How the arguments given to a function registered from Rust were wrong
+#[derive(Clone, Debug, PartialEq)]
+pub enum CallError {
+    /// It was called with the wrong number of arguments
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The argument at `index`, counting from 0, couldn't be converted to the type the
+    /// function takes
+    Argument {
+        function: String,
+        index: usize,
+        /// The Rust type it takes, without the module path
+        expected: String,
+        /// The argument as printed, cut short if it is long
+        found: String,
+        /// Why the conversion failed
+        reason: String,
+    },
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Arity {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} expected {} argument{}, got {}",
+                function,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            CallError::Argument {
+                function,
+                index,
+                expected,
+                found,
+                reason,
+            } => write!(
+                f,
+                "{} expected {} as argument {}, found {}: {}",
+                function,
+                expected,
+                index + 1,
+                found,
+                reason
+            ),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub enum ErrorKind {
     ArityMismatch,
@@ -45,10 +183,14 @@ pub enum ErrorKind {
     Parse,
     Infallible,
     Generic,
+    /// Evaluation ran out of fuel, a budget or memory
+    ResourceExhausted,
+    /// Evaluation was stopped through an interrupt handle
+    Interrupted,
 }
 
 impl ErrorKind {
-    fn to_error_code(&self) -> &str {
+    pub(crate) fn to_error_code(self) -> &'static str {
         use ErrorKind::*;
         match self {
             ArityMismatch => "E01",
@@ -62,6 +204,8 @@ impl ErrorKind {
             Parse => "E09",
             Infallible => "E10",
             Generic => "E11",
+            ResourceExhausted => "E12",
+            Interrupted => "E13",
         }
     }
 }
@@ -76,9 +220,10 @@ impl From<std::io::Error> for Repr {
     fn from(v: std::io::Error) -> Self {
         Repr {
             kind: ErrorKind::Io,
-            message: v.to_string(),
+            message: v.to_string().into(),
             span: None,
             source: None,
+            call: None,
         }
     }
 }
@@ -99,9 +244,10 @@ impl From<Infallible> for Repr {
     fn from(v: Infallible) -> Self {
         Repr {
             kind: ErrorKind::Infallible,
-            message: v.to_string(),
+            message: v.to_string().into(),
             span: None,
             source: None,
+            call: None,
         }
     }
 }
@@ -125,13 +271,57 @@ impl From<ParseError> for Repr {
 
         Repr {
             kind: ErrorKind::Parse,
-            message: v.to_string(),
+            message: v.to_string().into(),
             span,
             source: source.clone(),
+            call: None,
+        }
+    }
+}
+
+/// An error that can be sent to another thread, made from a [`SteelErr`] with `into`. Errors
+/// hold their source path in an `Rc`, so this is how they get across.
+#[derive(Clone, Debug)]
+pub struct SendableErr {
+    kind: ErrorKind,
+    message: String,
+    span: Option<Span>,
+    source: Option<PathBuf>,
+    call: Option<Box<CallError>>,
+}
+
+impl From<SteelErr> for SendableErr {
+    fn from(err: SteelErr) -> Self {
+        let Repr {
+            kind,
+            message,
+            span,
+            source,
+            call,
+        } = err.repr;
+
+        SendableErr {
+            kind,
+            message: message.to_string(),
+            span,
+            source: source.map(|x| x.as_ref().clone()),
+            call,
         }
     }
 }
 
+impl From<SendableErr> for SteelErr {
+    fn from(err: SendableErr) -> Self {
+        SteelErr::_new(Repr {
+            kind: err.kind,
+            message: err.message.into(),
+            span: err.span,
+            source: err.source.map(Rc::new),
+            call: err.call,
+        })
+    }
+}
+
 #[derive(Debug, Error, Clone, PartialEq)]
 pub struct SteelErr {
     repr: Repr,
@@ -152,17 +342,46 @@ impl SteelErr {
         self.repr.kind
     }
 
-    pub fn new(kind: ErrorKind, message: String) -> Self {
+    pub fn span(&self) -> Option<Span> {
+        self.repr.span
+    }
+
+    pub fn new(kind: ErrorKind, message: impl Into<ErrorMessage>) -> Self {
         SteelErr {
             repr: Repr {
                 kind,
-                message,
+                message: message.into(),
                 span: None,
                 source: None,
+                call: None,
             },
         }
     }
 
+    /// An error from calling a function registered from Rust, which keeps what went wrong
+    /// alongside the message
+    pub fn from_call(kind: ErrorKind, call: CallError) -> Self {
+        let mut err = SteelErr::new(kind, call.to_string());
+        err.repr.call = Some(Box::new(call));
+        err
+    }
+
+    /// What went wrong, if this came from calling a function registered from Rust with the
+    /// wrong arguments
+    pub fn call_error(&self) -> Option<&CallError> {
+        self.repr.call.as_deref()
+    }
+
+    /// An error whose message is only formatted if it gets displayed, for errors that
+    /// are expected to be handled more often than they are reported
+    pub fn deferred<F: Fn() -> String + 'static>(kind: ErrorKind, message: F) -> Self {
+        SteelErr::new(kind, ErrorMessage::deferred(message))
+    }
+
+    pub fn message(&self) -> &ErrorMessage {
+        &self.repr.message
+    }
+
     pub fn set_span(mut self, span: Span) -> Self {
         self.repr.set_span(span);
         self
@@ -222,17 +441,254 @@ impl SteelErr {
                 (),
                 self.repr.span.unwrap_or(_error_span),
             )
-            .with_message(&self.repr.message)])
+            .with_message(self.repr.message.to_string())])
     }
 }
 
+/// How errors get reported, either for people to read or for tools to consume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Rendered with the offending source, the same as `emit_result`
+    Human,
+    /// One JSON object per error, each on its own line
+    Json,
+    /// A single SARIF 2.1.0 log holding every error, written out once reporting finishes
+    Sarif,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = SteelErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            "sarif" => Ok(ErrorFormat::Sarif),
+            _ => Err(SteelErr::new(
+                ErrorKind::Generic,
+                format!("unknown error format: {}, expected human, json or sarif", s),
+            )),
+        }
+    }
+}
+
+impl ErrorFormat {
+    /// A reporter for this format that writes to stderr
+    pub fn reporter(self) -> Box<dyn ErrorReporter> {
+        match self {
+            ErrorFormat::Human => Box::new(HumanReporter),
+            ErrorFormat::Json => Box::new(JsonReporter::new(std::io::stderr())),
+            ErrorFormat::Sarif => Box::new(SarifReporter::new(std::io::stderr())),
+        }
+    }
+}
+
+/// Somewhere to send errors to, along with the source they came from
+pub trait ErrorReporter {
+    fn report(&mut self, error: &SteelErr, file_name: &str, file_content: &str);
+
+    /// Called once there are no more errors to report
+    fn finish(&mut self) {}
+}
+
+/// Reports errors the same way as `emit_result`
+pub struct HumanReporter;
+
+impl ErrorReporter for HumanReporter {
+    fn report(&mut self, error: &SteelErr, file_name: &str, file_content: &str) {
+        error.emit_result(file_name, file_content);
+    }
+}
+
+/// Writes each error as a JSON object on its own line
+pub struct JsonReporter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonReporter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonReporter { writer }
+    }
+}
+
+impl<W: Write> ErrorReporter for JsonReporter<W> {
+    fn report(&mut self, error: &SteelErr, file_name: &str, file_content: &str) {
+        let json = error.to_json(file_name, file_content);
+        // Nothing sensible to do if the diagnostics themselves can't be written
+        let _ = writeln!(self.writer, "{}", json);
+    }
+}
+
+/// Collects errors into a SARIF log, which is written when reporting finishes
+pub struct SarifReporter<W: Write> {
+    writer: W,
+    results: Vec<serde_json::Value>,
+}
+
+impl<W: Write> SarifReporter<W> {
+    pub fn new(writer: W) -> Self {
+        SarifReporter {
+            writer,
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> ErrorReporter for SarifReporter<W> {
+    fn report(&mut self, error: &SteelErr, file_name: &str, file_content: &str) {
+        self.results
+            .push(error.to_sarif_result(file_name, file_content));
+    }
+
+    fn finish(&mut self) {
+        let log = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "steel",
+                        "informationUri": "https://github.com/mattwparas/steel",
+                    }
+                },
+                "results": std::mem::take(&mut self.results),
+            }]
+        });
+        let _ = writeln!(self.writer, "{}", log);
+    }
+}
+
+// Lines and columns start at 1, the same as in the human readable output
+struct Location {
+    line: usize,
+    column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+impl Location {
+    fn find(span: Span, file_name: &str, file_content: &str) -> Option<Self> {
+        // The span could be from some other source, like a required module
+        if span.end() > file_content.len() {
+            return None;
+        }
+
+        let file = SimpleFile::new(file_name, file_content);
+        let position = |offset: usize| -> Option<(usize, usize)> {
+            let line = file.line_index((), offset).ok()?;
+            let column = file.column_number((), line, offset).ok()?;
+            Some((line + 1, column))
+        };
+
+        let (line, column) = position(span.start())?;
+        let (end_line, end_column) = position(span.end())?;
+
+        Some(Location {
+            line,
+            column,
+            end_line,
+            end_column,
+        })
+    }
+}
+
+impl SteelErr {
+    /// The error as a JSON object, with the span resolved to lines and columns in `file_content`
+    pub fn to_json(&self, file_name: &str, file_content: &str) -> serde_json::Value {
+        let span = self.repr.span.map(|span| {
+            let mut value = json!({
+                "start": span.start(),
+                "end": span.end(),
+            });
+
+            if let Some(location) = Location::find(span, file_name, file_content) {
+                value["line"] = json!(location.line);
+                value["column"] = json!(location.column);
+                value["end_line"] = json!(location.end_line);
+                value["end_column"] = json!(location.end_column);
+            }
+
+            value
+        });
+
+        json!({
+            "file": file_name,
+            "code": self.repr.kind.to_error_code(),
+            "kind": self.repr.kind.to_string(),
+            "message": self.repr.message.to_string(),
+            "span": span,
+        })
+    }
+
+    fn to_sarif_result(&self, file_name: &str, file_content: &str) -> serde_json::Value {
+        let mut physical_location = json!({
+            "artifactLocation": { "uri": file_name },
+        });
+
+        if let Some(location) = self
+            .repr
+            .span
+            .and_then(|span| Location::find(span, file_name, file_content))
+        {
+            physical_location["region"] = json!({
+                "startLine": location.line,
+                "startColumn": location.column,
+                "endLine": location.end_line,
+                "endColumn": location.end_column,
+            });
+        }
+
+        json!({
+            "ruleId": self.repr.kind.to_error_code(),
+            "level": "error",
+            "message": {
+                "text": format!("{}: {}", self.repr.kind, self.repr.message),
+            },
+            "locations": [{ "physicalLocation": physical_location }],
+        })
+    }
+}
+
+// A message made from a format string and its arguments, formatted only when it is displayed.
+// The arguments are cloned into the message, which for values is a reference count.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! deferred_message {
+    (@bind [$($bound:ident)*] $fmt:literal,) => {
+        $crate::rerrs::ErrorMessage::deferred(move || format!($fmt, $($bound),*))
+    };
+    (@bind [$($bound:ident)*] $fmt:literal, $arg:expr, $($rest:tt)*) => {{
+        let arg = ($arg).to_owned();
+        $crate::deferred_message!(@bind [$($bound)* arg] $fmt, $($rest)*)
+    }};
+    ($fmt:literal, $($arg:expr),+) => {
+        $crate::deferred_message!(@bind [] $fmt, $($arg,)+)
+    };
+}
+
 #[macro_export]
 macro_rules! stop {
     // ($type:ident) => {
     //     return Err(SteelErr::new(ErrorKind::$type, None));
     // };
-    ($type:ident => $fmt:expr, $($arg:tt)+) => {
-        return Err(SteelErr::new(ErrorKind::$type, format!($fmt, $($arg)+)));
+    ($type:ident => $fmt:literal, $($arg:expr),+ $(,)?) => {
+        return Err(SteelErr::new(ErrorKind::$type, $crate::deferred_message!($fmt, $($arg),+)));
+    };
+    ($type:ident => $fmt:literal, $($arg:expr),+; $span:expr) => {
+        return Err(SteelErr::new(ErrorKind::$type, $crate::deferred_message!($fmt, $($arg),+)).with_span($span));
+    };
+    ($type:ident => $fmt:literal, $($arg:expr),+; $span:expr; $source:expr) => {
+        return Err(SteelErr::new(ErrorKind::$type, $crate::deferred_message!($fmt, $($arg),+)).with_span($span).with_source($source));
+    };
+    // Plain string literals are kept as they are, rather than being copied into a `String`
+    ($type:ident => $message:literal) => {
+        return Err(SteelErr::new(ErrorKind::$type, $message));
+    };
+    ($type:ident => $message:literal; $span:expr) => {
+        return Err(SteelErr::new(ErrorKind::$type, $message).with_span($span));
+    };
+    ($type:ident => $message:literal; $span:expr; $source:expr) => {
+        return Err(SteelErr::new(ErrorKind::$type, $message).with_span($span).with_source($source));
     };
     ($type:ident => $thing:expr) => {
         return Err(SteelErr::new(ErrorKind::$type, ($thing).to_string()));
@@ -250,8 +706,17 @@ macro_rules! throw {
     // ($type:ident) => {
     //     || SteelErr::$type
     // };
-    ($type:ident => $fmt:expr, $($arg:tt)+) => {
-        || SteelErr::new(ErrorKind::$type, format!($fmt, $($arg)+))
+    ($type:ident => $fmt:literal, $($arg:expr),+ $(,)?) => {
+        || SteelErr::new(ErrorKind::$type, $crate::deferred_message!($fmt, $($arg),+))
+    };
+    ($type:ident => $fmt:literal, $($arg:expr),+; $span:expr) => {
+        || SteelErr::new(ErrorKind::$type, $crate::deferred_message!($fmt, $($arg),+)).with_span($span)
+    };
+    ($type:ident => $message:literal) => {
+        || SteelErr::new(ErrorKind::$type, $message)
+    };
+    ($type:ident => $message:literal; $span:expr) => {
+        || SteelErr::new(ErrorKind::$type, $message).with_span($span)
     };
     ($type:ident => $thing:expr) => {
         || SteelErr::new(ErrorKind::$type, ($thing).to_string())
@@ -260,3 +725,162 @@ macro_rules! throw {
         || SteelErr::new(ErrorKind::$type, ($thing).to_string()).with_span($span)
     };
 }
+
+#[cfg(test)]
+mod reporter_tests {
+    use super::*;
+
+    const SOURCE: &str = "(define x 10)\n  (foo x)\n";
+
+    fn free_identifier() -> SteelErr {
+        SteelErr::new(ErrorKind::FreeIdentifier, "foo").with_span(Span::new(17, 20))
+    }
+
+    #[test]
+    fn json_lines_resolve_spans() {
+        let mut out = Vec::new();
+        let mut reporter = JsonReporter::new(&mut out);
+        reporter.report(&free_identifier(), "test.rkt", SOURCE);
+        reporter.report(
+            &SteelErr::new(ErrorKind::Generic, "no span"),
+            "test.rkt",
+            SOURCE,
+        );
+        reporter.finish();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["code"], "E02");
+        assert_eq!(lines[0]["kind"], "FreeIdentifier");
+        assert_eq!(lines[0]["span"]["line"], 2);
+        assert_eq!(lines[0]["span"]["column"], 4);
+        assert_eq!(lines[0]["span"]["end_column"], 7);
+        assert!(lines[1]["span"].is_null());
+    }
+
+    #[test]
+    fn sarif_is_written_once_finished() {
+        let mut out = Vec::new();
+        {
+            let mut reporter = SarifReporter::new(&mut out);
+            reporter.report(&free_identifier(), "test.rkt", SOURCE);
+            reporter.finish();
+        }
+
+        let log: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+
+        let result = &log["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "E02");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "test.rkt");
+        assert_eq!(location["region"]["startLine"], 2);
+    }
+
+    #[test]
+    fn spans_outside_the_source_are_left_out() {
+        let err = SteelErr::new(ErrorKind::Generic, "bad").with_span(Span::new(5, 500));
+        let json = err.to_json("test.rkt", SOURCE);
+        assert_eq!(json["span"]["start"], 5);
+        assert!(json["span"].get("line").is_none());
+    }
+
+    #[test]
+    fn error_formats_parse() {
+        assert_eq!("json".parse::<ErrorFormat>().unwrap(), ErrorFormat::Json);
+        assert_eq!("sarif".parse::<ErrorFormat>().unwrap(), ErrorFormat::Sarif);
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+}
+
+#[cfg(test)]
+mod message_tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn deferred_messages_are_formatted_when_displayed() {
+        let formatted = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&formatted);
+        let err = SteelErr::deferred(ErrorKind::TypeMismatch, move || {
+            counter.set(counter.get() + 1);
+            format!("expected {}, found {}", "list", 10)
+        });
+
+        // Handling the error doesn't look at the message
+        let handled = err.clone().set_span(Span::new(0, 1));
+        assert_eq!(handled.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(formatted.get(), 0);
+
+        assert_eq!(
+            err.to_string(),
+            "Error: TypeMismatch: expected list, found 10"
+        );
+        assert_eq!(formatted.get(), 1);
+    }
+
+    #[test]
+    fn messages_compare_by_their_text() {
+        let literal = SteelErr::new(ErrorKind::Generic, "bad value");
+        let owned = SteelErr::new(ErrorKind::Generic, format!("bad {}", "value"));
+        let deferred = SteelErr::deferred(ErrorKind::Generic, || "bad value".to_string());
+
+        assert!(matches!(literal.message(), ErrorMessage::Static(_)));
+        assert_eq!(literal, owned);
+        assert_eq!(owned, deferred);
+    }
+
+    #[test]
+    fn literal_messages_in_macros_are_not_copied() {
+        fn fails() -> crate::rvals::Result<()> {
+            stop!(Generic => "a fixed message")
+        }
+
+        let err = fails().unwrap_err();
+        assert!(matches!(
+            err.message(),
+            ErrorMessage::Static("a fixed message")
+        ));
+    }
+
+    // Counts how many times it has been displayed
+    #[derive(Clone)]
+    struct Counted(Rc<Cell<usize>>);
+
+    impl fmt::Display for Counted {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            self.0.set(self.0.get() + 1);
+            write!(f, "counted")
+        }
+    }
+
+    #[test]
+    fn formatted_messages_in_macros_are_deferred() {
+        fn fails(value: &Counted) -> crate::rvals::Result<()> {
+            stop!(TypeMismatch => "expected a number, found {}", value; Span::new(0, 1))
+        }
+
+        let counted = Counted(Rc::new(Cell::new(0)));
+        let err = fails(&counted).unwrap_err();
+        let thrown = throw!(Generic => "{} went wrong", counted)();
+        assert!(matches!(err.message(), ErrorMessage::Deferred(_)));
+        assert_eq!(counted.0.get(), 0);
+
+        assert_eq!(err.message().as_str(), "expected a number, found counted");
+        assert_eq!(thrown.message().as_str(), "counted went wrong");
+        assert_eq!(counted.0.get(), 2);
+
+        // Each message is only formatted once, however many times it is read
+        assert_eq!(
+            err.to_string(),
+            "Error: TypeMismatch: expected a number, found counted"
+        );
+        assert_eq!(err.clone().message(), err.message());
+        assert_eq!(counted.0.get(), 2);
+    }
+}
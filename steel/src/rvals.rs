@@ -1,13 +1,15 @@
 use crate::{
     core::instructions::DenseInstruction,
     gc::Gc,
+    parser::tokens::TokenType,
     rerrs::{ErrorKind, SteelErr},
-    steel_vm::vm::Continuation,
+    steel_vm::{thread::SteelThread, vm::Continuation},
     values::port::SteelPort,
     values::structs::SteelStruct,
     values::{
         contracts::{ContractType, ContractedFunction},
         lazy_stream::LazyStream,
+        signatures::Signature,
     },
 };
 
@@ -25,6 +27,8 @@ use std::{
     task::Context,
 };
 
+pub use crate::conversions::{field_by_name, fields_to_hash_map};
+
 // use std::any::Any;
 use SteelVal::*;
 
@@ -43,6 +47,7 @@ pub type FunctionSignature = fn(&[SteelVal]) -> Result<SteelVal>;
 // pub type FunctionSignature = fn(&[SteelVal]) -> Result<SteelVal>;
 pub type StructClosureSignature = fn(&[SteelVal], &SteelStruct) -> Result<SteelVal>;
 pub type BoxedFunctionSignature = Rc<dyn Fn(&[SteelVal]) -> Result<SteelVal>>;
+pub type BuiltInSignature = Rc<dyn Fn(&mut SteelThread, &[SteelVal]) -> Result<SteelVal>>;
 
 pub type BoxedAsyncFunctionSignature = Rc<dyn Fn(&[SteelVal]) -> Result<FutureResult>>;
 
@@ -128,16 +133,24 @@ there is some overhead here but I think it might be worth it?
 
 // Box<Fn(i32) -> i32>
 
-pub trait Custom {}
+pub trait Custom {
+    /// The bytes the value holds on to beyond its own size, counted toward
+    /// [`Engine::heap_bytes`](crate::steel_vm::engine::Engine::heap_bytes)
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
 
 pub trait CustomType {
     fn box_clone(&self) -> Box<dyn CustomType>;
     fn as_any(&self) -> Box<dyn Any>;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
     fn name(&self) -> String {
         (std::any::type_name::<Self>()).to_string()
     }
     fn new_steel_val(&self) -> SteelVal;
     fn display(&self) -> std::result::Result<String, std::fmt::Error>;
+    fn heap_bytes(&self) -> usize;
 }
 
 impl Clone for Box<dyn CustomType> {
@@ -159,14 +172,20 @@ impl<T: Custom + Clone + 'static + std::fmt::Debug> CustomType for T {
     fn as_any(&self) -> Box<dyn Any> {
         Box::new((*self).clone())
     }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
     fn new_steel_val(&self) -> SteelVal {
-        SteelVal::Custom(Gc::new(Box::new(self.clone())))
+        SteelVal::Custom(Gc::new(RefCell::new(Box::new(self.clone()))))
     }
     fn display(&self) -> std::result::Result<String, std::fmt::Error> {
         let mut buf = String::new();
         write!(buf, "{:?}", &self)?;
         Ok(buf)
     }
+    fn heap_bytes(&self) -> usize {
+        std::mem::size_of::<T>() + Custom::heap_bytes(self)
+    }
 }
 
 impl<T: CustomType> IntoSteelVal for T {
@@ -178,26 +197,25 @@ impl<T: CustomType> IntoSteelVal for T {
 impl<T: CustomType + Clone + 'static> FromSteelVal for T {
     fn from_steelval(val: SteelVal) -> Result<Self> {
         if let SteelVal::Custom(v) = val {
-            let left_type = v.as_any();
+            let left_type = v.borrow().as_any();
             let left: Option<T> = left_type.downcast_ref::<T>().cloned();
-            left.ok_or_else(|| {
-                let error_message = format!(
-                    "Type Mismatch: Type of SteelVal did not match the given type: {}",
-                    std::any::type_name::<Self>()
-                );
-                SteelErr::new(ErrorKind::ConversionError, error_message)
-            })
+            left.ok_or_else(custom_type_mismatch::<T>)
         } else {
-            let error_message = format!(
-                "Type Mismatch: Type of SteelVal did not match the given type: {}",
-                std::any::type_name::<Self>()
-            );
-
-            Err(SteelErr::new(ErrorKind::ConversionError, error_message))
+            Err(custom_type_mismatch::<T>())
         }
     }
 }
 
+// Conversions are tried and thrown away when picking between types, so the message waits
+fn custom_type_mismatch<T: 'static>() -> SteelErr {
+    SteelErr::deferred(ErrorKind::ConversionError, || {
+        format!(
+            "Type Mismatch: Type of SteelVal did not match the given type: {}",
+            std::any::type_name::<T>()
+        )
+    })
+}
+
 /// The entry point for turning values into SteelVals
 /// The is implemented for most primitives and collections
 /// You can also manually implement this for any type, or can optionally
@@ -245,8 +263,11 @@ pub enum SteelVal {
     // MacroV(SteelMacro),
     /// Represents a symbol, internally represented as `String`s
     SymbolV(Gc<String>),
+    /// Represents a keyword, like `#:name`, internally represented as the name without the `#:`
+    KeywordV(Gc<String>),
     /// Container for a type that implements the `Custom Type` trait. (trait object)
-    Custom(Gc<Box<dyn CustomType>>),
+    /// Behind a `RefCell` so that functions taking `&mut` of the type can update it in place
+    Custom(Gc<RefCell<Box<dyn CustomType>>>),
     // Embedded HashMap
     HashMapV(Gc<HashMap<SteelVal, SteelVal>>), // TODO wrap in GC
     // Embedded HashSet
@@ -283,8 +304,12 @@ pub enum SteelVal {
     ContractedFunction(Gc<ContractedFunction>),
     /// Custom closure
     BoxedFunction(BoxedFunctionSignature),
+    /// Custom closure that can call back into the VM it is running on
+    BuiltIn(BuiltInSignature),
     // Continuation
     ContinuationFunction(Gc<Continuation>),
+    /// Multiple return values, made with `values`. A single value is never wrapped.
+    Values(Gc<Vec<SteelVal>>),
 }
 
 // pub trait Continuation: Clone {}
@@ -375,6 +400,10 @@ impl Hash for SteelVal {
                 sym.hash(state);
                 // format!("symbol: {}")
             }
+            KeywordV(k) => {
+                "keyword".hash(state);
+                k.hash(state);
+            }
             Custom(_) => unimplemented!(),
             StructV(_) => unimplemented!(),
             // StructClosureV(_) => unimplemented!(),
@@ -422,6 +451,7 @@ impl SteelVal {
                 | VectorV(_)
                 | StringV(_)
                 | SymbolV(_)
+                | KeywordV(_)
                 | HashMapV(_)
                 | Closure(_)
         )
@@ -430,7 +460,7 @@ impl SteelVal {
     pub fn is_function(&self) -> bool {
         matches!(
             self,
-            BoxedFunction(_) | Closure(_) | FuncV(_) | ContractedFunction(_)
+            BoxedFunction(_) | BuiltIn(_) | Closure(_) | FuncV(_) | ContractedFunction(_)
         )
     }
 
@@ -534,7 +564,7 @@ impl SteelVal {
     pub fn custom_or_else<E, F: FnOnce() -> E>(
         &self,
         err: F,
-    ) -> std::result::Result<&Box<dyn CustomType>, E> {
+    ) -> std::result::Result<&RefCell<Box<dyn CustomType>>, E> {
         match self {
             Self::Custom(v) => Ok(&v),
             _ => Err(err()),
@@ -621,6 +651,7 @@ impl PartialEq for SteelVal {
             (StringV(l), StringV(r)) => l == r,
             (VectorV(l), VectorV(r)) => l == r,
             (SymbolV(l), SymbolV(r)) => l == r,
+            (KeywordV(l), KeywordV(r)) => l == r,
             (CharV(l), CharV(r)) => l == r,
             (Pair(_), Pair(_)) => collect_pair_into_vector(self) == collect_pair_into_vector(other),
             (HashSetV(l), HashSetV(r)) => l == r,
@@ -630,6 +661,7 @@ impl PartialEq for SteelVal {
             (ContractedFunction(l), ContractedFunction(r)) => l == r,
             (Contract(l), Contract(r)) => l == r,
             (IterV(l), IterV(r)) => l == r,
+            (Values(l), Values(r)) => l == r,
             //TODO
             (_, _) => false, // (l, r) => {
                              //     let left = unwrap!(l, usize);
@@ -775,6 +807,13 @@ pub struct ByteCodeLambda {
     body_exp: Rc<[DenseInstruction]>,
     arity: usize,
     upvalues: Vec<Weak<RefCell<UpValue>>>,
+    /// Source that rebuilds this closure in another engine, for closures made with `serializable-lambda`
+    serialized: Option<Rc<str>>,
+    /// How the arguments line up with the parameters, for closures with optional or keyword arguments
+    signature: Option<Rc<Signature>>,
+    /// The global whose top level definition the closure was made in, which execution stats
+    /// use to tell which module it came from
+    origin: Option<usize>,
 }
 
 impl PartialEq for ByteCodeLambda {
@@ -800,6 +839,9 @@ impl ByteCodeLambda {
             body_exp: Rc::from(body_exp.into_boxed_slice()),
             arity,
             upvalues,
+            serialized: None,
+            signature: None,
+            origin: None,
         }
     }
 
@@ -826,85 +868,292 @@ impl ByteCodeLambda {
     pub fn upvalues(&self) -> &[Weak<RefCell<UpValue>>] {
         &self.upvalues
     }
+
+    pub fn serialized(&self) -> Option<&str> {
+        self.serialized.as_deref()
+    }
+
+    pub(crate) fn set_serialized(&mut self, source: String) {
+        self.serialized = Some(source.into());
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_deref()
+    }
+
+    pub(crate) fn set_signature(&mut self, signature: Signature) {
+        self.signature = Some(Rc::new(signature));
+    }
+
+    pub(crate) fn origin(&self) -> Option<usize> {
+        self.origin
+    }
+
+    pub(crate) fn set_origin(&mut self, origin: Option<usize>) {
+        self.origin = origin;
+    }
 }
 
 impl fmt::Display for SteelVal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // at the top level, print a ' if we are
-        // trying to print a symbol or list
-        match self {
-            SymbolV(_) | Pair(_) => write!(f, "'")?,
-            VectorV(_) => write!(f, "'#")?,
-            _ => (),
-        };
-        display_helper(self, f)
+        display_top_level(self, f, PrintStyle::default())
     }
 }
 
 impl fmt::Debug for SteelVal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // at the top level, print a ' if we are
-        // trying to print a symbol or list
-        match self {
-            SymbolV(_) | Pair(_) => write!(f, "'")?,
-            VectorV(_) => write!(f, "'#")?,
-            _ => (),
-        };
-        display_helper(self, f)
+        display_top_level(self, f, PrintStyle::default())
+    }
+}
+
+/// Bounds on how much of a nested value gets printed. Anything past them is elided
+/// and printed as `...`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrintLimits {
+    /// How many levels of nested containers to print
+    pub depth: Option<usize>,
+    /// How many elements of each container to print
+    pub length: Option<usize>,
+}
+
+impl PrintLimits {
+    fn depth_exceeded(&self, depth: usize) -> bool {
+        matches!(self.depth, Some(x) if depth >= x)
+    }
+
+    fn length_exceeded(&self, length: usize) -> bool {
+        matches!(self.length, Some(x) if length >= x)
     }
 }
 
+/// How floats that hold a whole number are printed. Every other float is printed with the
+/// fewest digits that read back as the same float.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// With a decimal point, `1.0`, so that they can be told apart from integers
+    #[default]
+    Decimal,
+    /// The same way as integers, `1`
+    Integer,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct PrintStyle {
+    limits: PrintLimits,
+    floats: FloatFormat,
+}
+
+/// Displays a value within some [`PrintLimits`], see [`SteelVal::limited`]
+pub struct LimitedDisplay<'a> {
+    value: &'a SteelVal,
+    style: PrintStyle,
+}
+
+impl LimitedDisplay<'_> {
+    /// Prints floats holding a whole number in the given format
+    pub fn floats(mut self, floats: FloatFormat) -> Self {
+        self.style.floats = floats;
+        self
+    }
+}
+
+impl fmt::Display for LimitedDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display_top_level(self.value, f, self.style)
+    }
+}
+
+impl SteelVal {
+    /// Displays the value the same way as `Display`, eliding anything past the limits
+    pub fn limited(&self, limits: PrintLimits) -> LimitedDisplay<'_> {
+        LimitedDisplay {
+            value: self,
+            style: PrintStyle {
+                limits,
+                floats: FloatFormat::default(),
+            },
+        }
+    }
+}
+
+// Past this, a whole float printed as an integer would be rounded when read back
+const MAX_SAFE_INTEGER: f64 = 9007199254740992.0;
+
+// Written the way the parser reads them back, `{:?}` already gives the shortest digits that do
+fn display_float(x: f64, f: &mut fmt::Formatter, floats: FloatFormat) -> fmt::Result {
+    if x.is_nan() {
+        write!(f, "+nan.0")
+    } else if x.is_infinite() {
+        write!(f, "{}inf.0", if x > 0.0 { "+" } else { "-" })
+    } else if floats == FloatFormat::Integer && x.fract() == 0.0 && x.abs() < MAX_SAFE_INTEGER {
+        write!(f, "{}", x)
+    } else {
+        write!(f, "{:?}", x)
+    }
+}
+
+fn display_top_level(val: &SteelVal, f: &mut fmt::Formatter, style: PrintStyle) -> fmt::Result {
+    display_quoted(val, f, style, 0)
+}
+
+// Writes the items of a container separated by spaces, the container itself has already
+// been checked against the depth limit
+fn display_items<T: std::borrow::Borrow<SteelVal>>(
+    items: impl Iterator<Item = T>,
+    f: &mut fmt::Formatter,
+    style: PrintStyle,
+    depth: usize,
+) -> fmt::Result {
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        if style.limits.length_exceeded(i) {
+            return write!(f, "...");
+        }
+        display_helper(item.borrow(), f, style, depth + 1)?;
+    }
+    Ok(())
+}
+
 /// this function recursively prints lists without prepending the `'`
 /// at the beginning
-fn display_helper(val: &SteelVal, f: &mut fmt::Formatter) -> fmt::Result {
+fn display_helper(
+    val: &SteelVal,
+    f: &mut fmt::Formatter,
+    style: PrintStyle,
+    depth: usize,
+) -> fmt::Result {
+    let limited = style != PrintStyle::default();
+
+    match val {
+        VectorV(_) | Pair(_) | HashMapV(_) | HashSetV(_) | BoxV(_) | StructV(_)
+            if style.limits.depth_exceeded(depth) =>
+        {
+            return write!(f, "...");
+        }
+        _ => {}
+    }
+
     match val {
         BoolV(b) => write!(f, "#{}", b),
-        NumV(x) => write!(f, "{:?}", x),
+        NumV(x) => display_float(*x, f, style.floats),
         IntV(x) => write!(f, "{}", x),
         StringV(s) => write!(f, "\"{}\"", s),
-        CharV(c) => write!(f, "#\\{}", c),
+        CharV(c) => write!(f, "{}", TokenType::CharacterLiteral(*c)),
         FuncV(_) => write!(f, "#<function>"),
         // LambdaV(_) => write!(f, "#<lambda-function>"),
         // LambdaV(l) => write!(f, "#<{}>", l.pretty_print_closure()),
         // MacroV(_) => write!(f, "#<macro>"),
         Void => write!(f, "#<void>"),
         SymbolV(s) => write!(f, "{}", s),
+        KeywordV(k) => write!(f, "#:{}", k),
         VectorV(lst) => {
-            let mut iter = lst.iter();
             write!(f, "(")?;
-            if let Some(last) = iter.next_back() {
-                for item in iter {
-                    display_helper(item, f)?;
-                    write!(f, " ")?;
-                }
-                display_helper(last, f)?;
-            }
+            display_items(lst.iter(), f, style, depth)?;
             write!(f, ")")
         }
-        Custom(x) => write!(f, "#<{}>", x.display()?),
+        Custom(x) => write!(f, "#<{}>", x.borrow().display()?),
         Pair(_) => {
-            let v = collect_pair_into_vector(val);
-            display_helper(&v, f)
+            // Walked lazily so that a long list isn't collected just to print the start of it
+            write!(f, "(")?;
+            display_items(SteelVal::iter(val.clone()), f, style, depth)?;
+            write!(f, ")")
         }
-        StructV(s) => write!(f, "#<{}>", s.pretty_print()), // TODO
+        StructV(s) => s
+            .printing(|| {
+                write!(f, "#<{}", s.display_name())?;
+                for (i, (name, value)) in s.field_names().iter().zip(s.fields().iter()).enumerate()
+                {
+                    if style.limits.length_exceeded(i) {
+                        return write!(f, " ...>");
+                    }
+                    write!(f, " {}: ", name)?;
+                    display_helper(value, f, style, depth + 1)?;
+                }
+                write!(f, ">")
+            })
+            // Where it refers back to itself
+            .unwrap_or_else(|| write!(f, "#<{} ...>", s.display_name())),
         // StructClosureV(_) => write!(f, "#<struct-constructor>"),
         PortV(_) => write!(f, "#<port>"),
         Closure(_) => write!(f, "#<bytecode-closure>"),
+        HashMapV(hm) if limited => {
+            write!(f, "#<hashmap {{")?;
+            for (i, (key, value)) in hm.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                if style.limits.length_exceeded(i) {
+                    write!(f, "...")?;
+                    break;
+                }
+                display_quoted(key, f, style, depth + 1)?;
+                write!(f, ": ")?;
+                display_quoted(value, f, style, depth + 1)?;
+            }
+            write!(f, "}}>")
+        }
         HashMapV(hm) => write!(f, "#<hashmap {:#?}>", hm),
         IterV(_) => write!(f, "#<iterator>"),
+        HashSetV(hs) if limited => {
+            write!(f, "#<hashset {{")?;
+            for (i, value) in hs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                if style.limits.length_exceeded(i) {
+                    write!(f, "...")?;
+                    break;
+                }
+                display_quoted(value, f, style, depth + 1)?;
+            }
+            write!(f, "}}>")
+        }
         HashSetV(hs) => write!(f, "#<hashset {:?}>", hs),
         FutureFunc(_) => write!(f, "#<future-func>"),
         FutureV(_) => write!(f, "#<future>"),
         // Promise(_) => write!(f, "#<promise>"),
         StreamV(_) => write!(f, "#<stream>"),
+        BoxV(b) if limited => {
+            write!(f, "#<box ")?;
+            display_quoted(&b.borrow(), f, style, depth + 1)?;
+            write!(f, ">")
+        }
         BoxV(b) => write!(f, "#<box {:?}>", b.borrow()),
         Contract(_) => write!(f, "#<contract>"),
         ContractedFunction(_) => write!(f, "#<contracted-function>"),
-        BoxedFunction(_) => write!(f, "#<function>"),
+        BoxedFunction(_) | BuiltIn(_) => write!(f, "#<function>"),
         ContinuationFunction(_) => write!(f, "#<continuation>"),
+        // Each value on its own line, the same as if they had been returned one at a time
+        Values(values) => {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    writeln!(f)?;
+                }
+                display_quoted(value, f, style, depth)?;
+            }
+            Ok(())
+        }
     }
 }
 
+// Values at the top level, and inside of maps, sets and boxes, print a ' if we are
+// trying to print a symbol or list
+fn display_quoted(
+    val: &SteelVal,
+    f: &mut fmt::Formatter,
+    style: PrintStyle,
+    depth: usize,
+) -> fmt::Result {
+    match val {
+        SymbolV(_) | Pair(_) => write!(f, "'")?,
+        VectorV(_) => write!(f, "'#")?,
+        _ => (),
+    };
+    display_helper(val, f, style, depth)
+}
+
 pub(crate) fn collect_pair_into_vector(p: &SteelVal) -> SteelVal {
     VectorV(Gc::new(SteelVal::iter(p.clone()).collect::<Vector<_>>()))
 }
@@ -1009,3 +1258,125 @@ mod or_else_tests {
         assert!(input.symbol_or_else(throw!(Generic => "test")).is_err())
     }
 }
+
+#[cfg(test)]
+mod print_limits_tests {
+    use super::*;
+    use crate::primitives::ListOperations;
+    use im_rc::vector;
+
+    fn nested() -> SteelVal {
+        // (1 (2 (3 4 5)))
+        let inner = ListOperations::built_in_list_func_flat(&[IntV(3), IntV(4), IntV(5)]).unwrap();
+        let middle = ListOperations::built_in_list_func_flat(&[IntV(2), inner]).unwrap();
+        ListOperations::built_in_list_func_flat(&[IntV(1), middle]).unwrap()
+    }
+
+    #[test]
+    fn default_limits_print_everything() {
+        assert_eq!(nested().to_string(), "'(1 (2 (3 4 5)))");
+        assert_eq!(
+            nested().limited(PrintLimits::default()).to_string(),
+            nested().to_string()
+        );
+    }
+
+    #[test]
+    fn depth_elides_nested_containers() {
+        let limits = PrintLimits {
+            depth: Some(2),
+            length: None,
+        };
+        assert_eq!(nested().limited(limits).to_string(), "'(1 (2 ...))");
+
+        let limits = PrintLimits {
+            depth: Some(0),
+            length: None,
+        };
+        assert_eq!(nested().limited(limits).to_string(), "'...");
+        // Atoms aren't containers, so they always print
+        assert_eq!(IntV(10).limited(limits).to_string(), "10");
+    }
+
+    #[test]
+    fn length_elides_trailing_elements() {
+        let limits = PrintLimits {
+            depth: None,
+            length: Some(2),
+        };
+        assert_eq!(nested().limited(limits).to_string(), "'(1 (2 (3 4 ...)))");
+
+        let v = VectorV(Gc::new(vector![IntV(1), IntV(2), IntV(3)]));
+        assert_eq!(v.limited(limits).to_string(), "'#(1 2 ...)");
+    }
+
+    #[test]
+    fn limits_reach_into_boxes() {
+        let b = BoxV(Gc::new(RefCell::new(nested())));
+        let limits = PrintLimits {
+            depth: Some(2),
+            length: Some(1),
+        };
+        assert_eq!(b.limited(limits).to_string(), "#<box '(1 ...)>");
+    }
+}
+
+#[cfg(test)]
+mod float_printing_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn floats_print_with_the_shortest_digits_that_read_back() {
+        let mut vm = Engine::new();
+        for float in [
+            0.1,
+            1.0 / 3.0,
+            -2.5e-9,
+            1e300,
+            123456789.125,
+            f64::MAX,
+            -0.0,
+        ] {
+            let printed = NumV(float).to_string();
+            match vm.run(&printed).unwrap().pop().unwrap() {
+                NumV(read) => assert_eq!(read.to_bits(), float.to_bits(), "{}", printed),
+                other => panic!("{} was read back as {}", printed, other),
+            }
+        }
+
+        assert_eq!(NumV(0.1).to_string(), "0.1");
+        assert_eq!(NumV(f64::INFINITY).to_string(), "+inf.0");
+        assert_eq!(NumV(f64::NEG_INFINITY).to_string(), "-inf.0");
+        assert_eq!(NumV(f64::NAN).to_string(), "+nan.0");
+    }
+
+    #[test]
+    fn whole_floats_can_print_like_integers() {
+        let value = VectorV(Gc::new(im_rc::vector![
+            NumV(2.0),
+            NumV(2.5),
+            NumV(-3.0),
+            IntV(4)
+        ]));
+        let limits = PrintLimits::default();
+
+        assert_eq!(value.to_string(), "'#(2.0 2.5 -3.0 4)");
+        assert_eq!(
+            value
+                .limited(limits)
+                .floats(FloatFormat::Integer)
+                .to_string(),
+            "'#(2 2.5 -3 4)"
+        );
+
+        // Too big to be read back as the same float if printed as an integer
+        assert_eq!(
+            NumV(1e300)
+                .limited(limits)
+                .floats(FloatFormat::Integer)
+                .to_string(),
+            "1e300"
+        );
+    }
+}
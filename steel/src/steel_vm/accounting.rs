@@ -0,0 +1,245 @@
+use super::profiler::{body_span, TOP_LEVEL};
+use crate::core::instructions::DenseInstruction;
+use crate::core::opcode::OpCode;
+use crate::rvals::ByteCodeLambda;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// What code that isn't part of a required module is counted under
+const MAIN: &str = "<main>";
+
+// Required modules are defined as globals named after their path, with this in front
+const MODULE_PREFIX: &str = "###";
+
+// Function bodies are looked up by address on every call, the cache is emptied once it
+// holds this many so that bodies that are long gone aren't kept alive forever
+const SPAN_CACHE_LIMIT: usize = 4096;
+
+/// The instructions one function ran, see [`ExecutionStats`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionExecution {
+    /// The path of the module the function is defined in, or `<main>`
+    pub module: String,
+    /// The global the function is bound to, `lambda@start..end` for anonymous functions, or
+    /// `<top-level>` for the top level code of the module
+    pub name: String,
+    pub instructions: usize,
+}
+
+/// What [`Engine::execution_stats`](crate::steel_vm::engine::Engine::execution_stats) counted
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    pub instructions: usize,
+    /// Instructions run by the code of each required module, keyed by the module's path. The
+    /// code of the programs themselves is counted under `<main>`.
+    pub modules: HashMap<String, usize>,
+    /// The busiest functions come first
+    pub functions: Vec<FunctionExecution>,
+}
+
+impl ExecutionStats {
+    /// The instructions run by the module whose path ends with `path`
+    pub fn module(&self, path: &str) -> usize {
+        self.modules
+            .iter()
+            .filter(|(module, _)| module.ends_with(path))
+            .map(|(_, instructions)| instructions)
+            .sum()
+    }
+
+    /// The function bound to the global `name`
+    pub fn function(&self, name: &str) -> Option<&FunctionExecution> {
+        self.functions.iter().find(|x| x.name == name)
+    }
+}
+
+/// The global the code at `ip` in a top level expression is part of the definition of,
+/// which is the one bound by the next `BIND`
+pub(crate) fn definition_at(body: &[DenseInstruction], ip: usize) -> Option<usize> {
+    body[ip.min(body.len())..]
+        .iter()
+        .find(|x| x.op_code == OpCode::BIND)
+        .map(|x| x.payload_size as usize)
+}
+
+// Instructions are counted against the global whose definition the code came from, and the
+// function running them, `None` for top level code
+type Account = (Option<usize>, Option<(usize, usize)>);
+
+type Body = Rc<[DenseInstruction]>;
+
+/// Counts every instruction against the definition and the function it ran in. Closures
+/// remember the definition they were made in, so everything a module does is counted
+/// against the global the module is defined as.
+#[derive(Default)]
+pub(crate) struct ExecutionAccounting {
+    counts: HashMap<Account, usize>,
+    // The span of each function body by address, each held on to so that the address can't
+    // be reused
+    spans: HashMap<usize, (Body, (usize, usize))>,
+    // The top level expression that ran last, with the definition each instruction is part of
+    top_level: Option<(Body, Vec<Option<usize>>)>,
+}
+
+impl ExecutionAccounting {
+    /// Called before each instruction runs, with the function running it if it isn't top
+    /// level code
+    pub(crate) fn record_instruction(
+        &mut self,
+        body: &Rc<[DenseInstruction]>,
+        ip: usize,
+        function: Option<&ByteCodeLambda>,
+    ) {
+        let account = match function {
+            Some(function) => (function.origin(), Some(self.span(body))),
+            None => (self.definition(body, ip), None),
+        };
+        *self.counts.entry(account).or_insert(0) += 1;
+    }
+
+    fn span(&mut self, body: &Rc<[DenseInstruction]>) -> (usize, usize) {
+        let key = body.as_ptr() as usize;
+        if let Some((_, span)) = self.spans.get(&key) {
+            return *span;
+        }
+
+        if self.spans.len() >= SPAN_CACHE_LIMIT {
+            self.spans.clear();
+        }
+        let span = body_span(body);
+        self.spans.insert(key, (Rc::clone(body), span));
+        span
+    }
+
+    fn definition(&mut self, body: &Rc<[DenseInstruction]>, ip: usize) -> Option<usize> {
+        match &self.top_level {
+            Some((last, definitions)) if Rc::ptr_eq(last, body) => definitions[ip],
+            _ => {
+                let mut definitions = vec![None; body.len()];
+                let mut next = None;
+                for (i, instruction) in body.iter().enumerate().rev() {
+                    if instruction.op_code == OpCode::BIND {
+                        next = Some(instruction.payload_size as usize);
+                    }
+                    definitions[i] = next;
+                }
+
+                let definition = definitions[ip];
+                self.top_level = Some((Rc::clone(body), definitions));
+                definition
+            }
+        }
+    }
+
+    /// Puts together what was counted, `globals` names the globals and `names` gives the name
+    /// of the global each named function was bound to by the span of its body
+    pub(crate) fn finish(
+        &self,
+        globals: &[String],
+        names: &HashMap<(usize, usize), String>,
+    ) -> ExecutionStats {
+        let module = |origin: Option<usize>| {
+            origin
+                .and_then(|x| globals.get(x))
+                .and_then(|x| x.strip_prefix(MODULE_PREFIX))
+                .unwrap_or(MAIN)
+                .to_string()
+        };
+
+        let mut stats = ExecutionStats::default();
+        let mut functions: HashMap<(String, String), usize> = HashMap::new();
+
+        for ((origin, span), instructions) in &self.counts {
+            let module = module(*origin);
+            let name = match span {
+                None => TOP_LEVEL.to_string(),
+                Some(span) => names
+                    .get(span)
+                    .cloned()
+                    .unwrap_or_else(|| format!("lambda@{}..{}", span.0, span.1)),
+            };
+
+            stats.instructions += instructions;
+            *stats.modules.entry(module.clone()).or_insert(0) += instructions;
+            *functions.entry((module, name)).or_insert(0) += instructions;
+        }
+
+        stats.functions = functions
+            .into_iter()
+            .map(|((module, name), instructions)| FunctionExecution {
+                module,
+                name,
+                instructions,
+            })
+            .collect();
+        stats.functions.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then(a.name.cmp(&b.name))
+        });
+        stats
+    }
+}
+
+#[cfg(test)]
+mod accounting_tests {
+    use crate::steel_vm::engine::Engine;
+
+    const PROGRAM: &str = r#"
+        (require "tests/modules/sort.rkt")
+        (require "tests/modules/sort_export.rkt")
+        (define (shuffled n) (map (lambda (x) (modulo (* x 7919) 101)) (range 0 n)))
+    "#;
+
+    #[test]
+    fn instructions_are_counted_per_module() {
+        let mut vm = Engine::new();
+        vm.enable_execution_stats();
+        vm.run(PROGRAM).unwrap();
+        vm.reset_execution_stats();
+
+        vm.run("(merge-sort (shuffled 50))").unwrap();
+        let stats = vm.execution_stats().unwrap();
+
+        let sort = stats.module("tests/modules/sort.rkt");
+        assert!(sort > stats.modules["<main>"]);
+        assert_eq!(stats.module("tests/modules/sort_export.rkt"), 0);
+        assert_eq!(stats.instructions, stats.modules.values().sum::<usize>());
+
+        // Functions are counted in the module they were defined in
+        assert!(stats
+            .function("merge-sort")
+            .unwrap()
+            .module
+            .ends_with("sort.rkt"));
+        assert_eq!(stats.function("shuffled").unwrap().module, "<main>");
+
+        // sort_export.rkt runs its own copy of sort.rkt, which is counted as part of it
+        vm.reset_execution_stats();
+        vm.run("(sort (shuffled 50))").unwrap();
+        let stats = vm.execution_stats().unwrap();
+        assert!(stats.module("tests/modules/sort_export.rkt") > sort);
+        assert_eq!(stats.module("tests/modules/sort.rkt"), 0);
+    }
+
+    #[test]
+    fn stats_start_over_when_reset() {
+        let mut vm = Engine::new();
+        assert!(vm.execution_stats().is_none());
+
+        vm.enable_execution_stats();
+        vm.run("(define (f x) (+ x 1)) (f 1)").unwrap();
+        let before = vm.execution_stats().unwrap();
+        assert!(before.function("f").unwrap().instructions > 0);
+
+        vm.reset_execution_stats();
+        assert_eq!(vm.execution_stats().unwrap().instructions, 0);
+
+        vm.run("(f 2)").unwrap();
+        let after = vm.execution_stats().unwrap();
+        assert_eq!(
+            after.function("f").unwrap().instructions,
+            before.function("f").unwrap().instructions
+        );
+    }
+}
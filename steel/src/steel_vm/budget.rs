@@ -0,0 +1,402 @@
+use crate::gc::Gc;
+use crate::rvals::{ConsCell, SteelVal, UpValue};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Resource limits for running code in an `Engine`. A budget applies to each top level
+/// expression, or each call made through
+/// [`call_function_with_budget`](crate::steel_vm::engine::Engine::call_function_with_budget),
+/// on its own. Leaving a limit as `None` means there is no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// The most bytecode instructions that can be executed
+    pub instructions: Option<usize>,
+    /// How long the code is allowed to run for
+    pub wall_time: Option<Duration>,
+}
+
+impl Budget {
+    pub fn is_unlimited(&self) -> bool {
+        self.instructions.is_none() && self.wall_time.is_none()
+    }
+}
+
+// Reading the clock on every instruction is too slow, so the wall time is only checked
+// once every this many instructions. Has to be a power of two.
+const CLOCK_CHECK_INTERVAL: usize = 256;
+
+/// A budget that has started being spent
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Allowance {
+    budget: Budget,
+    // Instruction counts are absolute, so the count when the budget started is kept around
+    started_at: usize,
+    deadline: Option<Instant>,
+}
+
+impl Allowance {
+    pub(crate) fn start(budget: Budget, instruction_count: usize) -> Self {
+        Allowance {
+            budget,
+            started_at: instruction_count,
+            deadline: budget.wall_time.map(|x| Instant::now() + x),
+        }
+    }
+
+    /// Returns a description of the limit that was hit, if one has been
+    pub(crate) fn exceeded(&self, instruction_count: usize) -> Option<String> {
+        let spent = instruction_count - self.started_at;
+
+        if let Some(instructions) = self.budget.instructions {
+            if spent > instructions {
+                return Some(format!(
+                    "evaluation exceeded its budget of {} instructions",
+                    instructions
+                ));
+            }
+        }
+
+        match (self.deadline, self.budget.wall_time) {
+            (Some(deadline), Some(wall_time))
+                if spent & (CLOCK_CHECK_INTERVAL - 1) == 0 && Instant::now() >= deadline =>
+            {
+                Some(format!(
+                    "evaluation exceeded its budget of {:?} wall time",
+                    wall_time
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Measuring the heap means walking everything reachable, so it is never measured more often
+// than once every this many instructions
+const MIN_HEAP_CHECK_INTERVAL: usize = 4096;
+
+// About the most a single instruction allocates, like a value pushed along with a cons cell
+const BYTES_PER_INSTRUCTION: usize = 64;
+
+// How many instructions can run per value walked before the heap is measured again, while
+// there is plenty of room left under the limit
+const INSTRUCTIONS_PER_VALUE: usize = 16;
+
+/// How many instructions to wait before measuring the heap again, given what the last
+/// measurement found. The wait grows with the heap, so walking it never takes more than a
+/// constant share of the time spent running code, and shrinks as the heap gets closer to
+/// `limit`, since that is when going over matters.
+pub(crate) fn next_heap_check(meter: &HeapMeter, limit: usize) -> usize {
+    let headroom = limit.saturating_sub(meter.bytes()) / BYTES_PER_INSTRUCTION;
+    let amortized = meter.values().saturating_mul(INSTRUCTIONS_PER_VALUE);
+
+    headroom
+        .min(amortized)
+        .max(meter.values())
+        .max(MIN_HEAP_CHECK_INTERVAL)
+}
+
+const VALUE_SIZE: usize = size_of::<SteelVal>();
+
+// Estimates the bytes taken up by the values reachable from some roots. Every value counts
+// for the size of a `SteelVal`, along with what its container holds, and containers shared
+// between values are only counted once. Compiled code doesn't count for more than the value
+// pointing to it, and custom types count for what their `Custom::heap_bytes` says.
+#[derive(Default)]
+pub(crate) struct HeapMeter {
+    seen: HashSet<usize>,
+    pending: Vec<SteelVal>,
+    bytes: usize,
+    values: usize,
+}
+
+impl HeapMeter {
+    pub(crate) fn add_values<'a>(&mut self, values: impl Iterator<Item = &'a SteelVal>) {
+        for value in values {
+            self.pending.push(value.clone());
+            self.drain();
+        }
+    }
+
+    pub(crate) fn add_upvalues<'a>(
+        &mut self,
+        upvalues: impl Iterator<Item = &'a Rc<RefCell<UpValue>>>,
+    ) {
+        for upvalue in upvalues {
+            self.add_upvalue(upvalue);
+            self.drain();
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// How many values were walked to get to the estimate
+    pub(crate) fn values(&self) -> usize {
+        self.values
+    }
+
+    fn first_visit<T>(&mut self, ptr: *const T) -> bool {
+        self.seen.insert(ptr as usize)
+    }
+
+    fn add_upvalue(&mut self, upvalue: &Rc<RefCell<UpValue>>) {
+        if self.first_visit(Rc::as_ptr(upvalue)) {
+            self.bytes += size_of::<UpValue>();
+            if let Some(value) = upvalue.borrow().get_value_if_closed() {
+                self.pending.push(value.clone());
+            }
+        }
+    }
+
+    // Lists are walked iteratively so long ones don't blow the stack
+    fn add_list(&mut self, cell: &Gc<ConsCell>) {
+        let mut current = Some(cell.clone());
+        while let Some(cell) = current {
+            if !self.first_visit(cell.as_ptr()) {
+                break;
+            }
+            self.bytes += size_of::<ConsCell>();
+            self.pending.push(cell.car());
+            current = cell.cdr().clone();
+        }
+    }
+
+    fn drain(&mut self) {
+        while let Some(value) = self.pending.pop() {
+            self.bytes += VALUE_SIZE;
+            self.values += 1;
+
+            match &value {
+                SteelVal::StringV(s) | SteelVal::SymbolV(s) | SteelVal::KeywordV(s)
+                    if self.first_visit(s.as_ptr()) =>
+                {
+                    self.bytes += s.len();
+                }
+                SteelVal::Pair(cell) => self.add_list(cell),
+                SteelVal::VectorV(v) if self.first_visit(v.as_ptr()) => {
+                    self.pending.extend(v.iter().cloned());
+                }
+                SteelVal::Values(v) if self.first_visit(v.as_ptr()) => {
+                    self.pending.extend(v.iter().cloned());
+                }
+                SteelVal::HashMapV(map) if self.first_visit(map.as_ptr()) => {
+                    for (key, value) in map.iter() {
+                        self.pending.push(key.clone());
+                        self.pending.push(value.clone());
+                    }
+                }
+                SteelVal::HashSetV(set) if self.first_visit(set.as_ptr()) => {
+                    self.pending.extend(set.iter().cloned());
+                }
+                SteelVal::StructV(s) if self.first_visit(s.as_ptr()) => {
+                    self.pending.extend(s.fields().iter().cloned());
+                }
+                SteelVal::BoxV(b) if self.first_visit(b.as_ptr()) => {
+                    self.pending.push(b.borrow().clone());
+                }
+                SteelVal::Custom(c) if self.first_visit(c.as_ptr()) => {
+                    if let Ok(c) = c.try_borrow() {
+                        self.bytes += c.heap_bytes();
+                    }
+                }
+                SteelVal::Closure(c) if self.first_visit(c.as_ptr()) => {
+                    for upvalue in c.upvalues() {
+                        if let Some(upvalue) = upvalue.upgrade() {
+                            self.add_upvalue(&upvalue);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use super::*;
+    use crate::rerrs::ErrorKind;
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    const SPIN: &str = "(define (spin) (spin))";
+
+    #[test]
+    fn instruction_budget_stops_runaway_calls() {
+        let mut vm = Engine::new();
+        vm.run(SPIN).unwrap();
+        let spin = vm.extract_value("spin").unwrap();
+
+        let budget = Budget {
+            instructions: Some(1000),
+            wall_time: None,
+        };
+        let err = vm
+            .call_function_with_budget(&spin, vec![], budget)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+        assert!(err.to_string().contains("budget of 1000 instructions"));
+
+        // The engine is still usable afterwards
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn wall_time_budget_stops_runaway_calls() {
+        let mut vm = Engine::new();
+        vm.run(SPIN).unwrap();
+        let spin = vm.extract_value("spin").unwrap();
+
+        let budget = Budget {
+            instructions: None,
+            wall_time: Some(Duration::from_millis(10)),
+        };
+        let err = vm
+            .call_function_with_budget(&spin, vec![], budget)
+            .unwrap_err();
+        assert!(err.to_string().contains("wall time"));
+    }
+
+    #[test]
+    fn previous_budget_is_restored() {
+        let mut vm = Engine::new();
+        let engine_budget = Budget {
+            instructions: Some(1_000_000),
+            wall_time: None,
+        };
+        vm.set_budget(engine_budget);
+        vm.run("(define (double x) (* x 2))").unwrap();
+
+        let double = vm.extract_value("double").unwrap();
+        let budget = Budget {
+            instructions: Some(100),
+            wall_time: None,
+        };
+        let result = vm.call_function_with_budget(&double, vec![SteelVal::IntV(21)], budget);
+        assert_eq!(result.unwrap(), SteelVal::IntV(42));
+        assert_eq!(vm.budget(), engine_budget);
+
+        // Top level expressions are held to the engine's budget
+        vm.set_budget(Budget {
+            instructions: Some(100),
+            wall_time: None,
+        });
+        assert!(vm.run(SPIN).is_ok());
+        assert!(vm.run("(spin)").is_err());
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        let mut vm = Engine::new();
+        vm.run("(define (double x) (* x 2))").unwrap();
+        let double = vm.extract_value("double").unwrap();
+
+        let err = vm.call_function(&double, vec![]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArityMismatch);
+
+        let err = vm.call_function(&SteelVal::IntV(1), vec![]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn fuel_is_spent_across_expressions() {
+        let mut vm = Engine::new();
+        vm.run("(define (count-down n) (if (= n 0) 'done (count-down (- n 1))))")
+            .unwrap();
+        vm.set_fuel(Some(5_000));
+
+        let mut runs = 0;
+        let err = loop {
+            match vm.run("(count-down 100)") {
+                Ok(_) => runs += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(runs > 1);
+        assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+        assert!(err.to_string().contains("ran out of fuel"));
+        assert!(vm.run("(+ 1 2)").is_err());
+
+        vm.set_fuel(None);
+        assert_eq!(vm.fuel(), None);
+        assert_eq!(vm.run("(count-down 100)").unwrap()[0].to_string(), "'done");
+    }
+
+    #[test]
+    fn heap_limit_stops_growing_programs() {
+        let mut vm = Engine::new();
+        let base = vm.heap_bytes();
+        vm.set_max_heap_bytes(Some(base + 100_000));
+
+        let err = vm
+            .run("(define (grow lst n) (grow (cons (list n n n) lst) (+ n 1))) (grow '() 0)")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+        assert!(err.to_string().contains("bytes of memory"));
+
+        // What was built up is let go of along with the stack
+        assert!(vm.heap_bytes() < base + 10_000);
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn shared_values_are_measured_once() {
+        let mut vm = Engine::new();
+        vm.run("(define big (range 0 1000))").unwrap();
+        let once = vm.heap_bytes();
+
+        vm.run("(define same big) (define pair (list big big))")
+            .unwrap();
+        assert!(vm.heap_bytes() - once < 1_000);
+    }
+
+    #[test]
+    fn pinned_values_count_toward_the_heap() {
+        let mut vm = Engine::new();
+        let list = vm.run("(range 0 1000)").unwrap().pop().unwrap();
+        let base = vm.heap_bytes();
+
+        let pinned = vm.pin(list);
+        assert!(vm.heap_bytes() - base > 10_000);
+
+        // Held only by a pin, the list still counts toward the limit
+        vm.set_max_heap_bytes(Some(base + 1_000));
+        let err = vm.run("(+ 1 2)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+
+        drop(pinned);
+        assert_eq!(vm.heap_bytes(), base);
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn custom_values_count_what_they_hold() {
+        let mut vm = Engine::new();
+        let base = vm.heap_bytes();
+
+        vm.run("(define a (make-array 10000 0))").unwrap();
+        assert!(vm.heap_bytes() - base > 80_000);
+    }
+
+    #[test]
+    fn heap_is_measured_less_often_as_it_grows() {
+        let measure = |n: isize| {
+            let values = (0..n).map(SteelVal::IntV).collect::<Vec<_>>();
+            let mut meter = HeapMeter::default();
+            meter.add_values(values.iter());
+            meter
+        };
+        let (small, big) = (measure(10), measure(100_000));
+
+        assert_eq!(next_heap_check(&small, 1 << 30), MIN_HEAP_CHECK_INTERVAL);
+        assert!(next_heap_check(&big, 1 << 30) > 100_000);
+
+        // Close to the limit it is measured sooner, but never more often than it is walked
+        let limit = big.bytes() + 1_000;
+        assert_eq!(next_heap_check(&big, limit), 100_000);
+    }
+}
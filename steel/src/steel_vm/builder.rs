@@ -0,0 +1,79 @@
+use super::budget::Budget;
+use super::engine::Engine;
+
+/// Builds an [`Engine`] with limits on what the code it runs can use, for running code that
+/// isn't trusted. Limits are put in place once the prelude has loaded, so loading it doesn't
+/// count against them.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::builder::EngineBuilder;
+/// # use steel::rerrs::ErrorKind;
+/// let mut vm = EngineBuilder::new()
+///     .sandboxed()
+///     .with_fuel(1_000_000)
+///     .with_max_heap_bytes(1 << 20)
+///     .with_instruction_budget_per_call(100_000)
+///     .build();
+///
+/// assert!(vm.run("(+ 1 2)").is_ok());
+///
+/// let err = vm.run("(define (grow lst) (grow (cons lst lst))) (grow '())").unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EngineBuilder {
+    sandboxed: bool,
+    fuel: Option<u64>,
+    max_heap_bytes: Option<usize>,
+    budget: Budget,
+}
+
+impl EngineBuilder {
+    /// A builder for an engine with every primitive and the prelude, and no limits
+    pub fn new() -> Self {
+        EngineBuilder::default()
+    }
+
+    /// Leaves out the primitives that do IO, like [`Engine::new_sandboxed`]
+    pub fn sandboxed(mut self) -> Self {
+        self.sandboxed = true;
+        self
+    }
+
+    /// The most instructions the engine can run over its whole life, see [`Engine::set_fuel`]
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// The most memory the values the engine can reach may take up, see
+    /// [`Engine::set_max_heap_bytes`]
+    pub fn with_max_heap_bytes(mut self, bytes: usize) -> Self {
+        self.max_heap_bytes = Some(bytes);
+        self
+    }
+
+    /// The most instructions each top level expression or call into the engine can run, see
+    /// [`Engine::set_budget`]
+    pub fn with_instruction_budget_per_call(mut self, instructions: usize) -> Self {
+        self.budget.instructions = Some(instructions);
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        let mut engine = if self.sandboxed {
+            Engine::new_sandboxed()
+        } else {
+            Engine::new()
+        };
+
+        engine
+            .set_fuel(self.fuel)
+            .set_max_heap_bytes(self.max_heap_bytes)
+            .set_budget(self.budget);
+        engine
+    }
+}
@@ -26,6 +26,13 @@ use im_rc::HashMap;
 use log::debug;
 
 type SharedEnv = Rc<RefCell<ConstantEnv>>;
+type PureFunctions = std::collections::HashMap<String, Rc<LambdaFunction>>;
+
+// Upper bound on the number of expressions evaluated when folding a call to a `define/pure` function,
+// so that a non terminating (or just very expensive) function gets left for the runtime instead
+const PURE_FUNCTION_FUEL: usize = 10_000;
+// The evaluator recurses on the native stack, so deep recursion also gets left for the runtime
+const PURE_FUNCTION_MAX_DEPTH: usize = 128;
 
 struct ConstantEnv {
     bindings: HashMap<String, SteelVal>,
@@ -54,6 +61,7 @@ impl ConstantEnv {
     }
 
     fn bind(&mut self, ident: &str, value: SteelVal) {
+        self.non_constant_bound.remove(ident);
         self.bindings.insert(ident.to_owned(), value);
     }
 
@@ -94,6 +102,22 @@ impl ConstantEnv {
         }
     }
 
+    // Whether a local scope between here and the global scope binds this identifier
+    fn shadows(&self, ident: &str) -> bool {
+        match &self.parent {
+            Some(parent) => {
+                self.bindings.contains_key(ident)
+                    || self.non_constant_bound.contains(ident)
+                    || parent
+                        .upgrade()
+                        .expect("Constant environment freed early")
+                        .borrow()
+                        .shadows(ident)
+            }
+            None => false,
+        }
+    }
+
     fn unbind(&mut self, ident: &str) -> Option<()> {
         if self.bindings.get(ident).is_some() {
             self.bindings.remove(ident);
@@ -115,6 +139,7 @@ impl ConstantEnv {
 pub struct ConstantEvaluatorManager {
     global_env: SharedEnv,
     set_idents: HashSet<String>,
+    pure_functions: PureFunctions,
     pub(crate) changed: bool,
     opt_level: OptLevel,
 }
@@ -124,6 +149,7 @@ impl ConstantEvaluatorManager {
         Self {
             global_env: Rc::new(RefCell::new(ConstantEnv::root(constant_bindings))),
             set_idents: HashSet::new(),
+            pure_functions: PureFunctions::new(),
             changed: false,
             opt_level,
         }
@@ -136,25 +162,33 @@ impl ConstantEvaluatorManager {
             CollectSet::new(&mut self.set_idents).visit(expr);
         }
 
-        input
+        let global_env = &self.global_env;
+        let set_idents = &self.set_idents;
+        let pure_functions = &mut self.pure_functions;
+        let opt_level = self.opt_level;
+        let mut changed = false;
+
+        let output = input
             .into_iter()
             .map(|x| {
-                let mut eval = ConstantEvaluator::new(
-                    Rc::clone(&self.global_env),
-                    &self.set_idents,
-                    self.opt_level,
-                );
+                let mut eval =
+                    ConstantEvaluator::new(global_env, set_idents, pure_functions, opt_level);
                 let output = eval.visit(x);
-                self.changed = self.changed || eval.changed;
+                changed = changed || eval.changed;
                 output
             })
-            .collect()
+            .collect();
+
+        self.changed = changed;
+        output
     }
 }
 
 struct ConstantEvaluator<'a> {
     bindings: SharedEnv,
+    global_env: SharedEnv,
     set_idents: &'a HashSet<String>,
+    pure_functions: &'a mut PureFunctions,
     changed: bool,
     opt_level: OptLevel,
 }
@@ -170,15 +204,29 @@ fn steelval_to_atom(value: &SteelVal) -> Option<TokenType> {
     }
 }
 
+fn literal_to_steelval(t: &TokenType) -> Option<SteelVal> {
+    match t {
+        TokenType::BooleanLiteral(b) => Some((*b).into()),
+        TokenType::NumberLiteral(n) => Some(SteelVal::NumV(*n)),
+        TokenType::StringLiteral(s) => Some(SteelVal::StringV(s.clone().into())),
+        TokenType::CharacterLiteral(c) => Some(SteelVal::CharV(*c)),
+        TokenType::IntegerLiteral(n) => Some(SteelVal::IntV(*n)),
+        _ => None,
+    }
+}
+
 impl<'a> ConstantEvaluator<'a> {
     fn new(
-        bindings: Rc<RefCell<ConstantEnv>>,
+        global_env: &SharedEnv,
         set_idents: &'a HashSet<String>,
+        pure_functions: &'a mut PureFunctions,
         opt_level: OptLevel,
     ) -> Self {
         Self {
-            bindings,
+            bindings: Rc::clone(global_env),
+            global_env: Rc::clone(global_env),
             set_idents,
+            pure_functions,
             changed: false,
             opt_level,
         }
@@ -197,7 +245,6 @@ impl<'a> ConstantEvaluator<'a> {
 
     fn eval_atom(&self, t: &SyntaxObject) -> Option<SteelVal> {
         match &t.ty {
-            TokenType::BooleanLiteral(b) => Some((*b).into()),
             TokenType::Identifier(s) => {
                 // If we found a set identifier, skip it
                 if self.set_idents.get(s).is_some() {
@@ -205,11 +252,7 @@ impl<'a> ConstantEvaluator<'a> {
                 };
                 self.bindings.borrow_mut().get(s.as_str())
             }
-            TokenType::NumberLiteral(n) => Some(SteelVal::NumV(*n)),
-            TokenType::StringLiteral(s) => Some(SteelVal::StringV(s.clone().into())),
-            TokenType::CharacterLiteral(c) => Some(SteelVal::CharV(*c)),
-            TokenType::IntegerLiteral(n) => Some(SteelVal::IntV(*n)),
-            _ => None,
+            other => literal_to_steelval(other),
         }
     }
 
@@ -217,6 +260,65 @@ impl<'a> ConstantEvaluator<'a> {
         exprs.iter().map(|x| self.to_constant(x)).collect()
     }
 
+    // Convert the result of a constant evaluation back into an expression in place of `func`
+    fn value_to_expr(&mut self, output: &SteelVal, func: &ExprKind) -> Option<ExprKind> {
+        if let Some(new_token) = steelval_to_atom(output) {
+            let atom = Atom::new(SyntaxObject::new(new_token, get_span(func)));
+            debug!(
+                "Const evaluation of a function resulted in an atom: {}",
+                atom
+            );
+            self.changed = true;
+            Some(ExprKind::Atom(atom))
+        } else if let Ok(lst) = ExprKind::try_from(output) {
+            self.changed = true;
+            let output = ExprKind::Quote(Box::new(Quote::new(
+                lst,
+                SyntaxObject::new(TokenType::Quote, get_span(func)),
+            )));
+            debug!(
+                "Const evaluation of a function resulted in a quoted value: {}",
+                output
+            );
+            Some(output)
+        } else {
+            None
+        }
+    }
+
+    // Attempt to evaluate a call to a function declared with `define/pure`
+    fn eval_pure_function(&mut self, func: &ExprKind, args: &[SteelVal]) -> Option<ExprKind> {
+        let name = if let ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(name),
+                    ..
+                },
+        }) = func
+        {
+            name
+        } else {
+            return None;
+        };
+
+        if !self.pure_functions.contains_key(name)
+            || self.set_idents.contains(name)
+            || self.bindings.borrow().shadows(name)
+        {
+            return None;
+        }
+
+        debug!(
+            "Attempting to evaluate pure function: {} with args: {:?}",
+            name, args
+        );
+
+        let output = PureEvaluator::new(&self.global_env, self.set_idents, self.pure_functions)
+            .call(name, args.to_vec())?;
+
+        self.value_to_expr(&output, func)
+    }
+
     fn eval_function(
         &mut self,
         evaluated_func: SteelVal,
@@ -229,24 +331,7 @@ impl<'a> ConstantEvaluator<'a> {
                 SteelVal::FuncV(f) => {
                     let output = f(args)?;
 
-                    if let Some(new_token) = steelval_to_atom(&output) {
-                        let atom = Atom::new(SyntaxObject::new(new_token, get_span(&func)));
-                        debug!(
-                            "Const evaluation of a function resulted in an atom: {}",
-                            atom
-                        );
-                        self.changed = true;
-                        Ok(ExprKind::Atom(atom))
-                    } else if let Ok(lst) = ExprKind::try_from(&output) {
-                        self.changed = true;
-                        let output = ExprKind::Quote(Box::new(Quote::new(
-                            lst,
-                            SyntaxObject::new(TokenType::Quote, get_span(&func)),
-                        )));
-                        debug!(
-                            "Const evaluation of a function resulted in a quoted value: {}",
-                            output
-                        );
+                    if let Some(output) = self.value_to_expr(&output, &func) {
                         Ok(output)
                     } else {
                         debug!(
@@ -321,8 +406,30 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
             throw!(BadSyntax => "Define expects an identifier"; define.location.span),
         )?;
 
+        let is_pure = define.is_pure();
+
+        // A local function refers to itself, not to a global with the same name, e.g. the
+        // loop of a named let
+        let global = Rc::ptr_eq(&self.bindings, &self.global_env);
+        if !global {
+            self.bindings.borrow_mut().bind_non_constant(identifier);
+        }
+
         let body = self.visit(define.body)?;
 
+        // Only global definitions are candidates for evaluating at compile time
+        if global {
+            match &body {
+                ExprKind::LambdaFunction(l) if is_pure => {
+                    self.pure_functions
+                        .insert(identifier.to_string(), Rc::new((**l).clone()));
+                }
+                _ => {
+                    self.pure_functions.remove(*identifier);
+                }
+            }
+        }
+
         if let Some(c) = self.to_constant(&body) {
             self.bindings.borrow_mut().bind(identifier, c);
         } else {
@@ -376,6 +483,11 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
 
     fn visit_apply(&mut self, mut apply: Box<crate::parser::ast::Apply>) -> Self::Output {
         apply.func = self.visit(apply.func)?;
+        apply.args = apply
+            .args
+            .into_iter()
+            .map(|x| self.visit(x))
+            .collect::<Result<Vec<_>>>()?;
         apply.list = self.visit(apply.list)?;
         Ok(ExprKind::Apply(apply))
     }
@@ -451,10 +563,12 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
             if let Some(evaluated_func) = self.to_constant(&func) {
                 debug!("Attempting to evaluate: {}", &func);
                 return self.eval_function(evaluated_func, func, Vec::new(), &[]);
+            } else if let Some(output) = self.eval_pure_function(&func, &[]) {
+                return Ok(output);
             } else {
                 if let ExprKind::LambdaFunction(f) = &func {
                     if f.args.len() != 0 {
-                        stop!(ArityMismatch => format!("function expected {} arguments, found 0", f.args.len()))
+                        stop!(ArityMismatch => "function expected {} arguments, found 0", f.args.len())
                     }
 
                     // If the body is constant we can safely remove the application
@@ -487,6 +601,10 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
                     );
                     return self.eval_function(evaluated_func, func_expr, args, &arguments);
                 }
+
+                if let Some(output) = self.eval_pure_function(&func_expr, &arguments) {
+                    return Ok(output);
+                }
                 // return self.eval_function(func_expr, span, &arguments);
             }
         }
@@ -564,6 +682,10 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
                 }
             }
 
+            // Unwind the 'recursion' - this has to happen before any of the early returns below,
+            // otherwise the enclosing environment gets dropped while it's still in use
+            self.bindings = parent;
+
             // Found no arguments are there are no non constant arguments
             if actually_used_arguments.is_empty() && non_constant_arguments.is_empty() {
                 // println!("Returning in here");
@@ -606,9 +728,6 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
                 }
             }
 
-            // Unwind the 'recursion'
-            self.bindings = parent;
-
             // let constructed_func = ExprKind::LambdaFunction(
             //     LambdaFunction::new(actually_used_variables, output, l.location).into(),
             // );
@@ -652,6 +771,127 @@ impl<'a> ConsumingVisitor for ConstantEvaluator<'a> {
     }
 }
 
+// Fuel limited interpreter for calls to functions declared with `define/pure`.
+// Anything it doesn't understand, fails, or runs out of fuel gets left alone for the runtime to deal with
+struct PureEvaluator<'a> {
+    global_env: &'a SharedEnv,
+    set_idents: &'a HashSet<String>,
+    pure_functions: &'a PureFunctions,
+    fuel: usize,
+    depth: usize,
+}
+
+impl<'a> PureEvaluator<'a> {
+    fn new(
+        global_env: &'a SharedEnv,
+        set_idents: &'a HashSet<String>,
+        pure_functions: &'a PureFunctions,
+    ) -> Self {
+        Self {
+            global_env,
+            set_idents,
+            pure_functions,
+            fuel: PURE_FUNCTION_FUEL,
+            depth: 0,
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<SteelVal>) -> Option<SteelVal> {
+        let function = Rc::clone(self.pure_functions.get(name)?);
+        self.apply(&function, args, HashMap::new())
+    }
+
+    fn apply(
+        &mut self,
+        function: &LambdaFunction,
+        args: Vec<SteelVal>,
+        mut frame: HashMap<String, SteelVal>,
+    ) -> Option<SteelVal> {
+        if function.args.len() != args.len() || self.depth == PURE_FUNCTION_MAX_DEPTH {
+            return None;
+        }
+
+        for (var, arg) in function.args.iter().zip(args) {
+            if let ExprKind::Atom(Atom {
+                syn:
+                    SyntaxObject {
+                        ty: TokenType::Identifier(identifier),
+                        ..
+                    },
+            }) = var
+            {
+                frame.insert(identifier.clone(), arg);
+            } else {
+                return None;
+            }
+        }
+
+        self.depth += 1;
+        let output = self.eval(&function.body, &frame);
+        self.depth -= 1;
+        output
+    }
+
+    fn eval(&mut self, expr: &ExprKind, frame: &HashMap<String, SteelVal>) -> Option<SteelVal> {
+        self.fuel = self.fuel.checked_sub(1)?;
+
+        match expr {
+            ExprKind::Atom(Atom { syn }) => match &syn.ty {
+                TokenType::Identifier(s) => frame.get(s).cloned().or_else(|| {
+                    if self.set_idents.contains(s) {
+                        None
+                    } else {
+                        self.global_env.borrow_mut().get(s)
+                    }
+                }),
+                other => literal_to_steelval(other),
+            },
+            ExprKind::Quote(q) => {
+                TryFromExprKindForSteelVal::try_from_expr_kind(q.expr.clone()).ok()
+            }
+            ExprKind::If(f) => {
+                if self.eval(&f.test_expr, frame)?.is_truthy() {
+                    self.eval(&f.then_expr, frame)
+                } else {
+                    self.eval(&f.else_expr, frame)
+                }
+            }
+            ExprKind::Begin(b) => {
+                let mut output = None;
+                for expr in &b.exprs {
+                    output = Some(self.eval(expr, frame)?);
+                }
+                output
+            }
+            ExprKind::List(l) => {
+                let (func, args) = l.args.split_first()?;
+                let args = args
+                    .iter()
+                    .map(|x| self.eval(x, frame))
+                    .collect::<Option<Vec<_>>>()?;
+
+                match func {
+                    ExprKind::LambdaFunction(l) => self.apply(l, args, frame.clone()),
+                    ExprKind::Atom(Atom {
+                        syn:
+                            SyntaxObject {
+                                ty: TokenType::Identifier(name),
+                                ..
+                            },
+                    }) if !frame.contains_key(name) && self.pure_functions.contains_key(name) => {
+                        self.call(name, args)
+                    }
+                    _ => match self.eval(func, frame)? {
+                        SteelVal::FuncV(f) => f(&args).ok(),
+                        _ => None,
+                    },
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
 struct CollectSet<'a> {
     set_idents: &'a mut HashSet<String>,
 }
@@ -692,6 +932,9 @@ impl<'a> VisitorMut for CollectSet<'a> {
 
     fn visit_apply(&mut self, apply: &crate::parser::ast::Apply) -> Self::Output {
         self.visit(&apply.func);
+        for arg in &apply.args {
+            self.visit(arg);
+        }
         self.visit(&apply.list);
     }
 
@@ -162,7 +162,7 @@ impl FlatContractExt for FlatContract {
         if output.is_truthy() {
             Ok(())
         } else {
-            stop!(ContractViolation => format!("Found in the application of a flat contract for {}: the given input: {} resulted in a contract violation", &self.name, arg); *cur_inst_span);
+            stop!(ContractViolation => "Found in the application of a flat contract for {}: the given input: {} resulted in a contract violation", &self.name, arg; *cur_inst_span);
         }
     }
 }
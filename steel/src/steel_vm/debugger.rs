@@ -0,0 +1,434 @@
+use crate::core::instructions::DenseInstruction;
+use crate::core::opcode::OpCode;
+use crate::parser::span::Span;
+use crate::rvals::SteelVal;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// What the VM should do once the handler registered with
+/// [`on_pause`](crate::steel_vm::engine::Engine::on_pause) returns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// Keep running until the next breakpoint
+    Continue,
+    /// Pause on the next line that runs, including lines in any function that gets called
+    StepInto,
+    /// Pause on the next line of the current function, or of its caller once it returns
+    StepOver,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+}
+
+/// A local variable of the function the VM paused in
+#[derive(Clone, Debug)]
+pub struct Local {
+    pub name: String,
+    pub value: SteelVal,
+}
+
+/// Where and why the VM paused
+#[derive(Clone, Debug)]
+pub struct Pause {
+    pub file: PathBuf,
+    /// Lines start at 1
+    pub line: usize,
+    pub span: Span,
+    pub reason: PauseReason,
+    /// How many function calls deep the VM is, top level code is at 0
+    pub depth: usize,
+    /// The locals of the function the VM paused in, in the order they were bound
+    pub locals: Vec<Local>,
+}
+
+impl Pause {
+    /// The value of the innermost local called `name`
+    pub fn local(&self, name: &str) -> Option<&SteelVal> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|x| x.name == name)
+            .map(|x| &x.value)
+    }
+}
+
+pub type PauseHandler = Box<dyn FnMut(&Pause) -> DebugCommand>;
+
+// A line in one of the files the debugger knows about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Location {
+    file: usize,
+    line: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Stepping {
+    Continue,
+    Into,
+    // Holds the depth that was stepped over from
+    Over(usize),
+}
+
+struct SourceFile {
+    path: PathBuf,
+    source: String,
+}
+
+/// Breakpoints and stepping state. Spans don't say which file they came from, so every
+/// program run with a path while debugging has its instructions indexed by file and span, and
+/// only instructions that were indexed can be paused on. Top level code comes from the program
+/// that ran last, and functions from the program that defined the global they were made in.
+pub(crate) struct Debugger {
+    files: Vec<SourceFile>,
+    locations: HashMap<(usize, Span), Location>,
+    // The file each global was defined in
+    globals: HashMap<usize, usize>,
+    // The file of the program running now, `None` when it was run without a path
+    current: Option<usize>,
+    breakpoints: HashSet<Location>,
+    handler: Option<PauseHandler>,
+    stepping: Stepping,
+    // The line the VM was last on at each depth, so that a line only pauses when it is first
+    // reached, and not again when a call made from it returns
+    last_locations: Vec<Option<Location>>,
+}
+
+impl Debugger {
+    pub(crate) fn new() -> Self {
+        Debugger {
+            files: Vec::new(),
+            locations: HashMap::new(),
+            globals: HashMap::new(),
+            current: None,
+            breakpoints: HashSet::new(),
+            handler: None,
+            stepping: Stepping::Continue,
+            last_locations: Vec::new(),
+        }
+    }
+
+    fn file_id(&mut self, path: &Path) -> usize {
+        // Programs are run from their canonical path, so breakpoints are set on it too
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        if let Some(id) = self.files.iter().position(|x| x.path == path) {
+            return id;
+        }
+
+        self.files.push(SourceFile {
+            path,
+            source: String::new(),
+        });
+        self.files.len() - 1
+    }
+
+    pub(crate) fn set_breakpoint(&mut self, path: &Path, line: usize) {
+        let file = self.file_id(path);
+        self.breakpoints.insert(Location { file, line });
+    }
+
+    pub(crate) fn clear_breakpoint(&mut self, path: &Path, line: usize) {
+        let file = self.file_id(path);
+        self.breakpoints.remove(&Location { file, line });
+    }
+
+    pub(crate) fn set_handler(&mut self, handler: PauseHandler) {
+        self.handler = Some(handler);
+    }
+
+    /// Records which line of `path` each instruction of a program compiled from `source` is on
+    pub(crate) fn index_program(
+        &mut self,
+        path: &Path,
+        source: &str,
+        instructions: &[Vec<DenseInstruction>],
+    ) {
+        let file = self.file_id(path);
+        self.files[file].source = source.to_string();
+        self.current = Some(file);
+
+        // Running the file again replaces what was there before
+        self.locations.retain(|(f, _), _| *f != file);
+
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        for instruction in instructions.iter().flatten() {
+            if instruction.op_code == OpCode::BIND {
+                self.globals.insert(instruction.payload_size as usize, file);
+            }
+
+            let span = instruction.span;
+            // Instructions the compiler made up have an empty span
+            if span.end() == 0 || span.end() > source.len() {
+                continue;
+            }
+
+            let line = match line_starts.binary_search(&span.start()) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            };
+            self.locations.insert((file, span), Location { file, line });
+        }
+    }
+
+    /// Called before each instruction runs, returns where to pause if the VM should pause there.
+    /// `origin` is the global the running function was made in, `None` for top level code.
+    pub(crate) fn check(
+        &mut self,
+        span: Span,
+        depth: usize,
+        origin: Option<usize>,
+    ) -> Option<(PauseReason, Location)> {
+        let file = origin
+            .and_then(|x| self.globals.get(&x).copied())
+            .or(self.current)?;
+        let location = *self.locations.get(&(file, span))?;
+
+        // Anything deeper than this has returned
+        self.last_locations.resize(depth + 1, None);
+        if self.last_locations[depth] == Some(location) {
+            return None;
+        }
+        self.last_locations[depth] = Some(location);
+
+        let step = match self.stepping {
+            Stepping::Continue => false,
+            Stepping::Into => true,
+            Stepping::Over(from) => depth <= from,
+        };
+
+        if step {
+            Some((PauseReason::Step, location))
+        } else if self.breakpoints.contains(&location) {
+            Some((PauseReason::Breakpoint, location))
+        } else {
+            None
+        }
+    }
+
+    /// Hands the pause over to the handler, `locals` are the spans the locals were bound at
+    /// along with their values
+    pub(crate) fn pause(
+        &mut self,
+        reason: PauseReason,
+        location: Location,
+        span: Span,
+        depth: usize,
+        locals: Vec<(Span, SteelVal)>,
+    ) {
+        let locals = locals
+            .into_iter()
+            .enumerate()
+            .map(|(i, (span, value))| Local {
+                name: self
+                    .source_text(location.file, span)
+                    .unwrap_or_else(|| format!("local-{}", i)),
+                value,
+            })
+            .collect();
+
+        let pause = Pause {
+            file: self.files[location.file].path.clone(),
+            line: location.line,
+            span,
+            reason,
+            depth,
+            locals,
+        };
+
+        let command = match self.handler.as_mut() {
+            Some(handler) => handler(&pause),
+            None => DebugCommand::Continue,
+        };
+
+        self.stepping = match command {
+            DebugCommand::Continue => Stepping::Continue,
+            DebugCommand::StepInto => Stepping::Into,
+            DebugCommand::StepOver => Stepping::Over(depth),
+        };
+    }
+
+    /// Called once a program finishes, so that stepping doesn't carry over into the next one,
+    /// and the top level code of the next one isn't taken to be from this one's file
+    pub(crate) fn finish(&mut self) {
+        self.stepping = Stepping::Continue;
+        self.last_locations.clear();
+        self.current = None;
+    }
+
+    fn source_text(&self, file: usize, span: Span) -> Option<String> {
+        self.files[file]
+            .source
+            .get(span.start()..span.end())
+            .map(|x| x.to_string())
+    }
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Programs run with a path have to exist on disk
+    fn source_file(name: &str, program: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "steel-debugger-{}-{}.scm",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, program).unwrap();
+        path
+    }
+
+    // Runs `program`, answering every pause with `command`
+    fn pauses(
+        name: &str,
+        program: &str,
+        breakpoints: &[usize],
+        command: impl Fn(&Pause) -> DebugCommand + 'static,
+    ) -> Vec<Pause> {
+        let path = source_file(name, program);
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&pauses);
+
+        let mut vm = Engine::new();
+        for line in breakpoints {
+            vm.set_breakpoint(&path, *line);
+        }
+        vm.on_pause(move |pause| {
+            seen.borrow_mut().push(pause.clone());
+            command(pause)
+        });
+
+        vm.parse_and_execute_from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let pauses = pauses.borrow().clone();
+        pauses
+    }
+
+    fn lines(pauses: &[Pause]) -> Vec<usize> {
+        pauses.iter().map(|x| x.line).collect()
+    }
+
+    const ADD: &str = r#"(define (add x y)
+  (+ x y))
+(add 1 2)
+(add 3 4)
+"#;
+
+    #[test]
+    fn breakpoints_pause_with_locals() {
+        let pauses = pauses("locals", ADD, &[2], |_| DebugCommand::Continue);
+        assert_eq!(lines(&pauses), vec![2, 2]);
+
+        assert_eq!(pauses[0].reason, PauseReason::Breakpoint);
+        assert!(pauses[0]
+            .file
+            .ends_with(format!("steel-debugger-locals-{}.scm", std::process::id())));
+        assert_eq!(pauses[0].depth, 1);
+        assert_eq!(pauses[0].local("x"), Some(&SteelVal::IntV(1)));
+        assert_eq!(pauses[0].local("y"), Some(&SteelVal::IntV(2)));
+        assert_eq!(pauses[1].local("x"), Some(&SteelVal::IntV(3)));
+    }
+
+    #[test]
+    fn breakpoints_can_be_cleared() {
+        let path = source_file("clear", ADD);
+        let mut vm = Engine::new();
+        let count = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&count);
+        vm.on_pause(move |_| {
+            *counter.borrow_mut() += 1;
+            DebugCommand::Continue
+        });
+
+        vm.set_breakpoint(&path, 3);
+        vm.parse_and_execute_from_path(&path).unwrap();
+        assert_eq!(*count.borrow(), 1);
+
+        vm.clear_breakpoint(&path, 3);
+        vm.parse_and_execute_from_path(&path).unwrap();
+        assert_eq!(*count.borrow(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn code_run_without_a_path_is_not_paused() {
+        let mut vm = Engine::new();
+        vm.set_breakpoint("not-run.scm", 2);
+        vm.on_pause(|_| panic!("nothing should pause"));
+        assert_eq!(vm.run(ADD).unwrap().last(), Some(&SteelVal::IntV(7)));
+    }
+
+    #[test]
+    fn files_with_the_same_spans_are_told_apart() {
+        let inc = source_file("inc", "(define (inc x)\n  (+ x 1))\n");
+        let dec = source_file("dec", "(define (dec x)\n  (- x 1))\n(inc (dec 5))\n");
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&pauses);
+
+        let mut vm = Engine::new();
+        vm.set_breakpoint(&dec, 2);
+        vm.on_pause(move |pause| {
+            seen.borrow_mut().push(pause.clone());
+            DebugCommand::Continue
+        });
+        vm.parse_and_execute_from_path(&inc).unwrap();
+        vm.parse_and_execute_from_path(&dec).unwrap();
+        std::fs::remove_file(&inc).unwrap();
+        std::fs::remove_file(&dec).unwrap();
+
+        // Only dec is on the line with the breakpoint, inc's body has the same span in its file
+        let pauses = pauses.borrow();
+        assert_eq!(lines(&pauses), vec![2]);
+        assert_eq!(pauses[0].local("x"), Some(&SteelVal::IntV(5)));
+    }
+
+    const NESTED: &str = r#"(define (double x)
+  (* x 2))
+(define (quadruple x)
+  (+ 0
+     (double (double x))))
+(quadruple 1)
+(quadruple 2)
+"#;
+
+    #[test]
+    fn step_into_goes_into_called_functions() {
+        let pauses = pauses("step-into", NESTED, &[6], |x| match x.line {
+            7 => DebugCommand::Continue,
+            _ => DebugCommand::StepInto,
+        });
+
+        // Both calls to double are stepped into, and each line is only paused on once
+        // per visit even when the call on it returns
+        assert_eq!(lines(&pauses), vec![6, 4, 5, 2, 2, 4, 7]);
+        assert_eq!(pauses[0].reason, PauseReason::Breakpoint);
+        assert_eq!(pauses[3].depth, 2);
+        assert_eq!(pauses[3].local("x"), Some(&SteelVal::IntV(1)));
+        assert_eq!(pauses[4].local("x"), Some(&SteelVal::IntV(2)));
+    }
+
+    #[test]
+    fn step_over_stays_in_the_current_function() {
+        let pauses = pauses("step-over", NESTED, &[5], |x| match x.line {
+            2 => panic!("stepped into double"),
+            _ => DebugCommand::StepOver,
+        });
+
+        // Stepping over the last line of quadruple comes back out to the top level,
+        // and the breakpoint is hit again by the next call
+        assert_eq!(lines(&pauses), vec![5, 4, 7, 5, 4]);
+        assert_eq!(pauses[2].depth, 0);
+        assert_eq!(pauses[3].reason, PauseReason::Breakpoint);
+    }
+}
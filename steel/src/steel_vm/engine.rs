@@ -1,18 +1,45 @@
 use super::{
+    accounting::ExecutionStats,
+    budget::Budget,
+    debugger::{DebugCommand, Pause},
+    evaluation_progress::PendingAwait,
+    instruction_stats::InstructionStats,
+    interrupt::InterruptHandle,
+    leaks::{LeakAudit, LeakReport},
+    metrics::{GcStats, Metrics},
     options::{ApplyContract, DoNotApplyContracts, DoNotUseCallback, UseCallback},
-    primitives::{embed_primitives, embed_primitives_without_io, CONSTANTS},
+    pin::{PinGuard, Pins},
+    primitives::{embed_primitives, embed_primitives_without_io, BUILTIN_MODULES, CONSTANTS},
+    profiler::{ProfileReport, VmSnapshot},
+    session::SavedGlobals,
+    snapshots::{check_snapshot, Snapshots},
+    thread::SteelThread,
+    typed_fn::TypedArgs,
     vm::VirtualMachineCore,
 };
 use crate::{
-    compiler::{compiler::Compiler, constants::ConstantMap, program::Program},
-    core::instructions::DenseInstruction,
+    compiler::{
+        compiler::{Compiler, OptLevel, RedefinitionPolicy},
+        constants::ConstantMap,
+        modules::{ExportSummary, ModuleReload},
+        passes::matches::CompileWarning,
+        program::{Executable, MappedExecutable, Program},
+        timings::CompileTimings,
+    },
+    core::{instructions::DenseInstruction, opcode::OpCode},
+    diagnostics::Diagnostic,
+    docs::{DocEntry, Docs},
+    gc::Gc,
     parser::ast::ExprKind,
     parser::parser::{ParseError, Parser},
+    parser::tracing::TRACING,
+    primitives::ListOperations,
     rerrs::{ErrorKind, SteelErr},
-    rvals::{FromSteelVal, IntoSteelVal, Result, SteelVal},
+    rvals::{CustomType, FloatFormat, FromSteelVal, IntoSteelVal, PrintLimits, Result, SteelVal},
     stop, throw,
 };
 use std::{
+    cell::{Cell, RefCell},
     collections::HashMap,
     io::Read,
     path::{Path, PathBuf},
@@ -22,10 +49,15 @@ use std::{
 use im_rc::HashMap as ImmutableHashMap;
 use itertools::Itertools;
 
+pub use super::sync_engine::SyncEngine;
+
 pub struct Engine {
     virtual_machine: VirtualMachineCore,
     compiler: Compiler,
     constants: Option<ImmutableHashMap<String, SteelVal>>,
+    leak_audit: LeakAudit,
+    print_limits: Rc<Cell<PrintLimits>>,
+    float_format: Rc<Cell<FloatFormat>>,
 }
 
 impl Engine {
@@ -44,6 +76,9 @@ impl Engine {
             virtual_machine: VirtualMachineCore::new(),
             compiler: Compiler::default(),
             constants: None,
+            leak_audit: LeakAudit::Off,
+            print_limits: Rc::new(Cell::new(PrintLimits::default())),
+            float_format: Rc::new(Cell::new(FloatFormat::default())),
         }
     }
 
@@ -70,6 +105,7 @@ impl Engine {
     #[inline]
     pub fn new_sandboxed() -> Self {
         let mut vm = Engine::new_raw();
+        vm.add_feature("sandbox");
         embed_primitives_without_io(&mut vm);
 
         let core_libraries = [crate::stdlib::PRELUDE, crate::stdlib::CONTRACTS];
@@ -109,6 +145,45 @@ impl Engine {
         vm
     }
 
+    /// Makes a new `Engine` that starts out with everything this one has defined, without
+    /// running the prelude again or copying the globals. The two share their globals, compiled
+    /// modules, symbols and constants until one of them defines or `set!`s a global, at which
+    /// point that one gets a copy of its own. That makes a fork per request a cheap way to keep
+    /// requests from seeing each other's definitions.
+    ///
+    /// Only the bindings are copied, the values themselves are still shared: mutating a vector,
+    /// a struct or a variable captured by a closure is seen by both. The budget is kept, but
+    /// callbacks, finalizers, the debugger and suspended computations aren't carried over, and
+    /// leaks aren't audited since the parent holds references to the same values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rvals::SteelVal;
+    /// let mut parent = Engine::new();
+    /// parent.run("(define greeting \"hello\") (define count 1)").unwrap();
+    ///
+    /// let mut request = parent.fork();
+    /// request.run("(define greeting \"bye\") (set! count 2) (define extra 3)").unwrap();
+    ///
+    /// assert_eq!(request.extract_value("count").unwrap(), SteelVal::IntV(2));
+    /// assert_eq!(parent.extract_value("count").unwrap(), SteelVal::IntV(1));
+    /// assert_eq!(parent.run("greeting").unwrap(), vec![SteelVal::StringV("hello".into())]);
+    /// assert!(parent.extract_value("extra").is_err());
+    /// ```
+    pub fn fork(&self) -> Engine {
+        Engine {
+            virtual_machine: self.virtual_machine.fork(),
+            compiler: self.compiler.fork(),
+            constants: self.constants.clone(),
+            leak_audit: LeakAudit::Off,
+            print_limits: Rc::clone(&self.print_limits),
+            float_format: Rc::clone(&self.float_format),
+        }
+    }
+
     /// Consumes the current `Engine` and emits a new `Engine` with the prelude added
     /// to the environment. The prelude won't work unless the primitives are also enabled.
     ///
@@ -172,6 +247,81 @@ impl Engine {
         self.compiler.compile_program(expr, None, constants)
     }
 
+    /// Compiles a program without running it, along with how long each phase of compiling it
+    /// took, for finding out what makes a large program slow to build. Like
+    /// [`emit_program`](Engine::emit_program), what the program defines is kept by this engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let (program, timings) = vm
+    ///     .compile_with_timings("(define (square x) (* x x)) (square 10)")
+    ///     .unwrap();
+    ///
+    /// assert!(timings.passes.iter().any(|(pass, _)| *pass == "constant evaluation"));
+    /// println!("{}", timings);
+    /// vm.execute_program(program).unwrap();
+    /// ```
+    pub fn compile_with_timings(&mut self, expr: &str) -> Result<(Program, CompileTimings)> {
+        let program = self.emit_program(expr)?;
+        Ok((program, self.compiler.timings().clone()))
+    }
+
+    /// How long the phases of compiling the last program took, whether it compiled or not
+    pub fn compile_timings(&self) -> &CompileTimings {
+        self.compiler.timings()
+    }
+
+    /// Emits a program from expressions built with the [`builder`](crate::builder), rather than source.
+    pub fn compile_exprs(&mut self, exprs: Vec<ExprKind>) -> Result<Program> {
+        let constants = self.constants();
+        self.compiler.compile_exprs(exprs, None, constants)
+    }
+
+    /// Compiles and runs expressions built with the [`builder`](crate::builder), returning the
+    /// value of each expression like [`run`](Engine::run).
+    pub fn run_exprs(&mut self, exprs: Vec<ExprKind>) -> Result<Vec<SteelVal>> {
+        let program = self.compile_exprs(exprs)?;
+        self.execute_program(program)
+    }
+
+    /// Rebuilds a closure from the output of `serialize-closure`, which may have come from another engine.
+    /// Any globals the closure refers to are looked up in this engine. The serialized form is Steel source,
+    /// so this should only be given trusted input.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::SteelVal;
+    /// let mut sender = Engine::new();
+    /// let serialized = sender
+    ///     .run("(define (make-adder n) (serializable-lambda (x) (+ x n))) (serialize-closure (make-adder 10))")
+    ///     .unwrap()
+    ///     .pop()
+    ///     .unwrap();
+    ///
+    /// let mut receiver = Engine::new();
+    /// let adder = receiver.deserialize_closure(serialized.string_or_else(|| ()).unwrap()).unwrap();
+    /// receiver.register_value("adder", adder);
+    /// assert_eq!(receiver.run("(adder 5)").unwrap(), vec![SteelVal::IntV(15)]);
+    /// ```
+    pub fn deserialize_closure(&mut self, serialized: &str) -> Result<SteelVal> {
+        match self.run(serialized)?.pop() {
+            // Keep the closure serializable, so it can be passed along again
+            Some(SteelVal::Closure(lambda)) => {
+                let mut lambda = lambda.unwrap();
+                lambda.set_serialized(serialized.to_string());
+                Ok(SteelVal::Closure(Gc::new(lambda)))
+            }
+            _ => stop!(TypeMismatch => "deserialize-closure expected the source of a closure"),
+        }
+    }
+
     // Attempts to disassemble the given expression into a series of bytecode dumps
     pub fn disassemble(&mut self, expr: &str) -> Result<String> {
         let constants = self.constants();
@@ -226,6 +376,121 @@ impl Engine {
         Ok(parsed.into_iter().map(|x| x.to_pretty(60)).join("\n\n"))
     }
 
+    /// Turns folding calls to builtins with constant arguments, like `(+ 1 2)`,
+    /// `(string-append "a" "b")` or `(list 1 2)`, into their values at compile time on or
+    /// off. It is on by default. Turning it off leaves those calls as written, which is useful
+    /// when looking at the bytecode, or when one of the folded builtins has been replaced with
+    /// [`register_value`](Engine::register_value). Inlining is turned on and off separately,
+    /// with [`set_inlining`](Engine::set_inlining).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// assert_eq!(vm.emit_fully_expanded_ast_to_string("(+ 1 2)").unwrap(), "3");
+    ///
+    /// vm.set_constant_folding(false);
+    /// assert_eq!(vm.emit_fully_expanded_ast_to_string("(+ 1 2)").unwrap(), "(+ 1 2)");
+    /// ```
+    pub fn set_constant_folding(&mut self, enabled: bool) -> &mut Self {
+        self.compiler.set_opt_level(if enabled {
+            OptLevel::Three
+        } else {
+            OptLevel::One
+        });
+        self
+    }
+
+    /// Turns removing code that can't have any effect on or off. It is on by default, and
+    /// drops expressions in the middle of a body whose values are thrown away, like literals
+    /// or lambdas, along with local defines that are never referred to. Top level defines are
+    /// always kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let program = "(define (f x) \"unused\" x)";
+    /// assert!(!vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("unused"));
+    ///
+    /// vm.set_dead_code_elimination(false);
+    /// assert!(vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("unused"));
+    /// ```
+    pub fn set_dead_code_elimination(&mut self, enabled: bool) -> &mut Self {
+        self.compiler.set_dead_code_elimination(enabled);
+        self
+    }
+
+    /// Turns inlining small functions into their callers on or off. It is on by default, and
+    /// applies to local functions along with top level ones marked `#:inline`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let program = "(define (square x) #:inline (* x x)) (define (f y) (square y))";
+    /// assert!(!vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("(square y)"));
+    ///
+    /// vm.set_inlining(false);
+    /// assert!(vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("(square y)"));
+    /// ```
+    pub fn set_inlining(&mut self, enabled: bool) -> &mut Self {
+        self.compiler.set_inlining(enabled);
+        self
+    }
+
+    /// Sets what happens when a program defines a global that is already defined. Redefining
+    /// is allowed by default, so that redefining a function at a REPL updates everything
+    /// calling it. Programs compiled ahead of time can warn about it, through
+    /// [`compile_warnings`](Engine::compile_warnings), or refuse to compile.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::compiler::compiler::RedefinitionPolicy;
+    /// use steel::rvals::SteelVal;
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.run("(define (f) 1) (define (g) (f))").unwrap();
+    /// vm.run("(define (f) 2)").unwrap();
+    /// assert_eq!(vm.run("(g)").unwrap(), vec![SteelVal::IntV(2)]);
+    ///
+    /// vm.set_redefinition_policy(RedefinitionPolicy::Warn);
+    /// vm.run("(define (f) 3)").unwrap();
+    /// assert_eq!(vm.compile_warnings().len(), 1);
+    ///
+    /// vm.set_redefinition_policy(RedefinitionPolicy::Error);
+    /// assert!(vm.run("(define (f) 4)").is_err());
+    /// assert_eq!(vm.run("(g)").unwrap(), vec![SteelVal::IntV(3)]);
+    /// ```
+    pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) -> &mut Self {
+        self.compiler.set_redefinition_policy(policy);
+        self
+    }
+
+    // Executables skip the compiler's check for redefinitions, so the VM checks them as they
+    // run instead. Only there, since requiring a module binds what it provides again.
+    fn run_checking_redefinitions<T>(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        if self.compiler.redefinition_policy() == RedefinitionPolicy::Error {
+            let names = self.compiler.symbol_map.clone();
+            self.virtual_machine.forbid_redefinitions(Some(names));
+        }
+        let result = run(self);
+        self.virtual_machine.forbid_redefinitions(None);
+        result
+    }
+
     /// Emit the fully expanded AST
     pub fn emit_fully_expanded_ast_to_string(&mut self, expr: &str) -> Result<String> {
         let constants = self.constants();
@@ -260,6 +525,60 @@ impl Engine {
         Ok(self.register_value(name, converted))
     }
 
+    /// Lends `value` to scripts under the name `name` while `f` runs, and writes back whatever they
+    /// did to it. The name goes back to what it was bound to before once `f` returns.
+    ///
+    /// Nothing here erases the lifetime of the reference, scripts get a copy of the value and the
+    /// copy is read back afterwards, so this is safe but costs two clones. A script that holds on
+    /// to the value past `f` only holds on to the copy, so changing it later doesn't reach `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::Custom;
+    /// use steel::steel_vm::register_fn::RegisterFn;
+    ///
+    /// #[derive(Clone, Debug)]
+    /// struct Counter(isize);
+    /// impl Custom for Counter {}
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.register_fn("counter-add!", |counter: &mut Counter, n: isize| counter.0 += n);
+    ///
+    /// let mut counter = Counter(1);
+    /// vm.with_mut_reference("counter", &mut counter, |vm| {
+    ///     vm.run("(counter-add! counter 10) (counter-add! counter 5)").map(|_| ())
+    /// })
+    /// .unwrap();
+    /// assert_eq!(counter.0, 16);
+    /// ```
+    pub fn with_mut_reference<T, R>(
+        &mut self,
+        name: &str,
+        value: &mut T,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R
+    where
+        T: CustomType + Clone + 'static,
+    {
+        let previous = self.extract_value(name).ok();
+        let lent = value.new_steel_val();
+
+        self.register_value(name, lent.clone());
+        let result = f(self);
+        self.register_value(name, previous.unwrap_or(SteelVal::Void));
+
+        if let SteelVal::Custom(cell) = &lent {
+            if let Some(changed) = cell.borrow_mut().as_any_mut().downcast_mut::<T>() {
+                value.clone_from(changed);
+            }
+        }
+
+        result
+    }
+
     /// Registers a [`SteelVal`](crate::rvals::SteelVal) under the name `name` in the `Engine`'s internal environment.
     ///
     /// # Examples
@@ -279,6 +598,232 @@ impl Engine {
         self
     }
 
+    // Remembers the builtins that calls get specialized into opcodes for, so that the opcodes
+    // only compute the result inline while the global still holds the builtin
+    pub(crate) fn mark_specialized_builtins(&mut self) {
+        for name in OpCode::SPECIALIZED_BUILTINS {
+            if let (Some(idx), Ok(SteelVal::FuncV(f))) =
+                (self.compiler.get_idx(name), self.extract_value(name))
+            {
+                self.virtual_machine.mark_specialized(idx, f);
+            }
+        }
+    }
+
+    /// Registers a native function that can call back into Steel through the
+    /// [`SteelThread`](crate::steel_vm::thread::SteelThread) it is given, such as to call a
+    /// closure it was passed as an argument.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::SteelVal;
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.register_builtin("call-with-ten", |thread, args| {
+    ///     thread.call_function(&args[0], vec![SteelVal::IntV(10)])
+    /// });
+    /// let output = vm.run("(call-with-ten (lambda (x) (* x x)))").unwrap();
+    /// assert_eq!(output, vec![SteelVal::IntV(100)]);
+    /// ```
+    pub fn register_builtin<FN>(&mut self, name: &str, func: FN) -> &mut Self
+    where
+        FN: Fn(&mut SteelThread, &[SteelVal]) -> Result<SteelVal> + 'static,
+    {
+        self.register_value(name, SteelVal::BuiltIn(Rc::new(func)))
+    }
+
+    /// Declares a custom feature for this engine. Features can be checked by
+    /// `cond-expand` when compiling, and are listed by `(features)` at runtime alongside
+    /// the host platform and the cargo features steel was compiled with.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rvals::SteelVal;
+    /// let mut vm = Engine::new();
+    /// vm.add_feature("my-application");
+    ///
+    /// let output = vm
+    ///     .run(r#"(cond-expand [my-application "embedded"] [else "standalone"])"#)
+    ///     .unwrap();
+    /// assert_eq!(output[0], SteelVal::StringV("embedded".into()));
+    ///
+    /// assert!(vm.features().contains(&"my-application".to_string()));
+    /// // (features) returns the same set as a list of symbols
+    /// vm.run("(features)").unwrap();
+    /// ```
+    pub fn add_feature(&mut self, feature: &str) -> &mut Self {
+        self.compiler.features.insert(feature.to_string());
+        self.register_features()
+    }
+
+    /// Turns `trace-define` and `trace` on or off, by declaring the `tracing` feature.
+    /// Traced functions log each call to stderr with its arguments, what it returned and how
+    /// long it took. Tracing is off by default, which compiles `trace-define` to a plain
+    /// `define` and `trace` to nothing. Only programs compiled afterwards are affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let program = "(trace-define (f x) x)";
+    /// assert!(!vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("%trace"));
+    ///
+    /// vm.set_tracing(true);
+    /// assert!(vm.emit_fully_expanded_ast_to_string(program).unwrap().contains("%trace"));
+    /// ```
+    pub fn set_tracing(&mut self, enabled: bool) -> &mut Self {
+        if enabled {
+            self.compiler.features.insert(TRACING.to_string());
+        } else {
+            self.compiler.features.remove(TRACING);
+        }
+        self.register_features()
+    }
+
+    /// Returns the features declared for this engine, in sorted order
+    pub fn features(&self) -> Vec<String> {
+        let mut features: Vec<_> = self.compiler.features.iter().cloned().collect();
+        features.sort();
+        features
+    }
+
+    // (features) just returns a snapshot of the feature set, so this needs to be
+    // registered again whenever a feature gets added
+    pub(crate) fn register_features(&mut self) -> &mut Self {
+        let features: Vec<_> = self
+            .features()
+            .into_iter()
+            .map(|x| SteelVal::SymbolV(x.into()))
+            .collect();
+
+        let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+            if !args.is_empty() {
+                stop!(ArityMismatch => "features expected 0 arguments, got {}", args.len());
+            }
+            ListOperations::built_in_list_func_flat(&features)
+        };
+
+        self.register_value("features", SteelVal::BoxedFunction(Rc::new(f)))
+    }
+
+    /// Sets how much of a nested value `display` and `write` print by default. Scripts can
+    /// change these with `print-depth` and `print-length`, or override them for a single call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::PrintLimits;
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.set_print_limits(PrintLimits {
+    ///     depth: Some(2),
+    ///     length: Some(3),
+    /// });
+    ///
+    /// let value = vm.run("(list 1 (list 2 (list 3)) 4 5)").unwrap().pop().unwrap();
+    /// assert_eq!(
+    ///     value.limited(vm.print_limits()).to_string(),
+    ///     "'(1 (2 ...) 4 ...)"
+    /// );
+    ///
+    /// vm.run("(print-length #false)").unwrap();
+    /// assert_eq!(vm.print_limits().length, None);
+    /// ```
+    pub fn set_print_limits(&mut self, limits: PrintLimits) -> &mut Self {
+        self.print_limits.set(limits);
+        self
+    }
+
+    /// The limits `display` and `write` currently use when printing
+    pub fn print_limits(&self) -> PrintLimits {
+        self.print_limits.get()
+    }
+
+    /// Sets how `display` and `write` print floats that hold a whole number, `1.0` by default
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::FloatFormat;
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.set_float_format(FloatFormat::Integer);
+    ///
+    /// let value = vm.run("(list (/ 10 4) (/ 10 5))").unwrap().pop().unwrap();
+    /// assert_eq!(
+    ///     value.limited(vm.print_limits()).floats(vm.float_format()).to_string(),
+    ///     "'(2.5 2)"
+    /// );
+    /// ```
+    pub fn set_float_format(&mut self, format: FloatFormat) -> &mut Self {
+        self.float_format.set(format);
+        self
+    }
+
+    /// How `display` and `write` currently print floats that hold a whole number
+    pub fn float_format(&self) -> FloatFormat {
+        self.float_format.get()
+    }
+
+    // Shared with `await`, which hands the VM the futures it has to wait on
+    pub(crate) fn pending_await_handle(&self) -> PendingAwait {
+        self.virtual_machine.pending_await_handle()
+    }
+
+    // Shared with the printing functions, so that changes from either side are seen by both
+    pub(crate) fn print_limits_handle(&self) -> Rc<Cell<PrintLimits>> {
+        Rc::clone(&self.print_limits)
+    }
+
+    /// Documents the global `name`, which comes from `module`, for `(doc name)` and
+    /// [`docs`](Engine::docs). Functions written in Steel are documented by their doc strings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.register_doc("answer", "my-app", "The answer to everything");
+    /// vm.run("(define (area r) \"The area of a circle\" (* 3.14 r r))").unwrap();
+    ///
+    /// let docs = vm.docs();
+    /// assert!(docs.iter().any(|x| x.name == "answer" && x.module == "my-app"));
+    /// assert!(docs.iter().any(|x| x.name == "area" && x.doc == "The area of a circle"));
+    /// assert!(docs.iter().any(|x| x.name == "uuid/v4" && x.module == "steel/uuid"));
+    /// ```
+    pub fn register_doc(&mut self, name: &str, module: &str, doc: &str) -> &mut Self {
+        self.compiler
+            .docs_handle()
+            .borrow_mut()
+            .insert(DocEntry::new(name, module, doc));
+        self
+    }
+
+    /// The documentation of every global documented so far, sorted by module and then by name
+    pub fn docs(&self) -> Vec<DocEntry> {
+        self.compiler.docs_handle().borrow().entries()
+    }
+
+    pub(crate) fn docs_handle(&self) -> Rc<RefCell<Docs>> {
+        self.compiler.docs_handle()
+    }
+
+    pub(crate) fn float_format_handle(&self) -> Rc<Cell<FloatFormat>> {
+        Rc::clone(&self.float_format)
+    }
+
     /// Registers multiple values at once
     pub fn register_values(
         &mut self,
@@ -316,7 +861,7 @@ impl Engine {
     ) -> &mut Self {
         let f = move |args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() != 1 {
-                stop!(ArityMismatch => format!("{} expected 1 argument, got {}", predicate_name, args.len()));
+                stop!(ArityMismatch => "{} expected 1 argument, got {}", predicate_name, args.len());
             }
 
             Ok(SteelVal::BoolV(T::from_steelval(args[0].clone()).is_ok()))
@@ -361,32 +906,876 @@ impl Engine {
         self
     }
 
-    /// Extracts a value with the given identifier `name` from the internal environment.
-    /// If a script calculated some series of bound values, then it can be extracted this way.
-    /// This will return the [`SteelVal`](crate::rvals::SteelVal), not the underlying data.
-    /// To unwrap the value, use the [`extract`](crate::steel_vm::engine::Engine::extract) method and pass the type parameter.
-    ///
-    /// The function will return an error if the `name` is not currently bound in the `Engine`'s internal environment.
+    /// Calls `callback` once every `interval` instructions with a [`VmSnapshot`] of what the
+    /// engine is doing, replacing the callback set before. Returning `false` stops evaluation
+    /// with an error. Between calls the engine only compares the instruction count, so a long
+    /// interval costs next to nothing. Like `on_progress`, the callback isn't called when
+    /// running without callbacks.
     ///
     /// # Examples
     ///
     /// ```
     /// # extern crate steel;
     /// # use steel::steel_vm::engine::Engine;
-    /// use steel::rvals::SteelVal;
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
     /// let mut vm = Engine::new();
-    /// vm.run("(define a 10)").unwrap();
-    /// assert_eq!(vm.extract_value("a").unwrap(), SteelVal::IntV(10));
+    /// let deepest = Rc::new(Cell::new(0));
+    /// let seen = Rc::clone(&deepest);
+    ///
+    /// vm.on_progress_every(100, move |snapshot| {
+    ///     seen.set(seen.get().max(snapshot.depth));
+    ///     snapshot.instruction_count < 1_000_000
+    /// });
+    ///
+    /// vm.run("(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1))))) (count 500)")
+    ///     .unwrap();
+    /// assert!(deepest.get() > 100);
+    ///
+    /// assert!(vm.run("(define (spin) (spin)) (spin)").is_err());
     /// ```
-    pub fn extract_value(&self, name: &str) -> Result<SteelVal> {
-        let idx = self.compiler.get_idx(name).ok_or_else(throw!(
-            Generic => format!("free identifier: {} - identifier given cannot be found in the global environment", name)
-        ))?;
+    pub fn on_progress_every<FN: Fn(&VmSnapshot) -> bool + 'static>(
+        &mut self,
+        interval: usize,
+        callback: FN,
+    ) -> &mut Self {
+        self.virtual_machine.on_progress_every(interval, callback);
+        self
+    }
 
-        self.virtual_machine.extract_value(idx)
-            .ok_or_else(throw!(
-                Generic => format!("free identifier: {} - identifier given cannot be found in the global environment", name)
-            ))
+    /// Limits how much every top level expression run from now on can do. Exceeding the budget
+    /// stops evaluation with an error. Like `on_progress`, budgets are not enforced when running
+    /// without callbacks.
+    pub fn set_budget(&mut self, budget: Budget) -> &mut Self {
+        self.virtual_machine.set_budget(budget);
+        self
+    }
+
+    /// The budget every top level expression is run with
+    pub fn budget(&self) -> Budget {
+        self.virtual_machine.budget()
+    }
+
+    /// Limits how many instructions the engine can run in total, across every expression and
+    /// call from now on. Unlike a budget, fuel isn't topped back up for each expression. Once
+    /// it runs out evaluation stops with a [`ResourceExhausted`](ErrorKind::ResourceExhausted)
+    /// error, and keeps doing so until more fuel is given. `None` takes the limit away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rerrs::ErrorKind;
+    /// let mut vm = Engine::new();
+    /// vm.set_fuel(Some(10_000));
+    ///
+    /// let err = vm.run("(define (spin) (spin)) (spin)").unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::ResourceExhausted);
+    /// assert_eq!(vm.fuel(), Some(0));
+    ///
+    /// vm.set_fuel(Some(10_000));
+    /// assert!(vm.run("(+ 1 2)").is_ok());
+    /// ```
+    pub fn set_fuel(&mut self, fuel: Option<u64>) -> &mut Self {
+        self.virtual_machine.set_fuel(fuel);
+        self
+    }
+
+    /// The instructions left before the fuel runs out, if there is a limit
+    pub fn fuel(&self) -> Option<u64> {
+        self.virtual_machine.fuel()
+    }
+
+    /// A handle that stops whatever the engine is running, which can be sent to another thread.
+    /// Every handle of an engine shares the same interrupt.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rerrs::ErrorKind;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let mut vm = Engine::new();
+    /// let handle = vm.interrupt_handle();
+    ///
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(50));
+    ///     handle.interrupt();
+    /// });
+    ///
+    /// let err = vm.run("(define (spin) (spin)) (spin)").unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::Interrupted);
+    /// assert!(vm.run("(+ 1 2)").is_ok());
+    /// ```
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.virtual_machine.interrupt_handle()
+    }
+
+    /// Limits the memory held by the values the engine can reach, as estimated by
+    /// [`heap_bytes`](Engine::heap_bytes). Measuring means walking every value, so the limit
+    /// is checked less often the bigger the heap is and the further it is under the limit,
+    /// and a program can briefly go over it.
+    /// Going over stops evaluation with a [`ResourceExhausted`](ErrorKind::ResourceExhausted)
+    /// error. Like budgets, the limit isn't enforced when running without callbacks.
+    pub fn set_max_heap_bytes(&mut self, bytes: Option<usize>) -> &mut Self {
+        self.virtual_machine.set_max_heap_bytes(bytes);
+        self
+    }
+
+    pub fn max_heap_bytes(&self) -> Option<usize> {
+        self.virtual_machine.max_heap_bytes()
+    }
+
+    /// An estimate of the bytes held by the globals, the stack, pinned values and the variables
+    /// captured by closures. Strings, lists, vectors, hash maps and structs count for what they hold, custom
+    /// types for their size along with what [`Custom::heap_bytes`](crate::rvals::Custom::heap_bytes)
+    /// says, and compiled code only for the value pointing to it.
+    pub fn heap_bytes(&self) -> usize {
+        self.virtual_machine.heap_bytes()
+    }
+
+    /// Calls a function value, such as a closure extracted with [`extract_value`](Engine::extract_value),
+    /// under the engine's current budget.
+    pub fn call_function(&mut self, function: &SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        self.virtual_machine.call_function(
+            function,
+            args,
+            &self.compiler.constant_map,
+            UseCallback,
+            ApplyContract,
+        )
+    }
+
+    /// Wraps the Steel function bound to `name` in a Rust closure taking `ARGS`, a tuple of the
+    /// argument types, and returning an `R`. The arguments and the result are converted on each
+    /// call. The closure borrows the engine, so it can fill an ordinary Rust callback slot for as
+    /// long as nothing else needs the engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.run(r#"(define (add x y) (+ x y)) (define (greet name) (string-append "hi " name))"#)
+    ///     .unwrap();
+    ///
+    /// {
+    ///     let mut add = vm.typed_fn::<(isize, isize), isize>("add").unwrap();
+    ///     assert_eq!(add(1, 2).unwrap(), 3);
+    /// }
+    ///
+    /// // The result has to convert to the type asked for
+    /// let mut greet = vm.typed_fn::<(String,), isize>("greet").unwrap();
+    /// assert!(greet("you".to_string()).is_err());
+    /// drop(greet);
+    ///
+    /// assert!(vm.typed_fn::<(), isize>("not-defined").is_err());
+    /// ```
+    pub fn typed_fn<'a, ARGS: TypedArgs<'a, R>, R>(
+        &'a mut self,
+        name: &str,
+    ) -> Result<ARGS::Function> {
+        let function = self.extract_value(name)?;
+        if !function.is_function() {
+            stop!(TypeMismatch => "{} is not a function, found: {}", name, function);
+        }
+        Ok(ARGS::typed(self, function))
+    }
+
+    /// Calls a function value under `budget` instead of the engine's budget, which is put back
+    /// once the call returns. Useful for bounding a single callback into a script.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::steel_vm::budget::Budget;
+    /// # use steel::rvals::SteelVal;
+    /// use std::time::Duration;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define (spin) (spin)) (define (add x y) (+ x y))").unwrap();
+    ///
+    /// let budget = Budget {
+    ///     instructions: Some(10_000),
+    ///     wall_time: Some(Duration::from_secs(1)),
+    /// };
+    ///
+    /// let add = vm.extract_value("add").unwrap();
+    /// let sum = vm.call_function_with_budget(&add, vec![SteelVal::IntV(1), SteelVal::IntV(2)], budget);
+    /// assert_eq!(sum.unwrap(), SteelVal::IntV(3));
+    ///
+    /// let spin = vm.extract_value("spin").unwrap();
+    /// assert!(vm.call_function_with_budget(&spin, vec![], budget).is_err());
+    /// ```
+    pub fn call_function_with_budget(
+        &mut self,
+        function: &SteelVal,
+        args: Vec<SteelVal>,
+        budget: Budget,
+    ) -> Result<SteelVal> {
+        let previous = self.budget();
+        self.set_budget(budget);
+        let result = self.call_function(function, args);
+        self.set_budget(previous);
+        result
+    }
+
+    /// Start collecting per function opcode histograms and branch taken/not-taken counts
+    /// for everything run through this engine. Functions are identified by their source span,
+    /// top level expressions are grouped under a `null` span.
+    /// Like `on_progress`, nothing is collected when running without callbacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.enable_instruction_stats();
+    /// vm.run(
+    ///     r#"
+    ///     (define (loop x)
+    ///         (if (= x 1000)
+    ///             x
+    ///             (loop (+ x 1))))
+    ///     (loop 0)
+    /// "#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let stats = vm.instruction_stats().unwrap();
+    /// // The hottest function comes first
+    /// let hottest = &stats.functions()[0];
+    /// assert_eq!(hottest.branches_not_taken, 1000);
+    /// assert_eq!(hottest.branches_taken, 1);
+    /// println!("{}", stats.to_json());
+    /// ```
+    pub fn enable_instruction_stats(&mut self) -> &mut Self {
+        self.virtual_machine.enable_instruction_stats();
+        self
+    }
+
+    /// Returns the instruction statistics collected so far, if they were enabled with `enable_instruction_stats`
+    pub fn instruction_stats(&self) -> Option<InstructionStats> {
+        self.virtual_machine.instruction_stats()
+    }
+
+    /// Start counting the instructions run by each module and each function, for hosts that
+    /// bill or limit scripts by what they run. Code is counted against the module it was
+    /// defined in, wherever it is called from, and the code of the programs themselves is
+    /// counted under `<main>`. A module runs its own copy of the modules it requires, so their
+    /// code is counted as part of it. Modules should be required after this is turned on,
+    /// since functions made before then are all counted under `<main>`. Like `on_progress`,
+    /// nothing is counted when running without callbacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.enable_execution_stats();
+    /// vm.run("(define (square x) (* x x))").unwrap();
+    ///
+    /// // Counting each request on its own
+    /// vm.reset_execution_stats();
+    /// vm.run("(square 12)").unwrap();
+    ///
+    /// let stats = vm.execution_stats().unwrap();
+    /// assert!(stats.function("square").unwrap().instructions > 0);
+    /// assert_eq!(stats.modules["<main>"], stats.instructions);
+    /// ```
+    pub fn enable_execution_stats(&mut self) -> &mut Self {
+        self.virtual_machine.enable_execution_stats();
+        self
+    }
+
+    /// Returns what has been counted since execution stats were enabled with
+    /// [`enable_execution_stats`](Engine::enable_execution_stats) or last reset
+    pub fn execution_stats(&self) -> Option<ExecutionStats> {
+        self.virtual_machine
+            .execution_stats(&self.compiler.symbol_map.copy_underlying_vec())
+    }
+
+    /// Starts counting execution stats from zero, such as at the start of each request
+    pub fn reset_execution_stats(&mut self) -> &mut Self {
+        self.virtual_machine.reset_execution_stats();
+        self
+    }
+
+    /// Runs a program like [`run`](Engine::run) while counting the instructions run in each
+    /// function, and the time spent in each, not counting the functions it calls.
+    /// Functions bound to globals are named after them, other functions after their span.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let report = vm
+    ///     .profile(
+    ///         r#"
+    ///         (define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+    ///         (fib 15)
+    ///     "#,
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(report.functions[0].name, "fib");
+    /// // Lines like `<top-level>;fib;fib 42`, ready for flamegraph.pl or inferno
+    /// println!("{}", report.to_folded_stacks());
+    /// ```
+    pub fn profile(&mut self, expr: &str) -> Result<ProfileReport> {
+        self.virtual_machine.start_profiling();
+        let result = self.run(expr);
+        let report = self
+            .virtual_machine
+            .finish_profiling(&self.compiler.symbol_map.copy_underlying_vec());
+
+        result.map(|_| report.expect("the profiler was started before running"))
+    }
+
+    /// Pauses before running anything on `line` of `file`, handing the pause to the handler
+    /// registered with [`on_pause`](Engine::on_pause). Lines start at 1.
+    ///
+    /// Only programs run with a path, like with [`run_with_path`](Engine::run_with_path),
+    /// after the first breakpoint or handler is set can be paused in. This includes functions
+    /// they define that get called later on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use steel::rvals::SteelVal;
+    /// use steel::steel_vm::debugger::DebugCommand;
+    ///
+    /// let path = std::env::temp_dir().join("steel-breakpoint-example.scm");
+    /// std::fs::write(&path, "(define (square x)\n  (* x x))\n(square 3)\n(square 4)").unwrap();
+    ///
+    /// let seen = Rc::new(RefCell::new(Vec::new()));
+    /// let pauses = Rc::clone(&seen);
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.set_breakpoint(&path, 2);
+    /// vm.on_pause(move |pause| {
+    ///     pauses.borrow_mut().push(pause.local("x").cloned());
+    ///     DebugCommand::Continue
+    /// });
+    ///
+    /// vm.parse_and_execute_from_path(&path).unwrap();
+    ///
+    /// assert_eq!(
+    ///     *seen.borrow(),
+    ///     vec![Some(SteelVal::IntV(3)), Some(SteelVal::IntV(4))]
+    /// );
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn set_breakpoint(&mut self, file: impl AsRef<Path>, line: usize) -> &mut Self {
+        self.virtual_machine
+            .debugger_mut()
+            .set_breakpoint(file.as_ref(), line);
+        self
+    }
+
+    /// Removes a breakpoint added with [`set_breakpoint`](Engine::set_breakpoint)
+    pub fn clear_breakpoint(&mut self, file: impl AsRef<Path>, line: usize) -> &mut Self {
+        self.virtual_machine
+            .debugger_mut()
+            .clear_breakpoint(file.as_ref(), line);
+        self
+    }
+
+    /// Registers the function that is called whenever the VM pauses, either on a breakpoint
+    /// or after a step. It can look at where the VM is and the values of the locals, and
+    /// returns whether to continue or to step.
+    pub fn on_pause<FN: FnMut(&Pause) -> DebugCommand + 'static>(
+        &mut self,
+        handler: FN,
+    ) -> &mut Self {
+        self.virtual_machine
+            .debugger_mut()
+            .set_handler(Box::new(handler));
+        self
+    }
+
+    /// Returns the counters for everything this engine has compiled and run so far,
+    /// for hosts that want to keep an eye on many scripts at once. Use
+    /// [`to_prometheus`](crate::steel_vm::metrics::Metrics::to_prometheus) to export them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let before = vm.metrics();
+    ///
+    /// vm.run("(define (first x) (car x))").unwrap();
+    /// vm.run("(first 10)").unwrap_err();
+    ///
+    /// let after = vm.metrics();
+    /// assert_eq!(after.executions - before.executions, 1);
+    /// // Calling `first` with something that isn't a list is caught while compiling
+    /// assert_eq!(after.compile_errors - before.compile_errors, 1);
+    /// println!("{}", after.to_prometheus());
+    /// ```
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            compile_errors: self.compiler.compile_errors(),
+            compilation_cache_hits: self.compiler.compilation_cache_hits(),
+            module_compile_times: self.compiler.module_compile_times(),
+            ..self.virtual_machine.metrics()
+        }
+    }
+
+    /// Returns the warnings from compiling the last program, like `match` expressions that
+    /// might not handle every value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    ///
+    /// vm.run("(define (describe x) (match x [0 'zero] [(? string?) 'text]))")
+    ///     .unwrap();
+    /// assert_eq!(vm.compile_warnings().len(), 1);
+    ///
+    /// vm.run("(define (describe x) (match x [0 'zero] [_ 'other]))").unwrap();
+    /// assert!(vm.compile_warnings().is_empty());
+    /// ```
+    pub fn compile_warnings(&self) -> &[CompileWarning] {
+        self.compiler.warnings()
+    }
+
+    /// Compiles a program without running it, returning what's wrong with it in a form editors
+    /// can draw over the source: the error that stopped it from compiling, if there was one,
+    /// followed by any warnings. Free identifiers that look like a typo of a global come with
+    /// a suggested fix. The program is compiled by a fork of the engine's compiler, so nothing
+    /// it defines, not even its macros, is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::diagnostics::Severity;
+    ///
+    /// let mut vm = Engine::new();
+    /// let diagnostics = vm.diagnostics("(dispaly 10)");
+    ///
+    /// assert_eq!(diagnostics[0].severity, Severity::Error);
+    /// assert_eq!(diagnostics[0].fixes[0].replacement, "display");
+    /// ```
+    pub fn diagnostics(&mut self, expr: &str) -> Vec<Diagnostic> {
+        let constants = self.constants();
+        let mut compiler = self.compiler.fork();
+        let mut diagnostics = Vec::new();
+
+        // The fork is thrown away, so the globals it got as far as defining are kept around for
+        // suggesting names
+        if let Err(e) = compiler.emit_instructions(expr, None, constants) {
+            let diagnostic = Diagnostic::from_error(&e);
+            let globals = compiler.symbol_map.copy_underlying_vec();
+            diagnostics.push(match e.kind() {
+                ErrorKind::FreeIdentifier => diagnostic.suggest_identifier(&globals),
+                _ => diagnostic,
+            });
+        }
+
+        diagnostics.extend(compiler.warnings().iter().map(Diagnostic::from_warning));
+        diagnostics
+    }
+
+    /// The modules the engine knows about along with the names each provides, for suggesting
+    /// requires: the builtin modules, like `steel/uuid`, and the modules required so far by
+    /// their path. Sorted by module.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let vm = Engine::new_base();
+    /// let exports = vm.module_exports();
+    ///
+    /// let (_, names) = exports.iter().find(|(x, _)| x == "steel/uuid").unwrap();
+    /// assert!(names.contains(&"uuid/v4".to_string()));
+    /// ```
+    pub fn module_exports(&self) -> Vec<(String, Vec<String>)> {
+        let mut exports: Vec<(String, Vec<String>)> = Vec::new();
+        for entry in self.docs() {
+            if !BUILTIN_MODULES.contains(&entry.module.as_str()) {
+                continue;
+            }
+            match exports
+                .iter_mut()
+                .find(|(module, _)| *module == entry.module)
+            {
+                Some((_, names)) => names.push(entry.name),
+                None => exports.push((entry.module, vec![entry.name])),
+            }
+        }
+
+        exports.extend(
+            self.compiler
+                .module_exports()
+                .into_iter()
+                .map(|(path, names)| (path.display().to_string(), names)),
+        );
+        exports.sort();
+        exports
+    }
+
+    /// Returns what the modules required so far provide, keyed by the path of each module. A
+    /// provided constant or `define/pure` function that doesn't refer to anything private to its
+    /// module gets folded into the code that requires it.
+    pub fn module_summaries(&self) -> HashMap<PathBuf, HashMap<String, ExportSummary>> {
+        self.compiler.module_summaries()
+    }
+
+    /// Compiles the module at `path` again and rebinds the globals it provides to their new
+    /// definitions, so a long running host can pick up changes to a script without starting
+    /// over. Functions that call a provided function by name call the new one from then on,
+    /// while values already handed out, like closures stored in a list, keep the old code.
+    /// Provided constants and `define/pure` functions are the exception: their values are
+    /// folded into the code that required the module, which keeps the old ones. Those are
+    /// reported in [`ModuleReload::folded`] rather than as updated.
+    ///
+    /// The module has to have been required already. Modules that require it are compiled
+    /// again the next time they're required, and names it no longer provides keep their old
+    /// values. Reloading doesn't count as redefining globals for the
+    /// [redefinition policy](Engine::set_redefinition_policy).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rvals::SteelVal;
+    /// let path = std::env::temp_dir().join(format!("steel-reload-doc-{}.scm", std::process::id()));
+    /// std::fs::write(&path, "(provide speed) (define (speed) 1)").unwrap();
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.run(&format!("(require {:?}) (define (tick) (* 10 (speed)))", path)).unwrap();
+    ///
+    /// std::fs::write(&path, "(provide speed boost) (define (speed) 2) (define (boost) 3)").unwrap();
+    /// let reload = vm.reload_module(&path).unwrap();
+    /// # std::fs::remove_file(&path).unwrap();
+    ///
+    /// assert_eq!(reload.added, vec!["boost"]);
+    /// assert_eq!(reload.updated, vec!["speed"]);
+    /// assert_eq!(vm.run("(tick)").unwrap(), vec![SteelVal::IntV(20)]);
+    /// ```
+    pub fn reload_module(&mut self, path: impl AsRef<Path>) -> Result<ModuleReload> {
+        let constants = self.constants();
+        let (program, reload) = self
+            .compiler
+            .compile_module_reload(path.as_ref(), constants)?;
+        self.virtual_machine
+            .execute_program(program, UseCallback, ApplyContract)?;
+        Ok(reload)
+    }
+
+    /// Returns what the collector for upvalues captured by closures has done so far, so that
+    /// hosts can keep an eye on how long collections pause the program and how much is live.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.run(
+    ///     r#"
+    ///     (define (make-adder n) (lambda (x) (+ x n)))
+    ///     (define (churn n) (if (= n 0) 0 (begin (make-adder n) (churn (- n 1)))))
+    ///     (churn 1000)
+    ///     "#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let stats = vm.gc_stats();
+    /// assert!(stats.minor_collections > 0);
+    /// assert!(stats.longest_pause <= stats.total_pause);
+    /// ```
+    pub fn gc_stats(&self) -> GcStats {
+        self.virtual_machine.gc_stats()
+    }
+
+    /// Calls `finalizer` once the custom `value` has been collected. Rust `Drop` impls can't
+    /// be relied on for this, since values handed back to Rust are clones of the one the
+    /// program holds. Finalizers run at the end of each top level expression, or on
+    /// [`Engine::run_finalizers`] for values that were still held by a returned result.
+    /// Registering a value that isn't a custom type is an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rvals::{Custom, IntoSteelVal};
+    /// # use std::cell::Cell;
+    /// # use std::rc::Rc;
+    /// #[derive(Clone, Debug)]
+    /// struct Socket(u16);
+    /// impl Custom for Socket {}
+    ///
+    /// let mut vm = Engine::new();
+    /// let closed = Rc::new(Cell::new(false));
+    ///
+    /// let socket = Socket(8080).into_steelval().unwrap();
+    /// let flag = Rc::clone(&closed);
+    /// vm.register_finalizer(&socket, move || flag.set(true)).unwrap();
+    /// vm.register_value("socket", socket);
+    ///
+    /// vm.run("(begin (set! socket #f) #t)").unwrap();
+    /// assert!(closed.get());
+    /// ```
+    pub fn register_finalizer(
+        &mut self,
+        value: &SteelVal,
+        finalizer: impl FnOnce() + 'static,
+    ) -> Result<()> {
+        self.virtual_machine
+            .register_finalizer(value, Box::new(finalizer))
+    }
+
+    /// Runs the finalizers of custom values that have been collected since the last top level
+    /// expression finished, returning how many ran
+    pub fn run_finalizers(&mut self) -> usize {
+        self.virtual_machine.run_finalizers()
+    }
+
+    /// Saves compiled programs under `directory`, keyed by a hash of the source and of the
+    /// definitions, macros and modules it was compiled against. Running the same program in
+    /// the same setting again, even from another process, loads it instead of compiling it.
+    ///
+    /// A program is only loaded while the modules it requires are the same as when it was
+    /// compiled. Loading it skips optimizing it and generating its bytecode, but it is still
+    /// expanded, so that the macros it defines and the modules it requires are there for the
+    /// programs that come after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let directory = std::env::temp_dir().join("steel-compilation-cache-example");
+    /// # let _ = std::fs::remove_dir_all(&directory);
+    ///
+    /// let program = "(define (square x) (* x x)) (square 12)";
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.with_compilation_cache(&directory);
+    /// let first = vm.run(program).unwrap();
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.with_compilation_cache(&directory);
+    /// assert_eq!(vm.run(program).unwrap().last(), first.last());
+    /// assert_eq!(vm.metrics().compilation_cache_hits, 1);
+    /// # std::fs::remove_dir_all(&directory).unwrap();
+    /// ```
+    pub fn with_compilation_cache(&mut self, directory: impl Into<PathBuf>) -> &mut Self {
+        self.compiler.with_compilation_cache(directory.into());
+        self
+    }
+
+    /// Finds the custom values held by the engine that something outside of the engine
+    /// also holds on to, and so will outlive it. Each leak names the rust type and where in
+    /// the engine the value was found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::{Custom, IntoSteelVal};
+    ///
+    /// #[derive(Clone, Debug)]
+    /// struct Connection(usize);
+    /// impl Custom for Connection {}
+    ///
+    /// let mut vm = Engine::new();
+    /// let connection = Connection(1).into_steelval().unwrap();
+    /// vm.register_value("connection", connection.clone());
+    /// vm.run("(define connections (list 1 connection))").unwrap();
+    ///
+    /// let report = vm.leaks();
+    /// assert_eq!(report.leaks.len(), 1);
+    /// assert_eq!(report.leaks[0].origin, "global `connection`");
+    /// assert_eq!(report.leaks[0].outside_references, 1);
+    ///
+    /// drop(connection);
+    /// assert!(vm.leaks().is_empty());
+    /// ```
+    pub fn leaks(&self) -> LeakReport {
+        self.virtual_machine
+            .leaks(&self.compiler.symbol_map.copy_underlying_vec())
+    }
+
+    /// Keeps `value` alive in the engine until the guard is dropped, for hosts that hold on to
+    /// a value across an await point before handing it back to the engine. Unlike a clone
+    /// kept by the host, a pinned value is one the engine knows about, so it counts as the
+    /// engine's own in [`leaks`](Engine::leaks).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::rvals::SteelVal;
+    /// let mut vm = Engine::new();
+    /// let list = vm.run("(list 1 2 3)").unwrap().pop().unwrap();
+    ///
+    /// let pinned = vm.pin(list);
+    /// assert_eq!(vm.pinned(), 1);
+    ///
+    /// // Later, once the host is done waiting
+    /// let length = vm.extract_value("length").unwrap();
+    /// let list = pinned.take().unwrap();
+    /// assert_eq!(vm.call_function(&length, vec![list]).unwrap(), SteelVal::IntV(3));
+    /// assert_eq!(vm.pinned(), 0);
+    /// ```
+    pub fn pin(&self, value: SteelVal) -> PinGuard {
+        Pins::pin(self.virtual_machine.pins(), value)
+    }
+
+    /// The number of values pinned with [`pin`](Engine::pin) that are still pinned
+    pub fn pinned(&self) -> usize {
+        self.virtual_machine.pins().borrow().len()
+    }
+
+    /// Checks `(check-snapshot expr)` against `snapshots`, which are recorded the first time
+    /// each expression is checked. Call [`Snapshots::save`] once the program is done to keep
+    /// any that were added or updated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::steel_vm::snapshots::{SnapshotMode, Snapshots};
+    ///
+    /// let path = std::env::temp_dir().join("record_snapshots_example.snap");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let snapshots = Snapshots::load(&path, SnapshotMode::Check).unwrap();
+    /// let mut vm = Engine::new();
+    /// vm.record_snapshots(snapshots.clone());
+    /// vm.run("(define (answer) 42) (check-snapshot (answer))").unwrap();
+    /// snapshots.save().unwrap();
+    ///
+    /// // From then on `(answer)` has to be 42
+    /// let mut vm = Engine::new();
+    /// vm.record_snapshots(Snapshots::load(&path, SnapshotMode::Check).unwrap());
+    /// assert!(vm.run("(define (answer) 41) (check-snapshot (answer))").is_err());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn record_snapshots(&mut self, snapshots: Snapshots) -> &mut Self {
+        self.register_value("%check-snapshot", check_snapshot(Some(snapshots)))
+    }
+
+    /// The names of every global, including the builtins and the prelude, in the order
+    /// they were first defined
+    pub fn globals(&self) -> Vec<String> {
+        self.compiler.symbol_map.copy_underlying_vec()
+    }
+
+    /// The names of the macros defined so far with `define-syntax`, in sorted order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define-syntax swap! (syntax-rules () [(swap! a b) (let ([t a]) (set! a b) (set! b t))]))")
+    ///     .unwrap();
+    /// assert!(vm.in_scope_macros().contains(&"swap!".to_string()));
+    /// ```
+    pub fn in_scope_macros(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.compiler.macro_env.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Writes out the globals in `names` as a program that defines them again, so that
+    /// running it in another engine picks up where this one left off. Only data, i.e.
+    /// numbers, strings, symbols and lists of them, can be written out; globals holding
+    /// anything else are skipped, as are names that aren't defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let builtins = vm.globals().len();
+    /// vm.run("(define xs (list 1 2 'three)) (define (f x) x)").unwrap();
+    ///
+    /// let saved = vm.save_globals(&vm.globals()[builtins..]);
+    /// assert_eq!(saved.skipped, vec!["f"]);
+    ///
+    /// let mut resumed = Engine::new();
+    /// resumed.run(&saved.source).unwrap();
+    /// assert_eq!(resumed.run("xs").unwrap()[0].to_string(), "'(1 2 three)");
+    /// ```
+    pub fn save_globals(&self, names: &[String]) -> SavedGlobals {
+        let mut saved = SavedGlobals::default();
+        for name in names {
+            if let Ok(value) = self.extract_value(name) {
+                saved.add(name, &value);
+            }
+        }
+        saved
+    }
+
+    /// Checks for leaked custom values when the engine is dropped, see [`Engine::leaks`].
+    /// With [`LeakAudit::Assert`] the engine panics in debug builds if anything leaked,
+    /// which is useful for catching lifetime bugs in tests of an embedding.
+    pub fn audit_leaks_on_drop(&mut self, audit: LeakAudit) -> &mut Self {
+        self.leak_audit = audit;
+        self
+    }
+
+    /// Extracts a value with the given identifier `name` from the internal environment.
+    /// If a script calculated some series of bound values, then it can be extracted this way.
+    /// This will return the [`SteelVal`](crate::rvals::SteelVal), not the underlying data.
+    /// To unwrap the value, use the [`extract`](crate::steel_vm::engine::Engine::extract) method and pass the type parameter.
+    ///
+    /// The function will return an error if the `name` is not currently bound in the `Engine`'s internal environment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::SteelVal;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define a 10)").unwrap();
+    /// assert_eq!(vm.extract_value("a").unwrap(), SteelVal::IntV(10));
+    /// ```
+    pub fn extract_value(&self, name: &str) -> Result<SteelVal> {
+        let idx = self.compiler.get_idx(name).ok_or_else(throw!(
+            Generic => "free identifier: {} - identifier given cannot be found in the global environment", name
+        ))?;
+
+        self.virtual_machine.extract_value(idx)
+            .ok_or_else(throw!(
+                Generic => "free identifier: {} - identifier given cannot be found in the global environment", name
+            ))
     }
 
     /// Extracts a value with the given identifier `name` from the internal environment, and attempts to coerce it to the
@@ -429,6 +1818,83 @@ impl Engine {
             .execute_program(program, UseCallback, ApplyContract)
     }
 
+    /// Execute a program like [`run`](Engine::run), except that `(await future)` suspends the
+    /// program until the future is done instead of blocking the thread. The returned future has
+    /// to be driven by an executor, which is also what polls the futures being awaited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// # use steel::steel_vm::register_fn::RegisterAsyncFn;
+    /// use futures::channel::oneshot;
+    /// use futures::executor::LocalPool;
+    /// use futures::task::LocalSpawnExt;
+    /// use std::cell::RefCell;
+    /// use steel::rvals::SteelVal;
+    ///
+    /// let (sender, receiver) = oneshot::channel::<isize>();
+    /// let receiver = RefCell::new(Some(receiver));
+    ///
+    /// let mut vm = Engine::new();
+    /// vm.register_async_fn("fetch", move || {
+    ///     let receiver = receiver.borrow_mut().take().unwrap();
+    ///     async move { receiver.await.unwrap() }
+    /// });
+    ///
+    /// let mut pool = LocalPool::new();
+    /// // Nothing has been sent yet, so the program has to wait for this to run
+    /// pool.spawner()
+    ///     .spawn_local(async move { sender.send(41).unwrap() })
+    ///     .unwrap();
+    ///
+    /// let output = pool.run_until(vm.run_async("(+ (await (fetch)) 1)")).unwrap();
+    /// assert_eq!(output, vec![SteelVal::IntV(42)]);
+    /// ```
+    pub async fn run_async(&mut self, expr: &str) -> Result<Vec<SteelVal>> {
+        let constants = self.constants();
+        let program = self.compiler.compile_program(expr, None, constants)?;
+        self.virtual_machine.execute_program_async(program).await
+    }
+
+    /// Compiles a program without running it, so that it can be saved with
+    /// [`Executable::serialize`] and run later by an engine set up the same way as this one.
+    /// Macros the program defines are expanded and kept by this engine, like with `run`, but
+    /// the globals it defines only exist once it's run.
+    pub fn compile_executable(&mut self, expr: &str) -> Result<Executable> {
+        let constants = self.constants();
+        self.compiler.compile_executable(expr, None, constants)
+    }
+
+    /// Runs a program compiled by [`compile_executable`](Engine::compile_executable),
+    /// as if it was run with [`run`](Engine::run)
+    pub fn run_executable(&mut self, executable: Executable) -> Result<Vec<SteelVal>> {
+        let program = self.compiler.load_executable(executable)?;
+        self.run_checking_redefinitions(|vm| {
+            vm.virtual_machine
+                .execute_program(program, UseCallback, ApplyContract)
+        })
+    }
+
+    /// Runs a program opened as a [`MappedExecutable`], as if it was run with
+    /// [`run`](Engine::run). Each top level expression is read from the executable's bytes
+    /// just before it runs.
+    pub fn run_mapped_executable<B: AsRef<[u8]>>(
+        &mut self,
+        executable: &MappedExecutable<B>,
+    ) -> Result<Vec<SteelVal>> {
+        let constant_map = self.compiler.load_mapped_executable(executable)?;
+        self.run_checking_redefinitions(|vm| {
+            vm.virtual_machine.execute_instructions(
+                (0..executable.len()).map(|i| executable.instructions(i)),
+                &constant_map,
+                UseCallback,
+                ApplyContract,
+            )
+        })
+    }
+
     /// Execute a program, however do not run any callbacks as registered with `on_progress`.
     pub fn run_without_callbacks(&mut self, expr: &str) -> Result<Vec<SteelVal>> {
         let constants = self.constants();
@@ -474,7 +1940,16 @@ impl Engine {
     /// for error reporting purposes.
     pub fn run_with_path(&mut self, expr: &str, path: PathBuf) -> Result<Vec<SteelVal>> {
         let constants = self.constants();
-        let program = self.compiler.compile_program(expr, Some(path), constants)?;
+        let program = self
+            .compiler
+            .compile_program(expr, Some(path.clone()), constants)?;
+
+        if self.virtual_machine.debugging() {
+            self.virtual_machine
+                .debugger_mut()
+                .index_program(&path, expr, &program.instructions);
+        }
+
         self.virtual_machine
             .execute_program(program, UseCallback, ApplyContract)
     }
@@ -531,6 +2006,25 @@ impl Engine {
     }
 }
 
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if self.leak_audit == LeakAudit::Off || std::thread::panicking() {
+            return;
+        }
+
+        let report = self.leaks();
+        if report.is_empty() {
+            return;
+        }
+
+        if self.leak_audit == LeakAudit::Assert && cfg!(debug_assertions) {
+            panic!("{}", report);
+        }
+
+        eprintln!("{}", report);
+    }
+}
+
 #[cfg(test)]
 mod on_progress_tests {
     use super::*;
@@ -571,4 +2065,32 @@ mod on_progress_tests {
 
         assert_eq!(external_count.get(), 4);
     }
+
+    #[test]
+    fn snapshots_every_interval() {
+        let mut vm = Engine::new();
+        vm.run("(define (loop x) (if (= x 1000) x (loop (+ x 1))))")
+            .unwrap();
+
+        let snapshots = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&snapshots);
+        vm.on_progress_every(500, move |snapshot| {
+            seen.borrow_mut().push(*snapshot);
+            true
+        });
+        vm.run("(loop 0)").unwrap();
+
+        let snapshots = snapshots.borrow();
+        assert!(snapshots.len() >= 4);
+        for pair in snapshots.windows(2) {
+            assert_eq!(pair[1].instruction_count - pair[0].instruction_count, 500);
+        }
+
+        // Everything after the first few instructions runs in `loop`, whose body is in the
+        // first program
+        let inside = snapshots.last().unwrap();
+        assert_eq!(inside.depth, 1);
+        let (start, end) = inside.function.unwrap();
+        assert!(start > 0 && end < 60);
+    }
 }
@@ -1,7 +1,68 @@
-use std::cell::Cell;
+use super::accounting::ExecutionAccounting;
+use super::budget::{next_heap_check, Allowance, Budget, HeapMeter};
+use super::debugger::{Debugger, Location, PauseReason};
+use super::instruction_stats::InstructionStats;
+use super::pin::PinTable;
+use super::profiler::{Profiler, VmSnapshot};
+use super::vm::{Suspension, Winder};
+use crate::core::instructions::DenseInstruction;
+use crate::core::opcode::OpCode;
+use crate::gc::Gc;
+use crate::parser::span::Span;
+use crate::rvals::{BoxedFunctionSignature, ByteCodeLambda, FutureResult, Result, SteelVal};
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub type Callback = Box<dyn Fn(usize) -> bool>;
 
+pub type ProgressCallback = Box<dyn Fn(&VmSnapshot) -> bool>;
+
+/// Where `await` leaves a future that isn't ready yet, for the VM to wait on
+pub(crate) type PendingAwait = Rc<AwaitSlot>;
+
+/// Only calls the VM makes to `await` itself can hand their future over, since the VM puts the
+/// value where the call's result would have gone. Calls from anywhere else, like from inside of
+/// `map`, have to block on their future instead.
+#[derive(Default)]
+pub(crate) struct AwaitSlot {
+    // The address of `await`
+    function: Cell<usize>,
+    called_directly: Cell<bool>,
+    future: Cell<Option<FutureResult>>,
+}
+
+impl AwaitSlot {
+    pub(crate) fn set_function(&self, function: &BoxedFunctionSignature) {
+        self.function
+            .set(Rc::as_ptr(function) as *const u8 as usize);
+    }
+
+    /// Whether the VM is the one calling `await` right now
+    pub(crate) fn called_directly(&self) -> bool {
+        self.called_directly.get()
+    }
+
+    pub(crate) fn hand_off(&self, future: FutureResult) {
+        self.future.set(Some(future));
+    }
+
+    // Calls a native function for the VM, letting it hand its future over if it's `await`
+    #[inline(always)]
+    fn call(&self, function: &BoxedFunctionSignature, args: &[SteelVal]) -> Result<SteelVal> {
+        if Rc::as_ptr(function) as *const u8 as usize != self.function.get() {
+            return function(args);
+        }
+
+        self.called_directly.set(true);
+        let result = function(args);
+        self.called_directly.set(false);
+        result
+    }
+}
+
 trait CallbackFunc {
     fn call(&self) -> Option<bool>;
 }
@@ -15,6 +76,28 @@ impl CallbackFunc for () {
 pub(crate) struct EvaluationProgress {
     instruction_count: Cell<usize>,
     callback: Option<Callback>,
+    // Called every so many instructions, the count it's next called at is kept separately so
+    // that checking for it is one comparison
+    progress: Option<(usize, ProgressCallback)>,
+    next_progress: Cell<usize>,
+    stats: Option<RefCell<InstructionStats>>,
+    allowance: Cell<Option<Allowance>>,
+    // The instruction count the fuel runs out at, which unlike a budget spans every expression
+    fuel_limit: Cell<Option<usize>>,
+    max_heap_bytes: Cell<Option<usize>>,
+    // The instruction count the heap is next measured at
+    next_heap_check: Cell<usize>,
+    // Set from interrupt handles, possibly on other threads
+    interrupted: Arc<AtomicBool>,
+    pending_await: PendingAwait,
+    suspension: RefCell<Option<Suspension>>,
+    debugger: Option<RefCell<Debugger>>,
+    profiler: Option<RefCell<Profiler>>,
+    accounting: Option<RefCell<ExecutionAccounting>>,
+    // The `dynamic-wind`s that are running, innermost last
+    winders: RefCell<Vec<Winder>>,
+    // Values the host has pinned, which are as much a part of the heap as the globals
+    pins: PinTable,
 }
 
 impl EvaluationProgress {
@@ -22,6 +105,21 @@ impl EvaluationProgress {
         EvaluationProgress {
             instruction_count: Cell::new(1),
             callback: None,
+            progress: None,
+            next_progress: Cell::new(usize::MAX),
+            stats: None,
+            allowance: Cell::new(None),
+            fuel_limit: Cell::new(None),
+            max_heap_bytes: Cell::new(None),
+            next_heap_check: Cell::new(0),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            pending_await: Rc::new(AwaitSlot::default()),
+            suspension: RefCell::new(None),
+            debugger: None,
+            profiler: None,
+            accounting: None,
+            winders: RefCell::new(Vec::new()),
+            pins: PinTable::default(),
         }
     }
 
@@ -37,6 +135,36 @@ impl EvaluationProgress {
         }
     }
 
+    pub fn with_progress_callback(&mut self, interval: usize, callback: ProgressCallback) {
+        let interval = interval.max(1);
+        self.next_progress
+            .set(self.instruction_count().saturating_add(interval));
+        self.progress = Some((interval, callback));
+    }
+
+    /// Whether it's time to call the progress callback
+    #[inline(always)]
+    pub fn progress_due(&self) -> bool {
+        self.instruction_count() >= self.next_progress.get()
+    }
+
+    /// Calls the progress callback, returning whether evaluation should go on
+    pub fn report_progress(&self, snapshot: &VmSnapshot) -> bool {
+        match &self.progress {
+            Some((interval, callback)) => {
+                self.next_progress
+                    .set(self.instruction_count().saturating_add(*interval));
+                callback(snapshot)
+            }
+            None => true,
+        }
+    }
+
+    /// The number of instructions executed so far
+    pub fn instruction_count(&self) -> usize {
+        self.instruction_count.get() - 1
+    }
+
     pub fn increment(&self) {
         self.instruction_count.set(self.instruction_count.get() + 1);
     }
@@ -47,4 +175,277 @@ impl EvaluationProgress {
         self.increment();
         b
     }
+
+    /// Starts spending `budget`, replacing whatever budget was being spent before
+    pub fn start_budget(&self, budget: Budget) {
+        if budget.is_unlimited() {
+            self.allowance.set(None);
+        } else {
+            self.allowance
+                .set(Some(Allowance::start(budget, self.instruction_count())));
+        }
+    }
+
+    /// Allows `fuel` more instructions to be executed, on top of any budget
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel_limit.set(fuel.map(|x| {
+            self.instruction_count()
+                .saturating_add(usize::try_from(x).unwrap_or(usize::MAX))
+        }));
+    }
+
+    /// How many more instructions can be executed before the fuel runs out
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel_limit
+            .get()
+            .map(|x| x.saturating_sub(self.instruction_count()) as u64)
+    }
+
+    pub fn set_max_heap_bytes(&self, bytes: Option<usize>) {
+        self.max_heap_bytes.set(bytes);
+        self.next_heap_check.set(self.instruction_count());
+    }
+
+    pub fn max_heap_bytes(&self) -> Option<usize> {
+        self.max_heap_bytes.get()
+    }
+
+    /// The limit on the heap, when it's time to check it
+    #[inline(always)]
+    pub fn heap_check_due(&self) -> Option<usize> {
+        match self.max_heap_bytes.get() {
+            Some(bytes) if self.instruction_count() >= self.next_heap_check.get() => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Schedules the next check of the heap, from what measuring it just found
+    pub fn heap_checked(&self, meter: &HeapMeter, limit: usize) {
+        self.next_heap_check.set(
+            self.instruction_count()
+                .saturating_add(next_heap_check(meter, limit)),
+        );
+    }
+
+    pub(crate) fn pins(&self) -> &PinTable {
+        &self.pins
+    }
+
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    /// Whether an interrupt has been asked for, using it up if it has
+    #[inline(always)]
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns why evaluation has to stop, if the fuel or the current budget has run out
+    #[inline(always)]
+    pub fn budget_exceeded(&self) -> Option<String> {
+        if let Some(limit) = self.fuel_limit.get() {
+            if self.instruction_count() > limit {
+                return Some("evaluation ran out of fuel".to_string());
+            }
+        }
+
+        self.allowance
+            .get()
+            .and_then(|x| x.exceeded(self.instruction_count()))
+    }
+
+    pub fn pending_await_handle(&self) -> PendingAwait {
+        Rc::clone(&self.pending_await)
+    }
+
+    /// Takes the future the last instruction started waiting on, if there is one
+    #[inline(always)]
+    pub fn take_pending_await(&self) -> Option<FutureResult> {
+        self.pending_await.future.take()
+    }
+
+    /// Calls a native function from an instruction
+    #[inline(always)]
+    pub fn call_boxed(
+        &self,
+        function: &BoxedFunctionSignature,
+        args: &[SteelVal],
+    ) -> Result<SteelVal> {
+        self.pending_await.call(function, args)
+    }
+
+    pub fn suspend(&self, suspension: Suspension) {
+        self.suspension.replace(Some(suspension));
+    }
+
+    /// Takes the state of the evaluation that was suspended to wait on a future, if there is one
+    pub fn take_suspension(&self) -> Option<Suspension> {
+        self.suspension.borrow_mut().take()
+    }
+
+    pub fn enable_instruction_stats(&mut self) {
+        if self.stats.is_none() {
+            self.stats = Some(RefCell::new(InstructionStats::new()));
+        }
+    }
+
+    #[inline(always)]
+    pub fn collecting_stats(&self) -> bool {
+        self.stats.is_some()
+    }
+
+    pub fn instruction_stats(&self) -> Option<InstructionStats> {
+        self.stats.as_ref().map(|x| x.borrow().clone())
+    }
+
+    pub fn record_instruction(
+        &self,
+        body: &Rc<[DenseInstruction]>,
+        in_function: bool,
+        ip: usize,
+        op_code: OpCode,
+    ) {
+        if let Some(stats) = &self.stats {
+            stats
+                .borrow_mut()
+                .record_instruction(body, in_function, ip, op_code);
+        }
+    }
+
+    pub fn record_branch(&self, body: &Rc<[DenseInstruction]>, in_function: bool, taken: bool) {
+        if let Some(stats) = &self.stats {
+            stats.borrow_mut().record_branch(body, in_function, taken);
+        }
+    }
+
+    /// Starts a new profile, replacing any profile that was already being taken
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(RefCell::new(Profiler::new()));
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(RefCell::into_inner)
+    }
+
+    #[inline(always)]
+    pub fn profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    pub fn record_profile(
+        &self,
+        body: &Rc<[DenseInstruction]>,
+        in_function: bool,
+        function_stack: &[Gc<ByteCodeLambda>],
+    ) {
+        if let Some(profiler) = &self.profiler {
+            profiler
+                .borrow_mut()
+                .record_instruction(body, in_function, function_stack);
+        }
+    }
+
+    /// Starts counting instructions by module and function, keeping what was already counted
+    pub fn enable_execution_stats(&mut self) {
+        if self.accounting.is_none() {
+            self.accounting = Some(RefCell::new(ExecutionAccounting::default()));
+        }
+    }
+
+    pub fn reset_execution_stats(&mut self) {
+        if let Some(accounting) = &mut self.accounting {
+            *accounting.get_mut() = ExecutionAccounting::default();
+        }
+    }
+
+    #[inline(always)]
+    pub fn accounting(&self) -> bool {
+        self.accounting.is_some()
+    }
+
+    pub fn execution_accounting(&self) -> Option<std::cell::Ref<'_, ExecutionAccounting>> {
+        self.accounting.as_ref().map(|x| x.borrow())
+    }
+
+    pub fn record_execution(
+        &self,
+        body: &Rc<[DenseInstruction]>,
+        ip: usize,
+        function: Option<&ByteCodeLambda>,
+    ) {
+        if let Some(accounting) = &self.accounting {
+            accounting
+                .borrow_mut()
+                .record_instruction(body, ip, function);
+        }
+    }
+
+    /// The debugger, which is set up the first time it is asked for
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        self.debugger
+            .get_or_insert_with(|| RefCell::new(Debugger::new()))
+            .get_mut()
+    }
+
+    #[inline(always)]
+    pub fn debugging(&self) -> bool {
+        self.debugger.is_some()
+    }
+
+    pub fn check_debugger(
+        &self,
+        span: Span,
+        depth: usize,
+        origin: Option<usize>,
+    ) -> Option<(PauseReason, Location)> {
+        self.debugger
+            .as_ref()
+            .and_then(|x| x.borrow_mut().check(span, depth, origin))
+    }
+
+    pub fn pause(
+        &self,
+        reason: PauseReason,
+        location: Location,
+        span: Span,
+        depth: usize,
+        locals: Vec<(Span, SteelVal)>,
+    ) {
+        if let Some(debugger) = &self.debugger {
+            debugger
+                .borrow_mut()
+                .pause(reason, location, span, depth, locals);
+        }
+    }
+
+    pub fn winders(&self) -> Vec<Winder> {
+        self.winders.borrow().clone()
+    }
+
+    pub fn set_winders(&self, winders: Vec<Winder>) {
+        *self.winders.borrow_mut() = winders;
+    }
+
+    pub fn winder_count(&self) -> usize {
+        self.winders.borrow().len()
+    }
+
+    pub fn push_winder(&self, winder: Winder) {
+        self.winders.borrow_mut().push(winder);
+    }
+
+    pub fn pop_winder(&self) -> Option<Winder> {
+        self.winders.borrow_mut().pop()
+    }
+
+    pub fn truncate_winders(&self, len: usize) {
+        self.winders.borrow_mut().truncate(len);
+    }
+
+    pub fn finish_debugging(&self) {
+        if let Some(debugger) = &self.debugger {
+            debugger.borrow_mut().finish();
+        }
+    }
 }
@@ -1,36 +1,72 @@
 use crate::{
     gc::Gc,
-    rvals::{ByteCodeLambda, UpValue},
+    rvals::{ByteCodeLambda, Transducers, UpValue},
+    steel_vm::metrics::GcStats,
     values::contracts::{ContractType, FunctionContract},
     SteelVal,
 };
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::{Rc, Weak};
+use std::time::Instant;
 
+// Upvalues start out in the nursery, which is swept every `NURSERY_SIZE` allocations.
+// Whatever survives is moved to the old space, which is only walked once it grows past
+// its threshold.
+const NURSERY_SIZE: usize = 32;
 const GC_THRESHOLD: usize = 100;
 const GC_GROW_FACTOR: usize = 2;
 const _RESET_LIMIT: usize = 5;
 
+// What an upvalue takes up on the heap, including the counts kept by its `Rc`
+const UPVALUE_SIZE: usize =
+    std::mem::size_of::<RefCell<UpValue>>() + 2 * std::mem::size_of::<usize>();
+
 pub struct UpValueHeap {
-    memory: Vec<Rc<RefCell<UpValue>>>,
-    count: usize,
+    nursery: Vec<Rc<RefCell<UpValue>>>,
+    old: Vec<Rc<RefCell<UpValue>>>,
     threshold: usize,
+    stats: GcStats,
 }
 
 impl UpValueHeap {
     pub fn new() -> Self {
         UpValueHeap {
-            memory: Vec::new(),
-            count: 1,
+            nursery: Vec::new(),
+            old: Vec::new(),
             threshold: GC_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+
+    // The fork keeps the upvalues of the closures it shares with this heap alive, even once
+    // this heap has let go of them
+    pub(crate) fn fork(&self) -> Self {
+        UpValueHeap {
+            nursery: Vec::new(),
+            old: self.upvalues().cloned().collect(),
+            threshold: self.threshold,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// The number of times the heap has been collected, counting nursery collections
+    pub fn collections(&self) -> usize {
+        self.stats.minor_collections + self.stats.major_collections
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        GcStats {
+            nursery_upvalues: self.nursery.len(),
+            old_upvalues: self.old.len(),
+            live_bytes: self.upvalue_count() * UPVALUE_SIZE,
+            ..self.stats.clone()
         }
     }
 
     fn _profile_heap(&self) {
         let mapped = self
-            .memory
-            .iter()
+            .upvalues()
             .map(|x| Rc::weak_count(x))
             .collect::<Vec<_>>();
         let mut hm: HashMap<usize, usize> = HashMap::new();
@@ -43,31 +79,64 @@ impl UpValueHeap {
         println!("{:#?}", hm);
     }
 
-    // This does not handle cycles, perhaps add an explicit cycle detection via traversal
-    // to mark things reachable?
+    /// The number of upvalues currently owned by the heap
+    pub fn upvalue_count(&self) -> usize {
+        self.nursery.len() + self.old.len()
+    }
+
+    pub(crate) fn upvalues(&self) -> impl Iterator<Item = &Rc<RefCell<UpValue>>> {
+        self.old.iter().chain(self.nursery.iter())
+    }
+
+    fn record_pause(&mut self, started: Instant) {
+        let pause = started.elapsed();
+        self.stats.last_pause = pause;
+        self.stats.longest_pause = self.stats.longest_pause.max(pause);
+        self.stats.total_pause += pause;
+    }
+
+    // Closures only hold weak references to their upvalues, so dropping the upvalues that
+    // can't be reached from the roots is what frees closures that capture each other
     fn collect<'a>(
         &mut self,
         roots: impl Iterator<Item = &'a SteelVal>,
         function_stack: impl Iterator<Item = &'a Gc<ByteCodeLambda>>,
     ) {
-        if self.memory.len() > self.threshold {
-            // let prior = self.memory.len();
-
-            let mut changed = true;
-            while changed {
-                let prior_len = self.memory.len();
-                self.memory.retain(|x| Rc::weak_count(x) > 0);
-                let after = self.memory.len();
-                changed = prior_len != after;
-            }
-
-            // TODO fix the garbage collector
-            self.mark_and_sweep(roots, function_stack);
+        if self.nursery.len() < NURSERY_SIZE {
+            return;
+        }
 
-            self.threshold = (self.threshold + self.memory.len()) * GC_GROW_FACTOR;
+        let started = Instant::now();
+        self.collect_nursery();
 
-            self.count += 1;
+        if self.old.len() > self.threshold {
+            self.collect_old(roots, function_stack);
         }
+
+        self.record_pause(started);
+    }
+
+    // Most upvalues belong to closures that are already gone by the time the nursery fills up,
+    // and nothing needs to be walked to find those. Cycles survive, and are left to the old space.
+    fn collect_nursery(&mut self) {
+        drop_unreferenced(&mut self.nursery);
+        self.old.append(&mut self.nursery);
+        self.stats.minor_collections += 1;
+    }
+
+    fn collect_old<'a>(
+        &mut self,
+        roots: impl Iterator<Item = &'a SteelVal>,
+        function_stack: impl Iterator<Item = &'a Gc<ByteCodeLambda>>,
+    ) {
+        drop_unreferenced(&mut self.old);
+        self.mark_and_sweep(roots, function_stack);
+
+        // Grow based on what survived, otherwise unreachable cycles pile up between
+        // ever rarer collections
+        self.threshold = std::cmp::max(GC_THRESHOLD, self.old.len() * GC_GROW_FACTOR);
+
+        self.stats.major_collections += 1;
     }
 
     fn mark_and_sweep<'a>(
@@ -75,24 +144,23 @@ impl UpValueHeap {
         roots: impl Iterator<Item = &'a SteelVal>,
         function_stack: impl Iterator<Item = &'a Gc<ByteCodeLambda>>,
     ) {
+        let mut marker = Marker::default();
+
         // mark
         for root in roots {
-            traverse(root);
+            marker.traverse(root);
         }
 
         for function in function_stack {
-            for upvalue in function.upvalues() {
-                let upvalue = upvalue.upgrade().unwrap();
-                mark_upvalue(&upvalue);
-            }
+            marker.visit_closure(function);
         }
 
         // sweep
-        self.memory
+        self.old
             .retain(|x| x.borrow().is_reachable() || x.borrow().is_open());
 
         // put them back as unreachable
-        self.memory.iter().for_each(|x| x.borrow_mut().reset());
+        self.old.iter().for_each(|x| x.borrow_mut().reset());
     }
 
     pub(crate) fn new_upvalue<'a>(
@@ -104,7 +172,7 @@ impl UpValueHeap {
     ) -> Weak<RefCell<UpValue>> {
         let upvalue = Rc::new(RefCell::new(UpValue::new(index, next)));
         let weak_ptr = Rc::downgrade(&upvalue);
-        self.memory.push(upvalue);
+        self.nursery.push(upvalue);
 
         self.collect(roots, function_stack);
 
@@ -112,68 +180,252 @@ impl UpValueHeap {
     }
 }
 
+// Drops the upvalues no closure refers to anymore. Dropping one can drop the last closure
+// referring to another, so this goes until nothing changes.
+fn drop_unreferenced(upvalues: &mut Vec<Rc<RefCell<UpValue>>>) {
+    let mut changed = true;
+    while changed {
+        let prior_len = upvalues.len();
+        upvalues.retain(|x| Rc::weak_count(x) > 0);
+        changed = prior_len != upvalues.len();
+    }
+}
+
 // Use this function to traverse and find all reachable things
 // 'reachable' should be values living in the heap, stack, and in the
-fn traverse(val: &SteelVal) {
-    match val {
-        SteelVal::Pair(_) => {}
-        SteelVal::VectorV(_) => {}
-        SteelVal::HashMapV(_) => {}
-        SteelVal::HashSetV(_) => {}
-        SteelVal::StructV(_) => {}
-        SteelVal::PortV(_) => {}
-        SteelVal::Closure(c) => {
-            for upvalue in c.upvalues() {
-                let upvalue = upvalue.upgrade().unwrap();
-                mark_upvalue(&upvalue);
+//
+// Mutually recursive closures close over each other through their upvalues, so the
+// graph being walked can have cycles. Upvalues are only visited the first time they are
+// marked. Everything else that holds values carries no mark of its own, so those are
+// tracked by address, which also keeps values shared between many others from being
+// walked once for every path to them.
+#[derive(Default)]
+struct Marker {
+    visited: HashSet<usize>,
+}
+
+impl Marker {
+    fn first_visit<T>(&mut self, ptr: *const T) -> bool {
+        self.visited.insert(ptr as usize)
+    }
+
+    fn traverse(&mut self, val: &SteelVal) {
+        match val {
+            SteelVal::Pair(cell) => {
+                let mut cell = Some(cell);
+                while let Some(current) = cell.filter(|x| self.first_visit(x.as_ptr())) {
+                    self.traverse(&current.car);
+                    cell = current.cdr.as_ref();
+                }
+            }
+            SteelVal::VectorV(v) if self.first_visit(v.as_ptr()) => {
+                for value in v.iter() {
+                    self.traverse(value);
+                }
             }
+            SteelVal::Values(values) if self.first_visit(values.as_ptr()) => {
+                for value in values.iter() {
+                    self.traverse(value);
+                }
+            }
+            SteelVal::HashMapV(hm) if self.first_visit(hm.as_ptr()) => {
+                for (key, value) in hm.iter() {
+                    self.traverse(key);
+                    self.traverse(value);
+                }
+            }
+            SteelVal::HashSetV(hs) if self.first_visit(hs.as_ptr()) => {
+                for value in hs.iter() {
+                    self.traverse(value);
+                }
+            }
+            SteelVal::StructV(s) if self.first_visit(s.as_ptr()) => {
+                for value in s.fields().iter() {
+                    self.traverse(value);
+                }
+            }
+            SteelVal::PortV(_) => {}
+            SteelVal::Closure(c) => self.visit_closure(c),
+            SteelVal::IterV(transducer) => {
+                for op in &transducer.ops {
+                    match op {
+                        Transducers::Map(f)
+                        | Transducers::Filter(f)
+                        | Transducers::Take(f)
+                        | Transducers::Drop(f) => self.traverse(f),
+                    }
+                }
+            }
+            SteelVal::FutureV(_) => {}
+            SteelVal::StreamV(stream) => {
+                self.traverse(&stream.initial_value);
+                self.traverse(&stream.stream_thunk);
+            }
+            SteelVal::BoxV(b) if self.first_visit(b.as_ptr()) => {
+                let inner = b.borrow().clone();
+                self.traverse(&inner);
+            }
+            SteelVal::Contract(c) => self.visit_contract_type(c),
+            SteelVal::ContractedFunction(c) => {
+                self.visit_function_contract(&c.contract);
+                self.visit_closure(&c.function);
+            }
+            SteelVal::ContinuationFunction(k) => {
+                for value in k.stack.0.iter() {
+                    self.traverse(value);
+                }
+                for function in k.function_stack.iter() {
+                    self.visit_closure(function);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_function_contract(&mut self, f: &FunctionContract) {
+        for pre_condition in f.pre_conditions() {
+            self.visit_contract_type(pre_condition)
         }
-        SteelVal::IterV(_) => {}
-        SteelVal::FutureV(_) => {}
-        SteelVal::StreamV(_) => {}
-        SteelVal::BoxV(_) => {}
-        SteelVal::Contract(_) => {}
-        SteelVal::ContractedFunction(c) => {
-            visit_function_contract(&c.contract);
-            visit_closure(&c.function);
+        self.visit_contract_type(f.post_condition());
+    }
+
+    fn visit_contract_type(&mut self, contract: &ContractType) {
+        match contract {
+            ContractType::Flat(f) => {
+                self.traverse(f.predicate());
+            }
+            ContractType::Function(f) => {
+                self.visit_function_contract(f);
+            }
         }
-        SteelVal::ContinuationFunction(_) => {}
-        _ => {}
     }
-}
 
-fn visit_function_contract(f: &FunctionContract) {
-    for pre_condition in f.pre_conditions() {
-        visit_contract_type(pre_condition)
+    fn visit_closure(&mut self, c: &Gc<ByteCodeLambda>) {
+        for upvalue in c.upvalues() {
+            if let Some(upvalue) = upvalue.upgrade() {
+                self.mark_upvalue(&upvalue);
+            }
+        }
     }
-    visit_contract_type(f.post_condition());
-}
 
-fn visit_contract_type(contract: &ContractType) {
-    match contract {
-        ContractType::Flat(f) => {
-            traverse(f.predicate());
+    #[inline(always)]
+    fn mark_upvalue(&mut self, upvalue: &Rc<RefCell<UpValue>>) {
+        // Already reached through another path, which also covers cycles
+        if upvalue.borrow().is_reachable() {
+            return;
         }
-        ContractType::Function(f) => {
-            visit_function_contract(f);
+
+        upvalue.borrow_mut().mark_reachable();
+
+        // Don't hold the borrow while walking, the value can lead back to this upvalue
+        let inner = upvalue.borrow().get_value_if_closed().cloned();
+        if let Some(inner) = inner {
+            self.traverse(&inner);
         }
     }
 }
 
-fn visit_closure(c: &Gc<ByteCodeLambda>) {
-    for upvalue in c.upvalues() {
-        let upvalue = upvalue.upgrade().unwrap();
-        mark_upvalue(&upvalue);
+#[cfg(test)]
+mod heap_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    const MUTUALLY_RECURSIVE: &str = r#"
+        (define (make-pair)
+          (let ((f void) (g void))
+            (let ((f-prime (lambda (x) (if (= x 0) 0 (g (- x 1)))))
+                  (g-prime (lambda (x) (f x))))
+              (set! f f-prime)
+              (set! g g-prime))
+            f))
+
+        (define kept (make-pair))
+
+        (define (churn n)
+          (if (= n 0) (kept 10) (begin (make-pair) (churn (- n 1)))))
+    "#;
+
+    #[test]
+    fn unreachable_closure_cycles_are_reclaimed() {
+        let mut vm = Engine::new();
+        vm.run(MUTUALLY_RECURSIVE).unwrap();
+
+        // Every pair captures two upvalues that only the pair itself refers to
+        let result = vm.run("(churn 2000)").unwrap();
+        assert_eq!(result, vec![SteelVal::IntV(0)]);
+
+        let metrics = vm.metrics();
+        assert!(metrics.gc_runs > 0);
+        assert!(metrics.live_upvalues < 2 * GC_THRESHOLD);
     }
-}
 
-#[inline(always)]
-fn mark_upvalue(upvalue: &Rc<RefCell<UpValue>>) {
-    {
-        upvalue.borrow_mut().mark_reachable();
+    #[test]
+    fn short_lived_upvalues_never_leave_the_nursery() {
+        let mut vm = Engine::new();
+        vm.run(
+            r#"
+            (define (make-adder n) (lambda (x) (+ x n)))
+            (define (churn-adders n)
+              (if (= n 0) 0 (begin (make-adder n) (churn-adders (- n 1)))))
+            (churn-adders 2000)
+            "#,
+        )
+        .unwrap();
+
+        let stats = vm.gc_stats();
+        assert!(stats.minor_collections >= 2000 / NURSERY_SIZE);
+        assert_eq!(stats.major_collections, 0);
+        assert!(stats.old_upvalues < GC_THRESHOLD);
+        assert_eq!(
+            stats.live_bytes,
+            (stats.nursery_upvalues + stats.old_upvalues) * UPVALUE_SIZE
+        );
+        assert_eq!(vm.metrics().gc_runs, stats.minor_collections);
+    }
+
+    #[test]
+    fn cycles_are_left_to_major_collections() {
+        let mut vm = Engine::new();
+        vm.run(MUTUALLY_RECURSIVE).unwrap();
+        vm.run("(churn 2000)").unwrap();
+
+        let stats = vm.gc_stats();
+        assert!(stats.major_collections > 0);
+        assert!(stats.minor_collections > stats.major_collections);
+        assert!(stats.total_pause >= stats.longest_pause);
+        assert!(stats.longest_pause >= stats.last_pause);
     }
 
-    if let Some(inner) = upvalue.borrow().get_value_if_closed() {
-        traverse(inner);
+    #[test]
+    fn reachable_closure_cycles_survive_collection() {
+        let mut vm = Engine::new();
+        vm.run(MUTUALLY_RECURSIVE).unwrap();
+        vm.run("(churn 1000)").unwrap();
+
+        let before = vm.metrics().gc_runs;
+        let result = vm.run("(churn 1000) (kept 25)").unwrap();
+        assert!(vm.metrics().gc_runs > before);
+        assert_eq!(result.last(), Some(&SteelVal::IntV(0)));
+    }
+
+    #[test]
+    fn shared_values_are_only_marked_once() {
+        let mut vm = Engine::new();
+        vm.run(MUTUALLY_RECURSIVE).unwrap();
+
+        // Each level refers to the one below twice, so there are 2^28 paths to the bottom
+        let result = vm
+            .run(
+                r#"
+                (define (dag n) (if (= n 0) '() (let ((below (dag (- n 1)))) (list below below))))
+                (let ((shared (dag 28)))
+                  (churn 2000)
+                  (length shared))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(result.last(), Some(&SteelVal::IntV(2)));
+        assert!(vm.gc_stats().major_collections > 0);
     }
 }
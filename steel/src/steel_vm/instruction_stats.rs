@@ -0,0 +1,319 @@
+use crate::core::instructions::DenseInstruction;
+use crate::core::opcode::OpCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Opcode histogram and branch counts for a single function body
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FunctionStats {
+    /// Source span covering the body of the function, `None` for top level expressions
+    pub span: Option<(usize, usize)>,
+    pub instruction_count: usize,
+    pub opcodes: HashMap<OpCode, usize>,
+    /// Adjacent opcodes where the first fell straight through to the second, keyed like
+    /// `"READLOCAL PUSHCONST"`. These are the candidates for fusing into a single instruction.
+    pub opcode_pairs: HashMap<String, usize>,
+    pub branches_taken: usize,
+    pub branches_not_taken: usize,
+}
+
+/// An opcode sequence that ran back to back, summed over every function.
+/// Hot patterns are the candidates for super instructions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuperInstructionPattern {
+    pub opcodes: Vec<String>,
+    pub count: usize,
+}
+
+// What gets computed once per function body
+#[derive(Clone, Debug)]
+struct BodyInfo {
+    // Holding on to the body means the address used as the key can't be reused by another body
+    _body: Rc<[DenseInstruction]>,
+    span: (usize, usize),
+}
+
+impl BodyInfo {
+    fn new(body: &Rc<[DenseInstruction]>) -> Self {
+        let span = body
+            .iter()
+            .map(|x| x.span)
+            .filter(|x| x.end() > 0)
+            .fold((usize::MAX, 0), |(start, end), x| {
+                (start.min(x.start()), end.max(x.end()))
+            });
+
+        BodyInfo {
+            _body: Rc::clone(body),
+            span,
+        }
+    }
+}
+
+// Instructions after which execution doesn't just fall through to the next instruction
+fn ends_block(op_code: OpCode) -> bool {
+    use OpCode::*;
+    matches!(
+        op_code,
+        IF | JMP
+            | TCOJMP
+            | FUNC
+            | TAILCALL
+            | CALLGLOBAL
+            | CALLGLOBALTAIL
+            | CGLOCALCONST
+            | CALLCC
+            | APPLY
+            | TAILAPPLY
+            | EVAL
+            | POP
+            | BRANCHLT
+            | BRANCHLTE
+            | BRANCHGT
+            | BRANCHGTE
+            | BRANCHEQ
+    )
+}
+
+/// Instruction statistics collected while running, grouped by the function being executed.
+/// Closures constructed from the same lambda share a source span, so they get grouped together.
+#[derive(Clone, Debug, Default)]
+pub struct InstructionStats {
+    functions: HashMap<Option<(usize, usize)>, FunctionStats>,
+    bodies: HashMap<usize, BodyInfo>,
+    // The body, instruction pointer and opcode of the last instruction recorded
+    last: Option<(usize, usize, OpCode)>,
+}
+
+impl InstructionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn body_info(&mut self, body: &Rc<[DenseInstruction]>) -> &BodyInfo {
+        self.bodies
+            .entry(body.as_ptr() as usize)
+            .or_insert_with(|| BodyInfo::new(body))
+    }
+
+    fn function_stats(
+        &mut self,
+        body: &Rc<[DenseInstruction]>,
+        in_function: bool,
+    ) -> &mut FunctionStats {
+        let span = if in_function {
+            Some(self.body_info(body).span)
+        } else {
+            None
+        };
+
+        self.functions.entry(span).or_insert_with(|| FunctionStats {
+            span,
+            ..FunctionStats::default()
+        })
+    }
+
+    pub fn record_instruction(
+        &mut self,
+        body: &Rc<[DenseInstruction]>,
+        in_function: bool,
+        ip: usize,
+        op_code: OpCode,
+    ) {
+        let key = body.as_ptr() as usize;
+
+        // Only count a pair when the previous instruction fell straight through to this one.
+        // Other instructions jumping here don't matter, a super instruction leaves its second
+        // instruction in place so jumps to it still land on it, so pairs are counted across
+        // the blocks merging here as well
+        let pair = match self.last.replace((key, ip, op_code)) {
+            Some((last_key, last_ip, last_op))
+                if last_key == key && last_ip + 1 == ip && !ends_block(last_op) =>
+            {
+                Some(format!("{:?} {:?}", last_op, op_code))
+            }
+            _ => None,
+        };
+
+        let stats = self.function_stats(body, in_function);
+        stats.instruction_count += 1;
+        *stats.opcodes.entry(op_code).or_insert(0) += 1;
+
+        if let Some(pair) = pair {
+            *stats.opcode_pairs.entry(pair).or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_branch(&mut self, body: &Rc<[DenseInstruction]>, in_function: bool, taken: bool) {
+        let stats = self.function_stats(body, in_function);
+        if taken {
+            stats.branches_taken += 1;
+        } else {
+            stats.branches_not_taken += 1;
+        }
+    }
+
+    /// The collected statistics, with the hottest functions first
+    pub fn functions(&self) -> Vec<FunctionStats> {
+        let mut functions: Vec<_> = self.functions.values().cloned().collect();
+        functions.sort_by_key(|x| std::cmp::Reverse(x.instruction_count));
+        functions
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.functions()).unwrap()
+    }
+
+    /// The opcode pairs that ran at least `min_count` times across all functions, hottest first
+    pub fn hot_patterns(&self, min_count: usize) -> Vec<SuperInstructionPattern> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for stats in self.functions.values() {
+            for (pair, count) in &stats.opcode_pairs {
+                *counts.entry(pair.as_str()).or_insert(0) += count;
+            }
+        }
+
+        let mut patterns: Vec<_> = counts
+            .into_iter()
+            .filter(|(_, count)| *count >= min_count)
+            .map(|(pair, count)| SuperInstructionPattern {
+                opcodes: pair.split(' ').map(|x| x.to_string()).collect(),
+                count,
+            })
+            .collect();
+        patterns.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.opcodes.cmp(&b.opcodes))
+        });
+        patterns
+    }
+
+    /// The hot patterns as JSON, which is what gets written out as `patterns.json` for
+    /// generating super instructions
+    pub fn patterns_json(&self, min_count: usize) -> String {
+        serde_json::to_string_pretty(&self.hot_patterns(min_count)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod instruction_stats_tests {
+    use super::*;
+    use crate::parser::span::Span;
+    use OpCode::*;
+
+    fn body(ops: &[(OpCode, u32)]) -> Rc<[DenseInstruction]> {
+        ops.iter()
+            .map(|(op, payload)| DenseInstruction::new(*op, *payload, Span::new(0, 0)))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn pairs(stats: &InstructionStats) -> HashMap<String, usize> {
+        stats.functions()[0].opcode_pairs.clone()
+    }
+
+    #[test]
+    fn straight_line_pairs_are_counted() {
+        let body = body(&[(READLOCAL, 0), (PUSHCONST, 1), (LT, 0), (POP, 1)]);
+        let mut stats = InstructionStats::new();
+
+        for (ip, inst) in body.iter().enumerate() {
+            stats.record_instruction(&body, false, ip, inst.op_code);
+        }
+
+        let pairs = pairs(&stats);
+        assert_eq!(pairs.get("READLOCAL PUSHCONST"), Some(&1));
+        assert_eq!(pairs.get("PUSHCONST LT"), Some(&1));
+        assert_eq!(pairs.get("LT POP"), Some(&1));
+        assert_eq!(pairs.len(), 3);
+    }
+
+    #[test]
+    fn traces_are_cut_at_jumps_but_not_jump_targets() {
+        // 0: READLOCAL, 1: IF -> 3, 2: JMP -> 4, 3: PUSHCONST, 4: READLOCAL, 5: POP
+        let body = body(&[
+            (READLOCAL, 0),
+            (IF, 3),
+            (JMP, 4),
+            (PUSHCONST, 0),
+            (READLOCAL, 0),
+            (POP, 1),
+        ]);
+        let mut stats = InstructionStats::new();
+
+        // Take the true branch, which then falls through into the merge point at 4
+        for ip in [0, 1, 3, 4, 5].iter() {
+            stats.record_instruction(&body, false, *ip, body[*ip].op_code);
+        }
+
+        // Then the false branch, which jumps to the merge point instead
+        for ip in [0, 1, 2, 4, 5].iter() {
+            stats.record_instruction(&body, false, *ip, body[*ip].op_code);
+        }
+
+        let pairs = pairs(&stats);
+        assert_eq!(pairs.get("READLOCAL IF"), Some(&2));
+        // IF and JMP end the block, so nothing after them pairs up with them
+        assert_eq!(pairs.get("IF PUSHCONST"), None);
+        assert_eq!(pairs.get("IF JMP"), None);
+        assert_eq!(pairs.get("JMP READLOCAL"), None);
+        // 4 is the target of the JMP, but 3 still falls through to it
+        assert_eq!(pairs.get("PUSHCONST READLOCAL"), Some(&1));
+        assert_eq!(pairs.get("READLOCAL POP"), Some(&2));
+        assert_eq!(pairs.len(), 3);
+    }
+
+    #[test]
+    fn traces_are_cut_across_function_bodies() {
+        let caller = body(&[(PUSHCONST, 0), (CALLGLOBAL, 0), (PASS, 1), (POP, 0)]);
+        let callee = body(&[(READLOCAL, 0), (POP, 1)]);
+        let mut stats = InstructionStats::new();
+
+        stats.record_instruction(&caller, false, 0, PUSHCONST);
+        stats.record_instruction(&caller, false, 1, CALLGLOBAL);
+        stats.record_instruction(&callee, true, 0, READLOCAL);
+        stats.record_instruction(&callee, true, 1, POP);
+        stats.record_instruction(&caller, false, 2, PASS);
+        stats.record_instruction(&caller, false, 3, POP);
+
+        let functions = stats.functions();
+        let top_level = functions.iter().find(|x| x.span.is_none()).unwrap();
+        assert_eq!(top_level.opcode_pairs.get("PUSHCONST CALLGLOBAL"), Some(&1));
+        assert_eq!(top_level.opcode_pairs.get("PASS POP"), Some(&1));
+        assert_eq!(top_level.opcode_pairs.len(), 2);
+    }
+
+    #[test]
+    fn hot_patterns_are_summed_across_functions() {
+        let first = body(&[(READLOCAL, 0), (PUSHCONST, 1), (LT, 0)]);
+        let second = body(&[(READLOCAL, 0), (PUSHCONST, 1), (POP, 1)]);
+        let mut stats = InstructionStats::new();
+
+        for _ in 0..2 {
+            for (ip, inst) in first.iter().enumerate() {
+                stats.record_instruction(&first, true, ip, inst.op_code);
+            }
+        }
+        for (ip, inst) in second.iter().enumerate() {
+            stats.record_instruction(&second, false, ip, inst.op_code);
+        }
+
+        let patterns = stats.hot_patterns(2);
+        assert_eq!(
+            patterns,
+            vec![
+                SuperInstructionPattern {
+                    opcodes: vec!["READLOCAL".to_string(), "PUSHCONST".to_string()],
+                    count: 3,
+                },
+                SuperInstructionPattern {
+                    opcodes: vec!["PUSHCONST".to_string(), "LT".to_string()],
+                    count: 2,
+                },
+            ]
+        );
+        assert!(stats.patterns_json(2).contains("\"READLOCAL\""));
+    }
+}
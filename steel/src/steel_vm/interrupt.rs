@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stops whatever an [`Engine`](crate::steel_vm::engine::Engine) is running, from any thread.
+/// Made by [`Engine::interrupt_handle`](crate::steel_vm::engine::Engine::interrupt_handle).
+///
+/// The engine checks for an interrupt between instructions, and stops with an
+/// [`Interrupted`](crate::rerrs::ErrorKind::Interrupted) error when it finds one. Native
+/// functions aren't stopped partway through, and like budgets, interrupts aren't noticed when
+/// running without callbacks. An interrupt is used up by the evaluation it stops, so one sent
+/// while the engine is idle stops the next thing it runs.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub(crate) fn new(interrupted: Arc<AtomicBool>) -> Self {
+        InterruptHandle { interrupted }
+    }
+
+    /// Asks the engine to stop at the next instruction
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt is waiting to be noticed by the engine
+    pub fn is_pending(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// Takes back an interrupt the engine hasn't noticed yet
+    pub fn clear(&self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,466 @@
+//! The kernel is the engine procedural macros run in at compile time. Each transformer a
+//! program defines with `define-syntax` is defined in the kernel, and every use of its macro
+//! calls the transformer with the use site, turning whatever it returns back into syntax.
+//!
+//! ```scheme
+//! (define-syntax swap!
+//!   (lambda (stx)
+//!     (syntax-case stx ()
+//!       [(_ a b)
+//!        (with-syntax ([(tmp) (generate-temporaries '(tmp))])
+//!          (syntax (let ((tmp a)) (set! a b) (set! b tmp))))])))
+//! ```
+//!
+//! Syntax is represented as plain data, so `syntax->datum` and `datum->syntax` hand back what
+//! they're given. Procedural macros aren't hygienic - identifiers that shouldn't capture
+//! anything at the use site come from `generate-temporaries`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::compiler::passes::Folder;
+use crate::parser::ast::{Atom, ExprKind, LambdaFunction, List, Quote, Set};
+use crate::parser::builder::{
+    apply_function, begin, boolean, call, define, if_else, int, lambda, list, symbol, with_span,
+};
+use crate::parser::expander::match_syntax;
+use crate::parser::parser::SyntaxObject;
+use crate::parser::replace_idents::replace_identifiers;
+use crate::parser::span::Span;
+use crate::parser::tokens::TokenType;
+use crate::primitives::ListOperations;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{
+    collect_pair_into_vector, Custom, FromSteelVal, IntoSteelVal, Result, SteelVal,
+};
+use crate::steel_vm::engine::Engine;
+
+pub struct Kernel {
+    engine: Engine,
+    syntax: Rc<SyntaxTable>,
+}
+
+// The patterns and templates of every `syntax-case` in the kernel, which the lowered
+// transformers refer to by their index
+#[derive(Default)]
+struct SyntaxTable {
+    patterns: RefCell<Vec<(ExprKind, Vec<String>)>>,
+    templates: RefCell<Vec<ExprKind>>,
+    temporaries: Cell<usize>,
+}
+
+// What the pattern variables of the `syntax-case` clauses in scope are bound to
+#[derive(Clone, Debug)]
+struct SyntaxBindings(HashMap<String, ExprKind>);
+
+impl Custom for SyntaxBindings {}
+
+impl Kernel {
+    pub fn new() -> Self {
+        let mut engine = Engine::new();
+        let syntax = Rc::new(SyntaxTable::default());
+
+        engine
+            .register_value("syntax->datum", SteelVal::FuncV(syntax_to_datum_primitive))
+            .register_value("datum->syntax", SteelVal::FuncV(datum_to_syntax_primitive))
+            .register_value("%syntax-error", SteelVal::FuncV(syntax_error));
+
+        let table = Rc::clone(&syntax);
+        engine.register_value(
+            "generate-temporaries",
+            SteelVal::BoxedFunction(Rc::new(move |args| generate_temporaries(&table, args))),
+        );
+
+        let table = Rc::clone(&syntax);
+        engine.register_value(
+            "%syntax-match",
+            SteelVal::BoxedFunction(Rc::new(move |args| syntax_match(&table, args))),
+        );
+
+        let table = Rc::clone(&syntax);
+        engine.register_value(
+            "%syntax-fill",
+            SteelVal::BoxedFunction(Rc::new(move |args| syntax_fill(&table, args))),
+        );
+
+        Kernel { engine, syntax }
+    }
+
+    /// A kernel with its own copy of the transformers defined so far
+    pub fn fork(&self) -> Self {
+        Kernel {
+            engine: self.engine.fork(),
+            syntax: Rc::clone(&self.syntax),
+        }
+    }
+
+    /// Evaluates the transformer of the macro `name`, lowering any `syntax-case` inside of it
+    pub fn define_transformer(&mut self, name: &str, transformer: ExprKind) -> Result<()> {
+        let mut lowering = LowerSyntaxCase {
+            syntax: &self.syntax,
+            bindings: None,
+            error: None,
+        };
+        let transformer = lowering.visit(transformer);
+        if let Some(e) = lowering.error {
+            return Err(e);
+        }
+
+        self.engine.run_exprs(vec![define(name, transformer)])?;
+        Ok(())
+    }
+
+    /// Calls the transformer of the macro `name` on a use of the macro
+    pub fn expand(&mut self, name: &str, expr: List, span: Span) -> Result<ExprKind> {
+        // Syntax errors are about the use site, rather than where the transformer noticed them
+        let with_use_site = |e: SteelErr| match (e.kind(), e.span()) {
+            (ErrorKind::BadSyntax, _) | (_, None) => e.with_span(span),
+            _ => e,
+        };
+
+        let transformer = self.engine.extract_value(name)?;
+        let syntax = syntax_to_datum(ExprKind::List(expr))?;
+        let expanded = self
+            .engine
+            .call_function(&transformer, vec![syntax])
+            .map_err(with_use_site)?;
+
+        datum_to_syntax(&expanded).map_err(with_use_site)
+    }
+}
+
+// Turns syntax into the datum a transformer works on
+fn syntax_to_datum(syntax: ExprKind) -> Result<SteelVal> {
+    SteelVal::try_from(WriteOutForms.visit(syntax))
+}
+
+// Turns the datum a transformer returns back into syntax, parsing the special forms in it
+fn datum_to_syntax(value: &SteelVal) -> Result<ExprKind> {
+    match value {
+        SteelVal::SymbolV(s) => Ok(symbol(s)),
+        SteelVal::Pair(_) => datum_to_syntax(&collect_pair_into_vector(value)),
+        SteelVal::VectorV(items) => {
+            let items = items
+                .iter()
+                .map(datum_to_syntax)
+                .collect::<Result<Vec<_>>>()?;
+            list(items)
+        }
+        other => ExprKind::try_from(other)
+            .map_err(|e| SteelErr::new(ErrorKind::ConversionError, e.to_string())),
+    }
+}
+
+// Converting syntax straight to a value is meant for quoted data, which drops `quote` and the
+// variable of a `set!`, and names a `lambda` lowered from a `let` after the `let`. Those are
+// written out as lists first.
+struct WriteOutForms;
+
+impl Folder for WriteOutForms {
+    fn visit_lambda_function(&mut self, lambda_function: Box<LambdaFunction>) -> ExprKind {
+        let LambdaFunction { args, body, .. } = *lambda_function;
+        let args = args.into_iter().map(|x| self.visit(x)).collect();
+        ExprKind::List(List::new(vec![
+            symbol("lambda"),
+            ExprKind::List(List::new(args)),
+            self.visit(body),
+        ]))
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> ExprKind {
+        let Quote { expr, location } = *quote;
+        ExprKind::List(List::new(vec![
+            ExprKind::Atom(Atom::new(location)),
+            self.visit(expr),
+        ]))
+    }
+
+    fn visit_set(&mut self, s: Box<Set>) -> ExprKind {
+        let Set {
+            variable,
+            expr,
+            location,
+        } = *s;
+        ExprKind::List(List::new(vec![
+            ExprKind::Atom(Atom::new(location)),
+            self.visit(variable),
+            self.visit(expr),
+        ]))
+    }
+}
+
+fn syntax_to_datum_primitive(args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 1 {
+        stop!(ArityMismatch => "syntax->datum takes 1 argument");
+    }
+    Ok(args[0].clone())
+}
+
+fn datum_to_syntax_primitive(args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "datum->syntax takes 2 arguments");
+    }
+    Ok(args[1].clone())
+}
+
+fn syntax_error(args: &[SteelVal]) -> Result<SteelVal> {
+    match args {
+        [syntax] => stop!(BadSyntax => "syntax-case found no pattern matching {}", syntax),
+        _ => stop!(ArityMismatch => "%syntax-error takes 1 argument"),
+    }
+}
+
+fn generate_temporaries(table: &SyntaxTable, args: &[SteelVal]) -> Result<SteelVal> {
+    let count = match args {
+        [value @ SteelVal::Pair(_)] => match collect_pair_into_vector(value) {
+            SteelVal::VectorV(items) => items.len(),
+            _ => 0,
+        },
+        [SteelVal::VectorV(items)] => items.len(),
+        [_] => stop!(TypeMismatch => "generate-temporaries expects a list"),
+        _ => stop!(ArityMismatch => "generate-temporaries takes 1 argument"),
+    };
+
+    let temporaries = (0..count)
+        .map(|_| {
+            let next = table.temporaries.get();
+            table.temporaries.set(next + 1);
+            SteelVal::SymbolV(format!("##temporary{}", next).into())
+        })
+        .collect::<Vec<_>>();
+
+    ListOperations::built_in_list_func_flat(&temporaries)
+}
+
+// `(%syntax-match syntax pattern outer-bindings)`, which is the bindings of the pattern along
+// with the outer ones when it matches, and #f otherwise
+fn syntax_match(table: &SyntaxTable, args: &[SteelVal]) -> Result<SteelVal> {
+    let (syntax, index, outer) = match args {
+        [syntax, SteelVal::IntV(index), outer] => (syntax, *index as usize, outer),
+        _ => stop!(ArityMismatch => "%syntax-match takes syntax, a pattern and bindings"),
+    };
+
+    let syntax = ExprKind::try_from(syntax)
+        .map_err(|e| SteelErr::new(ErrorKind::ConversionError, e.to_string()))?;
+    let patterns = table.patterns.borrow();
+    let (pattern, literals) = &patterns[index];
+
+    match match_syntax(pattern, literals, &syntax)? {
+        Some(bindings) => {
+            let mut all = match outer {
+                SteelVal::BoolV(false) => HashMap::new(),
+                outer => SyntaxBindings::from_steelval(outer.clone())?.0,
+            };
+            all.extend(bindings);
+            SyntaxBindings(all).into_steelval()
+        }
+        None => Ok(SteelVal::BoolV(false)),
+    }
+}
+
+// `(%syntax-fill template bindings)`, the template with the pattern variables filled in
+fn syntax_fill(table: &SyntaxTable, args: &[SteelVal]) -> Result<SteelVal> {
+    let (index, bindings) = match args {
+        [SteelVal::IntV(index), bindings] => (*index as usize, bindings),
+        _ => stop!(ArityMismatch => "%syntax-fill takes a template and bindings"),
+    };
+
+    let bindings = match bindings {
+        SteelVal::BoolV(false) => HashMap::new(),
+        bindings => SyntaxBindings::from_steelval(bindings.clone())?.0,
+    };
+    let template = table.templates.borrow()[index].clone();
+
+    syntax_to_datum(replace_identifiers(
+        template,
+        &bindings,
+        None,
+        Span::new(0, 0),
+    )?)
+}
+
+// Lowers `syntax-case`, `with-syntax` and `syntax` into calls to the kernel's primitives,
+// keeping the patterns and templates on the Rust side:
+//
+// ```scheme
+// (syntax-case stx () [(_ a) (syntax (f a))])
+// ;; =>
+// ((lambda (##syntax0)
+//    ((lambda (##bindings0)
+//       (if ##bindings0 (%syntax-fill 0 ##bindings0) (%syntax-error ##syntax0)))
+//     (%syntax-match ##syntax0 0 #f)))
+//  stx)
+// ```
+struct LowerSyntaxCase<'a> {
+    syntax: &'a SyntaxTable,
+    // The variable holding the bindings of the innermost clause being lowered
+    bindings: Option<String>,
+    error: Option<SteelErr>,
+}
+
+fn identifier_span(expr: Option<&ExprKind>) -> Option<(&str, Span)> {
+    match expr {
+        Some(ExprKind::Atom(Atom {
+            syn:
+                SyntaxObject {
+                    ty: TokenType::Identifier(s),
+                    span,
+                    ..
+                },
+        })) => Some((s, *span)),
+        _ => None,
+    }
+}
+
+impl<'a> LowerSyntaxCase<'a> {
+    fn bindings(&self) -> ExprKind {
+        match &self.bindings {
+            Some(bindings) => symbol(bindings),
+            None => boolean(false),
+        }
+    }
+
+    fn syntax_case(&mut self, l: List, span: Span) -> Result<ExprKind> {
+        let mut args = l.args.into_iter().skip(1);
+        let (input, literals) = match (args.next(), args.next()) {
+            (Some(input), Some(ExprKind::List(literals))) => (input, literals),
+            _ => {
+                stop!(BadSyntax => "syntax-case expects syntax, a list of literals and clauses"; span)
+            }
+        };
+        let literals = literals
+            .iter()
+            .map(|x| {
+                x.atom_identifier_or_else(
+                    throw!(BadSyntax => "syntax-case literals must be identifiers"; span),
+                )
+                .map(|x| x.to_string())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let input_variable = format!("##syntax{}", self.syntax.patterns.borrow().len());
+        let input = self.visit(input);
+
+        let mut lowered = call("%syntax-error", vec![symbol(&input_variable)]);
+        for clause in args.collect::<Vec<_>>().into_iter().rev() {
+            let mut parts = match clause {
+                ExprKind::List(l) if l.len() == 2 || l.len() == 3 => l.args,
+                _ => {
+                    stop!(BadSyntax => "syntax-case clauses are a pattern, an optional fender and an expression"; span)
+                }
+            };
+            let body = parts.pop().unwrap();
+            let fender = if parts.len() == 2 { parts.pop() } else { None };
+            let pattern = parts.pop().unwrap();
+
+            let index = {
+                let mut patterns = self.syntax.patterns.borrow_mut();
+                patterns.push((pattern, literals.clone()));
+                patterns.len() - 1
+            };
+            let variable = format!("##bindings{}", index);
+            let matched = call(
+                "%syntax-match",
+                vec![
+                    symbol(&input_variable),
+                    int(index as isize),
+                    self.bindings(),
+                ],
+            );
+
+            let outer = self.bindings.replace(variable.clone());
+            let body = self.visit(body);
+            let fender = fender.map(|x| self.visit(x));
+            self.bindings = outer;
+
+            let test = match fender {
+                Some(fender) => if_else(symbol(&variable), fender, boolean(false)),
+                None => symbol(&variable),
+            };
+            lowered = apply_function(
+                lambda(&[&variable], if_else(test, body, lowered)),
+                vec![matched],
+            );
+        }
+
+        Ok(with_span(
+            apply_function(lambda(&[&input_variable], lowered), vec![input]),
+            span,
+        ))
+    }
+
+    // `(with-syntax ([pattern syntax] ...) body ...)` matches each pattern in turn
+    fn with_syntax(&mut self, l: List, span: Span) -> Result<ExprKind> {
+        let mut args = l.args.into_iter().skip(1);
+        let clauses = match args.next() {
+            Some(ExprKind::List(clauses)) => clauses,
+            _ => stop!(BadSyntax => "with-syntax expects a list of patterns and syntax"; span),
+        };
+
+        let mut body = args.collect::<Vec<_>>();
+        let mut lowered = match body.len() {
+            0 => stop!(BadSyntax => "with-syntax expects a body"; span),
+            1 => body.pop().unwrap(),
+            _ => begin(body),
+        };
+
+        for clause in clauses.args.into_iter().rev() {
+            let (pattern, syntax) = match clause {
+                ExprKind::List(l) if l.len() == 2 => {
+                    let mut parts = l.args.into_iter();
+                    (parts.next().unwrap(), parts.next().unwrap())
+                }
+                _ => stop!(BadSyntax => "with-syntax clauses are a pattern and syntax"; span),
+            };
+            lowered = ExprKind::List(List::new(vec![
+                symbol("syntax-case"),
+                syntax,
+                ExprKind::List(List::new(Vec::new())),
+                ExprKind::List(List::new(vec![pattern, lowered])),
+            ]));
+        }
+
+        Ok(self.visit(lowered))
+    }
+
+    fn template(&mut self, mut l: List, span: Span) -> Result<ExprKind> {
+        if l.len() != 2 {
+            stop!(BadSyntax => "syntax expects a template"; span);
+        }
+
+        let index = {
+            let mut templates = self.syntax.templates.borrow_mut();
+            templates.push(l.args.pop().unwrap());
+            templates.len() - 1
+        };
+        Ok(with_span(
+            call("%syntax-fill", vec![int(index as isize), self.bindings()]),
+            span,
+        ))
+    }
+}
+
+impl<'a> Folder for LowerSyntaxCase<'a> {
+    fn visit_list(&mut self, mut l: List) -> ExprKind {
+        let lowered = match identifier_span(l.first()) {
+            Some(("syntax-case", span)) => self.syntax_case(l, span),
+            Some(("with-syntax", span)) => self.with_syntax(l, span),
+            Some(("syntax", span)) => self.template(l, span),
+            _ => {
+                l.args = l.args.into_iter().map(|e| self.visit(e)).collect();
+                return ExprKind::List(l);
+            }
+        };
+
+        lowered.unwrap_or_else(|e| {
+            self.error.get_or_insert(e);
+            boolean(false)
+        })
+    }
+
+    fn visit_quote(&mut self, quote: Box<Quote>) -> ExprKind {
+        ExprKind::Quote(quote)
+    }
+}
@@ -20,6 +20,7 @@ use crate::values::lazy_stream::LazyStream;
 pub(crate) struct LazyStreamIter<'global, 'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts>
 {
     stream: LazyStream,
+    error: Option<SteelErr>,
     constants: &'global CT,
     cur_inst_span: &'global Span,
     callback: &'global EvaluationProgress,
@@ -43,6 +44,7 @@ impl<'global, 'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts>
     ) -> Self {
         Self {
             stream,
+            error: None,
             constants,
             cur_inst_span,
             callback,
@@ -60,7 +62,7 @@ impl<'global, 'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> Iterato
     type Item = Result<SteelVal>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.stream.empty_stream {
-            return None;
+            return self.error.take().map(Err);
         }
 
         let stream_first = self.stream.stream_first();
@@ -77,11 +79,13 @@ impl<'global, 'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> Iterato
             self.apply_contracts,
         );
 
-        if let Ok(next_value) = next_value {
-            if let SteelVal::StreamV(lazy_stream) = next_value {
-                self.stream = lazy_stream.unwrap();
-            } else {
-                panic!("Lazy stream not implemented for the given type");
+        match next_value {
+            Ok(SteelVal::StreamV(lazy_stream)) => self.stream = lazy_stream.unwrap(),
+            Ok(_) => panic!("Lazy stream not implemented for the given type"),
+            // The rest of the stream couldn't be made, which is the last thing the iterator gives
+            Err(e) => {
+                self.stream = LazyStream::new_empty_stream();
+                self.error = Some(e);
             }
         }
 
@@ -0,0 +1,341 @@
+use crate::{
+    gc::Gc,
+    rvals::{ByteCodeLambda, ConsCell, CustomType, Transducers, UpValue},
+    values::contracts::{ContractType, FunctionContract},
+    SteelVal,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// What an `Engine` does about leaked values when it is dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LeakAudit {
+    /// Don't look for leaks
+    #[default]
+    Off,
+    /// Print any leaks to stderr
+    Report,
+    /// Panic with the leaks in debug builds, and print them in release builds
+    Assert,
+}
+
+/// A custom value held by the engine that is also referenced from somewhere the engine
+/// can't see, so it will outlive the engine
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leak {
+    /// The rust type of the value
+    pub type_name: String,
+    /// Where in the engine the value was first found, i.e. global `conn` > vector[1]
+    pub origin: String,
+    /// References to the value that the engine does not own
+    pub outside_references: usize,
+}
+
+/// Every leaked value found in an `Engine`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    pub leaks: Vec<Leak>,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} value(s) will outlive the engine:", self.leaks.len())?;
+        for leak in &self.leaks {
+            writeln!(
+                f,
+                "  {} at {}, with {} outside reference(s)",
+                leak.type_name, leak.origin, leak.outside_references
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Found {
+    type_name: String,
+    origin: String,
+    strong_count: usize,
+    references: usize,
+}
+
+// Walks everything the engine owns, counting the references it holds to each custom value.
+// Shared containers are only walked once, so every reference counted is a distinct one, and
+// nothing is cloned along the way since that would add references of its own.
+#[derive(Default)]
+pub(crate) struct LeakAuditor {
+    containers: HashSet<usize>,
+    found: HashMap<usize, Found>,
+    order: Vec<usize>,
+    path: Vec<String>,
+}
+
+impl LeakAuditor {
+    pub(crate) fn visit_global(&mut self, name: &str, value: &SteelVal) {
+        self.path.push(format!("global `{}`", name));
+        self.traverse(value);
+        self.path.pop();
+    }
+
+    pub(crate) fn visit_heap_upvalue(&mut self, upvalue: &Rc<RefCell<UpValue>>) {
+        self.path.push("an unreachable closure".to_string());
+        self.visit_upvalue(upvalue);
+        self.path.pop();
+    }
+
+    pub(crate) fn visit_pinned(&mut self, value: &SteelVal) {
+        self.path.push("a pinned value".to_string());
+        self.traverse(value);
+        self.path.pop();
+    }
+
+    pub(crate) fn report(self) -> LeakReport {
+        let mut found = self.found;
+        let leaks = self
+            .order
+            .into_iter()
+            .filter_map(|ptr| found.remove(&ptr))
+            .filter(|x| x.strong_count > x.references)
+            .map(|x| Leak {
+                type_name: x.type_name,
+                origin: x.origin,
+                outside_references: x.strong_count - x.references,
+            })
+            .collect();
+
+        LeakReport { leaks }
+    }
+
+    // Returns true the first time a container is seen
+    fn first_visit<T>(&mut self, ptr: *const T) -> bool {
+        self.containers.insert(ptr as usize)
+    }
+
+    fn nested(&mut self, segment: String, value: &SteelVal) {
+        self.path.push(segment);
+        self.traverse(value);
+        self.path.pop();
+    }
+
+    fn traverse(&mut self, val: &SteelVal) {
+        match val {
+            SteelVal::Custom(c) => self.visit_custom(c),
+            SteelVal::Pair(cell) => self.visit_list(cell),
+            SteelVal::VectorV(v) if self.first_visit(v.as_ptr()) => {
+                for (i, value) in v.iter().enumerate() {
+                    self.nested(format!("vector[{}]", i), value);
+                }
+            }
+            SteelVal::Values(values) if self.first_visit(values.as_ptr()) => {
+                for (i, value) in values.iter().enumerate() {
+                    self.nested(format!("values[{}]", i), value);
+                }
+            }
+            SteelVal::HashMapV(hm) if self.first_visit(hm.as_ptr()) => {
+                for (key, value) in hm.iter() {
+                    self.nested(format!("hash map value for {}", key), value);
+                }
+            }
+            SteelVal::HashSetV(hs) if self.first_visit(hs.as_ptr()) => {
+                for value in hs.iter() {
+                    self.nested("hash set".to_string(), value);
+                }
+            }
+            SteelVal::StructV(s) if self.first_visit(s.as_ptr()) => {
+                for (i, value) in s.fields().iter().enumerate() {
+                    self.nested(format!("field {} of struct {}", i, s.pretty_print()), value);
+                }
+            }
+            SteelVal::BoxV(b) if self.first_visit(b.as_ptr()) => {
+                self.nested("box".to_string(), &b.borrow());
+            }
+            SteelVal::Closure(c) => self.visit_closure(c),
+            SteelVal::IterV(t) if self.first_visit(t.as_ptr()) => {
+                for op in &t.ops {
+                    match op {
+                        Transducers::Map(f)
+                        | Transducers::Filter(f)
+                        | Transducers::Take(f)
+                        | Transducers::Drop(f) => self.nested("transducer".to_string(), f),
+                    }
+                }
+            }
+            SteelVal::StreamV(s) if self.first_visit(s.as_ptr()) => {
+                self.nested("stream".to_string(), &s.initial_value);
+                self.nested("stream".to_string(), &s.stream_thunk);
+            }
+            SteelVal::Contract(c) if self.first_visit(c.as_ptr()) => self.visit_contract_type(c),
+            SteelVal::ContractedFunction(c) if self.first_visit(c.as_ptr()) => {
+                self.visit_function_contract(&c.contract);
+                self.visit_closure(&c.function);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_custom(&mut self, c: &Gc<RefCell<Box<dyn CustomType>>>) {
+        let ptr = c.as_ptr() as usize;
+
+        if let Some(found) = self.found.get_mut(&ptr) {
+            found.references += 1;
+            return;
+        }
+
+        self.order.push(ptr);
+        self.found.insert(
+            ptr,
+            Found {
+                type_name: c.borrow().name(),
+                origin: self.path.join(" > "),
+                strong_count: Gc::strong_count(c),
+                references: 1,
+            },
+        );
+    }
+
+    // Lists are walked iteratively so long ones don't blow the stack
+    fn visit_list(&mut self, cell: &Gc<ConsCell>) {
+        let mut current = Some(cell.clone());
+        let mut i = 0;
+
+        while let Some(cell) = current {
+            if !self.first_visit(cell.as_ptr()) {
+                break;
+            }
+            self.nested(format!("list[{}]", i), &cell.car);
+            current = cell.cdr.clone();
+            i += 1;
+        }
+    }
+
+    fn visit_closure(&mut self, c: &Gc<ByteCodeLambda>) {
+        if !self.first_visit(c.as_ptr()) {
+            return;
+        }
+
+        for upvalue in c.upvalues() {
+            if let Some(upvalue) = upvalue.upgrade() {
+                self.path.push("closure".to_string());
+                self.visit_upvalue(&upvalue);
+                self.path.pop();
+            }
+        }
+    }
+
+    fn visit_upvalue(&mut self, upvalue: &Rc<RefCell<UpValue>>) {
+        if !self.first_visit(Rc::as_ptr(upvalue)) {
+            return;
+        }
+
+        // Borrow rather than clone, a clone would show up as another reference. Containers
+        // are only visited once, so this can't come back around to a mutable borrow.
+        if let Some(inner) = upvalue.borrow().get_value_if_closed() {
+            self.traverse(inner);
+        }
+    }
+
+    fn visit_function_contract(&mut self, f: &FunctionContract) {
+        for pre_condition in f.pre_conditions() {
+            self.visit_contract_type(pre_condition)
+        }
+        self.visit_contract_type(f.post_condition());
+    }
+
+    fn visit_contract_type(&mut self, contract: &ContractType) {
+        match contract {
+            ContractType::Flat(f) => self.nested("contract".to_string(), f.predicate()),
+            ContractType::Function(f) => self.visit_function_contract(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod leaks_tests {
+    use super::*;
+    use crate::rvals::{Custom, IntoSteelVal};
+    use crate::steel_vm::engine::Engine;
+
+    #[derive(Clone, Debug)]
+    struct Handle;
+    impl Custom for Handle {}
+
+    #[test]
+    fn values_only_the_engine_holds_are_not_leaks() {
+        let mut vm = Engine::new();
+        vm.register_value("handle", Handle.into_steelval().unwrap());
+        vm.run("(define handles (vector handle handle))").unwrap();
+        assert!(vm.leaks().is_empty());
+    }
+
+    #[test]
+    fn leaks_report_where_they_were_found() {
+        let mut vm = Engine::new();
+        let handle = Handle.into_steelval().unwrap();
+        vm.register_value("handle", handle.clone());
+        vm.run("(define stash (list 1 handle))").unwrap();
+        vm.run("(define held ((lambda (x) (lambda () x)) handle))")
+            .unwrap();
+        vm.run("(set! handle 0)").unwrap();
+
+        let report = vm.leaks();
+        assert_eq!(report.leaks.len(), 1);
+
+        let leak = &report.leaks[0];
+        assert!(leak.type_name.ends_with("Handle"));
+        assert_eq!(leak.origin, "global `stash` > list[1]");
+        assert_eq!(leak.outside_references, 1);
+        assert!(report.to_string().contains("global `stash` > list[1]"));
+
+        // Once it's gone from the list, the closure is where it's held
+        vm.run("(set! stash (list 1))").unwrap();
+        let report = vm.leaks();
+        assert_eq!(report.leaks[0].origin, "global `held` > closure");
+    }
+
+    #[test]
+    #[should_panic(expected = "will outlive the engine")]
+    fn asserting_audit_panics_on_drop() {
+        let handle = Handle.into_steelval().unwrap();
+        let mut vm = Engine::new();
+        vm.audit_leaks_on_drop(LeakAudit::Assert);
+        vm.register_value("handle", handle.clone());
+        drop(vm);
+    }
+
+    #[test]
+    fn asserting_audit_is_quiet_without_leaks() {
+        let mut vm = Engine::new();
+        vm.audit_leaks_on_drop(LeakAudit::Assert);
+        vm.register_value("handle", Handle.into_steelval().unwrap());
+        drop(vm);
+    }
+
+    #[test]
+    fn pinned_values_belong_to_the_engine() {
+        let vm = Engine::new();
+        let handle = Handle.into_steelval().unwrap();
+
+        // The host still holds a clone of its own
+        let pinned = vm.pin(handle.clone());
+        assert_eq!(vm.leaks().leaks[0].origin, "a pinned value");
+        drop(handle);
+        assert!(vm.leaks().is_empty());
+
+        let handle = pinned.take().unwrap();
+        assert_eq!(vm.pinned(), 0);
+        drop(vm.pin(handle));
+        assert_eq!(vm.pinned(), 0);
+
+        // A guard that outlives its engine has nothing left to give
+        let pinned = vm.pin(SteelVal::IntV(1));
+        drop(vm);
+        assert!(pinned.get().is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Counters for everything an `Engine` has done since it was created
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Top level expressions executed, including the ones that errored
+    pub executions: usize,
+    /// Top level expressions that returned an error while running
+    pub errors: usize,
+    /// Programs that failed to parse, expand or compile, and so never ran
+    pub compile_errors: usize,
+    /// Programs loaded from the compilation cache instead of being compiled
+    pub compilation_cache_hits: usize,
+    /// Collections of the upvalue heap
+    pub gc_runs: usize,
+    /// Upvalues captured by closures that the heap is currently holding on to
+    pub live_upvalues: usize,
+    /// Only counted when running with callbacks, the same as `on_progress`
+    pub instructions_executed: usize,
+    /// How long each required module took to compile the last time it was compiled,
+    /// including compiling its own dependencies
+    pub module_compile_times: HashMap<PathBuf, Duration>,
+}
+
+/// What the collector for the upvalues captured by closures has done so far, see
+/// [`Engine::gc_stats`](crate::steel_vm::engine::Engine::gc_stats)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Collections of the nursery, where newly captured upvalues start out
+    pub minor_collections: usize,
+    /// Collections of the upvalues that survived the nursery
+    pub major_collections: usize,
+    pub nursery_upvalues: usize,
+    pub old_upvalues: usize,
+    /// Bytes taken up by the upvalues on the heap, not counting the values they hold on to
+    pub live_bytes: usize,
+    /// How long the most recent collection paused the program for
+    pub last_pause: Duration,
+    pub longest_pause: Duration,
+    pub total_pause: Duration,
+}
+
+impl Metrics {
+    /// Writes the metrics out in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            (
+                "steel_executions_total",
+                "Top level expressions executed",
+                self.executions,
+            ),
+            (
+                "steel_errors_total",
+                "Top level expressions that returned an error while running",
+                self.errors,
+            ),
+            (
+                "steel_compile_errors_total",
+                "Programs that failed to compile",
+                self.compile_errors,
+            ),
+            (
+                "steel_compilation_cache_hits_total",
+                "Programs loaded from the compilation cache",
+                self.compilation_cache_hits,
+            ),
+            (
+                "steel_gc_runs_total",
+                "Garbage collections of the upvalue heap",
+                self.gc_runs,
+            ),
+            (
+                "steel_instructions_executed_total",
+                "Bytecode instructions executed",
+                self.instructions_executed,
+            ),
+        ];
+
+        for (name, help, value) in counters.iter() {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+
+        let name = "steel_live_upvalues";
+        writeln!(out, "# HELP {} Upvalues held by the upvalue heap", name).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        writeln!(out, "{} {}", name, self.live_upvalues).unwrap();
+
+        let name = "steel_module_compile_seconds";
+        writeln!(out, "# HELP {} Time spent compiling each module", name).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+
+        let mut modules: Vec<_> = self.module_compile_times.iter().collect();
+        modules.sort();
+
+        for (module, time) in modules {
+            writeln!(
+                out,
+                "{}{{module=\"{}\"}} {}",
+                name,
+                escape_label(&module.to_string_lossy()),
+                time.as_secs_f64()
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+    use crate::rerrs::{ErrorKind, SteelErr};
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+
+    #[test]
+    fn executions_and_errors_are_counted() {
+        let mut vm = Engine::new();
+        vm.register_value(
+            "fail",
+            SteelVal::FuncV(|_| stop!(Generic => "failed on purpose")),
+        );
+        let before = vm.metrics();
+
+        let results = vm.run("(define x 10) (+ x 1)").unwrap();
+        assert!(vm.run("(fail x)").is_err());
+        assert!(vm.run("(+ x").is_err());
+
+        // Each top level expression that compiles is executed on its own
+        let after = vm.metrics();
+        assert_eq!(after.executions - before.executions, results.len() + 1);
+        assert_eq!(after.errors - before.errors, 1);
+        assert_eq!(after.compile_errors - before.compile_errors, 1);
+        assert!(after.instructions_executed > before.instructions_executed);
+    }
+
+    #[test]
+    fn instructions_are_not_counted_without_callbacks() {
+        let mut vm = Engine::new();
+        let before = vm.metrics().instructions_executed;
+        vm.run_without_callbacks("(+ 1 2)").unwrap();
+        assert_eq!(vm.metrics().instructions_executed, before);
+    }
+
+    #[test]
+    fn prometheus_text_format() {
+        let mut metrics = Metrics {
+            executions: 4,
+            errors: 1,
+            ..Metrics::default()
+        };
+        metrics.module_compile_times.insert(
+            PathBuf::from("/lib/\"odd\".rkt"),
+            Duration::from_millis(250),
+        );
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE steel_executions_total counter\nsteel_executions_total 4\n"));
+        assert!(text.contains("steel_errors_total 1\n"));
+        assert!(
+            text.contains("steel_module_compile_seconds{module=\"/lib/\\\"odd\\\".rkt\"} 0.25\n")
+        );
+    }
+}
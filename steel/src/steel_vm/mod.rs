@@ -1,16 +1,32 @@
+pub mod accounting;
+pub mod budget;
+pub mod builder;
 pub(crate) mod const_evaluation;
 mod contracts;
+pub mod debugger;
 pub mod engine;
-mod evaluation_progress;
+pub(crate) mod evaluation_progress;
 mod heap;
+pub mod instruction_stats;
+pub mod interrupt;
+pub(crate) mod kernel;
 mod lazy_stream;
+pub mod leaks;
+pub mod metrics;
 pub mod options;
-mod primitives;
+pub mod pin;
+pub(crate) mod primitives;
+pub mod profiler;
 pub mod register_fn;
+pub mod session;
+pub mod snapshots;
 mod stack;
+mod sync_engine;
 #[cfg(test)]
 mod test_util;
 #[cfg(test)]
 mod tests;
+pub mod thread;
 mod transducers;
+pub mod typed_fn;
 pub(crate) mod vm;
@@ -0,0 +1,73 @@
+use crate::SteelVal;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// The values pinned in an engine, by the id of their guard
+#[derive(Debug, Default)]
+pub(crate) struct Pins {
+    next: usize,
+    values: HashMap<usize, SteelVal>,
+}
+
+pub(crate) type PinTable = Rc<RefCell<Pins>>;
+
+impl Pins {
+    pub(crate) fn pin(table: &PinTable, value: SteelVal) -> PinGuard {
+        let mut pins = table.borrow_mut();
+        let id = pins.next;
+        pins.next += 1;
+        pins.values.insert(id, value);
+
+        PinGuard {
+            id,
+            table: Rc::downgrade(table),
+        }
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &SteelVal> {
+        self.values.values()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// A value kept alive by an [`Engine`](crate::steel_vm::engine::Engine) while the host holds
+/// on to it, made by [`Engine::pin`](crate::steel_vm::engine::Engine::pin).
+///
+/// The engine counts pinned values as its own, so they aren't reported by
+/// [`Engine::leaks`](crate::steel_vm::engine::Engine::leaks) the way a value the host cloned
+/// would be. Dropping the guard unpins the value, and once the engine is gone the value is
+/// too.
+#[derive(Debug)]
+pub struct PinGuard {
+    id: usize,
+    table: Weak<RefCell<Pins>>,
+}
+
+impl PinGuard {
+    /// The pinned value, if the engine it was pinned in is still around
+    pub fn get(&self) -> Option<SteelVal> {
+        let table = self.table.upgrade()?;
+        let value = table.borrow().values.get(&self.id).cloned();
+        value
+    }
+
+    /// Unpins the value and hands it back, to be passed into the engine again
+    pub fn take(self) -> Option<SteelVal> {
+        let table = self.table.upgrade()?;
+        let value = table.borrow_mut().values.remove(&self.id);
+        value
+    }
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        if let Some(table) = self.table.upgrade() {
+            table.borrow_mut().values.remove(&self.id);
+        }
+    }
+}
@@ -1,21 +1,29 @@
 use super::engine::Engine;
+use super::snapshots::check_snapshot;
+use crate::compiler::passes::matches::MATCH_PRIMITIVES;
+use crate::docs::{print_doc, DocEntry, Effect};
+use crate::parser::lambda_signature::LAMBDA_SIGNATURE;
+use crate::parser::serializable_lambda::SERIALIZABLE_CLOSURE;
 use crate::primitives::{
-    ContractOperations, ControlOperations, FsFunctions, HashMapOperations, HashSetOperations,
-    IoFunctions, ListOperations, MetaOperations, NumOperations, PortOperations, StreamOperations,
-    StringOperations, SymbolOperations, TransducerOperations, VectorOperations,
+    ArrayOperations, CharOperations, ContractOperations, ControlOperations, FfiOperations,
+    FsFunctions, HashMapOperations, HashSetOperations, IoFunctions, ListOperations, MathOperations,
+    MemoizeOperations, MemoizedFunctions, MetaOperations, NumOperations, PortOperations,
+    SemverOperations, StreamOperations, StringOperations, SymbolOperations, SyncOperations,
+    ThreadOperations, ThreadScopes, TransducerOperations, UuidOperations, VectorOperations,
+    WeakOperations,
 };
 use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{Result, SteelVal};
+use crate::values::structs;
 
 #[macro_use]
 macro_rules! ensure_tonicity {
     ($check_fn:expr) => {{
         |args: &[SteelVal]| -> Result<SteelVal> {
             let mut args_iter = args.iter();
-            let first = args_iter.next().ok_or(SteelErr::new(
-                ErrorKind::ArityMismatch,
-                "expected at least one argument".to_string(),
-            ))?;
+            let first = args_iter
+                .next()
+                .ok_or_else(throw!(ArityMismatch => "expected at least one argument"))?;
             fn f<'a>(prev: &SteelVal, mut xs: impl Iterator<Item = &'a SteelVal>) -> bool {
                 match xs.next() {
                     Some(x) => $check_fn(prev, x) && f(x, xs),
@@ -158,6 +166,11 @@ pub const CONSTANTS: &[&str] = &[
     "*",
     "/",
     "-",
+    "modulo",
+    "remainder",
+    "abs",
+    "min",
+    "max",
     CAR,
     CDR,
     FIRST,
@@ -225,6 +238,8 @@ pub(crate) fn register_list_functions(engine: &mut Engine) {
 pub(crate) fn register_vector_functions(engine: &mut Engine) {
     engine
         .register_value("vector", VectorOperations::vec_construct())
+        .register_value("vector-ref", VectorOperations::vec_ref())
+        .register_value("vector-length", VectorOperations::vec_length())
         .register_value("push-front", VectorOperations::vec_cons())
         .register_value("pop-front", VectorOperations::vec_car())
         .register_value("vec-rest", VectorOperations::vec_cdr())
@@ -250,7 +265,13 @@ pub(crate) fn register_string_functions(engine: &mut Engine) {
         .register_value("int->string", StringOperations::int_to_string())
         .register_value("string->symbol", StringOperations::string_to_symbol())
         .register_value("starts-with?", StringOperations::starts_with())
-        .register_value("ends-with?", StringOperations::ends_with());
+        .register_value("ends-with?", StringOperations::ends_with())
+        .register_value("string-contains?", StringOperations::string_contains())
+        .register_value("string-index-of", StringOperations::string_index_of())
+        .register_value("string-split", StringOperations::string_split())
+        .register_value("string-replace-all", StringOperations::string_replace_all())
+        .register_value("regex", StringOperations::regex())
+        .register_value("regex?", StringOperations::is_regex());
 }
 
 #[inline(always)]
@@ -290,6 +311,7 @@ pub(crate) fn register_identity_predicates(engine: &mut Engine) {
         .register_value("number?", gen_pred!(NumV, IntV))
         .register_value("string?", gen_pred!(StringV))
         .register_value("symbol?", gen_pred!(SymbolV))
+        .register_value("keyword?", gen_pred!(KeywordV))
         .register_value("vector?", gen_pred!(VectorV))
         .register_value("list?", gen_pred!(Pair))
         .register_value("pair?", gen_pred!(Pair))
@@ -306,6 +328,7 @@ pub(crate) fn register_identity_predicates(engine: &mut Engine) {
                 FuncV,
                 ContractedFunction,
                 BoxedFunction,
+                BuiltIn,
                 ContinuationFunction
             ),
         )
@@ -316,6 +339,7 @@ pub(crate) fn register_identity_predicates(engine: &mut Engine) {
                 FuncV,
                 ContractedFunction,
                 BoxedFunction,
+                BuiltIn,
                 ContinuationFunction
             ),
         )
@@ -357,7 +381,141 @@ pub(crate) fn register_number_functions(engine: &mut Engine) {
         .register_value("-", NumOperations::subtract())
         .register_value("even?", NumOperations::even())
         .register_value("odd?", NumOperations::odd())
-        .register_value("arithmetic-shift", NumOperations::arithmetic_shift());
+        .register_value("arithmetic-shift", NumOperations::arithmetic_shift())
+        .register_value("modulo", NumOperations::modulo())
+        .register_value("remainder", NumOperations::remainder())
+        .register_value("abs", NumOperations::abs())
+        .register_value("exact->inexact", NumOperations::exact_to_inexact())
+        .register_value("inexact->exact", NumOperations::inexact_to_exact())
+        .register_value("min", NumOperations::min())
+        .register_value("max", NumOperations::max());
+}
+
+#[inline(always)]
+pub(crate) fn register_array_functions(engine: &mut Engine) {
+    engine
+        .register_value("array", ArrayOperations::array())
+        .register_value("list->array", ArrayOperations::list_to_array())
+        .register_value("make-array", ArrayOperations::make_array())
+        .register_value("array->list", ArrayOperations::array_to_list())
+        .register_value("array-shape", ArrayOperations::array_shape())
+        .register_value("array-ref", ArrayOperations::array_ref())
+        .register_value("array-slice", ArrayOperations::array_slice())
+        .register_value("array-transpose", ArrayOperations::array_transpose())
+        .register_value("array-sum", ArrayOperations::array_sum())
+        .register_value("array+", ArrayOperations::add())
+        .register_value("array-", ArrayOperations::subtract())
+        .register_value("array*", ArrayOperations::multiply())
+        .register_value("array/", ArrayOperations::divide())
+        .register_value("array-map", ArrayOperations::array_map())
+        .register_value("matmul", ArrayOperations::matmul());
+}
+
+#[inline(always)]
+pub(crate) fn register_ffi_functions(engine: &mut Engine) {
+    engine
+        .register_value("make-ffi-buffer", FfiOperations::make_ffi_buffer())
+        .register_value("ffi-buffer?", FfiOperations::is_ffi_buffer())
+        .register_value("ffi-buffer-length", FfiOperations::ffi_buffer_length())
+        .register_value(
+            "ffi-buffer-writable?",
+            FfiOperations::is_ffi_buffer_writable(),
+        )
+        .register_value("ffi-buffer-ref", FfiOperations::ffi_buffer_ref())
+        .register_value("ffi-buffer-set!", FfiOperations::ffi_buffer_set())
+        .register_value("ffi-buffer-slice", FfiOperations::ffi_buffer_slice())
+        .register_value("ffi-buffer-copy!", FfiOperations::ffi_buffer_copy())
+        .register_value("ffi-buffer->list", FfiOperations::ffi_buffer_to_list())
+        .register_value("list->ffi-buffer", FfiOperations::list_to_ffi_buffer());
+}
+
+#[inline(always)]
+pub(crate) fn register_struct_functions(engine: &mut Engine) {
+    engine
+        .register_value("%struct-modifier", structs::struct_modifier())
+        .register_value("%struct-ref", structs::struct_ref());
+}
+
+#[inline(always)]
+pub(crate) fn register_weak_functions(engine: &mut Engine) {
+    engine
+        .register_value("weak-box", WeakOperations::weak_box())
+        .register_value("weak-box-value", WeakOperations::weak_box_value())
+        .register_value("weak-box?", WeakOperations::is_weak_box())
+        .register_value("make-weak-cache", WeakOperations::make_weak_cache())
+        .register_value("weak-cache-ref", WeakOperations::weak_cache_ref())
+        .register_value("weak-cache-set!", WeakOperations::weak_cache_set())
+        .register_value("weak-cache-count", WeakOperations::weak_cache_count());
+}
+
+#[inline(always)]
+pub(crate) fn register_memoize_functions(engine: &mut Engine) {
+    let memoized = MemoizedFunctions::default();
+    engine
+        .register_value("memoize", MemoizeOperations::memoize(memoized.clone()))
+        .register_value("memoize-stats", MemoizeOperations::memoize_stats(memoized));
+}
+
+#[inline(always)]
+pub(crate) fn register_math_functions(engine: &mut Engine) {
+    register_module(
+        engine,
+        "steel/math",
+        vec![
+            ("sin", MathOperations::sin(), Effect::Pure, "`(sin x)` is the sine of `x` radians"),
+            ("cos", MathOperations::cos(), Effect::Pure, "`(cos x)` is the cosine of `x` radians"),
+            ("tan", MathOperations::tan(), Effect::Pure, "`(tan x)` is the tangent of `x` radians"),
+            ("asin", MathOperations::asin(), Effect::Pure, "`(asin x)` is the arcsine of `x`, in radians"),
+            ("acos", MathOperations::acos(), Effect::Pure, "`(acos x)` is the arccosine of `x`, in radians"),
+            (
+                "atan",
+                MathOperations::atan(), Effect::Pure,
+                "`(atan x)` is the arctangent of `x`, and `(atan y x)` the angle of the point \
+                 `(x, y)`, in radians",
+            ),
+            ("exp", MathOperations::exp(), Effect::Pure, "`(exp x)` is e raised to `x`"),
+            (
+                "log",
+                MathOperations::log(), Effect::Pure,
+                "`(log x)` is the natural logarithm of `x`, and `(log x b)` its logarithm in base `b`",
+            ),
+            (
+                "sqrt",
+                MathOperations::sqrt(), Effect::Pure,
+                "`(sqrt x)` is the square root of `x`, exact for perfect squares",
+            ),
+            (
+                "exact-integer-sqrt",
+                MathOperations::exact_integer_sqrt(), Effect::Pure,
+                "`(exact-integer-sqrt n)` is the list `(s r)` where `s * s + r = n`",
+            ),
+            (
+                "expt",
+                MathOperations::expt(), Effect::Pure,
+                "`(expt base power)` raises `base` to `power`, staying exact for integers unless \
+                 the result overflows",
+            ),
+            ("floor", MathOperations::floor(), Effect::Pure, "`(floor x)` rounds `x` toward negative infinity"),
+            ("ceiling", MathOperations::ceiling(), Effect::Pure, "`(ceiling x)` rounds `x` toward positive infinity"),
+            ("truncate", MathOperations::truncate(), Effect::Pure, "`(truncate x)` rounds `x` toward zero"),
+            (
+                "round",
+                MathOperations::round(), Effect::Pure,
+                "`(round x)` rounds halfway cases to even, and `(round x mode)` rounds with one of \
+                 the IEEE 754 rounding modes",
+            ),
+            (
+                "floor/",
+                MathOperations::floor_division(), Effect::Pure,
+                "`(floor/ n d)` is the list `(q r)`, with the quotient rounded toward negative infinity",
+            ),
+            (
+                "truncate/",
+                MathOperations::truncate_division(), Effect::Pure,
+                "`(truncate/ n d)` is the list `(q r)`, with the quotient rounded toward zero",
+            ),
+        ],
+    );
 }
 
 #[inline(always)]
@@ -390,13 +548,159 @@ pub(crate) fn register_transducer_functions(engine: &mut Engine) {
 pub(crate) fn register_symbol_functions(engine: &mut Engine) {
     engine
         .register_value("concat-symbols", SymbolOperations::concat_symbols())
-        .register_value("symbol->string", SymbolOperations::symbol_to_string());
+        .register_value("symbol->string", SymbolOperations::symbol_to_string())
+        .register_value("keyword->string", SymbolOperations::keyword_to_string())
+        .register_value("string->keyword", SymbolOperations::string_to_keyword());
+}
+
+#[inline(always)]
+pub(crate) fn register_print_functions(engine: &mut Engine) {
+    let limits = engine.print_limits_handle();
+    engine
+        .register_value("print-depth", IoFunctions::print_depth(limits.clone()))
+        .register_value("print-length", IoFunctions::print_length(limits));
+}
+
+#[inline(always)]
+pub(crate) fn register_await(engine: &mut Engine) {
+    let pending = engine.pending_await_handle();
+    engine.register_value("await", MetaOperations::await_future(pending));
+}
+
+#[inline(always)]
+pub(crate) fn register_control_functions(engine: &mut Engine) {
+    engine
+        .register_value("error!", ControlOperations::error())
+        .register_value("%push-winder!", ControlOperations::push_winder())
+        .register_value("%pop-winder!", ControlOperations::pop_winder())
+        .register_value("values", ControlOperations::values())
+        .register_value("call-with-values", ControlOperations::call_with_values())
+        .register_value("#%values->vector", ControlOperations::values_to_vector())
+        .register_value("#%values-ref", ControlOperations::values_ref())
+        .register_value("%trace", ControlOperations::trace());
+}
+
+// Registered last, since they're the builtins registered above under a second name
+#[inline(always)]
+pub(crate) fn register_match_primitives(engine: &mut Engine) {
+    for name in MATCH_PRIMITIVES {
+        let value = engine
+            .extract_value(name)
+            .expect("match primitives are all builtins");
+        engine.register_value(&format!("#%{}", name), value);
+    }
+}
+
+#[inline(always)]
+pub(crate) fn register_thread_functions(engine: &mut Engine) {
+    let scopes = ThreadScopes::default();
+    engine
+        .register_value("spawn", ThreadOperations::spawn(scopes.clone()))
+        .register_value(
+            "call-with-threads",
+            ThreadOperations::call_with_threads(scopes),
+        )
+        .register_value("thread-join", ThreadOperations::thread_join())
+        .register_value("thread-cancel", ThreadOperations::thread_cancel());
+}
+
+#[inline(always)]
+pub(crate) fn register_sync_functions(engine: &mut Engine) {
+    register_module(
+        engine,
+        "steel/sync",
+        vec![
+            (
+                "atom",
+                SyncOperations::atom(),
+                Effect::Effectful,
+                "`(atom value)` makes a box that threads can share, holding data",
+            ),
+            (
+                "atomic-box",
+                SyncOperations::atom(),
+                Effect::Effectful,
+                "`(atomic-box value)` is another name for `atom`",
+            ),
+            (
+                "atom-ref",
+                SyncOperations::atom_ref(),
+                Effect::Effectful,
+                "`(atom-ref atom)` is the value the atom holds",
+            ),
+            (
+                "swap!",
+                SyncOperations::swap(),
+                Effect::Effectful,
+                "`(swap! atom f args ...)` stores `(f value args ...)` in the atom, calling `f` \
+                 again if another thread changed the atom in the meantime",
+            ),
+            (
+                "compare-and-set!",
+                SyncOperations::compare_and_set(),
+                Effect::Effectful,
+                "`(compare-and-set! atom old new)` stores `new` if the atom holds something \
+                 `equal?` to `old`, and returns whether it did",
+            ),
+            (
+                "compare-and-swap!",
+                SyncOperations::compare_and_swap(),
+                Effect::Effectful,
+                "`(compare-and-swap! atom old new)` stores `new` if the atom holds something \
+                 `equal?` to `old`, and returns what the atom held before",
+            ),
+            (
+                "mutex",
+                SyncOperations::mutex(),
+                Effect::Effectful,
+                "`(mutex value)` makes a lock around data, which one thread at a time can \
+                 change",
+            ),
+            (
+                "with-lock",
+                SyncOperations::with_lock(),
+                Effect::Effectful,
+                "`(with-lock mutex f)` calls `f` with the value while holding the lock, and \
+                 stores what it returns",
+            ),
+            (
+                "lock!",
+                SyncOperations::with_lock(),
+                Effect::Effectful,
+                "`(lock! mutex f)` is another name for `with-lock`",
+            ),
+            (
+                "make-channel",
+                SyncOperations::make_channel(),
+                Effect::Effectful,
+                "`(make-channel)` makes a queue that threads send copies of data through",
+            ),
+            (
+                "channel-send!",
+                SyncOperations::channel_send(),
+                Effect::Effectful,
+                "`(channel-send! channel value)` sends a copy of `value`",
+            ),
+            (
+                "channel-recv!",
+                SyncOperations::channel_recv(),
+                Effect::Effectful,
+                "`(channel-recv! channel)` waits for the next value sent on the channel",
+            ),
+        ],
+    );
 }
 
 #[inline(always)]
 pub(crate) fn register_io_functions(engine: &mut Engine) {
+    let limits = engine.print_limits_handle();
+    let floats = engine.float_format_handle();
     engine
-        .register_value("display", IoFunctions::display())
+        .register_value(
+            "display",
+            IoFunctions::display(limits.clone(), floats.clone()),
+        )
+        .register_value("write", IoFunctions::write(limits, floats))
         .register_value("display-color", IoFunctions::display_color())
         .register_value("newline", IoFunctions::newline())
         .register_value("read-to-string", IoFunctions::read_to_string());
@@ -423,7 +727,9 @@ pub(crate) fn register_port_functions(engine: &mut Engine) {
     engine
         .register_value("open-input-file", PortOperations::open_input_file())
         .register_value("read-port-to-string", PortOperations::read_port_to_string())
-        .register_value("read-line-from-port", PortOperations::read_line_to_string());
+        .register_value("read-line-from-port", PortOperations::read_line_to_string())
+        .register_value("lines", PortOperations::lines())
+        .register_value("for-each-line", PortOperations::for_each_line());
 }
 
 #[inline(always)]
@@ -438,7 +744,11 @@ pub(crate) fn register_meta_functions(engine: &mut Engine) {
         .register_value("memory-address", MetaOperations::memory_address())
         .register_value("async-exec", MetaOperations::exec_async())
         .register_value("poll!", MetaOperations::poll_value())
-        .register_value("join!", MetaOperations::join_futures());
+        .register_value("join!", MetaOperations::join_futures())
+        .register_value(SERIALIZABLE_CLOSURE, MetaOperations::serializable_closure())
+        .register_value(LAMBDA_SIGNATURE, MetaOperations::lambda_signature())
+        .register_value("serialize-closure", MetaOperations::serialize_closure())
+        .register_value("%check-snapshot", check_snapshot(None));
 }
 
 #[inline(always)]
@@ -454,6 +764,160 @@ pub(crate) fn register_json_functions(engine: &mut Engine) {
         );
 }
 
+#[inline(always)]
+pub(crate) fn register_char_functions(engine: &mut Engine) {
+    engine
+        .register_value("char=?", CharOperations::char_equals())
+        .register_value("char<?", CharOperations::char_less_than())
+        .register_value("char>?", CharOperations::char_greater_than())
+        .register_value("char<=?", CharOperations::char_less_than_equals())
+        .register_value("char>=?", CharOperations::char_greater_than_equals())
+        .register_value("char-ci=?", CharOperations::char_ci_equals())
+        .register_value("char-ci<?", CharOperations::char_ci_less_than())
+        .register_value("char-ci>?", CharOperations::char_ci_greater_than())
+        .register_value("char-ci<=?", CharOperations::char_ci_less_than_equals())
+        .register_value("char-ci>=?", CharOperations::char_ci_greater_than_equals())
+        .register_value("char-alphabetic?", CharOperations::is_alphabetic())
+        .register_value("char-numeric?", CharOperations::is_numeric())
+        .register_value("char-whitespace?", CharOperations::is_whitespace())
+        .register_value("char-upper-case?", CharOperations::is_upper_case())
+        .register_value("char-lower-case?", CharOperations::is_lower_case())
+        .register_value("char-upcase", CharOperations::char_upcase())
+        .register_value("char-downcase", CharOperations::char_downcase())
+        .register_value("char-foldcase", CharOperations::char_downcase())
+        .register_value("digit-value", CharOperations::digit_value())
+        .register_value("char->integer", CharOperations::char_to_integer())
+        .register_value("integer->char", CharOperations::integer_to_char());
+}
+
+/// The modules built into every engine. Their functions are always defined, so requiring one
+/// of them, like `(require "steel/uuid")`, only documents that the functions are used.
+pub(crate) const BUILTIN_MODULES: &[&str] =
+    &["steel/uuid", "steel/semver", "steel/sync", "steel/math"];
+
+// Registers the functions of a builtin module along with their documentation, and whether
+// calling them does anything besides giving back a value
+fn register_module<'a>(
+    engine: &mut Engine,
+    module: &str,
+    functions: impl IntoIterator<Item = (&'a str, SteelVal, Effect, &'a str)>,
+) {
+    for (name, value, effect, doc) in functions {
+        engine.register_value(name, value);
+        engine.docs_handle().borrow_mut().insert(DocEntry {
+            effect,
+            ..DocEntry::new(name, module, doc)
+        });
+    }
+}
+
+#[inline(always)]
+pub(crate) fn register_uuid_functions(engine: &mut Engine) {
+    register_module(
+        engine,
+        "steel/uuid",
+        vec![
+            (
+                "uuid/v4",
+                UuidOperations::v4(),
+                Effect::Effectful,
+                "`(uuid/v4)` makes a new random uuid, as a string like \
+                 `\"67e55044-10b1-426f-9247-bb680e5fe0c8\"`",
+            ),
+            (
+                "uuid/nil",
+                UuidOperations::nil(),
+                Effect::Pure,
+                "`(uuid/nil)` is the uuid with every bit unset",
+            ),
+            (
+                "uuid/parse",
+                UuidOperations::parse(),
+                Effect::Pure,
+                "`(uuid/parse s)` reads a uuid with or without hyphens, in braces or after \
+                 `urn:uuid:`, giving it back in the lower case, hyphenated form",
+            ),
+            (
+                "uuid/valid?",
+                UuidOperations::is_valid(),
+                Effect::Pure,
+                "`(uuid/valid? s)` checks whether `uuid/parse` would accept `s`",
+            ),
+            (
+                "uuid/version",
+                UuidOperations::version(),
+                Effect::Pure,
+                "`(uuid/version s)` is the version of the uuid, 4 for random ones\n\n\
+                 ```scheme\n\
+                 (assert! (= 4 (uuid/version (uuid/v4))))\n\
+                 ```",
+            ),
+        ],
+    );
+}
+
+#[inline(always)]
+pub(crate) fn register_semver_functions(engine: &mut Engine) {
+    register_module(
+        engine,
+        "steel/semver",
+        vec![
+            (
+                "semver/parse",
+                SemverOperations::parse(),
+                Effect::Pure,
+                "`(semver/parse s)` splits a version into a list of its major, minor and patch \
+                 numbers, prerelease and build metadata, with `#f` for the parts that aren't there",
+            ),
+            (
+                "semver/valid?",
+                SemverOperations::is_valid(),
+                Effect::Pure,
+                "`(semver/valid? s)` checks whether `s` is a semantic version",
+            ),
+            (
+                "semver/compare",
+                SemverOperations::compare(),
+                Effect::Pure,
+                "`(semver/compare a b)` is -1, 0 or 1 as `a` comes before, alongside or after `b`\n\n\
+                 ```scheme\n\
+                 (assert! (= -1 (semver/compare \"1.2.0\" \"1.10.0\")))\n\
+                 (assert! (= 1 (semver/compare \"1.0.0\" \"1.0.0-alpha\")))\n\
+                 ```",
+            ),
+            (
+                "semver<?",
+                SemverOperations::less_than(),
+                Effect::Pure,
+                "`(semver<? a b)` checks whether `a` comes before `b`",
+            ),
+            (
+                "semver=?",
+                SemverOperations::equals(),
+                Effect::Pure,
+                "`(semver=? a b)` checks whether `a` and `b` are the same version, ignoring \
+                 build metadata",
+            ),
+            (
+                "semver/bump",
+                SemverOperations::bump(),
+                Effect::Pure,
+                "`(semver/bump version part)` goes to the next major, minor or patch release, \
+                 given by the symbol `part`\n\n\
+                 ```scheme\n\
+                 (assert! (equal? \"1.3.0\" (semver/bump \"1.2.7\" 'minor)))\n\
+                 ```",
+            ),
+        ],
+    );
+}
+
+#[inline(always)]
+pub(crate) fn register_doc_functions(engine: &mut Engine) {
+    let docs = engine.docs_handle();
+    engine.register_value("%doc", print_doc(docs));
+}
+
 #[inline(always)]
 pub(crate) fn embed_primitives(engine: &mut Engine) {
     register_constants(engine);
@@ -462,9 +926,17 @@ pub(crate) fn embed_primitives(engine: &mut Engine) {
     register_ord_functions(engine);
 
     register_number_functions(engine);
+    engine.mark_specialized_builtins();
+    register_math_functions(engine);
+    register_array_functions(engine);
+    register_weak_functions(engine);
+    register_ffi_functions(engine);
+    register_memoize_functions(engine);
+    register_struct_functions(engine);
     register_list_functions(engine);
     register_vector_functions(engine);
     register_string_functions(engine);
+    register_char_functions(engine);
     register_hashmap_functions(engine);
     register_hashset_functions(engine);
     register_identity_predicates(engine);
@@ -472,15 +944,24 @@ pub(crate) fn embed_primitives(engine: &mut Engine) {
     register_contract_functions(engine);
     register_transducer_functions(engine);
     register_symbol_functions(engine);
+    register_print_functions(engine);
+    register_sync_functions(engine);
 
     register_io_functions(engine);
     register_fs_functions(engine);
     register_port_functions(engine);
+    register_thread_functions(engine);
 
     register_meta_functions(engine);
     register_json_functions(engine);
-
-    engine.register_value("error!", ControlOperations::error());
+    register_uuid_functions(engine);
+    register_semver_functions(engine);
+    register_doc_functions(engine);
+
+    register_control_functions(engine);
+    register_await(engine);
+    register_match_primitives(engine);
+    engine.register_features();
 }
 
 #[inline(always)]
@@ -491,9 +972,17 @@ pub(crate) fn embed_primitives_without_io(engine: &mut Engine) {
     register_ord_functions(engine);
 
     register_number_functions(engine);
+    engine.mark_specialized_builtins();
+    register_math_functions(engine);
+    register_array_functions(engine);
+    register_weak_functions(engine);
+    register_ffi_functions(engine);
+    register_memoize_functions(engine);
+    register_struct_functions(engine);
     register_list_functions(engine);
     register_vector_functions(engine);
     register_string_functions(engine);
+    register_char_functions(engine);
     register_hashmap_functions(engine);
     register_hashset_functions(engine);
     register_identity_predicates(engine);
@@ -501,9 +990,17 @@ pub(crate) fn embed_primitives_without_io(engine: &mut Engine) {
     register_contract_functions(engine);
     register_transducer_functions(engine);
     register_symbol_functions(engine);
+    register_print_functions(engine);
+    register_sync_functions(engine);
 
     register_meta_functions(engine);
     register_json_functions(engine);
-
-    engine.register_value("error!", ControlOperations::error());
+    register_uuid_functions(engine);
+    register_semver_functions(engine);
+    register_doc_functions(engine);
+
+    register_control_functions(engine);
+    register_await(engine);
+    register_match_primitives(engine);
+    engine.register_features();
 }
@@ -0,0 +1,330 @@
+use crate::core::instructions::DenseInstruction;
+use crate::gc::Gc;
+use crate::rvals::ByteCodeLambda;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+pub(crate) const TOP_LEVEL: &str = "<top-level>";
+
+/// Instruction count and wall time for one function
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionProfile {
+    /// The global the function is bound to, or `lambda@start..end` for anonymous functions
+    pub name: String,
+    /// Source span covering the body of the function, `None` for top level expressions
+    pub span: Option<(usize, usize)>,
+    /// Instructions run in the function itself, not counting the functions it called
+    pub instructions: usize,
+    /// Time spent in the function itself, not counting the functions it called
+    pub time: Duration,
+}
+
+/// What [`Engine::profile`](crate::steel_vm::engine::Engine::profile) found
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub instructions: usize,
+    pub wall_time: Duration,
+    /// The hottest functions come first
+    pub functions: Vec<FunctionProfile>,
+    // Each call stack seen, outermost function first, with the instructions run at its top
+    stacks: Vec<(Vec<String>, usize)>,
+}
+
+impl ProfileReport {
+    /// The function bound to the global `name`, or the top level code if `name` is `<top-level>`
+    pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|x| x.name == name)
+    }
+
+    /// The call stacks in the folded format read by flamegraph tools, one stack per line
+    /// with its frames separated by `;` and weighted by the instructions run at its top
+    pub fn to_folded_stacks(&self) -> String {
+        let mut folded = String::new();
+        for (frames, instructions) in &self.stacks {
+            writeln!(folded, "{} {}", frames.join(";"), instructions).unwrap();
+        }
+        folded
+    }
+}
+
+/// What the engine was doing when a callback set with
+/// [`Engine::on_progress_every`](crate::steel_vm::engine::Engine::on_progress_every) was called
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VmSnapshot {
+    /// The instructions executed so far
+    pub instruction_count: usize,
+    /// The number of function calls evaluation is inside of, 0 for top level code
+    pub depth: usize,
+    /// Source span covering the body of the function running, `None` for top level code
+    pub function: Option<(usize, usize)>,
+}
+
+// The source span a function body covers, closures made from the same lambda share it
+pub(crate) fn body_span(body: &[DenseInstruction]) -> (usize, usize) {
+    body.iter()
+        .map(|x| x.span)
+        .filter(|x| x.end() > 0)
+        .fold((usize::MAX, 0), |(start, end), x| {
+            (start.min(x.start()), end.max(x.end()))
+        })
+}
+
+#[derive(Default)]
+struct Counts {
+    instructions: usize,
+    time: Duration,
+}
+
+/// Counts every instruction against the function and the call stack it ran in. The clock is
+/// only read when the call stack changes, and the time since is charged to the stack that was
+/// running before the change.
+pub(crate) struct Profiler {
+    started: Instant,
+    last_switch: Instant,
+    // Function bodies by address, each held on to so that the address can't be reused
+    bodies: HashMap<usize, (Rc<[DenseInstruction]>, usize)>,
+    // Evaluating a lambda again copies its body, so functions are told apart by span.
+    // The top level is always the first function.
+    function_ids: HashMap<Option<(usize, usize)>, usize>,
+    functions: Vec<(Option<(usize, usize)>, Counts)>,
+    stack_ids: HashMap<Vec<usize>, usize>,
+    stacks: Vec<(Vec<usize>, Counts)>,
+    current_function: usize,
+    current_stack: usize,
+    // The depth and body that were running at the last instruction
+    last_frame: Option<(usize, usize)>,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        let mut profiler = Profiler {
+            started: now,
+            last_switch: now,
+            bodies: HashMap::new(),
+            function_ids: HashMap::new(),
+            functions: Vec::new(),
+            stack_ids: HashMap::new(),
+            stacks: Vec::new(),
+            current_function: 0,
+            current_stack: 0,
+            last_frame: None,
+        };
+        profiler.current_function = profiler.function_id(None);
+        profiler.current_stack = profiler.stack_id(vec![0]);
+        profiler
+    }
+
+    fn function_id(&mut self, span: Option<(usize, usize)>) -> usize {
+        if let Some(id) = self.function_ids.get(&span) {
+            return *id;
+        }
+
+        self.functions.push((span, Counts::default()));
+        self.function_ids.insert(span, self.functions.len() - 1);
+        self.functions.len() - 1
+    }
+
+    fn body_id(&mut self, body: &Rc<[DenseInstruction]>) -> usize {
+        let key = body.as_ptr() as usize;
+        if let Some((_, id)) = self.bodies.get(&key) {
+            return *id;
+        }
+
+        let id = self.function_id(Some(body_span(body)));
+        self.bodies.insert(key, (Rc::clone(body), id));
+        id
+    }
+
+    fn stack_id(&mut self, stack: Vec<usize>) -> usize {
+        if let Some(id) = self.stack_ids.get(&stack) {
+            return *id;
+        }
+
+        self.stacks.push((stack.clone(), Counts::default()));
+        self.stack_ids.insert(stack, self.stacks.len() - 1);
+        self.stacks.len() - 1
+    }
+
+    // Charges the time since the last change of stack to what was running
+    fn charge(&mut self, now: Instant) {
+        let elapsed = now - self.last_switch;
+        self.functions[self.current_function].1.time += elapsed;
+        self.stacks[self.current_stack].1.time += elapsed;
+        self.last_switch = now;
+    }
+
+    /// Called before each instruction runs, with the body it belongs to and the functions
+    /// being called, innermost last
+    pub(crate) fn record_instruction(
+        &mut self,
+        body: &Rc<[DenseInstruction]>,
+        in_function: bool,
+        function_stack: &[Gc<ByteCodeLambda>],
+    ) {
+        let frame = (function_stack.len(), body.as_ptr() as usize);
+
+        if self.last_frame != Some(frame) {
+            self.last_frame = Some(frame);
+            self.charge(Instant::now());
+
+            let mut stack = Vec::with_capacity(function_stack.len() + 1);
+            stack.push(0);
+            for function in function_stack {
+                let id = self.body_id(&function.body_exp());
+                stack.push(id);
+            }
+
+            self.current_function = if in_function { self.body_id(body) } else { 0 };
+            self.current_stack = self.stack_id(stack);
+        }
+
+        self.functions[self.current_function].1.instructions += 1;
+        self.stacks[self.current_stack].1.instructions += 1;
+    }
+
+    /// Stops the clock and puts together the report, `names` gives the name of the global
+    /// each named function was bound to by the span of its body
+    pub(crate) fn finish(mut self, names: &HashMap<(usize, usize), String>) -> ProfileReport {
+        let now = Instant::now();
+        self.charge(now);
+
+        let function_names: Vec<String> = self
+            .functions
+            .iter()
+            .map(|(span, _)| match span {
+                None => TOP_LEVEL.to_string(),
+                Some(span) => names
+                    .get(span)
+                    .cloned()
+                    .unwrap_or_else(|| format!("lambda@{}..{}", span.0, span.1)),
+            })
+            .collect();
+
+        let mut functions: Vec<FunctionProfile> = self
+            .functions
+            .iter()
+            .zip(&function_names)
+            .filter(|((_, counts), _)| counts.instructions > 0)
+            .map(|((span, counts), name)| FunctionProfile {
+                name: name.clone(),
+                span: *span,
+                instructions: counts.instructions,
+                time: counts.time,
+            })
+            .collect();
+        functions.sort_by_key(|x| std::cmp::Reverse(x.instructions));
+
+        let stacks = self
+            .stacks
+            .iter()
+            .filter(|(_, counts)| counts.instructions > 0)
+            .map(|(stack, counts)| {
+                let frames = stack.iter().map(|x| function_names[*x].clone()).collect();
+                (frames, counts.instructions)
+            })
+            .collect();
+
+        ProfileReport {
+            instructions: functions.iter().map(|x| x.instructions).sum(),
+            wall_time: now - self.started,
+            functions,
+            stacks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod profiler_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    const FIB: &str = r#"
+        (define (fib n)
+            (if (< n 2)
+                n
+                (+ (fib (- n 1)) (fib (- n 2)))))
+        (define (run) (+ 0 (fib 10)))
+        (run)
+    "#;
+
+    #[test]
+    fn instructions_are_counted_per_function() {
+        let mut vm = Engine::new();
+        let report = vm.profile(FIB).unwrap();
+
+        let fib = report.function("fib").unwrap();
+        let run = report.function("run").unwrap();
+        assert!(fib.instructions > 100 * run.instructions);
+        assert_eq!(report.functions[0].name, "fib");
+        assert!(fib.span.is_some());
+        assert!(report.function(TOP_LEVEL).unwrap().span.is_none());
+
+        let total: usize = report.functions.iter().map(|x| x.instructions).sum();
+        assert_eq!(report.instructions, total);
+        assert!(report.wall_time >= fib.time);
+    }
+
+    #[test]
+    fn folded_stacks_weigh_each_stack_by_its_instructions() {
+        let mut vm = Engine::new();
+        let report = vm.profile(FIB).unwrap();
+        let folded = report.to_folded_stacks();
+
+        let mut total = 0;
+        for line in folded.lines() {
+            let (frames, weight) = line.rsplit_once(' ').unwrap();
+            assert!(frames.starts_with(TOP_LEVEL));
+            total += weight.parse::<usize>().unwrap();
+        }
+        assert_eq!(total, report.instructions);
+
+        // fib is recursive, so the deepest stack has all of its calls on it
+        let deepest = format!("{};run{}", TOP_LEVEL, ";fib".repeat(10));
+        assert!(folded
+            .lines()
+            .any(|x| x.starts_with(&format!("{} ", deepest))));
+    }
+
+    #[test]
+    fn closures_from_the_same_lambda_are_one_function() {
+        let mut vm = Engine::new();
+        let report = vm
+            .profile(
+                r#"
+                (define (add-all xs n)
+                    (define (add x) (+ x n))
+                    (map add xs))
+                (add-all (list 1 2 3) 1)
+                (add-all (list 1 2 3) 2)
+                "#,
+            )
+            .unwrap();
+
+        let lambdas: Vec<_> = report
+            .functions
+            .iter()
+            .filter(|x| x.name.starts_with("lambda@"))
+            .collect();
+        assert_eq!(lambdas.len(), 1);
+        assert!(report.function("add-all").is_some());
+    }
+
+    #[test]
+    fn profiling_only_covers_the_profiled_program() {
+        let mut vm = Engine::new();
+        vm.run("(define (square x) (* x x)) (square 3)").unwrap();
+
+        let report = vm.profile("(+ 1 2)").unwrap();
+        assert!(report.function("square").is_none());
+
+        // Running normally afterwards doesn't need a profiler
+        vm.run("(square 4)").unwrap();
+        let err = vm
+            .profile("(define (fail x) (car x)) (fail 1)")
+            .unwrap_err();
+        assert_eq!(err.kind(), crate::rerrs::ErrorKind::TypeMismatch);
+    }
+}
@@ -1,10 +1,11 @@
 use std::{future::Future, marker::PhantomData, rc::Rc};
 
 use super::engine::Engine;
-use crate::rvals::{FromSteelVal, IntoSteelVal, Result, SteelVal};
+use crate::primitives::StreamOperations;
+use crate::rvals::{CustomType, FromSteelVal, IntoSteelVal, PrintLimits, Result, SteelVal};
 use crate::stop;
 use crate::{
-    rerrs::{ErrorKind, SteelErr},
+    rerrs::{CallError, ErrorKind, SteelErr},
     rvals::FutureResult,
 };
 use futures::FutureExt;
@@ -24,6 +25,124 @@ pub trait RegisterAsyncFn<FN, ARGS, RET> {
 // This allows us to get away with some funny business in the arguments
 pub struct Wrapper<ARGS>(PhantomData<ARGS>);
 
+// Same as above, for functions whose first argument is `&mut` of a custom type
+pub struct MutReceiver<ARGS>(PhantomData<ARGS>);
+
+// Same as above, for functions returning an iterator of results, which become streams
+pub struct IterWrapper<ARGS>(PhantomData<ARGS>);
+
+// How much of an argument goes into an error about it
+const FOUND_LIMIT: usize = 60;
+
+// The name of a type without the paths of the modules it and its parameters come from,
+// `Vec<isize>` rather than `alloc::vec::Vec<isize>`
+fn short_type_name<T>() -> String {
+    let full = std::any::type_name::<T>();
+    let mut out = String::new();
+    let mut path = String::new();
+
+    for c in full.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            path.push(c);
+        } else {
+            out.push_str(path.rsplit("::").next().unwrap_or(""));
+            path.clear();
+            out.push(c);
+        }
+    }
+
+    out.pop();
+    out
+}
+
+fn printed_argument(value: &SteelVal) -> String {
+    let limits = PrintLimits {
+        depth: Some(3),
+        length: Some(8),
+    };
+    let printed = value.limited(limits).to_string();
+
+    if printed.chars().count() > FOUND_LIMIT {
+        printed
+            .chars()
+            .take(FOUND_LIMIT)
+            .chain("...".chars())
+            .collect()
+    } else {
+        printed
+    }
+}
+
+fn check_arity(name: &str, expected: usize, args: &[SteelVal]) -> Result<()> {
+    if args.len() == expected {
+        return Ok(());
+    }
+
+    Err(SteelErr::from_call(
+        ErrorKind::ArityMismatch,
+        CallError::Arity {
+            function: name.to_string(),
+            expected,
+            found: args.len(),
+        },
+    ))
+}
+
+fn argument_error<T>(
+    name: &str,
+    index: usize,
+    value: &SteelVal,
+    kind: ErrorKind,
+    reason: String,
+) -> SteelErr {
+    SteelErr::from_call(
+        kind,
+        CallError::Argument {
+            function: name.to_string(),
+            index,
+            expected: short_type_name::<T>(),
+            found: printed_argument(value),
+            reason,
+        },
+    )
+}
+
+// Converts the argument at `index`, keeping the kind of error the conversion failed with
+fn convert_argument<T: FromSteelVal>(name: &str, args: &[SteelVal], index: usize) -> Result<T> {
+    T::from_steelval(args[index].clone()).map_err(|e| {
+        argument_error::<T>(name, index, &args[index], e.kind(), e.message().to_string())
+    })
+}
+
+// Borrows the custom type behind `value` for the duration of `func`. Nothing stops a function
+// from getting a hold of its own receiver again while it's running, so this has to be checked.
+fn with_custom_mut<SELF: 'static, RET>(
+    name: &str,
+    value: &SteelVal,
+    func: impl FnOnce(&mut SELF) -> RET,
+) -> Result<RET> {
+    if let SteelVal::Custom(cell) = value {
+        let mut custom = match cell.try_borrow_mut() {
+            Ok(custom) => custom,
+            Err(_) => {
+                stop!(Generic => "{} could not borrow its receiver, it is already in use", name)
+            }
+        };
+
+        if let Some(receiver) = custom.as_any_mut().downcast_mut::<SELF>() {
+            return Ok(func(receiver));
+        }
+    }
+
+    Err(argument_error::<SELF>(
+        name,
+        0,
+        value,
+        ErrorKind::TypeMismatch,
+        "the receiver has to be this type".to_string(),
+    ))
+}
+
 impl<
         FUT: Future<Output = RET> + 'static,
         RET: IntoSteelVal + 'static,
@@ -32,9 +151,7 @@ impl<
 {
     fn register_async_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
         let f = move |args: &[SteelVal]| -> Result<FutureResult> {
-            if !args.is_empty() {
-                stop!(ArityMismatch => format!("{} expected 0 arguments, got {}", name, args.len()));
-            }
+            check_arity(name, 0, args)?;
 
             let res = func();
 
@@ -48,9 +165,7 @@ impl<
 impl<RET: IntoSteelVal, FN: Fn() -> RET + 'static> RegisterFn<FN, Wrapper<()>, RET> for Engine {
     fn register_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
         let f = move |args: &[SteelVal]| -> Result<SteelVal> {
-            if !args.is_empty() {
-                stop!(ArityMismatch => format!("{} expected 0 arguments, got {}", name, args.len()));
-            }
+            check_arity(name, 0, args)?;
 
             let res = func();
 
@@ -70,11 +185,36 @@ macro_rules! impl_register_fn {
         > RegisterFn<FN, Wrapper<($($param,)*)>, RET> for Engine {
             fn register_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
                 let f = move |args: &[SteelVal]| -> Result<SteelVal> {
-                    if args.len() != $arg_count {
-                        stop!(ArityMismatch => format!("{} expected {} argument, got {}", name, $arg_count, args.len()));
-                    }
+                    check_arity(name, $arg_count, args)?;
+
+                    let res = func($(convert_argument::<$param>(name, args, $idx)?,)*);
+
+                    res.into_steelval()
+                };
+
+                self.register_value(name, SteelVal::BoxedFunction(Rc::new(f)))
+            }
+        }
+    };
+}
+
+macro_rules! impl_register_fn_mut_receiver {
+    ($arg_count:expr => $($param:ident $arg:ident: $idx:expr),*) => {
+        impl<
+            SELF: CustomType + 'static,
+            $($param: FromSteelVal,)*
+            FN: Fn(&mut SELF, $($param),*) -> RET + 'static,
+            RET: IntoSteelVal
+        > RegisterFn<FN, MutReceiver<(SELF, $($param,)*)>, RET> for Engine {
+            fn register_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
+                let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+                    check_arity(name, $arg_count, args)?;
 
-                    let res = func($(<$param>::from_steelval(args[$idx].clone())?,)*);
+                    // Convert the rest of the arguments before borrowing the receiver,
+                    // the receiver itself could be one of them
+                    $(let $arg = convert_argument::<$param>(name, args, $idx)?;)*
+
+                    let res = with_custom_mut(name, &args[0], |receiver| func(receiver, $($arg),*))?;
 
                     res.into_steelval()
                 };
@@ -85,6 +225,30 @@ macro_rules! impl_register_fn {
     };
 }
 
+macro_rules! impl_register_iter_fn {
+    ($arg_count:expr => $($param:ident: $idx:expr),*) => {
+        impl<
+            $($param: FromSteelVal,)*
+            FN: Fn($($param),*) -> ITER + 'static,
+            ITER: Iterator<Item = std::result::Result<OUT, ERR>> + 'static,
+            OUT: IntoSteelVal,
+            ERR: std::fmt::Debug + 'static
+        > RegisterFn<FN, IterWrapper<($($param,)*)>, ITER> for Engine {
+            fn register_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
+                let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+                    check_arity(name, $arg_count, args)?;
+
+                    let res = func($(convert_argument::<$param>(name, args, $idx)?,)*);
+
+                    StreamOperations::from_iter(res)
+                };
+
+                self.register_value(name, SteelVal::BoxedFunction(Rc::new(f)))
+            }
+        }
+    };
+}
+
 macro_rules! impl_register_async_fn {
     ($arg_count:expr => $($param:ident: $idx:expr),*) => {
         impl<
@@ -95,11 +259,9 @@ macro_rules! impl_register_async_fn {
         > RegisterAsyncFn<FN, Wrapper<($($param,)*)>, RET> for Engine {
             fn register_async_fn(&mut self, name: &'static str, func: FN) -> &mut Self {
                 let f = move |args: &[SteelVal]| -> Result<FutureResult> {
-                    if args.len() != $arg_count {
-                        stop!(ArityMismatch => format!("{} expected {} argument, got {}", name, $arg_count, args.len()));
-                    }
+                    check_arity(name, $arg_count, args)?;
 
-                    let res = func($(<$param>::from_steelval(args[$idx].clone())?,)*);
+                    let res = func($(convert_argument::<$param>(name, args, $idx)?,)*);
 
                     Ok(FutureResult::new(Box::pin(res.map(|x| x.into_steelval()))))
                 };
@@ -127,6 +289,25 @@ impl_register_fn!(14 => A:0, B:1, C:2, D:3, E:4, F:5, G:6, H:7, I:8, J:9, K:10,
 impl_register_fn!(15 => A:0, B:1, C:2, D:3, E:4, F:5, G:6, H:7, I:8, J:9, K:10, L:11, M: 12, N: 13, O: 14);
 impl_register_fn!(16 => A:0, B:1, C:2, D:3, E:4, F:5, G:6, H:7, I:8, J:9, K:10, L:11, M: 12, N: 14, O: 14, P: 15);
 
+impl_register_fn_mut_receiver!(1 =>);
+impl_register_fn_mut_receiver!(2 => A a:1);
+impl_register_fn_mut_receiver!(3 => A a:1, B b:2);
+impl_register_fn_mut_receiver!(4 => A a:1, B b:2, C c:3);
+impl_register_fn_mut_receiver!(5 => A a:1, B b:2, C c:3, D d:4);
+impl_register_fn_mut_receiver!(6 => A a:1, B b:2, C c:3, D d:4, E e:5);
+impl_register_fn_mut_receiver!(7 => A a:1, B b:2, C c:3, D d:4, E e:5, F f:6);
+impl_register_fn_mut_receiver!(8 => A a:1, B b:2, C c:3, D d:4, E e:5, F f:6, G g:7);
+
+impl_register_iter_fn!(0 =>);
+impl_register_iter_fn!(1 => A:0);
+impl_register_iter_fn!(2 => A:0, B:1);
+impl_register_iter_fn!(3 => A:0, B:1, C:2);
+impl_register_iter_fn!(4 => A:0, B:1, C:2, D:3);
+impl_register_iter_fn!(5 => A:0, B:1, C:2, D:3, E:4);
+impl_register_iter_fn!(6 => A:0, B:1, C:2, D:3, E:4, F:5);
+impl_register_iter_fn!(7 => A:0, B:1, C:2, D:3, E:4, F:5, G:6);
+impl_register_iter_fn!(8 => A:0, B:1, C:2, D:3, E:4, F:5, G:6, H:7);
+
 impl_register_async_fn!(1 => A:0);
 impl_register_async_fn!(2 => A:0, B:1);
 impl_register_async_fn!(3 => A:0, B:1, C:2);
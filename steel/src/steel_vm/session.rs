@@ -0,0 +1,24 @@
+use crate::primitives::serialize_datum;
+use crate::rvals::SteelVal;
+
+/// Globals written out as a program that defines them again, see
+/// [`Engine::save_globals`](crate::steel_vm::engine::Engine::save_globals)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SavedGlobals {
+    /// A `(define name 'value)` for each global that could be written out
+    pub source: String,
+    /// Globals holding values that can't be written out, like closures and custom values
+    pub skipped: Vec<String>,
+}
+
+impl SavedGlobals {
+    pub(crate) fn add(&mut self, name: &str, value: &SteelVal) {
+        match serialize_datum(value) {
+            Some(datum) => {
+                self.source
+                    .push_str(&format!("(define {} (quote {}))\n", name, datum));
+            }
+            None => self.skipped.push(name.to_string()),
+        }
+    }
+}
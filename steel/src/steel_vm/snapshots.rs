@@ -0,0 +1,246 @@
+use crate::parser::ast::ExprKind;
+use crate::parser::parser::Parser;
+use crate::parser::tokens::TokenType;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Each snapshot starts with this, followed by the expression it was taken of
+const ENTRY: &str = "== ";
+
+/// What `check-snapshot` does with a value that doesn't match its snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotMode {
+    /// Fail with an error
+    Check,
+    /// Record the new value instead, and forget snapshots that weren't checked
+    Update,
+}
+
+#[derive(Debug)]
+struct SnapshotFile {
+    path: PathBuf,
+    mode: SnapshotMode,
+    /// The printed value of each expression, in the order they appear in the file
+    recorded: Vec<(String, String)>,
+    /// How many times each expression has been checked, so that the same expression can
+    /// be checked more than once
+    counts: HashMap<String, usize>,
+    /// The snapshots that have been checked
+    seen: HashSet<String>,
+    changed: bool,
+}
+
+/// The snapshots taken by `(check-snapshot expr)`, which are kept in a file next to the
+/// program. An expression without a snapshot has its value recorded the first time it is
+/// checked, and has to print the same way from then on.
+///
+/// ```text
+/// == (fib 10)
+/// 55
+/// ```
+///
+/// Handed to an engine with [`Engine::record_snapshots`](crate::steel_vm::engine::Engine::record_snapshots),
+/// and written back out with [`save`](Snapshots::save) once the program is done.
+#[derive(Clone, Debug)]
+pub struct Snapshots(Rc<RefCell<SnapshotFile>>);
+
+impl Snapshots {
+    /// Reads the snapshots in `path`, if there are any yet
+    pub fn load(path: impl AsRef<Path>, mode: SnapshotMode) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let recorded = match std::fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Snapshots(Rc::new(RefCell::new(SnapshotFile {
+            path,
+            mode,
+            recorded,
+            counts: HashMap::new(),
+            seen: HashSet::new(),
+            changed: false,
+        }))))
+    }
+
+    /// The file the snapshots are kept in, for a program at `path`
+    pub fn path_for(path: impl AsRef<Path>) -> PathBuf {
+        path.as_ref().with_extension("snap")
+    }
+
+    /// Writes the snapshots back out, if any were added or updated
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut file = self.0.borrow_mut();
+
+        if file.mode == SnapshotMode::Update {
+            let SnapshotFile { recorded, seen, .. } = &mut *file;
+            let before = recorded.len();
+            recorded.retain(|(key, _)| seen.contains(key));
+            file.changed |= file.recorded.len() != before;
+        }
+
+        if !file.changed {
+            return Ok(());
+        }
+
+        let contents: String = file
+            .recorded
+            .iter()
+            .map(|(key, value)| format!("{}{}\n{}\n", ENTRY, key, value))
+            .collect();
+        std::fs::write(&file.path, contents)?;
+        file.changed = false;
+        Ok(())
+    }
+
+    pub(crate) fn check(&self, expr: &SteelVal, value: &SteelVal) -> Result<SteelVal> {
+        let mut file = self.0.borrow_mut();
+        let file = &mut *file;
+        let expr = expr.to_string();
+        let expr = expr.strip_prefix('\'').unwrap_or(&expr);
+
+        let count = file.counts.entry(expr.to_string()).or_insert(0);
+        *count += 1;
+        let key = match *count {
+            1 => expr.to_string(),
+            n => format!("{} #{}", expr, n),
+        };
+        file.seen.insert(key.clone());
+        let actual = value.to_string();
+
+        match file.recorded.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, expected)) if *expected == actual => {}
+            Some((_, expected)) if file.mode == SnapshotMode::Update => {
+                *expected = actual;
+                file.changed = true;
+            }
+            Some((_, expected)) => {
+                stop!(Generic => "snapshot of {} doesn't match, expected {} but found {}, run with --update-snapshots to accept it",
+                    key, expected, actual)
+            }
+            None => {
+                file.recorded.push((key, actual));
+                file.changed = true;
+            }
+        }
+
+        Ok(SteelVal::Void)
+    }
+}
+
+/// `(%check-snapshot 'expr value)`, which `check-snapshot` expands into. Without any
+/// snapshots to check against it is an error.
+pub(crate) fn check_snapshot(snapshots: Option<Snapshots>) -> SteelVal {
+    let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+        if args.len() != 2 {
+            stop!(ArityMismatch => "check-snapshot takes one argument");
+        }
+
+        match &snapshots {
+            Some(snapshots) => snapshots.check(&args[0], &args[1]),
+            None => {
+                stop!(Generic => "check-snapshot only works when running tests, with steel test")
+            }
+        }
+    };
+
+    SteelVal::BoxedFunction(Rc::new(f))
+}
+
+/// Whether a test file is marked with `#:serial` at its top level, for tests that can't run
+/// alongside others, like ones that write to the same file. `steel test` runs every other
+/// file in parallel.
+pub fn is_serial(source: &str) -> bool {
+    let mut intern = HashMap::new();
+    Parser::new(source, &mut intern).any(|expr| match expr {
+        Ok(ExprKind::Atom(a)) => matches!(&a.syn.ty, TokenType::Keyword(x) if x == "serial"),
+        _ => false,
+    })
+}
+
+fn parse(contents: &str) -> Vec<(String, String)> {
+    let mut recorded: Vec<(String, String)> = Vec::new();
+    for line in contents.lines() {
+        match (line.strip_prefix(ENTRY), recorded.last_mut()) {
+            (Some(key), _) => recorded.push((key.to_string(), String::new())),
+            (None, Some((_, value))) => {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line);
+            }
+            (None, None) => {}
+        }
+    }
+    recorded
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    fn run(path: &Path, mode: SnapshotMode, program: &str) -> Result<Vec<SteelVal>> {
+        let snapshots = Snapshots::load(path, mode).unwrap();
+        let mut vm = Engine::new();
+        vm.record_snapshots(snapshots.clone());
+        let res = vm.run(program);
+        snapshots.save().unwrap();
+        res
+    }
+
+    #[test]
+    fn snapshots_are_recorded_then_checked() {
+        let path = std::env::temp_dir().join("snapshots_are_recorded_then_checked.snap");
+        let _ = std::fs::remove_file(&path);
+
+        let program = "(define (f) (list 1 2)) (check-snapshot (f)) (check-snapshot (f))";
+        run(&path, SnapshotMode::Check, program).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "== (f)\n'(1 2)\n== (f) #2\n'(1 2)\n"
+        );
+
+        let changed = "(define (f) (list 1 3)) (check-snapshot (f))";
+        let err = run(&path, SnapshotMode::Check, changed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Generic);
+
+        // Updating also forgets the second snapshot, which wasn't checked
+        run(&path, SnapshotMode::Update, changed).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "== (f)\n'(1 3)\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_snapshot_needs_snapshots() {
+        let mut vm = Engine::new();
+        assert!(vm.run("(check-snapshot (+ 1 2))").is_err());
+    }
+
+    #[test]
+    fn values_can_span_lines() {
+        let recorded = parse("== (values 1 2)\n1\n2\n== x\n3\n");
+        assert_eq!(
+            recorded,
+            vec![
+                ("(values 1 2)".to_string(), "1\n2".to_string()),
+                ("x".to_string(), "3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn serial_files_are_marked_at_the_top_level() {
+        assert!(is_serial("#:serial\n(define x 1)"));
+        assert!(!is_serial("(define x 1) (f #:serial)"));
+        assert!(!is_serial("(define x 1) ; #:serial"));
+        assert!(Engine::new().run("#:serial (+ 1 2)").is_ok());
+    }
+}
@@ -0,0 +1,360 @@
+use super::engine::Engine;
+use crate::rerrs::{ErrorKind, SendableErr, SteelErr};
+use crate::rvals::{FromSteelVal, Result};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce(&mut Engine) + Send>;
+
+/// A handle to an `Engine` that can be shared between threads.
+///
+/// Values in an `Engine` are reference counted with `Rc`, so the engine itself can't leave
+/// the thread it was made on. Instead, a `SyncEngine` keeps its engine on a thread of its own
+/// and sends it work, so one set of globals can be used from a whole thread pool. Work sent
+/// from different threads is run one piece at a time, in the order it arrives. Anything that
+/// goes in or comes out has to be `Send`, so values are passed as rust types rather than `SteelVal`s.
+///
+/// To run scripts in parallel, [`SyncEngine::pool`] starts several workers, each with an
+/// engine of its own. Programs passed to [`run`](SyncEngine::run) go to every worker, in the
+/// same order everywhere, so their globals stay the same without being copied around by hand.
+/// [`eval`](SyncEngine::eval) and [`with`](SyncEngine::with) go to one worker, taking turns.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::SyncEngine;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let engine = Arc::new(SyncEngine::new());
+/// engine.run("(define (square x) (* x x))").unwrap();
+///
+/// let workers: Vec<_> = (0..4)
+///     .map(|i| {
+///         let engine = Arc::clone(&engine);
+///         thread::spawn(move || engine.eval::<isize>(&format!("(square {})", i)).unwrap())
+///     })
+///     .collect();
+///
+/// let squares: Vec<isize> = workers.into_iter().map(|x| x.join().unwrap()).collect();
+/// assert_eq!(squares, vec![0, 1, 4, 9]);
+/// ```
+pub struct SyncEngine {
+    // One channel per worker. Sending to all of them happens under the lock, so every worker
+    // gets the jobs sent to all of them in the same order
+    jobs: Mutex<Vec<Sender<Job>>>,
+    next: AtomicUsize,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl SyncEngine {
+    /// Starts a thread with a new engine, the same as `Engine::new`
+    pub fn new() -> Self {
+        Self::with_engine(Engine::new)
+    }
+
+    /// Starts a thread with the engine `make` returns. The engine is made on that thread,
+    /// so this is where any values and types should be registered.
+    pub fn with_engine<F: FnOnce() -> Engine + Send + 'static>(make: F) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+
+        SyncEngine {
+            jobs: Mutex::new(vec![sender]),
+            next: AtomicUsize::new(0),
+            workers: vec![Self::start_worker(make, receiver)],
+        }
+    }
+
+    /// Starts `workers` threads, each with the engine `make` returns, made on that thread.
+    ///
+    /// # Panics
+    ///
+    /// If `workers` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::{Engine, SyncEngine};
+    /// let engine = SyncEngine::pool(4, Engine::new);
+    /// engine.run("(define greeting \"hello\")").unwrap();
+    ///
+    /// // Every worker has the definition
+    /// let greetings = engine.with_all(|engine| engine.extract::<String>("greeting").unwrap());
+    /// assert_eq!(greetings, vec!["hello"; 4]);
+    /// ```
+    pub fn pool<F: Fn() -> Engine + Send + Sync + 'static>(workers: usize, make: F) -> Self {
+        assert!(workers > 0, "a pool needs at least one worker");
+
+        let make = Arc::new(make);
+        let (jobs, workers) = (0..workers)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel::<Job>();
+                let make = Arc::clone(&make);
+                (sender, Self::start_worker(move || make(), receiver))
+            })
+            .unzip();
+
+        SyncEngine {
+            jobs: Mutex::new(jobs),
+            next: AtomicUsize::new(0),
+            workers,
+        }
+    }
+
+    fn start_worker<F: FnOnce() -> Engine + Send + 'static>(
+        make: F,
+        receiver: Receiver<Job>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut engine = make();
+            for job in receiver {
+                job(&mut engine);
+            }
+        })
+    }
+
+    /// The number of engines, and threads, work can be sent to
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Wraps `f` so that its result, or its panic, is sent back to whoever is waiting on it
+    fn job<T, F>(f: F, sender: Sender<thread::Result<T>>) -> Job
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Engine) -> T + Send + 'static,
+    {
+        Box::new(move |engine| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(engine)));
+            // The caller is waiting on this, so it can't have gone away
+            let _ = sender.send(result);
+        })
+    }
+
+    /// Runs `f` with an engine on its thread, and returns what it returns. With more than one
+    /// worker, the workers take turns, and anything `f` changes is only seen by that worker.
+    /// If `f` panics, the panic is carried over to the calling thread, and the engine
+    /// is left as `f` left it.
+    pub fn with<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Engine) -> T + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let jobs = self.jobs.lock().unwrap_or_else(|x| x.into_inner());
+            let worker = self.next.fetch_add(1, Ordering::Relaxed) % jobs.len();
+            jobs[worker]
+                .send(Self::job(f, sender))
+                .expect("the engine thread stopped running");
+        }
+
+        match receiver.recv() {
+            Ok(Ok(value)) => value,
+            Ok(Err(payload)) => panic::resume_unwind(payload),
+            Err(_) => panic!("the engine thread stopped running"),
+        }
+    }
+
+    /// Runs `f` with every worker's engine, and returns what each of them returned, in the
+    /// order the workers were started. If `f` panics on any of them, the panic is carried over
+    /// once all of them are done.
+    pub fn with_all<T, F>(&self, f: F) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: Fn(&mut Engine) -> T + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+
+        let receivers: Vec<_> = {
+            let jobs = self.jobs.lock().unwrap_or_else(|x| x.into_inner());
+            jobs.iter()
+                .map(|jobs| {
+                    let (sender, receiver) = mpsc::channel();
+                    let f = Arc::clone(&f);
+                    jobs.send(Self::job(move |engine| f(engine), sender))
+                        .expect("the engine thread stopped running");
+                    receiver
+                })
+                .collect()
+        };
+
+        let results: Vec<_> = receivers
+            .iter()
+            .map(|x| x.recv().expect("the engine thread stopped running"))
+            .collect();
+
+        results
+            .into_iter()
+            .map(|x| x.unwrap_or_else(|payload| panic::resume_unwind(payload)))
+            .collect()
+    }
+
+    /// Runs a program for its effects, such as defining functions for later calls. The program
+    /// is run by every worker, and the error from the first one to fail is returned.
+    pub fn run(&self, expr: &str) -> Result<()> {
+        let expr = expr.to_string();
+        self.with_all(move |engine| engine.run(&expr).map(|_| ()).map_err(SendableErr::from))
+            .into_iter()
+            .collect::<std::result::Result<(), _>>()
+            .map_err(SteelErr::from)
+    }
+
+    /// Runs a program on one of the workers and converts the value of its last expression to `T`
+    pub fn eval<T: FromSteelVal + Send + 'static>(&self, expr: &str) -> Result<T> {
+        let expr = expr.to_string();
+        self.with(move |engine| {
+            engine
+                .run(&expr)
+                .and_then(|mut values| match values.pop() {
+                    Some(value) => T::from_steelval(value),
+                    None => stop!(Generic => "eval expected at least one expression"),
+                })
+                .map_err(SendableErr::from)
+        })
+        .map_err(SteelErr::from)
+    }
+}
+
+impl Default for SyncEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SyncEngine {
+    fn drop(&mut self) {
+        // Closing the channels lets the engine threads finish up, and drop the engines there
+        self.jobs.lock().unwrap_or_else(|x| x.into_inner()).clear();
+
+        for worker in self.workers.drain(..) {
+            // Panics from jobs are caught, so this only fails if making the engine did
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod sync_engine_tests {
+    use super::*;
+    use crate::SteelVal;
+    use std::sync::Arc;
+
+    #[test]
+    fn handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncEngine>();
+    }
+
+    #[test]
+    fn globals_are_shared_between_threads() {
+        let engine = Arc::new(SyncEngine::new());
+        engine.run("(define counter 0)").unwrap();
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || engine.run("(set! counter (+ counter 1))").unwrap())
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(engine.eval::<isize>("counter").unwrap(), 8);
+    }
+
+    #[test]
+    fn errors_come_back_with_their_details() {
+        let engine = SyncEngine::new();
+        let err = engine.eval::<isize>("(car 1)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        let err = engine.eval::<isize>("\"not a number\"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConversionError);
+    }
+
+    #[test]
+    fn values_can_be_registered_on_the_engine_thread() {
+        let engine = SyncEngine::with_engine(|| {
+            let mut engine = Engine::new();
+            engine.register_value("answer", SteelVal::IntV(42));
+            engine
+        });
+
+        assert_eq!(engine.eval::<isize>("answer").unwrap(), 42);
+        assert_eq!(
+            engine.with(|engine| engine.extract::<isize>("answer").unwrap()),
+            42
+        );
+    }
+
+    #[test]
+    fn panics_are_carried_over_and_the_engine_keeps_going() {
+        let engine = SyncEngine::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            engine.with(|_| panic!("job panicked"));
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(engine.eval::<isize>("(+ 1 2)").unwrap(), 3);
+    }
+    #[test]
+    fn pools_run_programs_on_every_worker() {
+        let engine = Arc::new(SyncEngine::pool(4, Engine::new));
+        assert_eq!(engine.workers(), 4);
+        engine.run("(define counter 0)").unwrap();
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || engine.run("(set! counter (+ counter 1))").unwrap())
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let counters = engine.with_all(|engine| engine.extract::<isize>("counter").unwrap());
+        assert_eq!(counters, vec![8; 4]);
+        assert_eq!(engine.eval::<isize>("counter").unwrap(), 8);
+
+        let err = engine.run("(car 1)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn pools_run_work_in_parallel() {
+        use std::sync::Barrier;
+
+        let engine = Arc::new(SyncEngine::pool(4, Engine::new));
+        let barrier = Arc::new(Barrier::new(4));
+
+        // Each job waits for the other three, which only works if they run at the same time
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let engine = Arc::clone(&engine);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    engine.with(move |engine| {
+                        barrier.wait();
+                        engine.run(&format!("(* {} {})", i, i)).unwrap().len()
+                    })
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), 1);
+        }
+    }
+}
@@ -196,12 +196,115 @@ mod register_fn_tests {
         let res: usize = vm.extract("res").unwrap();
         assert_eq!(10, res);
     }
+
+    // Counts up from `start`, and fails once it gets to `end`
+    fn count_until(start: usize, end: usize) -> impl Iterator<Item = Result<usize, String>> {
+        (start..=end).map(move |x| {
+            if x < end {
+                Ok(x)
+            } else {
+                Err(format!("ran out at {}", x))
+            }
+        })
+    }
+
+    #[test]
+    fn test_register_iter_fn() {
+        let mut vm = Engine::new();
+        vm.register_fn("count-until", count_until);
+        vm.register_fn("letters", || "abc".chars().map(Ok::<_, String>));
+
+        let output = vm
+            .run(
+                r#"
+            (define (stream-cdr stream)
+                ((stream-cdr' stream)))
+            (define (take-stream n stream)
+                (if (or (= n 0) (stream-empty? stream))
+                    '()
+                    (cons (stream-car stream) (take-stream (- n 1) (stream-cdr stream)))))
+            (define counter (count-until 3 10))
+            ;; Streams remember their values, so walking one twice gives the same values
+            (list (take-stream 3 counter) (take-stream 4 counter) (take-stream 5 (letters)))
+        "#,
+            )
+            .unwrap();
+        assert_eq!(
+            output.last().unwrap().to_string(),
+            "'((3 4 5) (3 4 5 6) (#\\a #\\b #\\c))"
+        );
+
+        // The error is raised when the stream gets to it, and can be recovered from
+        let err = vm.run("(take-stream 10 counter)").unwrap_err();
+        assert!(err.to_string().contains("ran out at 10"));
+        let err = vm
+            .run("(transduce (mapping (lambda (x) x)) + 0 (count-until 0 5))")
+            .unwrap_err();
+        assert!(err.to_string().contains("ran out at 5"));
+        let err = vm.run("(count-until 2 2)").unwrap_err();
+        assert!(err.to_string().contains("ran out at 2"));
+
+        let output = vm.run("(take-stream 2 counter)").unwrap();
+        assert_eq!(output[0].to_string(), "'(3 4)");
+    }
+
+    #[test]
+    fn bad_arguments_are_described() {
+        use crate::rerrs::{CallError, ErrorKind};
+
+        let mut vm = Engine::new();
+        vm.register_fn("external-function", external_function);
+        vm.register_fn("sum-all", |x: Vec<isize>| x.iter().sum::<isize>());
+
+        let err = vm.run("(external-function 1 \"two\")").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConversionError);
+        assert_eq!(
+            err.call_error(),
+            Some(&CallError::Argument {
+                function: "external-function".to_string(),
+                index: 1,
+                expected: "usize".to_string(),
+                found: "\"two\"".to_string(),
+                reason: "Expected number".to_string(),
+            })
+        );
+        assert!(err
+            .to_string()
+            .contains("external-function expected usize as argument 2, found \"two\""));
+
+        // Long values are cut short, and types are named without their module path
+        let err = vm.run("(sum-all (cons \"x\" (range 0 100)))").unwrap_err();
+        match err.call_error() {
+            Some(CallError::Argument {
+                expected, found, ..
+            }) => {
+                assert_eq!(expected, "Vec<isize>");
+                assert_eq!(found, "'(\"x\" 0 1 2 3 4 5 6 ...)");
+            }
+            other => panic!("expected an argument error, found {:?}", other),
+        }
+
+        let err = vm.run("(external-function 1)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArityMismatch);
+        assert_eq!(
+            err.call_error(),
+            Some(&CallError::Arity {
+                function: "external-function".to_string(),
+                expected: 2,
+                found: 1,
+            })
+        );
+        assert!(err
+            .to_string()
+            .contains("external-function expected 2 arguments, got 1"));
+    }
 }
 
 #[cfg(test)]
 mod register_type_tests {
     use crate::steel_vm::engine::Engine;
     use crate::steel_vm::register_fn::RegisterFn;
+    use crate::SteelVal;
 
     // In order to register a type with Steel,
     // it must implement Clone, Debug, and Steel
@@ -219,11 +322,26 @@ mod register_type_tests {
             ExternalStruct { foo, bar, baz }
         }
 
-        // Embedding functions that take self must take by value
+        // Embedding functions that take self must take by value, or by `&mut`
         pub fn method_by_value(self) -> usize {
             self.foo
         }
 
+        // Methods taking `&mut self` update the value in place
+        pub fn increment_foo(&mut self, amount: usize) -> usize {
+            self.foo += amount;
+            self.foo
+        }
+
+        pub fn reset_foo(&mut self) {
+            self.foo = 0;
+        }
+
+        pub fn add_foo(&mut self, other: ExternalStruct) -> usize {
+            self.foo += other.foo;
+            self.foo
+        }
+
         // Setters should update the value and return a new instance (functional set)
         pub fn set_foo(mut self, foo: usize) -> Self {
             self.foo = foo;
@@ -280,6 +398,80 @@ mod register_type_tests {
             second_new_external_struct
         );
     }
+
+    #[test]
+    fn register_mut_receiver_test() {
+        let mut vm = Engine::new();
+
+        vm.register_fn("ExternalStruct", ExternalStruct::new)
+            .register_fn("method-by-value", ExternalStruct::method_by_value)
+            .register_fn("increment-foo!", ExternalStruct::increment_foo)
+            .register_fn("reset-foo!", ExternalStruct::reset_foo)
+            .register_fn("add-foo!", ExternalStruct::add_foo);
+
+        let results = vm
+            .run(
+                r#"
+            (define external (ExternalStruct 1 "foo" 12.4))
+            (increment-foo! external 10)
+            (increment-foo! external 10)
+            (define before-reset (method-by-value external))
+            (reset-foo! external)
+            (method-by-value external)
+            (add-foo! external (ExternalStruct 5 "bar" 1.0))
+        "#,
+            )
+            .unwrap();
+
+        assert_eq!(results[1], SteelVal::IntV(11));
+        assert_eq!(results[2], SteelVal::IntV(21));
+        assert_eq!(results[5], SteelVal::IntV(0));
+        assert_eq!(results[6], SteelVal::IntV(5));
+        assert_eq!(vm.extract::<usize>("before-reset").unwrap(), 21);
+    }
+
+    #[test]
+    fn mut_receiver_reports_bad_arguments() {
+        let mut vm = Engine::new();
+
+        vm.register_fn("ExternalStruct", ExternalStruct::new)
+            .register_fn("increment-foo!", ExternalStruct::increment_foo)
+            .register_fn("add-foo!", ExternalStruct::add_foo);
+
+        assert!(vm.run("(increment-foo! 10 10)").is_err());
+        assert!(vm
+            .run("(increment-foo! (ExternalStruct 1 \"foo\" 1.0))")
+            .is_err());
+
+        // The receiver is still usable as a plain argument, since that is converted first
+        let results = vm
+            .run("(define s (ExternalStruct 2 \"foo\" 1.0)) (add-foo! s s)")
+            .unwrap();
+        assert_eq!(results.last(), Some(&SteelVal::IntV(4)));
+    }
+    #[test]
+    fn mut_references_are_lent_and_written_back() {
+        let mut vm = Engine::new();
+        vm.register_fn("increment-foo!", ExternalStruct::increment_foo);
+        vm.run("(define external 'taken)").unwrap();
+
+        let mut external = ExternalStruct::new(1, "foo".to_string(), 1.0);
+        let result = vm.with_mut_reference("external", &mut external, |vm| {
+            vm.run(
+                "(define kept external) (increment-foo! external 10) (increment-foo! external 10)",
+            )
+        });
+        assert_eq!(result.unwrap().last(), Some(&SteelVal::IntV(21)));
+        assert_eq!(external.foo, 21);
+
+        // The name is bound to what it was before, and a copy kept by the script is on its own
+        assert_eq!(
+            vm.run("external").unwrap(),
+            vec![SteelVal::SymbolV("taken".into())]
+        );
+        vm.run("(increment-foo! kept 5)").unwrap();
+        assert_eq!(external.foo, 21);
+    }
 }
 
 #[cfg(test)]
@@ -316,3 +508,815 @@ mod stream_tests {
         assert!(lazy_iter.into_iter().next().is_none());
     }
 }
+
+#[cfg(test)]
+mod specialized_instruction_tests {
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    fn run(program: &str) -> SteelVal {
+        Engine::new().run(program).unwrap().pop().unwrap()
+    }
+
+    fn symbol(s: &str) -> SteelVal {
+        SteelVal::SymbolV(s.into())
+    }
+
+    // Arguments the fast paths don't handle should produce the same result as the generic builtin
+    #[test]
+    fn unspecialized_types_fall_back_to_generic_call() {
+        let program = r#"
+            (define (lt x y) (if (< x y) 'yes 'no))
+            (define (num-eq x y) (if (= x y) 'yes 'no))
+            (define (generic op x y) (if (op x y) 'yes 'no))
+            ;; = is not reflexive on floats, so compare numbers this way
+            (define (same? x y) (and (<= x y) (>= x y)))
+
+            (list
+                (equal? (lt 1 2.5) (generic < 1 2.5))
+                (equal? (lt 2.5 1) (generic < 2.5 1))
+                (equal? (lt "a" "b") (generic < "a" "b"))
+                (equal? (lt 'a 1) (generic < 'a 1))
+                (equal? (num-eq 1 1.0) (generic = 1 1.0))
+                (same? (modulo 7.5 2) ((lambda (f) (f 7.5 2)) modulo))
+                (same? (abs -2.5) ((lambda (f) (f -2.5)) abs))
+                (same? (min 1 2.5) ((lambda (f) (f 1 2.5)) min)))
+        "#;
+
+        assert_eq!(run(program), run("(list #t #t #t #t #t #t #t #t)"),);
+    }
+
+    #[test]
+    fn type_errors_are_reported_not_panics() {
+        for program in &[
+            "(define (f x) (modulo x 2)) (f \"a\")",
+            "(define (f x) (abs x)) (f 'a)",
+            "(define (f x) (max x 2)) (f \"a\")",
+        ] {
+            assert!(Engine::new().run(program).is_err(), "{}", program);
+        }
+    }
+
+    #[test]
+    fn rebound_builtins_are_not_specialized() {
+        let program = r#"
+            (define (max a b) 'mine)
+            (define (f) (max 1 2))
+            (f)
+        "#;
+
+        assert_eq!(run(program), symbol("mine"));
+    }
+
+    #[test]
+    fn builtins_redefined_as_other_builtins_are_called() {
+        let program = r#"
+            (define max min)
+            (define < >)
+            (define (f) (max 1 2))
+            (define (g x y) (if (< x y) 'less 'not-less))
+            (list (f) (g 1 2) (< 1 2))
+        "#;
+
+        assert_eq!(run(program), run("(list 1 'not-less #f)"));
+    }
+}
+
+#[cfg(test)]
+mod super_instruction_tests {
+    use crate::compiler::code_generator::fuse_super_instructions;
+    use crate::core::instructions::Instruction;
+    use crate::core::opcode::{unpack_super_instruction, OpCode};
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    fn instructions(op_codes: &[(OpCode, usize)]) -> Vec<Instruction> {
+        op_codes
+            .iter()
+            .map(|(op_code, payload_size)| Instruction {
+                op_code: *op_code,
+                payload_size: *payload_size,
+                contents: None,
+                constant: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_the_first_instruction_of_a_pair_is_rewritten() {
+        use OpCode::*;
+        let mut body = instructions(&[
+            (READLOCAL, 0),
+            (READLOCAL, 1),
+            (READLOCAL, 2),
+            (CGLOCALCONST, 3),
+            (READLOCAL, 4),
+            (READLOCAL, 5),
+            (PASS, 2),
+        ]);
+
+        fuse_super_instructions(&mut body, &[(READLOCAL, READLOCAL)]);
+
+        let op_codes: Vec<_> = body.iter().map(|x| x.op_code).collect();
+        // The third local is left for the loop, and CGLOCALCONST still finds the locals it reads
+        assert_eq!(
+            op_codes,
+            vec![
+                SUPER,
+                READLOCAL,
+                READLOCAL,
+                CGLOCALCONST,
+                READLOCAL,
+                READLOCAL,
+                PASS
+            ]
+        );
+        assert_eq!(
+            unpack_super_instruction(body[0].payload_size as u32),
+            (READLOCAL, 0)
+        );
+        assert_eq!(body[1].payload_size, 1);
+    }
+
+    #[test]
+    fn jumps_only_end_super_instructions() {
+        use OpCode::*;
+        let mut body = instructions(&[(PUSHCONST, 0), (JMP, 3), (PUSHCONST, 1), (JMP, 0)]);
+
+        fuse_super_instructions(&mut body, &[(PUSHCONST, JMP), (JMP, PUSHCONST)]);
+
+        let op_codes: Vec<_> = body.iter().map(|x| x.op_code).collect();
+        assert_eq!(op_codes, vec![SUPER, JMP, SUPER, JMP]);
+    }
+
+    #[test]
+    fn super_instructions_can_branch_and_set_globals() {
+        let program = r#"
+            (define calls 0)
+            (define (count-to i)
+                (set! calls (+ calls 1))
+                (if (= i 100) (if (<= calls 1000) calls 'too-many) (count-to (+ i 1))))
+            (count-to 0)
+        "#;
+
+        let mut vm = Engine::new();
+        let fused: Vec<_> = vm
+            .emit_instructions(program)
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter(|x| x.op_code == OpCode::SUPER)
+            .map(|x| unpack_super_instruction(x.payload_size).0)
+            .collect();
+        assert!(fused.contains(&OpCode::PUSHCONST));
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(101)));
+
+        let mut vm = Engine::new();
+        vm.enable_instruction_stats();
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(101)));
+    }
+
+    #[test]
+    fn jumps_can_land_in_the_middle_of_a_super_instruction() {
+        use crate::core::instructions::DenseInstruction;
+
+        // The false branch reads `y` and falls through to the merge point, which reads `y` again.
+        // The true branch jumps straight to the second read
+        let program = "(define (f x y) (+ (if (= x 3) x y) y))";
+
+        let mut vm = Engine::new();
+        let body: Vec<DenseInstruction> = vm.emit_instructions(program).unwrap().remove(0);
+        let fused_across = body.windows(2).enumerate().any(|(i, pair)| {
+            pair[0].op_code == OpCode::SUPER
+                && unpack_super_instruction(pair[0].payload_size).0 == OpCode::READLOCAL
+                && pair[1].op_code == OpCode::READLOCAL
+                && body[i - 1].op_code == OpCode::JMP
+        });
+        assert!(fused_across);
+
+        vm.run(program).unwrap();
+        for (args, expected) in [("3 0", 3), ("3 4", 7), ("1 5", 10)].iter() {
+            let result = vm.run(&format!("(f {})", args)).unwrap().pop();
+            assert_eq!(result, Some(SteelVal::IntV(*expected)));
+        }
+
+        let mut vm = Engine::new();
+        vm.enable_instruction_stats();
+        vm.run(program).unwrap();
+        vm.run("(f 3 4) (f 1 5)").unwrap();
+        let stats = vm.instruction_stats().unwrap();
+        assert!(stats
+            .hot_patterns(1)
+            .iter()
+            .any(|x| x.opcodes == ["READLOCAL", "READLOCAL"]));
+    }
+
+    #[test]
+    fn super_instructions_that_cant_be_fused_run_as_written() {
+        use crate::compiler::constants::ConstantMap;
+        use crate::core::instructions::DenseInstruction;
+        use crate::core::opcode::super_instruction_payload;
+        use OpCode::*;
+
+        let mut vm = Engine::new();
+        vm.run("(define x 3)").unwrap();
+        let program = vm.emit_instructions("(+ x x)").unwrap().pop().unwrap();
+        let op_codes: Vec<_> = program.iter().map(|x| x.op_code).collect();
+        assert_eq!(op_codes[..3], [PUSH, PUSH, CALLGLOBAL]);
+
+        // Neither CALLGLOBAL after PUSH, nor CALLGLOBAL on its own, can be run as part of a
+        // super instruction
+        for i in 1..=2 {
+            let mut body = program.clone();
+            let payload = super_instruction_payload(body[i].op_code, body[i].payload_size as usize);
+            body[i] = DenseInstruction {
+                op_code: SUPER,
+                payload_size: payload.unwrap() as u32,
+                ..body[i]
+            };
+
+            let result = vm.execute(body.into(), &ConstantMap::new()).unwrap();
+            assert_eq!(result, SteelVal::IntV(6));
+        }
+    }
+
+    #[test]
+    fn fused_programs_run_the_same_with_callbacks_on() {
+        let program = r#"
+            (define (count-up n)
+                (let ((total 0))
+                    (define (loop i)
+                        (if (= i n) total (begin (set! total (+ total i)) (loop (+ i 1)))))
+                    (loop 0)))
+            (count-up 100)
+        "#;
+
+        let mut vm = Engine::new();
+        let fused = vm
+            .emit_instructions(program)
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|x| x.op_code == OpCode::SUPER);
+        assert!(fused);
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(4950)));
+
+        let mut vm = Engine::new();
+        vm.enable_instruction_stats();
+        assert_eq!(vm.run(program).unwrap().pop(), Some(SteelVal::IntV(4950)));
+
+        // The stats only see the instructions super instructions are made of
+        let stats = vm.instruction_stats().unwrap();
+        assert!(stats
+            .functions()
+            .iter()
+            .all(|x| !x.opcodes.contains_key(&OpCode::SUPER)));
+    }
+}
+
+#[cfg(test)]
+mod async_tests {
+    use crate::rerrs::ErrorKind;
+    use crate::rvals::{Custom, IntoSteelVal, SteelVal};
+    use crate::steel_vm::engine::Engine;
+    use crate::steel_vm::register_fn::RegisterAsyncFn;
+    use futures::channel::oneshot;
+    use futures::executor::{block_on, LocalPool};
+    use futures::task::LocalSpawnExt;
+    use std::cell::{Cell, RefCell};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+
+    // Not ready the first time it's polled, so anything awaiting it has to wait
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    async fn tick(n: isize) -> isize {
+        YieldOnce(false).await;
+        n
+    }
+
+    async fn fail() -> Result<isize, String> {
+        YieldOnce(false).await;
+        Err("the request failed".to_string())
+    }
+
+    #[test]
+    fn suspended_programs_pick_up_where_they_left_off() {
+        let mut vm = Engine::new();
+        vm.register_async_fn("tick", tick);
+
+        let program = r#"
+            (define (sum-ticks n acc)
+                (if (= n 0)
+                    acc
+                    (sum-ticks (- n 1) (+ acc (await (tick n))))))
+            (define (adder x) (lambda (y) (+ x (await (tick y)))))
+            (sum-ticks 10 0)
+            ((adder (await (tick 1))) 2)
+        "#;
+
+        let output = block_on(vm.run_async(program)).unwrap();
+        assert_eq!(output[2], SteelVal::IntV(55));
+        assert_eq!(output[3], SteelVal::IntV(3));
+    }
+
+    #[test]
+    fn other_tasks_run_while_waiting() {
+        let (sender, receiver) = oneshot::channel::<isize>();
+        let receiver = RefCell::new(Some(receiver));
+        let sent = Rc::new(Cell::new(false));
+
+        let mut vm = Engine::new();
+        vm.register_async_fn("fetch", move || {
+            let receiver = receiver.borrow_mut().take().unwrap();
+            async move { receiver.await.unwrap() }
+        });
+
+        let mut pool = LocalPool::new();
+        let task_sent = Rc::clone(&sent);
+        pool.spawner()
+            .spawn_local(async move {
+                YieldOnce(false).await;
+                task_sent.set(true);
+                sender.send(10).unwrap();
+            })
+            .unwrap();
+
+        let output = pool
+            .run_until(vm.run_async("(* (await (fetch)) 2)"))
+            .unwrap();
+        assert_eq!(output, vec![SteelVal::IntV(20)]);
+        assert!(sent.get());
+    }
+
+    #[test]
+    fn run_blocks_on_awaited_futures() {
+        let mut vm = Engine::new();
+        vm.register_async_fn("tick", tick);
+        assert_eq!(
+            vm.run("(+ (await (tick 1)) (await (tick 2)))").unwrap(),
+            vec![SteelVal::IntV(3)]
+        );
+    }
+
+    #[test]
+    fn await_inside_native_functions_blocks() {
+        let mut vm = Engine::new();
+        vm.register_async_fn("tick", tick);
+        assert_eq!(
+            vm.run("(map await (list (tick 1) (tick 2)))").unwrap()[0].to_string(),
+            "'(1 2)"
+        );
+
+        // Suspending from inside of map would lose the rest of the list, and blocking isn't
+        // possible inside of an executor
+        let err = block_on(vm.run_async("(map await (list (tick 1) (tick 2)))")).unwrap_err();
+        assert!(err.to_string().contains("calls it directly"));
+        let output = block_on(vm.run_async("(await (tick 3))")).unwrap();
+        assert_eq!(output, vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn errors_from_futures_are_raised_at_the_await() {
+        let mut vm = Engine::new();
+        vm.register_async_fn("fail", fail);
+        vm.register_async_fn("tick", tick);
+
+        let err = block_on(vm.run_async("(await (fail))")).unwrap_err();
+        assert!(err.to_string().contains("the request failed"));
+        assert!(err.span().is_some());
+
+        let err = block_on(vm.run_async("(await 10)")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        // The engine is fine afterwards
+        let output = block_on(vm.run_async("(await (tick 5))")).unwrap();
+        assert_eq!(output, vec![SteelVal::IntV(5)]);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Handle;
+    impl Custom for Handle {}
+
+    #[test]
+    fn finalizers_run_after_suspended_programs() {
+        let mut vm = Engine::new();
+        vm.register_async_fn("tick", tick);
+        let closed = Rc::new(Cell::new(0));
+
+        let handle = Handle.into_steelval().unwrap();
+        let counter = Rc::clone(&closed);
+        vm.register_finalizer(&handle, move || counter.set(counter.get() + 1))
+            .unwrap();
+        vm.register_value("handle", handle);
+
+        block_on(vm.run_async("(begin (set! handle 0) (await (tick 1)))")).unwrap();
+        assert_eq!(closed.get(), 1);
+        assert_eq!(vm.run_finalizers(), 0);
+    }
+}
+
+#[cfg(test)]
+mod builtin_module_tests {
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn builtin_modules_can_be_required() {
+        let mut vm = Engine::new();
+        let output = vm
+            .run(
+                r#"
+                (require "steel/uuid")
+                (require "steel/semver")
+                (require "steel/math")
+                (define id (uuid/v4))
+                (list (uuid/valid? id)
+                      (uuid/version id)
+                      (semver<? "1.0.0-rc.1" "1.0.0")
+                      (semver/compare "2.1.0" "2.0.9")
+                      (floor/ 7 -2))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            output.last().unwrap().to_string(),
+            "'(#true 4 #true 1 (-4 -1))"
+        );
+        assert!(matches!(
+            vm.extract_value("id").unwrap(),
+            SteelVal::StringV(_)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod redefinition_tests {
+    use crate::compiler::compiler::RedefinitionPolicy;
+    use crate::rerrs::ErrorKind;
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn redefinitions_in_one_program_are_caught() {
+        let mut vm = Engine::new();
+        vm.set_redefinition_policy(RedefinitionPolicy::Warn);
+        vm.run("(define x 1) (begin (define y 2) (define x 3))")
+            .unwrap();
+
+        let warnings = vm.compile_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.starts_with("x is already defined"));
+
+        vm.set_redefinition_policy(RedefinitionPolicy::Error);
+        let err = vm.run("(define z 1) (define z 2)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadSyntax);
+        assert!(vm.run("(define map 1)").is_err());
+    }
+
+    #[test]
+    fn requiring_a_module_again_is_not_a_redefinition() {
+        let path =
+            std::env::temp_dir().join(format!("steel-redefinition-{}.stl", std::process::id()));
+        std::fs::write(&path, "(provide next) (define (next x) (+ x 1))").unwrap();
+        let program = format!("(require {:?}) (next 1)", path);
+
+        let mut vm = Engine::new();
+        vm.run(&program).unwrap();
+        vm.set_redefinition_policy(RedefinitionPolicy::Error);
+        let output = vm.run(&program);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(output.unwrap().last(), Some(&SteelVal::IntV(2)));
+    }
+
+    #[test]
+    fn programs_that_fail_to_compile_define_nothing() {
+        let mut vm = Engine::new();
+        vm.set_redefinition_policy(RedefinitionPolicy::Error);
+        let err = vm.run("(define xx 1) (not-defined-anywhere)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FreeIdentifier);
+
+        let output = vm.run("(define xx 2) xx").unwrap();
+        assert_eq!(output.last(), Some(&SteelVal::IntV(2)));
+    }
+
+    #[test]
+    fn executables_cant_redefine_globals_when_not_allowed() {
+        let compile = || {
+            Engine::new()
+                .compile_executable("(define x 1) (define x 2) x")
+                .unwrap()
+        };
+
+        let mut vm = Engine::new();
+        vm.set_redefinition_policy(RedefinitionPolicy::Error);
+        let err = vm.run_executable(compile()).unwrap_err();
+        assert!(err.to_string().contains("x is already defined"));
+
+        let mut vm = Engine::new();
+        assert_eq!(
+            vm.run_executable(compile()).unwrap().last(),
+            Some(&SteelVal::IntV(2))
+        );
+    }
+}
+
+#[cfg(test)]
+mod doc_tests {
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn doc_strings_of_programs_and_modules_are_kept() {
+        let path = std::env::temp_dir().join(format!("steel-docs-{}.stl", std::process::id()));
+        std::fs::write(
+            &path,
+            "(provide next) (define (next x) \"The number after `x`\" (+ x 1))",
+        )
+        .unwrap();
+
+        let mut vm = Engine::new();
+        let output = vm.run(&format!(
+            "(require {:?}) (define (twice x) \"Doubles `x`\" (* 2 x)) (next (twice 2))",
+            path
+        ));
+        std::fs::remove_file(&path).unwrap();
+        output.unwrap();
+
+        let docs = vm.docs();
+        let next = docs.iter().find(|x| x.name == "next").unwrap();
+        assert_eq!(next.module, path.display().to_string());
+        assert_eq!(next.doc, "The number after `x`");
+        assert!(docs.iter().any(|x| x.name == "twice"));
+
+        assert!(vm.run("(doc twice) (doc uuid/parse)").is_ok());
+        assert!(vm.run("(doc car)").is_err());
+    }
+}
+
+#[cfg(test)]
+mod fork_tests {
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn forks_define_globals_independently() {
+        let mut parent = Engine::new();
+        parent
+            .run("(define (double x) (* 2 x)) (define total 0)")
+            .unwrap();
+
+        let mut first = parent.fork();
+        let mut second = parent.fork();
+
+        // The same new global gets the same slot in each, without them seeing each other's value
+        first.run("(define answer (double 21))").unwrap();
+        second
+            .run("(define answer \"none\") (set! total 5)")
+            .unwrap();
+        parent.run("(define answer 'parent)").unwrap();
+
+        assert_eq!(first.extract_value("answer").unwrap(), SteelVal::IntV(42));
+        assert_eq!(
+            second.run("answer").unwrap(),
+            vec![SteelVal::StringV("none".into())]
+        );
+        assert_eq!(
+            parent.run("(list answer total)").unwrap()[0].to_string(),
+            "'(parent 0)"
+        );
+        assert_eq!(first.extract_value("total").unwrap(), SteelVal::IntV(0));
+    }
+
+    #[test]
+    fn macros_defined_in_a_fork_stay_there() {
+        let mut parent = Engine::new();
+        let mut fork = parent.fork();
+
+        fork.run("(define-syntax twice (syntax-rules () [(twice e) (begin e e)]))")
+            .unwrap();
+        assert_eq!(fork.run("(twice 1)").unwrap(), vec![SteelVal::IntV(1)]);
+        assert!(parent.run("(twice 1)").is_err());
+    }
+
+    #[test]
+    fn captured_variables_are_shared() {
+        let mut parent = Engine::new();
+        parent
+            .run("(define counter (let ([n 0]) (lambda () (set! n (+ n 1)) n)))")
+            .unwrap();
+
+        let mut fork = parent.fork();
+        assert_eq!(fork.run("(counter)").unwrap(), vec![SteelVal::IntV(1)]);
+
+        assert_eq!(parent.run("(counter)").unwrap(), vec![SteelVal::IntV(2)]);
+
+        // The fork keeps the upvalue alive once the parent is gone
+        drop(parent);
+        assert_eq!(fork.run("(counter)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+}
+
+#[cfg(test)]
+mod module_reload_tests {
+    use crate::compiler::compiler::RedefinitionPolicy;
+    use crate::rvals::SteelVal;
+    use crate::steel_vm::engine::Engine;
+    use std::path::PathBuf;
+
+    fn module_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("steel-reload-{}-{}.scm", name, std::process::id()))
+    }
+
+    #[test]
+    fn reloading_rebinds_what_the_module_provides() {
+        let path = module_path("rebind");
+        std::fs::write(
+            &path,
+            "(provide scale old) (define (scale x) x) (define old 1)",
+        )
+        .unwrap();
+
+        let mut vm = Engine::new();
+        vm.set_redefinition_policy(RedefinitionPolicy::Error);
+        vm.run(&format!(
+            "(require {:?}) (define (apply-scale) (scale 5))",
+            path
+        ))
+        .unwrap();
+
+        std::fs::write(&path, "(provide scale) (define (scale x) (* 3 x))").unwrap();
+        let reload = vm.reload_module(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let reload = reload.unwrap();
+        assert_eq!(reload.removed, vec!["old"]);
+        assert_eq!(reload.updated, vec!["scale"]);
+        assert!(reload.added.is_empty());
+
+        assert_eq!(vm.run("(apply-scale)").unwrap(), vec![SteelVal::IntV(15)]);
+        assert_eq!(vm.extract_value("old").unwrap(), SteelVal::IntV(1));
+    }
+
+    #[test]
+    fn folded_exports_are_reported_separately() {
+        let path = module_path("folded");
+        std::fs::write(
+            &path,
+            "(provide rate scale) (define rate 2) (define (scale x) x)",
+        )
+        .unwrap();
+
+        let mut vm = Engine::new();
+        vm.run(&format!("(require {:?}) (define (cost) (* rate 10))", path))
+            .unwrap();
+
+        std::fs::write(
+            &path,
+            "(provide rate scale) (define rate 3) (define (scale x) x)",
+        )
+        .unwrap();
+        let reload = vm.reload_module(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let reload = reload.unwrap();
+        assert_eq!(reload.folded, vec!["rate"]);
+        assert_eq!(reload.updated, vec!["scale"]);
+
+        // The old value was folded into cost, only code compiled from now on sees the new one
+        assert_eq!(vm.run("(cost)").unwrap(), vec![SteelVal::IntV(20)]);
+        assert_eq!(vm.run("rate").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn modules_requiring_a_reloaded_module_are_compiled_again() {
+        let base = module_path("base");
+        let user = module_path("user");
+        std::fs::write(&base, "(provide base) (define base 1)").unwrap();
+        std::fs::write(
+            &user,
+            format!(
+                "(require {:?}) (provide total) (define (total) (+ base 1))",
+                base
+            ),
+        )
+        .unwrap();
+
+        let mut vm = Engine::new();
+        vm.run(&format!("(require {:?})", user)).unwrap();
+
+        std::fs::write(&base, "(provide base) (define base 10)").unwrap();
+        let reloaded = vm
+            .reload_module(&base)
+            .and_then(|_| vm.run(&format!("(require {:?}) (total)", user)));
+        std::fs::remove_file(&base).unwrap();
+        std::fs::remove_file(&user).unwrap();
+
+        assert_eq!(reloaded.unwrap().last(), Some(&SteelVal::IntV(11)));
+    }
+
+    #[test]
+    fn only_required_modules_can_be_reloaded() {
+        let mut vm = Engine::new();
+        assert!(vm.reload_module(module_path("missing")).is_err());
+    }
+}
+
+#[cfg(test)]
+mod interrupt_tests {
+    use crate::rerrs::ErrorKind;
+    use crate::steel_vm::engine::Engine;
+    use crate::SteelVal;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn interrupts_stop_runaway_scripts_from_other_threads() {
+        let mut vm = Engine::new();
+        vm.run("(define (spin) (spin))").unwrap();
+        let handle = vm.interrupt_handle();
+
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            handle.interrupt();
+        });
+        let err = vm.run("(spin)").unwrap_err();
+        interrupter.join().unwrap();
+
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert!(err.to_string().contains("interrupted"));
+        assert!(!vm.interrupt_handle().is_pending());
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+
+    #[test]
+    fn interrupts_sent_while_idle_stop_the_next_run() {
+        let mut vm = Engine::new();
+        let handle = vm.interrupt_handle();
+
+        handle.interrupt();
+        assert!(handle.is_pending());
+        assert_eq!(
+            vm.run("(+ 1 2)").unwrap_err().kind(),
+            ErrorKind::Interrupted
+        );
+
+        handle.interrupt();
+        handle.clear();
+        assert!(vm.run("(+ 1 2)").is_ok());
+
+        // Forks are interrupted on their own
+        let mut fork = vm.fork();
+        handle.interrupt();
+        assert!(fork.run("(+ 1 2)").is_ok());
+    }
+}
+
+#[cfg(test)]
+mod timings_tests {
+    use crate::steel_vm::engine::Engine;
+
+    #[test]
+    fn modules_and_passes_are_timed() {
+        let path = std::env::temp_dir().join(format!("steel-timings-{}.scm", std::process::id()));
+        std::fs::write(&path, "(provide next) (define (next x) (+ x 1))").unwrap();
+
+        let mut vm = Engine::new();
+        let compiled = vm.compile_with_timings(&format!(
+            "(require {:?}) (define (twice x) (* 2 x)) (next (twice 2))",
+            path
+        ));
+        std::fs::remove_file(&path).unwrap();
+        let (program, timings) = compiled.unwrap();
+
+        assert!(timings
+            .modules
+            .iter()
+            .any(|(module, _)| module.file_name() == path.file_name()));
+        assert!(!timings.passes.is_empty());
+        assert!(timings.total() >= timings.expand + timings.codegen);
+        assert_eq!(vm.compile_timings(), &timings);
+        assert!(timings.to_string().contains("  inlining"));
+
+        vm.execute_program(program).unwrap();
+        vm.compile_with_timings("(+ 1 2)").unwrap();
+        assert!(vm.compile_timings().modules.is_empty());
+    }
+}
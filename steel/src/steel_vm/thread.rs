@@ -0,0 +1,270 @@
+use super::vm::Winder;
+use crate::rvals::{Result, SteelVal};
+
+use std::fmt;
+use std::rc::Rc;
+
+// How deep native functions and the Steel functions they call can nest inside each other,
+// as deep as Steel's own stack. Every level is another run of the VM on the rust stack,
+// which is grown as needed.
+pub(crate) const NESTING_LIMIT: usize = 1000;
+
+// Nesting grows the stack once there is less than this much of it left, with a new piece
+// of `STACK_GROWTH` bytes. A level takes about a hundred kilobytes in debug builds.
+pub(crate) const STACK_RED_ZONE: usize = 256 * 1024;
+pub(crate) const STACK_GROWTH: usize = 4 * 1024 * 1024;
+
+// Implemented by the VM, runs a call to completion on top of whatever it is already doing
+pub(crate) trait NestedCall {
+    fn call_nested(&mut self, function: &SteelVal, args: Vec<SteelVal>) -> Result<SteelVal>;
+
+    fn push_winder(&mut self, winder: Winder);
+
+    fn pop_winder(&mut self);
+
+    fn take_interrupt(&mut self) -> bool;
+
+    fn defer_call(&mut self, call: DeferredCall);
+}
+
+// A call a native function asked to have made in its place, see `SteelThread::call_then`
+pub(crate) struct DeferredCall {
+    pub(crate) function: SteelVal,
+    pub(crate) args: Vec<SteelVal>,
+    pub(crate) then: ReturnHook,
+}
+
+// Turns what a deferred call returned into what the native function that deferred it returns
+#[derive(Clone)]
+pub(crate) struct ReturnHook(Rc<dyn Fn(SteelVal) -> Result<SteelVal>>);
+
+impl ReturnHook {
+    pub(crate) fn call(&self, value: SteelVal) -> Result<SteelVal> {
+        (self.0)(value)
+    }
+}
+
+impl fmt::Debug for ReturnHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<return-hook>")
+    }
+}
+
+/// The VM a native function registered with
+/// [`register_builtin`](crate::steel_vm::engine::Engine::register_builtin) is running on.
+///
+/// Calls made through it run in a nested execution context: they start a fresh frame on top
+/// of the caller's stacks, and everything they leave behind is rolled back when they return,
+/// even if they return an error. This means a native function can call into Steel, which can
+/// call another native function, which can call into Steel again, without any of them seeing
+/// each other's state.
+pub struct SteelThread<'a> {
+    vm: &'a mut dyn NestedCall,
+}
+
+impl<'a> SteelThread<'a> {
+    pub(crate) fn new(vm: &'a mut dyn NestedCall) -> Self {
+        SteelThread { vm }
+    }
+
+    /// Calls `function` with `args` and returns its result. Globals, closures and the upvalue
+    /// heap are all shared with the code that called the native function.
+    pub fn call_function(&mut self, function: &SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        self.vm.call_nested(function, args)
+    }
+
+    /// Has `function` called with `args` once the native function returns, in its place. What it
+    /// returns is passed to `then`, and what `then` returns is the native function's result, the
+    /// value the native function itself returns is ignored. Unlike `call_function`, a closure
+    /// called this way runs on the VM that called the native function rather than a nested one,
+    /// so recursing through the native function isn't limited by how deep calls can nest.
+    pub(crate) fn call_then(
+        &mut self,
+        function: SteelVal,
+        args: Vec<SteelVal>,
+        then: impl Fn(SteelVal) -> Result<SteelVal> + 'static,
+    ) {
+        self.vm.defer_call(DeferredCall {
+            function,
+            args,
+            then: ReturnHook(Rc::new(then)),
+        });
+    }
+
+    /// Marks the start of the thunk of a `dynamic-wind`, continuations capture the
+    /// `dynamic-wind`s they are inside of
+    pub(crate) fn push_winder(&mut self, before: SteelVal, after: SteelVal) {
+        self.vm.push_winder(Winder::new(before, after));
+    }
+
+    /// Marks the end of the thunk of the innermost `dynamic-wind`
+    pub(crate) fn pop_winder(&mut self) {
+        self.vm.pop_winder();
+    }
+
+    /// Whether the engine's interrupt handle has been used, using the interrupt up if it has.
+    /// Native functions that wait on something check this to stop waiting.
+    pub(crate) fn take_interrupt(&mut self) -> bool {
+        self.vm.take_interrupt()
+    }
+}
+
+#[cfg(test)]
+mod thread_tests {
+    use super::*;
+    use crate::rerrs::{ErrorKind, SteelErr};
+    use crate::steel_vm::engine::Engine;
+
+    // (apply-twice f x) => (f (f x)), with both calls made from rust
+    fn apply_twice(thread: &mut SteelThread, args: &[SteelVal]) -> Result<SteelVal> {
+        if args.len() != 2 {
+            stop!(ArityMismatch => "apply-twice takes two arguments");
+        }
+
+        let once = thread.call_function(&args[0], vec![args[1].clone()])?;
+        thread.call_function(&args[0], vec![once])
+    }
+
+    fn engine() -> Engine {
+        let mut vm = Engine::new();
+        vm.register_builtin("apply-twice", apply_twice);
+        vm
+    }
+
+    #[test]
+    fn builtins_call_steel_functions() {
+        let mut vm = engine();
+        let output = vm
+            .run("(define (add-one x) (+ x 1)) (apply-twice add-one 10)")
+            .unwrap();
+        assert_eq!(output.last().unwrap(), &SteelVal::IntV(12));
+    }
+
+    #[test]
+    fn builtin_steel_builtin_steel_chains() {
+        let mut vm = engine();
+
+        // Each call to quadruple goes rust -> steel -> rust -> steel
+        let program = r#"
+            (define (double x) (* x 2))
+            (define (quadruple x) (apply-twice double x))
+            (define (sixteen-times x) (apply-twice quadruple x))
+            (list (sixteen-times 1) (+ 1 (apply-twice (lambda (x) (apply-twice double x)) 1)))
+        "#;
+
+        let output = vm.run(program).unwrap();
+        assert_eq!(output.last().unwrap().to_string(), "'(16 17)");
+    }
+
+    #[test]
+    fn closures_and_globals_are_shared() {
+        let mut vm = engine();
+        let program = r#"
+            (define total 0)
+            (define (make-counter)
+                (define count 0)
+                (lambda (x)
+                    (set! count (+ count 1))
+                    (set! total (+ total x))
+                    count))
+            (define counter (make-counter))
+            (apply-twice counter 5)
+            (list (counter 0) total)
+        "#;
+
+        let output = vm.run(program).unwrap();
+        assert_eq!(output.last().unwrap().to_string(), "'(3 6)");
+    }
+
+    #[test]
+    fn errors_in_nested_calls_leave_the_caller_intact() {
+        let mut vm = engine();
+        vm.register_builtin("try-call", |thread, args| {
+            match thread.call_function(&args[0], args[1..].to_vec()) {
+                Ok(value) => Ok(value),
+                Err(_) => Ok(SteelVal::BoolV(false)),
+            }
+        });
+
+        // The failed call leaves values on the stack partway through an expression,
+        // the rest of the expression has to still see its own values
+        let program = r#"
+            (define (fail x) (+ 1 2 (car x)))
+            (define (ok x) x)
+            (list 1 (try-call fail 1) (+ 2 (try-call ok 10)) 4)
+        "#;
+
+        let output = vm.run(program).unwrap();
+        assert_eq!(output.last().unwrap().to_string(), "'(1 #false 12 4)");
+
+        let err = vm.run("(apply-twice car 10)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn arguments_stay_alive_during_nested_calls() {
+        let mut vm = engine();
+
+        // Collecting upvalues while `produce` runs must not free the one the consumer captured
+        let program = r#"
+            (define (make-adder n) (lambda (x) (+ x n)))
+            (define keep '())
+            (define (churn k)
+                (if (= k 0) 0 (begin (set! keep (cons (make-adder k) keep)) (churn (- k 1)))))
+            (define (produce) (churn 50000) 1)
+            (call-with-values produce (make-adder 7))
+        "#;
+
+        let output = vm.run(program).unwrap();
+        assert_eq!(output.last().unwrap(), &SteelVal::IntV(8));
+    }
+
+    #[test]
+    fn recursion_through_native_functions() {
+        let mut vm = engine();
+        vm.register_builtin("call", |thread, args| {
+            thread.call_function(&args[0], args[1..].to_vec())
+        });
+
+        let output = vm
+            .run("(define (depth n) (if (= n 0) 0 (+ 1 (call depth (- n 1))))) (depth 500)")
+            .unwrap();
+        assert_eq!(output.last().unwrap(), &SteelVal::IntV(500));
+    }
+
+    #[test]
+    fn every_kind_of_function_can_be_called() {
+        let mut vm = engine();
+        vm.run(
+            r#"
+            (define/contract (add-one x)
+                (->/c int? int?)
+                (+ x 1))
+            "#,
+        )
+        .unwrap();
+
+        let output = vm.run("(apply-twice add-one 1)").unwrap();
+        assert_eq!(output.last().unwrap(), &SteelVal::IntV(3));
+
+        let err = vm.run("(apply-twice add-one \"one\")").unwrap_err();
+        assert!(err.to_string().contains("contract"));
+
+        let err = vm
+            .run("(call/cc (lambda (k) (apply-twice k 1)))")
+            .unwrap_err();
+        assert!(err.to_string().contains("continuation"));
+    }
+
+    #[test]
+    fn runaway_nesting_is_an_error() {
+        let mut vm = engine();
+        let err = vm
+            .run("(define (forever x) (apply-twice forever x)) (forever 1)")
+            .unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"));
+
+        // The engine can still be used afterwards
+        assert_eq!(vm.run("(+ 1 2)").unwrap(), vec![SteelVal::IntV(3)]);
+    }
+}
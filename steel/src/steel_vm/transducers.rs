@@ -8,7 +8,7 @@ use crate::{
     parser::span::Span,
     primitives::{ListOperations, VectorOperations},
     rerrs::{ErrorKind, SteelErr},
-    rvals::{CollectionType, Result, SteelVal, Transducers},
+    rvals::{ByteCodeLambda, CollectionType, Result, SteelVal, Transducers},
     stop,
 };
 
@@ -26,6 +26,19 @@ use crate::gc::Gc;
 // use super::inline_iter::*;
 use super::lazy_stream::LazyStreamIter;
 
+// The arguments for a call to a closure, lined up with its parameters if it takes optional or
+// keyword arguments
+fn bind_arguments(
+    closure: &ByteCodeLambda,
+    args: Vec<SteelVal>,
+    span: &Span,
+) -> Result<Vec<SteelVal>> {
+    match closure.signature() {
+        Some(signature) => signature.bind(&args).map_err(|x| x.set_span(*span)),
+        None => Ok(args),
+    }
+}
+
 /// Generates the take transducer - wrapper around the take iterator
 macro_rules! generate_take {
     ($iter:expr, $num:expr, $cur_inst_span:expr) => {
@@ -135,12 +148,16 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                         SteelVal::Closure(closure) => {
                             let mut local_upvalue_heap = UpValueHeap::new();
 
+                            let args = bind_arguments(closure, vec![arg?], cur_inst_span)?;
+
                             // Set the state prior to the recursive call
                             vm_stack_index_copy
                                 .borrow_mut()
                                 .push(vm_stack_copy.borrow().len());
 
-                            vm_stack_copy.borrow_mut().push(arg?);
+                            for arg in args {
+                                vm_stack_copy.borrow_mut().push(arg);
+                            }
 
                             function_stack_copy.borrow_mut().push(Gc::clone(closure));
 
@@ -233,12 +250,23 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                                 SteelVal::Closure(closure) => {
                                     let mut local_upvalue_heap = UpValueHeap::new();
 
+                                    let args = match bind_arguments(
+                                        closure,
+                                        vec![arg.clone()],
+                                        cur_inst_span,
+                                    ) {
+                                        Ok(args) => args,
+                                        Err(e) => return Some(Err(e)),
+                                    };
+
                                     // Set the state prior to the recursive call
                                     vm_stack_index_copy
                                         .borrow_mut()
                                         .push(vm_stack_copy.borrow().len());
 
-                                    vm_stack_copy.borrow_mut().push(arg.clone());
+                                    for arg in args {
+                                        vm_stack_copy.borrow_mut().push(arg);
+                                    }
 
                                     function_stack_copy.borrow_mut().push(Gc::clone(closure));
 
@@ -268,7 +296,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                                 }
                                 _ => Some(Err(SteelErr::new(
                                     ErrorKind::TypeMismatch,
-                                    "filter expected a function".to_string(),
+                                    "filter expected a function",
                                 )
                                 .with_span(*cur_inst_span))),
                             }
@@ -374,12 +402,16 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                             )
                         }
                         SteelVal::Closure(closure) => {
+                            let args = bind_arguments(closure, vec![arg?], cur_inst_span)?;
+
                             // Set the state prior to the recursive call
                             vm_stack_index_copy
                                 .borrow_mut()
                                 .push(vm_stack_copy.borrow().len());
 
-                            vm_stack_copy.borrow_mut().push(arg?);
+                            for arg in args {
+                                vm_stack_copy.borrow_mut().push(arg);
+                            }
 
                             function_stack_copy.borrow_mut().push(Gc::clone(closure));
 
@@ -468,12 +500,23 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                                     }
                                 }
                                 SteelVal::Closure(closure) => {
+                                    let args = match bind_arguments(
+                                        closure,
+                                        vec![arg.clone()],
+                                        cur_inst_span,
+                                    ) {
+                                        Ok(args) => args,
+                                        Err(e) => return Some(Err(e)),
+                                    };
+
                                     // Set the state prior to the recursive call
                                     vm_stack_index_copy
                                         .borrow_mut()
                                         .push(vm_stack_copy.borrow().len());
 
-                                    vm_stack_copy.borrow_mut().push(arg.clone());
+                                    for arg in args {
+                                        vm_stack_copy.borrow_mut().push(arg);
+                                    }
 
                                     function_stack_copy.borrow_mut().push(Gc::clone(closure));
 
@@ -503,7 +546,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                                 }
                                 _ => Some(Err(SteelErr::new(
                                     ErrorKind::TypeMismatch,
-                                    "filter expected a function".to_string(),
+                                    "filter expected a function",
                                 )
                                 .with_span(*cur_inst_span))),
                             }
@@ -551,11 +594,14 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                 )
             }
             SteelVal::Closure(closure) => {
+                let args = bind_arguments(closure, vec![acc?, x?], cur_inst_span)?;
+
                 // Set the state prior to the recursive call
                 vm_stack_index.borrow_mut().push(vm_stack.borrow().len());
 
-                vm_stack.borrow_mut().push(acc?);
-                vm_stack.borrow_mut().push(x?);
+                for arg in args {
+                    vm_stack.borrow_mut().push(arg);
+                }
 
                 function_stack.borrow_mut().push(Gc::clone(closure));
 
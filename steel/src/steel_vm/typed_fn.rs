@@ -0,0 +1,108 @@
+use super::engine::Engine;
+use super::thread::SteelThread;
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{FromSteelVal, IntoSteelVal, Result, SteelVal};
+
+/// The arguments of a Steel function called from Rust through
+/// [`typed_fn`](crate::steel_vm::engine::Engine::typed_fn), as a tuple of their types.
+/// `Function` is the Rust closure taking those arguments and returning an `R`.
+pub trait TypedArgs<'a, R> {
+    type Function;
+
+    fn typed(engine: &'a mut Engine, function: SteelVal) -> Self::Function;
+}
+
+macro_rules! impl_typed_args {
+    ($($param:ident $arg:ident),*) => {
+        impl<'a, $($param: IntoSteelVal + 'a,)* R: FromSteelVal + 'a> TypedArgs<'a, R>
+            for ($($param,)*)
+        {
+            type Function = Box<dyn FnMut($($param),*) -> Result<R> + 'a>;
+
+            fn typed(engine: &'a mut Engine, function: SteelVal) -> Self::Function {
+                Box::new(move |$($arg),*| {
+                    let args = vec![$($arg.into_steelval()?),*];
+                    R::from_steelval(engine.call_function(&function, args)?)
+                })
+            }
+        }
+    };
+}
+
+impl_typed_args!();
+impl_typed_args!(A a);
+impl_typed_args!(A a, B b);
+impl_typed_args!(A a, B b, C c);
+impl_typed_args!(A a, B b, C c, D d);
+impl_typed_args!(A a, B b, C c, D d, E e);
+impl_typed_args!(A a, B b, C c, D d, E e, F f);
+impl_typed_args!(A a, B b, C c, D d, E e, F f, G g);
+impl_typed_args!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+/// A Steel function handed to Rust, for native functions that take a callback. Registered
+/// functions can take one as an argument like any other type, and keep it to call back into
+/// Steel later, such as for hooks a script registers with the host. Values that aren't
+/// functions are rejected when the arguments are converted.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// # use steel::steel_vm::register_fn::RegisterFn;
+/// # use steel::rvals::SteelVal;
+/// use steel::steel_vm::typed_fn::SteelFunction;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// let hooks: Rc<RefCell<Vec<SteelFunction>>> = Rc::default();
+///
+/// let mut vm = Engine::new();
+/// let registered = hooks.clone();
+/// vm.register_fn("on-save", move |hook: SteelFunction| {
+///     registered.borrow_mut().push(hook)
+/// });
+/// vm.run("(define saved 0) (on-save (lambda (n) (set! saved (+ saved n))))").unwrap();
+/// assert!(vm.run("(on-save 10)").is_err());
+///
+/// // Later, when the host saves
+/// for hook in hooks.borrow().iter() {
+///     hook.call(&mut vm, vec![SteelVal::IntV(3)]).unwrap();
+/// }
+/// assert_eq!(vm.extract_value("saved").unwrap(), SteelVal::IntV(3));
+/// ```
+#[derive(Clone, Debug)]
+pub struct SteelFunction(SteelVal);
+
+impl SteelFunction {
+    /// Calls the function on `engine`, which should be the engine it came from
+    pub fn call(&self, engine: &mut Engine, args: Vec<SteelVal>) -> Result<SteelVal> {
+        engine.call_function(&self.0, args)
+    }
+
+    /// Calls the function from inside of a native function registered with
+    /// [`register_builtin`](crate::steel_vm::engine::Engine::register_builtin)
+    pub fn call_in(&self, thread: &mut SteelThread, args: Vec<SteelVal>) -> Result<SteelVal> {
+        thread.call_function(&self.0, args)
+    }
+
+    pub fn value(&self) -> &SteelVal {
+        &self.0
+    }
+}
+
+impl FromSteelVal for SteelFunction {
+    fn from_steelval(val: SteelVal) -> Result<Self> {
+        if val.is_function() {
+            Ok(SteelFunction(val))
+        } else {
+            stop!(ConversionError => "expected a function, found: {}", val)
+        }
+    }
+}
+
+impl IntoSteelVal for SteelFunction {
+    fn into_steelval(self) -> Result<SteelVal> {
+        Ok(self.0)
+    }
+}
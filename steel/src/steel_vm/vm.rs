@@ -5,14 +5,19 @@ use super::options::UseCallback;
 use super::options::UseCallbacks;
 use super::{
     heap::UpValueHeap,
+    interrupt::InterruptHandle,
     stack::{Stack, StackFrame},
 };
 use crate::{
     compiler::{
         constants::{ConstantMap, ConstantTable},
+        map::SymbolMap,
         program::Program,
     },
-    core::{instructions::DenseInstruction, opcode::OpCode},
+    core::{
+        instructions::DenseInstruction,
+        opcode::{unpack_super_instruction, OpCode},
+    },
     rvals::{FutureResult, UpValue},
     values::contracts::ContractedFunction,
 };
@@ -25,14 +30,15 @@ use crate::{
         parser::{ParseError, Parser},
         span::Span,
     },
-    primitives::ListOperations,
+    primitives::{block_on_future, float_modulo, int_modulo, Finalizers, ListOperations},
     rerrs::{ErrorKind, SteelErr},
-    rvals::{ByteCodeLambda, Result, SteelVal},
+    rvals::{BuiltInSignature, ByteCodeLambda, FunctionSignature, Result, SteelVal},
     stop,
     values::structs::SteelStruct,
 };
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     collections::HashMap,
     convert::TryFrom,
     iter::Iterator,
@@ -40,7 +46,18 @@ use std::{
     result,
 };
 
-use super::evaluation_progress::EvaluationProgress;
+use super::accounting::{definition_at, ExecutionStats};
+use super::budget::{Budget, HeapMeter};
+use super::debugger::Debugger;
+use super::evaluation_progress::{EvaluationProgress, PendingAwait};
+use super::instruction_stats::InstructionStats;
+use super::leaks::{LeakAuditor, LeakReport};
+use super::metrics::{GcStats, Metrics};
+use super::pin::{PinTable, Pins};
+use super::profiler::{body_span, ProfileReport, VmSnapshot};
+use super::thread::{
+    DeferredCall, NestedCall, ReturnHook, SteelThread, NESTING_LIMIT, STACK_GROWTH, STACK_RED_ZONE,
+};
 
 use log::error;
 
@@ -53,6 +70,10 @@ pub struct VirtualMachineCore {
     stack: StackFrame,
     function_stack: Vec<Gc<ByteCodeLambda>>,
     stack_index: Stack<usize>,
+    executions: usize,
+    errors: usize,
+    budget: Budget,
+    finalizers: Finalizers,
 }
 
 impl VirtualMachineCore {
@@ -64,6 +85,21 @@ impl VirtualMachineCore {
             stack: StackFrame::with_capacity(256),
             function_stack: Vec::with_capacity(64),
             stack_index: Stack::with_capacity(64),
+            executions: 0,
+            errors: 0,
+            budget: Budget::default(),
+            finalizers: Finalizers::default(),
+        }
+    }
+
+    // Shares the globals, and the upvalues closures in them capture, with this VM. Callbacks,
+    // debuggers, profilers and a suspended program stay behind.
+    pub(crate) fn fork(&self) -> VirtualMachineCore {
+        VirtualMachineCore {
+            global_env: self.global_env.fork(),
+            global_upvalue_heap: self.global_upvalue_heap.fork(),
+            budget: self.budget,
+            ..VirtualMachineCore::new()
         }
     }
 
@@ -75,10 +111,181 @@ impl VirtualMachineCore {
         self.global_env.extract(idx)
     }
 
+    pub(crate) fn mark_specialized(&mut self, idx: usize, func: FunctionSignature) {
+        self.global_env.mark_specialized(idx, func);
+    }
+
     pub fn on_progress<FN: Fn(usize) -> bool + 'static>(&mut self, callback: FN) {
         &self.callback.with_callback(Box::new(callback));
     }
 
+    pub fn on_progress_every<FN: Fn(&VmSnapshot) -> bool + 'static>(
+        &mut self,
+        interval: usize,
+        callback: FN,
+    ) {
+        self.callback
+            .with_progress_callback(interval, Box::new(callback));
+    }
+
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    // Stops programs from defining globals that are already bound, naming them with `names`
+    // when they try, or lets them again with `None`
+    pub(crate) fn forbid_redefinitions(&mut self, names: Option<SymbolMap>) {
+        self.global_env.redefinitions_forbidden = names;
+    }
+
+    pub(crate) fn pending_await_handle(&self) -> PendingAwait {
+        self.callback.pending_await_handle()
+    }
+
+    pub fn enable_instruction_stats(&mut self) {
+        self.callback.enable_instruction_stats();
+    }
+
+    pub(crate) fn debugger_mut(&mut self) -> &mut Debugger {
+        self.callback.debugger_mut()
+    }
+
+    pub(crate) fn debugging(&self) -> bool {
+        self.callback.debugging()
+    }
+
+    pub fn instruction_stats(&self) -> Option<InstructionStats> {
+        self.callback.instruction_stats()
+    }
+
+    pub(crate) fn start_profiling(&mut self) {
+        self.callback.start_profiling();
+    }
+
+    /// Stops profiling, using `names` to name the functions bound to globals
+    pub(crate) fn finish_profiling(&mut self, names: &[String]) -> Option<ProfileReport> {
+        let profiler = self.callback.take_profiler()?;
+        Some(profiler.finish(&self.function_names(names)))
+    }
+
+    // The name of the global each function is bound to, by the span of its body
+    fn function_names(&self, names: &[String]) -> HashMap<(usize, usize), String> {
+        let mut spans = HashMap::new();
+        for (i, value) in self.global_env.bindings_vec.iter().enumerate() {
+            if let (SteelVal::Closure(closure), Some(name)) = (value, names.get(i)) {
+                spans
+                    .entry(body_span(&closure.body_exp()))
+                    .or_insert_with(|| name.clone());
+            }
+        }
+        spans
+    }
+
+    pub(crate) fn enable_execution_stats(&mut self) {
+        self.callback.enable_execution_stats();
+    }
+
+    pub(crate) fn reset_execution_stats(&mut self) {
+        self.callback.reset_execution_stats();
+    }
+
+    /// What has been counted since execution stats were enabled or last reset, using `names`
+    /// to name the globals
+    pub(crate) fn execution_stats(&self, names: &[String]) -> Option<ExecutionStats> {
+        self.callback
+            .execution_accounting()
+            .map(|x| x.finish(names, &self.function_names(names)))
+    }
+
+    /// The counters for everything executed so far. Compile times are tracked by the compiler.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            executions: self.executions,
+            errors: self.errors,
+            gc_runs: self.global_upvalue_heap.collections(),
+            live_upvalues: self.global_upvalue_heap.upvalue_count(),
+            instructions_executed: self.callback.instruction_count(),
+            ..Metrics::default()
+        }
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.global_upvalue_heap.stats()
+    }
+
+    pub(crate) fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.callback.set_fuel(fuel);
+    }
+
+    pub(crate) fn fuel(&self) -> Option<u64> {
+        self.callback.fuel()
+    }
+
+    pub(crate) fn set_max_heap_bytes(&mut self, bytes: Option<usize>) {
+        self.callback.set_max_heap_bytes(bytes);
+    }
+
+    pub(crate) fn max_heap_bytes(&self) -> Option<usize> {
+        self.callback.max_heap_bytes()
+    }
+
+    pub(crate) fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle::new(self.callback.interrupt_flag())
+    }
+
+    pub(crate) fn heap_bytes(&self) -> usize {
+        measure_heap(
+            &self.stack,
+            &self.global_env,
+            &self.global_upvalue_heap,
+            &self.callback.pins().borrow(),
+        )
+        .bytes()
+    }
+
+    pub(crate) fn pins(&self) -> &PinTable {
+        self.callback.pins()
+    }
+
+    pub(crate) fn register_finalizer(
+        &mut self,
+        value: &SteelVal,
+        finalizer: Box<dyn FnOnce()>,
+    ) -> Result<()> {
+        self.finalizers.register(value, finalizer)
+    }
+
+    pub fn run_finalizers(&mut self) -> usize {
+        self.finalizers.run_collected()
+    }
+
+    /// Looks for custom values that are referenced from outside of the VM, using `names`
+    /// to describe the globals they were found in
+    pub(crate) fn leaks(&self, names: &[String]) -> LeakReport {
+        let mut auditor = LeakAuditor::default();
+
+        for (i, value) in self.global_env.bindings_vec.iter().enumerate() {
+            match names.get(i) {
+                Some(name) => auditor.visit_global(name, value),
+                None => auditor.visit_global(&format!("#{}", i), value),
+            }
+        }
+
+        for upvalue in self.global_upvalue_heap.upvalues() {
+            auditor.visit_heap_upvalue(upvalue);
+        }
+
+        for value in self.callback.pins().borrow().values() {
+            auditor.visit_pinned(value);
+        }
+
+        auditor.report()
+    }
+
     pub fn execute_program<U: UseCallbacks, A: ApplyContracts>(
         &mut self,
         program: Program,
@@ -90,18 +297,36 @@ impl VirtualMachineCore {
             constant_map,
         } = program;
 
+        self.execute_instructions(
+            instructions.into_iter().map(Ok),
+            &constant_map,
+            use_callbacks,
+            apply_contracts,
+        )
+    }
+
+    /// Runs each top level expression as `instructions` gets to it, so that only the one
+    /// being run has to be decoded at a time
+    pub(crate) fn execute_instructions<U: UseCallbacks, A: ApplyContracts>(
+        &mut self,
+        instructions: impl Iterator<Item = Result<Vec<DenseInstruction>>>,
+        constant_map: &ConstantMap,
+        use_callbacks: U,
+        apply_contracts: A,
+    ) -> Result<Vec<SteelVal>> {
         let output = instructions
-            .into_iter()
             .map(|x| {
                 self.execute(
-                    Rc::from(x.into_boxed_slice()),
-                    &constant_map,
+                    Rc::from(x?.into_boxed_slice()),
+                    constant_map,
                     use_callbacks,
                     apply_contracts,
                 )
             })
             .collect();
 
+        self.callback.finish_debugging();
+
         output
     }
 
@@ -130,6 +355,8 @@ impl VirtualMachineCore {
         use_callbacks: U,
         apply_contracts: A,
     ) -> Result<SteelVal> {
+        self.callback.start_budget(self.budget);
+
         let result = vm(
             instructions,
             &mut self.stack,
@@ -143,26 +370,151 @@ impl VirtualMachineCore {
             apply_contracts,
         );
 
+        self.executions += 1;
+        if result.is_err() {
+            self.errors += 1;
+        }
+
         // Clean up
         self.stack.clear();
         self.stack_index.clear();
         self.function_stack.clear();
+        self.callback.truncate_winders(0);
+        self.finalizers.run_collected();
+
+        result
+    }
+
+    /// Calls `function` with `args` from outside of any running code, counting as a top level execution
+    pub fn call_function<U: UseCallbacks, A: ApplyContracts>(
+        &mut self,
+        function: &SteelVal,
+        args: Vec<SteelVal>,
+        constant_map: &ConstantMap,
+        use_callbacks: U,
+        apply_contracts: A,
+    ) -> Result<SteelVal> {
+        self.callback.start_budget(self.budget);
+
+        let result = VmCore::without_code(
+            &mut self.stack,
+            &mut self.global_env,
+            constant_map,
+            &self.callback,
+            &mut self.global_upvalue_heap,
+            &mut self.function_stack,
+            &mut self.stack_index,
+            use_callbacks,
+            apply_contracts,
+        )
+        .call_nested(function, args);
+
+        self.executions += 1;
+        if result.is_err() {
+            self.errors += 1;
+        }
+
+        self.stack.clear();
+        self.stack_index.clear();
+        self.function_stack.clear();
+        self.callback.truncate_winders(0);
+        self.finalizers.run_collected();
+
+        result
+    }
+
+    /// Like `execute_program`, except that when `await` is given a future that isn't ready, the
+    /// evaluation is suspended until the future is done, rather than blocking the thread. The
+    /// futures are polled by whatever executor is driving the returned future.
+    pub async fn execute_program_async(&mut self, program: Program) -> Result<Vec<SteelVal>> {
+        let Program {
+            instructions,
+            constant_map,
+        } = program;
+
+        let mut results = Vec::with_capacity(instructions.len());
+        for x in instructions {
+            let result = self
+                .execute_async(Rc::from(x.into_boxed_slice()), &constant_map)
+                .await?;
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    async fn execute_async(
+        &mut self,
+        instructions: Rc<[DenseInstruction]>,
+        constant_map: &ConstantMap,
+    ) -> Result<SteelVal> {
+        self.callback.start_budget(self.budget);
+
+        let mut result = self
+            .suspendable_core(instructions, constant_map)
+            .and_then(|core| core.vm());
+
+        while let Some(suspension) = self.callback.take_suspension() {
+            result = match suspension.future.clone().into_shared().await {
+                Ok(value) => self
+                    .suspendable_core(
+                        Rc::clone(&suspension.continuation.instructions),
+                        constant_map,
+                    )
+                    .and_then(|core| core.resume(suspension, value)),
+                Err(e) => Err(e.set_span(suspension.span)),
+            };
+        }
+
+        self.executions += 1;
+        if result.is_err() {
+            self.errors += 1;
+        }
+
+        self.stack.clear();
+        self.stack_index.clear();
+        self.function_stack.clear();
+        self.callback.truncate_winders(0);
+        self.finalizers.run_collected();
 
         result
     }
+
+    fn suspendable_core<'a>(
+        &'a mut self,
+        instructions: Rc<[DenseInstruction]>,
+        constant_map: &'a ConstantMap,
+    ) -> Result<VmCore<'a, ConstantMap, UseCallback, ApplyContract>> {
+        let mut core = VmCore::new(
+            instructions,
+            &mut self.stack,
+            &mut self.global_env,
+            constant_map,
+            &self.callback,
+            &mut self.global_upvalue_heap,
+            &mut self.function_stack,
+            &mut self.stack_index,
+            UseCallback,
+            ApplyContract,
+        )?;
+        core.suspendable = true;
+        Ok(core)
+    }
 }
 
+// Where to return to once a function is done, along with what to do with its result for calls
+// deferred by native functions
 #[derive(Debug, Clone)]
-pub struct InstructionPointer(usize, Rc<[DenseInstruction]>);
+pub struct InstructionPointer(usize, Rc<[DenseInstruction]>, Option<ReturnHook>);
 
 impl InstructionPointer {
     pub fn _new_raw() -> Self {
-        InstructionPointer(0, Rc::from(Vec::new().into_boxed_slice()))
+        InstructionPointer(0, Rc::from(Vec::new().into_boxed_slice()), None)
     }
 
     #[inline(always)]
     pub fn new(ip: usize, instrs: Rc<[DenseInstruction]>) -> Self {
-        InstructionPointer(ip, instrs)
+        InstructionPointer(ip, instrs, None)
     }
 
     pub fn instrs_ref(&self) -> &Rc<[DenseInstruction]> {
@@ -183,8 +535,40 @@ pub struct Continuation {
     stack_index: Stack<usize>,
     ip: usize,
     pop_count: usize,
-    function_stack: Vec<Gc<ByteCodeLambda>>,
+    pub(crate) function_stack: Vec<Gc<ByteCodeLambda>>,
     upvalue_head: Option<Weak<RefCell<UpValue>>>,
+    winders: Vec<Winder>,
+}
+
+/// The `before` and `after` thunks of a `dynamic-wind` that is running. Continuations that jump
+/// out of it run `after`, and ones that jump back into it run `before` again.
+#[derive(Clone, Debug)]
+pub(crate) struct Winder(Rc<(SteelVal, SteelVal)>);
+
+impl Winder {
+    pub(crate) fn new(before: SteelVal, after: SteelVal) -> Self {
+        Winder(Rc::new((before, after)))
+    }
+
+    fn before(&self) -> &SteelVal {
+        &self.0 .0
+    }
+
+    fn after(&self) -> &SteelVal {
+        &self.0 .1
+    }
+
+    // The same `dynamic-wind`, rather than one with the same thunks
+    fn same(&self, other: &Winder) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// An evaluation that stopped to wait on a future, to be resumed with its value
+pub(crate) struct Suspension {
+    continuation: Continuation,
+    future: FutureResult,
+    span: Span,
 }
 
 #[inline(always)]
@@ -204,6 +588,41 @@ fn validate_closure_for_call_cc(function: &SteelVal, span: Span) -> Result<()> {
     Ok(())
 }
 
+// Anything that holds on to a value, like a variable or an argument, needs exactly one
+#[inline(always)]
+fn single_value(value: &SteelVal, span: &Span) -> Result<()> {
+    if let SteelVal::Values(values) = value {
+        stop!(ArityMismatch => "expected a single value, found {} values", values.len(); *span);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "threaded_dispatch")]
+use crate::core::opcode::OPCODE_COUNT;
+
+#[cfg(feature = "threaded_dispatch")]
+type OpHandler<'a, CT, U, A> =
+    fn(&mut VmCore<'a, CT, U, A>, DenseInstruction) -> Result<Option<SteelVal>>;
+
+// Estimates the bytes held by everything a VM can reach, see `HeapMeter`
+fn measure_heap(
+    stack: &[SteelVal],
+    global_env: &Env,
+    upvalue_heap: &UpValueHeap,
+    pins: &Pins,
+) -> HeapMeter {
+    let mut meter = HeapMeter::default();
+    meter.add_values(
+        stack
+            .iter()
+            .chain(global_env.bindings_vec.iter())
+            .chain(pins.values()),
+    );
+    meter.add_upvalues(upvalue_heap.upvalues());
+    meter
+}
+
 pub(crate) struct VmCore<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> {
     pub(crate) instructions: Rc<[DenseInstruction]>,
     pub(crate) stack: &'a mut StackFrame,
@@ -219,9 +638,31 @@ pub(crate) struct VmCore<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContrac
     pub(crate) function_stack: &'a mut Vec<Gc<ByteCodeLambda>>,
     pub(crate) use_callbacks: U,
     pub(crate) apply_contracts: A,
+    // Only the outermost VM can stop and pick back up later, nested ones are
+    // called from rust and have to wait on futures where they are
+    pub(crate) suspendable: bool,
+    // How many native functions deep this VM was started from
+    nesting: usize,
+    // The call the native function that is running asked to have made in its place
+    deferred: Option<DeferredCall>,
 }
 
 impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U, A> {
+    // Stops evaluation if the heap has grown past `limit`, otherwise schedules the next check
+    fn check_heap(&self, limit: usize, span: Span) -> Result<()> {
+        let meter = measure_heap(
+            self.stack,
+            self.global_env,
+            self.upvalue_heap,
+            &self.callback.pins().borrow(),
+        );
+        if meter.bytes() > limit {
+            stop!(ResourceExhausted => "evaluation exceeded its limit of {} bytes of memory", limit; span);
+        }
+        self.callback.heap_checked(&meter, limit);
+        Ok(())
+    }
+
     fn new(
         instructions: Rc<[DenseInstruction]>,
         stack: &'a mut StackFrame,
@@ -238,8 +679,36 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             stop!(Generic => "empty stack!")
         }
 
-        Ok(VmCore {
-            instructions: Rc::clone(&instructions),
+        let mut core = Self::without_code(
+            stack,
+            global_env,
+            constants,
+            callback,
+            upvalue_heap,
+            function_stack,
+            stack_index,
+            use_callbacks,
+            apply_contracts,
+        );
+        core.instructions = instructions;
+        Ok(core)
+    }
+
+    // A VM that isn't running anything yet, for making calls from outside of the VM
+    #[allow(clippy::too_many_arguments)]
+    fn without_code(
+        stack: &'a mut StackFrame,
+        global_env: &'a mut Env,
+        constants: &'a CT,
+        callback: &'a EvaluationProgress,
+        upvalue_heap: &'a mut UpValueHeap,
+        function_stack: &'a mut Vec<Gc<ByteCodeLambda>>,
+        stack_index: &'a mut Stack<usize>,
+        use_callbacks: U,
+        apply_contracts: A,
+    ) -> VmCore<'a, CT, U, A> {
+        VmCore {
+            instructions: Rc::from(Vec::new().into_boxed_slice()),
             stack,
             global_env,
             instruction_stack: Stack::new(),
@@ -253,7 +722,10 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             function_stack,
             use_callbacks,
             apply_contracts,
-        })
+            suspendable: false,
+            nesting: 0,
+            deferred: None,
+        }
     }
 
     fn capture_upvalue(&mut self, local_idx: usize) -> Weak<RefCell<UpValue>> {
@@ -351,6 +823,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             pop_count: self.pop_count,
             function_stack: self.function_stack.clone(),
             upvalue_head: self.upvalue_head.clone(),
+            winders: self.callback.winders(),
         }
     }
 
@@ -364,6 +837,30 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         *self.stack_index = continuation.stack_index;
         *self.function_stack = continuation.function_stack;
         self.upvalue_head = continuation.upvalue_head;
+        self.callback.set_winders(continuation.winders);
+    }
+
+    // Leaves the `dynamic-wind`s that `target` isn't in, innermost first, then enters the ones
+    // that only `target` is in, outermost first
+    fn wind_to(&mut self, target: &[Winder]) -> Result<()> {
+        let current = self.callback.winders();
+        let shared = current
+            .iter()
+            .zip(target)
+            .take_while(|(x, y)| x.same(y))
+            .count();
+
+        for winder in current[shared..].iter().rev() {
+            self.callback.pop_winder();
+            self.call_nested(winder.after(), Vec::new())?;
+        }
+
+        for winder in &target[shared..] {
+            self.call_nested(winder.before(), Vec::new())?;
+            self.callback.push_winder(winder.clone());
+        }
+
+        Ok(())
     }
 
     #[inline(always)]
@@ -372,230 +869,751 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         SteelVal::ContinuationFunction(Gc::new(captured_continuation))
     }
 
+    // `await` leaves a placeholder on the stack when its future isn't ready, which
+    // gets swapped out for the value once the future is done
+    fn suspend(&mut self, future: FutureResult, span: Span) {
+        self.stack.pop();
+        let continuation = self.new_continuation_from_state();
+        self.callback.suspend(Suspension {
+            continuation,
+            future,
+            span,
+        });
+    }
+
+    fn wait_on(&mut self, future: FutureResult, span: Span) -> Result<()> {
+        self.stack.pop();
+        let value = block_on_future(future).map_err(|x| x.set_span(span))?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn resume(mut self, suspension: Suspension, value: SteelVal) -> Result<SteelVal> {
+        self.set_state_from_continuation(suspension.continuation);
+        self.stack.push(value);
+        self.vm()
+    }
+
+    #[cfg(not(feature = "threaded_dispatch"))]
     fn vm(mut self) -> Result<SteelVal> {
         let mut cur_inst;
 
         while self.ip < self.instructions.len() {
-            cur_inst = self.instructions[self.ip];
+            cur_inst = self.deoptimize_super_instruction(self.instructions[self.ip]);
+
+            if self.use_callbacks.use_callbacks() && self.callback.collecting_stats() {
+                self.record_instruction(cur_inst);
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.debugging() {
+                self.check_debugger(cur_inst.span);
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.profiling() {
+                self.record_profile();
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.accounting() {
+                self.record_execution();
+            }
 
             match cur_inst.op_code {
                 OpCode::PANIC => self.handle_panic(cur_inst.span)?,
-                OpCode::EVAL => {
-                    let _expr_to_eval = self.stack.pop().unwrap();
-                    panic!("eval not yet supported - internal compiler error");
-                }
-                OpCode::PASS => {
-                    println!("Hitting a pass - this shouldn't happen");
-                    self.ip += 1;
-                }
-                OpCode::VOID => {
-                    self.stack.push(SteelVal::Void);
-                    self.ip += 1;
-                }
+                OpCode::EVAL => self.handle_eval(),
+                OpCode::PASS => self.handle_pass(),
+                OpCode::VOID => self.handle_void(),
                 OpCode::STRUCT => {
                     // For now, only allow structs at the top level
                     // In the future, allow structs to be also available in a nested scope
                     self.handle_struct(cur_inst.payload_size as usize)?;
-                    self.stack.push(SteelVal::Void);
-                    self.ip += 1;
-                    // return Ok(SteelVal::Void);
-                }
-                OpCode::INNERSTRUCT => {
-                    self.handle_inner_struct(cur_inst.payload_size as usize)?;
-                    self.stack.push(SteelVal::Void);
-                    self.ip += 1;
-                }
-                OpCode::CALLCC => {
-                    /*
-                    - Construct the continuation
-                    - Get the function that has been passed in (off the stack)
-                    - Apply the function with the continuation
-                    - Handle continuation function call separately in the handle_func_call
-                    */
-                    let function = self.stack.pop().unwrap();
-
-                    validate_closure_for_call_cc(&function, cur_inst.span)?;
-
-                    let continuation = self.construct_continuation_function();
-
-                    match function {
-                        SteelVal::Closure(closure) => {
-                            if self.stack_index.len() == STACK_LIMIT {
-                                println!("stack frame at exit: {:?}", self.stack);
-                                stop!(Generic => "stack overflowed!"; cur_inst.span);
-                            }
-
-                            if closure.arity() != 1 {
-                                stop!(Generic => "call/cc expects a function with arity 1");
-                            }
-
-                            self.stack_index.push(self.stack.len());
-
-                            // Put the continuation as the argument
-                            self.stack.push(continuation);
-
-                            // self.global_env = inner_env;
-                            self.instruction_stack.push(InstructionPointer::new(
-                                self.ip + 1,
-                                Rc::clone(&self.instructions),
-                            ));
-                            self.pop_count += 1;
-
-                            self.instructions = closure.body_exp();
-                            self.function_stack.push(closure);
-
-                            self.ip = 0;
-                        }
-                        SteelVal::ContinuationFunction(cc) => {
-                            self.set_state_from_continuation(cc.unwrap());
-                            self.ip += 1;
-                            self.stack.push(continuation);
-                        }
-
-                        _ => {
-                            stop!(Generic => "call/cc expects a function");
-                        }
-                    }
                 }
+                OpCode::INNERSTRUCT => self.handle_inner_struct(cur_inst.payload_size as usize)?,
+                OpCode::CALLCC => self.handle_call_cc(cur_inst.span)?,
                 OpCode::READ => self.handle_read(&cur_inst.span)?,
                 OpCode::COLLECT => self.handle_collect(&cur_inst.span)?,
                 OpCode::COLLECTTO => self.handle_collect_to(&cur_inst.span)?,
                 OpCode::TRANSDUCE => self.handle_transduce(&cur_inst.span)?,
                 OpCode::SET => self.handle_set(cur_inst.payload_size as usize)?,
-                OpCode::PUSHCONST => {
-                    let val = self.constants.get(cur_inst.payload_size as usize);
-                    self.stack.push(val);
-                    self.ip += 1;
-                }
+                OpCode::PUSHCONST => self.handle_push_const(cur_inst.payload_size as usize),
                 OpCode::PUSH => self.handle_push(cur_inst.payload_size as usize)?,
                 OpCode::READLOCAL => self.handle_local(cur_inst.payload_size as usize)?,
                 OpCode::SETLOCAL => self.handle_set_local(cur_inst.payload_size as usize),
                 OpCode::READUPVALUE => self.handle_upvalue(cur_inst.payload_size as usize),
                 OpCode::SETUPVALUE => self.handle_set_upvalue(cur_inst.payload_size as usize),
-                OpCode::APPLY => self.handle_apply(cur_inst.span)?,
-                OpCode::CLEAR => {
-                    self.ip += 1;
+                OpCode::APPLY => {
+                    self.handle_apply(cur_inst.payload_size as usize, cur_inst.span, false)?
                 }
-                OpCode::LOADINT1 => {
-                    self.stack.push(SteelVal::INT_ONE);
-                    self.ip += 1;
+                OpCode::TAILAPPLY => {
+                    self.handle_apply(cur_inst.payload_size as usize, cur_inst.span, true)?
                 }
-                OpCode::LOADINT2 => {
-                    self.stack.push(SteelVal::INT_TWO);
-                    self.ip += 1;
+                OpCode::CLEAR => self.handle_clear(),
+                OpCode::LOADINT1 => self.handle_load_int(SteelVal::INT_ONE),
+                OpCode::LOADINT2 => self.handle_load_int(SteelVal::INT_TWO),
+                OpCode::CGLOCALCONST => self.handle_call_global_local_const(cur_inst)?,
+                OpCode::CALLGLOBAL => self.handle_call_global_instruction(cur_inst)?,
+                OpCode::LT
+                | OpCode::LTE
+                | OpCode::GT
+                | OpCode::GTE
+                | OpCode::MOD
+                | OpCode::REMAINDER
+                | OpCode::ABS
+                | OpCode::MIN
+                | OpCode::MAX => self.handle_specialized_instruction(cur_inst)?,
+                OpCode::BRANCHLT
+                | OpCode::BRANCHLTE
+                | OpCode::BRANCHGT
+                | OpCode::BRANCHGTE
+                | OpCode::BRANCHEQ => self.handle_branch_instruction(cur_inst)?,
+                OpCode::CALLGLOBALTAIL => self.handle_tail_call_global_instruction(cur_inst)?,
+                OpCode::SUPER => self.handle_super_instruction(cur_inst)?,
+                OpCode::FUNC => {
+                    let func = self.stack.pop().unwrap();
+                    self.handle_function_call(
+                        func,
+                        cur_inst.payload_size as usize,
+                        &cur_inst.span,
+                    )?;
+                }
+                // Tail call basically says "hey this function is exiting"
+                // In the closure case, transfer ownership of the stack to the called function
+                OpCode::TAILCALL => {
+                    let func = self.stack.pop().unwrap();
+                    self.handle_tail_call(func, cur_inst.payload_size as usize, &cur_inst.span)?
+                }
+                OpCode::IF => self.handle_if(cur_inst.payload_size as usize),
+                OpCode::TCOJMP => self.handle_tco_jump(cur_inst.payload_size as usize)?,
+                OpCode::JMP => self.handle_jump(cur_inst.payload_size as usize),
+                OpCode::POP => {
+                    if let Some(r) = self.handle_pop(cur_inst.payload_size, &cur_inst.span) {
+                        return r;
+                    }
+                }
+                OpCode::BIND => self.handle_bind(cur_inst.payload_size as usize, cur_inst.span)?,
+                OpCode::SCLOSURE => self.handle_start_closure(cur_inst.payload_size as usize),
+                OpCode::SDEF => self.handle_start_def(),
+                OpCode::EDEF => self.handle_end_def(),
+                _ => self.handle_unknown_opcode(cur_inst),
+            }
+
+            // Put callbacks behind generic
+            if self.use_callbacks.use_callbacks() {
+                match self.callback.call_and_increment() {
+                    Some(b) if !b => stop!(Generic => "Callback forced quit of function!"),
+                    _ => {}
+                }
+
+                if self.callback.progress_due() && !self.report_progress() {
+                    stop!(Generic => "Callback forced quit of function!");
+                }
+
+                if self.callback.take_interrupt() {
+                    stop!(Interrupted => "evaluation was interrupted"; cur_inst.span);
+                }
+
+                if let Some(message) = self.callback.budget_exceeded() {
+                    stop!(ResourceExhausted => message; cur_inst.span);
+                }
+
+                if let Some(limit) = self.callback.heap_check_due() {
+                    self.check_heap(limit, cur_inst.span)?;
+                }
+            }
+
+            if let Some(future) = self.callback.take_pending_await() {
+                if self.suspendable {
+                    self.suspend(future, cur_inst.span);
+                    return Ok(SteelVal::Void);
+                }
+                self.wait_on(future, cur_inst.span)?;
+            }
+        }
+
+        self.out_of_bounds_instruction()
+    }
+
+    // Direct threaded version of the main loop - rather than matching on the op code,
+    // index into a table of handlers and jump straight to the function for that instruction
+    #[cfg(feature = "threaded_dispatch")]
+    fn vm(mut self) -> Result<SteelVal> {
+        while self.ip < self.instructions.len() {
+            let cur_inst = self.deoptimize_super_instruction(self.instructions[self.ip]);
+
+            if self.use_callbacks.use_callbacks() && self.callback.collecting_stats() {
+                self.record_instruction(cur_inst);
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.debugging() {
+                self.check_debugger(cur_inst.span);
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.profiling() {
+                self.record_profile();
+            }
+
+            if self.use_callbacks.use_callbacks() && self.callback.accounting() {
+                self.record_execution();
+            }
+
+            if let Some(r) = Self::DISPATCH_TABLE[cur_inst.op_code as usize](&mut self, cur_inst)? {
+                return Ok(r);
+            }
+
+            // Put callbacks behind generic
+            if self.use_callbacks.use_callbacks() {
+                match self.callback.call_and_increment() {
+                    Some(b) if !b => stop!(Generic => "Callback forced quit of function!"),
+                    _ => {}
+                }
+
+                if self.callback.progress_due() && !self.report_progress() {
+                    stop!(Generic => "Callback forced quit of function!");
+                }
+
+                if self.callback.take_interrupt() {
+                    stop!(Interrupted => "evaluation was interrupted"; cur_inst.span);
+                }
+
+                if let Some(message) = self.callback.budget_exceeded() {
+                    stop!(ResourceExhausted => message; cur_inst.span);
+                }
+
+                if let Some(limit) = self.callback.heap_check_due() {
+                    self.check_heap(limit, cur_inst.span)?;
+                }
+            }
+
+            if let Some(future) = self.callback.take_pending_await() {
+                if self.suspendable {
+                    self.suspend(future, cur_inst.span);
+                    return Ok(SteelVal::Void);
+                }
+                self.wait_on(future, cur_inst.span)?;
+            }
+        }
+
+        self.out_of_bounds_instruction()
+    }
+
+    #[cfg(feature = "threaded_dispatch")]
+    const DISPATCH_TABLE: [OpHandler<'a, CT, U, A>; OPCODE_COUNT] = {
+        let mut table: [OpHandler<'a, CT, U, A>; OPCODE_COUNT] =
+            [|vm, inst| Ok(vm.handle_unknown_opcode(inst)).map(|_| None); OPCODE_COUNT];
+
+        table[OpCode::PANIC as usize] = |vm, inst| vm.handle_panic(inst.span).map(|_| None);
+        table[OpCode::EVAL as usize] = |vm, _| Ok(vm.handle_eval()).map(|_| None);
+        table[OpCode::PASS as usize] = |vm, _| Ok(vm.handle_pass()).map(|_| None);
+        table[OpCode::VOID as usize] = |vm, _| Ok(vm.handle_void()).map(|_| None);
+        table[OpCode::STRUCT as usize] =
+            |vm, inst| vm.handle_struct(inst.payload_size as usize).map(|_| None);
+        table[OpCode::INNERSTRUCT as usize] = |vm, inst| {
+            vm.handle_inner_struct(inst.payload_size as usize)
+                .map(|_| None)
+        };
+        table[OpCode::CALLCC as usize] = |vm, inst| vm.handle_call_cc(inst.span).map(|_| None);
+        table[OpCode::READ as usize] = |vm, inst| vm.handle_read(&inst.span).map(|_| None);
+        table[OpCode::COLLECT as usize] = |vm, inst| vm.handle_collect(&inst.span).map(|_| None);
+        table[OpCode::COLLECTTO as usize] =
+            |vm, inst| vm.handle_collect_to(&inst.span).map(|_| None);
+        table[OpCode::TRANSDUCE as usize] =
+            |vm, inst| vm.handle_transduce(&inst.span).map(|_| None);
+        table[OpCode::SET as usize] =
+            |vm, inst| vm.handle_set(inst.payload_size as usize).map(|_| None);
+        table[OpCode::PUSHCONST as usize] =
+            |vm, inst| Ok(vm.handle_push_const(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::PUSH as usize] =
+            |vm, inst| vm.handle_push(inst.payload_size as usize).map(|_| None);
+        table[OpCode::READLOCAL as usize] =
+            |vm, inst| vm.handle_local(inst.payload_size as usize).map(|_| None);
+        table[OpCode::SETLOCAL as usize] =
+            |vm, inst| Ok(vm.handle_set_local(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::READUPVALUE as usize] =
+            |vm, inst| Ok(vm.handle_upvalue(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::SETUPVALUE as usize] =
+            |vm, inst| Ok(vm.handle_set_upvalue(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::APPLY as usize] = |vm, inst| {
+            vm.handle_apply(inst.payload_size as usize, inst.span, false)
+                .map(|_| None)
+        };
+        table[OpCode::TAILAPPLY as usize] = |vm, inst| {
+            vm.handle_apply(inst.payload_size as usize, inst.span, true)
+                .map(|_| None)
+        };
+        table[OpCode::CLEAR as usize] = |vm, _| Ok(vm.handle_clear()).map(|_| None);
+        table[OpCode::LOADINT1 as usize] =
+            |vm, _| Ok(vm.handle_load_int(SteelVal::INT_ONE)).map(|_| None);
+        table[OpCode::LOADINT2 as usize] =
+            |vm, _| Ok(vm.handle_load_int(SteelVal::INT_TWO)).map(|_| None);
+        table[OpCode::CGLOCALCONST as usize] =
+            |vm, inst| vm.handle_call_global_local_const(inst).map(|_| None);
+        table[OpCode::CALLGLOBAL as usize] =
+            |vm, inst| vm.handle_call_global_instruction(inst).map(|_| None);
+        table[OpCode::CALLGLOBALTAIL as usize] =
+            |vm, inst| vm.handle_tail_call_global_instruction(inst).map(|_| None);
+
+        let specialized: OpHandler<'a, CT, U, A> =
+            |vm, inst| vm.handle_specialized_instruction(inst).map(|_| None);
+        table[OpCode::LT as usize] = specialized;
+        table[OpCode::LTE as usize] = specialized;
+        table[OpCode::GT as usize] = specialized;
+        table[OpCode::GTE as usize] = specialized;
+        table[OpCode::MOD as usize] = specialized;
+        table[OpCode::REMAINDER as usize] = specialized;
+        table[OpCode::ABS as usize] = specialized;
+        table[OpCode::MIN as usize] = specialized;
+        table[OpCode::MAX as usize] = specialized;
+
+        let branch: OpHandler<'a, CT, U, A> =
+            |vm, inst| vm.handle_branch_instruction(inst).map(|_| None);
+        table[OpCode::BRANCHLT as usize] = branch;
+        table[OpCode::BRANCHLTE as usize] = branch;
+        table[OpCode::BRANCHGT as usize] = branch;
+        table[OpCode::BRANCHGTE as usize] = branch;
+        table[OpCode::BRANCHEQ as usize] = branch;
+
+        table[OpCode::SUPER as usize] = |vm, inst| vm.handle_super_instruction(inst).map(|_| None);
+
+        table[OpCode::FUNC as usize] = |vm, inst| {
+            let func = vm.stack.pop().unwrap();
+            vm.handle_function_call(func, inst.payload_size as usize, &inst.span)
+                .map(|_| None)
+        };
+        table[OpCode::TAILCALL as usize] = |vm, inst| {
+            let func = vm.stack.pop().unwrap();
+            vm.handle_tail_call(func, inst.payload_size as usize, &inst.span)
+                .map(|_| None)
+        };
+        table[OpCode::IF as usize] =
+            |vm, inst| Ok(vm.handle_if(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::TCOJMP as usize] =
+            |vm, inst| vm.handle_tco_jump(inst.payload_size as usize).map(|_| None);
+        table[OpCode::JMP as usize] =
+            |vm, inst| Ok(vm.handle_jump(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::POP as usize] = |vm, inst| match vm.handle_pop(inst.payload_size, &inst.span)
+        {
+            Some(r) => r.map(Some),
+            None => Ok(None),
+        };
+        table[OpCode::BIND as usize] = |vm, inst| {
+            vm.handle_bind(inst.payload_size as usize, inst.span)
+                .map(|_| None)
+        };
+        table[OpCode::SCLOSURE as usize] =
+            |vm, inst| Ok(vm.handle_start_closure(inst.payload_size as usize)).map(|_| None);
+        table[OpCode::SDEF as usize] = |vm, _| Ok(vm.handle_start_def()).map(|_| None);
+        table[OpCode::EDEF as usize] = |vm, _| Ok(vm.handle_end_def()).map(|_| None);
+
+        table
+    };
+
+    fn out_of_bounds_instruction(&self) -> Result<SteelVal> {
+        error!(
+            "Out of bounds instruction!: instruction pointer: {}, instruction length: {}",
+            self.ip,
+            self.instructions.len()
+        );
+        panic!("Out of bounds instruction")
+    }
+
+    fn handle_unknown_opcode(&self, cur_inst: DenseInstruction) {
+        // crate::core::instructions::pretty_print_dense_instructions(&self.instructions);
+        panic!("Unhandled opcode: {:?} @ {}", cur_inst.op_code, self.ip);
+    }
+
+    #[inline(always)]
+    fn handle_eval(&mut self) {
+        let _expr_to_eval = self.stack.pop().unwrap();
+        panic!("eval not yet supported - internal compiler error");
+    }
+
+    #[inline(always)]
+    fn handle_pass(&mut self) {
+        println!("Hitting a pass - this shouldn't happen");
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn handle_void(&mut self) {
+        self.stack.push(SteelVal::Void);
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn handle_clear(&mut self) {
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn handle_load_int(&mut self, value: SteelVal) {
+        self.stack.push(value);
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn handle_push_const(&mut self, index: usize) {
+        let val = self.constants.get(index);
+        self.stack.push(val);
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn handle_call_cc(&mut self, span: Span) -> Result<()> {
+        /*
+        - Construct the continuation
+        - Get the function that has been passed in (off the stack)
+        - Apply the function with the continuation
+        - Handle continuation function call separately in the handle_func_call
+        */
+        let function = self.stack.pop().unwrap();
+
+        validate_closure_for_call_cc(&function, span)?;
+
+        let continuation = self.construct_continuation_function();
+
+        match function {
+            SteelVal::Closure(closure) => {
+                if self.stack_index.len() == STACK_LIMIT {
+                    println!("stack frame at exit: {:?}", self.stack);
+                    stop!(Generic => "stack overflowed!"; span);
+                }
+
+                if closure.arity() != 1 {
+                    stop!(Generic => "call/cc expects a function with arity 1");
                 }
-                OpCode::CGLOCALCONST => {
-                    let read_local = self.instructions[self.ip + 1];
-                    let push_const = self.instructions[self.ip + 2];
 
-                    // Snag the function
-                    let func = self
-                        .global_env
-                        .repl_lookup_idx(cur_inst.payload_size as usize)?;
+                self.stack_index.push(self.stack.len());
+
+                // Put the continuation as the argument
+                self.stack.push(continuation);
+
+                // self.global_env = inner_env;
+                self.instruction_stack.push(InstructionPointer::new(
+                    self.ip + 1,
+                    Rc::clone(&self.instructions),
+                ));
+                self.pop_count += 1;
+
+                self.instructions = closure.body_exp();
+                self.function_stack.push(closure);
+
+                self.ip = 0;
+            }
+            SteelVal::ContinuationFunction(cc) => {
+                self.wind_to(&cc.winders)?;
+                self.set_state_from_continuation(cc.unwrap());
+                self.ip += 1;
+                self.stack.push(continuation);
+            }
+
+            _ => {
+                stop!(Generic => "call/cc expects a function");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn handle_call_global_local_const(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let read_local = self.instructions[self.ip + 1];
+        let push_const = self.instructions[self.ip + 2];
+
+        // Snag the function
+        let func = self
+            .global_env
+            .repl_lookup_idx(cur_inst.payload_size as usize)?;
+
+        // get the local
+        let offset = self.stack_index.last().copied().unwrap_or(0);
+        let local_value = self.stack[read_local.payload_size as usize + offset].clone();
+
+        // get the const
+        let const_val = self.constants.get(push_const.payload_size as usize);
+
+        self.handle_lazy_function_call(func, local_value, const_val, &cur_inst.span)
+    }
+
+    #[inline(always)]
+    fn handle_call_global_instruction(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let next_inst = self.instructions[self.ip + 1];
+        self.handle_call_global(
+            cur_inst.payload_size as usize,
+            next_inst.payload_size as usize,
+            &next_inst.span,
+        )
+    }
+
+    #[inline(always)]
+    fn handle_tail_call_global_instruction(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let next_inst = self.instructions[self.ip + 1];
+        self.handle_tail_call_global(
+            cur_inst.payload_size as usize,
+            next_inst.payload_size as usize,
+            &next_inst.span,
+        )
+    }
+
+    #[inline(always)]
+    fn handle_specialized_instruction(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let next_inst = self.instructions[self.ip + 1];
+        self.handle_specialized_call(
+            cur_inst.op_code,
+            cur_inst.payload_size as usize,
+            next_inst.payload_size as usize,
+            &next_inst.span,
+        )
+    }
+
+    #[inline(always)]
+    fn handle_super_instruction(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let (op_code, payload) = unpack_super_instruction(cur_inst.payload_size);
+        self.handle_fused_instruction(DenseInstruction {
+            op_code,
+            payload_size: payload,
+            ..cur_inst
+        })?;
+
+        // With callbacks on, the second instruction is left to the loop so that it still gets
+        // counted, checked for breakpoints and so on. The same goes for a second instruction
+        // that can't be fused, since the instruction pointer is already on it
+        if !self.use_callbacks.use_callbacks() {
+            let next_inst = self.instructions[self.ip];
+            self.handle_fused_instruction(next_inst)?;
+        }
+
+        Ok(())
+    }
+
+    // Bytecode can hold super instructions the VM doesn't run as a pair, for instance when it was
+    // compiled with a different set of patterns. Only the first instruction of a pair is rewritten,
+    // so those go back to the original instruction, and the rest of the sequence runs as it was
+    #[inline(always)]
+    fn deoptimize_super_instruction(&self, cur_inst: DenseInstruction) -> DenseInstruction {
+        if cur_inst.op_code != OpCode::SUPER {
+            return cur_inst;
+        }
+
+        let (op_code, payload_size) = unpack_super_instruction(cur_inst.payload_size);
+        let fuses = op_code.fuses_first() && self.ip + 1 < self.instructions.len();
+
+        if fuses {
+            cur_inst
+        } else {
+            DenseInstruction {
+                op_code,
+                payload_size,
+                ..cur_inst
+            }
+        }
+    }
+
+    // Runs the instructions that `OpCode::fuses_first` and `OpCode::fuses_second` allow in a
+    // super instruction, and returns false without doing anything for the rest
+    #[inline(always)]
+    fn handle_fused_instruction(&mut self, inst: DenseInstruction) -> Result<bool> {
+        let payload = inst.payload_size as usize;
+        match inst.op_code {
+            OpCode::PUSH => self.handle_push(payload)?,
+            OpCode::PUSHCONST => self.handle_push_const(payload),
+            OpCode::READLOCAL => self.handle_local(payload)?,
+            OpCode::SETLOCAL => self.handle_set_local(payload),
+            OpCode::READUPVALUE => self.handle_upvalue(payload),
+            OpCode::SETUPVALUE => self.handle_set_upvalue(payload),
+            OpCode::SET => self.handle_set(payload)?,
+            OpCode::BIND => self.handle_bind(payload, inst.span)?,
+            OpCode::SDEF => self.handle_start_def(),
+            OpCode::EDEF => self.handle_end_def(),
+            OpCode::CLEAR => self.handle_clear(),
+            OpCode::LOADINT1 => self.handle_load_int(SteelVal::INT_ONE),
+            OpCode::LOADINT2 => self.handle_load_int(SteelVal::INT_TWO),
+            OpCode::VOID => self.handle_void(),
+            OpCode::IF => self.handle_if(payload),
+            OpCode::JMP => self.handle_jump(payload),
+            OpCode::TCOJMP => self.handle_tco_jump(payload)?,
+            OpCode::SCLOSURE => self.handle_start_closure(payload),
+            OpCode::LT
+            | OpCode::LTE
+            | OpCode::GT
+            | OpCode::GTE
+            | OpCode::MOD
+            | OpCode::REMAINDER
+            | OpCode::ABS
+            | OpCode::MIN
+            | OpCode::MAX => self.handle_specialized_instruction(inst)?,
+            OpCode::BRANCHLT
+            | OpCode::BRANCHLTE
+            | OpCode::BRANCHGT
+            | OpCode::BRANCHGTE
+            | OpCode::BRANCHEQ => self.handle_branch_instruction(inst)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    #[inline(always)]
+    fn handle_branch_instruction(&mut self, cur_inst: DenseInstruction) -> Result<()> {
+        let next_inst = self.instructions[self.ip + 1];
+        self.handle_specialized_branch(
+            cur_inst.op_code,
+            cur_inst.payload_size as usize,
+            &next_inst.span,
+        )
+    }
+
+    // Whether the instructions being executed belong to a function, rather than a top level expression
+    fn in_function(&self) -> bool {
+        self.function_stack
+            .last()
+            .map(|x| Rc::ptr_eq(&x.body_exp(), &self.instructions))
+            .unwrap_or(false)
+    }
+
+    #[cold]
+    fn record_instruction(&self, cur_inst: DenseInstruction) {
+        // Super instructions are counted as the instructions they're made of
+        let op_code = match cur_inst.op_code {
+            OpCode::SUPER => unpack_super_instruction(cur_inst.payload_size).0,
+            op_code => op_code,
+        };
+
+        self.callback
+            .record_instruction(&self.instructions, self.in_function(), self.ip, op_code);
+    }
+
+    #[cold]
+    fn record_profile(&self) {
+        self.callback
+            .record_profile(&self.instructions, self.in_function(), self.function_stack);
+    }
+
+    #[cold]
+    fn report_progress(&self) -> bool {
+        let function = if self.in_function() {
+            Some(body_span(&self.instructions))
+        } else {
+            None
+        };
+        self.callback.report_progress(&VmSnapshot {
+            instruction_count: self.callback.instruction_count(),
+            depth: self.stack_index.len(),
+            function,
+        })
+    }
+
+    #[cold]
+    fn record_execution(&self) {
+        let function = if self.in_function() {
+            self.function_stack.last().map(|x| x.as_ref())
+        } else {
+            None
+        };
+        self.callback
+            .record_execution(&self.instructions, self.ip, function);
+    }
+
+    // Pauses before running the instruction at `span`, if the debugger wants to
+    fn check_debugger(&self, span: Span) {
+        let depth = self.stack_index.len();
+        let origin = if self.in_function() {
+            self.function_stack.last().and_then(|x| x.origin())
+        } else {
+            None
+        };
+        if let Some((reason, location)) = self.callback.check_debugger(span, depth, origin) {
+            let locals = self.locals_for_debugger();
+            self.callback.pause(reason, location, span, depth, locals);
+        }
+    }
+
+    // A function body ends with an instruction for each of its locals, in the order of their
+    // slots on the stack, holding the span of where that local was bound
+    fn locals_for_debugger(&self) -> Vec<(Span, SteelVal)> {
+        if !self.in_function() {
+            return Vec::new();
+        }
+
+        let base = self.stack_index.last().copied().unwrap_or(0);
+        let bindings = self
+            .instructions
+            .iter()
+            .rev()
+            .take_while(|x| x.op_code == OpCode::CLOSEUPVALUE)
+            .count();
+
+        self.instructions[self.instructions.len() - bindings..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| {
+                self.stack
+                    .get(base + i)
+                    .map(|value| (x.span, value.clone()))
+            })
+            .collect()
+    }
+
+    #[inline(always)]
+    fn record_branch(&self, taken: bool) {
+        if self.use_callbacks.use_callbacks() && self.callback.collecting_stats() {
+            self.callback
+                .record_branch(&self.instructions, self.in_function(), taken);
+        }
+    }
 
-                    // get the local
-                    let offset = self.stack_index.last().copied().unwrap_or(0);
-                    let local_value = self.stack[read_local.payload_size as usize + offset].clone();
+    #[inline(always)]
+    fn handle_if(&mut self, true_branch: usize) {
+        // change to truthy...
+        if self.stack.pop().unwrap().is_truthy() {
+            self.record_branch(true);
+            self.ip = true_branch;
+        } else {
+            self.record_branch(false);
+            self.ip = self.instructions[self.ip + 1].payload_size as usize
+            // self.ip += 1;
+        }
+    }
 
-                    // get the const
-                    let const_val = self.constants.get(push_const.payload_size as usize);
+    #[inline(always)]
+    fn handle_tco_jump(&mut self, target: usize) -> Result<()> {
+        let mut current_arity = self.instructions[self.ip + 1].payload_size as usize;
 
-                    self.handle_lazy_function_call(func, local_value, const_val, &cur_inst.span)?;
-                }
-                OpCode::CALLGLOBAL => {
-                    let next_inst = self.instructions[self.ip + 1];
-                    self.handle_call_global(
-                        cur_inst.payload_size as usize,
-                        next_inst.payload_size as usize,
-                        &next_inst.span,
-                    )?;
-                }
-                OpCode::CALLGLOBALTAIL => {
-                    let next_inst = self.instructions[self.ip + 1];
-                    self.handle_tail_call_global(
-                        cur_inst.payload_size as usize,
-                        next_inst.payload_size as usize,
-                        &next_inst.span,
-                    )?;
-                }
-                OpCode::FUNC => {
-                    let func = self.stack.pop().unwrap();
-                    self.handle_function_call(
-                        func,
-                        cur_inst.payload_size as usize,
-                        &cur_inst.span,
-                    )?;
-                }
-                // Tail call basically says "hey this function is exiting"
-                // In the closure case, transfer ownership of the stack to the called function
-                OpCode::TAILCALL => {
-                    let func = self.stack.pop().unwrap();
-                    self.handle_tail_call(func, cur_inst.payload_size as usize, &cur_inst.span)?
-                }
-                OpCode::IF => {
-                    // change to truthy...
-                    if self.stack.pop().unwrap().is_truthy() {
-                        self.ip = cur_inst.payload_size as usize;
-                    } else {
-                        self.ip = self.instructions[self.ip + 1].payload_size as usize
-                        // self.ip += 1;
-                    }
-                }
-                OpCode::TCOJMP => {
-                    let current_arity = self.instructions[self.ip + 1].payload_size as usize;
-                    self.ip = cur_inst.payload_size as usize;
-
-                    // HACK COME BACK TO THIS
-                    // if self.ip == 0 && self.heap.len() > self.heap.limit() {
-                    // TODO collect here
-                    // self.heap.collect_garbage();
-                    // }
-                    let offset = self.stack_index.last().copied().unwrap_or(0);
-
-                    // We should have arity at this point, drop the stack up to this point
-                    // take the last arity off the stack, go back and replace those in order
-                    let back = self.stack.len() - current_arity;
-                    for i in 0..current_arity {
-                        self.stack.set_idx(offset + i, self.stack[back + i].clone());
-                    }
+        // A function with optional or keyword arguments calling itself lines the arguments up first
+        let current = self
+            .function_stack
+            .last()
+            .filter(|x| x.signature().is_some());
+        if let Some(closure) = current.cloned() {
+            let span = self.instructions[self.ip + 1].span;
+            current_arity = self.bind_arguments(&closure, current_arity, &span)?;
+        }
 
-                    self.stack.truncate(offset + current_arity);
-                }
-                OpCode::JMP => {
-                    self.ip = cur_inst.payload_size as usize;
-                }
-                OpCode::POP => {
-                    if let Some(r) = self.handle_pop(cur_inst.payload_size, &cur_inst.span) {
-                        return r;
-                    }
-                }
-                OpCode::BIND => self.handle_bind(cur_inst.payload_size as usize),
-                OpCode::SCLOSURE => self.handle_start_closure(cur_inst.payload_size as usize),
-                OpCode::SDEF => self.handle_start_def(),
-                OpCode::EDEF => {
-                    self.ip += 1;
-                }
-                _ => {
-                    // crate::core::instructions::pretty_print_dense_instructions(&self.instructions);
-                    panic!("Unhandled opcode: {:?} @ {}", cur_inst.op_code, self.ip);
-                }
-            }
+        self.ip = target;
 
-            // Put callbacks behind generic
-            if self.use_callbacks.use_callbacks() {
-                match self.callback.call_and_increment() {
-                    Some(b) if !b => stop!(Generic => "Callback forced quit of function!"),
-                    _ => {}
-                }
-            }
+        // HACK COME BACK TO THIS
+        // if self.ip == 0 && self.heap.len() > self.heap.limit() {
+        // TODO collect here
+        // self.heap.collect_garbage();
+        // }
+        let offset = self.stack_index.last().copied().unwrap_or(0);
+
+        // We should have arity at this point, drop the stack up to this point
+        // take the last arity off the stack, go back and replace those in order
+        let back = self.stack.len() - current_arity;
+        for i in 0..current_arity {
+            self.stack.set_idx(offset + i, self.stack[back + i].clone());
         }
 
-        error!(
-            "Out of bounds instruction!: instruction pointer: {}, instruction length: {}",
-            self.ip,
-            self.instructions.len()
-        );
-        panic!("Out of bounds instruction")
+        self.stack.truncate(offset + current_arity);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn handle_jump(&mut self, target: usize) {
+        self.ip = target;
+    }
+
+    #[inline(always)]
+    fn handle_end_def(&mut self) {
+        self.ip += 1;
     }
 
     #[inline(always)]
@@ -608,7 +1626,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
 
         if self.pop_count == 0 {
             let ret_val = self.stack.try_pop().ok_or_else(|| {
-                SteelErr::new(ErrorKind::Generic, "stack empty at pop".to_string()).with_span(*span)
+                SteelErr::new(ErrorKind::Generic, "stack empty at pop").with_span(*span)
             });
 
             // Roll back if needed
@@ -655,9 +1673,18 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                 .instrs_ref()
                 .is_empty()
             {
-                let prev_state = self.instruction_stack.pop().unwrap();
-                self.ip = prev_state.0;
-                self.instructions = prev_state.instrs();
+                let InstructionPointer(ip, instructions, then) =
+                    self.instruction_stack.pop().unwrap();
+                self.ip = ip;
+                self.instructions = instructions;
+
+                if let Some(then) = then {
+                    let ret_val = self.stack.pop().unwrap();
+                    match then.call(ret_val) {
+                        Ok(value) => self.stack.push(value),
+                        Err(e) => return Some(Err(e.set_span(*span))),
+                    }
+                }
             } else {
                 self.ip += 1;
             }
@@ -762,6 +1789,9 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
 
             self.global_env.repl_define_idx(idx, func);
         }
+
+        self.stack.push(SteelVal::Void);
+        self.ip += 1;
         Ok(())
     }
 
@@ -803,6 +1833,8 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             self.stack.push(func);
         }
 
+        self.stack.push(SteelVal::Void);
+        self.ip += 1;
         Ok(())
     }
 
@@ -830,7 +1862,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                         .push(ListOperations::built_in_list_func_flat_non_gc(converted?)?);
                     self.ip += 1;
                 }
-                Err(e) => stop!(Generic => format!("{}", e); *span),
+                Err(e) => stop!(Generic => "{}", e; *span),
             }
         } else {
             stop!(TypeMismatch => "read expects a string"; *span)
@@ -861,6 +1893,62 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         self.handle_function_call(func, payload_size, span)
     }
 
+    // Calls to builtin comparison and numeric operators get their own opcodes.
+    // If the arguments are numbers that can be handled directly, compute the result inline,
+    // otherwise (or if the global no longer holds the builtin) fall back to calling the global
+    #[inline(always)]
+    fn handle_specialized_call(
+        &mut self,
+        op: OpCode,
+        index: usize,
+        payload_size: usize,
+        span: &Span,
+    ) -> Result<()> {
+        let func = self.global_env.repl_lookup_idx(index)?;
+
+        if self.global_env.is_specialized(index, &func) {
+            let args = self.stack.peek_range(self.stack.len() - payload_size..);
+
+            if let Some(result) = specialized_numeric_op(op, args) {
+                self.stack.truncate(self.stack.len() - payload_size);
+                self.stack.push(result);
+                // Skip over the pass as well
+                self.ip += 2;
+                return Ok(());
+            }
+        }
+
+        self.ip += 1;
+        self.handle_function_call(func, payload_size, span)
+    }
+
+    // A comparison immediately followed by an `if` - the layout is still
+    // [BRANCH, PASS, IF, JMP], so the comparison can jump straight to the correct branch
+    // without pushing the boolean. When the fast path doesn't apply, this just acts as a
+    // normal call and the IF gets executed as usual
+    #[inline(always)]
+    fn handle_specialized_branch(&mut self, op: OpCode, index: usize, span: &Span) -> Result<()> {
+        let func = self.global_env.repl_lookup_idx(index)?;
+
+        if self.global_env.is_specialized(index, &func) {
+            let len = self.stack.len();
+
+            if let Some(result) = compare_numbers(op, &self.stack[len - 2], &self.stack[len - 1]) {
+                self.stack.truncate(len - 2);
+                self.record_branch(result);
+                self.ip = if result {
+                    self.instructions[self.ip + 2].payload_size as usize
+                } else {
+                    self.instructions[self.ip + 3].payload_size as usize
+                };
+                return Ok(());
+            }
+        }
+
+        self.ip += 1;
+        self.handle_function_call(func, 2, span)
+    }
+
     #[inline(always)]
     fn handle_tail_call_global(
         &mut self,
@@ -959,7 +2047,15 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         // snag the arity from the eclosure instruction
         let arity = self.instructions[forward_index - 1].payload_size;
 
-        let constructed_lambda = ByteCodeLambda::new(closure_body, arity as usize, upvalues);
+        let mut constructed_lambda = ByteCodeLambda::new(closure_body, arity as usize, upvalues);
+
+        // Closures made in a function come from wherever that function did
+        let origin = if self.in_function() {
+            self.function_stack.last().and_then(|x| x.origin())
+        } else {
+            definition_at(&self.instructions, forward_index)
+        };
+        constructed_lambda.set_origin(origin);
 
         self.stack
             .push(SteelVal::Closure(Gc::new(constructed_lambda)));
@@ -968,11 +2064,20 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
     }
 
     #[inline(always)]
-    fn handle_bind(&mut self, payload_size: usize) {
-        self.global_env
-            .repl_define_idx(payload_size, self.stack.pop().unwrap());
+    fn handle_bind(&mut self, payload_size: usize, span: Span) -> Result<()> {
+        if let Some(names) = &self.global_env.redefinitions_forbidden {
+            if payload_size < self.global_env.bindings_vec.len() {
+                let name = names.name(payload_size).unwrap_or("the global");
+                stop!(Generic => "{} is already defined, and can't be defined again", name; span);
+            }
+        }
+
+        let value = self.stack.pop().unwrap();
+        single_value(&value, &span)?;
+        self.global_env.repl_define_idx(payload_size, value);
 
         self.ip += 1;
+        Ok(())
     }
 
     #[inline(always)]
@@ -1018,8 +2123,9 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             stop!(Generic => "stack overflowed!"; *span);
         }
 
+        let payload_size = self.bind_arguments(closure, payload_size, span)?;
         if closure.arity() != payload_size {
-            stop!(ArityMismatch => format!("function expected {} arguments, found {}", closure.arity(), payload_size); *span);
+            stop!(ArityMismatch => "function expected {} arguments, found {}", closure.arity(), payload_size; *span);
         }
 
         // println!("stack index before: {:?}", self.stack_index);
@@ -1064,6 +2170,29 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         Ok(())
     }
 
+    // Lines the arguments on top of the stack up with the parameters of a closure with optional
+    // or keyword arguments, returning how many arguments there are now
+    #[inline(always)]
+    fn bind_arguments(
+        &mut self,
+        closure: &ByteCodeLambda,
+        payload_size: usize,
+        span: &Span,
+    ) -> Result<usize> {
+        match closure.signature() {
+            Some(signature) => {
+                let args = self.stack.split_off(self.stack.len() - payload_size);
+                let bound = signature.bind(&args).map_err(|x| x.set_span(*span))?;
+                let payload_size = bound.len();
+                for arg in bound {
+                    self.stack.push(arg);
+                }
+                Ok(payload_size)
+            }
+            None => Ok(payload_size),
+        }
+    }
+
     #[inline(always)]
     fn handle_tail_call(
         &mut self,
@@ -1072,8 +2201,11 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         span: &Span,
     ) -> Result<()> {
         use SteelVal::*;
+        self.check_single_values(&stack_func, payload_size, span)?;
+
         match &stack_func {
             BoxedFunction(f) => self.call_boxed_func(f, payload_size, span)?,
+            BuiltIn(f) => self.call_builtin_func(f, payload_size, span)?,
             FuncV(f) => self.call_primitive_func(f, payload_size, span)?,
             FutureFunc(f) => self.call_future_func(f, payload_size)?,
             ContractedFunction(cf) => {
@@ -1096,7 +2228,12 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         payload_size: usize,
         span: &Span,
     ) -> Result<()> {
-        let result = func(self.stack.peek_range(self.stack.len() - payload_size..))
+        let result = self
+            .callback
+            .call_boxed(
+                func,
+                self.stack.peek_range(self.stack.len() - payload_size..),
+            )
             .map_err(|x| x.set_span(*span))?;
 
         self.stack.truncate(self.stack.len() - payload_size);
@@ -1106,6 +2243,110 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         Ok(())
     }
 
+    #[inline(always)]
+    fn call_builtin_func(
+        &mut self,
+        func: &BuiltInSignature,
+        payload_size: usize,
+        span: &Span,
+    ) -> Result<()> {
+        self.continue_builtin(func, payload_size, 1, span)
+    }
+
+    // Calls a native function for the instruction at `ip`, then moves on to the instruction `step`
+    // past it. A closure the native function deferred a call to runs on this VM, returning there
+    // once it's done.
+    fn continue_builtin(
+        &mut self,
+        func: &BuiltInSignature,
+        payload_size: usize,
+        step: usize,
+        span: &Span,
+    ) -> Result<()> {
+        let (mut result, deferred) = self
+            .run_builtin(func, payload_size)
+            .map_err(|x| x.set_span(*span))?;
+
+        match deferred {
+            None => {}
+            Some(DeferredCall {
+                function: SteelVal::Closure(closure),
+                args,
+                then,
+            }) => {
+                let return_ip = self.ip + step;
+                let payload_size = args.len();
+                for arg in args {
+                    self.stack.push(arg);
+                }
+                self.handle_function_call_closure(&closure, payload_size, span)?;
+
+                let frame = self.instruction_stack.pop().unwrap();
+                self.instruction_stack.push(InstructionPointer(
+                    return_ip,
+                    frame.instrs(),
+                    Some(then),
+                ));
+                return Ok(());
+            }
+            Some(call) => {
+                result = self.call_deferred(call).map_err(|x| x.set_span(*span))?;
+            }
+        }
+
+        self.stack.push(result);
+        self.ip += step;
+        Ok(())
+    }
+
+    // Calls a native function with the `payload_size` values on top of the stack and runs the call
+    // it deferred, if it did, to completion
+    fn call_builtin(&mut self, func: &BuiltInSignature, payload_size: usize) -> Result<SteelVal> {
+        match self.run_builtin(func, payload_size)? {
+            (_, Some(call)) => self.call_deferred(call),
+            (result, None) => Ok(result),
+        }
+    }
+
+    // Runs a native function with the `payload_size` values on top of the stack, returning what it
+    // returned along with the call it deferred. The arguments stay on the stack until it returns,
+    // so that the garbage collector can see them while calls back into the VM are running above
+    // them.
+    fn run_builtin(
+        &mut self,
+        func: &BuiltInSignature,
+        payload_size: usize,
+    ) -> Result<(SteelVal, Option<DeferredCall>)> {
+        let args = self
+            .stack
+            .peek_range(self.stack.len() - payload_size..)
+            .to_vec();
+        let result = self.nested(|core| func(&mut SteelThread::new(core), &args));
+        let deferred = self.deferred.take();
+
+        let result = result?;
+        self.stack.truncate(self.stack.len() - payload_size);
+        Ok((result, deferred))
+    }
+
+    fn call_deferred(&mut self, call: DeferredCall) -> Result<SteelVal> {
+        let value = self.call_nested(&call.function, call.args)?;
+        call.then.call(value)
+    }
+
+    // Runs `f` one level deeper into native functions calling back into the VM, growing the
+    // rust stack first if it's running low
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.nesting == NESTING_LIMIT {
+            stop!(Generic => "native functions calling back into the VM nested too deeply");
+        }
+
+        self.nesting += 1;
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || f(self));
+        self.nesting -= 1;
+        result
+    }
+
     #[inline(always)]
     fn call_primitive_func(
         &mut self,
@@ -1131,7 +2372,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         span: &Span,
     ) -> Result<()> {
         if cf.arity() != payload_size {
-            stop!(ArityMismatch => format!("function expected {} arguments, found {}", cf.arity(), payload_size); *span);
+            stop!(ArityMismatch => "function expected {} arguments, found {}", cf.arity(), payload_size; *span);
         }
 
         if self.apply_contracts.enforce_contracts() {
@@ -1167,7 +2408,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         span: &Span,
     ) -> Result<()> {
         if cf.arity() != payload_size {
-            stop!(ArityMismatch => format!("function expected {} arguments, found {}", cf.arity(), payload_size); *span);
+            stop!(ArityMismatch => "function expected {} arguments, found {}", cf.arity(), payload_size; *span);
         }
 
         if self.apply_contracts.enforce_contracts() {
@@ -1218,6 +2459,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             .pop()
             .ok_or_else(throw!(ArityMismatch => "continuation expected 1 argument, found none"))?;
 
+        self.wind_to(&continuation.winders)?;
         self.set_state_from_continuation(continuation.clone());
 
         self.ip += 1;
@@ -1240,8 +2482,9 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         // Push on the function stack so we have access to it later
         self.function_stack.push(Gc::clone(closure));
 
-        if closure.arity() != 2 {
-            stop!(ArityMismatch => format!("function expected {} arguments, found {}", closure.arity(), 2); *span);
+        let payload_size = self.bind_arguments(closure, 2, span)?;
+        if closure.arity() != payload_size {
+            stop!(ArityMismatch => "function expected {} arguments, found {}", closure.arity(), 2; *span);
         }
 
         // self.current_arity = Some(closure.arity());
@@ -1251,7 +2494,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             stop!(Generic => "stack overflowed!"; *span);
         }
 
-        self.stack_index.push(self.stack.len() - 2);
+        self.stack_index.push(self.stack.len() - payload_size);
 
         // TODO use new heap
         // self.heap
@@ -1289,6 +2532,11 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
                     .push(f(&[local, const_value]).map_err(|x| x.set_span(*span))?);
                 self.ip += 4;
             }
+            BuiltIn(f) => {
+                self.stack.push(local);
+                self.stack.push(const_value);
+                self.continue_builtin(f, 2, 4, span)?;
+            }
             FuncV(f) => {
                 self.stack
                     .push(f(&[local, const_value]).map_err(|x| x.set_span(*span))?);
@@ -1304,7 +2552,7 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
             }
             ContractedFunction(cf) => {
                 if cf.arity() != 2 {
-                    stop!(ArityMismatch => format!("function expected {} arguments, found {}", cf.arity(), 2); *span);
+                    stop!(ArityMismatch => "function expected {} arguments, found {}", cf.arity(), 2; *span);
                 }
 
                 if self.apply_contracts.enforce_contracts() {
@@ -1352,8 +2600,9 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         // Push on the function stack so we have access to it later
         self.function_stack.push(Gc::clone(closure));
 
+        let payload_size = self.bind_arguments(closure, payload_size, span)?;
         if closure.arity() != payload_size {
-            stop!(ArityMismatch => format!("function expected {} arguments, found {}", closure.arity(), payload_size); *span);
+            stop!(ArityMismatch => "function expected {} arguments, found {}", closure.arity(), payload_size; *span);
         }
 
         // self.current_arity = Some(closure.arity());
@@ -1393,9 +2642,11 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         span: &Span,
     ) -> Result<()> {
         use SteelVal::*;
+        self.check_single_values(&stack_func, payload_size, span)?;
 
         match &stack_func {
             BoxedFunction(f) => self.call_boxed_func(f, payload_size, span)?,
+            BuiltIn(f) => self.call_builtin_func(f, payload_size, span)?,
             FuncV(f) => self.call_primitive_func(f, payload_size, span)?,
             FutureFunc(f) => self.call_future_func(f, payload_size)?,
             ContractedFunction(cf) => self.call_contracted_function(cf, payload_size, span)?,
@@ -1409,63 +2660,188 @@ impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U
         Ok(())
     }
 
+    // Multiple values can only be returned, the arguments to a function each have to be a
+    // single value. Continuations are the exception, since they return what they're given.
+    #[inline(always)]
+    fn check_single_values(
+        &self,
+        stack_func: &SteelVal,
+        payload_size: usize,
+        span: &Span,
+    ) -> Result<()> {
+        if let SteelVal::ContinuationFunction(_) = stack_func {
+            return Ok(());
+        }
+
+        self.stack
+            .peek_range(self.stack.len() - payload_size..)
+            .iter()
+            .try_for_each(|arg| single_value(arg, span))
+    }
+
     #[inline(always)]
     fn handle_start_def(&mut self) {
         self.ip += 1;
     }
 
     #[inline(always)]
-    fn handle_apply(&mut self, span: Span) -> Result<()> {
+    // (apply f a b ... list), the leading arguments and the list are put on the stack
+    // as if the function had been called with all of them directly
+    fn handle_apply(&mut self, leading_args: usize, span: Span, tail: bool) -> Result<()> {
         let list = self.stack.pop().unwrap();
-        let func = self.stack.pop().unwrap();
 
-        let mut args = match ListOperations::collect_into_vec(&list) {
-            Ok(args) => args,
-            Err(_) => stop!(TypeMismatch => "apply expected a list"; span),
+        // The empty list is an empty vector
+        let mut rest = match &list {
+            SteelVal::Pair(_) => ListOperations::collect_into_vec(&list)?,
+            SteelVal::VectorV(v) if v.is_empty() => Vec::new(),
+            _ => {
+                stop!(TypeMismatch => "apply expected a list, found: {}", list; span)
+            }
         };
 
-        match &func {
-            SteelVal::FuncV(f) => {
-                let result = f(&args).map_err(|x| x.set_span(span))?;
-                self.stack.push(result);
-                self.ip += 1;
+        let mut args = self.stack.split_off(self.stack.len() - leading_args);
+        let func = self.stack.pop().unwrap();
+        let payload_size = args.len() + rest.len();
+
+        self.stack.append_vec(&mut args);
+        self.stack.append_vec(&mut rest);
+
+        if tail {
+            self.handle_tail_call(func, payload_size, &span)
+        } else {
+            self.handle_function_call(func, payload_size, &span)
+        }
+    }
+}
+
+impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> VmCore<'a, CT, U, A> {
+    // Calls `function` with the arguments above `base` on the stack, running it to completion
+    fn call_on_stack(&mut self, function: &SteelVal, base: usize) -> Result<SteelVal> {
+        match function {
+            SteelVal::FuncV(f) => f(self.stack.peek_range(base..)),
+            SteelVal::BoxedFunction(f) => f(self.stack.peek_range(base..)),
+            SteelVal::BuiltIn(f) => self.call_builtin(f, self.stack.len() - base),
+            SteelVal::Closure(closure) => self.call_closure_on_stack(closure, base),
+            SteelVal::ContractedFunction(cf) => {
+                if cf.arity() != self.stack.len() - base {
+                    stop!(ArityMismatch => "function expected {} arguments, found {}", cf.arity(), self.stack.len() - base);
+                }
+
+                if self.apply_contracts.enforce_contracts() {
+                    let args = self.stack.peek_range(base..).to_vec();
+                    self.nested(|core| {
+                        cf.apply(
+                            args,
+                            core.constants,
+                            &Span::new(0, 0),
+                            core.callback,
+                            core.upvalue_heap,
+                            core.global_env,
+                            core.stack,
+                            core.function_stack,
+                            core.stack_index,
+                            core.use_callbacks,
+                            core.apply_contracts,
+                        )
+                    })
+                } else {
+                    self.call_closure_on_stack(&cf.function, base)
+                }
             }
-            SteelVal::BoxedFunction(f) => {
-                let result = f(&args).map_err(|x| x.set_span(span))?;
-                self.stack.push(result);
-                self.ip += 1;
+            SteelVal::ContinuationFunction(_) => {
+                stop!(Generic => "a continuation can't be called from inside a native function")
             }
-            SteelVal::Closure(closure) => {
-                if self.stack_index.len() == STACK_LIMIT {
-                    // println!("stacks at exit: {:?}", stacks);
-                    println!("stack frame at exit: {:?}", self.stack);
-                    stop!(Generic => "stack overflowed!"; span);
-                }
+            _ => {
+                stop!(TypeMismatch => "call_function expected a function, found {}", function)
+            }
+        }
+    }
 
-                // self.global_env = inner_env;
-                self.instruction_stack.push(InstructionPointer::new(
-                    self.ip + 1,
-                    Rc::clone(&self.instructions),
-                ));
-                self.pop_count += 1;
+    fn call_closure_on_stack(
+        &mut self,
+        closure: &Gc<ByteCodeLambda>,
+        base: usize,
+    ) -> Result<SteelVal> {
+        if let Some(signature) = closure.signature() {
+            let args = signature.bind(self.stack.peek_range(base..))?;
+            self.stack.truncate(base);
+            for arg in args {
+                self.stack.push(arg);
+            }
+        }
 
-                self.function_stack.push(Gc::clone(closure));
+        let payload_size = self.stack.len() - base;
+        if closure.arity() != payload_size {
+            stop!(ArityMismatch => "function expected {} arguments, found {}", closure.arity(), payload_size);
+        }
 
-                let payload_size = args.len();
+        if self.stack_index.len() >= STACK_LIMIT {
+            stop!(Generic => "native functions calling back into the VM nested too deeply");
+        }
 
-                // Append the arguments to the function
-                self.stack.append_vec(&mut args);
+        self.stack_index.push(base);
+        self.function_stack.push(Gc::clone(closure));
 
-                self.stack_index.push(self.stack.len() - payload_size);
+        self.nested(|core| {
+            let nesting = core.nesting;
+            VmCore::new(
+                closure.body_exp(),
+                core.stack,
+                core.global_env,
+                core.constants,
+                core.callback,
+                core.upvalue_heap,
+                core.function_stack,
+                core.stack_index,
+                core.use_callbacks,
+                core.apply_contracts,
+            )
+            .and_then(|mut core| {
+                core.nesting = nesting;
+                core.vm()
+            })
+        })
+    }
+}
 
-                self.instructions = closure.body_exp();
-                self.ip = 0;
-            }
-            _ => {
-                stop!(BadSyntax => "Apply - Application not a procedure or function type not supported"; span);
-            }
+impl<'a, CT: ConstantTable, U: UseCallbacks, A: ApplyContracts> NestedCall
+    for VmCore<'a, CT, U, A>
+{
+    fn call_nested(&mut self, function: &SteelVal, args: Vec<SteelVal>) -> Result<SteelVal> {
+        let stack_len = self.stack.len();
+        let stack_index_len = self.stack_index.len();
+        let function_stack_len = self.function_stack.len();
+        let winders_len = self.callback.winder_count();
+
+        // The arguments go on the stack, where the garbage collector can see them
+        for arg in args {
+            self.stack.push(arg);
         }
-        Ok(())
+        let result = self.call_on_stack(function, stack_len);
+
+        // Whatever the call left behind is rolled back, so the caller sees the stacks as they were
+        self.stack.truncate(stack_len);
+        self.stack_index.truncate(stack_index_len);
+        self.function_stack.truncate(function_stack_len);
+        self.callback.truncate_winders(winders_len);
+
+        result
+    }
+
+    fn push_winder(&mut self, winder: Winder) {
+        self.callback.push_winder(winder);
+    }
+
+    fn pop_winder(&mut self) {
+        self.callback.pop_winder();
+    }
+
+    fn take_interrupt(&mut self) -> bool {
+        self.callback.take_interrupt()
+    }
+
+    fn defer_call(&mut self, call: DeferredCall) {
+        self.deferred = Some(call);
     }
 }
 
@@ -1496,3 +2872,46 @@ pub(crate) fn vm<CT: ConstantTable, U: UseCallbacks, A: ApplyContracts>(
     )?
     .vm()
 }
+
+// Fast paths for the specialized numeric opcodes. Returns `None` whenever the
+// arguments aren't something we can handle here, in which case the caller should
+// go through the real function to get the full behavior (and error messages)
+#[inline(always)]
+fn specialized_numeric_op(op: OpCode, args: &[SteelVal]) -> Option<SteelVal> {
+    use SteelVal::{BoolV, IntV, NumV};
+
+    match (op, args) {
+        (OpCode::ABS, [IntV(n)]) => n.checked_abs().map(IntV),
+        (OpCode::ABS, [NumV(n)]) => Some(NumV(n.abs())),
+        (OpCode::MOD, [IntV(l), IntV(r)]) if *r != 0 => Some(IntV(int_modulo(*l, *r))),
+        (OpCode::MOD, [NumV(l), NumV(r)]) => Some(NumV(float_modulo(*l, *r))),
+        (OpCode::REMAINDER, [IntV(l), IntV(r)]) if *r != 0 => Some(IntV(l.wrapping_rem(*r))),
+        (OpCode::REMAINDER, [NumV(l), NumV(r)]) => Some(NumV(l % r)),
+        (OpCode::MIN, [IntV(l), IntV(r)]) => Some(IntV(*l.min(r))),
+        (OpCode::MIN, [NumV(l), NumV(r)]) => Some(NumV(if r < l { *r } else { *l })),
+        (OpCode::MAX, [IntV(l), IntV(r)]) => Some(IntV(*l.max(r))),
+        (OpCode::MAX, [NumV(l), NumV(r)]) => Some(NumV(if r > l { *r } else { *l })),
+        (_, [l, r]) => compare_numbers(op, l, r).map(BoolV),
+        _ => None,
+    }
+}
+
+// Shared between the comparison opcodes and their fused compare-and-branch counterparts.
+// Only compares numbers of the same type - anything else goes through the real function
+#[inline(always)]
+fn compare_numbers(op: OpCode, l: &SteelVal, r: &SteelVal) -> Option<bool> {
+    let ordering = match (l, r) {
+        (SteelVal::IntV(l), SteelVal::IntV(r)) => l.cmp(r),
+        (SteelVal::NumV(l), SteelVal::NumV(r)) if op != OpCode::BRANCHEQ => l.partial_cmp(r)?,
+        _ => return None,
+    };
+
+    match op {
+        OpCode::LT | OpCode::BRANCHLT => Some(ordering == Ordering::Less),
+        OpCode::LTE | OpCode::BRANCHLTE => Some(ordering != Ordering::Greater),
+        OpCode::GT | OpCode::BRANCHGT => Some(ordering == Ordering::Greater),
+        OpCode::GTE | OpCode::BRANCHGTE => Some(ordering != Ordering::Less),
+        OpCode::BRANCHEQ => Some(ordering == Ordering::Equal),
+        _ => None,
+    }
+}
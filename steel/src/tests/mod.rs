@@ -56,14 +56,19 @@ macro_rules! test_harness_failure {
 }
 
 test_harness_success! {
+    apply_leading_args,
     apply_more_complex,
     basic_apply,
     calculator,
     capture_upvalue,
+    chars,
     capture_upvalues_arity_two,
     close_upvalue,
+    comparisons,
     define_normal,
     dfs,
+    dynamic_wind,
+    dynamic_wind_reentry,
     fib,
     generator,
     generic_execution_dropping,
@@ -74,9 +79,20 @@ test_harness_success! {
     letrec_mutual_recursion,
     letrec_simple_recursion,
     local_struct,
+    macro_hygiene,
+    match_patterns,
     matcher,
     merge_sort,
+    multiple_values,
+    named_let_and_do,
+    number_and_keyword_syntax,
+    numeric_ops,
+    optional_arguments,
+    print_limits,
+    procedural_macros,
+    pure_functions,
     read,
+    record_types,
     set_local,
     sieve,
     simple_stream_with_map,
@@ -93,7 +109,15 @@ test_harness_success! {
 }
 
 test_harness_failure! {
+    define_multiple_values,
+    define_values_wrong_count,
     function_used_before_definition,
     identifier_used_before_definition,
-    local_struct_inaccessible
+    let_multiple_values,
+    local_struct_inaccessible,
+    multiple_values_as_argument,
+    procedural_macro_no_matching_pattern,
+    record_accessor_wrong_type,
+    unknown_keyword_argument,
+    values_ref_out_of_range
 }
@@ -28,7 +28,7 @@ impl FlatContract {
         } else if predicate.is_function() {
             Ok(FlatContract::new(predicate, name).into())
         } else {
-            stop!(TypeMismatch => format!("flat contracts require a function argument, found {}", predicate.to_string()));
+            stop!(TypeMismatch => "flat contracts require a function argument, found {}", predicate.to_string());
         }
     }
 
@@ -222,7 +222,7 @@ impl ContractedFunction {
         };
 
         if contract.arity() != function.arity() {
-            stop!(TypeMismatch => format!("contract did not match function arity: function has arity: {}, contract has arity: {}", function.arity(), contract.arity()));
+            stop!(TypeMismatch => "contract did not match function arity: function has arity: {}, contract has arity: {}", function.arity(), contract.arity());
         }
 
         Ok(ContractedFunction::new(contract, function, name).into())
@@ -22,7 +22,7 @@ pub fn string_to_jsexpr() -> SteelVal {
             let res: std::result::Result<Value, _> = serde_json::from_str(unescaped.as_str());
             match res {
                 Ok(res) => res.try_into(),
-                Err(e) => stop!(Generic => format!("string->jsexpr failed: {}", e.to_string())),
+                Err(e) => stop!(Generic => "string->jsexpr failed: {}", e.to_string()),
             }
         }
     })
@@ -2,4 +2,5 @@ pub(crate) mod contracts;
 pub(crate) mod json_vals;
 pub(crate) mod lazy_stream;
 pub(crate) mod port;
+pub(crate) mod signatures;
 pub(crate) mod structs;
@@ -0,0 +1,160 @@
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{Result, SteelVal};
+
+use std::rc::Rc;
+
+/// The parameters of a lambda with `#:optional` or `#:key` arguments, e.g.
+/// `(lambda (a #:optional (b 10) #:key (c 20)) ...)`. The closure itself takes every
+/// parameter positionally, in order, and the arguments of each call are lined up with
+/// them using this.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    required: usize,
+    optional: usize,
+    /// The names of the keywords, without the `#:`
+    keywords: Vec<Rc<str>>,
+}
+
+fn is_keyword(value: &SteelVal) -> bool {
+    matches!(value, SteelVal::KeywordV(_))
+}
+
+impl Signature {
+    pub fn new(required: usize, optional: usize, keywords: Vec<Rc<str>>) -> Self {
+        Signature {
+            required,
+            optional,
+            keywords,
+        }
+    }
+
+    /// How many parameters the closure has
+    pub fn arity(&self) -> usize {
+        self.required + self.optional + self.keywords.len()
+    }
+
+    /// Lines `args` up with the parameters. Optional and keyword arguments that aren't
+    /// given are left as `void`, for the defaults to fill in.
+    pub fn bind(&self, args: &[SteelVal]) -> Result<Vec<SteelVal>> {
+        let positional = if self.keywords.is_empty() {
+            args.len()
+        } else {
+            args.iter().position(is_keyword).unwrap_or(args.len())
+        };
+
+        if positional < self.required || positional > self.required + self.optional {
+            if self.optional == 0 {
+                stop!(ArityMismatch => "function expected {} arguments, found {}", self.required, positional);
+            }
+            stop!(ArityMismatch => "function expected {} to {} arguments, found {}",
+                self.required, self.required + self.optional, positional);
+        }
+
+        let mut bound = Vec::with_capacity(self.arity());
+        bound.extend_from_slice(&args[..positional]);
+        bound.resize(self.arity(), SteelVal::Void);
+
+        let offset = self.required + self.optional;
+        let mut given = vec![false; self.keywords.len()];
+        for pair in args[positional..].chunks(2) {
+            let keyword = match &pair[0] {
+                SteelVal::KeywordV(k) => k.as_str(),
+                other => {
+                    stop!(ArityMismatch => "function expected a keyword, found: {}", other)
+                }
+            };
+
+            let index = match self.keywords.iter().position(|k| k.as_ref() == keyword) {
+                Some(index) => index,
+                None => {
+                    stop!(ContractViolation => "function does not take the keyword #:{}", keyword)
+                }
+            };
+
+            if given[index] {
+                stop!(ContractViolation => "function was given the keyword #:{} more than once", keyword);
+            }
+
+            match pair.get(1) {
+                Some(value) => bound[offset + index] = value.clone(),
+                None => stop!(ArityMismatch => "function expected a value after #:{}", keyword),
+            }
+            given[index] = true;
+        }
+
+        Ok(bound)
+    }
+}
+
+#[cfg(test)]
+mod signature_tests {
+    use super::*;
+
+    fn keyword(name: &str) -> SteelVal {
+        SteelVal::KeywordV(name.into())
+    }
+
+    fn signature() -> Signature {
+        Signature::new(1, 1, vec!["c".into(), "d".into()])
+    }
+
+    #[test]
+    fn missing_arguments_are_left_void() {
+        let bound = signature().bind(&[SteelVal::IntV(1)]).unwrap();
+        assert_eq!(bound.len(), 4);
+        assert_eq!(bound[0], SteelVal::IntV(1));
+        assert!(bound[1..].iter().all(|x| matches!(x, SteelVal::Void)));
+    }
+
+    #[test]
+    fn keywords_can_come_in_any_order() {
+        let bound = signature()
+            .bind(&[
+                SteelVal::IntV(1),
+                SteelVal::IntV(2),
+                keyword("d"),
+                SteelVal::IntV(4),
+                keyword("c"),
+                SteelVal::IntV(3),
+            ])
+            .unwrap();
+        assert_eq!(
+            bound,
+            vec![
+                SteelVal::IntV(1),
+                SteelVal::IntV(2),
+                SteelVal::IntV(3),
+                SteelVal::IntV(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_calls_are_errors() {
+        let kind = |args: &[SteelVal]| signature().bind(args).unwrap_err().kind();
+
+        assert_eq!(kind(&[]), ErrorKind::ArityMismatch);
+        assert_eq!(
+            kind(&[SteelVal::IntV(1), SteelVal::IntV(2), SteelVal::IntV(3)]),
+            ErrorKind::ArityMismatch
+        );
+        assert_eq!(
+            kind(&[SteelVal::IntV(1), keyword("c")]),
+            ErrorKind::ArityMismatch
+        );
+        assert_eq!(
+            kind(&[SteelVal::IntV(1), keyword("e"), SteelVal::IntV(5)]),
+            ErrorKind::ContractViolation
+        );
+        assert_eq!(
+            kind(&[
+                SteelVal::IntV(1),
+                keyword("c"),
+                SteelVal::IntV(3),
+                keyword("c"),
+                SteelVal::IntV(3)
+            ]),
+            ErrorKind::ContractViolation
+        );
+    }
+}
@@ -3,16 +3,46 @@ use crate::rerrs::{ErrorKind, SteelErr};
 use crate::rvals::{Result, SteelVal};
 use crate::stop;
 use crate::throw;
+use std::cell::{Ref, RefCell};
 use std::rc::Rc;
 
 use serde::{Deserialize, Serialize};
 
 use crate::parser::ast::Struct;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct SteelStruct {
     name: Rc<str>,
-    fields: Vec<SteelVal>,
+    field_names: Rc<[Rc<str>]>,
+    // Only changed in place by the modifiers of record types, which can make a struct refer
+    // back to itself
+    fields: RefCell<Vec<SteelVal>>,
+}
+
+thread_local! {
+    // The structs being printed and the pairs of them being compared, by address, so that a
+    // struct that refers back to itself isn't walked forever
+    static PRINTING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static COMPARING: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Comparing structs that refer back to themselves gets back to a pair of them that is already
+// being compared, which are taken to be equal as far as the rest of the comparison goes
+impl PartialEq for SteelStruct {
+    fn eq(&self, other: &Self) -> bool {
+        let pair = (self as *const Self as usize, other as *const Self as usize);
+        if pair.0 == pair.1 || COMPARING.with(|x| x.borrow().contains(&pair)) {
+            return true;
+        }
+        if self.name != other.name || self.field_names != other.field_names {
+            return false;
+        }
+
+        COMPARING.with(|x| x.borrow_mut().push(pair));
+        let equal = *self.fields.borrow() == *other.fields.borrow();
+        COMPARING.with(|x| x.borrow_mut().pop());
+        equal
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -85,8 +115,44 @@ impl<'a> StructFuncBuilder<'a> {
 // }
 
 impl SteelStruct {
-    pub fn new(name: Rc<str>, fields: Vec<SteelVal>) -> Self {
-        SteelStruct { name, fields }
+    pub fn new(name: Rc<str>, field_names: Rc<[Rc<str>]>, fields: Vec<SteelVal>) -> Self {
+        SteelStruct {
+            name,
+            field_names,
+            fields: RefCell::new(fields),
+        }
+    }
+
+    pub(crate) fn fields(&self) -> Ref<'_, Vec<SteelVal>> {
+        self.fields.borrow()
+    }
+
+    pub(crate) fn field_names(&self) -> &[Rc<str>] {
+        &self.field_names
+    }
+
+    /// The name to print the struct with. Record types are conventionally named like `<point>`,
+    /// which prints as `point`.
+    pub(crate) fn display_name(&self) -> &str {
+        self.name
+            .strip_prefix('<')
+            .and_then(|name| name.strip_suffix('>'))
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&self.name)
+    }
+
+    /// Runs `print`, unless the struct is already being printed further up because one of its
+    /// fields refers back to it
+    pub(crate) fn printing<R>(&self, print: impl FnOnce() -> R) -> Option<R> {
+        let address = self as *const Self as usize;
+        if PRINTING.with(|x| x.borrow().contains(&address)) {
+            return None;
+        }
+
+        PRINTING.with(|x| x.borrow_mut().push(address));
+        let result = print();
+        PRINTING.with(|x| x.borrow_mut().pop());
+        Some(result)
     }
 
     // This will blow up the stack with a sufficiently large recursive struct
@@ -119,8 +185,9 @@ impl SteelStruct {
         // add 2 for the constructor and the predicate
         let mut funcs = Vec::with_capacity(field_names_as_strs.len() * 2 + 2);
         let name = Rc::from(name);
+        let field_names: Rc<[Rc<str>]> = field_names_as_strs.iter().map(|x| Rc::from(*x)).collect();
         // generate constructor
-        let cons = constructor(Rc::clone(&name), field_names_as_strs.len());
+        let cons = constructor(Rc::clone(&name), field_names);
         funcs.push((name.to_string(), cons));
         // generate predicate
         funcs.push((format!("{}?", name), predicate(Rc::clone(&name))));
@@ -139,8 +206,9 @@ impl SteelStruct {
 // initialize hashmap to be field_names -> void
 // just do arity check before inserting to make sure things check out
 // that way field names as a vec are no longer necessary
-fn constructor(name: Rc<str>, len: usize) -> SteelVal {
+fn constructor(name: Rc<str>, field_names: Rc<[Rc<str>]>) -> SteelVal {
     let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+        let len = field_names.len();
         if args.len() != len {
             let error_message = format!(
                 "{} expected {} arguments, found {}",
@@ -151,15 +219,7 @@ fn constructor(name: Rc<str>, len: usize) -> SteelVal {
             stop!(ArityMismatch => error_message);
         }
 
-        let mut new_struct = SteelStruct::new(Rc::clone(&name), vec![SteelVal::Void; len]);
-
-        for (idx, arg) in args.iter().enumerate() {
-            let key = new_struct
-                .fields
-                .get_mut(idx)
-                .ok_or_else(throw!(TypeMismatch => "Couldn't find that field in the struct"))?;
-            *key = arg.clone();
-        }
+        let new_struct = SteelStruct::new(Rc::clone(&name), Rc::clone(&field_names), args.to_vec());
 
         Ok(SteelVal::StructV(Gc::new(new_struct)))
     };
@@ -188,6 +248,17 @@ fn predicate(name: Rc<str>) -> SteelVal {
     SteelVal::BoxedFunction(Rc::new(f))
 }
 
+// The struct in `value`, as long as it was made by the constructor for `name`
+fn struct_named<'a>(value: &'a SteelVal, name: &str, func: &str) -> Result<&'a SteelStruct> {
+    match value {
+        SteelVal::StructV(s) if s.name.as_ref() == name => Ok(s),
+        _ => {
+            stop!(TypeMismatch => "{} {} expected a {}, found {}", name, func, name, value)
+        }
+    }
+}
+
+// Only takes structs made by the constructor for `name`, not any struct with enough fields
 fn getter(name: Rc<str>, idx: usize) -> SteelVal {
     let f = move |args: &[SteelVal]| -> Result<SteelVal> {
         if args.len() != 1 {
@@ -199,9 +270,9 @@ fn getter(name: Rc<str>, idx: usize) -> SteelVal {
             stop!(ArityMismatch => error_message);
         }
 
-        let my_struct = args[0].struct_or_else(throw!(TypeMismatch => "expected struct"))?;
+        let my_struct = struct_named(&args[0], &name, "getter")?;
 
-        if let Some(ret_val) = my_struct.fields.get(idx) {
+        if let Some(ret_val) = my_struct.fields().get(idx) {
             Ok(ret_val.clone())
         } else {
             stop!(TypeMismatch => "Couldn't find that field in the struct")
@@ -222,21 +293,77 @@ fn setter(name: Rc<str>, idx: usize) -> SteelVal {
             stop!(ArityMismatch => error_message);
         }
 
-        let my_struct = args[0].struct_or_else(throw!(TypeMismatch => "expected struct"))?;
+        let my_struct = struct_named(&args[0], &name, "setter")?;
         let value = args[1].clone();
 
-        let mut new_struct = my_struct.clone();
-        let key = new_struct
-            .fields
+        let new_struct = my_struct.clone();
+        let mut fields = new_struct.fields.borrow_mut();
+        let key = fields
             .get_mut(idx)
             .ok_or_else(throw!(TypeMismatch => "Couldn't find that field in the struct"))?;
         *key = value;
+        drop(fields);
         Ok(SteelVal::StructV(Gc::new(new_struct)))
     };
 
     SteelVal::BoxedFunction(Rc::new(f))
 }
 
+// Unlike the setter, changes the field of the struct it is given
+fn modifier(name: Rc<str>, idx: usize) -> SteelVal {
+    let f = move |args: &[SteelVal]| -> Result<SteelVal> {
+        if args.len() != 2 {
+            let error_message = format!(
+                "{} modifier expected two arguments, found {}",
+                name,
+                args.len()
+            );
+            stop!(ArityMismatch => error_message);
+        }
+
+        let my_struct = struct_named(&args[0], &name, "modifier")?;
+
+        match my_struct.fields.borrow_mut().get_mut(idx) {
+            Some(key) => *key = args[1].clone(),
+            None => stop!(TypeMismatch => "Couldn't find that field in the struct"),
+        }
+        Ok(SteelVal::Void)
+    };
+
+    SteelVal::BoxedFunction(Rc::new(f))
+}
+
+/// (%struct-modifier name index) makes a function that changes field `index` of structs
+/// named `name` in place. `define-record-type` expands its modifiers into this.
+pub fn struct_modifier() -> SteelVal {
+    SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+        match args {
+            [SteelVal::StringV(name), SteelVal::IntV(idx)] if *idx >= 0 => {
+                Ok(modifier(Rc::from(name.as_str()), *idx as usize))
+            }
+            [_, _] => stop!(TypeMismatch => "%struct-modifier expected a name and an index"),
+            _ => stop!(ArityMismatch => "%struct-modifier expected two arguments"),
+        }
+    })
+}
+
+/// (%struct-ref struct index) returns field `index` of any struct. `match` expands struct
+/// patterns into this, after checking the struct with its predicate.
+pub fn struct_ref() -> SteelVal {
+    SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+        match args {
+            [SteelVal::StructV(s), SteelVal::IntV(idx)] => match s.fields().get(*idx as usize) {
+                Some(value) if *idx >= 0 => Ok(value.clone()),
+                _ => {
+                    stop!(ContractViolation => "{} doesn't have a field {}", s.display_name(), idx)
+                }
+            },
+            [_, _] => stop!(TypeMismatch => "%struct-ref expected a struct and an index"),
+            _ => stop!(ArityMismatch => "%struct-ref expected two arguments"),
+        }
+    })
+}
+
 #[cfg(test)]
 mod struct_tests {
 
@@ -250,62 +377,133 @@ mod struct_tests {
         func(&args)
     }
 
+    fn promise_fields() -> Rc<[Rc<str>]> {
+        vec![Rc::from("value"), Rc::from("state")].into()
+    }
+
+    fn promise(fields: Vec<SteelVal>) -> SteelStruct {
+        SteelStruct::new(Rc::from("Promise"), promise_fields(), fields)
+    }
+
     #[test]
     fn constructor_normal() {
         let args = vec![SteelVal::IntV(1), SteelVal::IntV(2)];
-        let res = apply_function(constructor(Rc::from("Promise"), 2), args);
-        let expected = SteelVal::StructV(Gc::new(SteelStruct {
-            name: Rc::from("Promise"),
-            fields: vec![SteelVal::IntV(1), SteelVal::IntV(2)],
-        }));
+        let res = apply_function(constructor(Rc::from("Promise"), promise_fields()), args);
+        let expected =
+            SteelVal::StructV(Gc::new(promise(vec![SteelVal::IntV(1), SteelVal::IntV(2)])));
         assert_eq!(res.unwrap(), expected)
     }
 
     #[test]
     fn setter_position_0() {
         let args = vec![
-            SteelVal::StructV(Gc::new(SteelStruct {
-                name: Rc::from("Promise"),
-                fields: vec![SteelVal::IntV(1), SteelVal::IntV(2)],
-            })),
+            SteelVal::StructV(Gc::new(promise(vec![SteelVal::IntV(1), SteelVal::IntV(2)]))),
             SteelVal::IntV(100),
         ];
 
         let res = apply_function(setter(Rc::from("Promise"), 0), args);
-        let expected = SteelVal::StructV(Gc::new(SteelStruct {
-            name: Rc::from("Promise"),
-            fields: vec![SteelVal::IntV(100), SteelVal::IntV(2)],
-        }));
+        let expected = SteelVal::StructV(Gc::new(promise(vec![
+            SteelVal::IntV(100),
+            SteelVal::IntV(2),
+        ])));
         assert_eq!(res.unwrap(), expected);
     }
 
     #[test]
     fn setter_position_1() {
         let args = vec![
-            SteelVal::StructV(Gc::new(SteelStruct {
-                name: Rc::from("Promise"),
-                fields: vec![SteelVal::IntV(1), SteelVal::IntV(2)],
-            })),
+            SteelVal::StructV(Gc::new(promise(vec![SteelVal::IntV(1), SteelVal::IntV(2)]))),
             SteelVal::IntV(100),
         ];
 
         let res = apply_function(setter(Rc::from("Promise"), 1), args);
-        let expected = SteelVal::StructV(Gc::new(SteelStruct {
-            name: Rc::from("Promise"),
-            fields: vec![SteelVal::IntV(1), SteelVal::IntV(100)],
-        }));
+        let expected = SteelVal::StructV(Gc::new(promise(vec![
+            SteelVal::IntV(1),
+            SteelVal::IntV(100),
+        ])));
         assert_eq!(res.unwrap(), expected);
     }
 
     #[test]
     fn getter_position_0() {
-        let args = vec![SteelVal::StructV(Gc::new(SteelStruct {
-            name: Rc::from("Promise"),
-            fields: vec![SteelVal::IntV(1), SteelVal::IntV(2)],
-        }))];
+        let args = vec![SteelVal::StructV(Gc::new(promise(vec![
+            SteelVal::IntV(1),
+            SteelVal::IntV(2),
+        ])))];
 
         let res = apply_function(getter(Rc::from("Promise"), 0), args);
         let expected = SteelVal::IntV(1);
         assert_eq!(res.unwrap(), expected);
     }
+
+    #[test]
+    fn structs_print_with_their_field_names() {
+        let mut vm = crate::steel_vm::engine::Engine::new();
+        let printed = vm
+            .run(
+                r#"
+                (define-record-type <point> (make-point x y) point? (x point-x) (y point-y))
+                (struct Node (value next))
+                (list (make-point 1 2) (Node "a" void))
+                "#,
+            )
+            .unwrap()
+            .last()
+            .unwrap()
+            .to_string();
+
+        assert_eq!(
+            printed,
+            "'(#<point x: 1 y: 2> #<Node value: \"a\" next: #<void>>)"
+        );
+    }
+
+    #[test]
+    fn getter_only_takes_its_own_structs() {
+        let other = SteelVal::StructV(Gc::new(SteelStruct::new(
+            Rc::from("Other"),
+            promise_fields(),
+            vec![SteelVal::IntV(1), SteelVal::IntV(2)],
+        )));
+
+        // Even with the same fields, a struct with another name is the wrong type
+        let err = apply_function(getter(Rc::from("Promise"), 0), vec![other.clone()]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert!(err
+            .to_string()
+            .contains("Promise getter expected a Promise"));
+
+        let err = apply_function(
+            setter(Rc::from("Promise"), 0),
+            vec![other, SteelVal::IntV(3)],
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn structs_that_refer_to_themselves_print_and_compare() {
+        let mut vm = crate::steel_vm::engine::Engine::new();
+        let result = vm
+            .run(
+                r#"
+                (define-record-type <node>
+                  (make-node value next)
+                  node?
+                  (value node-value)
+                  (next node-next set-node-next!))
+                (define a (make-node 1 void))
+                (set-node-next! a a)
+                (define b (make-node 1 void))
+                (set-node-next! b b)
+                (list a (equal? a b) (equal? a (make-node 2 a)))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            result.last().unwrap().to_string(),
+            "'(#<node value: 1 next: #<node ...>> #true #false)"
+        );
+    }
 }
@@ -40,6 +40,35 @@ fn module_test_chain() {
     .unwrap();
 }
 
+#[test]
+fn module_constants_are_folded_into_requiring_code() {
+    let program = r#"
+        (require "tests/modules/constants.rkt")
+        (define area (square scale))
+    "#;
+
+    let mut vm = Engine::new();
+    let expanded = vm.emit_fully_expanded_ast_to_string(program).unwrap();
+    assert!(!expanded.contains("(square scale)"));
+    assert!(expanded.contains("100"));
+
+    let summaries = vm.module_summaries();
+    let (_, summary) = summaries
+        .iter()
+        .find(|(path, _)| path.ends_with("constants.rkt"))
+        .unwrap();
+    assert_eq!(summary["square"].arity, Some(1));
+    assert!(summary["square"].pure);
+    assert!(summary["scale"].constant.is_some());
+    // Something that gets `set!` can't be treated as a constant
+    assert!(summary["count"].constant.is_none());
+
+    vm.run(program).unwrap();
+    test_line("area", &["100"], &mut vm);
+    test_line("label", &["\"points\""], &mut vm);
+    test_line("(bump)", &["1"], &mut vm);
+}
+
 #[test]
 fn if_test() {
     let mut evaluator = Engine::new();
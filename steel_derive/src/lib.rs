@@ -4,8 +4,11 @@ extern crate proc_macro2;
 extern crate syn;
 extern crate quote;
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{Data, DeriveInput};
+use quote::{format_ident, quote};
+use syn::{
+    AttributeArgs, Data, DataStruct, DeriveInput, Fields, FnArg, Item, ItemMod, ItemStruct,
+    ItemTrait, Lit, Meta, NestedMeta, Receiver, TraitItem,
+};
 
 #[proc_macro_derive(Steel)]
 pub fn derive_steel(input: TokenStream) -> TokenStream {
@@ -22,3 +25,488 @@ pub fn derive_steel(input: TokenStream) -> TokenStream {
     let output = quote! { #input };
     output.into()
 }
+
+/// Converts a struct to and from Steel values field by field, instead of wrapping it as a custom
+/// value like `#[derive(Steel)]` does. Converting into Steel makes a hash map from each field
+/// name to its value, and converting back accepts a hash map keyed by symbols or strings, or a
+/// struct with fields of the same names.
+///
+/// Field names have `_` replaced by `-`, and each field can be changed with:
+///
+/// * `#[steel_convert(rename = "...")]` to use a different name in Steel
+/// * `#[steel_convert(skip)]` to leave it out, using `Default::default()` when converting back
+///
+/// ```ignore
+/// #[derive(SteelConvert)]
+/// struct Config {
+///     max_size: usize,
+///     #[steel_convert(rename = "label")]
+///     name: String,
+///     #[steel_convert(skip)]
+///     cache: Vec<String>,
+/// }
+///
+/// engine.register_value("config", config.into_steelval()?);
+/// engine.run("(hash-get config 'max-size)");
+/// ```
+#[proc_macro_derive(SteelConvert, attributes(steel_convert))]
+pub fn derive_steel_convert(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let ident = input.ident.clone();
+    let type_name = ident.to_string();
+
+    let fields = match &input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return syn::Error::new_spanned(
+                &input,
+                "SteelConvert can only be derived for structs with named fields",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    let mut converted = Vec::new();
+    let mut names = Vec::new();
+    let mut skipped = Vec::new();
+
+    for field in fields {
+        let field_ident = field.ident.clone().unwrap();
+        match convert_attribute(field) {
+            Ok(FieldConversion::Skip) => skipped.push(field_ident),
+            Ok(FieldConversion::Rename(name)) => {
+                names.push(name);
+                converted.push(field_ident);
+            }
+            Ok(FieldConversion::Default) => {
+                names.push(field_ident.to_string().replace('_', "-"));
+                converted.push(field_ident);
+            }
+            Err(e) => return e.to_compile_error().into(),
+        }
+    }
+
+    let types: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|x| x.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for ty in &types {
+        where_clause.predicates.push(parse_quote! {
+            #ty: steel::rvals::FromSteelVal + steel::rvals::IntoSteelVal
+        });
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let output = quote! {
+        impl #impl_generics steel::rvals::IntoSteelVal for #ident #ty_generics #where_clause {
+            fn into_steelval(self) -> steel::rvals::Result<steel::SteelVal> {
+                Ok(steel::rvals::fields_to_hash_map(vec![
+                    #((#names, steel::rvals::IntoSteelVal::into_steelval(self.#converted)?)),*
+                ]))
+            }
+        }
+
+        impl #impl_generics steel::rvals::FromSteelVal for #ident #ty_generics #where_clause {
+            fn from_steelval(value: steel::SteelVal) -> steel::rvals::Result<Self> {
+                Ok(#ident {
+                    #(#converted: steel::rvals::FromSteelVal::from_steelval(
+                        steel::rvals::field_by_name(&value, #type_name, #names)?,
+                    )?,)*
+                    #(#skipped: Default::default(),)*
+                })
+            }
+        }
+    };
+    output.into()
+}
+
+enum FieldConversion {
+    Default,
+    Rename(String),
+    Skip,
+}
+
+// Reads `#[steel_convert(rename = "...")]` or `#[steel_convert(skip)]` off of a field
+fn convert_attribute(field: &syn::Field) -> syn::Result<FieldConversion> {
+    let attr = match field
+        .attrs
+        .iter()
+        .find(|x| x.path.is_ident("steel_convert"))
+    {
+        Some(attr) => attr,
+        None => return Ok(FieldConversion::Default),
+    };
+
+    let args: Vec<NestedMeta> = match attr.parse_meta()? {
+        Meta::List(list) => list.nested.into_iter().collect(),
+        other => {
+            return Err(syn::Error::new_spanned(
+                other,
+                "expected #[steel_convert(rename = \"...\")] or #[steel_convert(skip)]",
+            ))
+        }
+    };
+
+    match args.as_slice() {
+        [NestedMeta::Meta(Meta::Path(path))] if path.is_ident("skip") => Ok(FieldConversion::Skip),
+        _ => match string_argument(&args, "rename")? {
+            Some(name) => Ok(FieldConversion::Rename(name)),
+            None => Ok(FieldConversion::Default),
+        },
+    }
+}
+
+/// Makes a struct usable from Steel, the way `#[steel]` did before types were registered
+/// as custom values. Along with what `#[derive(Steel)]` gives, this generates a
+/// `register_steel_type` function that registers:
+///
+/// * `Name?`, a predicate for the type
+/// * `Name`, a constructor taking every field in order
+/// * `Name-field` and `set-Name-field!` for each field, where setters change the value in place
+///
+/// Field names have `_` replaced by `-`, and `#[steel(name = "...")]` changes the name used for
+/// the type. Fields have to convert to and from Steel values, and tuple structs only get the
+/// predicate.
+///
+/// ```ignore
+/// #[steel]
+/// #[derive(Clone, Debug)]
+/// pub struct Point {
+///     x: isize,
+///     y: isize,
+/// }
+///
+/// Point::register_steel_type(&mut engine);
+/// engine.run("(define p (Point 1 2)) (set-Point-x! p 10) (Point-x p)");
+/// ```
+#[proc_macro_attribute]
+pub fn steel(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemStruct);
+    let ident = &item.ident;
+
+    let name = match string_argument(&args, "name") {
+        Ok(name) => name.unwrap_or_else(|| ident.to_string()),
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let predicate = format!("{}?", name);
+
+    let mut registrations = vec![quote! {
+        engine.register_type::<#ident>(#predicate);
+    }];
+
+    if let Fields::Named(fields) = &item.fields {
+        let fields: Vec<_> = fields.named.iter().collect();
+        let idents: Vec<_> = fields.iter().map(|x| x.ident.clone().unwrap()).collect();
+        let types: Vec<_> = fields.iter().map(|x| x.ty.clone()).collect();
+
+        registrations.push(quote! {
+            engine.register_fn(#name, |#(#idents: #types),*| #ident { #(#idents),* });
+        });
+
+        for (field, ty) in idents.iter().zip(&types) {
+            let field_name = field.to_string().replace('_', "-");
+            let getter = format!("{}-{}", name, field_name);
+            let setter = format!("set-{}-{}!", name, field_name);
+
+            registrations.push(quote! {
+                engine.register_fn(#getter, |value: &mut #ident| value.#field.clone());
+                engine.register_fn(#setter, |value: &mut #ident, field: #ty| {
+                    value.#field = field;
+                });
+            });
+        }
+    }
+
+    let output = quote! {
+        #item
+
+        impl steel::rvals::Custom for #ident {}
+
+        impl #ident {
+            /// Registers the predicate, constructor, getters and setters for this type
+            pub fn register_steel_type(
+                engine: &mut steel::steel_vm::engine::Engine,
+            ) -> &mut steel::steel_vm::engine::Engine {
+                use steel::steel_vm::register_fn::RegisterFn;
+                #(#registrations)*
+                engine
+            }
+        }
+    };
+    output.into()
+}
+
+/// Lets values behind a trait be passed to Steel, so hosts can expose an interface that plugins
+/// implement rather than only concrete types. Alongside the trait this generates `DynName`,
+/// a shared handle to a `Box<dyn Name>` that converts to and from Steel values, with a
+/// `register_steel_trait` function that registers:
+///
+/// * `Name?`, a predicate for values of the trait
+/// * `Name-method` for each method taking `&self` or `&mut self`, which calls the method on
+///   whatever type is behind the value
+///
+/// Method names have `_` replaced by `-`, and `#[steel_trait(name = "...")]` changes the name
+/// used for the trait. Arguments and return values have to convert to and from Steel values,
+/// and generic methods are left out.
+///
+/// ```ignore
+/// #[steel_trait]
+/// pub trait Shape {
+///     fn area(&self) -> f64;
+///     fn scale(&mut self, factor: f64);
+/// }
+///
+/// DynShape::register_steel_trait(&mut engine);
+/// engine.register_value("circle", DynShape::new(Circle { radius: 1.0 }).into_steelval()?);
+/// engine.run("(Shape-scale circle 2.0) (Shape-area circle)");
+/// ```
+#[proc_macro_attribute]
+pub fn steel_trait(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemTrait);
+    let ident = &item.ident;
+    let vis = &item.vis;
+    let handle = format_ident!("Dyn{}", ident);
+
+    let name = match string_argument(&args, "name") {
+        Ok(name) => name.unwrap_or_else(|| ident.to_string()),
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let predicate = format!("{}?", name);
+    let display = format!("#<{}>", name);
+
+    let mut registrations = Vec::new();
+
+    for trait_item in &item.items {
+        let method = match trait_item {
+            TraitItem::Method(method) if method.sig.generics.params.is_empty() => method,
+            _ => continue,
+        };
+
+        // Only methods that borrow the value can be called through the handle
+        let borrow = match method.sig.receiver() {
+            Some(FnArg::Receiver(Receiver {
+                reference: Some(_),
+                mutability,
+                ..
+            })) => {
+                if mutability.is_some() {
+                    quote! { borrow_mut }
+                } else {
+                    quote! { borrow }
+                }
+            }
+            _ => continue,
+        };
+
+        let types: Vec<_> = method
+            .sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                FnArg::Typed(arg) => Some(arg.ty.clone()),
+                FnArg::Receiver(_) => None,
+            })
+            .collect();
+        let args: Vec<_> = (0..types.len())
+            .map(|i| format_ident!("arg{}", i))
+            .collect();
+
+        let method_ident = &method.sig.ident;
+        let function = format!("{}-{}", name, method_ident.to_string().replace('_', "-"));
+
+        registrations.push(quote! {
+            engine.register_fn(#function, |value: &mut #handle, #(#args: #types),*| {
+                value.0.#borrow().#method_ident(#(#args),*)
+            });
+        });
+    }
+
+    let output = quote! {
+        #item
+
+        /// A shared handle to a value implementing the trait, which can be passed to and from
+        /// Steel
+        #[derive(Clone)]
+        #vis struct #handle(
+            pub std::rc::Rc<std::cell::RefCell<Box<dyn #ident>>>,
+        );
+
+        impl #handle {
+            pub fn new<T: #ident + 'static>(value: T) -> Self {
+                Self::from(Box::new(value) as Box<dyn #ident>)
+            }
+
+            /// Registers the predicate and a function for each method of the trait
+            pub fn register_steel_trait(
+                engine: &mut steel::steel_vm::engine::Engine,
+            ) -> &mut steel::steel_vm::engine::Engine {
+                use steel::steel_vm::register_fn::RegisterFn;
+                engine.register_type::<#handle>(#predicate);
+                #(#registrations)*
+                engine
+            }
+        }
+
+        impl From<Box<dyn #ident>> for #handle {
+            fn from(value: Box<dyn #ident>) -> Self {
+                #handle(std::rc::Rc::new(std::cell::RefCell::new(value)))
+            }
+        }
+
+        impl std::fmt::Debug for #handle {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, #display)
+            }
+        }
+
+        impl steel::rvals::Custom for #handle {}
+    };
+    output.into()
+}
+
+/// Marks a function inside of a `#[steel_module]` to be registered with the engine.
+/// The name defaults to the name of the function with `_` replaced by `-`, and can be
+/// given explicitly with `#[function(name = "...")]`. Calling it with the wrong number or
+/// types of arguments fails with an error whose `call_error` says which argument was wrong.
+#[proc_macro_attribute]
+pub fn function(_args: TokenStream, input: TokenStream) -> TokenStream {
+    input
+}
+
+/// Generates a `register_module` function for a module of `#[function]`s, which registers
+/// each of them with an engine via `register_fn`.
+///
+/// ```ignore
+/// #[steel_module]
+/// mod math {
+///     #[function]
+///     pub fn square(x: isize) -> isize {
+///         x * x
+///     }
+/// }
+///
+/// math::register_module(&mut engine);
+/// ```
+///
+/// `#[steel_module(prefix = "math/")]` prepends the prefix to every name in the module.
+#[proc_macro_attribute]
+pub fn steel_module(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let mut module = parse_macro_input!(input as ItemMod);
+
+    let prefix = match string_argument(&args, "prefix") {
+        Ok(prefix) => prefix.unwrap_or_default(),
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let items = match &mut module.content {
+        Some((_, items)) => items,
+        None => {
+            return syn::Error::new_spanned(
+                &module,
+                "#[steel_module] can only be used on a module with a body",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    let mut names = Vec::new();
+    let mut functions = Vec::new();
+
+    for item in items.iter_mut() {
+        if let Item::Fn(f) = item {
+            let position = match f.attrs.iter().position(is_function_attribute) {
+                Some(position) => position,
+                None => continue,
+            };
+
+            // Strip the attribute, it's only a marker for this macro
+            let attr = f.attrs.remove(position);
+
+            let name = match function_name(&attr) {
+                Ok(Some(name)) => name,
+                Ok(None) => f.sig.ident.to_string().replace('_', "-"),
+                Err(e) => return e.to_compile_error().into(),
+            };
+
+            names.push(format!("{}{}", prefix, name));
+            functions.push(f.sig.ident.clone());
+        }
+    }
+
+    items.push(syn::parse_quote! {
+        /// Registers every `#[function]` in this module with the engine
+        pub fn register_module(
+            engine: &mut steel::steel_vm::engine::Engine,
+        ) -> &mut steel::steel_vm::engine::Engine {
+            use steel::steel_vm::register_fn::RegisterFn;
+            #(engine.register_fn(#names, #functions);)*
+            engine
+        }
+    });
+
+    let output = quote! { #module };
+    output.into()
+}
+
+// Either `#[function]` or `#[steel_derive::function]`
+fn is_function_attribute(attr: &syn::Attribute) -> bool {
+    attr.path
+        .segments
+        .last()
+        .map(|x| x.ident == "function")
+        .unwrap_or(false)
+}
+
+fn function_name(attr: &syn::Attribute) -> syn::Result<Option<String>> {
+    if attr.tokens.is_empty() {
+        return Ok(None);
+    }
+
+    match attr.parse_meta()? {
+        Meta::List(list) => {
+            let args: Vec<NestedMeta> = list.nested.into_iter().collect();
+            string_argument(&args, "name")
+        }
+        other => Err(syn::Error::new_spanned(
+            other,
+            "expected #[function] or #[function(name = \"...\")]",
+        )),
+    }
+}
+
+// Finds `key = "value"` in the arguments of an attribute
+fn string_argument(args: &[NestedMeta], key: &str) -> syn::Result<Option<String>> {
+    let mut value = None;
+
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident(key) => match &nv.lit {
+                Lit::Str(s) => value = Some(s.value()),
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        format!("expected a string for {}", key),
+                    ))
+                }
+            },
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    format!("unexpected argument, expected {} = \"...\"", key),
+                ))
+            }
+        }
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,121 @@
+//! Generates the parts of steel that come from profiling it, shared by steel's build script
+//! (with the `codegen` feature) and `cargo xtask gen-superinstructions`.
+//!
+//! This can't depend on steel itself, since steel's build script depends on it, so opcodes are
+//! handled by name and whoever calls in says which of them exist.
+
+use serde::Deserialize;
+
+/// The compiler checks every pair of instructions against all of the patterns
+pub const MAX_PATTERNS: usize = 16;
+
+/// Where an opcode can go in a super instruction. Ones that jump can only come second, since
+/// the VM runs the second instruction of a super instruction wherever the first one left it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fusion {
+    pub first: bool,
+    pub second: bool,
+}
+
+// An entry of `patterns.json`, the count is only there for whoever is reading it
+#[derive(Deserialize)]
+struct Pattern {
+    opcodes: Vec<String>,
+}
+
+/// Generates the source of `super_patterns.rs` from the contents of `patterns.json`.
+///
+/// `fusion` says where the opcode with the given name can go in a super instruction, returning
+/// `None` if there is no such opcode. Pairs that can't be fused are skipped, and at most
+/// [`MAX_PATTERNS`] of the rest are kept, hottest first.
+pub fn generate_super_patterns(
+    patterns_json: &str,
+    fusion: impl Fn(&str) -> Option<Fusion>,
+) -> Result<String, String> {
+    let patterns: Vec<Pattern> = serde_json::from_str(patterns_json).map_err(|e| e.to_string())?;
+
+    let mut pairs = Vec::new();
+    for pattern in &patterns {
+        for opcode in &pattern.opcodes {
+            if fusion(opcode).is_none() {
+                return Err(format!("unknown opcode in patterns: {}", opcode));
+            }
+        }
+
+        if let [first, second] = &pattern.opcodes[..] {
+            let pair = (first.as_str(), second.as_str());
+            let fuses =
+                fusion(first).is_some_and(|x| x.first) && fusion(second).is_some_and(|x| x.second);
+            if fuses && !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+    }
+    pairs.truncate(MAX_PATTERNS);
+
+    let mut source = String::from(
+        "// @generated by steel_gen from patterns.json, don't edit by hand
+use crate::core::opcode::OpCode;
+
+/// The pairs of instructions that ran back to back the most while recording patterns, hottest
+/// first. The compiler fuses these into super instructions.
+",
+    );
+
+    if pairs.is_empty() {
+        source.push_str("pub const DYNAMIC_SUPER_PATTERNS: &[(OpCode, OpCode)] = &[];\n");
+    } else {
+        source.push_str("pub const DYNAMIC_SUPER_PATTERNS: &[(OpCode, OpCode)] = &[\n");
+        for (first, second) in &pairs {
+            source.push_str(&format!("    (OpCode::{}, OpCode::{}),\n", first, second));
+        }
+        source.push_str("];\n");
+    }
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod steel_gen_tests {
+    use super::*;
+
+    fn fusion(name: &str) -> Option<Fusion> {
+        let (first, second) = match name {
+            "READLOCAL" | "PUSHCONST" => (true, true),
+            "JMP" => (false, true),
+            "CALLGLOBAL" => (false, false),
+            _ => return None,
+        };
+        Some(Fusion { first, second })
+    }
+
+    #[test]
+    fn only_fusible_pairs_are_kept() {
+        let json = r#"[
+            {"opcodes": ["READLOCAL", "PUSHCONST"], "count": 30},
+            {"opcodes": ["PUSHCONST", "CALLGLOBAL"], "count": 20},
+            {"opcodes": ["JMP", "READLOCAL"], "count": 15},
+            {"opcodes": ["PUSHCONST", "JMP"], "count": 12},
+            {"opcodes": ["PUSHCONST", "READLOCAL"], "count": 10}
+        ]"#;
+
+        let source = generate_super_patterns(json, fusion).unwrap();
+        assert!(source.ends_with(
+            "&[
+    (OpCode::READLOCAL, OpCode::PUSHCONST),
+    (OpCode::PUSHCONST, OpCode::JMP),
+    (OpCode::PUSHCONST, OpCode::READLOCAL),
+];
+"
+        ));
+    }
+
+    #[test]
+    fn unknown_opcodes_are_an_error() {
+        let json = r#"[{"opcodes": ["READLOCAL", "NOTANOPCODE"], "count": 30}]"#;
+        assert_eq!(
+            generate_super_patterns(json, fusion),
+            Err("unknown opcode in patterns: NOTANOPCODE".to_string())
+        );
+    }
+}
@@ -5,17 +5,15 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 
 use rustyline::error::ReadlineError;
 use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
-use rustyline::validate::{
-    MatchingBracketValidator, ValidationContext, ValidationResult, Validator,
-};
-use rustyline::Editor;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{hint::Hinter, CompletionType, Context};
+use rustyline::{Config, Editor};
 use rustyline_derive::Helper;
 use std::{
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
-use steel::rvals::SteelVal;
+use steel::rvals::{PrintLimits, SteelVal};
 
 use rustyline::completion::Completer;
 use rustyline::completion::Pair;
@@ -36,23 +34,100 @@ pub(crate) static INTERRUPT_CHANNEL: Lazy<(Arc<Mutex<Sender<()>>>, Arc<Mutex<Rec
         (Arc::new(Mutex::new(sender)), Arc::new(Mutex::new(receiver)))
     });
 
+// Characters that end the name being completed
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '\'' | '`' | ',' | '"' | ';')
+}
+
+// The number of brackets left open at the end of `input`, skipping over strings, comments and
+// character literals like `#\(`. `None` if `input` ends inside a string.
+fn open_brackets(input: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ';' => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '#' if chars.as_str().starts_with('\\') => {
+                chars.next();
+                chars.next();
+            }
+            '"' => loop {
+                match chars.next() {
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(_) => {}
+                    None => return None,
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Some(depth)
+}
+
 impl Completer for RustylineHelper {
     type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(is_delimiter)
+            .map(|i| i + line[i..].chars().next().unwrap().len_utf8())
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let vm = self.vm.lock().unwrap();
+        let mut names: Vec<_> = vm
+            .globals()
+            .into_iter()
+            .chain(vm.in_scope_macros())
+            // Names starting with `#` are made up by the compiler
+            .filter(|x| x.starts_with(prefix) && !x.starts_with('#'))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let candidates = names
+            .into_iter()
+            .map(|x| Pair {
+                display: x.clone(),
+                replacement: x,
+            })
+            .collect();
+
+        Ok((start, candidates))
+    }
 }
 
 #[derive(Helper)]
 struct RustylineHelper {
     highlighter: MatchingBracketHighlighter,
-    validator: MatchingBracketValidator,
+    vm: Arc<Mutex<Engine>>,
 }
 
+// Enter only runs the input once every bracket is closed, until then it starts a new line
 impl Validator for RustylineHelper {
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
-        self.validator.validate(ctx)
-    }
-
-    fn validate_while_typing(&self) -> bool {
-        self.validator.validate_while_typing()
+        Ok(match open_brackets(ctx.input()) {
+            Some(0) => ValidationResult::Valid(None),
+            _ => ValidationResult::Incomplete,
+        })
     }
 }
 
@@ -99,6 +174,11 @@ fn display_help() {
         :? | :help  -- displays help dialog
         :quit       -- exits the REPL
         :pwd        -- displays the current working directory
+        :save-session <file> -- saves the globals defined so far, to pick up later
+        :load-session <file> -- defines the globals saved with :save-session again
+
+        Tab completes the names of globals and macros. Input with brackets left open
+        carries on over more lines. History is kept in $STEEL_HOME/history.
         "#
     );
 }
@@ -117,11 +197,12 @@ async fn finish_load_or_interrupt(vm: Arc<Mutex<Engine>>, exprs: String, path: P
         let file_name = path.to_str().unwrap().to_string();
 
         let res = vm.lock().unwrap().run_with_path(exprs.as_str(), path);
+        let limits = vm.lock().unwrap().print_limits();
 
         match res {
             Ok(r) => r.iter().for_each(|x| match x {
                 SteelVal::Void => {}
-                _ => println!("{} {}", "=>".bright_blue().bold(), x),
+                _ => println!("{} {}", "=>".bright_blue().bold(), x.limited(limits)),
             }),
             Err(e) => {
                 e.emit_result(file_name.as_str(), exprs.as_str());
@@ -147,11 +228,12 @@ async fn finish_or_interrupt(vm: Arc<Mutex<Engine>>, line: String, print_time: b
         let now = Instant::now();
 
         let res = vm.lock().unwrap().parse_and_execute(&line);
+        let limits = vm.lock().unwrap().print_limits();
 
         match res {
             Ok(r) => r.iter().for_each(|x| match x {
                 SteelVal::Void => {}
-                _ => println!("{} {}", "=>".bright_blue().bold(), x),
+                _ => println!("{} {}", "=>".bright_blue().bold(), x.limited(limits)),
             }),
             Err(e) => {
                 e.emit_result("repl.stl", line.as_str());
@@ -168,7 +250,26 @@ async fn finish_or_interrupt(vm: Arc<Mutex<Engine>>, line: String, print_time: b
 
 /// Entire point for the repl
 /// Automatically adds the prelude and contracts for the core library
+// Printing a huge value would otherwise hang the repl, unless the engine was already given
+// its own limits. `(print-depth #false)` and `(print-length #false)` turn these off.
+const REPL_PRINT_LIMITS: PrintLimits = PrintLimits {
+    depth: Some(64),
+    length: Some(1000),
+};
+
+// `$STEEL_HOME`, or `~/.steel` when that isn't set
+fn steel_home() -> Option<PathBuf> {
+    match std::env::var_os("STEEL_HOME") {
+        Some(home) => Some(PathBuf::from(home)),
+        None => std::env::var_os("HOME").map(|home| Path::new(&home).join(".steel")),
+    }
+}
+
 pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
+    if vm.print_limits() == PrintLimits::default() {
+        vm.set_print_limits(REPL_PRINT_LIMITS);
+    }
+
     println!(
         "{}",
         r#"
@@ -183,12 +284,6 @@ pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
     );
     let prompt = format!("{}", "λ > ".bright_green().bold().italic());
 
-    let mut rl = Editor::<RustylineHelper>::new();
-    rl.set_helper(Some(RustylineHelper {
-        highlighter: MatchingBracketHighlighter::default(),
-        validator: MatchingBracketValidator::default(),
-    }));
-
     let buffer = String::new();
 
     // TODO make this better
@@ -211,7 +306,8 @@ pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
         }
     }
 
-    let mut print_time = false;
+    // Sessions only save what was defined at the repl, not the prelude
+    let prelude_globals = vm.globals().len();
 
     // Create the runtime
     // We really only need this for interrupts
@@ -229,8 +325,47 @@ pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
 
     let vm = Arc::new(Mutex::new(vm));
 
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = Editor::<RustylineHelper>::with_config(config);
+    rl.set_helper(Some(RustylineHelper {
+        highlighter: MatchingBracketHighlighter::default(),
+        vm: Arc::clone(&vm),
+    }));
+
+    // Missing history just means this is the first session
+    let history = steel_home().map(|home| home.join("history"));
+    if let Some(history) = &history {
+        let _ = rl.load_history(history);
+    }
+
+    let result = read_eval_print(&mut rl, &prompt, vm, &rt, &current_dir, prelude_globals);
+
+    if let Some(history) = &history {
+        let saved = std::fs::create_dir_all(history.parent().unwrap())
+            .map_err(ReadlineError::from)
+            .and_then(|_| rl.save_history(history));
+        if let Err(e) = saved {
+            eprintln!("Couldn't save the history to {}: {}", history.display(), e);
+        }
+    }
+
+    result
+}
+
+fn read_eval_print(
+    rl: &mut Editor<RustylineHelper>,
+    prompt: &str,
+    vm: Arc<Mutex<Engine>>,
+    rt: &tokio::runtime::Runtime,
+    current_dir: &Path,
+    prelude_globals: usize,
+) -> std::io::Result<()> {
+    let mut print_time = false;
+
     loop {
-        let readline = rl.readline(&prompt);
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
@@ -248,8 +383,28 @@ pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
                     ":pwd" => println!("{:#?}", current_dir),
                     // ":env" => vm.print_bindings(),
                     ":?" | ":help" => display_help(),
+                    line if line.starts_with(":save-session") => {
+                        let path = line.trim_start_matches(":save-session").trim();
+                        let vm = vm.lock().unwrap();
+                        let saved = vm.save_globals(&vm.globals()[prelude_globals..]);
+
+                        for name in &saved.skipped {
+                            println!(
+                                "{} {}, only data like numbers, strings and lists can be saved",
+                                "Warning: skipping".bright_yellow(),
+                                name
+                            );
+                        }
+
+                        if let Err(e) = std::fs::write(path, saved.source) {
+                            eprintln!("{}", e);
+                        }
+                    }
                     line if line.contains(":load") => {
-                        let line = line.trim_start_matches(":load").trim();
+                        let line = line
+                            .trim_start_matches(":load-session")
+                            .trim_start_matches(":load")
+                            .trim();
                         let path = Path::new(line);
 
                         let file = std::fs::File::open(path);
@@ -294,3 +449,17 @@ pub fn repl_base(mut vm: Engine) -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+
+    #[test]
+    fn input_carries_on_while_brackets_are_open() {
+        assert_eq!(open_brackets("(define (f x)"), Some(1));
+        assert_eq!(open_brackets("(define (f x) [+ x 1])"), Some(0));
+        assert_eq!(open_brackets("(displayln \"(\" #\\( ; (\n)"), Some(0));
+        assert_eq!(open_brackets("(displayln \"a \\\" b"), None);
+        assert_eq!(open_brackets(")"), Some(0));
+    }
+}
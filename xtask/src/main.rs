@@ -0,0 +1,87 @@
+//! Development tasks for Steel, run with `cargo xtask <task>`.
+//!
+//! Super instructions come from profiling: `record-patterns` runs some programs with instruction
+//! stats on and writes the pairs of opcodes that ran back to back the most to `patterns.json`,
+//! then `gen-superinstructions` turns those into the `DYNAMIC_SUPER_PATTERNS` the compiler fuses.
+//! Building steel with the `codegen` feature does the same as `gen-superinstructions`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use steel::core::opcode::OpCode;
+use steel::steel_vm::engine::Engine;
+use steel_gen::Fusion;
+
+const USAGE: &str = "usage: cargo xtask record-patterns <program>...
+       cargo xtask gen-superinstructions [patterns.json]";
+
+// Pairs that ran fewer times than this aren't worth a super instruction
+const MIN_COUNT: usize = 1000;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.split_first() {
+        Some((task, programs)) if task == "record-patterns" => record_patterns(programs),
+        Some((task, rest)) if task == "gen-superinstructions" && rest.len() <= 1 => {
+            gen_superinstructions(rest.first().map(PathBuf::from))
+        }
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn core_directory() -> PathBuf {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    root.join("steel/src/core")
+}
+
+fn record_patterns(programs: &[String]) -> Result<(), String> {
+    if programs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut vm = Engine::new();
+    vm.enable_instruction_stats();
+
+    for program in programs {
+        let source = fs::read_to_string(program).map_err(|e| format!("{}: {}", program, e))?;
+        vm.run(&source).map_err(|e| format!("{}: {}", program, e))?;
+    }
+
+    let stats = vm
+        .instruction_stats()
+        .expect("instruction stats were enabled");
+    let path = core_directory().join("patterns.json");
+
+    fs::write(&path, stats.patterns_json(MIN_COUNT))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn gen_superinstructions(patterns: Option<PathBuf>) -> Result<(), String> {
+    let path = patterns.unwrap_or_else(|| core_directory().join("patterns.json"));
+    let json = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let source = steel_gen::generate_super_patterns(&json, |name| {
+        OpCode::ALL
+            .iter()
+            .find(|x| x.mnemonic() == name)
+            .map(|x| Fusion {
+                first: x.fuses_first(),
+                second: x.fuses_second(),
+            })
+    })
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let path = core_directory().join("super_patterns.rs");
+    fs::write(&path, source).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}